@@ -0,0 +1,56 @@
+//! Measures how long it takes to register many external fonts upfront.
+//!
+//! The font face is parsed when a font is registered, so that malformed fonts are
+//! rejected by `add_external_font()`. The TrueType parser only reads the table
+//! directory at that point, glyphs are read from the font bytes when they are drawn
+//! or measured. The "full parse" case parses every font and reads the glyphs of a
+//! sample text, for comparison. Reading the font bytes from the stream is included
+//! in both cases. The parsed face is kept, so writing text doesn't re-parse the font
+//! (and clone the font bytes) on every call.
+//!
+//! Run with `cargo bench --bench font_loading`
+
+extern crate printpdf;
+extern crate rusttype;
+
+use printpdf::*;
+use std::time::Instant;
+
+const FONT_COUNT: usize = 200;
+
+fn main() {
+    let font_bytes: &[u8] = include_bytes!("../assets/fonts/RobotoMedium.ttf");
+
+    // full parse: parse every font and read the glyphs of the text
+    let start = Instant::now();
+    for _ in 0..FONT_COUNT {
+        let collection = rusttype::FontCollection::from_bytes(font_bytes.to_vec()).unwrap();
+        let font = collection.into_font().unwrap();
+        for c in "The quick brown fox".chars() {
+            let _ = font.glyph(c).standalone().get_data();
+        }
+    }
+    let full_parse = start.elapsed();
+
+    // register all fonts (each one is parsed), only use one of them
+    let (doc, page1, layer1) = PdfDocument::new("font_loading", Mm(210.0), Mm(297.0), "Layer 1");
+    let start = Instant::now();
+    let fonts: Vec<IndirectFontRef> = (0..FONT_COUNT)
+        .map(|_| doc.add_external_font(font_bytes).unwrap())
+        .collect();
+    let register = start.elapsed();
+
+    let layer = doc.get_page(page1).get_layer(layer1);
+    let start = Instant::now();
+    layer.use_text("The quick brown fox", 12.0, Mm(10.0), Mm(10.0), &fonts[0]);
+    let first_use = start.elapsed();
+
+    let start = Instant::now();
+    layer.use_text("The quick brown fox", 12.0, Mm(10.0), Mm(20.0), &fonts[0]);
+    let second_use = start.elapsed();
+
+    println!("full parse of {} fonts:         {:?}", FONT_COUNT, full_parse);
+    println!("registration of {} fonts:       {:?}", FONT_COUNT, register);
+    println!("first use:                      {:?}", first_use);
+    println!("second use:                     {:?}", second_use);
+}
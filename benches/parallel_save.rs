@@ -0,0 +1,49 @@
+//! Measures how long it takes to save a large document with many pages.
+//!
+//! Encoding the content streams of the pages is the part that runs on the
+//! rayon thread pool with the `parallel` feature, so compare:
+//!
+//! `cargo bench --bench parallel_save`
+//! `cargo bench --bench parallel_save --features parallel`
+
+extern crate printpdf;
+
+use printpdf::*;
+use std::io::BufWriter;
+use std::time::Instant;
+
+const PAGE_COUNT: usize = 1000;
+const LINES_PER_PAGE: usize = 500;
+
+fn main() {
+    let (doc, page1, layer1) = PdfDocument::new("parallel_save", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+
+    let mut layers = vec![doc.get_page(page1).get_layer(layer1)];
+    for _ in 1..PAGE_COUNT {
+        let (page, layer) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+        layers.push(doc.get_page(page).get_layer(layer));
+    }
+
+    for layer in &layers {
+        for i in 0..LINES_PER_PAGE {
+            let y = 10.0 + (i % 250) as f64;
+            layer.use_text("The quick brown fox jumps over the lazy dog", 10.0, Mm(10.0), Mm(y), &font);
+            layer.add_shape(Line {
+                points: vec![(Point::new(Mm(10.0), Mm(y)), false), (Point::new(Mm(200.0), Mm(y)), false)],
+                is_closed: false,
+                has_fill: false,
+                has_stroke: true,
+                is_clipping_path: false,
+            });
+        }
+    }
+
+    let mut target = BufWriter::new(Vec::<u8>::new());
+    let start = Instant::now();
+    doc.save(&mut target).unwrap();
+    let elapsed = start.elapsed();
+
+    println!("saving {} pages ({} text lines each): {:?} ({} bytes)",
+             PAGE_COUNT, LINES_PER_PAGE, elapsed, target.get_ref().len());
+}
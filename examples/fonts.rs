@@ -15,7 +15,7 @@ fn main() {
     let font = doc.add_external_font(&mut font_reader).unwrap();
 
     // `use_text` is a wrapper around making a simple string
-    current_layer.use_text(text, 48, Mm(10.0), Mm(200.0), &font);
+    current_layer.use_text(text, 48.0, Mm(10.0), Mm(200.0), &font);
 
     // text fill color = blue
     let blue = Rgb::new(13.0 / 256.0, 71.0 / 256.0, 161.0 / 256.0, None);
@@ -31,7 +31,7 @@ fn main() {
  
         // setup the general fonts.
         // see the docs for these functions for details
-        current_layer.set_font(&font, 33);
+        current_layer.set_font(&font, 33.0);
         current_layer.set_text_cursor(Mm(10.0), Mm(100.0));
         current_layer.set_line_height(33);
         current_layer.set_word_spacing(3000);
@@ -51,7 +51,7 @@ fn main() {
         current_layer.write_text(text, &font);
         current_layer.set_line_offset(10);
         current_layer.set_text_rendering_mode(TextRenderingMode::Stroke);
-        current_layer.set_font(&font, 18);
+        current_layer.set_font(&font, 18.0);
         current_layer.write_text(text2, &font);
 
     current_layer.end_text_section();
@@ -4,6 +4,7 @@ use std::error::Error as IError;
 use std::io::Error as IoError;
 use rusttype::Error as RusttypeError;
 use std::fmt;
+use PdfVersion;
 
 /// error_chain and failure are certainly nice, but completely overengineered
 /// for this use-case. For example, neither of them allow error localization.
@@ -24,21 +25,50 @@ macro_rules! impl_from {
     )
 }
 
+/// Error of any operation of the crate. More variants may be added in the future.
+///
+/// Errors of other crates (I/O, font parsing) are the `source()` of the error and are not
+/// repeated in its message, so that error reporters (like `anyhow`) print every cause once.
+/// The errors of this crate (`PdfError`, `IndexError`) are shown as they are.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
-    /// External: std::io::Error
+    /// External: std::io::Error, e.g. from reading a font or writing the document
     Io(IoError),
-    /// External: rusttype::Error
+    /// External: rusttype::Error, from parsing a font
     Rusttype(RusttypeError),
     /// PDF error
     Pdf(PdfError),
     /// Indexing error (please report if this happens, shouldn't happen)
     Index(IndexError),
+    /// The document is invalid, see `PdfDocumentReference::validate()`
+    Validation(Vec<ValidationError>),
+    /// Error of a `RasterBackend`, while rendering a page
+    Rasterization(String),
+    /// Saving was cancelled, see `PdfDocumentReference::save_cancellable()`
+    Cancelled,
+    /// Writing the document to the target failed with the I/O error after `bytes` bytes.
+    /// `objects` is the number of the `total_objects` objects that were written completely,
+    /// `None` if it isn't known (documents without object streams, linearization or streamed
+    /// images are written by `lopdf`). The output is incomplete and can't be opened.
+    PartialWrite { bytes: usize, objects: Option<usize>, total_objects: usize, error: IoError },
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum PdfError {
     FontFaceError,
+    ImageFormatError,
+    FontVariationError,
+    ObjectIdsExhausted,
+    CorruptDocument,
+    WrongPassword,
+    UnsupportedEncryption,
+    UnknownRegion,
+    InvalidTableSpan,
+    MissingGlyph,
+    InvalidTextEncoding,
+    InvalidInfoKey,
 }
 
 impl fmt::Display for PdfError {
@@ -52,11 +82,23 @@ impl IError for PdfError {
         use self::PdfError::*;
         match *self {
             FontFaceError => "Invalid or corrupt font face",
+            ImageFormatError => "Invalid or unsupported image data",
+            FontVariationError => "Font is not a variable TrueType font or has no such variation axis",
+            ObjectIdsExhausted => "The document has more objects than a PDF file can contain",
+            CorruptDocument => "The file is not a PDF document or is damaged beyond repair",
+            WrongPassword => "The password of the encrypted document is wrong",
+            UnsupportedEncryption => "The document is encrypted with an unsupported security handler",
+            UnknownRegion => "The page has no region with this name",
+            InvalidTableSpan => "A table cell overlaps another cell or spans beyond the table",
+            MissingGlyph => "The font has no glyph for a character of the text",
+            InvalidTextEncoding => "The text is not valid in the given encoding",
+            InvalidInfoKey => "The key of the document info entry is not a valid name or is set by the library",
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum IndexError {
     PdfPageIndexError,
     PdfLayerIndexError,
@@ -75,7 +117,123 @@ impl IError for IndexError {
         match *self {
             PdfPageIndexError => "Page index out of bounds",
             PdfLayerIndexError => "PDF layer index out of bounds",
-            PdfMarkerIndexError => "PDF layer index out of bounds",
+            PdfMarkerIndexError => "PDF marker index out of bounds",
+        }
+    }
+}
+
+/// Structural problem in a document, found before saving it.
+/// Page and layer are the (zero-based) indices of the page and the layer on the page.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ValidationError {
+    /// A layer uses a font that wasn't added to the document
+    MissingFont { page: usize, layer: usize, font: String },
+    /// A layer uses an XObject (image, form) that wasn't added to the page
+    MissingXObject { page: usize, layer: usize, xobject: String },
+    /// A layer uses a graphics state that wasn't added to the page
+    MissingGraphicsState { page: usize, layer: usize, graphics_state: String },
+    /// Saving and restoring the graphics state (`q` / `Q`) doesn't match up
+    UnbalancedGraphicsState { page: usize, layer: usize },
+    /// Beginning and ending text sections (`BT` / `ET`) doesn't match up
+    UnbalancedTextSection { page: usize, layer: usize },
+    /// The page has no content, only reported if empty pages are forbidden
+    EmptyPage { page: usize },
+    /// An operation of a layer has an operand that is NaN or infinite, only the first
+    /// one of each layer is reported (see `CoordinateValidation`)
+    NonFiniteNumber { page: usize, layer: usize, operator: String },
+    /// The width or height of the page is zero, negative, NaN or infinite (see `CoordinateValidation`)
+    InvalidPageSize { page: usize },
+    /// The document uses a feature that requires a newer PDF version than the one that was set
+    UnsupportedFeature { feature: &'static str, required: PdfVersion, version: PdfVersion },
+    /// A tag (structure element) was opened with `begin_tag()`, but never closed
+    UnclosedTag { tag: String },
+    /// A custom structure type isn't mapped to a standard structure type
+    UnmappedStructureType { name: String },
+    /// More than one form field has the same name
+    DuplicateFieldName { name: String },
+    /// An option of a radio group or choice field is empty or used more than once
+    InvalidFieldOption { field: String, option: String },
+    /// A value was set for a form field that doesn't exist
+    UnknownField { field: String },
+    /// The value set for a form field isn't one of its options
+    InvalidFieldValue { field: String, value: String },
+    /// Two options of the document were enabled, that can't be used together
+    IncompatibleOptions { first: &'static str, second: &'static str },
+    /// A link refers to a named destination that wasn't added to the document
+    UnknownDestination { name: String },
+    /// A named destination or a link refers to a page that doesn't exist
+    MissingDestinationPage { name: Option<String>, page: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationError::*;
+        match *self {
+            MissingFont { page, layer, ref font } =>
+                write!(f, "page {}, layer {}: font \"{}\" is not part of the document", page, layer, font),
+            MissingXObject { page, layer, ref xobject } =>
+                write!(f, "page {}, layer {}: XObject \"{}\" is not part of the page", page, layer, xobject),
+            MissingGraphicsState { page, layer, ref graphics_state } =>
+                write!(f, "page {}, layer {}: graphics state \"{}\" is not part of the page", page, layer, graphics_state),
+            UnbalancedGraphicsState { page, layer } =>
+                write!(f, "page {}, layer {}: unbalanced save / restore of the graphics state", page, layer),
+            UnbalancedTextSection { page, layer } =>
+                write!(f, "page {}, layer {}: unbalanced begin / end of a text section", page, layer),
+            EmptyPage { page } =>
+                write!(f, "page {}: page is empty", page),
+            NonFiniteNumber { page, layer, ref operator } =>
+                write!(f, "page {}, layer {}: operator \"{}\" has a NaN or infinite operand", page, layer, operator),
+            InvalidPageSize { page } =>
+                write!(f, "page {}: width and height must be positive", page),
+            UnsupportedFeature { feature, required, version } =>
+                write!(f, "{} requires PDF {}, but the document is PDF {}", feature, required.as_str(), version.as_str()),
+            UnclosedTag { ref tag } =>
+                write!(f, "tag \"{}\" was never closed", tag),
+            UnmappedStructureType { ref name } =>
+                write!(f, "custom structure type \"{}\" is not mapped to a standard structure type", name),
+            DuplicateFieldName { ref name } =>
+                write!(f, "form field name \"{}\" is used more than once", name),
+            InvalidFieldOption { ref field, ref option } =>
+                write!(f, "form field \"{}\": option \"{}\" is empty or not unique", field, option),
+            UnknownField { ref field } =>
+                write!(f, "form field \"{}\" doesn't exist", field),
+            InvalidFieldValue { ref field, ref value } =>
+                write!(f, "form field \"{}\" can't have the value \"{}\"", field, value),
+            IncompatibleOptions { first, second } =>
+                write!(f, "{} can't be combined with {}", first, second.to_lowercase()),
+            UnknownDestination { ref name } =>
+                write!(f, "named destination \"{}\" doesn't exist", name),
+            MissingDestinationPage { name: Some(ref name), page } =>
+                write!(f, "named destination \"{}\": page {} doesn't exist", name, page),
+            MissingDestinationPage { name: None, page } =>
+                write!(f, "link target: page {} doesn't exist", page),
+        }
+    }
+}
+
+impl IError for ValidationError {
+    fn description(&self) -> &str {
+        use self::ValidationError::*;
+        match *self {
+            MissingFont { .. } => "Font is not part of the document",
+            MissingXObject { .. } => "XObject is not part of the page",
+            MissingGraphicsState { .. } => "Graphics state is not part of the page",
+            UnbalancedGraphicsState { .. } => "Unbalanced save / restore of the graphics state",
+            UnbalancedTextSection { .. } => "Unbalanced begin / end of a text section",
+            EmptyPage { .. } => "Page is empty",
+            NonFiniteNumber { .. } => "NaN or infinite number in the content",
+            InvalidPageSize { .. } => "Page size is not positive",
+            UnsupportedFeature { .. } => "Feature is not supported by the PDF version",
+            UnclosedTag { .. } => "Tag was never closed",
+            UnmappedStructureType { .. } => "Custom structure type is not mapped to a standard structure type",
+            DuplicateFieldName { .. } => "Form field name is used more than once",
+            InvalidFieldOption { .. } => "Form field option is empty or not unique",
+            UnknownField { .. } => "Form field doesn't exist",
+            InvalidFieldValue { .. } => "Invalid value for form field",
+            IncompatibleOptions { .. } => "Options can't be combined",
+            UnknownDestination { .. } => "Named destination doesn't exist",
+            MissingDestinationPage { .. } => "Destination page doesn't exist",
         }
     }
 }
@@ -89,10 +247,24 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::Error::*;
         match *self {
-            Io(ref e) => write!(f, "{}", e),
-            Rusttype(ref e) => write!(f, "{}", e),
+            Io(_) => write!(f, "I/O error"),
+            Rusttype(_) => write!(f, "Font could not be parsed"),
             Pdf(ref e) => write!(f, "{}", e),
             Index(ref e) => write!(f, "{}", e),
+            Validation(ref errors) => {
+                write!(f, "Invalid document: ")?;
+                for (idx, e) in errors.iter().enumerate() {
+                    if idx != 0 { write!(f, "; ")?; }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            },
+            Rasterization(ref e) => write!(f, "Rasterization failed: {}", e),
+            Cancelled => write!(f, "Saving was cancelled"),
+            PartialWrite { bytes, objects: Some(objects), total_objects, .. } =>
+                write!(f, "Writing the document failed after {} bytes ({} of {} objects)", bytes, objects, total_objects),
+            PartialWrite { bytes, objects: None, .. } =>
+                write!(f, "Writing the document failed after {} bytes", bytes),
         }
     }
 }
@@ -101,11 +273,74 @@ impl IError for Error {
     fn description(&self) -> &str {
         use self::Error::*;
         match self {
-            Io(ref e) => e.description(),
-            Rusttype(ref e) => e.description(),
+            Io(_) => "I/O error",
+            Rusttype(_) => "Font could not be parsed",
             Pdf(ref e) => e.description(),
             Index(ref e) => e.description(),
+            Validation(_) => "Invalid document",
+            Rasterization(_) => "Rasterization failed",
+            Cancelled => "Saving was cancelled",
+            PartialWrite { .. } => "Writing the document failed",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn IError + 'static)> {
+        use self::Error::*;
+        match *self {
+            Io(ref e) | PartialWrite { error: ref e, .. } => Some(e),
+            Rusttype(ref e) => Some(e),
+            Pdf(ref e) => e.source(),
+            Index(ref e) => e.source(),
+            Validation(_) | Rasterization(_) | Cancelled => None,
         }
     }
 }
 
+#[test]
+fn test_error_source() {
+    use std::io::ErrorKind;
+
+    // external errors are the source, their message is not repeated
+    let error: Error = IoError::new(ErrorKind::UnexpectedEof, "font file is truncated").into();
+    assert_eq!(error.to_string(), "I/O error");
+    let source = error.source().unwrap();
+    assert_eq!(source.to_string(), "font file is truncated");
+    assert_eq!(source.downcast_ref::<IoError>().map(|e| e.kind()), Some(ErrorKind::UnexpectedEof));
+
+    let error: Error = RusttypeError::UnrecognizedFormat.into();
+    assert!(error.source().unwrap().downcast_ref::<RusttypeError>().is_some());
+
+    // errors of the crate itself have no further cause
+    let error: Error = IndexError::PdfPageIndexError.into();
+    assert_eq!(error.to_string(), "Page index out of bounds");
+    assert!(error.source().is_none());
+
+    let error = Error::Validation(vec![ValidationError::EmptyPage { page: 2 }]);
+    assert_eq!(error.to_string(), "Invalid document: page 2: page is empty");
+    assert!(error.source().is_none());
+}
+
+
+#[test]
+fn test_error_chain() {
+    use std::io::{BufWriter, ErrorKind, Write};
+    use {PdfDocument, Mm};
+
+    struct FullDisk;
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> ::std::io::Result<usize> { Err(IoError::new(ErrorKind::Other, "disk full")) }
+        fn flush(&mut self) -> ::std::io::Result<()> { Ok(()) }
+    }
+
+    // the I/O error of the writer is the cause of the error returned by `save()`
+    let (doc, _, _) = PdfDocument::new("chain", Mm(210.0), Mm(297.0), "Layer 1");
+    let error = doc.save(&mut BufWriter::with_capacity(0, FullDisk)).unwrap_err();
+    let mut chain = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        chain.push(cause.to_string());
+        source = cause.source();
+    }
+    assert_eq!(chain.last().map(|s| s.as_str()), Some("disk full"));
+    assert_eq!(chain.iter().filter(|s| s.contains("disk full")).count(), 1);
+}
@@ -0,0 +1,908 @@
+//! Rendering of a small subset of HTML (feature `html`) with a `TextFlow`
+//!
+//! The HTML is laid out on pages of the size in `HtmlStyle`, in one column.
+//! The following tags are supported (all other tags are ignored, their text is kept):
+//!
+//! - blocks: `<p>`, `<div>`, `<h1>` to `<h6>`, `<blockquote>`, `<pre>`, `<br>`, `<hr>`
+//!   (as vertical space) and the sectioning tags `<html>`, `<body>`, `<section>`, `<article>`,
+//!   `<header>`, `<footer>`, `<main>` and `<nav>`
+//! - lists: `<ul>`, `<ol>` (with the `start` attribute) and `<li>`, nested to any depth
+//! - inline: `<b>`, `<strong>`, `<i>`, `<em>`, `<code>`, `<kbd>`, `<samp>`, `<tt>`, `<span>`,
+//!   `<a>` (as text, links are not created) and `<font color="...">`
+//! - tables: `<table>` with `<tr>`, `<td>` and `<th>` (in bold), optionally inside of `<thead>`,
+//!   `<tbody>` and `<tfoot>`. All columns have the same width, rows are never split across pages.
+//! - images: `<img src="..." width="..." height="..." alt="...">`. The images are not loaded
+//!   from files, they are looked up by their `src` in `HtmlStyle::images`. Missing images are
+//!   replaced by their `alt` text.
+//!
+//! `<head>`, `<script>` and `<style>` are skipped, stylesheets are not supported. CSS is
+//! only read from the `style` attribute, with these properties:
+//!
+//! - `color`: `#rgb`, `#rrggbb`, `rgb(r, g, b)` or one of the basic color names
+//!   (black, white, gray / grey, silver, red, maroon, green, lime, blue, navy, yellow, orange, purple, teal)
+//! - `font-weight`: `bold` or `normal`
+//! - `font-style`: `italic`, `oblique` or `normal`
+//! - `font-family`: `monospace` selects the code font, everything else the regular fonts
+//! - `font-size` on block elements: in `pt`, `px`, `mm`, `em` or `%`
+//! - `width` and `height` on images: in `pt`, `px`, `mm` or `%` (of the width of the text)
+//!
+//! The `width` and `height` attributes of images are in pixels (1 px = 0.75 pt).
+//! Entities are decoded for `&amp;`, `&lt;`, `&gt;`, `&quot;`, `&apos;`, `&#39;`, `&nbsp;`
+//! and numeric entities (`&#169;`, `&#xA9;`).
+
+use std::collections::BTreeMap;
+
+use {Error, BuiltinFont, Color, Image, ImageXObject, IndirectFontRef, Mm, Pt, Px, PdfDocumentReference, Rect, Rgb, TextFlow};
+
+/// Fonts, sizes and images used by `render_html()`
+#[derive(Debug, Clone)]
+pub struct HtmlStyle {
+    pub regular: IndirectFontRef,
+    pub bold: IndirectFontRef,
+    pub italic: IndirectFontRef,
+    pub bold_italic: IndirectFontRef,
+    /// Font for `<code>` and `<pre>`, usually a monospaced font
+    pub code: IndirectFontRef,
+    /// Size of the body text in pt
+    pub font_size: f64,
+    /// Sizes of the headings `<h1>` to `<h6>` in pt
+    pub heading_sizes: [f64; 6],
+    /// Space between two blocks (paragraphs, headings, lists, tables)
+    pub block_spacing: Mm,
+    /// Indentation of each level of lists and quotes
+    pub list_indent: Mm,
+    /// Size of the pages added for the text
+    pub page_width: Mm,
+    pub page_height: Mm,
+    /// Margin of the text on all sides of the pages
+    pub margin: Mm,
+    /// Images for the `<img>` tags, by their `src`
+    pub images: BTreeMap<String, ImageXObject>,
+}
+
+impl HtmlStyle {
+
+    /// Creates a style with the builtin Helvetica and Courier fonts (which are added to
+    /// the document), 11 pt body text, A4 pages and no images
+    pub fn builtin(doc: &PdfDocumentReference)
+    -> Result<Self, Error>
+    {
+        Ok(Self {
+            regular: doc.add_builtin_font(BuiltinFont::Helvetica)?,
+            bold: doc.add_builtin_font(BuiltinFont::HelveticaBold)?,
+            italic: doc.add_builtin_font(BuiltinFont::HelveticaOblique)?,
+            bold_italic: doc.add_builtin_font(BuiltinFont::HelveticaBoldOblique)?,
+            code: doc.add_builtin_font(BuiltinFont::Courier)?,
+            font_size: 11.0,
+            heading_sizes: [22.0, 17.0, 14.0, 12.0, 11.0, 10.0],
+            block_spacing: Mm(3.0),
+            list_indent: Mm(6.0),
+            page_width: Mm(210.0),
+            page_height: Mm(297.0),
+            margin: Mm(20.0),
+            images: BTreeMap::new(),
+        })
+    }
+
+    /// Adds an image, which is used for `<img>` tags with the `src`
+    #[inline]
+    pub fn add_image<S>(&mut self, src: S, image: ImageXObject) where S: Into<String>
+    {
+        self.images.insert(src.into(), image);
+    }
+
+    fn font(&self, style: &InlineStyle)
+    -> &IndirectFontRef
+    {
+        match (style.code, style.bold, style.italic) {
+            (true, _, _) => &self.code,
+            (false, true, true) => &self.bold_italic,
+            (false, true, false) => &self.bold,
+            (false, false, true) => &self.italic,
+            (false, false, false) => &self.regular,
+        }
+    }
+}
+
+/// Node of the parsed document
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Element { tag: String, attributes: BTreeMap<String, String>, children: Vec<Node> },
+    Text(String),
+}
+
+impl Node {
+
+    fn attribute(&self, name: &str)
+    -> Option<&str>
+    {
+        match *self {
+            Node::Element { ref attributes, .. } => attributes.get(name).map(|value| value.as_str()),
+            Node::Text(_) => None,
+        }
+    }
+
+    /// Value of a property in the `style` attribute
+    fn css(&self, property: &str)
+    -> Option<String>
+    {
+        self.attribute("style")?.split(';').find_map(|declaration| {
+            let mut parts = declaration.splitn(2, ':');
+            let name = parts.next()?.trim();
+            let value = parts.next()?.trim();
+            if name.eq_ignore_ascii_case(property) { Some(value.to_ascii_lowercase()) } else { None }
+        })
+    }
+
+    /// Text of the node and all its descendants, without any whitespace handling
+    fn text(&self)
+    -> String
+    {
+        match *self {
+            Node::Text(ref text) => text.clone(),
+            Node::Element { ref tag, ref children, .. } => {
+                if tag == "br" { "\n".to_string() } else { children.iter().map(Node::text).collect() }
+            },
+        }
+    }
+}
+
+/// Style of a run of text
+#[derive(Debug, Default, Clone, PartialEq)]
+struct InlineStyle {
+    bold: bool,
+    italic: bool,
+    code: bool,
+    color: Option<Color>,
+}
+
+/// Elements without content (they have no closing tag)
+const VOID_ELEMENTS: &[&str] = &["br", "img", "hr", "meta", "link", "input", "col", "wbr", "area", "base", "source"];
+/// Elements whose content is skipped
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "title", "template"];
+/// Elements, that end an open `<p>`
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre", "ul", "ol", "li", "table", "hr",
+    "section", "article", "header", "footer", "main", "nav",
+];
+/// Elements, which are only containers for blocks
+const SECTION_ELEMENTS: &[&str] = &["html", "body", "section", "article", "header", "footer", "main", "nav"];
+
+/// Renders the HTML on new pages, which are added to the document
+pub fn render_html(doc: &PdfDocumentReference, html: &str, style: &HtmlStyle)
+{
+    let (page, layer) = doc.add_page(style.page_width, style.page_height, "Text");
+    let region = Rect::new(style.margin, style.margin, style.page_width - style.margin * 2.0, style.page_height - style.margin * 2.0);
+    let mut flow = TextFlow::new(doc.get_page(page).get_layer(layer), region);
+    render_html_into(&mut flow, html, style);
+    flow.finish();
+}
+
+/// Renders the HTML into an existing flow, e.g. after other content of the document
+pub fn render_html_into(flow: &mut TextFlow, html: &str, style: &HtmlStyle)
+{
+    let base_indent = flow.indent;
+    {
+        let mut renderer = Renderer {
+            flow,
+            style,
+            base_indent,
+            runs: Vec::new(),
+            depth: 0,
+            lists: Vec::new(),
+            started: false,
+            space_pending: false,
+        };
+        let block = BlockStyle { font_size: style.font_size, keep_with_next: false };
+        renderer.walk(&parse_html(html), &InlineStyle::default(), &block);
+        renderer.flush(&block);
+    }
+    flow.indent = base_indent;
+}
+
+/// Properties of the block, that the text is in
+#[derive(Debug, Clone)]
+struct BlockStyle {
+    font_size: f64,
+    /// For headings
+    keep_with_next: bool,
+}
+
+struct Renderer<'a> {
+    flow: &'a mut TextFlow,
+    style: &'a HtmlStyle,
+    /// Indentation of the flow before the HTML
+    base_indent: Mm,
+    /// Inline text of the current block, not added to the flow yet
+    runs: Vec<(String, InlineStyle)>,
+    /// Nesting level of lists and quotes
+    depth: usize,
+    /// Open lists, with the number of the next item for numbered lists
+    lists: Vec<Option<usize>>,
+    /// Whether anything was added to the flow
+    started: bool,
+    /// Whether a block ended or started, so that space has to be added before the next content
+    space_pending: bool,
+}
+
+impl<'a> Renderer<'a> {
+
+    fn walk(&mut self, nodes: &[Node], inline: &InlineStyle, block: &BlockStyle)
+    {
+        for node in nodes {
+            let (tag, children) = match *node {
+                Node::Text(ref text) => {
+                    self.push_text(&collapse_whitespace(text), inline);
+                    continue;
+                },
+                Node::Element { ref tag, ref children, .. } => (tag.as_str(), children),
+            };
+
+            let inline = &element_style(node, inline);
+            let block = &BlockStyle {
+                font_size: node.css("font-size").and_then(|size| parse_length(&size, block.font_size, self.style.font_size))
+                    .map(|size| size.0).unwrap_or(block.font_size),
+                keep_with_next: false,
+            };
+
+            match tag {
+                "br" => self.push_text("\n", inline),
+                "hr" => { self.flush(block); self.space_pending = true; },
+                "p" | "div" => self.block(children, inline, block),
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    let level = (tag.as_bytes()[1] - b'1') as usize;
+                    let heading = BlockStyle { font_size: self.style.heading_sizes[level], keep_with_next: true };
+                    self.block(children, &InlineStyle { bold: true, .. inline.clone() }, &heading);
+                },
+                "blockquote" => {
+                    self.depth += 1;
+                    self.block(children, &InlineStyle { italic: true, .. inline.clone() }, block);
+                    self.depth -= 1;
+                },
+                "ul" | "ol" => {
+                    self.flush(block);
+                    let start = node.attribute("start").and_then(|start| start.trim().parse().ok()).unwrap_or(1);
+                    self.lists.push(if tag == "ol" { Some(start) } else { None });
+                    self.depth += 1;
+                    if self.lists.len() == 1 {
+                        self.space_pending = true;
+                    }
+                    self.walk(children, inline, block);
+                    self.flush(block);
+                    self.depth -= 1;
+                    self.lists.pop();
+                    if self.lists.is_empty() {
+                        self.space_pending = true;
+                    }
+                },
+                "li" => {
+                    self.flush(block);
+                    let marker = match self.lists.last_mut() {
+                        Some(&mut Some(ref mut number)) => { *number += 1; format!("{}. ", *number - 1) },
+                        _ => "- ".to_string(),
+                    };
+                    self.runs.push((marker, InlineStyle { color: inline.color.clone(), .. InlineStyle::default() }));
+                    self.walk(children, inline, block);
+                    self.flush(block);
+                },
+                "pre" => {
+                    self.flush(block);
+                    let text = node.text();
+                    let text = text.strip_prefix('\n').unwrap_or(&text);
+                    self.before_content();
+                    self.flow.add_preformatted(text.trim_end(), &self.style.code, block.font_size * 0.9);
+                    self.space_pending = true;
+                },
+                "table" => {
+                    self.flush(block);
+                    self.space_pending = true;
+                    self.table(node, inline, block);
+                    self.space_pending = true;
+                },
+                "img" => {
+                    self.flush(block);
+                    self.image(node, block);
+                },
+                _ if SKIPPED_ELEMENTS.contains(&tag) => { },
+                _ if SECTION_ELEMENTS.contains(&tag) => {
+                    self.flush(block);
+                    self.walk(children, inline, block);
+                    self.flush(block);
+                },
+                _ => self.walk(children, inline, block),
+            }
+        }
+    }
+
+    /// Renders a block with space before and after it
+    fn block(&mut self, children: &[Node], inline: &InlineStyle, block: &BlockStyle)
+    {
+        self.flush(block);
+        self.space_pending = true;
+        self.walk(children, inline, block);
+        self.flush(block);
+        self.space_pending = true;
+    }
+
+    fn push_text(&mut self, text: &str, inline: &InlineStyle)
+    {
+        match self.runs.last_mut() {
+            Some(&mut (ref mut last, ref style)) if style == inline => last.push_str(text),
+            _ => self.runs.push((text.to_string(), inline.clone())),
+        }
+    }
+
+    /// Space between blocks, which is added lazily so that empty blocks don't add space
+    fn before_content(&mut self)
+    {
+        self.flow.indent = self.base_indent + self.style.list_indent * self.depth as f64;
+        if self.started && self.space_pending {
+            self.flow.add_space(self.style.block_spacing);
+        }
+        self.started = true;
+        self.space_pending = false;
+    }
+
+    /// Adds the collected inline text as a paragraph
+    fn flush(&mut self, block: &BlockStyle)
+    {
+        let runs = trim_runs(::std::mem::take(&mut self.runs));
+        if runs.is_empty() {
+            return;
+        }
+
+        self.before_content();
+        let style = self.style;
+        let runs: Vec<(&str, &IndirectFontRef, Option<&Color>)> = runs.iter()
+            .map(|(text, inline)| (text.as_str(), style.font(inline), inline.color.as_ref()))
+            .collect();
+        self.flow.add_colored_paragraph(&runs, block.font_size, block.keep_with_next);
+    }
+
+    fn table(&mut self, table: &Node, inline: &InlineStyle, block: &BlockStyle)
+    {
+        let mut rows = Vec::new();
+        collect_rows(table, &mut rows);
+
+        let mut cells: Vec<Vec<Vec<(String, InlineStyle)>>> = Vec::new();
+        for row in rows {
+            let row_style = element_style(row, inline);
+            let mut row_cells = Vec::new();
+            if let Node::Element { ref children, .. } = *row {
+                for cell in children {
+                    let tag = match *cell { Node::Element { ref tag, .. } => tag.as_str(), Node::Text(_) => continue };
+                    if tag != "td" && tag != "th" {
+                        continue;
+                    }
+                    let mut cell_style = element_style(cell, &row_style);
+                    cell_style.bold |= tag == "th";
+                    row_cells.push(self.inline_runs(cell, &cell_style));
+                }
+            }
+            cells.push(row_cells);
+        }
+
+        let columns = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+
+        self.before_content();
+        let width: Mm = (self.flow.region().width() - self.flow.indent.into()).into();
+        let widths = vec![width / columns as f64; columns];
+        let style = self.style;
+
+        for row in &cells {
+            let row: Vec<Vec<(&str, &IndirectFontRef, Option<&Color>)>> = (0..columns).map(|column| {
+                row.get(column).map(|runs| runs.iter()
+                    .map(|(text, inline)| (text.as_str(), style.font(inline), inline.color.as_ref()))
+                    .collect()).unwrap_or_default()
+            }).collect();
+            self.flow.add_table_row(&row, &widths, block.font_size);
+        }
+    }
+
+    /// Inline text of a table cell, blocks inside of the cell are joined with spaces
+    fn inline_runs(&mut self, node: &Node, inline: &InlineStyle)
+    -> Vec<(String, InlineStyle)>
+    {
+        let outer = ::std::mem::take(&mut self.runs);
+        if let Node::Element { ref children, .. } = *node {
+            self.walk_inline(children, inline);
+        }
+        trim_runs(::std::mem::replace(&mut self.runs, outer))
+    }
+
+    fn walk_inline(&mut self, nodes: &[Node], inline: &InlineStyle)
+    {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => self.push_text(&collapse_whitespace(text), inline),
+                Node::Element { ref tag, ref children, .. } => {
+                    if tag == "br" {
+                        self.push_text("\n", inline);
+                    } else if !SKIPPED_ELEMENTS.contains(&tag.as_str()) {
+                        if BLOCK_ELEMENTS.contains(&tag.as_str()) {
+                            self.push_text(" ", inline);
+                        }
+                        self.walk_inline(children, &element_style(node, inline));
+                    }
+                },
+            }
+        }
+    }
+
+    fn image(&mut self, node: &Node, block: &BlockStyle)
+    {
+        let image = match node.attribute("src").and_then(|src| self.style.images.get(src)) {
+            Some(image) => image.clone(),
+            None => {
+                if let Some(alt) = node.attribute("alt") {
+                    self.space_pending = true;
+                    self.push_text(&collapse_whitespace(alt), &InlineStyle { italic: true, .. InlineStyle::default() });
+                    self.flush(block);
+                    self.space_pending = true;
+                }
+                return;
+            },
+        };
+
+        self.before_content();
+        let available = self.flow.region().width() - self.flow.indent.into();
+        let (Px(pixel_width), Px(pixel_height)) = (image.width, image.height);
+        let ratio = pixel_height as f64 / pixel_width.max(1) as f64;
+        // attributes are in pixels, CSS with units; relative to the available width
+        let length = |css: &str, attribute: &str| node.css(css).and_then(|value| parse_length(&value, available.0, self.style.font_size))
+            .or_else(|| node.attribute(attribute).and_then(|value| value.trim().trim_end_matches("px").parse::<f64>().ok()).map(|px| Pt(px * 0.75)));
+
+        let (mut width, mut height) = match (length("width", "width"), length("height", "height")) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, width * ratio),
+            (None, Some(height)) => (height / ratio, height),
+            (None, None) => (Pt(pixel_width as f64 * 0.75), Pt(pixel_height as f64 * 0.75)),
+        };
+        if width > available {
+            height *= available.0 / width.0;
+            width = available;
+        }
+
+        let mut image = Image::from(image);
+        if let Some(alt) = node.attribute("alt") {
+            image = image.with_alt_text(alt);
+        }
+        self.flow.add_image(image, width.into(), height.into());
+        self.space_pending = true;
+    }
+}
+
+/// All rows of a table in document order, also inside of `<thead>`, `<tbody>` and `<tfoot>`
+fn collect_rows<'n>(node: &'n Node, rows: &mut Vec<&'n Node>)
+{
+    if let Node::Element { ref children, .. } = *node {
+        for child in children {
+            match *child {
+                Node::Element { ref tag, .. } if tag == "tr" => rows.push(child),
+                Node::Element { ref tag, .. } if tag == "thead" || tag == "tbody" || tag == "tfoot" => collect_rows(child, rows),
+                _ => { },
+            }
+        }
+    }
+}
+
+/// Applies the inline style of an element (tags and the `style` attribute)
+fn element_style(node: &Node, parent: &InlineStyle)
+-> InlineStyle
+{
+    let mut style = parent.clone();
+    let tag = match *node { Node::Element { ref tag, .. } => tag.as_str(), Node::Text(_) => return style };
+
+    match tag {
+        "b" | "strong" => style.bold = true,
+        "i" | "em" => style.italic = true,
+        "code" | "kbd" | "samp" | "tt" => style.code = true,
+        "font" => if let Some(color) = node.attribute("color").and_then(parse_color) { style.color = Some(color); },
+        _ => { },
+    }
+
+    if let Some(color) = node.css("color").as_ref().and_then(|color| parse_color(color)) {
+        style.color = Some(color);
+    }
+    match node.css("font-weight").as_deref() {
+        Some("bold") | Some("bolder") | Some("700") | Some("800") | Some("900") => style.bold = true,
+        Some("normal") | Some("400") => style.bold = false,
+        _ => { },
+    }
+    match node.css("font-style").as_deref() {
+        Some("italic") | Some("oblique") => style.italic = true,
+        Some("normal") => style.italic = false,
+        _ => { },
+    }
+    if let Some(family) = node.css("font-family") {
+        style.code = family.contains("monospace");
+    }
+
+    style
+}
+
+/// Parses a CSS color
+fn parse_color(color: &str)
+-> Option<Color>
+{
+    let color = color.trim().to_ascii_lowercase();
+    let rgb = |r: u8, g: u8, b: u8| Some(Color::Rgb(Rgb::new(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0, None)));
+
+    if let Some(hex) = color.strip_prefix('#') {
+        let digit = |idx: usize, len: usize| u8::from_str_radix(hex.get(idx..idx + len)?, 16).ok();
+        return match hex.len() {
+            3 => rgb(digit(0, 1)? * 17, digit(1, 1)? * 17, digit(2, 1)? * 17),
+            6 => rgb(digit(0, 2)?, digit(2, 2)?, digit(4, 2)?),
+            _ => None,
+        };
+    }
+
+    if let Some(components) = color.strip_prefix("rgb(").and_then(|rest| rest.strip_suffix(')')) {
+        let components: Vec<u8> = components.split(',').filter_map(|c| c.trim().parse().ok()).collect();
+        return match components[..] {
+            [r, g, b] => rgb(r, g, b),
+            _ => None,
+        };
+    }
+
+    match color.as_str() {
+        "black" => rgb(0, 0, 0),
+        "white" => rgb(255, 255, 255),
+        "gray" | "grey" => rgb(128, 128, 128),
+        "silver" => rgb(192, 192, 192),
+        "red" => rgb(255, 0, 0),
+        "maroon" => rgb(128, 0, 0),
+        "green" => rgb(0, 128, 0),
+        "lime" => rgb(0, 255, 0),
+        "blue" => rgb(0, 0, 255),
+        "navy" => rgb(0, 0, 128),
+        "yellow" => rgb(255, 255, 0),
+        "orange" => rgb(255, 165, 0),
+        "purple" => rgb(128, 0, 128),
+        "teal" => rgb(0, 128, 128),
+        _ => None,
+    }
+}
+
+/// Parses a CSS length in pt, `%` is relative to `relative_to`, `em` to the font size
+fn parse_length(length: &str, relative_to: f64, font_size: f64)
+-> Option<Pt>
+{
+    let length = length.trim();
+    let number_end = length.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-')).unwrap_or(length.len());
+    let value: f64 = length[..number_end].parse().ok()?;
+
+    match length[number_end..].trim() {
+        "pt" => Some(Pt(value)),
+        "px" | "" => Some(Pt(value * 0.75)),
+        "mm" => Some(Mm(value).into()),
+        "cm" => Some(Mm(value * 10.0).into()),
+        "in" => Some(Pt(value * 72.0)),
+        "em" | "rem" => Some(Pt(value * font_size)),
+        "%" => Some(Pt(value / 100.0 * relative_to)),
+        _ => None,
+    }
+}
+
+/// Replaces whitespace (including line breaks) with single spaces
+fn collapse_whitespace(text: &str)
+-> String
+{
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for ch in text.chars() {
+        // non-breaking spaces are kept
+        if ch.is_whitespace() && ch != '\u{a0}' {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(ch);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+/// Removes the whitespace at the start and the end of the runs and empty runs
+fn trim_runs(mut runs: Vec<(String, InlineStyle)>)
+-> Vec<(String, InlineStyle)>
+{
+    // spaces around line breaks, and double spaces between runs
+    let mut previous_space = true;
+    for run in runs.iter_mut() {
+        let mut text = String::with_capacity(run.0.len());
+        for ch in run.0.chars() {
+            if ch == ' ' && previous_space {
+                continue;
+            }
+            if ch == '\n' && text.ends_with(' ') {
+                text.pop();
+            }
+            previous_space = ch == ' ' || ch == '\n';
+            text.push(ch);
+        }
+        run.0 = text;
+    }
+
+    while let Some(last) = runs.last_mut() {
+        let trimmed = last.0.trim_end_matches(' ').len();
+        last.0.truncate(trimmed);
+        if !last.0.is_empty() {
+            break;
+        }
+        runs.pop();
+    }
+
+    runs.retain(|run| !run.0.is_empty());
+    runs
+}
+
+/// Decodes the entities of a text or attribute value
+fn decode_entities(text: &str)
+-> String
+{
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find(';').filter(|end| *end <= 10);
+        let entity = end.and_then(|end| {
+            let name = &rest[1..end];
+            let ch = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ if name.starts_with("#x") || name.starts_with("#X") => u32::from_str_radix(&name[2..], 16).ok().and_then(::std::char::from_u32),
+                _ if name.starts_with('#') => name[1..].parse().ok().and_then(::std::char::from_u32),
+                _ => None,
+            };
+            ch.map(|ch| (ch, end))
+        });
+
+        match entity {
+            Some((ch, end)) => { decoded.push(ch); rest = &rest[end + 1..]; },
+            None => { decoded.push('&'); rest = &rest[1..]; },
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Open element while parsing
+struct OpenElement {
+    tag: String,
+    attributes: BTreeMap<String, String>,
+    children: Vec<Node>,
+}
+
+/// Parses the HTML into a tree. Missing end tags are closed implicitly (like `<p>` and `<li>`),
+/// end tags without a matching start tag are ignored.
+fn parse_html(html: &str)
+-> Vec<Node>
+{
+    let mut root = Vec::new();
+    let mut stack: Vec<OpenElement> = Vec::new();
+    let mut rest = html;
+
+    fn close(stack: &mut Vec<OpenElement>, root: &mut Vec<Node>)
+    {
+        if let Some(element) = stack.pop() {
+            let node = Node::Element { tag: element.tag, attributes: element.attributes, children: element.children };
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => root.push(node),
+            }
+        }
+    }
+
+    // closes the open element `tag` unless one of the `boundaries` is opened after it
+    fn close_implicitly(stack: &mut Vec<OpenElement>, root: &mut Vec<Node>, tags: &[&str], boundaries: &[&str])
+    {
+        for (depth, element) in stack.iter().enumerate().rev() {
+            if boundaries.contains(&element.tag.as_str()) {
+                return;
+            }
+            if tags.contains(&element.tag.as_str()) {
+                while stack.len() > depth {
+                    close(stack, root);
+                }
+                return;
+            }
+        }
+    }
+
+    while !rest.is_empty() {
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        if text_end > 0 {
+            let text = Node::Text(decode_entities(&rest[..text_end]));
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(text),
+                None => root.push(text),
+            }
+            rest = &rest[text_end..];
+            continue;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map(|end| &comment[end + 3..]).unwrap_or("");
+            continue;
+        }
+
+        let tag_end = match rest.find('>') {
+            Some(end) => end,
+            None => {
+                // not a tag, but text
+                let text = Node::Text(decode_entities(rest));
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(text),
+                    None => root.push(text),
+                }
+                break;
+            },
+        };
+        let tag_source = &rest[1..tag_end];
+        rest = &rest[tag_end + 1..];
+
+        if tag_source.starts_with('!') || tag_source.starts_with('?') {
+            continue;
+        }
+
+        if let Some(name) = tag_source.strip_prefix('/') {
+            let name = name.trim().to_ascii_lowercase();
+            if let Some(depth) = stack.iter().rposition(|element| element.tag == name) {
+                while stack.len() > depth {
+                    close(&mut stack, &mut root);
+                }
+            }
+            continue;
+        }
+
+        let (tag, attributes, self_closing) = parse_tag(tag_source);
+
+        if SKIPPED_ELEMENTS.contains(&tag.as_str()) && !self_closing {
+            // the content is not parsed (e.g. scripts), it is skipped until the end tag
+            let end_tag = format!("</{}", tag);
+            let end = rest.to_ascii_lowercase().find(&end_tag).unwrap_or(rest.len());
+            rest = &rest[end..];
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+            continue;
+        }
+
+        if BLOCK_ELEMENTS.contains(&tag.as_str()) {
+            close_implicitly(&mut stack, &mut root, &["p"], &["div", "li", "td", "th", "blockquote", "section", "article"]);
+        }
+        match tag.as_str() {
+            "li" => close_implicitly(&mut stack, &mut root, &["li"], &["ul", "ol"]),
+            "td" | "th" => close_implicitly(&mut stack, &mut root, &["td", "th"], &["tr", "table"]),
+            "tr" => close_implicitly(&mut stack, &mut root, &["tr"], &["table", "thead", "tbody", "tfoot"]),
+            "thead" | "tbody" | "tfoot" => close_implicitly(&mut stack, &mut root, &["thead", "tbody", "tfoot"], &["table"]),
+            _ => { },
+        }
+
+        stack.push(OpenElement { tag: tag.clone(), attributes, children: Vec::new() });
+        if self_closing || VOID_ELEMENTS.contains(&tag.as_str()) {
+            close(&mut stack, &mut root);
+        }
+    }
+
+    while !stack.is_empty() {
+        close(&mut stack, &mut root);
+    }
+
+    root
+}
+
+/// Parses the name and the attributes of a start tag (the source between `<` and `>`)
+fn parse_tag(source: &str)
+-> (String, BTreeMap<String, String>, bool)
+{
+    let self_closing = source.trim_end().ends_with('/');
+    let source = source.trim_end().trim_end_matches('/');
+    let name_end = source.find(|c: char| c.is_whitespace()).unwrap_or(source.len());
+    let tag = source[..name_end].to_ascii_lowercase();
+
+    let mut attributes = BTreeMap::new();
+    let mut rest = source[name_end..].trim_start();
+    while !rest.is_empty() {
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = if let Some(value) = rest.strip_prefix('=') {
+            let value = value.trim_start();
+            let (value, remaining) = match value.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let end = value[1..].find(quote).map(|end| end + 1).unwrap_or(value.len());
+                    (&value[1..end], value.get(end + 1..).unwrap_or(""))
+                },
+                _ => {
+                    let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                    (&value[..end], &value[end..])
+                },
+            };
+            rest = remaining.trim_start();
+            decode_entities(value)
+        } else {
+            String::new()
+        };
+
+        if !name.is_empty() {
+            attributes.insert(name, value);
+        }
+    }
+
+    (tag, attributes, self_closing)
+}
+
+#[test]
+fn test_parse_html() {
+    let element = |tag: &str, children: Vec<Node>| Node::Element { tag: tag.into(), attributes: BTreeMap::new(), children };
+    let text = |text: &str| Node::Text(text.into());
+
+    // implicitly closed paragraphs and list items, void elements, comments and skipped content
+    let nodes = parse_html("<!DOCTYPE html><p>One<br>two<p>Three &amp; <b>four</b><!-- note --><ul><li>a<li>b</ul><script>if (a < b) {}</script>");
+    assert_eq!(nodes, vec![
+        element("p", vec![text("One"), element("br", vec![]), text("two")]),
+        element("p", vec![text("Three & "), element("b", vec![text("four")])]),
+        element("ul", vec![element("li", vec![text("a")]), element("li", vec![text("b")])]),
+    ]);
+
+    let (tag, attributes, self_closing) = parse_tag(r#"IMG src="a b.png" width=100 alt='x &lt; y' hidden /"#);
+    assert_eq!(tag, "img");
+    assert!(self_closing);
+    assert_eq!(attributes.get("src").map(String::as_str), Some("a b.png"));
+    assert_eq!(attributes.get("width").map(String::as_str), Some("100"));
+    assert_eq!(attributes.get("alt").map(String::as_str), Some("x < y"));
+    assert_eq!(attributes.get("hidden").map(String::as_str), Some(""));
+
+    // rows and cells
+    let nodes = parse_html("<table><tr><th>A<th>B<tr><td>1<td>2</table>");
+    let mut rows = Vec::new();
+    collect_rows(&nodes[0], &mut rows);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].text(), "12");
+}
+
+#[test]
+fn test_css() {
+    assert_eq!(parse_color("#f00"), Some(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None))));
+    assert_eq!(parse_color("#0000FF"), Some(Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None))));
+    assert_eq!(parse_color("rgb(255, 255, 255)"), parse_color("white"));
+    assert_eq!(parse_color("#12"), None);
+    assert_eq!(parse_color("transparent"), None);
+
+    assert_eq!(parse_length("12pt", 100.0, 10.0).map(|l| l.0), Some(12.0));
+    assert_eq!(parse_length("16px", 100.0, 10.0).map(|l| l.0), Some(12.0));
+    assert_eq!(parse_length("50%", 100.0, 10.0).map(|l| l.0), Some(50.0));
+    assert_eq!(parse_length("1.5em", 100.0, 10.0).map(|l| l.0), Some(15.0));
+    assert_eq!(parse_length("large", 100.0, 10.0), None);
+
+    let node = parse_html(r#"<span style="color: red; font-weight: bold">x</span>"#).remove(0);
+    let style = element_style(&node, &InlineStyle { italic: true, .. InlineStyle::default() });
+    assert!(style.bold && style.italic && !style.code);
+    assert_eq!(style.color, parse_color("red"));
+}
+
+#[test]
+fn test_whitespace() {
+    assert_eq!(decode_entities("a &lt;b&gt; &#169; &#xA9; &unknown; & c"), "a <b> \u{a9} \u{a9} &unknown; & c");
+    assert_eq!(collapse_whitespace("  a \n\t b "), " a b ");
+
+    let plain = InlineStyle::default();
+    let bold = InlineStyle { bold: true, .. InlineStyle::default() };
+    let runs = trim_runs(vec![(" a ".into(), plain.clone()), (" b".into(), bold.clone()), (" \n c ".into(), plain.clone()), (" ".into(), bold)]);
+    let texts: Vec<&str> = runs.iter().map(|run| run.0.as_str()).collect();
+    assert_eq!(texts, vec!["a ", "b", "\nc"]);
+}
@@ -33,3 +33,15 @@ impl Into<PdfContentIndex> for SvgIndex {
         self.0
     }
 }
+
+/// Index of an annotation in the document
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PdfAnnotationIndex(pub(crate) usize);
+
+/// Index of a bookmark in the document
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PdfBookmarkIndex(pub(crate) usize);
+
+/// Index of a reusable form XObject in the document, see `define_form()`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct XObjectIndex(pub(crate) usize);
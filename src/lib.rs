@@ -154,6 +154,8 @@
 //!         image_data: Vec::new(),
 //!         image_filter: None, /* does not work yet */
 //!         clipping_bbox: None, /* doesn't work either, untested */
+//!         icc_profile: None,
+//!         rendering_intent: None,
 //!     };
 //!
 //!     let image2 = Image::from(image_file_2);
@@ -181,7 +183,7 @@
 //! let font2 = doc.add_external_font(File::open("assets/fonts/RobotoMedium.ttf").unwrap()).unwrap();
 //!
 //! // text, font size, x from left edge, y from bottom edge, font
-//! current_layer.use_text(text, 48, Mm(200.0), Mm(200.0), &font);
+//! current_layer.use_text(text, 48.0, Mm(200.0), Mm(200.0), &font);
 //!
 //! // For more complex layout of text, you can use functions
 //! // defined on the PdfLayerReference
@@ -191,7 +193,7 @@
 //!
 //!     // setup the general fonts.
 //!     // see the docs for these functions for details
-//!     current_layer.set_font(&font2, 33);
+//!     current_layer.set_font(&font2, 33.0);
 //!     current_layer.set_text_cursor(Mm(10.0), Mm(10.0));
 //!     current_layer.set_line_height(33);
 //!     current_layer.set_word_spacing(3000);
@@ -334,10 +336,13 @@
 #[macro_use] pub extern crate log;
 
 extern crate lopdf;
+extern crate encoding;
 extern crate rusttype;
 extern crate time;
 #[cfg(feature = "embedded_images")]
 pub extern crate image;
+#[cfg(feature = "parallel")]
+extern crate rayon;
 
 pub mod types;
 pub mod scale;
@@ -345,18 +350,27 @@ pub mod errors;
 pub mod utils;
 mod glob_defines;
 pub mod indices;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+#[cfg(feature = "html")]
+pub mod html;
 
 pub use self::errors::Error;
 pub use self::errors::PdfError;
 pub use self::errors::IndexError;
+pub use self::errors::ValidationError;
 pub use rusttype::Error as RusttypeError;
 
 pub use self::scale::{Mm, Pt, Px};
-pub use self::types::pdf_conformance::{CustomPdfConformance, PdfConformance};
-pub use self::types::pdf_document::{PdfDocumentReference, PdfDocument};
-pub use self::types::pdf_metadata::PdfMetadata;
-pub use self::types::pdf_page::{PdfPage, PdfPageReference};
-pub use self::types::pdf_layer::{PdfLayer, PdfLayerReference};
+pub use self::types::pdf_conformance::{CustomPdfConformance, PdfConformance, PdfVersion};
+pub use self::types::pdf_structure::StructureType;
+pub use self::types::pdf_document::{PdfDocumentReference, PdfDocument, Checkpoint, ByteEstimate, ResourceRef, CoordinateValidation};
+pub use self::types::pdf_metadata::{PdfMetadata, Trapped};
+pub use self::types::pdf_page::{PdfPage, PdfPageReference, GuideOrientation};
+pub use self::types::pdf_layer::{PdfLayer, PdfLayerReference, GraphicsState, TextOptions, MissingGlyphPolicy, LayerRole};
+pub use self::types::pdf_reader::{load_lenient, load_encrypted};
 
 pub use self::types::plugins::xmp::xmp_metadata::XmpMetadata;
 pub use self::types::plugins::misc::document_info::DocumentInfo;
@@ -364,11 +378,19 @@ pub use self::types::plugins::misc::document_info::DocumentInfo;
 /// Stub module for 3D content in a PDF
 pub use self::types::plugins::graphics::three_dimensional;
 pub use self::types::plugins::graphics::two_dimensional::font::{
-    Font, BuiltinFont, ExternalFont, TextRenderingMode, IndirectFontRef, DirectFontRef, FontList
+    Font, BuiltinFont, ExternalFont, TextRenderingMode, IndirectFontRef, DirectFontRef, FontList, Ligatures, FeatureTag, FontFlags
 };
-pub use self::types::plugins::graphics::two_dimensional::image::Image;
+pub use self::types::plugins::graphics::two_dimensional::variations::{Tag, FontAxis, NamedInstance, font_axes, named_instances};
+pub use self::types::plugins::graphics::two_dimensional::bidi::{TextDirection, reorder_text};
+pub use self::types::plugins::graphics::two_dimensional::hyphenation::Hyphenator;
+pub use self::types::plugins::graphics::two_dimensional::footnote::FootnoteNumbering;
+pub use self::types::plugins::graphics::two_dimensional::flow::{TextFlow, TableCell, HorizontalAlignment, VerticalAlignment};
+pub use self::types::plugins::graphics::two_dimensional::text_extraction::TextRun;
+pub use self::types::plugins::graphics::two_dimensional::text_encoding::TextEncoding;
+pub use self::types::plugins::graphics::two_dimensional::image::{Image, StreamedImage};
 pub use self::types::plugins::graphics::two_dimensional::line::Line;
 pub use self::types::plugins::graphics::two_dimensional::point::Point;
+pub use self::types::plugins::graphics::two_dimensional::rect::Rect;
 
 pub use self::types::plugins::graphics::color::{
     Color, Rgb, Cmyk, Greyscale, SpotColor, PdfColor, ColorSpace, ColorBits
@@ -385,10 +407,12 @@ pub use self::types::plugins::graphics::icc_profile::{
     IccProfileType, IccProfile, IccProfileRef, IccProfileList
 };
 pub use self::types::plugins::graphics::ocg::{OCGList, OCGRef};
-pub use self::types::plugins::graphics::pattern::{Pattern, PatternRef, PatternList};
+pub use self::types::plugins::graphics::pattern::{Pattern, PatternRef, PatternList, LinearGradient};
 pub use self::types::plugins::graphics::pdf_resources::PdfResources;
+pub use self::types::plugins::graphics::compression::{StreamFilter, ImageCompression};
+pub use self::types::plugins::graphics::raster::{RasterBackend, RasterPage, RasterImage, BlankRasterBackend};
 pub use self::types::plugins::graphics::xobject::{
-    XObject, XObjectList, XObjectRef, ImageXObject, ImageXObjectRef,
+    XObject, XObjectList, XObjectRef, ImageXObject, ImageXObjectRef, StreamedImageXObject,
     ImageFilter, FormXObject, FormXObjectRef, FormType, SMask, GroupXObject,
     GroupXObjectType, ReferenceXObject, OptionalContentGroup, OCGIntent, PostScriptXObject,
 };
@@ -399,5 +423,8 @@ pub use self::types::plugins::media::audio;
 pub use self::types::plugins::media::video;
 /// Stub module for interactive (JavaScript) content, embedded in PDF files
 pub use self::types::plugins::interactive;
-/// Stub module for encryption (passwords). Not implemented yet.
+pub use self::types::plugins::interactive::annotation::{BorderStyle, HighlightMode, NoteIcon, PdfAnnotationReference};
+pub use self::types::plugins::interactive::destination::LinkTarget;
+pub use self::types::plugins::interactive::form::{ButtonAction, SubmitFormat};
+/// Stub module for encryption (passwords). Only decryption is implemented, see `load_encrypted()`.
 pub use self::types::plugins::security;
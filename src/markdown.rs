@@ -0,0 +1,336 @@
+//! Rendering of basic Markdown (feature `markdown`) with a `TextFlow`
+//!
+//! Supported are headings (`#` to `######`), paragraphs, `**bold**`, `*italic*` (or with
+//! underscores), `` `code` ``, bullet lists (`-`, `*`, `+`) and numbered lists (`1.`), nested
+//! by indenting them, quotes (`>`), fenced code blocks (```` ``` ````) and indented code blocks.
+//! Links, images, tables and HTML are written as plain text.
+
+use {Error, BuiltinFont, IndirectFontRef, Mm, PdfDocumentReference, Rect, TextFlow};
+
+/// Fonts and sizes used by `render_markdown()`
+#[derive(Debug, Clone)]
+pub struct MarkdownStyle {
+    pub regular: IndirectFontRef,
+    pub bold: IndirectFontRef,
+    pub italic: IndirectFontRef,
+    pub bold_italic: IndirectFontRef,
+    /// Font for code spans and code blocks, usually a monospaced font
+    pub code: IndirectFontRef,
+    /// Size of the body text in pt
+    pub font_size: f64,
+    /// Sizes of the headings (level 1, 2 and 3 or deeper) in pt
+    pub heading_sizes: [f64; 3],
+    /// Space between two blocks (paragraphs, headings, lists)
+    pub block_spacing: Mm,
+    /// Indentation of each level of lists and quotes
+    pub list_indent: Mm,
+    /// Size of the pages added for the text
+    pub page_width: Mm,
+    pub page_height: Mm,
+    /// Margin of the text on all sides of the pages
+    pub margin: Mm,
+}
+
+impl MarkdownStyle {
+
+    /// Creates a style with the builtin Helvetica and Courier fonts (which are added to
+    /// the document), 11 pt body text and A4 pages
+    pub fn builtin(doc: &PdfDocumentReference)
+    -> Result<Self, Error>
+    {
+        Ok(Self {
+            regular: doc.add_builtin_font(BuiltinFont::Helvetica)?,
+            bold: doc.add_builtin_font(BuiltinFont::HelveticaBold)?,
+            italic: doc.add_builtin_font(BuiltinFont::HelveticaOblique)?,
+            bold_italic: doc.add_builtin_font(BuiltinFont::HelveticaBoldOblique)?,
+            code: doc.add_builtin_font(BuiltinFont::Courier)?,
+            font_size: 11.0,
+            heading_sizes: [22.0, 17.0, 13.0],
+            block_spacing: Mm(3.0),
+            list_indent: Mm(6.0),
+            page_width: Mm(210.0),
+            page_height: Mm(297.0),
+            margin: Mm(20.0),
+        })
+    }
+
+    fn font(&self, style: InlineStyle)
+    -> &IndirectFontRef
+    {
+        match (style.code, style.bold, style.italic) {
+            (true, _, _) => &self.code,
+            (false, true, true) => &self.bold_italic,
+            (false, true, false) => &self.bold,
+            (false, false, true) => &self.italic,
+            (false, false, false) => &self.regular,
+        }
+    }
+}
+
+/// Block of a Markdown document
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Heading { level: usize, text: String },
+    Paragraph { text: String },
+    /// List item with its nesting level (0 for the outermost list) and its marker (`-` or `1.`)
+    ListItem { level: usize, marker: String, text: String },
+    Quote { text: String },
+    Code { text: String },
+}
+
+/// Style of a run of text
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+struct InlineStyle {
+    bold: bool,
+    italic: bool,
+    code: bool,
+}
+
+/// Renders the Markdown text on new pages, which are added to the document
+pub fn render_markdown(doc: &PdfDocumentReference, markdown: &str, style: &MarkdownStyle)
+{
+    let (page, layer) = doc.add_page(style.page_width, style.page_height, "Text");
+    let region = Rect::new(style.margin, style.margin, style.page_width - style.margin * 2.0, style.page_height - style.margin * 2.0);
+    let mut flow = TextFlow::new(doc.get_page(page).get_layer(layer), region);
+    render_markdown_into(&mut flow, markdown, style);
+    flow.finish();
+}
+
+/// Renders the Markdown text into an existing flow, e.g. after other content of the document
+pub fn render_markdown_into(flow: &mut TextFlow, markdown: &str, style: &MarkdownStyle)
+{
+    let base_indent = flow.indent;
+
+    for (idx, block) in parse_blocks(markdown).into_iter().enumerate() {
+        if idx > 0 {
+            flow.add_space(style.block_spacing);
+        }
+        flow.indent = base_indent;
+
+        match block {
+            Block::Heading { level, text } => {
+                let size = style.heading_sizes[(level - 1).min(2)];
+                let runs = parse_inline(&text);
+                let runs: Vec<(&str, &IndirectFontRef)> = runs.iter()
+                    .map(|(text, s)| (text.as_str(), style.font(InlineStyle { bold: true, .. *s }))).collect();
+                flow.add_rich_paragraph(&runs, size, true);
+            },
+            Block::Paragraph { text } => add_inline(flow, &text, style, ""),
+            Block::ListItem { level, marker, text } => {
+                flow.indent = base_indent + style.list_indent * (level + 1) as f64;
+                add_inline(flow, &text, style, &format!("{} ", marker));
+            },
+            Block::Quote { text } => {
+                flow.indent = base_indent + style.list_indent;
+                let runs = parse_inline(&text);
+                let runs: Vec<(&str, &IndirectFontRef)> = runs.iter()
+                    .map(|(text, s)| (text.as_str(), style.font(InlineStyle { italic: true, .. *s }))).collect();
+                flow.add_rich_paragraph(&runs, style.font_size, false);
+            },
+            Block::Code { text } => flow.add_preformatted(text, &style.code, style.font_size * 0.9),
+        }
+    }
+
+    flow.indent = base_indent;
+}
+
+fn add_inline(flow: &mut TextFlow, text: &str, style: &MarkdownStyle, prefix: &str)
+{
+    let runs = parse_inline(text);
+    let mut styled: Vec<(&str, &IndirectFontRef)> = Vec::with_capacity(runs.len() + 1);
+    if !prefix.is_empty() {
+        styled.push((prefix, &style.regular));
+    }
+    styled.extend(runs.iter().map(|(text, s)| (text.as_str(), style.font(*s))));
+    flow.add_rich_paragraph(&styled, style.font_size, false);
+}
+
+/// Splits the document into blocks. Lines of paragraphs, list items and quotes are joined with spaces.
+fn parse_blocks(markdown: &str)
+-> Vec<Block>
+{
+    let mut blocks = Vec::new();
+    let mut lines = markdown.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        let indentation = line.len() - line.trim_start().len();
+
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if trimmed.starts_with("```") {
+            let mut code = Vec::new();
+            for line in lines.by_ref() {
+                if line.trim_start().starts_with("```") {
+                    break;
+                }
+                code.push(line);
+            }
+            blocks.push(Block::Code { text: code.join("\n") });
+            continue;
+        }
+
+        if indentation >= 4 && !is_list_item(trimmed) {
+            let mut code = vec![&line[4..]];
+            while let Some(next) = lines.peek() {
+                match next.strip_prefix("    ") {
+                    Some(code_line) => { code.push(code_line); lines.next(); },
+                    None => break,
+                }
+            }
+            blocks.push(Block::Code { text: code.join("\n") });
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            blocks.push(Block::Heading { level: hashes, text: trimmed[hashes..].trim().trim_end_matches('#').trim().to_string() });
+            continue;
+        }
+
+        // continuation lines of paragraphs, items and quotes
+        let mut continuation = |text: &mut String, quote: bool| {
+            while let Some(next) = lines.peek() {
+                let next_trimmed = next.trim();
+                let ends_block = next_trimmed.is_empty() || next_trimmed.starts_with('#') || next_trimmed.starts_with("```")
+                    || is_list_item(next_trimmed) || (next_trimmed.starts_with('>') != quote);
+                if ends_block {
+                    break;
+                }
+                text.push(' ');
+                text.push_str(next_trimmed.trim_start_matches('>').trim());
+                lines.next();
+            }
+        };
+
+        if let Some((marker, rest)) = list_marker(trimmed) {
+            let mut text = rest.to_string();
+            continuation(&mut text, false);
+            blocks.push(Block::ListItem { level: indentation / 2, marker, text });
+        } else if trimmed.starts_with('>') {
+            let mut text = trimmed.trim_start_matches('>').trim().to_string();
+            continuation(&mut text, true);
+            blocks.push(Block::Quote { text });
+        } else {
+            let mut text = trimmed.to_string();
+            continuation(&mut text, false);
+            blocks.push(Block::Paragraph { text });
+        }
+    }
+
+    blocks
+}
+
+#[inline]
+fn is_list_item(line: &str)
+-> bool
+{
+    list_marker(line).is_some()
+}
+
+/// Returns the marker of a list item (`-` for bullet lists, `1.` for numbered lists) and the text after it
+fn list_marker(line: &str)
+-> Option<(String, &str)>
+{
+    for bullet in &["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(bullet) {
+            return Some(("-".to_string(), rest.trim()));
+        }
+    }
+
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && line[digits..].starts_with(". ") {
+        return Some((line[..digits + 1].to_string(), line[digits + 2..].trim()));
+    }
+
+    None
+}
+
+/// Splits the text of a block into runs of the same style. `**` / `__` toggle bold, `*` / `_`
+/// toggle italic (underscores only at the start or end of a word), text between backticks is code.
+fn parse_inline(text: &str)
+-> Vec<(String, InlineStyle)>
+{
+    let mut runs: Vec<(String, InlineStyle)> = Vec::new();
+    let mut style = InlineStyle::default();
+    let mut current = String::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut idx = 0;
+
+    let mut finish = |current: &mut String, style: InlineStyle| {
+        if !current.is_empty() {
+            runs.push((::std::mem::take(current), style));
+        }
+    };
+
+    while idx < chars.len() {
+        let ch = chars[idx];
+        let next = chars.get(idx + 1).cloned();
+        let previous = if idx > 0 { Some(chars[idx - 1]) } else { None };
+        let word_boundary = !previous.is_some_and(|c| c.is_alphanumeric()) || !next.is_some_and(|c| c.is_alphanumeric());
+
+        match ch {
+            '`' if !style.code => {
+                if let Some(end) = chars[idx + 1..].iter().position(|c| *c == '`') {
+                    finish(&mut current, style);
+                    current = chars[idx + 1..idx + 1 + end].iter().collect();
+                    finish(&mut current, InlineStyle { code: true, .. style });
+                    idx += end + 2;
+                    continue;
+                }
+                current.push(ch);
+            },
+            '*' | '_' if next == Some(ch) && (ch == '*' || word_boundary) => {
+                finish(&mut current, style);
+                style.bold = !style.bold;
+                idx += 2;
+                continue;
+            },
+            '*' | '_' if ch == '*' || word_boundary => {
+                finish(&mut current, style);
+                style.italic = !style.italic;
+            },
+            _ => current.push(ch),
+        }
+        idx += 1;
+    }
+
+    finish(&mut current, style);
+    runs
+}
+
+#[test]
+fn test_parse_blocks() {
+    let markdown = "# Title #\n\nFirst line\nsecond line.\n\n- one\n  - nested\n2. two\n\n> quoted\n> text\n\n```\nfn main() {\n    x\n}\n```\n\n    indented";
+    assert_eq!(parse_blocks(markdown), vec![
+        Block::Heading { level: 1, text: "Title".into() },
+        Block::Paragraph { text: "First line second line.".into() },
+        Block::ListItem { level: 0, marker: "-".into(), text: "one".into() },
+        Block::ListItem { level: 1, marker: "-".into(), text: "nested".into() },
+        Block::ListItem { level: 0, marker: "2.".into(), text: "two".into() },
+        Block::Quote { text: "quoted text".into() },
+        Block::Code { text: "fn main() {\n    x\n}".into() },
+        Block::Code { text: "indented".into() },
+    ]);
+}
+
+#[test]
+fn test_parse_inline() {
+    let plain = InlineStyle::default();
+    let bold = InlineStyle { bold: true, .. plain };
+    let italic = InlineStyle { italic: true, .. plain };
+    let code = InlineStyle { code: true, .. plain };
+
+    assert_eq!(parse_inline("a **bold** and _italic_ `snake_case` word"), vec![
+        ("a ".to_string(), plain),
+        ("bold".to_string(), bold),
+        (" and ".to_string(), plain),
+        ("italic".to_string(), italic),
+        (" ".to_string(), plain),
+        ("snake_case".to_string(), code),
+        (" word".to_string(), plain),
+    ]);
+    assert_eq!(parse_inline("snake_case_name"), vec![("snake_case_name".to_string(), plain)]);
+}
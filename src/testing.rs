@@ -0,0 +1,97 @@
+//! Helpers for visual regression tests (feature `testing`): a page is rendered with a
+//! `RasterBackend` and compared with a reference image, which catches changes in the
+//! geometry of the drawn content that comparing the bytes of the PDF can't.
+//!
+//! Set the environment variable `PRINTPDF_UPDATE_REFERENCES` to write the rendered
+//! images as the new reference images instead of comparing them.
+
+use std::path::{Path, PathBuf};
+use {PdfPageReference, RasterBackend, RasterImage};
+
+/// Environment variable, that replaces the reference images with the rendered page
+const UPDATE_REFERENCES: &str = "PRINTPDF_UPDATE_REFERENCES";
+
+/// Difference between two images: the mean absolute difference of all color components,
+/// from `0.0` (identical) to `1.0` (black and white). Returns `None` if the images don't
+/// have the same size.
+pub fn image_difference(first: &RasterImage, second: &RasterImage)
+-> Option<f64>
+{
+    if first.width != second.width || first.height != second.height || first.pixels.len() != second.pixels.len() {
+        return None;
+    }
+
+    if first.pixels.is_empty() {
+        return Some(0.0);
+    }
+
+    let sum: u64 = first.pixels.iter().zip(second.pixels.iter())
+        .map(|(a, b)| u64::from((i16::from(*a) - i16::from(*b)).unsigned_abs()))
+        .sum();
+
+    Some(sum as f64 / (first.pixels.len() as f64 * 255.0))
+}
+
+/// Renders the page with `backend` at `dpi` and compares it with the reference image
+/// (any image format supported by the `image` crate, usually PNG). Panics if the
+/// reference can't be loaded, the sizes don't match or the `image_difference` is
+/// larger than `tolerance`. A failed comparison writes the rendered page next to the
+/// reference (`ref.png` becomes `ref.actual.png`), to make the difference easy to inspect.
+pub fn assert_page_matches<B, P>(page: &PdfPageReference, backend: &B, dpi: f64, reference: P, tolerance: f64)
+where B: RasterBackend, P: AsRef<Path>
+{
+    let reference = reference.as_ref();
+    let rendered = page.rasterize(backend, dpi).unwrap_or_else(|e| panic!("rendering the page failed: {}", e));
+
+    if ::std::env::var_os(UPDATE_REFERENCES).is_some() {
+        save_image(rendered, reference);
+        return;
+    }
+
+    let expected = ::image::open(reference)
+        .unwrap_or_else(|e| panic!("can't load the reference image {}: {} (set {} to create it)", reference.display(), e, UPDATE_REFERENCES))
+        .to_rgb();
+    let (width, height) = expected.dimensions();
+    let expected = RasterImage { width: ::Px(width as usize), height: ::Px(height as usize), pixels: expected.into_raw() };
+
+    let difference = image_difference(&rendered, &expected);
+    let failure = match difference {
+        None => format!("the page is {}x{} px, the reference image {}x{} px",
+                        rendered.width.0, rendered.height.0, expected.width.0, expected.height.0),
+        Some(difference) if difference > tolerance => format!("the difference is {:.4}, more than the tolerance {:.4}", difference, tolerance),
+        Some(_) => return,
+    };
+
+    let actual = actual_path(reference);
+    save_image(rendered, &actual);
+    panic!("page doesn't match {}: {} (rendered page: {})", reference.display(), failure, actual.display());
+}
+
+/// Path of the rendered image of a failed comparison, `ref.png` becomes `ref.actual.png`
+fn actual_path(reference: &Path)
+-> PathBuf
+{
+    let stem = reference.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = reference.extension().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "png".into());
+    reference.with_file_name(format!("{}.actual.{}", stem, extension))
+}
+
+fn save_image(image: RasterImage, path: &Path)
+{
+    let image = image.into_rgb_image().expect("the backend returned an image with the wrong number of pixels");
+    image.save(path).unwrap_or_else(|e| panic!("can't write {}: {}", path.display(), e));
+}
+
+#[test]
+fn test_image_difference() {
+    use Px;
+
+    let white = RasterImage { width: Px(2), height: Px(1), pixels: vec![255; 6] };
+    let half = RasterImage { width: Px(2), height: Px(1), pixels: vec![255, 255, 255, 0, 0, 0] };
+    let tall = RasterImage { width: Px(1), height: Px(2), pixels: vec![255; 6] };
+
+    assert_eq!(image_difference(&white, &white), Some(0.0));
+    assert_eq!(image_difference(&white, &half), Some(0.5));
+    assert_eq!(image_difference(&white, &tall), None);
+    assert_eq!(actual_path(Path::new("refs/page.png")), Path::new("refs/page.actual.png"));
+}
@@ -1,15 +1,20 @@
 //! Shared types regarding the structure of a PDF.
 
+pub(crate) mod object_ids;
 pub mod pdf_conformance;
 pub mod pdf_document;
 pub mod pdf_layer;
 pub mod pdf_metadata;
 pub mod pdf_page;
+pub mod pdf_reader;
+pub mod pdf_structure;
+pub(crate) mod pdf_writer;
 pub mod plugins;
 
 pub use self::pdf_document::{PdfDocument, PdfDocumentReference};
 pub use self::pdf_layer::{PdfLayer, PdfLayerReference};
 pub use self::pdf_page::{PdfPage, PdfPageReference};
-pub use self::pdf_conformance::{PdfConformance, CustomPdfConformance};
-pub use self::pdf_metadata::PdfMetadata;
+pub use self::pdf_conformance::{PdfConformance, CustomPdfConformance, PdfVersion};
+pub use self::pdf_metadata::{PdfMetadata, Trapped};
+pub use self::pdf_structure::StructureType;
 pub use self::plugins::*;
\ No newline at end of file
@@ -0,0 +1,239 @@
+//! Allocation of object numbers when objects of another document are merged into a document
+//!
+//! The objects of both documents usually use the same numbers (starting at 1), so the imported
+//! objects get new numbers after the highest number of the target and all references between
+//! them are changed to the new numbers. Without this, an imported object would silently replace
+//! the object of the target with the same number.
+
+use lopdf::{Object, ObjectId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use {Error, PdfError};
+
+/// Highest object number that readers are required to support (PDF Reference, Appendix C)
+pub(crate) const MAX_OBJECT_NUMBER: u32 = 8_388_607;
+
+/// Hands out unused object numbers and remembers which number each imported object got
+#[derive(Debug, Clone)]
+pub(crate) struct ObjectIdAllocator {
+    /// Highest object number that is in use
+    max_id: u32,
+    /// New ID of each imported object, by its ID in the source document
+    remapped: HashMap<ObjectId, ObjectId>,
+}
+
+impl ObjectIdAllocator {
+
+    /// Creates an allocator for a document whose highest object number is `max_id`
+    pub(crate) fn new(max_id: u32)
+    -> Self
+    {
+        Self { max_id, remapped: HashMap::new() }
+    }
+
+    /// Returns the highest object number that is in use, including the allocated numbers
+    #[inline]
+    pub(crate) fn max_id(&self)
+    -> u32
+    {
+        self.max_id
+    }
+
+    /// Returns a new object ID, or an error if there are no more numbers
+    pub(crate) fn allocate(&mut self)
+    -> Result<ObjectId, Error>
+    {
+        match self.max_id.checked_add(1) {
+            Some(id) if id <= MAX_OBJECT_NUMBER => {
+                self.max_id = id;
+                Ok((id, 0))
+            },
+            _ => Err(Error::Pdf(PdfError::ObjectIdsExhausted)),
+        }
+    }
+
+    /// Returns the new ID of an object of the source document, a new ID is allocated the first
+    /// time the object is seen
+    pub(crate) fn remap(&mut self, source: ObjectId)
+    -> Result<ObjectId, Error>
+    {
+        if let Some(id) = self.remapped.get(&source) {
+            return Ok(*id);
+        }
+        let id = self.allocate()?;
+        self.remapped.insert(source, id);
+        Ok(id)
+    }
+
+    /// Returns the new ID of an object that was already imported or remapped
+    #[inline]
+    pub(crate) fn get(&self, source: ObjectId)
+    -> Option<ObjectId>
+    {
+        self.remapped.get(&source).cloned()
+    }
+
+    /// Moves the objects of another document into `target` with new IDs and changes the references
+    /// between them. References to objects that aren't part of `objects` become `null`, they
+    /// would otherwise point to unrelated objects of the target.
+    ///
+    /// Nothing is moved if the numbers are exhausted.
+    pub(crate) fn import(&mut self, target: &mut BTreeMap<ObjectId, Object>, objects: BTreeMap<ObjectId, Object>)
+    -> Result<(), Error>
+    {
+        let previous = (self.max_id, self.remapped.clone());
+        for id in objects.keys() {
+            if let Err(e) = self.remap(*id) {
+                self.max_id = previous.0;
+                self.remapped = previous.1;
+                return Err(e);
+            }
+        }
+
+        for (id, mut object) in objects {
+            self.renumber_references(&mut object);
+            target.insert(self.remapped[&id], object);
+        }
+
+        Ok(())
+    }
+
+    /// Changes all references in `object` to the new IDs
+    pub(crate) fn renumber_references(&self, object: &mut Object)
+    {
+        match *object {
+            Object::Reference(id) => {
+                *object = match self.remapped.get(&id) {
+                    Some(new_id) => Object::Reference(*new_id),
+                    None => Object::Null,
+                };
+            },
+            Object::Array(ref mut array) => for item in array { self.renumber_references(item); },
+            Object::Dictionary(ref mut dict) => for (_, value) in dict.iter_mut() { self.renumber_references(value); },
+            Object::Stream(ref mut stream) => for (_, value) in stream.dict.iter_mut() { self.renumber_references(value); },
+            _ => { },
+        }
+    }
+}
+
+/// Removes the objects that can't be reached from `roots` (usually the references of the
+/// trailer) and renumbers the remaining objects from 1, in the order of their old numbers.
+/// Returns the allocator with the new ID of each remaining object, to change references
+/// that are stored outside of `objects`.
+pub(crate) fn compact_objects(objects: &mut BTreeMap<ObjectId, Object>, roots: &[ObjectId])
+-> ObjectIdAllocator
+{
+    let mut reachable = HashSet::new();
+    let mut pending = roots.to_vec();
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(object) = objects.get(&id) {
+            collect_references(object, &mut pending);
+        }
+    }
+
+    let mut allocator = ObjectIdAllocator::new(0);
+    let kept = ::std::mem::take(objects).into_iter()
+        .filter(|(id, _)| reachable.contains(id))
+        .collect::<BTreeMap<ObjectId, Object>>();
+    for id in kept.keys() {
+        // the numbers can't be exhausted, there are less objects than before
+        let _ = allocator.remap(*id);
+    }
+    for (id, mut object) in kept {
+        allocator.renumber_references(&mut object);
+        objects.insert(allocator.remapped[&id], object);
+    }
+
+    allocator
+}
+
+/// Appends the IDs of all objects that `object` references
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>)
+{
+    match *object {
+        Object::Reference(id) => references.push(id),
+        Object::Array(ref array) => for item in array { collect_references(item, references); },
+        Object::Dictionary(ref dict) => for (_, value) in dict.iter() { collect_references(value, references); },
+        Object::Stream(ref stream) => for (_, value) in stream.dict.iter() { collect_references(value, references); },
+        _ => { },
+    }
+}
+
+#[test]
+fn test_import_overlapping_ids() {
+    use lopdf::Object::*;
+
+    let mut target = BTreeMap::<ObjectId, Object>::new();
+    target.insert((1, 0), Array(vec![Reference((2, 0))]));
+    target.insert((2, 0), Integer(2));
+
+    let mut source = BTreeMap::<ObjectId, Object>::new();
+    source.insert((1, 0), Array(vec![Reference((2, 0)), Reference((7, 0))]));
+    source.insert((2, 0), Integer(20));
+    source.insert((3, 0), Array(vec![Reference((1, 0))]));
+
+    let mut allocator = ObjectIdAllocator::new(2);
+    allocator.import(&mut target, source).unwrap();
+
+    // no object of the target was replaced
+    assert_eq!(target.len(), 5);
+    assert_eq!(allocator.max_id(), 5);
+    assert_eq!(format!("{:?}", target[&(1, 0)]), format!("{:?}", Array(vec![Reference((2, 0))])));
+    assert_eq!(format!("{:?}", target[&(2, 0)]), format!("{:?}", Integer(2)));
+
+    assert_eq!(allocator.get((1, 0)), Some((3, 0)));
+    assert_eq!(format!("{:?}", target[&(3, 0)]), format!("{:?}", Array(vec![Reference((4, 0)), Null])));
+    assert_eq!(format!("{:?}", target[&(4, 0)]), format!("{:?}", Integer(20)));
+    assert_eq!(format!("{:?}", target[&(5, 0)]), format!("{:?}", Array(vec![Reference((3, 0))])));
+}
+
+#[test]
+fn test_allocate_exhausted() {
+    let mut allocator = ObjectIdAllocator::new(MAX_OBJECT_NUMBER - 1);
+    assert_eq!(allocator.allocate().unwrap(), (MAX_OBJECT_NUMBER, 0));
+    assert!(allocator.allocate().is_err());
+
+    // a failed import doesn't allocate anything
+    let mut allocator = ObjectIdAllocator::new(MAX_OBJECT_NUMBER - 1);
+    let mut target = BTreeMap::new();
+    let source = vec![((1, 0), Object::Null), ((2, 0), Object::Null)].into_iter().collect();
+    assert!(allocator.import(&mut target, source).is_err());
+    assert!(target.is_empty());
+    assert_eq!(allocator.max_id(), MAX_OBJECT_NUMBER - 1);
+    assert_eq!(allocator.get((1, 0)), None);
+}
+
+#[test]
+fn test_compact_objects() {
+    use lopdf::Object::*;
+
+    // 1: pages, 2 and 5: pages with content (3, 6) and an image (4), 7: unreferenced
+    let mut objects = BTreeMap::<ObjectId, Object>::new();
+    objects.insert((1, 0), Array(vec![Reference((2, 0))]));
+    objects.insert((2, 0), Array(vec![Reference((1, 0)), Reference((3, 0)), Reference((4, 0))]));
+    objects.insert((3, 0), Integer(3));
+    objects.insert((4, 0), Integer(4));
+    objects.insert((5, 0), Array(vec![Reference((1, 0)), Reference((6, 0)), Reference((4, 0))]));
+    objects.insert((6, 0), Integer(6));
+    objects.insert((7, 0), Integer(7));
+
+    // page 5 was removed from the pages: its content goes away, the shared image stays
+    let allocator = compact_objects(&mut objects, &[(1, 0)]);
+    assert_eq!(objects.keys().cloned().collect::<Vec<ObjectId>>(), vec![(1, 0), (2, 0), (3, 0), (4, 0)]);
+    assert_eq!(allocator.get((1, 0)), Some((1, 0)));
+    assert_eq!(allocator.get((5, 0)), None);
+
+    // the page that references the image now
+    objects.insert((5, 0), Array(vec![Reference((4, 0))]));
+    objects.insert((1, 0), Array(vec![Reference((5, 0))]));
+    let allocator = compact_objects(&mut objects, &[(1, 0)]);
+    assert_eq!(allocator.get((4, 0)), Some((2, 0)));
+    assert_eq!(allocator.get((5, 0)), Some((3, 0)));
+    assert_eq!(format!("{:?}", objects), format!("{:?}", vec![
+        ((1, 0), Array(vec![Reference((3, 0))])),
+        ((2, 0), Integer(4)),
+        ((3, 0), Array(vec![Reference((2, 0))])),
+    ].into_iter().collect::<BTreeMap<ObjectId, Object>>()));
+}
@@ -242,6 +242,22 @@ impl PdfConformance {
         }
     }
 
+    /// Detects if the conformance is one of the PDF/X (printing) standards
+    pub fn is_pdf_x(&self)
+    -> bool
+    {
+        matches!(*self,
+            PdfConformance::X1A_2001_PDF_1_3 |
+            PdfConformance::X3_2002_PDF_1_3 |
+            PdfConformance::X1A_2003_PDF_1_4 |
+            PdfConformance::X3_2003_PDF_1_4 |
+            PdfConformance::X4_2010_PDF_1_4 |
+            PdfConformance::X4P_2010_PDF_1_6 |
+            PdfConformance::X5G_2010_PDF_1_6 |
+            PdfConformance::X5PG_2010_PDF_1_6 |
+            PdfConformance::X5N_2010_PDF_1_6)
+    }
+
     /// Check if the conformance level must have an ICC Profile
     pub fn must_have_icc_profile(&self)
     -> bool
@@ -270,3 +286,39 @@ impl PdfConformance {
         }
     }
 }
+
+/// Version of the PDF specification, written into the file header (e.g. `%PDF-1.4`)
+/// and into the `/Version` entry of the document catalog
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(non_camel_case_types)]
+pub enum PdfVersion {
+    /// PDF 1.3 (Acrobat 4)
+    V1_3,
+    /// PDF 1.4 (Acrobat 5) - transparency
+    V1_4,
+    /// PDF 1.5 (Acrobat 6) - object streams, cross-reference streams
+    V1_5,
+    /// PDF 1.6 (Acrobat 7)
+    V1_6,
+    /// PDF 1.7 (Acrobat 8, ISO 32000-1)
+    V1_7,
+    /// PDF 2.0 (ISO 32000-2)
+    V2_0,
+}
+
+impl PdfVersion {
+
+    /// Returns the version number as it is written in the file, e.g. `"1.4"`
+    pub fn as_str(&self)
+    -> &'static str
+    {
+        match *self {
+            PdfVersion::V1_3 => "1.3",
+            PdfVersion::V1_4 => "1.4",
+            PdfVersion::V1_5 => "1.5",
+            PdfVersion::V1_6 => "1.6",
+            PdfVersion::V1_7 => "1.7",
+            PdfVersion::V2_0 => "2.0",
+        }
+    }
+}
@@ -1,20 +1,101 @@
 //! A `PDFDocument` represents the whole content of the file
 
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
 use std::io::Write;
 use std::io::BufWriter;
-use utils::random_character_string_32;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use utils::{random_character_string_32, SeededRandom};
 
 use lopdf;
 use time::OffsetDateTime;
 
 use indices::*;
+use types::pdf_page::{PageCheckpoint, PageContents};
+use types::pdf_structure::StructureTree;
+use types::plugins::interactive::annotation::AnnotationList;
+use types::plugins::interactive::attachment::{Attachments, collection};
+use types::plugins::interactive::bookmark::{BookmarkList, layout_toc};
+use types::plugins::interactive::destination::NamedDestinations;
+use types::plugins::graphics::two_dimensional::bounding_box::{content_bounding_box, text_width};
+use types::plugins::graphics::two_dimensional::text_extraction::replace_text;
+use types::plugins::graphics::two_dimensional::variations::instantiate;
+use types::plugins::graphics::two_dimensional::footnote::{
+    Footnote, FootnoteLine, footnote_lines, fitting_lines, FOOTNOTE_MARGIN, FOOTNOTE_RULE_LENGTH, FOOTNOTE_MAX_HEIGHT
+};
+use types::plugins::interactive::flatten::flatten_annotations;
+use types::plugins::interactive::form::{AcroForm, DefaultAppearance, FieldTrigger};
+use types::plugins::graphics::xobject::{XObjectCache, StreamedXObjects, ReusableForm};
+use types::plugins::graphics::ocg::OCConfigurations;
+use types::plugins::graphics::compression::{compress_stream, compress_images};
+use types::plugins::graphics::icc_profile::tag_untagged_rgb_images;
+use types::plugins::graphics::pdf_resources::merge_into_shared_resources;
+use types::pdf_writer;
+use types::object_ids::compact_objects;
 use {
     ExternalFont, Font, PdfPage, FontList, IccProfileList, PdfMetadata, PdfConformance, IndirectFontRef,
-    DirectFontRef, BuiltinFont, PdfPageReference, Error, Mm
+    DirectFontRef, BuiltinFont, PdfPageReference, Error, ValidationError, PdfVersion, StructureType, Mm,
+    StreamFilter, ImageCompression, IccProfile, Point, LinkTarget, FootnoteNumbering, Line, Pt, Tag, Ligatures, FeatureTag,
+    Trapped, Rect, PdfLayerReference, Color, FontFlags,
 };
 
+/// Approximate size of the parts of a document in bytes, see
+/// `PdfDocumentReference::estimated_size()`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ByteEstimate {
+    /// Content streams of the pages and forms, uncompressed
+    pub content: usize,
+    /// Embedded font files
+    pub fonts: usize,
+    /// Images that are kept in memory, before they are compressed
+    pub images: usize,
+    /// Streamed images, which are read (and copied into the file) while saving
+    pub streamed_images: usize,
+    /// Embedded files, see `PdfDocumentReference::add_attachment()`
+    pub attachments: usize,
+    /// Dictionaries of the pages, resources, annotations, etc.
+    pub overhead: usize,
+}
+
+/// A resource that was added to the document, but isn't used by any content,
+/// see `PdfDocumentReference::unused_resources()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceRef {
+    /// A font that no text is written with
+    Font(IndirectFontRef),
+    /// An XObject (usually an image) of a page that the page doesn't draw, e.g. `X0`
+    XObject { page: PdfPageIndex, name: String },
+    /// A form (see `define_form()`) that isn't drawn on any page or in another form
+    Form(XObjectIndex),
+}
+
+/// Handling of numbers that aren't finite (NaN or infinite, e.g. from a division by zero) in the
+/// content of the pages and of page sizes that aren't positive, see
+/// `PdfDocumentReference::set_coordinate_validation()`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CoordinateValidation {
+    /// Saving fails with `ValidationError::NonFiniteNumber` or `ValidationError::InvalidPageSize`
+    #[default]
+    Strict,
+    /// NaN is replaced with 0, infinite numbers with the largest number a PDF reader supports.
+    /// Page sizes that aren't positive are replaced with the smallest size (3 x 3 pt).
+    Lenient,
+    /// The numbers aren't checked, the content stream is written as it is
+    Off,
+}
+
+impl ByteEstimate {
+    /// Sum of all parts
+    pub fn total(&self)
+    -> usize
+    {
+        self.content + self.fonts + self.images + self.streamed_images + self.attachments + self.overhead
+    }
+}
+
 /// PDF document
 #[derive(Debug, Clone)]
 pub struct PdfDocument {
@@ -30,6 +111,71 @@ pub struct PdfDocument {
     pub document_id: String,
     /// Metadata for this document
     pub metadata: PdfMetadata,
+    /// Should identical fonts and images only be stored once? See `set_dedup()`
+    pub(crate) dedup: bool,
+    /// Should the document be saved using object streams? See `set_object_streams()`
+    pub(crate) object_streams: bool,
+    /// Should the document be saved as a linearized file? See `set_linearized()`
+    pub(crate) linearized: bool,
+    /// Should unreferenced objects be removed when saving? See `set_garbage_collect()`
+    pub(crate) garbage_collect: bool,
+    /// Should pages without content be reported as errors? See `set_forbid_empty_pages()`
+    pub(crate) forbid_empty_pages: bool,
+    /// Generator of the random IDs if a seed was set, see `set_random_seed()`
+    pub(crate) random: Option<SeededRandom>,
+    /// Should the guides of the pages be drawn? See `set_show_guides()`
+    pub(crate) show_guides: bool,
+    /// Handling of NaN and infinite numbers, see `set_coordinate_validation()`
+    pub(crate) coordinate_validation: CoordinateValidation,
+    /// PDF version, `None` if it wasn't set explicitly. See `set_pdf_version()`
+    pub(crate) pdf_version: Option<PdfVersion>,
+    /// Structure tree for tagged PDF, see `PdfLayerReference::begin_tag()`
+    pub(crate) structure: StructureTree,
+    /// Interactive form fields, see `PdfPageReference::add_checkbox()`
+    pub(crate) form: AcroForm,
+    /// Annotations (notes, highlights, ...), see `PdfPageReference::add_note()`
+    pub(crate) annotations: AnnotationList,
+    /// Named destinations (link targets), see `add_named_destination()`
+    pub(crate) destinations: NamedDestinations,
+    /// Embedded files, see `add_attachment()` and `into_portfolio()`
+    pub(crate) attachments: Attachments,
+    /// Layer configurations and radio groups, see `add_oc_configuration()`
+    pub(crate) oc_configurations: OCConfigurations,
+    /// Bookmarks (document outline), see `add_bookmark()`
+    pub(crate) bookmarks: BookmarkList,
+    /// Footnotes, drawn at the bottom of their pages when saving, see `PdfLayerReference::add_footnote()`
+    pub(crate) footnotes: Vec<Footnote>,
+    /// Numbering of the footnotes, see `set_footnote_numbering()`
+    pub(crate) footnote_numbering: FootnoteNumbering,
+    /// Should the same content always produce the same file? See `set_deterministic()`
+    pub(crate) deterministic: bool,
+    /// Decimal places of the numbers in the content streams, see `set_number_precision()`
+    pub(crate) number_precision: Option<u8>,
+    /// Should the content streams be indented and uncompressed? See `set_pretty_content()`
+    pub(crate) pretty_content: bool,
+    /// Spacing and offset from the top of the page of the baseline grid, see `set_baseline_grid()`
+    pub(crate) baseline_grid: Option<(Pt, Pt)>,
+    /// Content that is drawn on several pages, see `define_form()`
+    pub(crate) forms: Vec<ReusableForm>,
+    /// Temporary pages that the forms are drawn on while they are defined, with their IDs.
+    /// They aren't part of `pages`, so that pages added in the meantime get the right indices.
+    pub(crate) form_pages: Vec<(usize, PdfPage)>,
+    /// ID of the next temporary form page
+    next_form_page: usize,
+    /// Page for the layers of finished forms, see `layer_page_mut()`
+    discarded_page: PdfPage,
+    /// Filter for the content streams of the pages, see `set_content_filter()`
+    pub(crate) content_filter: Option<StreamFilter>,
+    /// Compression of the images, see `set_image_filter()`
+    pub(crate) image_filter: Option<ImageCompression>,
+    /// Should RGB images without an ICC profile be tagged as sRGB? See `set_assume_srgb()`
+    pub(crate) assume_srgb: bool,
+    /// Images up to this size are written into the content stream, see `set_inline_image_limit()`
+    pub(crate) inline_image_limit: usize,
+    /// Maximum size of the content streams of a page, see `set_max_content_stream_size()`
+    pub(crate) max_content_stream_size: Option<usize>,
+    /// Should annotations and form fields be drawn into the page content? See `flatten_annotations()`
+    pub(crate) flatten_annotations: bool,
 }
 
 /// Marker struct for a document. Used to make the API a bit nicer.
@@ -41,6 +187,30 @@ pub struct PdfDocumentReference {
     pub(crate) document: Rc<RefCell<PdfDocument>>,
 }
 
+/// Saved state of the pages, layers and content of a document, created by
+/// `PdfDocumentReference::checkpoint()`. Restoring a checkpoint is much cheaper
+/// than cloning the whole document, which makes it useful for "speculative" layout
+/// (place content, measure, roll back if it doesn't fit).
+///
+/// __NOTE__: Fonts are shared on the document level and are not rolled back, neither are
+/// bookmarks, named destinations, attachments, layer configurations and the settings of the
+/// document (e.g. `set_footnote_numbering()`).
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// State of each page at the time of the checkpoint
+    pages: Vec<PageCheckpoint>,
+    /// Structure tree (tagged content and the next free MCIDs) at the time of the checkpoint
+    structure: StructureTree,
+    /// Number of forms defined with `define_form()` at the time of the checkpoint
+    forms: usize,
+    /// Number of form fields at the time of the checkpoint
+    form_fields: usize,
+    /// Number of annotations at the time of the checkpoint
+    annotations: usize,
+    /// Number of footnotes at the time of the checkpoint
+    footnotes: usize,
+}
+
 impl PdfDocument {
 
     /// Creates a new PDF document
@@ -55,7 +225,39 @@ impl PdfDocument {
             fonts: FontList::new(),
             icc_profiles: IccProfileList::new(),
             inner_doc: lopdf::Document::with_version("1.3"),
-            metadata: PdfMetadata::new(document_title, 1, false, PdfConformance::X3_2002_PDF_1_3)
+            metadata: PdfMetadata::new(document_title, 1, false, PdfConformance::X3_2002_PDF_1_3),
+            dedup: false,
+            object_streams: false,
+            linearized: false,
+            garbage_collect: false,
+            forbid_empty_pages: false,
+            random: None,
+            show_guides: false,
+            coordinate_validation: CoordinateValidation::Strict,
+            pdf_version: None,
+            structure: StructureTree::default(),
+            form: AcroForm::default(),
+            annotations: AnnotationList::default(),
+            destinations: NamedDestinations::default(),
+            attachments: Attachments::default(),
+            oc_configurations: OCConfigurations::default(),
+            bookmarks: BookmarkList::default(),
+            footnotes: Vec::new(),
+            footnote_numbering: FootnoteNumbering::default(),
+            deterministic: false,
+            number_precision: None,
+            pretty_content: false,
+            baseline_grid: None,
+            forms: Vec::new(),
+            form_pages: Vec::new(),
+            next_form_page: 0,
+            discarded_page: PdfPage::new(Mm(0.0), Mm(0.0), "Discarded", 0).0,
+            content_filter: None,
+            image_filter: None,
+            assume_srgb: false,
+            inline_image_limit: 0,
+            max_content_stream_size: None,
+            flatten_annotations: false,
         };
 
         let doc_ref = Rc::new(RefCell::new(doc));
@@ -79,12 +281,89 @@ impl PdfDocument {
             icc_profiles: IccProfileList::new(),
             inner_doc: lopdf::Document::with_version("1.3"),
             metadata: PdfMetadata::new(document_title, 1, false, PdfConformance::X3_2002_PDF_1_3),
+            dedup: false,
+            object_streams: false,
+            linearized: false,
+            garbage_collect: false,
+            forbid_empty_pages: false,
+            random: None,
+            show_guides: false,
+            coordinate_validation: CoordinateValidation::Strict,
+            pdf_version: None,
+            structure: StructureTree::default(),
+            form: AcroForm::default(),
+            annotations: AnnotationList::default(),
+            destinations: NamedDestinations::default(),
+            attachments: Attachments::default(),
+            oc_configurations: OCConfigurations::default(),
+            bookmarks: BookmarkList::default(),
+            footnotes: Vec::new(),
+            footnote_numbering: FootnoteNumbering::default(),
+            deterministic: false,
+            number_precision: None,
+            pretty_content: false,
+            baseline_grid: None,
+            forms: Vec::new(),
+            form_pages: Vec::new(),
+            next_form_page: 0,
+            discarded_page: PdfPage::new(Mm(0.0), Mm(0.0), "Discarded", 0).0,
+            content_filter: None,
+            image_filter: None,
+            assume_srgb: false,
+            inline_image_limit: 0,
+            max_content_stream_size: None,
+            flatten_annotations: false,
         };
 
         let doc_ref = Rc::new(RefCell::new(doc));
         PdfDocumentReference { document: doc_ref }
     }
 
+    /// Adds the temporary page of a form that is being defined (see `define_form()`), returns its ID
+    pub(crate) fn add_form_page(&mut self, page: PdfPage)
+    -> usize
+    {
+        let id = self.next_form_page;
+        self.next_form_page += 1;
+        self.form_pages.push((id, page));
+        id
+    }
+
+    /// Removes the temporary page of a finished form, its layer draws on the discarded page afterwards
+    pub(crate) fn remove_form_page(&mut self, id: usize)
+    -> Option<PdfPage>
+    {
+        let index = self.form_pages.iter().position(|page| page.0 == id)?;
+        Some(self.form_pages.remove(index).1)
+    }
+
+    /// The page that the layer draws on: a page of the document or the temporary page of a form.
+    /// A layer reference of a form can be kept after the form is finished, it then draws on
+    /// a page that isn't written and is cleared on every access, so its content is discarded.
+    pub(crate) fn layer_page_mut(&mut self, layer: &PdfLayerReference)
+    -> &mut PdfPage
+    {
+        match layer.form {
+            None => &mut self.pages[layer.page.0],
+            Some(id) => match self.form_pages.iter().position(|page| page.0 == id) {
+                Some(index) => &mut self.form_pages[index].1,
+                None => {
+                    self.discarded_page = PdfPage::new(Mm(0.0), Mm(0.0), "Discarded", 0).0;
+                    &mut self.discarded_page
+                },
+            },
+        }
+    }
+
+    /// Same as `layer_page_mut()`, for reading
+    pub(crate) fn layer_page(&self, layer: &PdfLayerReference)
+    -> &PdfPage
+    {
+        match layer.form {
+            None => &self.pages[layer.page.0],
+            Some(id) => self.form_pages.iter().find(|page| page.0 == id).map(|page| &page.1).unwrap_or(&self.discarded_page),
+        }
+    }
 }
 
 macro_rules! implement_adding_fonts {
@@ -124,15 +403,36 @@ impl PdfDocumentReference {
         self.document.borrow_mut().metadata.document_title = new_title.into();
     }
 
+    /// Adds a custom entry to the document information dictionary (`/Info`), e.g. the ID
+    /// of the document in an asset management system. Setting a key again replaces its value.
+    /// Fails with `PdfError::InvalidInfoKey` if the key isn't a valid PDF name (it must be
+    /// printable ASCII without spaces, delimiters and `#`) or if the key is written from the
+    /// other settings of the document (`Title`, `Trapped`, `CreationDate`, `ModDate`,
+    /// `GTS_PDFXVersion`), use their setters instead.
+    #[inline]
+    pub fn set_info_entry<K, V>(&self, key: K, value: V)
+    -> ::std::result::Result<(), Error> where K: Into<String>, V: Into<String>
+    {
+        self.document.borrow_mut().metadata.document_info.set_entry(key.into(), value.into())
+    }
+
     /// Set the trapping of the document
     #[inline]
     pub fn with_trapping(self, trapping: bool)
     -> Self
     {
-        self.document.borrow_mut().metadata.trapping = trapping;
+        self.document.borrow_mut().metadata.trapping = trapping.into();
         self
     }
 
+    /// Sets the `/Trapped` entry of the document info and the XMP metadata. PDF/X
+    /// documents must be either `Trapped::True` or `Trapped::False`.
+    #[inline]
+    pub fn set_trapped(&self, trapped: Trapped)
+    {
+        self.document.borrow_mut().metadata.trapping = trapped;
+    }
+
     /// Sets the document ID (for comparing two PDF documents for equality)
     #[inline]
     pub fn with_document_id(self, id: String)
@@ -171,6 +471,441 @@ impl PdfDocumentReference {
         self
     }
 
+    /// Enables or disables the deduplication of fonts and images. If enabled, adding
+    /// the same font file twice returns the same font reference and identical images
+    /// (e.g. a logo on every page) are only stored once in the final PDF.
+    /// The content hash is computed once when the font / image is added.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_dedup(&self, dedup: bool)
+    {
+        self.document.borrow_mut().dedup = dedup;
+    }
+
+    /// If enabled, all objects except for streams are packed into compressed object
+    /// streams (`/ObjStm`) on save and a cross-reference stream is written instead of a
+    /// classic cross-reference table. This shrinks documents with many small objects
+    /// significantly, but requires PDF 1.5 (the version in the file header is raised,
+    /// unless a lower version was set with `set_pdf_version()`, then saving fails).
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_object_streams(&self, object_streams: bool)
+    {
+        self.document.borrow_mut().object_streams = object_streams;
+    }
+
+    /// If enabled, the objects that can't be reached from the document catalog or the document
+    /// information are removed when saving, and the remaining objects are numbered without gaps.
+    /// This cleans up objects that were orphaned while editing, e.g. through `inner_mut()`
+    /// or in imported documents.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_garbage_collect(&self, garbage_collect: bool)
+    {
+        self.document.borrow_mut().garbage_collect = garbage_collect;
+    }
+
+    /// Draws the guides and safe areas of the pages (see `PdfPageReference::add_guide()`) on a
+    /// layer "Guides" of each page, which is shown on screen but never printed. Without this,
+    /// the guides are left out of the file, so they can stay in the code for the final output.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_show_guides(&self, show_guides: bool)
+    {
+        self.document.borrow_mut().show_guides = show_guides;
+    }
+
+    /// Takes all random values of the document (the document and instance IDs) from a
+    /// generator with the seed, so that the same program produces the same file in every run,
+    /// e.g. for golden-file tests. Unlike `set_deterministic()`, the IDs don't depend on the
+    /// content and the dates are kept. Replaces the current document ID.
+    #[inline]
+    pub fn set_random_seed(&self, seed: u64)
+    {
+        let mut doc = self.document.borrow_mut();
+        let mut random = SeededRandom::new(seed);
+        doc.document_id = random.character_string_32();
+        doc.metadata.xmp_metadata.document_id = random.character_string_32();
+        doc.random = Some(random);
+    }
+
+    /// If enabled, saving the same content always produces the same bytes, e.g. for
+    /// comparing generated PDFs with reference files. The creation, modification and
+    /// metadata dates are set to the UNIX epoch (they can still be changed afterwards,
+    /// e.g. with `with_mod_date()`) and the random document and instance IDs are replaced
+    /// by a hash of the content of the pages.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_deterministic(&self, deterministic: bool)
+    {
+        let mut doc = self.document.borrow_mut();
+        doc.deterministic = deterministic;
+        if deterministic {
+            let epoch = OffsetDateTime::unix_epoch();
+            doc.metadata.creation_date = epoch;
+            doc.metadata.modification_date = epoch;
+            doc.metadata.metadata_date = epoch;
+        }
+    }
+
+    /// Rounds the numbers (coordinates, matrices, colors, ...) in the content streams of the
+    /// pages to the given number of decimal places. Fewer digits make the files smaller, 3
+    /// decimal places are still far below the resolution of any output device.
+    ///
+    /// Default: numbers are written with full precision
+    #[inline]
+    pub fn set_number_precision(&self, digits: u8)
+    {
+        self.document.borrow_mut().number_precision = Some(digits);
+    }
+
+    /// Sets a baseline grid for the paragraphs of a `TextFlow`: the baselines of their lines are moved
+    /// down to the next line of the grid, and the leading is rounded up to a multiple of the
+    /// spacing, so that the lines of neighbouring columns and facing pages line up. The grid
+    /// lines are `spacing` apart, the first one is `offset` below the top edge of the page.
+    /// A spacing of 0 removes the grid.
+    ///
+    /// Default: no baseline grid
+    #[inline]
+    pub fn set_baseline_grid(&self, spacing: Mm, offset: Mm)
+    {
+        self.document.borrow_mut().baseline_grid = if spacing.0 > 0.0 { Some((spacing.into(), offset.into())) } else { None };
+    }
+
+    /// If enabled, the document is saved as a linearized file ("fast web view"): the
+    /// objects of the first page are written at the beginning of the file, together with
+    /// hint tables that tell the viewer where to find the other pages. This way, a viewer
+    /// can show the first page before the whole file is downloaded.
+    ///
+    /// __NOTE__: Linearization can't be combined with object streams, see `set_object_streams()`
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_linearized(&self, linearized: bool)
+    {
+        self.document.borrow_mut().linearized = linearized;
+    }
+
+    /// Sets the filter for the content streams of the pages.
+    ///
+    /// Default: content streams are written uncompressed
+    #[inline]
+    pub fn set_content_filter(&self, filter: StreamFilter)
+    {
+        self.document.borrow_mut().content_filter = Some(filter);
+    }
+
+    /// For debugging: writes the content streams of the pages uncompressed (ignoring
+    /// `set_content_filter()`), with the operators indented by the nesting of the graphics
+    /// states, text sections and marked content, so that they can be read in a text editor.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_pretty_content(&self, pretty_content: bool)
+    {
+        self.document.borrow_mut().pretty_content = pretty_content;
+    }
+
+    /// Tags all RGB images without an ICC profile (see `Image::with_icc_profile()`) with the
+    /// sRGB profile when saving, instead of writing them with the device color space
+    /// `/DeviceRGB`, whose colors are up to the viewer. Most photos and screenshots are sRGB.
+    ///
+    /// Default: false
+    #[inline]
+    pub fn set_assume_srgb(&self, assume_srgb: bool)
+    {
+        self.document.borrow_mut().assume_srgb = assume_srgb;
+    }
+
+    /// Writes images whose data has at most `max_bytes` bytes as inline images into the content
+    /// stream of the page, instead of as separate image objects. This saves the overhead of an
+    /// object per image for many tiny rasters (icons, bullets, QR modules); the PDF Reference
+    /// recommends inline images only up to 4 KB. Other images are added as usual.
+    ///
+    /// Inline images always use the device color spaces: images with an ICC profile, a palette or
+    /// an alpha channel aren't inlined, and neither `set_assume_srgb()` nor `set_image_filter()`
+    /// applies to them (they are compressed with the content of the page).
+    ///
+    /// Default: 0 (no inline images)
+    #[inline]
+    pub fn set_inline_image_limit(&self, max_bytes: usize)
+    {
+        self.document.borrow_mut().inline_image_limit = max_bytes;
+    }
+
+    /// Splits the content of a page into several content streams of at most `max_bytes` bytes
+    /// (before compression), for pages with huge amounts of content (e.g. dense vector maps)
+    /// that some viewers can't handle in a single stream. The streams are split between two
+    /// operators, an operator that is larger than `max_bytes` gets a stream of its own.
+    ///
+    /// Default: one content stream per page
+    #[inline]
+    pub fn set_max_content_stream_size(&self, max_bytes: usize)
+    {
+        self.document.borrow_mut().max_content_stream_size = Some(max_bytes);
+    }
+
+    /// Flattens the annotations and form fields when saving: their appearances are drawn into
+    /// the content of the pages and the annotations are removed, so that filled in forms become
+    /// static text. Sticky notes are drawn as a simple note icon, links stay clickable.
+    #[inline]
+    pub fn flatten_annotations(&self)
+    {
+        self.document.borrow_mut().flatten_annotations = true;
+    }
+
+    /// Sets the compression of the images. A scanned black and white document, for
+    /// example, is much smaller with `ImageCompression::CcittFaxG4`.
+    ///
+    /// Default: images are compressed with `Flate` in release builds
+    /// (without the `less-optimization` feature), otherwise they are written uncompressed
+    #[inline]
+    pub fn set_image_filter(&self, compression: ImageCompression)
+    {
+        self.document.borrow_mut().image_filter = Some(compression);
+    }
+
+    /// Sets the PDF version, which is written into the file header and the `/Version`
+    /// entry of the catalog. Saving fails if the document uses features that the version
+    /// doesn't support (object streams require 1.5, transparency requires 1.4).
+    ///
+    /// Default: __1.3__ (raised to 1.5 for object streams)
+    #[inline]
+    pub fn set_pdf_version(&self, version: PdfVersion)
+    {
+        self.document.borrow_mut().pdf_version = Some(version);
+    }
+
+    /// If enabled, `validate()` (and therefore `save()`) reports pages without any
+    /// content as an error.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_forbid_empty_pages(&self, forbid_empty_pages: bool)
+    {
+        self.document.borrow_mut().forbid_empty_pages = forbid_empty_pages;
+    }
+
+    /// Sets how NaN and infinite numbers in the content of the pages (coordinates of lines,
+    /// rectangles, text and images, colors, ...) and pages with a zero or negative width or
+    /// height are handled when the document is validated and saved. Such numbers are usually
+    /// the result of a bug in the calculation of the position and make the file unreadable. `CoordinateValidation::Off` skips
+    /// the check, which is a bit faster for documents with a lot of content.
+    ///
+    /// Default: __CoordinateValidation::Strict__
+    #[inline]
+    pub fn set_coordinate_validation(&self, coordinate_validation: CoordinateValidation)
+    {
+        self.document.borrow_mut().coordinate_validation = coordinate_validation;
+    }
+
+    /// Maps a custom structure type (`StructureType::Custom`) to a standard structure type,
+    /// so that PDF readers know how to interpret it (written into the `/RoleMap`)
+    #[inline]
+    pub fn add_role_mapping<S>(&self, custom: S, standard: StructureType) where S: Into<String>
+    {
+        self.document.borrow_mut().structure.role_map.push((custom.into(), standard));
+    }
+
+    /// Registers a named destination: the position on the page, which links with
+    /// `LinkTarget::Named(name)` (or from other documents, `LinkTarget::Remote`) jump to.
+    /// Adding a destination with an existing name replaces the destination.
+    #[inline]
+    pub fn add_named_destination<S>(&self, name: S, page: PdfPageIndex, position: Point) where S: Into<String>
+    {
+        self.document.borrow_mut().destinations.insert(name.into(), page.0, position);
+    }
+
+    /// Adds a named configuration of the layers, which viewers with a layers panel let
+    /// the user switch to. Only the `visible_layers` are visible in the configuration,
+    /// e.g. the labels in one language. The layers are given as `(page, layer)`.
+    pub fn add_oc_configuration<S>(&self, name: S, visible_layers: &[(PdfPageIndex, PdfLayerIndex)]) where S: Into<String>
+    {
+        let visible = visible_layers.iter().map(|(page, layer)| (page.0, layer.0)).collect();
+        self.document.borrow_mut().oc_configurations.configurations.push((name.into(), visible));
+    }
+
+    /// Groups layers like radio buttons: turning one of them on in the layers panel turns
+    /// the others off. Initially only the first layer of the group is visible.
+    pub fn add_layer_radio_group(&self, layers: &[(PdfPageIndex, PdfLayerIndex)])
+    {
+        let group = layers.iter().map(|(page, layer)| (page.0, layer.0)).collect();
+        self.document.borrow_mut().oc_configurations.radio_groups.push(group);
+    }
+
+    /// Embeds a file into the document, which viewers list in their attachments panel.
+    /// Adding a file with an existing name replaces the file.
+    #[inline]
+    pub fn add_attachment<S>(&self, name: S, data: Vec<u8>) where S: Into<String>
+    {
+        self.document.borrow_mut().attachments.insert(name.into(), data);
+    }
+
+    /// Turns the document into a portfolio (PDF 1.7) of the attachments: viewers show a list
+    /// of the files with their name and size instead of the pages. The pages of the document
+    /// are the cover sheet, which is shown by viewers that don't support portfolios.
+    pub fn into_portfolio(self, attachments: Vec<(String, Vec<u8>)>)
+    -> Self
+    {
+        {
+            let mut doc = self.document.borrow_mut();
+            for (name, data) in attachments {
+                doc.attachments.insert(name, data);
+            }
+            doc.attachments.portfolio = true;
+        }
+        self
+    }
+
+    /// Sets whether footnotes are numbered through the document or per page. The numbers
+    /// are assigned when the footnotes are added, so set this before adding footnotes.
+    ///
+    /// Default: __FootnoteNumbering::PerDocument__
+    #[inline]
+    pub fn set_footnote_numbering(&self, numbering: FootnoteNumbering)
+    {
+        self.document.borrow_mut().footnote_numbering = numbering;
+    }
+
+    /// Adds a top level bookmark, which jumps to the target when it is clicked in the
+    /// outline of the viewer. Bookmarks are shown in the order they were added.
+    #[inline]
+    pub fn add_bookmark<S>(&self, title: S, target: LinkTarget)
+    -> PdfBookmarkIndex where S: Into<String>
+    {
+        self.document.borrow_mut().bookmarks.add(title.into(), target, None)
+    }
+
+    /// Adds a bookmark below `parent` in the outline
+    #[inline]
+    pub fn add_child_bookmark<S>(&self, parent: PdfBookmarkIndex, title: S, target: LinkTarget)
+    -> PdfBookmarkIndex where S: Into<String>
+    {
+        self.document.borrow_mut().bookmarks.add(title.into(), target, Some(parent))
+    }
+
+    /// Draws a table of contents of the bookmarks on a new layer of `page`: one line per
+    /// bookmark with the title (indented by its nesting level), leader dots and the page
+    /// number, and a link to the target of the bookmark. Add the bookmarks first.
+    ///
+    /// Returns the number of bookmarks that were drawn, entries that don't fit on the
+    /// page are left out. Bookmarks to other documents have no page number.
+    pub fn generate_toc(&self, font: &IndirectFontRef, font_size: f64, page: PdfPageIndex)
+    -> usize
+    {
+        let lines = {
+            let doc = self.document.borrow();
+            let entries = doc.bookmarks.toc_entries(&doc.destinations);
+            let (width, height) = (doc.pages[page.0].width, doc.pages[page.0].height);
+            layout_toc(entries, width, height, font_size, |text| text_width(&doc.fonts, font, text, font_size))
+        };
+
+        let page = self.get_page(page);
+        let layer = page.add_layer("Table of contents");
+        for line in &lines {
+            let texts = Some(&line.title).into_iter().chain(line.leader.as_ref()).chain(line.page_number.as_ref());
+            for &(x, y, ref text) in texts {
+                layer.use_text(text.clone(), font_size, x.into(), y.into(), font);
+            }
+            page.add_link(line.rect, line.target.clone());
+        }
+
+        lines.len()
+    }
+
+    /// Fills the placeholders of a template: every `{{key}}` on all pages is replaced with the
+    /// value of the key, in the font, size and position of the placeholder. Returns the number
+    /// of replaced placeholders, placeholders without a value stay as they are. A value that
+    /// is wider than its placeholder is compressed horizontally to the width of the placeholder,
+    /// so reserve enough space for the longest value when designing the template.
+    ///
+    /// See `PdfPageReference::replace_text()` for which text can be found.
+    pub fn fill_template(&self, values: &HashMap<String, String>)
+    -> usize
+    {
+        let mut doc = self.document.borrow_mut();
+        let doc = &mut *doc;
+
+        let mut count = 0;
+        for layer in doc.pages.iter_mut().flat_map(|page| page.layers.iter_mut()) {
+            for (key, value) in values {
+                let token = format!("{{{{{}}}}}", key);
+                let (operations, replaced) = replace_text(::std::mem::take(&mut layer.operations), &token, value, true, &doc.fonts);
+                layer.operations = operations;
+                count += replaced;
+            }
+        }
+        count
+    }
+
+    /// Sets the value of a form field: the export value of the selected radio button,
+    /// the selected option of a choice field or `"Yes"` / `"Off"` for a checkbox.
+    /// The field doesn't have to exist yet, unknown fields and invalid values are
+    /// reported by `validate()`.
+    #[inline]
+    pub fn set_field_value<S, V>(&self, field: S, value: V) where S: Into<String>, V: Into<String>
+    {
+        self.document.borrow_mut().form.set_value(field.into(), value.into());
+    }
+
+    /// Sets the font, size (in points) and color of the text of all form fields that don't
+    /// have their own appearance (see `set_field_default_appearance()`). A size of 0 fits
+    /// the text to the height of the field.
+    ///
+    /// Default: __Helvetica__, __0__, __black__
+    #[inline]
+    pub fn set_form_default_appearance(&self, font: BuiltinFont, size: f64, color: Color)
+    {
+        self.document.borrow_mut().form.set_default_appearance(DefaultAppearance::new(font, size, color));
+    }
+
+    /// Sets the font, size and color of the text of a single form field,
+    /// overriding the appearance set with `set_form_default_appearance()`
+    #[inline]
+    pub fn set_field_default_appearance<S>(&self, field: S, font: BuiltinFont, size: f64, color: Color) where S: Into<String>
+    {
+        self.document.borrow_mut().form.set_field_appearance(field.into(), DefaultAppearance::new(font, size, color));
+    }
+
+    /// Sets the JavaScript that formats the value of a form field before it is shown,
+    /// for example `AFNumber_Format(2, 0, 0, 0, "$", true);` for a currency.
+    /// See the module documentation of `interactive::form` for the viewer support.
+    #[inline]
+    pub fn set_field_format_action<S, J>(&self, field: S, js: J) where S: Into<String>, J: Into<String>
+    {
+        self.document.borrow_mut().form.set_action(field.into(), FieldTrigger::Format, js.into());
+    }
+
+    /// Sets the JavaScript that runs on every keystroke in a form field. Acrobat usually
+    /// expects a matching keystroke action for a format action (`AFNumber_Keystroke()`).
+    #[inline]
+    pub fn set_field_keystroke_action<S, J>(&self, field: S, js: J) where S: Into<String>, J: Into<String>
+    {
+        self.document.borrow_mut().form.set_action(field.into(), FieldTrigger::Keystroke, js.into());
+    }
+
+    /// Sets the JavaScript that validates a new value of a form field,
+    /// the script rejects the value by setting `event.rc = false`
+    #[inline]
+    pub fn set_field_validate_action<S, J>(&self, field: S, js: J) where S: Into<String>, J: Into<String>
+    {
+        self.document.borrow_mut().form.set_action(field.into(), FieldTrigger::Validate, js.into());
+    }
+
+    /// Sets the JavaScript that calculates the value of a form field from other fields
+    /// (`event.value = ...`). The fields are recalculated in the order their calculate
+    /// actions were set (the `/CO` array of the form), so set totals after their parts.
+    #[inline]
+    pub fn set_field_calculate_action<S, J>(&self, field: S, js: J) where S: Into<String>, J: Into<String>
+    {
+        self.document.borrow_mut().form.set_action(field.into(), FieldTrigger::Calculate, js.into());
+    }
+
     // ----- ADD FUNCTIONS
 
     /// Create a new pdf page and returns the index of the page
@@ -185,23 +920,403 @@ impl PdfDocumentReference {
         (page_index, pdf_layer_index)
     }
 
+    /// Adds `count` pages of the same size, each with an initial layer with the name, and
+    /// returns their indices. The indices are contiguous, the first layer of every page has
+    /// the index `PdfLayerIndex(0)`.
+    pub fn add_pages<S>(&self, count: usize, x_mm: Mm, y_mm: Mm, initial_layer_name: S)
+    -> Vec<PdfPageIndex> where S: Into<String>
+    {
+        let layer_name = initial_layer_name.into();
+        let mut doc = self.document.borrow_mut();
+        let first = doc.pages.len();
+        for index in first..first + count {
+            doc.pages.push(PdfPage::new(x_mm, y_mm, layer_name.clone(), index).0);
+        }
+        (first..first + count).map(PdfPageIndex).collect()
+    }
+
+    /// Adds a layer with the name to every page of the document (on top of the existing
+    /// layers), e.g. for a watermark or a letterhead. Returns the index of the new layer on
+    /// each page, in page order. Pages that are added later don't get the layer.
+    pub fn add_layer_to_all<S>(&self, layer_name: S)
+    -> Vec<PdfLayerIndex> where S: Into<String>
+    {
+        let layer_name = layer_name.into();
+        let page_count = self.document.borrow().pages.len();
+        (0..page_count).map(|page| self.get_page(PdfPageIndex(page)).add_layer(layer_name.clone()).layer).collect()
+    }
+
+    /// Adds `frame_count` pages of the same size, one for every frame of an animation (e.g. a
+    /// flipbook), and calls `f` with the index of the frame (starting at 0) and the layer of
+    /// its page to draw the frame. Returns the pages of the frames.
+    ///
+    /// ```rust,ignore
+    /// doc.add_animation_frames(24, Mm(100.0), Mm(70.0), "Frame", |frame, layer| {
+    ///     layer.set_line_dash_pattern(LineDashPattern::new(frame as i64, Some(6), Some(6), None, None, None, None));
+    ///     layer.add_shape(line.clone());
+    /// });
+    /// ```
+    pub fn add_animation_frames<S, F>(&self, frame_count: usize, width: Mm, height: Mm, layer_name: S, mut f: F)
+    -> Vec<PdfPageIndex> where S: Into<String>, F: FnMut(usize, &PdfLayerReference)
+    {
+        let layer_name = layer_name.into();
+        (0..frame_count).map(|frame| {
+            let (page, layer) = self.add_page(width, height, layer_name.clone());
+            f(frame, &self.get_page(page).get_layer(layer));
+            page
+        }).collect()
+    }
+
+    /// Defines content that is drawn on many pages (e.g. a logo) once, as a form XObject.
+    /// `f` draws the content onto a layer, in the coordinates of the form. The layer belongs
+    /// to a temporary page, which isn't part of the document (pages that `f` adds to the
+    /// document are kept), so annotations and form fields can't be added to it. Draw the form with `PdfLayerReference::draw_form()`.
+    /// A copy of the layer reference that is kept after `f` returns doesn't draw anywhere.
+    ///
+    /// ```rust,ignore
+    /// let logo = doc.define_form(|layer| layer.add_shape(logo_outline));
+    /// layer.draw_form(&logo, &[CurTransMat::Translate(Mm(10.0), Mm(280.0))]);
+    /// ```
+    pub fn define_form<F>(&self, f: F)
+    -> XObjectIndex where F: FnOnce(&PdfLayerReference)
+    {
+        let (id, layer) = {
+            let mut doc = self.document.borrow_mut();
+            let (pdf_page, layer) = PdfPage::new(Mm(0.0), Mm(0.0), "Form", 0);
+            (doc.add_form_page(pdf_page), layer)
+        };
+
+        f(&PdfLayerReference::form_layer(Rc::downgrade(&self.document), id, layer));
+
+        let mut doc = self.document.borrow_mut();
+        let mut page = doc.remove_form_page(id).unwrap();
+        let operations = page.layers.pop().map(|layer| layer.operations).unwrap_or_default();
+        let zero = Rect { llx: Pt(0.0), lly: Pt(0.0), urx: Pt(0.0), ury: Pt(0.0) };
+        let bbox = content_bounding_box(&operations, &doc.fonts).unwrap_or(zero);
+        doc.forms.push(ReusableForm { operations, resources: page.resources, bbox, knockout: false });
+        XObjectIndex(doc.forms.len() - 1)
+    }
+
+    /// Defines a form (see `define_form()`) that draws the dark modules of a QR code, data matrix
+    /// or barcode, e.g. from the module matrix of a QR code library. `modules` are the rows
+    /// from the top to the bottom, `true` is a dark module. Each module is a `module_size` square,
+    /// horizontal runs of dark modules are merged into one rectangle. The origin of the form
+    /// is the bottom left corner of the code, the fill color is the one of the layer it is
+    /// drawn on (black by default).
+    ///
+    /// A version 10 QR code (57 x 57 modules) needs several hundred rectangles, around
+    /// 20 KB of uncompressed content. Drawn with `PdfLayerReference::draw_form()`, it is written into the file
+    /// once and each placement only adds a few bytes, instead of repeating the rectangles
+    /// for every label on a sheet.
+    pub fn define_module_form(&self, modules: &[Vec<bool>], module_size: Mm)
+    -> XObjectIndex
+    {
+        use lopdf::Object::Real;
+
+        let size: Pt = module_size.into();
+        self.define_form(|layer| {
+            for (row, dark) in modules.iter().enumerate() {
+                let y = (modules.len() - row - 1) as f64 * size.0;
+                let mut column = 0;
+                while column < dark.len() {
+                    if !dark[column] {
+                        column += 1;
+                        continue;
+                    }
+                    let start = column;
+                    while column < dark.len() && dark[column] {
+                        column += 1;
+                    }
+                    let width = (column - start) as f64 * size.0;
+                    layer.push_operation("re", vec![Real(start as f64 * size.0), Real(y), Real(width), Real(size.0)]);
+                }
+            }
+            if modules.iter().any(|row| row.contains(&true)) {
+                layer.push_operation("f", vec![]);
+            }
+        })
+    }
+
+    /// Estimates the size of the document, e.g. to decide about saving with streamed images
+    /// when memory is tight. The estimate is rough: numbers in the content streams are
+    /// assumed to have 8 characters, images are counted before they are compressed and each
+    /// page, annotation, bookmark and form field adds 200 bytes of overhead. Saving needs
+    /// about twice the memory, since the objects of the file are built next to the document.
+    pub fn estimated_size(&self)
+    -> ByteEstimate
+    {
+        let doc = self.document.borrow();
+        let mut estimate = ByteEstimate::default();
+        let mut counted_xobjects = ::std::collections::HashSet::new();
+
+        for page in &doc.pages {
+            for layer in &page.layers {
+                estimate.content += layer.operations.iter().map(estimated_operation_size).sum::<usize>();
+            }
+            let (images, streamed_images, forms) = page.resources.xobjects.estimated_sizes(&mut counted_xobjects);
+            estimate.images += images;
+            estimate.streamed_images += streamed_images;
+            estimate.content += forms;
+        }
+
+        for form in &doc.forms {
+            estimate.content += form.operations.iter().map(estimated_operation_size).sum::<usize>();
+            let (images, streamed_images, forms) = form.resources.xobjects.estimated_sizes(&mut counted_xobjects);
+            estimate.images += images;
+            estimate.streamed_images += streamed_images;
+            estimate.content += forms;
+        }
+
+        estimate.fonts = doc.fonts.font_data_size();
+        estimate.attachments = doc.attachments.data_size();
+        let objects = doc.pages.len() + doc.annotations.len() + doc.bookmarks.len() + doc.form.len() + doc.attachments.len();
+        estimate.overhead = objects * 200;
+        estimate
+    }
+
+    /// Lists the fonts, images and forms that were added to the document, but aren't used by
+    /// the content (yet): fonts that no text is written with, XObjects that their page doesn't
+    /// draw and forms that aren't drawn anywhere. Fonts of pending footnotes count as used.
+    ///
+    /// Unlike `set_garbage_collect()`, nothing is removed, this only reports what could be
+    /// trimmed (unused fonts are still embedded when saving).
+    pub fn unused_resources(&self)
+    -> Vec<ResourceRef>
+    {
+        use lopdf::Object::Name;
+        use std::collections::HashSet;
+
+        let doc = self.document.borrow();
+        let mut used_fonts = doc.footnotes.iter().map(|note| note.font.name.clone()).collect::<HashSet<String>>();
+        let mut used_forms = HashSet::new();
+        let mut unused_xobjects = Vec::new();
+
+        {
+            // the names of the fonts and XObjects that the operations use
+            let mut used_names = |operations: &[lopdf::content::Operation], xobjects: &mut HashSet<String>| {
+                for operation in operations {
+                    match (operation.operator.as_str(), operation.operands.first()) {
+                        ("Tf", Some(Name(name))) => { used_fonts.insert(String::from_utf8_lossy(name).into_owned()); },
+                        ("Do", Some(Name(name))) => { xobjects.insert(String::from_utf8_lossy(name).into_owned()); },
+                        _ => { },
+                    }
+                }
+            };
+
+            let resources = doc.pages.iter().enumerate()
+                .map(|(idx, page)| (Some(idx), page.layers.iter().map(|layer| &layer.operations[..]).collect::<Vec<_>>(), &page.resources))
+                .chain(doc.forms.iter().map(|form| (None, vec![&form.operations[..]], &form.resources)));
+
+            for (page, operations, resources) in resources {
+                // forms that are drawn by a pattern or a soft mask count as drawn
+                used_forms.extend(resources.drawn_forms());
+                let mut drawn = HashSet::new();
+                for operations in operations {
+                    used_names(operations, &mut drawn);
+                }
+                for name in resources.xobjects.names() {
+                    let form = resources.xobjects.form(&name).map(|form| form.0);
+                    let is_drawn = drawn.contains(&name);
+                    match (form, page) {
+                        (Some(form), _) => if is_drawn { used_forms.insert(form); },
+                        (None, Some(page)) => if !is_drawn { unused_xobjects.push(ResourceRef::XObject { page: PdfPageIndex(page), name }); },
+                        // the XObjects of forms are only added when the form draws them
+                        (None, None) => { },
+                    }
+                }
+            }
+        }
+
+        let mut unused = doc.fonts.font_refs().into_iter()
+            .filter(|font| !used_fonts.contains(&font.name))
+            .map(ResourceRef::Font)
+            .collect::<Vec<ResourceRef>>();
+        unused.append(&mut unused_xobjects);
+        unused.extend((0..doc.forms.len()).filter(|form| !used_forms.contains(form)).map(|form| ResourceRef::Form(XObjectIndex(form))));
+        unused
+    }
+
     /// Add a font from a font stream
     pub fn add_external_font<R>(&self, font_stream: R)
     -> ::std::result::Result<IndirectFontRef, Error> where R: ::std::io::Read
     {
-        let last_font_index = { let doc = self.document.borrow(); doc.fonts.len() };
+        let (last_font_index, dedup) = { let doc = self.document.borrow(); (doc.fonts.len(), doc.dedup) };
         let external_font = ExternalFont::new(font_stream, last_font_index)?;
+
+        let font_hash = if dedup { Some(external_font.content_hash()) } else { None };
+        if let Some(hash) = font_hash {
+            let doc = self.document.borrow();
+            // two different fonts can have the same hash, so the data is compared as well
+            let existing = doc.fonts.get_font_by_hash(hash).filter(|existing| match doc.fonts.get_font_data(existing) {
+                Some(Font::ExternalFont(font)) => font.font_bytes == external_font.font_bytes && font.vertical_writing == external_font.vertical_writing,
+                _ => false,
+            });
+            if let Some(existing) = existing {
+                return Ok(existing);
+            }
+        }
+
         let external_font_name = external_font.face_name.clone();
         let font = Font::ExternalFont(external_font);
-        implement_adding_fonts!(&self, external_font_name, font)
+        let font_ref = implement_adding_fonts!(&self, external_font_name, font);
+
+        if let (Some(hash), Ok(ref added)) = (font_hash, &font_ref) {
+            self.document.borrow_mut().fonts.add_font_hash(hash, added.clone());
+        }
+
+        font_ref
+    }
+
+    /// Add an instance of a variable font to the document, e.g. `&[(Tag(*b"wght"), 700.0)]` for
+    /// the bold weight. Axes that aren't given keep their default value. The variations are
+    /// applied before embedding, so the PDF contains a static font. See `font_axes()` and
+    /// `named_instances()` for the axes and the predefined instances of a font.
+    pub fn add_font_instance<R>(&self, mut font_stream: R, axes: &[(Tag, f32)])
+    -> ::std::result::Result<IndirectFontRef, Error> where R: ::std::io::Read
+    {
+        let mut font = Vec::new();
+        font_stream.read_to_end(&mut font)?;
+        let instance = instantiate(&font, axes)?;
+        self.add_external_font(&instance[..])
+    }
+
+    /// Add a built-in font to the document
+    pub fn add_builtin_font(&self, builtin_font: BuiltinFont)
+    -> ::std::result::Result<IndirectFontRef, Error>
+    {
+        let builtin_font_name: &'static str = builtin_font.clone().into();
+        implement_adding_fonts!(&self, builtin_font_name, Font::BuiltinFont(builtin_font))
+    }
+
+    /// Sets which ligatures of an external font are used for the text written with it, the
+    /// default is `Ligatures::Standard`. Has no effect on built-in fonts. Set this before
+    /// writing text with the font, text that was already written keeps its glyphs.
+    pub fn set_ligatures(&self, font: &IndirectFontRef, ligatures: Ligatures)
+    {
+        if let Some(Font::ExternalFont(font)) = self.document.borrow_mut().fonts.get_font_data_mut(font) {
+            font.ligatures = ligatures;
+        }
+    }
+
+    /// Overrides the `/Flags` of the font descriptor of an external font, which are read from
+    /// the font tables by default (see `FontFlags`). Wrong flags make viewers substitute a
+    /// font of the wrong style when they can't use the embedded font.
+    pub fn set_font_flags(&self, font: &IndirectFontRef, flags: FontFlags)
+    {
+        if let Some(Font::ExternalFont(font)) = self.document.borrow_mut().fonts.get_font_data_mut(font) {
+            font.flags = Some(flags);
+        }
+    }
+
+    /// Sets the OpenType features (e.g. `&[Tag(*b"smcp"), Tag(*b"onum")]` for small caps with
+    /// oldstyle figures) that are applied to the text written with an external font. Features
+    /// the font doesn't have are ignored, see `FeatureTag` for the supported features. Like
+    /// `set_ligatures()`, this has to be set before writing text with the font.
+    pub fn set_font_features(&self, font: &IndirectFontRef, features: &[FeatureTag])
+    {
+        if let Some(Font::ExternalFont(font)) = self.document.borrow_mut().fonts.get_font_data_mut(font) {
+            font.features = features.to_vec();
+        }
+    }
+
+    // ----- CHECKPOINT FUNCTIONS
+
+    /// Records the current state of the pages, layers and page resources, so
+    /// that the document can be rolled back later using `restore()`
+    pub fn checkpoint(&self)
+    -> Checkpoint
+    {
+        let doc = self.document.borrow();
+        Checkpoint {
+            pages: doc.pages.iter().map(|page| page.checkpoint()).collect(),
+            structure: doc.structure.clone(),
+            forms: doc.forms.len(),
+            form_fields: doc.form.len(),
+            annotations: doc.annotations.len(),
+            footnotes: doc.footnotes.len(),
+        }
     }
 
-    /// Add a built-in font to the document
-    pub fn add_builtin_font(&self, builtin_font: BuiltinFont)
-    -> ::std::result::Result<IndirectFontRef, Error>
-    {
-        let builtin_font_name: &'static str = builtin_font.clone().into();
-        implement_adding_fonts!(&self, builtin_font_name, Font::BuiltinFont(builtin_font))
+    /// Rolls the document back to a previous checkpoint. All pages, layers, operations, page
+    /// resources, regions, guides, structure elements, forms, form fields, annotations and
+    /// footnotes added after the checkpoint are removed, the footnotes are numbered from the
+    /// checkpoint on again. `PdfPageIndex` / `PdfLayerIndex` / `XObjectIndex` values created
+    /// after the checkpoint are invalid after this call. See `Checkpoint` for what isn't rolled back.
+    pub fn restore(&self, checkpoint: Checkpoint)
+    {
+        let mut doc = self.document.borrow_mut();
+        doc.pages.truncate(checkpoint.pages.len());
+        for (page, page_checkpoint) in doc.pages.iter_mut().zip(checkpoint.pages.iter()) {
+            page.restore(page_checkpoint);
+        }
+        doc.structure = checkpoint.structure;
+        doc.forms.truncate(checkpoint.forms);
+        doc.form.truncate(checkpoint.form_fields);
+        doc.annotations.truncate(checkpoint.annotations);
+        doc.footnotes.truncate(checkpoint.footnotes);
+    }
+
+    /// Draws the footnotes at the bottom of their pages: a short rule and the wrapped notes below
+    /// it. Notes that take more than 40% of the page height continue on the next page, after the
+    /// last page new pages are added for them.
+    fn render_footnotes(&self)
+    {
+        let footnotes = ::std::mem::take(&mut self.document.borrow_mut().footnotes);
+        if footnotes.is_empty() {
+            return;
+        }
+
+        let margin: Pt = FOOTNOTE_MARGIN.into();
+        let mut carried: Vec<FootnoteLine> = Vec::new();
+        let mut page = 0;
+
+        while page < self.document.borrow().pages.len() || !carried.is_empty() {
+            if page == self.document.borrow().pages.len() {
+                let (width, height) = self.document.borrow().pages.last().map(|p| (p.width, p.height)).unwrap();
+                self.add_page(width.into(), height.into(), "Footnotes");
+            }
+
+            let mut lines = ::std::mem::take(&mut carried);
+            let (width, height) = {
+                let doc = self.document.borrow();
+                let (width, height) = (doc.pages[page].width, doc.pages[page].height);
+                let notes: Vec<&Footnote> = footnotes.iter().filter(|note| note.page == page).collect();
+                lines.extend(footnote_lines(&notes, width - margin * 2.0, |font, text, size| text_width(&doc.fonts, font, text, size)));
+                (width, height)
+            };
+
+            if lines.is_empty() {
+                page += 1;
+                continue;
+            }
+
+            let shown = fitting_lines(&lines, height * FOOTNOTE_MAX_HEIGHT);
+            carried = lines.split_off(shown);
+
+            let page_ref = self.get_page(PdfPageIndex(page));
+            let layer = page_ref.add_layer("Footnotes");
+            let mut top = margin + lines.iter().fold(Pt(0.0), |height, line| height + line.leading());
+
+            let rule_y = top + Pt(lines[0].font_size * 0.5);
+            let rule_length: Pt = FOOTNOTE_RULE_LENGTH.into();
+            layer.save_graphics_state();
+            layer.set_outline_thickness(0.5);
+            layer.add_shape(Line {
+                points: vec![(Point { x: margin, y: rule_y }, false), (Point { x: Pt((margin + rule_length).0.min((width - margin).0)), y: rule_y }, false)],
+                is_closed: false,
+                has_fill: false,
+                has_stroke: true,
+                is_clipping_path: false,
+            });
+            layer.restore_graphics_state();
+
+            for line in lines {
+                layer.use_text(line.text.clone(), line.font_size, margin.into(), (top - Pt(line.font_size)).into(), &line.font);
+                top -= line.leading();
+            }
+
+            page += 1;
+        }
     }
 
     // ----- GET FUNCTIONS
@@ -236,18 +1351,105 @@ impl PdfDocumentReference {
         doc.inner_doc
     }
 
+    /// Returns the inner `lopdf::Document`, without dropping the `PdfDocument`. The pages,
+    /// fonts, etc. are only added to it when the document is saved, so until then it mostly
+    /// contains the objects that were added through `inner_mut()`.
+    #[inline]
+    pub fn inner(&self)
+    -> Ref<'_, lopdf::Document>
+    {
+        Ref::map(self.document.borrow(), |doc| &doc.inner_doc)
+    }
+
+    /// Mutable access to the inner `lopdf::Document` (see `inner()`), e.g. to add objects that
+    /// the high-level API doesn't support. The reference has to be dropped before the document
+    /// is used again, the other methods panic while it is borrowed.
+    ///
+    /// __WARNING__: Nothing of the high-level state is synchronized with the changes. Add
+    /// objects with `lopdf::Document::add_object()`, so that the objects written when saving
+    /// get other IDs, and don't rely on the trailer or the version: the `/Root`, `/Info` and
+    /// `/ID` entries and the version are overwritten when saving.
+    #[inline]
+    pub fn inner_mut(&self)
+    -> RefMut<'_, lopdf::Document>
+    {
+        RefMut::map(self.document.borrow_mut(), |doc| &mut doc.inner_doc)
+    }
+
     // --- MISC FUNCTIONS
 
-    /// Checks for invalid settings in the document
+    /// Checks for invalid settings in the document, see `validate()`
     pub fn check_for_errors(&self)
     -> ::std::result::Result<(), Error>
     {
-        // TODO
-        #[cfg(feature = "logging")] {
-            warn!("Checking PDFs for errors is currently not supported!");
+        self.validate().map_err(Error::Validation)
+    }
+
+    /// Checks the structure of the document: all fonts, images and graphics states
+    /// used in the layers must exist and saving / restoring the graphics state must be
+    /// balanced. Returns all problems that were found. This is done automatically
+    /// before the document is saved.
+    pub fn validate(&self)
+    -> ::std::result::Result<(), Vec<ValidationError>>
+    {
+        let doc = self.document.borrow();
+        let mut errors = Vec::new();
+
+        for (idx, page) in doc.pages.iter().enumerate() {
+            let check_numbers = doc.coordinate_validation == CoordinateValidation::Strict;
+            page.validate(idx, &doc.fonts, doc.forbid_empty_pages, check_numbers, &mut errors);
         }
 
-        Ok(())
+        for tag in doc.structure.unclosed_elements() {
+            errors.push(ValidationError::UnclosedTag { tag: tag.name().to_string() });
+        }
+
+        for name in doc.structure.unmapped_types() {
+            errors.push(ValidationError::UnmappedStructureType { name });
+        }
+
+        doc.form.validate(&mut errors);
+        doc.annotations.validate(&doc.destinations, doc.pages.len(), &mut errors);
+        doc.bookmarks.validate(&doc.destinations, doc.pages.len(), &mut errors);
+
+        for (name, page) in doc.destinations.missing_pages(doc.pages.len()) {
+            errors.push(ValidationError::MissingDestinationPage { name: Some(name), page });
+        }
+
+        if doc.metadata.trapping == Trapped::Unknown && doc.metadata.conformance.is_pdf_x() {
+            errors.push(ValidationError::IncompatibleOptions { first: "Unknown trapping", second: "PDF/X conformance" });
+        }
+
+        if doc.linearized && doc.object_streams {
+            errors.push(ValidationError::IncompatibleOptions { first: "Linearization", second: "Object streams" });
+        }
+
+        if let Some(version) = doc.pdf_version {
+            if !doc.structure.is_empty() && version < PdfVersion::V1_4 {
+                errors.push(ValidationError::UnsupportedFeature { feature: "Tagged PDF", required: PdfVersion::V1_4, version });
+            }
+
+            if doc.attachments.portfolio && version < PdfVersion::V1_7 {
+                errors.push(ValidationError::UnsupportedFeature { feature: "Portfolios", required: PdfVersion::V1_7, version });
+            }
+
+            if doc.object_streams && version < PdfVersion::V1_5 {
+                errors.push(ValidationError::UnsupportedFeature { feature: "Object streams", required: PdfVersion::V1_5, version });
+            }
+
+            if doc.fonts.has_cff_fonts() && version < PdfVersion::V1_6 {
+                errors.push(ValidationError::UnsupportedFeature { feature: "OpenType (CFF) fonts", required: PdfVersion::V1_6, version });
+            }
+
+            let uses_transparency = doc.pages.iter().any(|page|
+                page.resources.graphics_states.all_graphics_states.values().any(|(_, gs)| gs.uses_transparency()));
+
+            if uses_transparency && version < PdfVersion::V1_4 {
+                errors.push(ValidationError::UnsupportedFeature { feature: "Transparency", required: PdfVersion::V1_4, version });
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
     /// Tries to match the document to the given conformance.
@@ -263,9 +1465,52 @@ impl PdfDocumentReference {
         Ok(())
     }
 
-    /// Save PDF Document, writing the contents to the target
+    /// Save PDF Document, writing the contents to the target.
+    /// Fails with `Error::Validation` if the document is invalid, see `validate()`
+    ///
+    /// The trailer always contains an `/ID`: the first element is the `document_id`, which
+    /// should stay the same for all versions of the document, the second one is new for every
+    /// save. With `set_deterministic()`, both are derived from the content instead.
+    ///
+    /// With the `parallel` feature, the content streams of the pages are encoded in parallel
+    ///
+    /// If writing to the target fails, `Error::PartialWrite` tells how many bytes and objects
+    /// were written before the failure. The output is incomplete and not a valid PDF file.
+    #[inline]
     pub fn save<W: Write>(self, target: &mut BufWriter<W>)
     -> ::std::result::Result<(), Error>
+    {
+        self.save_with_progress(target, |_, _| { })
+    }
+
+    /// Same as `save()`, but calls `progress(done, total)` after each page was
+    /// added to the file, with the number of finished pages and the page count.
+    /// The file is written to the target after the last page.
+    ///
+    /// ```rust,ignore
+    /// doc.save_with_progress(&mut writer, |done, total| progress_bar.set(done as f64 / total as f64))?;
+    /// ```
+    #[inline]
+    pub fn save_with_progress<W: Write, F>(self, target: &mut BufWriter<W>, mut progress: F)
+    -> ::std::result::Result<(), Error> where F: FnMut(usize, usize)
+    {
+        self.save_pages(target, |done, total| { progress(done, total); true })
+    }
+
+    /// Same as `save()`, but stops with `Error::Cancelled` as soon as `cancel` is set
+    /// (e.g. by another thread, when a request timed out). It is checked after each page,
+    /// nothing has been written to the target if saving was cancelled.
+    #[inline]
+    pub fn save_cancellable<W: Write>(self, target: &mut BufWriter<W>, cancel: &AtomicBool)
+    -> ::std::result::Result<(), Error>
+    {
+        self.save_pages(target, |_, _| !cancel.load(Ordering::Relaxed))
+    }
+
+    /// Saves the document, `page_done(done, total)` is called after each page and
+    /// returns whether saving should continue
+    fn save_pages<W: Write, F>(self, target: &mut BufWriter<W>, mut page_done: F)
+    -> ::std::result::Result<(), Error> where F: FnMut(usize, usize) -> bool
     {
         use lopdf::{Dictionary as LoDictionary,
                     Object as LoObject};
@@ -273,12 +1518,38 @@ impl PdfDocumentReference {
         use std::iter::FromIterator;
         use lopdf::StringFormat::Literal as Literal;
 
+        self.render_footnotes();
+
+        {
+            let mut doc = self.document.borrow_mut();
+            if doc.coordinate_validation == CoordinateValidation::Lenient {
+                doc.pages.iter_mut().for_each(PdfPage::clamp_numbers);
+            }
+        }
+
+        // don't write a corrupt PDF
+        self.check_for_errors()?;
+
         // todo: remove unwrap, handle error
         let mut doc = Rc::try_unwrap(self.document).unwrap().into_inner();
         let pages_id = doc.inner_doc.new_object_id();
 
+        // the document ID stays the same for all versions of a document, the instance ID
+        // changes whenever the document is saved (unless the output is deterministic)
+        let (document_id, instance_id) = if doc.deterministic {
+            let id = content_id(&doc);
+            doc.metadata.xmp_metadata.document_id = id.clone();
+            (id.clone(), id)
+        } else {
+            let instance_id = match doc.random {
+                Some(ref mut random) => random.character_string_32(),
+                None => random_character_string_32(),
+            };
+            (doc.document_id.clone(), instance_id)
+        };
+
         // extra pdf infos
-        let (xmp_metadata, document_info, icc_profile) = doc.metadata.clone().into_obj();
+        let (xmp_metadata, document_info, icc_profile) = doc.metadata.clone().into_obj_with_instance_id(instance_id.clone());
 
         let xmp_metadata_id = match xmp_metadata {
             Some(metadata) => Some(doc.inner_doc.add_object(metadata)),
@@ -321,6 +1592,11 @@ impl PdfDocumentReference {
             catalog.set("Metadata", Reference(metadata_id));
         }
 
+        if let Some(version) = doc.pdf_version {
+            doc.inner_doc.version = version.as_str().to_string();
+            catalog.set("Version", Name(version.as_str().into()));
+        }
+
         let mut pages = LoDictionary::from_iter(vec![
                       ("Type", "Pages".into()),
                       ("Count", Integer(doc.pages.len() as i64)),
@@ -330,15 +1606,30 @@ impl PdfDocumentReference {
         // add all pages with contents
         let mut page_ids = Vec::<LoObject>::new();
 
+        // deduplicated XObjects, shared across all pages
+        let mut xobject_cache = XObjectCache::new();
+        // streamed images, copied into the output by the writer
+        let mut streamed_xobjects = StreamedXObjects::new();
+
+        if doc.show_guides {
+            for page in &mut doc.pages {
+                page.add_guides_layer();
+            }
+        }
+
         // ----- OCG CONTENT
 
         // page index + page names to add the OCG to the /Catalog
-        let page_layer_names: Vec<(usize, Vec<::std::string::String>)> =
+        let page_layer_names: Vec<(usize, Vec<(::std::string::String, bool)>)> =
             doc.pages.iter().map(|page|
                 (page.index, page.layers.iter().map(|layer|
-                    layer.name.clone()).collect()
+                    (layer.name.clone(), layer.printable)).collect()
             )).collect();
 
+        // parent of each layer, for the layer tree in /Order
+        let layer_parents: Vec<Vec<Option<usize>>> =
+            doc.pages.iter().map(|page| page.layers.iter().map(|layer| layer.parent).collect()).collect();
+
         // add optional content groups (layers) to the /Catalog
         let usage_ocg_dict = LoDictionary::from_iter(vec![
             ("Type", Name("OCG".into())),
@@ -348,7 +1639,13 @@ impl PdfDocumentReference {
             ]))),
         ]);
 
+        // layers that are shown on screen, but not printed
+        let mut non_printing_usage_dict = usage_ocg_dict.clone();
+        non_printing_usage_dict.set("Print", Dictionary(LoDictionary::from_iter(vec![("PrintState", Name("OFF".into()))])));
+        non_printing_usage_dict.set("View", Dictionary(LoDictionary::from_iter(vec![("ViewState", Name("ON".into()))])));
+
         let usage_ocg_dict_ref = doc.inner_doc.add_object(Dictionary(usage_ocg_dict));
+        let mut non_printing_usage_ref = None;
 
         let intent_arr = Array(vec![
             Name("View".into()),
@@ -358,21 +1655,30 @@ impl PdfDocumentReference {
         let intent_arr_ref = doc.inner_doc.add_object(intent_arr);
 
         // page index, layer index, reference to OCG dictionary
+        let mut non_printing_ocgs = Vec::new();
         let ocg_list: Vec<(usize, Vec<(usize, lopdf::Object)>)> =
 
         page_layer_names.into_iter().map(|(page_idx, layer_names)|
             (page_idx,
-            layer_names.into_iter().enumerate().map(|(layer_idx, layer_name)|
-                (layer_idx,
-                Reference(doc.inner_doc.add_object(
+            layer_names.into_iter().enumerate().map(|(layer_idx, (layer_name, printable))| {
+                let usage = if printable {
+                    usage_ocg_dict_ref
+                } else {
+                    *non_printing_usage_ref.get_or_insert_with(|| doc.inner_doc.add_object(Dictionary(non_printing_usage_dict.clone())))
+                };
+                let ocg = Reference(doc.inner_doc.add_object(
                     Dictionary(LoDictionary::from_iter(vec![
                         ("Type", Name("OCG".into())),
                         ("Name", String(layer_name.into(), Literal)),
                         ("Intent", Reference(intent_arr_ref)),
-                        ("Usage", Reference(usage_ocg_dict_ref))
+                        ("Usage", Reference(usage))
                     ]))
-                )))
-            ).collect()))
+                ));
+                if !printable {
+                    non_printing_ocgs.push(ocg.clone());
+                }
+                (layer_idx, ocg)
+            }).collect()))
         .collect();
 
         let flattened_ocg_list: Vec<lopdf::Object> =
@@ -380,17 +1686,50 @@ impl PdfDocumentReference {
                 layers.iter().map(|&(_, ref obj)| obj.clone())
             ).collect();
 
-        catalog.set("OCProperties", Dictionary(LoDictionary::from_iter(vec![
+        // OCGs of the layers of each page
+        let page_ocgs: Vec<Vec<lopdf::Object>> =
+            ocg_list.iter().map(|(_, layers)| layers.iter().map(|(_, obj)| obj.clone()).collect()).collect();
+
+        let ocg_order: Vec<lopdf::Object> =
+            page_ocgs.iter().zip(&layer_parents).flat_map(|(ocgs, parents)| layer_order(ocgs, parents, None)).collect();
+
+        let hidden_ocgs = doc.oc_configurations.initially_hidden(&page_ocgs);
+        let visible_ocgs: Vec<lopdf::Object> = flattened_ocg_list.iter()
+            .filter(|ocg| !hidden_ocgs.iter().any(|hidden| hidden.as_reference().ok() == ocg.as_reference().ok()))
+            .cloned()
+            .collect();
+
+        let mut oc_properties = LoDictionary::from_iter(vec![
             ("OCGs", Array(flattened_ocg_list.clone())),
             // optional content configuration dictionary, page 376
             ("D", Dictionary(LoDictionary::from_iter(vec![
-                ("Order", Array(flattened_ocg_list.clone())),
+                ("Order", Array(ocg_order.clone())),
                 // "radio button groups"
-                ("RBGroups", Array(vec![])),
+                ("RBGroups", Array(doc.oc_configurations.radio_button_groups(&page_ocgs))),
                 // initially visible OCG
-                ("ON", Array(flattened_ocg_list)),
+                ("ON", Array(visible_ocgs)),
+                ("OFF", Array(hidden_ocgs)),
             ])))
-        ])));
+        ]);
+
+        if !non_printing_ocgs.is_empty() {
+            // viewers only apply the print usage of the OCGs when printing, if it is listed in /AS
+            let print_usage = LoDictionary::from_iter(vec![
+                ("Event", Name("Print".into())),
+                ("Category", Array(vec![Name("Print".into())])),
+                ("OCGs", Array(non_printing_ocgs)),
+            ]);
+            if let Ok(&mut Dictionary(ref mut default_config)) = oc_properties.get_mut(b"D") {
+                default_config.set("AS", Array(vec![Dictionary(print_usage)]));
+            }
+        }
+
+        let configurations = doc.oc_configurations.configuration_dictionaries(&page_ocgs, &ocg_order);
+        if !configurations.is_empty() {
+            oc_properties.set("Configs", Array(configurations.into_iter().map(Dictionary).collect()));
+        }
+
+        catalog.set("OCProperties", Dictionary(oc_properties));
 
         // ----- END OCG CONTENT (on document level)
 
@@ -406,7 +1745,26 @@ impl PdfDocumentReference {
             font_dict_id = Some(doc.inner_doc.add_object(Dictionary(fonts_dict)));
         }
 
-        for (idx, page) in doc.pages.into_iter().enumerate() {
+        // the reusable forms are written once, the pages find them in the cache. A form can
+        // only draw the forms that were defined before it, so they are written in order.
+        for (index, form) in ::std::mem::take(&mut doc.forms).into_iter().enumerate() {
+            let form_id = form.into_object(&mut doc.inner_doc, font_dict_id, &mut xobject_cache, &mut streamed_xobjects);
+            xobject_cache.forms.insert(XObjectIndex(index), form_id);
+        }
+
+        // the page dictionaries and resources need the document, so they are added one page after another
+        let mut page_dicts = Vec::<LoDictionary>::with_capacity(doc.pages.len());
+        let mut page_resources = Vec::<LoDictionary>::with_capacity(doc.pages.len());
+        let mut page_contents = Vec::<PageContents>::with_capacity(doc.pages.len());
+        // the names of the layers are unique in the document, so that the page resources can be shared
+        let mut first_layer_index = 0;
+
+        for (idx, mut page) in doc.pages.into_iter().enumerate() {
+
+            let crop_box: Vec<lopdf::Object> = match page.crop_box {
+                Some(crop_box) => vec![crop_box.llx.into(), crop_box.lly.into(), crop_box.urx.into(), crop_box.ury.into()],
+                None => vec![0.into(), 0.into(), page.width.into(), page.height.into()],
+            };
 
             let mut p = LoDictionary::from_iter(vec![
                       ("Type", "Page".into()),
@@ -415,34 +1773,139 @@ impl PdfDocumentReference {
                        page.width.into(), page.height.into()].into()),
                       ("TrimBox", vec![0.into(), 0.into(),
                        page.width.into(), page.height.into()].into()),
-                      ("CropBox", vec![0.into(), 0.into(),
-                       page.width.into(), page.height.into()].into()),
+                      ("CropBox", crop_box.into()),
                       ("Parent", Reference(pages_id)) ]);
 
+            if let Some(measurement) = page.measurement.take() {
+                p.set("VP", Array(vec![Dictionary(measurement.into_viewport())]));
+            }
+
             // this will collect the resources needed for rendering this page
             let layers_temp = ocg_list.iter().find(|e| e.0 == idx).unwrap();
-            let (mut resources_page, layer_streams) = page.collect_resources_and_streams(&mut doc.inner_doc, &layers_temp.1);
+            page.resources.layers.first_index = first_layer_index;
+            first_layer_index += layers_temp.1.len();
+            let (resources_page, contents) = page.collect_resources(&mut doc.inner_doc, &layers_temp.1, font_dict_id, doc.number_precision,
+                                                                    &mut xobject_cache, &mut streamed_xobjects);
+
+            page_dicts.push(p);
+            page_resources.push(resources_page);
+            page_contents.push(contents);
+        }
 
-            if let Some(f) = font_dict_id {
-                resources_page.set("Font", Reference(f));
-            }
+        // resources that several pages have in common are inherited from the /Pages node, pages
+        // with conflicting resource names keep their own resources. The first page of a linearized
+        // file has to reference all of its objects, so it doesn't inherit resources.
+        let mut shared_resources = LoDictionary::new();
+        let linearized = doc.linearized;
+        let inherits_resources = page_resources.iter()
+            .map(|resources| !resources.is_empty() && !linearized && merge_into_shared_resources(&mut shared_resources, resources))
+            .collect::<Vec<bool>>();
+        let inheritance = inherits_resources.iter().filter(|inherits| **inherits).count() > 1;
+        if inheritance {
+            pages.set("Resources", Reference(doc.inner_doc.add_object(Dictionary(shared_resources))));
+        }
 
-            if resources_page.len() > 0 {
+        for ((p, resources_page), inherits) in page_dicts.iter_mut().zip(page_resources).zip(inherits_resources) {
+            if !resources_page.is_empty() && (!inheritance || !inherits) {
                 let resources_page_id = doc.inner_doc.add_object(Dictionary(resources_page));
                 p.set("Resources", Reference(resources_page_id));
             }
+        }
+
+        // merge all streams of the individual layers into one big stream per page
+        let page_streams = encode_page_contents(page_contents, doc.number_precision, doc.max_content_stream_size, doc.pretty_content);
+
+        // annotations and the widgets of the form fields are written after the pages,
+        // but the pages already have to reference them
+        let mut widget_ids = doc.form.reserve_widget_ids(&mut doc.inner_doc, page_dicts.len());
+        let mut annotation_ids = doc.annotations.reserve_ids(&mut doc.inner_doc, page_dicts.len());
+
+        let page_count = page_dicts.len();
+        for (idx, (mut p, streams)) in page_dicts.into_iter().zip(page_streams).enumerate() {
+            let mut page_content_ids = Vec::with_capacity(streams.len());
+            for content in streams {
+                let mut merged_layer_stream = lopdf::Stream::new(lopdf::Dictionary::new(), content).with_compression(false);
+                if let Some(filter) = doc.content_filter {
+                    if !doc.pretty_content {
+                        compress_stream(&mut merged_layer_stream, filter);
+                    }
+                }
+                #[cfg(feature = "logging")] {
+                    debug!("Page {} of {} finalized ({} bytes of content)", idx + 1, page_count, merged_layer_stream.content.len());
+                }
+                page_content_ids.push(Reference(doc.inner_doc.add_object(merged_layer_stream)));
+            }
+
+            if let Some(struct_parents) = doc.structure.struct_parents(idx) {
+                p.set("StructParents", Integer(struct_parents));
+            }
+
+            let mut annotations = ::std::mem::take(&mut annotation_ids.pages[idx]);
+            annotations.append(&mut widget_ids.pages[idx]);
+            if !annotations.is_empty() {
+                p.set("Annots", Array(annotations));
+            }
+
+            if page_content_ids.len() == 1 {
+                p.set("Contents", page_content_ids.remove(0));
+            } else {
+                p.set("Contents", Array(page_content_ids));
+            }
+            page_ids.push(Reference(doc.inner_doc.add_object(p)));
+            if !page_done(idx + 1, page_count) {
+                return Err(Error::Cancelled);
+            }
+        }
+
+        let page_object_ids: Vec<lopdf::ObjectId> = page_ids.iter().filter_map(|p| p.as_reference().ok()).collect();
+
+        // ----- ANNOTATIONS AND INTERACTIVE FORM
+
+        let layer_ocgs: Vec<Vec<lopdf::ObjectId>> =
+            page_ocgs.iter().map(|ocgs| ocgs.iter().filter_map(|ocg| ocg.as_reference().ok()).collect()).collect();
+        doc.annotations.into_with_document(&mut doc.inner_doc, annotation_ids, &page_object_ids, &layer_ocgs);
+
+        let mut names = LoDictionary::new();
+        if let Some(dests_id) = doc.destinations.into_with_document(&mut doc.inner_doc, &page_object_ids) {
+            names.set("Dests", Reference(dests_id));
+        }
+
+        let portfolio = doc.attachments.portfolio;
+        if let Some(files_id) = doc.attachments.into_with_document(&mut doc.inner_doc) {
+            names.set("EmbeddedFiles", Reference(files_id));
+            if portfolio {
+                catalog.set("Collection", Dictionary(collection()));
+            }
+            catalog.set("PageMode", Name("UseAttachments".into()));
+        }
+
+        if !names.is_empty() {
+            catalog.set("Names", Dictionary(names));
+        }
+
+        if !doc.bookmarks.is_empty() {
+            let outlines_id = doc.bookmarks.into_with_document(&mut doc.inner_doc, &page_object_ids);
+            catalog.set("Outlines", Reference(outlines_id));
+        }
 
-            // merge all streams of the individual layers into one big stream
-            let mut layer_streams_merged_vec = Vec::<u8>::new();
-            for mut stream in layer_streams {
-                layer_streams_merged_vec.append(&mut stream.content);
+        if !doc.form.is_empty() {
+            let acro_form = doc.form.into_with_document(&mut doc.inner_doc, widget_ids, &page_object_ids);
+            if !doc.flatten_annotations {
+                catalog.set("AcroForm", Dictionary(acro_form));
             }
+        }
+
+        if doc.flatten_annotations {
+            let inherited_resources = pages.get(b"Resources").and_then(|r| r.as_reference()).ok();
+            flatten_annotations(&mut doc.inner_doc, &page_object_ids, inherited_resources);
+        }
 
-            let merged_layer_stream = lopdf::Stream::new(lopdf::Dictionary::new(), layer_streams_merged_vec).with_compression(false);
-            let page_content_id = doc.inner_doc.add_object(merged_layer_stream);
+        // ----- STRUCTURE TREE (tagged PDF)
 
-            p.set("Contents", Reference(page_content_id));
-            page_ids.push(Reference(doc.inner_doc.add_object(p)))
+        if !doc.structure.is_empty() {
+            let struct_tree_root_id = doc.structure.into_with_document(&mut doc.inner_doc, &page_object_ids);
+            catalog.set("StructTreeRoot", Reference(struct_tree_root_id));
+            catalog.set("MarkInfo", Dictionary(LoDictionary::from_iter(vec![("Marked", Boolean(true))])));
         }
 
         pages.set::<_, LoObject>("Kids".to_string(), page_ids.into());
@@ -453,22 +1916,75 @@ impl PdfDocumentReference {
 
         // save inner document
         let catalog_id = doc.inner_doc.add_object(catalog);
-        let instance_id = random_character_string_32();
 
         doc.inner_doc.trailer.set("Root", Reference(catalog_id));
         doc.inner_doc.trailer.set("Info", Reference(document_info_id));
         doc.inner_doc.trailer.set("ID", Array(vec![
-                                            String(doc.document_id.as_bytes().to_vec(), Literal),
-                                            String(instance_id.as_bytes().to_vec(), Literal)
+                                            String(document_id.into_bytes(), Literal),
+                                            String(instance_id.into_bytes(), Literal)
                                         ]));
 
+        if doc.assume_srgb {
+            tag_untagged_rgb_images(&mut doc.inner_doc, IccProfile::srgb());
+        }
+
+        if let Some(compression) = doc.image_filter {
+            compress_images(&mut doc.inner_doc, compression);
+        }
+
+        if doc.garbage_collect {
+            collect_garbage(&mut doc.inner_doc, &mut streamed_xobjects);
+        }
+
         // does nothing in debug mode, optimized in release mode
         Self::optimize(&mut doc.inner_doc);
-        doc.inner_doc.save_to(target)?;
 
+        #[cfg(feature = "logging")] {
+            info!("Writing document with {} pages and {} objects", page_count, doc.inner_doc.objects.len());
+        }
+
+        let total_objects = doc.inner_doc.objects.len();
+        let mut out = pdf_writer::CountingWriter::new(target);
+        // lopdf doesn't report how many objects it wrote
+        let (result, counted) = if doc.linearized {
+            (pdf_writer::save_linearized(&mut doc.inner_doc, &streamed_xobjects, &mut out), true)
+        } else if doc.object_streams {
+            (pdf_writer::save_with_object_streams(&mut doc.inner_doc, &streamed_xobjects, &mut out), true)
+        } else if !streamed_xobjects.is_empty() {
+            (pdf_writer::save_with_streamed_objects(&mut doc.inner_doc, &streamed_xobjects, &mut out), true)
+        } else {
+            (doc.inner_doc.save_to(&mut out), false)
+        };
+
+        result.map_err(|error| Error::PartialWrite {
+            bytes: out.written,
+            objects: if counted { Some(out.objects) } else { None },
+            total_objects,
+            error,
+        })
+    }
+
+    /// Saves the document into a file, see `save()`. The file is created or truncated, its
+    /// directory must already exist, otherwise `Error::Io` is returned.
+    pub fn save_to_path<P: AsRef<Path>>(self, path: P)
+    -> ::std::result::Result<(), Error>
+    {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        self.save(&mut writer)?;
+        writer.flush()?;
         Ok(())
     }
 
+    /// Saves the document into memory and returns the bytes of the PDF file, see `save()`
+    pub fn save_to_bytes(self)
+    -> ::std::result::Result<Vec<u8>, Error>
+    {
+        let mut writer = BufWriter::new(Vec::new());
+        self.save(&mut writer)?;
+        writer.into_inner().map_err(|e| Error::Io(e.into_error()))
+    }
+
     #[cfg(any(debug_assertions, feature="less-optimization"))]
     #[inline]
     fn optimize(_: &mut lopdf::Document) { }
@@ -482,3 +1998,746 @@ impl PdfDocumentReference {
         doc.compress();
     }
 }
+
+/// Removes the objects that aren't referenced (directly or indirectly) by the trailer and
+/// renumbers the others, see `PdfDocumentReference::set_garbage_collect()`
+fn collect_garbage(doc: &mut lopdf::Document, streamed: &mut StreamedXObjects)
+{
+    let roots = doc.trailer.iter().filter_map(|(_, value)| value.as_reference().ok()).collect::<Vec<lopdf::ObjectId>>();
+    #[cfg(feature = "logging")]
+    let object_count = doc.objects.len();
+    let allocator = compact_objects(&mut doc.objects, &roots);
+
+    for (_, value) in doc.trailer.iter_mut() {
+        allocator.renumber_references(value);
+    }
+    *streamed = streamed.drain().filter_map(|(id, image)| allocator.get(id).map(|id| (id, image))).collect();
+    doc.max_id = allocator.max_id();
+
+    #[cfg(feature = "logging")] {
+        info!("Removed {} unreferenced objects", object_count - doc.objects.len());
+    }
+}
+
+/// Approximate length of the operation in a content stream
+fn estimated_operation_size(operation: &lopdf::content::Operation)
+-> usize
+{
+    fn object_size(object: &lopdf::Object) -> usize {
+        use lopdf::Object::*;
+        match *object {
+            Name(ref name) => name.len() + 1,
+            String(ref string, lopdf::StringFormat::Literal) => string.len() + 2,
+            String(ref string, lopdf::StringFormat::Hexadecimal) => string.len() * 2 + 2,
+            Array(ref array) => array.iter().map(|o| object_size(o) + 1).sum::<usize>() + 2,
+            Dictionary(ref dict) => dict.iter().map(|(key, value)| key.len() + object_size(value) + 2).sum::<usize>() + 4,
+            _ => 8,
+        }
+    }
+
+    operation.operator.len() + 1 + operation.operands.iter().map(|o| object_size(o) + 1).sum::<usize>()
+}
+
+/// Builds the `/Order` array of the optional content groups of a page: each group
+/// with the parent `parent` is followed by an array of its children, if it has any
+fn layer_order(ocgs: &[lopdf::Object], parents: &[Option<usize>], parent: Option<usize>)
+-> Vec<lopdf::Object>
+{
+    let mut order = Vec::new();
+    for (idx, ocg) in ocgs.iter().enumerate().filter(|(idx, _)| parents.get(*idx).cloned().flatten() == parent) {
+        order.push(ocg.clone());
+        let children = layer_order(ocgs, parents, Some(idx));
+        if !children.is_empty() {
+            order.push(lopdf::Object::Array(children));
+        }
+    }
+    order
+}
+
+/// Hashes the title and the content of all pages into a 32 character ID, which
+/// replaces the random IDs if the document is saved with `set_deterministic()`. MD5 is used,
+/// so that the ID doesn't change with the Rust version (unlike the `DefaultHasher`).
+fn content_id(doc: &PdfDocument)
+-> ::std::string::String
+{
+    use types::plugins::security::encrypt::md5;
+
+    // the lengths separate the strings, e.g. title "ab" + layer "c" from "a" + "bc"
+    let mut data = Vec::<u8>::new();
+    let mut add = |bytes: &[u8]| {
+        data.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        data.extend_from_slice(bytes);
+    };
+    add(doc.metadata.document_title.as_bytes());
+    for page in &doc.pages {
+        add(&page.width.0.to_bits().to_be_bytes());
+        add(&page.height.0.to_bits().to_be_bytes());
+        for layer in &page.layers {
+            add(layer.name.as_bytes());
+            add(format!("{:?}", layer.operations).as_bytes());
+        }
+    }
+    md5(&data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Encodes the content streams of all pages, each page is split into streams of at most
+/// `max_size` bytes. The pages are independent of each other,
+/// so with the `parallel` feature, they are encoded on the rayon thread pool
+#[cfg(feature = "parallel")]
+fn encode_page_contents(page_contents: Vec<PageContents>, precision: Option<u8>, max_size: Option<usize>, pretty: bool)
+-> Vec<Vec<Vec<u8>>>
+{
+    use rayon::prelude::*;
+    page_contents.into_par_iter().map(|contents| encode_page(contents, precision, max_size, pretty)).collect()
+}
+
+/// Encodes the content streams of all pages, one after another
+#[cfg(not(feature = "parallel"))]
+fn encode_page_contents(page_contents: Vec<PageContents>, precision: Option<u8>, max_size: Option<usize>, pretty: bool)
+-> Vec<Vec<Vec<u8>>>
+{
+    page_contents.into_iter().map(|contents| encode_page(contents, precision, max_size, pretty)).collect()
+}
+
+/// Encodes the content streams of a single page
+fn encode_page(contents: PageContents, precision: Option<u8>, max_size: Option<usize>, pretty: bool)
+-> Vec<Vec<u8>>
+{
+    match max_size {
+        Some(max_size) => contents.encode_split(precision, max_size, pretty),
+        None => vec![contents.encode(precision, pretty)],
+    }
+}
+
+#[test]
+fn test_layer_order()
+{
+    use lopdf::Object::*;
+
+    // 0: Base, 1: Markup (child of 0), 2: Dimensions (child of 1), 3: Notes (child of 0), 4: Top
+    let ocgs = (1..6).map(|id| Reference((id, 0))).collect::<Vec<_>>();
+    let parents = vec![None, Some(0), Some(1), Some(0), None];
+    let order = layer_order(&ocgs, &parents, None);
+    assert_eq!(format!("{:?}", order), format!("{:?}", vec![
+        Reference((1, 0)),
+        Array(vec![Reference((2, 0)), Array(vec![Reference((3, 0))]), Reference((4, 0))]),
+        Reference((5, 0)),
+    ]));
+}
+
+#[test]
+fn test_checkpoint_restore()
+{
+    use std::iter::FromIterator;
+    use {BuiltinFont, GuideOrientation, Line, Point, StructureType};
+
+    let (doc, page1, layer1) = PdfDocument::new("checkpoint", Mm(210.0), Mm(297.0), "Layer 1");
+    let layer = doc.get_page(page1).get_layer(layer1);
+    layer.set_outline_thickness(1.0);
+
+    let checkpoint = doc.checkpoint();
+
+    layer.add_shape(Line::from_iter(vec![
+        (Point::new(Mm(10.0), Mm(10.0)), false),
+        (Point::new(Mm(20.0), Mm(20.0)), false),
+    ]));
+    doc.get_page(page1).add_layer("Layer 2");
+    doc.add_page(Mm(210.0), Mm(297.0), "Page 2, Layer 1");
+
+    doc.restore(checkpoint);
+
+    {
+        let inner = doc.document.borrow();
+        assert_eq!(inner.pages.len(), 1);
+        assert_eq!(inner.pages[0].layers.len(), 1);
+        assert_eq!(inner.pages[0].layers[0].operations.len(), 1);
+    }
+
+    // regions, guides, structure elements, forms and footnote numbers are rolled back as well
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let page = doc.get_page(page1);
+    let region = Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(100.0), ury: Pt(100.0) };
+    page.define_region("body", region);
+    let checkpoint = doc.checkpoint();
+
+    page.define_region("body", Rect { urx: Pt(50.0), .. region });
+    page.define_region("sidebar", region);
+    page.add_guide(GuideOrientation::Vertical, Mm(20.0));
+    layer.begin_tag(StructureType::P, None);
+    layer.begin_text_section();
+    assert_eq!(layer.add_footnote("first", &font, 10.0), "1");
+    layer.end_text_section();
+    layer.end_tag();
+    doc.define_form(|_| { });
+
+    doc.restore(checkpoint);
+    assert_eq!(page.region("body"), Some(region));
+    assert_eq!(page.region("sidebar"), None);
+    {
+        let inner = doc.document.borrow();
+        assert!(inner.pages[0].guides.is_empty());
+        assert!(inner.structure.is_empty());
+        assert!(inner.forms.is_empty());
+    }
+    layer.begin_text_section();
+    assert_eq!(layer.add_footnote("again", &font, 10.0), "1");
+}
+
+#[test]
+fn test_dedup_external_fonts()
+{
+    let font_bytes: &[u8] = include_bytes!("../../assets/fonts/RobotoMedium.ttf");
+    let doc = PdfDocument::empty("dedup");
+    doc.set_dedup(true);
+
+    let font1 = doc.add_external_font(font_bytes).unwrap();
+    let font2 = doc.add_external_font(font_bytes).unwrap();
+
+    assert_eq!(font1, font2);
+    assert_eq!(doc.document.borrow().fonts.len(), 1);
+
+    // a different font with the same hash isn't replaced by the first one
+    let mut other_bytes = font_bytes.to_vec();
+    other_bytes.extend_from_slice(&[0; 4]);
+    let other_hash = ExternalFont::new(&other_bytes[..], 1).unwrap().content_hash();
+    doc.document.borrow_mut().fonts.add_font_hash(other_hash, font1.clone());
+    let other = doc.add_external_font(&other_bytes[..]).unwrap();
+    assert_ne!(other, font1);
+    assert_eq!(doc.document.borrow().fonts.len(), 2);
+}
+
+#[test]
+fn test_validate()
+{
+    let (doc, page1, layer1) = PdfDocument::new("Validation", Mm(210.0), Mm(297.0), "Layer 1");
+    assert!(doc.validate().is_ok());
+
+    doc.set_forbid_empty_pages(true);
+    assert_eq!(doc.validate(), Err(vec![ValidationError::EmptyPage { page: 0 }]));
+
+    let layer = doc.get_page(page1).get_layer(layer1);
+    layer.save_graphics_state();
+    layer.set_font(&IndirectFontRef::new("F99"), 12.0);
+
+    assert_eq!(doc.validate(), Err(vec![
+        ValidationError::MissingFont { page: 0, layer: 0, font: "F99".into() },
+        ValidationError::UnbalancedGraphicsState { page: 0, layer: 0 },
+    ]));
+}
+
+#[test]
+fn test_coordinate_validation()
+{
+    use lopdf::Object::Real;
+
+    let (doc, page1, layer1) = PdfDocument::new("Validation", Mm(210.0), Mm(297.0), "Layer 1");
+    let layer = doc.get_page(page1).get_layer(layer1);
+    layer.set_outline_thickness(1.0);
+    layer.push_operation("re", vec![Real(10.0), Real(::std::f64::NAN), Real(::std::f64::INFINITY), Real(-::std::f64::INFINITY)]);
+    layer.push_operation("d", vec![lopdf::Object::Array(vec![Real(::std::f64::NAN)]), Real(0.0)]);
+
+    assert_eq!(doc.validate(), Err(vec![
+        ValidationError::NonFiniteNumber { page: 0, layer: 0, operator: "re".into() },
+    ]));
+
+    doc.set_coordinate_validation(CoordinateValidation::Off);
+    assert!(doc.validate().is_ok());
+
+    doc.document.borrow_mut().pages[0].clamp_numbers();
+    doc.set_coordinate_validation(CoordinateValidation::Strict);
+    assert!(doc.validate().is_ok());
+
+    let document = doc.document.borrow();
+    let operands = &document.pages[0].layers[0].operations[1].operands;
+    let numbers: Vec<f64> = operands.iter().map(|operand| operand.as_f64().unwrap()).collect();
+    assert_eq!(numbers, vec![10.0, 0.0, f64::from(::std::f32::MAX), -f64::from(::std::f32::MAX)]);
+}
+
+#[test]
+fn test_invalid_page_size()
+{
+    let (doc, _, _) = PdfDocument::new("Validation", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.add_page(Mm(0.0), Mm(-10.0), "Layer 1");
+    doc.add_page(Mm(::std::f64::NAN), Mm(100.0), "Layer 1");
+
+    assert_eq!(doc.validate(), Err(vec![
+        ValidationError::InvalidPageSize { page: 1 },
+        ValidationError::InvalidPageSize { page: 2 },
+    ]));
+
+    doc.document.borrow_mut().pages.iter_mut().for_each(PdfPage::clamp_numbers);
+    assert!(doc.validate().is_ok());
+
+    let document = doc.document.borrow();
+    assert_eq!((document.pages[1].width, document.pages[1].height), (Pt(3.0), Pt(3.0)));
+    assert_eq!(document.pages[2].height, Mm(100.0).into());
+}
+
+#[test]
+fn test_define_form()
+{
+    use {CurTransMat, Line, Point};
+
+    let (doc, page1, layer1) = PdfDocument::new("Forms", Mm(210.0), Mm(297.0), "Layer 1");
+    let (page2, layer2) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    let logo = doc.define_form(|layer| layer.add_shape(Line {
+        points: vec![(Point { x: Pt(0.0), y: Pt(0.0) }, false), (Point { x: Pt(20.0), y: Pt(10.0) }, false)],
+        is_closed: true,
+        has_fill: true,
+        has_stroke: false,
+        is_clipping_path: false,
+    }));
+
+    doc.get_page(page1).get_layer(layer1).draw_form(&logo, &[CurTransMat::Translate(Mm(10.0), Mm(10.0))]);
+    doc.get_page(page2).get_layer(layer2).draw_form(&logo, &[]);
+
+    let document = doc.document.borrow();
+    assert_eq!(document.pages.len(), 2);
+    assert_eq!(document.forms.len(), 1);
+    let bbox = document.forms[0].bbox;
+    assert!((bbox.urx.0 - 20.0).abs() < 0.001 && (bbox.ury.0 - 10.0).abs() < 0.001);
+    for page in &document.pages {
+        assert_eq!(page.resources.xobjects.len(), 1);
+        assert!(page.layers[0].operations.iter().any(|op| op.operator == "Do"));
+    }
+    assert!(doc.validate().is_ok());
+}
+
+#[test]
+fn test_define_form_adding_pages()
+{
+    // a page that is added while the form is defined keeps its index
+    let (doc, page1, _) = PdfDocument::new("Forms", Mm(210.0), Mm(297.0), "Layer 1");
+    let mut added = None;
+    let form = doc.define_form(|layer| {
+        layer.set_outline_thickness(2.0);
+        let (page, inner_layer) = doc.add_page(Mm(100.0), Mm(100.0), "Added");
+        doc.get_page(page).get_layer(inner_layer).set_outline_thickness(3.0);
+        let inner_form = doc.define_form(|layer| layer.set_outline_thickness(4.0));
+        layer.draw_form(&inner_form, &[]);
+        added = Some(page);
+    });
+
+    let document = doc.document.borrow();
+    assert_eq!(document.pages.len(), 2);
+    assert!(document.form_pages.is_empty());
+    assert_eq!(added.map(|page| page.0), Some(1));
+    assert_eq!(document.pages[page1.0].height, Mm(297.0).into());
+    assert_eq!(document.pages[1].layers[0].name, "Added");
+    assert_eq!(document.pages[1].layers[0].operations.len(), 1);
+    assert_eq!(document.forms.len(), 2);
+    let ops = |index: usize| document.forms[index].operations.iter().map(|op| op.operator.as_str()).collect::<Vec<_>>();
+    assert_eq!(ops(0), vec!["w"]);
+    assert_eq!(ops(form.0), vec!["w", "q", "Do", "Q"]);
+}
+
+#[test]
+fn test_define_form_kept_layer()
+{
+    // a layer reference that is kept after the form is defined draws nowhere
+    let (doc, page1, layer1) = PdfDocument::new("Forms", Mm(210.0), Mm(297.0), "Layer 1");
+    let mut kept = None;
+    let form = doc.define_form(|layer| {
+        layer.set_outline_thickness(2.0);
+        kept = Some(layer.clone());
+    });
+
+    let kept = kept.unwrap();
+    kept.set_outline_thickness(3.0);
+    kept.begin_tag(StructureType::P, None);
+    kept.end_tag();
+    doc.get_page(page1).get_layer(layer1).draw_form(&form, &[]);
+    kept.set_outline_thickness(4.0);
+
+    let document = doc.document.borrow();
+    assert_eq!(document.forms[form.0].operations.len(), 1);
+    assert_eq!(document.pages[0].layers[0].operations.iter().map(|op| op.operator.as_str()).collect::<Vec<_>>(), vec!["q", "Do", "Q"]);
+    assert!(document.form_pages.is_empty());
+}
+
+#[test]
+fn test_define_module_form()
+{
+    use CurTransMat;
+
+    let (doc, page1, layer1) = PdfDocument::new("Labels", Mm(210.0), Mm(297.0), "Layer 1");
+    let modules = vec![
+        vec![true, true, false, true],
+        vec![false, false, false, false],
+        vec![true, false, true, true],
+    ];
+    let code = doc.define_module_form(&modules, Mm(1.0));
+    let layer = doc.get_page(page1).get_layer(layer1);
+    for column in 0..3 {
+        layer.draw_form(&code, &[CurTransMat::Translate(Mm(10.0 + 20.0 * column as f64), Mm(10.0))]);
+    }
+
+    let document = doc.document.borrow();
+    assert_eq!(document.forms.len(), 1);
+    let operations = &document.forms[0].operations;
+    let rects = operations.iter().filter(|op| op.operator == "re")
+        .map(|op| op.operands.iter().map(|o| (o.as_f64().unwrap() / 2.834_646).round() as i64).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(rects, vec![vec![0, 2, 2, 1], vec![3, 2, 1, 1], vec![0, 0, 1, 1], vec![2, 0, 2, 1]]);
+    assert_eq!(operations.last().unwrap().operator, "f");
+    let bbox = document.forms[0].bbox;
+    let width: Pt = Mm(4.0).into();
+    assert!((bbox.urx.0 - width.0).abs() < 0.001);
+    assert_eq!(document.pages[0].resources.xobjects.len(), 1);
+}
+
+#[test]
+fn test_estimated_size()
+{
+    use {ColorBits, ColorSpace, ImageXObject, Px};
+
+    let (doc, page1, layer1) = PdfDocument::new("Estimate", Mm(210.0), Mm(297.0), "Layer 1");
+    let layer = doc.get_page(page1).get_layer(layer1);
+    // "1 w\n" and "2 w\n", with 8 characters per number
+    layer.set_outline_thickness(1.0);
+    layer.set_outline_thickness(2.0);
+    layer.add_image(ImageXObject::new(Px(10), Px(10), ColorSpace::Rgb, ColorBits::Bit8, false, None, None, vec![0; 300]));
+
+    let estimate = doc.estimated_size();
+    assert_eq!(estimate.content, 22);
+    assert_eq!(estimate.images, 300);
+    assert_eq!(estimate.fonts, 0);
+    assert_eq!(estimate.overhead, 200);
+    assert_eq!(estimate.total(), 522);
+}
+
+#[test]
+fn test_set_trapped()
+{
+    let (doc, _, _) = PdfDocument::new("Trapping", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.set_trapped(Trapped::True);
+    assert!(doc.validate().is_ok());
+
+    // the default conformance is PDF/X-3
+    doc.set_trapped(Trapped::Unknown);
+    assert_eq!(doc.validate(), Err(vec![ValidationError::IncompatibleOptions {
+        first: "Unknown trapping", second: "PDF/X conformance"
+    }]));
+}
+
+#[test]
+fn test_pdf_version_features()
+{
+    let (doc, _, _) = PdfDocument::new("Version", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.set_object_streams(true);
+    assert!(doc.validate().is_ok());
+
+    doc.set_pdf_version(PdfVersion::V1_4);
+    assert_eq!(doc.validate(), Err(vec![ValidationError::UnsupportedFeature {
+        feature: "Object streams", required: PdfVersion::V1_5, version: PdfVersion::V1_4
+    }]));
+
+    doc.set_pdf_version(PdfVersion::V1_5);
+    assert!(doc.validate().is_ok());
+}
+
+#[test]
+fn test_cff_font()
+{
+    use types::plugins::graphics::two_dimensional::sfnt::test_font;
+    use types::plugins::graphics::two_dimensional::bounding_box::text_width;
+
+    let doc = PdfDocument::empty("CFF");
+    let font = doc.add_external_font(&test_font()[..]).unwrap();
+    assert_eq!(text_width(&doc.document.borrow().fonts, &font, "AB", 10.0), Pt(13.0));
+
+    doc.set_pdf_version(PdfVersion::V1_5);
+    assert_eq!(doc.validate(), Err(vec![ValidationError::UnsupportedFeature {
+        feature: "OpenType (CFF) fonts", required: PdfVersion::V1_6, version: PdfVersion::V1_5
+    }]));
+
+    doc.set_pdf_version(PdfVersion::V1_6);
+    assert!(doc.validate().is_ok());
+}
+
+#[test]
+fn test_font_features()
+{
+    use types::plugins::graphics::two_dimensional::sfnt::test_font_with;
+    use types::plugins::graphics::two_dimensional::gsub::test_gsub;
+
+    let (doc, page1, layer1) = PdfDocument::new("Ligatures", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&test_font_with(vec![(*b"GSUB", test_gsub())])[..]).unwrap();
+    let layer = doc.get_page(page1).get_layer(layer1);
+    let last_text = || match doc.document.borrow().pages[0].layers[0].operations.last().unwrap().operands[0] {
+        lopdf::Object::String(ref bytes, _) => bytes.clone(),
+        _ => Vec::new(),
+    };
+
+    // "AB" is written with the ligature glyph
+    assert_eq!(text_width(&doc.document.borrow().fonts, &font, "AB", 10.0), Pt(7.0));
+    layer.write_text("ABA", &font);
+    assert_eq!(last_text(), vec![0, 3, 0, 1]);
+
+    doc.set_ligatures(&font, Ligatures::None);
+    assert_eq!(text_width(&doc.document.borrow().fonts, &font, "AB", 10.0), Pt(13.0));
+    layer.write_text("AB", &font);
+    assert_eq!(last_text(), vec![0, 1, 0, 2]);
+
+    // small caps: glyph 1 is replaced with glyph 2
+    doc.set_font_features(&font, &[Tag(*b"smcp")]);
+    assert_eq!(text_width(&doc.document.borrow().fonts, &font, "AB", 10.0), Pt(14.0));
+    layer.write_text("AB", &font);
+    assert_eq!(last_text(), vec![0, 2, 0, 2]);
+}
+
+#[test]
+fn test_missing_font_features()
+{
+    use types::plugins::graphics::two_dimensional::sfnt::test_font_with;
+    use types::plugins::graphics::two_dimensional::gsub::test_gsub;
+
+    let (doc, page1, layer1) = PdfDocument::new("Features", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&test_font_with(vec![(*b"GSUB", test_gsub())])[..]).unwrap();
+    let builtin = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page1).get_layer(layer1);
+    let last_text = || match doc.document.borrow().pages[0].layers[0].operations.last().unwrap().operands[0] {
+        lopdf::Object::String(ref bytes, _) => bytes.clone(),
+        _ => Vec::new(),
+    };
+
+    // the font has no oldstyle figures, only the ligature is applied
+    doc.set_font_features(&font, &[Tag(*b"onum")]);
+    layer.write_text("ABA", &font);
+    assert_eq!(last_text(), vec![0, 3, 0, 1]);
+
+    // the ligature is applied before the small caps
+    doc.set_font_features(&font, &[Tag(*b"onum"), Tag(*b"smcp")]);
+    layer.write_text("ABA", &font);
+    assert_eq!(last_text(), vec![0, 3, 0, 2]);
+
+    // builtin fonts have no OpenType features
+    doc.set_font_features(&builtin, &[Tag(*b"smcp")]);
+    layer.write_text("AB", &builtin);
+    assert_eq!(last_text(), b"AB".to_vec());
+}
+
+#[test]
+fn test_content_id()
+{
+    let create = |text: &str| {
+        let (doc, page1, layer1) = PdfDocument::new("Content ID", Mm(210.0), Mm(297.0), "Layer 1");
+        doc.set_deterministic(true);
+        doc.get_page(page1).get_layer(layer1).write_text(text, &IndirectFontRef::new("F0"));
+        doc
+    };
+
+    let id = content_id(&create("Hello").document.borrow());
+    assert_eq!(id.len(), 32);
+    assert_eq!(id, content_id(&create("Hello").document.borrow()));
+    assert_ne!(id, content_id(&create("World").document.borrow()));
+    // the ID must not change between releases
+    assert_eq!(id, "66dda74d5184e4679653b3159fda548a");
+    assert_eq!(create("Hello").document.borrow().metadata.creation_date, OffsetDateTime::unix_epoch());
+}
+
+#[test]
+fn test_fill_template()
+{
+    use std::iter::FromIterator;
+
+    let (doc, page1, layer1) = PdfDocument::new("template", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    doc.get_page(page1).get_layer(layer1).use_text("Dear {{name}},", 12.0, Mm(20.0), Mm(270.0), &font);
+    let (page2, layer2) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    doc.get_page(page2).get_layer(layer2).use_text("{{name}} ordered {{count}} items", 12.0, Mm(20.0), Mm(270.0), &font);
+
+    let values = HashMap::from_iter(vec![("name".to_string(), "Jane".to_string()), ("count".to_string(), "3".to_string())]);
+    assert_eq!(doc.fill_template(&values), 3);
+    assert_eq!(doc.get_page(page1).extract_text(), "Dear Jane,");
+    assert_eq!(doc.get_page(page2).extract_text(), "Jane ordered 3 items");
+}
+
+#[test]
+fn test_add_animation_frames()
+{
+    let (doc, _, _) = PdfDocument::new("Flipbook", Mm(100.0), Mm(70.0), "Cover");
+    let mut frames = Vec::new();
+    let pages = doc.add_animation_frames(3, Mm(100.0), Mm(70.0), "Frame", |frame, layer| {
+        layer.set_outline_thickness(frame as f64);
+        frames.push((frame, layer.page));
+    });
+
+    assert_eq!(pages, vec![PdfPageIndex(1), PdfPageIndex(2), PdfPageIndex(3)]);
+    assert_eq!(frames, vec![(0, PdfPageIndex(1)), (1, PdfPageIndex(2)), (2, PdfPageIndex(3))]);
+    let document = doc.document.borrow();
+    assert_eq!(document.pages.len(), 4);
+    assert_eq!(document.pages[3].layers[0].current_state().line_width, 2.0);
+}
+
+#[test]
+fn test_inner_document()
+{
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let id = doc.inner_mut().add_object(lopdf::Object::Integer(42));
+    assert_eq!(doc.inner().get_object(id).and_then(|o| o.as_i64()).ok(), Some(42));
+    // the document is still usable afterwards
+    assert_eq!(doc.get_page(PdfPageIndex(0)).get_layer(PdfLayerIndex(0)).current_state().depth, 0);
+}
+
+#[test]
+fn test_unused_resources()
+{
+    use {BuiltinFont, XObject, ImageXObject, ColorSpace, ColorBits, Px};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let used_font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let unused_font = doc.add_builtin_font(BuiltinFont::Courier).unwrap();
+    let logo = doc.define_form(|layer| layer.use_text("Logo", 10.0, Mm(0.0), Mm(0.0), &used_font));
+    let unused_form = doc.define_form(|layer| layer.set_outline_thickness(2.0));
+
+    let page = doc.get_page(page);
+    let layer = page.get_layer(layer);
+    layer.draw_form(&logo, &[]);
+    let image = XObject::Image(ImageXObject::new(Px(1), Px(1), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![0]));
+    let (drawn, unused_image) = {
+        let mut document = doc.document.borrow_mut();
+        (document.pages[0].add_xobject(image.clone()), document.pages[0].add_xobject(image))
+    };
+    layer.push_operation("Do", vec![lopdf::Object::Name(drawn.name.into())]);
+
+    assert_eq!(doc.unused_resources(), vec![
+        ResourceRef::Font(unused_font),
+        ResourceRef::XObject { page: PdfPageIndex(0), name: unused_image.name },
+        ResourceRef::Form(unused_form),
+    ]);
+}
+
+#[test]
+fn test_set_random_seed()
+{
+    let ids = |seed| {
+        let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+        doc.set_random_seed(seed);
+        let inner = doc.document.borrow();
+        (inner.document_id.clone(), inner.metadata.xmp_metadata.document_id.clone(), inner.random)
+    };
+
+    assert_eq!(ids(7), ids(7));
+    assert_ne!(ids(7).0, ids(8).0);
+    assert_ne!(ids(7).0, ids(7).1);
+}
+
+#[test]
+fn test_add_pages()
+{
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let pages = doc.add_pages(3, Mm(100.0), Mm(100.0), "Content");
+    assert_eq!(pages, vec![PdfPageIndex(1), PdfPageIndex(2), PdfPageIndex(3)]);
+    assert!(doc.add_pages(0, Mm(100.0), Mm(100.0), "Content").is_empty());
+
+    let layers = doc.add_layer_to_all("Watermark");
+    assert_eq!(layers, vec![PdfLayerIndex(1); 4]);
+    doc.get_page(PdfPageIndex(1)).add_layer("Notes");
+    assert_eq!(doc.add_layer_to_all("Background")[1], PdfLayerIndex(3));
+    let document = doc.document.borrow();
+    assert_eq!(document.pages[2].layers[0].name, "Content");
+    assert_eq!(document.pages[3].layers[1].name, "Watermark");
+}
+
+#[test]
+fn test_save_to_path()
+{
+    use std::io::ErrorKind;
+
+    let dir = ::std::env::temp_dir().join(format!("printpdf-save-to-path-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("test.pdf");
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.save_to_path(&path).unwrap();
+    let bytes = ::std::fs::read(&path).unwrap();
+    ::std::fs::remove_dir_all(&dir).unwrap();
+    assert!(bytes.starts_with(b"%PDF-"));
+    assert!(bytes.ends_with(b"%%EOF") || bytes.ends_with(b"%%EOF\n"));
+
+    // the directory isn't created
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    match doc.save_to_path(dir.join("missing").join("test.pdf")) {
+        Err(Error::Io(ref e)) => assert_eq!(e.kind(), ErrorKind::NotFound),
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_save_with_progress()
+{
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.add_pages(2, Mm(100.0), Mm(100.0), "Content");
+    let mut calls = Vec::new();
+    let mut writer = BufWriter::new(Vec::new());
+    doc.save_with_progress(&mut writer, |done, total| calls.push((done, total))).unwrap();
+    assert_eq!(calls, vec![(1, 3), (2, 3), (3, 3)]);
+    assert!(writer.into_inner().unwrap().starts_with(b"%PDF-"));
+}
+
+#[test]
+fn test_save_cancellable()
+{
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let mut writer = BufWriter::new(Vec::new());
+    let cancel = AtomicBool::new(true);
+    assert!(matches!(doc.save_cancellable(&mut writer, &cancel), Err(Error::Cancelled)));
+    assert!(writer.into_inner().unwrap().is_empty());
+
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let mut writer = BufWriter::new(Vec::new());
+    cancel.store(false, Ordering::Relaxed);
+    doc.save_cancellable(&mut writer, &cancel).unwrap();
+    assert!(writer.into_inner().unwrap().starts_with(b"%PDF-"));
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn test_log_events()
+{
+    use std::sync::Mutex;
+    use log::{Log, Metadata, Record, LevelFilter};
+    use types::plugins::graphics::two_dimensional::sfnt::test_font;
+
+    struct TestLogger(Mutex<Vec<String>>);
+    impl Log for TestLogger {
+        fn enabled(&self, _: &Metadata) -> bool { true }
+        fn log(&self, record: &Record) { self.0.lock().unwrap().push(record.args().to_string()); }
+        fn flush(&self) { }
+    }
+    static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Debug);
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&test_font()[..]).unwrap();
+    doc.get_page(page).get_layer(layer).use_text("AB", 12.0, Mm(10.0), Mm(10.0), &font);
+    doc.add_pages(1, Mm(100.0), Mm(100.0), "Content");
+    doc.save_to_bytes().unwrap();
+
+    // other tests may log at the same time, so only the events of this document are checked
+    let messages = LOGGER.0.lock().unwrap();
+    assert!(messages.iter().any(|m| m.starts_with("Embedding font ")));
+    assert!(messages.iter().any(|m| m.starts_with("Page 1 of 2 finalized")));
+    assert!(messages.iter().any(|m| m.starts_with("Page 2 of 2 finalized")));
+    assert!(messages.iter().any(|m| m.starts_with("Writing document with 2 pages")));
+}
+
+#[test]
+fn test_add_layer_to_all()
+{
+    let (doc, page1, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Content");
+    doc.get_page(page1).add_layer("Background");
+    doc.add_page(Mm(210.0), Mm(297.0), "Content");
+
+    // the index of the layer differs between the pages
+    assert_eq!(doc.add_layer_to_all("Annotations"), vec![PdfLayerIndex(2), PdfLayerIndex(1)]);
+
+    // pages that are added afterwards don't get the layer
+    doc.add_page(Mm(210.0), Mm(297.0), "Content");
+    let document = doc.document.borrow();
+    let names = |page: usize| document.pages[page].layers.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names(0), vec!["Content", "Background", "Annotations"]);
+    assert_eq!(names(1), vec!["Content", "Annotations"]);
+    assert_eq!(names(2), vec!["Content"]);
+}
@@ -2,15 +2,39 @@
 
 use lopdf;
 
-use indices::{PdfPageIndex, PdfLayerIndex};
+use indices::{PdfPageIndex, PdfLayerIndex, XObjectIndex};
 use std::rc::Weak;
 use std::cell::RefCell;
 use lopdf::content::Operation;
-use glob_defines::OP_PATH_STATE_SET_LINE_WIDTH;
+use glob_defines::{OP_PATH_STATE_SET_LINE_WIDTH, OP_PATH_STATE_SET_MITER_LIMIT};
+use utils::pdf_text_string;
 use {
-    Font, XObject, PdfColor,  PdfDocument, ExtendedGraphicsStateBuilder, Line, ImageXObject, XObjectRef, Color, IndirectFontRef, BlendMode,
-    LineJoinStyle, LineCapStyle, LineDashPattern, CurTransMat, TextMatrix, TextRenderingMode, Mm, Pt
+    Font, XObject, PdfColor,  PdfDocument, PdfPage, ExtendedGraphicsStateBuilder, Line, ImageXObject, StreamedImageXObject, XObjectRef, Color, IndirectFontRef, BlendMode, OverprintMode,
+    LineJoinStyle, LineCapStyle, LineDashPattern, RenderingIntent, CurTransMat, TextMatrix, TextRenderingMode, TextDirection, StructureType, Point, Mm, Pt,
+    Rect, SoftMask, SoftMaskFunction, Pattern, FootnoteNumbering, ExternalFont, Rgb, LinearGradient, Error, PdfError, TextEncoding
 };
+use types::plugins::graphics::two_dimensional::bidi::reorder_text;
+use types::plugins::graphics::xobject::ReusableForm;
+use types::plugins::graphics::two_dimensional::bounding_box::{content_bounding_box, graphics_state, text_width};
+use types::plugins::graphics::two_dimensional::footnote::{Footnote, FOOTNOTE_SCALE};
+use types::plugins::graphics::two_dimensional::text_layout::{wrap_lines, balance_columns, line_capacity};
+use types::plugins::graphics::two_dimensional::text_path::{flatten, glyph_positions};
+use types::plugins::graphics::two_dimensional::outline::PathSegment;
+use types::plugins::graphics::two_dimensional::stroke::stroke_outline;
+use types::plugins::graphics::two_dimensional::colr::color_layers;
+
+/// Leading of multi-line text written with `use_text`, as a multiple of the font size
+const DEFAULT_LEADING: f64 = 1.2;
+/// Size of superscript footnote markers, relative to the font size of the text
+const SUPERSCRIPT_SCALE: f64 = 0.6;
+/// Baseline shift of superscript footnote markers, relative to the font size of the text
+const SUPERSCRIPT_RISE: f64 = 0.35;
+/// Approximate height of capital letters (in em), used to size drop caps
+const DROP_CAP_HEIGHT: f64 = 0.7;
+/// Slant of faux italic text in degrees, see `TextOptions`
+const FAUX_ITALIC_ANGLE: f64 = 12.0;
+/// Width of the outline of faux bold text, as a multiple of the font size
+const FAUX_BOLD_STROKE: f64 = 0.03;
 
 /// One layer of PDF data
 #[derive(Debug, Clone)]
@@ -19,6 +43,87 @@ pub struct PdfLayer {
     pub(crate) name: String,
     /// Stream objects in this layer. Usually, one layer == one stream
     pub(super) operations: Vec<Operation>,
+    /// Direction of the text written with `write_text`, see `set_text_direction`
+    pub(crate) text_direction: TextDirection,
+    /// Index of the parent layer (on the same page) in the layer tree of the viewer,
+    /// see `PdfPageReference::add_layer_child()`
+    pub(crate) parent: Option<usize>,
+    /// Opacity of the whole layer, see `PdfLayerReference::set_opacity()`
+    pub(crate) opacity: f64,
+    /// Whether the layer is printed, only the guides layer isn't (see `PdfPageReference::add_guide()`)
+    pub(crate) printable: bool,
+    /// Whether `use_text` moves the text cursor to the end of the text, see
+    /// `PdfLayerReference::set_cursor_mode()`
+    pub(crate) cursor_mode: bool,
+    /// End of the baseline of the last text written in cursor mode (x, y in mm)
+    pub(crate) cursor: Option<(Mm, Mm)>,
+    /// Stacking of the layer on its page, see `PdfLayerReference::set_role()`
+    pub(crate) role: LayerRole,
+}
+
+/// Stacking of a layer, see `PdfLayerReference::set_role()`. When the page is saved, the
+/// background layers are drawn first and the foreground layers last, layers with the same
+/// role are drawn in the order they were added.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LayerRole {
+    /// Drawn below the other layers, e.g. for page backgrounds or watermarks
+    Background,
+    /// Regular content
+    #[default]
+    Content,
+    /// Drawn above the other layers, e.g. for stamps
+    Foreground,
+}
+
+/// Graphics state of a layer after its operations, see `PdfLayer::current_state()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphicsState {
+    /// Current transformation matrix `[a b c d e f]`, from the coordinates of the layer
+    /// to page coordinates (in pt)
+    pub ctm: [f64; 6],
+    /// Fill color, also used for text. `None` if it isn't a device color (e.g. a pattern).
+    /// The colors are read from the operators, so they have no ICC profile and spot colors
+    /// show up as their CMYK fallback.
+    pub fill_color: Option<Color>,
+    /// Outline (stroke) color, see `fill_color`
+    pub outline_color: Option<Color>,
+    /// Width of outlines, in the coordinates of the layer
+    pub line_width: f64,
+    /// Current font, if a font was set
+    pub font: Option<IndirectFontRef>,
+    /// Current font size
+    pub font_size: f64,
+    /// Number of saved graphics states (`save_graphics_state()`) that weren't restored yet
+    pub depth: usize,
+}
+
+/// Synthesized styles for fonts without a bold or italic cut, see
+/// `PdfLayerReference::use_text_with_options()`. A real bold or italic font always looks
+/// better: the faux styles only distort the regular glyphs.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Thickens the glyphs by stroking their outlines with the fill color
+    pub faux_bold: bool,
+    /// Slants the glyphs to the right, by shearing the text matrix
+    pub faux_italic: bool,
+    /// What happens with characters that the (external) font has no glyph for.
+    /// Default: __MissingGlyphPolicy::Notdef__
+    pub missing_glyphs: MissingGlyphPolicy,
+}
+
+/// Handling of characters that an external font has no glyph for, see `TextOptions`.
+/// Builtin fonts are not checked.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum MissingGlyphPolicy {
+    /// Shows the `.notdef` glyph of the font, usually an empty box ("tofu")
+    #[default]
+    Notdef,
+    /// Leaves the characters out
+    Skip,
+    /// Shows this character instead, or `.notdef` if the font doesn't have it either
+    Replace(char),
+    /// Fails with `PdfError::MissingGlyph`, nothing is written
+    Error,
 }
 
 /// A "reference" to the current layer, allows for inner mutability
@@ -31,10 +136,21 @@ pub struct PdfLayerReference {
     pub page: PdfPageIndex,
     /// The index of the layer this layer has (inside the page)
     pub layer: PdfLayerIndex,
+    /// ID of the temporary page of the form that the layer draws, `None` if the layer is
+    /// on a page of the document (see `PdfDocumentReference::define_form()`)
+    pub(crate) form: Option<usize>,
 }
 
 impl PdfLayer {
 
+    /// Returns the graphics state that the next operation of the layer is drawn with. The state
+    /// is computed from the operations of the layer, so it takes time proportional to their number.
+    pub fn current_state(&self)
+    -> GraphicsState
+    {
+        graphics_state(&self.operations)
+    }
+
     /// Create a new layer, with a name and what index the layer has in the page
     #[inline]
     pub fn new<S>(name: S)
@@ -43,8 +159,28 @@ impl PdfLayer {
         Self {
             name: name.into(),
             operations: Vec::new(),
+            text_direction: TextDirection::default(),
+            parent: None,
+            opacity: 1.0,
+            printable: true,
+            cursor_mode: false,
+            cursor: None,
+            role: LayerRole::Content,
         }
     }
+
+    /// Appends a raw content stream operator with its operands to the layer, for
+    /// operators that aren't covered by the rest of the API, e.g.
+    /// `layer.push_operation("w", vec![2.into()])` to set the line width.
+    ///
+    /// __NOTE__: The operator is written as-is, without any checks. It is your
+    /// responsibility to write valid operators and to keep the graphics state
+    /// balanced (every `q` needs a matching `Q`, every `BT` a matching `ET`, etc.).
+    #[inline]
+    pub fn push_operation(&mut self, operator: &str, operands: Vec<lopdf::Object>)
+    {
+        self.operations.push(Operation::new(operator, operands));
+    }
 }
 
 impl Into<lopdf::Stream> for PdfLayer {
@@ -61,6 +197,14 @@ impl Into<lopdf::Stream> for PdfLayer {
 
 impl PdfLayerReference {
 
+    /// Layer of the temporary page `id` of a form that is being defined
+    pub(crate) fn form_layer(document: Weak<RefCell<PdfDocument>>, id: usize, layer: PdfLayerIndex)
+    -> Self
+    {
+        // the page index isn't used, the layer draws on the form page
+        Self { document, page: PdfPageIndex(usize::MAX), layer, form: Some(id) }
+    }
+
     /// Add a shape to the layer. Use `closed` to indicate whether the line is a closed line
     /// Use has_fill to determine if the line should be filled.
     pub fn add_shape(&self, line: Line)
@@ -78,9 +222,27 @@ impl PdfLayerReference {
     {
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let page_mut = &mut doc.pages[self.page.0];
+        let dedup = doc.dedup;
+        let page_mut = doc.layer_page_mut(self);
+        let image = image.into();
+
+        if dedup {
+            let hash = image.content_hash();
+            page_mut.resources.xobjects.add_xobject_with_hash(XObject::Image(image), hash)
+        } else {
+            page_mut.add_xobject(XObject::Image(image))
+        }
+    }
 
-        page_mut.add_xobject(XObject::Image(image.into()))
+    /// Add a streamed image to the layer, the image data is read when the document is saved.
+    /// To be called from the `streamed_image.add_to_layer()` class
+    pub(crate) fn add_streamed_image(&self, image: StreamedImageXObject)
+    -> XObjectRef
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let page_mut = doc.layer_page_mut(self);
+        page_mut.add_xobject(XObject::StreamedImage(image))
     }
 /*
     /// Add an svg element to the layer
@@ -90,7 +252,7 @@ impl PdfLayerReference {
     {
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let page_mut = &mut doc.pages[self.page.0];
+        let page_mut = doc.layer_page_mut(self);
         let form_data = svg.try_into()?;
         Ok(page_mut.add_xobject(XObject::Form(Box::new(form_data))))
     }
@@ -121,14 +283,77 @@ impl PdfLayerReference {
         self.internal_add_operation(PdfColor::FillColor(fill_color));
     }
 
+    /// Fills the following shapes by tiling a form (e.g. one that draws an image, see
+    /// `PdfDocumentReference::define_form()`) instead of a color, each tile is `tile_width` x
+    /// `tile_height` big and shows that area of the form, starting at its origin. The form is
+    /// written into the file once, no matter how often it is used. Use a clipping path to fill
+    /// any other shape with the pattern. Setting a fill color ends the pattern fill.
+    pub fn set_fill_pattern(&self, form: &XObjectIndex, tile_width: Mm, tile_height: Mm)
+    {
+        let pattern_ref = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            doc.layer_page_mut(self).add_pattern(Pattern::new(form, tile_width, tile_height))
+        };
+
+        self.internal_add_operation(Operation::new("cs", vec![lopdf::Object::Name("Pattern".into())]));
+        self.internal_add_operation(Operation::new("scn", vec![lopdf::Object::Name(pattern_ref.name.into_bytes())]));
+    }
+
+    /// Fills the following shapes with a gradient instead of a color, see `LinearGradient`.
+    /// Setting a fill color ends the gradient fill.
+    pub fn set_fill_gradient(&self, gradient: LinearGradient)
+    {
+        let pattern_ref = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            doc.layer_page_mut(self).add_pattern(Pattern::linear_gradient(gradient))
+        };
+
+        self.internal_add_operation(Operation::new("cs", vec![lopdf::Object::Name("Pattern".into())]));
+        self.internal_add_operation(Operation::new("scn", vec![lopdf::Object::Name(pattern_ref.name.into_bytes())]));
+    }
+
+    /// Strokes the line with a gradient, `thickness` is the width of the line in pt. PDF can
+    /// only fill areas with a gradient, so the stroke is converted to an outline, which is
+    /// filled: a rectangle for each segment and a circle at each joint. The joints are always
+    /// round and the ends are cut off flat, regardless of the line join and cap style.
+    /// If `line` is filled, the area is filled with the current fill color first.
+    pub fn add_line(&self, line: Line, thickness: f64, gradient: LinearGradient)
+    {
+        use lopdf::Object::Real;
+
+        let polyline = flatten(&line);
+        let outline = stroke_outline(&polyline, thickness, line.is_closed);
+
+        if line.has_fill {
+            self.add_shape(Line { has_stroke: false, is_clipping_path: false, .. line });
+        }
+
+        if outline.is_empty() {
+            return;
+        }
+
+        self.save_graphics_state();
+        self.set_fill_gradient(gradient);
+        for subpath in outline {
+            self.internal_add_operation(Operation::new("m", vec![Real(subpath[0].0), Real(subpath[0].1)]));
+            for point in &subpath[1..] {
+                self.internal_add_operation(Operation::new("l", vec![Real(point.0), Real(point.1)]));
+            }
+            self.internal_add_operation(Operation::new("h", vec![]));
+        }
+        self.internal_add_operation(Operation::new("f", vec![]));
+        self.restore_graphics_state();
+    }
+
     /// Set the current font, only valid in a `begin_text_section` to
     /// `end_text_section` block
     #[inline]
-    pub fn set_font(&self, font: &IndirectFontRef, font_size: i64)
-    -> ()
+    pub fn set_font(&self, font: &IndirectFontRef, font_size: f64)
     {
         self.internal_add_operation(Operation::new("Tf",
-            vec![font.name.clone().into(), (font_size).into()]
+            vec![font.name.clone().into(), font_size.into()]
         ));
     }
 
@@ -150,6 +375,39 @@ impl PdfLayerReference {
                         translate_x: Option<Mm>, translate_y: Option<Mm>,
                         rotate_cw: Option<f64>,
                         scale_x: Option<f64>, scale_y: Option<f64>)
+    {
+        let invoke = Operation::new("Do", vec![lopdf::Object::Name(xobj.name.into_bytes())]);
+        self.use_transformed(vec![invoke], translate_x, translate_y, rotate_cw, scale_x, scale_y);
+    }
+
+    /// Draws an inline image, see `ImageXObject::inline_operations()`. The transformations are
+    /// the same as for `use_xobject()`.
+    pub(crate) fn use_inline_image(&self, operations: Vec<Operation>,
+                        translate_x: Option<Mm>, translate_y: Option<Mm>,
+                        rotate_cw: Option<f64>,
+                        scale_x: Option<f64>, scale_y: Option<f64>)
+    {
+        self.use_transformed(operations, translate_x, translate_y, rotate_cw, scale_x, scale_y);
+    }
+
+    /// Returns the operations that draw the image inline, if the image is small enough for
+    /// the limit of the document, see `PdfDocumentReference::set_inline_image_limit()`
+    pub(crate) fn inline_image_operations(&self, image: &ImageXObject)
+    -> Option<Vec<Operation>>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let limit = doc.borrow().inline_image_limit;
+        if image.image_data.len() > limit {
+            return None;
+        }
+        image.inline_operations()
+    }
+
+    /// Adds the operations with the transformations of `use_xobject()`
+    fn use_transformed(&self, operations: Vec<Operation>,
+                        translate_x: Option<Mm>, translate_y: Option<Mm>,
+                        rotate_cw: Option<f64>,
+                        scale_x: Option<f64>, scale_y: Option<f64>)
     {
         // save graphics state
         self.save_graphics_state();
@@ -181,13 +439,59 @@ impl PdfLayerReference {
         }
 
         // invoke object
-        self.internal_invoke_xobject(xobj.name);
+        for operation in operations {
+            self.internal_add_operation(operation);
+        }
 
         // restore graphics state
         self.restore_graphics_state();
     }
 
-    /// Set the overprint mode of the stroke color to true (overprint) or false (no overprint)
+    /// Draws a form that was defined with `PdfDocumentReference::define_form()`. The
+    /// transformations are applied in the given order, e.g.
+    /// `&[CurTransMat::Translate(Mm(10.0), Mm(10.0)), CurTransMat::Scale(0.5, 0.5)]`.
+    /// The form is written into the file only once, no matter on how many pages it is drawn.
+    pub fn draw_form(&self, form: &XObjectIndex, transform: &[CurTransMat])
+    {
+        let xobject = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            doc.layer_page_mut(self).resources.xobjects.add_form(*form)
+        };
+
+        self.save_graphics_state();
+        for matrix in transform {
+            self.internal_add_operation(*matrix);
+        }
+        self.internal_invoke_xobject(xobject.name);
+        self.restore_graphics_state();
+    }
+
+    /// Sets the rendering intent (`ri`) for the conversion of the following colors to the
+    /// colors of the output device, e.g. `RenderingIntent::AbsoluteColorimetric` for logos that
+    /// have to match exactly and `RenderingIntent::Perceptual` for photos. The intent is part of
+    /// the graphics state, so it is reset by `restore_graphics_state()`.
+    ///
+    /// Default: `RenderingIntent::RelativeColorimetric`
+    #[inline]
+    pub fn set_rendering_intent(&self, intent: RenderingIntent)
+    {
+        for operation in intent.into_stream_op() {
+            self.internal_add_operation(operation);
+        }
+    }
+
+    /// Returns the graphics state that the next operation is drawn with, e.g. to skip setting
+    /// a color that is already set, see `PdfLayer::current_state()`
+    pub fn current_state(&self)
+    -> GraphicsState
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        doc.layer_page(self).layers[self.layer.0].current_state()
+    }
+
+    /// Set the overprint mode of the fill color to true (overprint) or false (no overprint)
     pub fn set_overprint_fill(&self, overprint: bool)
     {
         let new_overprint_state = ExtendedGraphicsStateBuilder::new()
@@ -196,7 +500,7 @@ impl PdfLayerReference {
 
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let page_mut = &mut doc.pages[self.page.0];
+        let page_mut = doc.layer_page_mut(self);
 
         let new_ref = page_mut.add_graphics_state(new_overprint_state);
 
@@ -207,7 +511,7 @@ impl PdfLayerReference {
         ));
     }
 
-    /// Set the overprint mode of the fill color to true (overprint) or false (no overprint)
+    /// Set the overprint mode of the stroke color to true (overprint) or false (no overprint)
     /// This changes the graphics state of the current page, don't do it too often or you'll bloat the file size
     pub fn set_overprint_stroke(&self, overprint: bool)
     {
@@ -218,7 +522,7 @@ impl PdfLayerReference {
 
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let page_mut = &mut doc.pages[self.page.0];
+        let page_mut = doc.layer_page_mut(self);
 
         let new_ref = page_mut.add_graphics_state(new_overprint_state);
         page_mut.layers[self.layer.0]
@@ -227,7 +531,32 @@ impl PdfLayerReference {
         ));
     }
 
-    /// Set the overprint mode of the fill color to true (overprint) or false (no overprint)
+    /// Sets the overprint of fills (including text) and strokes and the overprint mode in a single
+    /// graphics state. Overprinted objects don't knock out the colors below them, e.g. black text
+    /// on a colored background, or a trap around an object. With `OverprintMode::KeepUnderlying`,
+    /// CMYK components that are 0 leave the color below unchanged instead of erasing it (this
+    /// only applies to `DeviceCMYK` colors). Separations (spot colors) are always overprinted
+    /// separately from the process colors.
+    pub fn set_overprint(&self, fill: bool, stroke: bool, mode: OverprintMode)
+    {
+        let new_overprint_state = ExtendedGraphicsStateBuilder::new()
+                                      .with_overprint_fill(fill)
+                                      .with_overprint_stroke(stroke)
+                                      .with_overprint_mode(mode)
+                                      .build();
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let page_mut = doc.layer_page_mut(self);
+
+        let new_ref = page_mut.add_graphics_state(new_overprint_state);
+        page_mut.layers[self.layer.0]
+            .operations.push(Operation::new(
+                "gs", vec![lopdf::Object::Name(new_ref.gs_name.as_bytes().to_vec())]
+        ));
+    }
+
+    /// Set the blend mode
     /// This changes the graphics state of the current page, don't do it too often or you'll bloat the file size
     pub fn set_blend_mode(&self, blend_mode: BlendMode)
     {
@@ -238,7 +567,7 @@ impl PdfLayerReference {
 
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let page_mut = &mut doc.pages[self.page.0];
+        let page_mut = doc.layer_page_mut(self);
 
         let new_ref = page_mut.add_graphics_state(new_blend_mode_state);
 
@@ -248,6 +577,33 @@ impl PdfLayerReference {
         ));
     }
 
+    /// Masks everything that is drawn afterwards with a luminosity soft mask: the form (see
+    /// `PdfDocumentReference::define_form()`, usually it draws a greyscale image) is drawn
+    /// as is, white areas of the form are opaque, black areas are transparent and everything
+    /// outside of `rect` is not drawn at all. The form is written into the file once, so the
+    /// same mask can be used on many pages.
+    /// The mask stays active until the graphics state is restored, so call
+    /// `save_graphics_state()` before and `restore_graphics_state()` after the masked content.
+    ///
+    /// __NOTE__: Soft masks require PDF 1.4
+    pub fn set_soft_mask(&self, mask: &XObjectIndex, rect: Rect)
+    {
+        let new_soft_mask_state = ExtendedGraphicsStateBuilder::new()
+                                      .with_soft_mask(Some(SoftMask::new(mask, rect, SoftMaskFunction::GroupLuminosity)))
+                                      .build();
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let page_mut = doc.layer_page_mut(self);
+
+        let new_ref = page_mut.add_graphics_state(new_soft_mask_state);
+
+        page_mut.layers[self.layer.0]
+            .operations.push(Operation::new(
+                "gs", vec![lopdf::Object::Name(new_ref.gs_name.as_bytes().to_vec())]
+        ));
+    }
+
     /// Set the current line thickness, in points
     ///
     /// __NOTE__: 0.0 is a special value, it does not make the line disappear, but rather
@@ -265,6 +621,21 @@ impl PdfLayerReference {
         self.internal_add_operation(line_join);
     }
 
+    /// Sets the miter limit for outlines with `LineJoinStyle::Miter`. A miter join gets longer
+    /// the sharper the angle of the corner is: when the ratio of its length to the line width
+    /// exceeds the limit, the viewer draws a bevel join instead (`LineJoinStyle::Limit`).
+    /// The ratio is `1 / sin(angle / 2)`, so a limit of `1.414` bevels all corners sharper
+    /// than 90 degrees, and the default of `10.0` bevels corners sharper than about 11.5 degrees.
+    ///
+    /// The limit has no effect on round and bevel joins. Values below 1.0 are invalid
+    /// and are raised to 1.0 (which bevels every corner).
+    #[inline]
+    pub fn set_miter_limit(&self, miter_limit: f64)
+    {
+        use lopdf::Object::*;
+        self.internal_add_operation(Operation::new(OP_PATH_STATE_SET_MITER_LIMIT, vec![Real(miter_limit.max(1.0))]));
+    }
+
     /// Set the current line join style for outlines
     #[inline]
     pub fn set_line_cap_style(&self, line_cap: LineCapStyle) {
@@ -323,6 +694,17 @@ impl PdfLayerReference {
         ));
     }
 
+    /// Sets the leading (the distance between the baselines of two lines, used by
+    /// `add_line_break`) inside a text block. Unlike `set_line_height`, the leading
+    /// is given in millimeter, so that it doesn't have to be a whole number of points
+    #[inline]
+    pub fn set_leading(&self, leading: Mm) {
+        let leading: Pt = leading.into();
+        self.internal_add_operation(Operation::new("TL",
+            vec![leading.into()]
+        ));
+    }
+
     /// Sets the character spacing inside a text block
     /// Values are given in points. A value of 3 (pt) will increase
     /// the spacing inside a word by 3pt.
@@ -371,6 +753,101 @@ impl PdfLayerReference {
         ));
     }
 
+    /// Adds a footnote: writes the marker (the number of the footnote) as a superscript at the
+    /// current text position and stores the note, which is drawn at the bottom of the page
+    /// when the document is saved. Must be called in a text section, `font` and `font_size`
+    /// are the font of the surrounding text, which is restored after the marker. The note is
+    /// written in the same font at 80% of the size. Returns the marker.
+    pub fn add_footnote<S>(&self, note: S, font: &IndirectFontRef, font_size: f64)
+    -> String where S: Into<String>
+    {
+        let marker = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            let number = match doc.footnote_numbering {
+                FootnoteNumbering::PerDocument => doc.footnotes.len() + 1,
+                FootnoteNumbering::PerPage => doc.footnotes.iter().filter(|f| f.page == self.page.0).count() + 1,
+            };
+            let marker = number.to_string();
+            doc.footnotes.push(Footnote {
+                page: self.page.0,
+                marker: marker.clone(),
+                text: note.into(),
+                font: font.clone(),
+                font_size: font_size * FOOTNOTE_SCALE,
+            });
+            marker
+        };
+
+        self.set_font(font, font_size * SUPERSCRIPT_SCALE);
+        self.internal_add_operation(Operation::new("Ts", vec![lopdf::Object::Real(font_size * SUPERSCRIPT_RISE)]));
+        self.write_text(marker.clone(), font);
+        self.internal_add_operation(Operation::new("Ts", vec![lopdf::Object::Integer(0)]));
+        self.set_font(font, font_size);
+        marker
+    }
+
+    /// Sets the direction of the text written with `write_text` (and `use_text`) on this
+    /// layer. Right-to-left text is reordered into the visual order, see `reorder_text`.
+    /// The position of the text is still the left end of the text.
+    ///
+    /// Default: __TextDirection::Ltr__
+    #[inline]
+    pub fn set_text_direction(&self, direction: TextDirection) {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().layer_page_mut(self).layers[self.layer.0].text_direction = direction;
+    }
+
+    /// Sets the opacity (0.0 - 1.0) of the whole layer. The content of the layer is drawn
+    /// into a transparency group first, which is then composited with this opacity, so
+    /// overlapping shapes on the layer don't shine through each other. The other layers
+    /// of the page are not affected.
+    ///
+    /// Default: __1.0__
+    #[inline]
+    pub fn set_opacity(&self, opacity: f64) {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().layer_page_mut(self).layers[self.layer.0].opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    /// Turns the cursor mode on or off. In cursor mode, `use_text` and `use_text_with_leading`
+    /// remember where the baseline of the text ends (after the measured width of the last line),
+    /// so that the next run can be written there with the position from `text_cursor()`.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_cursor_mode(&self, cursor_mode: bool) {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let layer = &mut doc.layer_page_mut(self).layers[self.layer.0];
+        layer.cursor_mode = cursor_mode;
+        if !cursor_mode {
+            layer.cursor = None;
+        }
+    }
+
+    /// Returns the end of the baseline of the last text written in cursor mode (x, y in mm),
+    /// or `None` if no text was written since the cursor mode was turned on
+    #[inline]
+    pub fn text_cursor(&self)
+    -> Option<(Mm, Mm)>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let cursor = doc.borrow().layer_page(self).layers[self.layer.0].cursor;
+        cursor
+    }
+
+    /// Sets whether the layer is drawn below or above the other layers of the page,
+    /// independent of the order the layers were added in. The order of the layers in the
+    /// layer panel of the viewer doesn't change.
+    ///
+    /// Default: __LayerRole::Content__
+    #[inline]
+    pub fn set_role(&self, role: LayerRole) {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().layer_page_mut(self).layers[self.layer.0].role = role;
+    }
+
     #[inline]
     pub fn set_text_rendering_mode(&self, mode: TextRenderingMode) {
         self.internal_add_operation(Operation::new("Tr",
@@ -389,12 +866,12 @@ impl PdfLayerReference {
         use lopdf::Object::*;
         use lopdf::StringFormat::Hexadecimal;
 
-        let text = text.into();
-
         // we need to transform the characters into glyph ids and then add them to the layer
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
 
+        let text = reorder_text(&text.into(), doc.layer_page(self).layers[self.layer.0].text_direction);
+
         // glyph IDs that make up this string
 
         // kerning for each glyph id. If no kerning is present, will be 0
@@ -402,27 +879,15 @@ impl PdfLayerReference {
         // let mut kerning_data = Vec::<freetype::Vector>::new();
 
         let bytes: Vec<u8> = {
-            use rusttype::FontCollection;
-            use rusttype::Codepoint as Cp;
+            if let Some(Font::ExternalFont(face_direct_ref)) = doc.fonts.get_font_data(font) {
 
-            if let Font::ExternalFont(face_direct_ref) = doc.fonts.get_font(font).unwrap().data {
+                let font = face_direct_ref.get_font();
 
-                let mut list_gid = Vec::<u16>::new();
-                let collection = FontCollection::from_bytes(&*face_direct_ref.font_bytes).unwrap();
-                let font = collection.clone().into_font().unwrap_or(collection.font_at(0).unwrap());
+                // convert into list of glyph ids - unicode magic, with the ligatures of the font
+                let list_gid = face_direct_ref.encode_text(&font, &text);
 
-                // convert into list of glyph ids - unicode magic
-                let char_iter = text.chars();
-
-                for ch in char_iter {
-                    // note: font.glyph will panic if the character is \0
-                    // since that can't happen in Rust, I think we're safe here
-                    let glyph = font.glyph(Cp(ch as u32));
-                    list_gid.push(glyph.id().0 as u16);
-
-                    // todo - kerning !!
-                    // font.pair_kerning(scale, id, base_glyph.id());
-                }
+                // todo - kerning !!
+                // font.pair_kerning(scale, id, base_glyph.id());
 
                 list_gid.iter()
                     .flat_map(|x| vec!((x >> 8) as u8, (x & 255) as u8))
@@ -432,13 +897,79 @@ impl PdfLayerReference {
             }
         };
 
-        doc.pages[self.page.0]
+        doc.layer_page_mut(self)
             .layers[self.layer.0]
                 .operations.push(Operation::new("Tj",
                     vec![String(bytes, Hexadecimal)]
         ));
     }
 
+    /// Places individual glyphs (by glyph ID) at absolute positions on the page,
+    /// bypassing the text layout of this library entirely. This is intended for
+    /// layout engines that do their own shaping (e.g. HarfBuzz), so the glyph IDs
+    /// have to come from the same font file that was added to the document.
+    ///
+    /// __Note:__ Only works for external fonts, since these are embedded as CID
+    /// fonts with `Identity-H` encoding (glyph ID == character code). The positions
+    /// are measured from the bottom left corner of the page.
+    pub fn add_glyphs(&self, font: &IndirectFontRef, glyphs: &[(u16, Point)], font_size: f64)
+    {
+        use lopdf::Object::*;
+        use lopdf::StringFormat::Hexadecimal;
+
+        if glyphs.is_empty() { return; }
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let layer = &mut doc.layer_page_mut(self).layers[self.layer.0];
+
+        layer.operations.push(Operation::new("BT", vec![]));
+        layer.operations.push(Operation::new("Tf", vec![font.name.clone().into(), font_size.into()]));
+
+        for &(gid, ref position) in glyphs {
+            // replace the text matrix, so that every glyph is positioned absolutely
+            layer.operations.push(Operation::new("Tm", vec![
+                Real(1.0), Real(0.0), Real(0.0), Real(1.0), position.x.into(), position.y.into()
+            ]));
+            layer.operations.push(Operation::new("Tj", vec![
+                String(vec![(gid >> 8) as u8, (gid & 255) as u8], Hexadecimal)
+            ]));
+        }
+
+        layer.operations.push(Operation::new("ET", vec![]));
+    }
+
+    /// Same as `add_glyphs`, but keeps the text searchable and copyable: `text` is the original
+    /// text that the glyphs were shaped from. The glyphs are marked with the text as their
+    /// `/ActualText`, and if every glyph stands for one character (or a single glyph for
+    /// the whole text), the glyphs that the font doesn't map to characters are added to
+    /// the `ToUnicode` map of the font.
+    pub fn add_glyphs_with_text(&self, font: &IndirectFontRef, glyphs: &[(u16, Point)], text: &str, font_size: f64)
+    {
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
+
+        if glyphs.is_empty() { return; }
+
+        {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+
+            if let Some(Font::ExternalFont(font)) = doc.fonts.get_font_data_mut(font) {
+                for (glyph, text) in shaped_glyph_text(glyphs, text) {
+                    font.shaped_glyphs.entry(glyph).or_insert(text);
+                }
+            }
+
+            let properties = lopdf::Dictionary::from_iter(vec![("ActualText", pdf_text_string(text))]);
+            doc.layer_page_mut(self).layers[self.layer.0].operations
+                .push(Operation::new("BDC", vec![Name("Span".into()), Dictionary(properties)]));
+        }
+
+        self.add_glyphs(font, glyphs, font_size);
+        self.internal_add_operation(Operation::new("EMC", vec![]));
+    }
+
     /// Saves the current graphic state
     #[inline]
     pub fn save_graphics_state(&self) {
@@ -451,17 +982,570 @@ impl PdfLayerReference {
         self.internal_add_operation(Operation::new("Q", Vec::new()));
     }
 
-    /// Add text to the file, x and y are measure in millimeter from the bottom left corner
+    /// Returns the bounding box (in page coordinates) of everything drawn on this layer so far,
+    /// or `None` if nothing was drawn. Stroked paths include half of the line width, text is
+    /// measured with the metrics of the font (approximated for builtin fonts). Useful to size
+    /// a page to its content or to draw a border around it.
+    pub fn bounding_box(&self)
+    -> Option<Rect>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        let page = doc.layer_page(self);
+        content_bounding_box(&page.layers[self.layer.0].operations, &doc.fonts).map(|bbox| bbox.scaled(page.drawing_scale))
+    }
+
+    /// Begins a tag (structure element) for tagged PDF. Everything that is drawn on this
+    /// layer until the matching `end_tag()` belongs to the tag. Tags can be nested, e.g. a
+    /// `StructureType::P` inside a `StructureType::Sect`. Use `alt_text` to describe the
+    /// content of figures and formulas for screen readers.
+    ///
+    /// __NOTE__: `begin_tag()` and the matching `end_tag()` should be called on the same
+    /// layer. Every tag has to be closed before the document is saved. Tags are ignored
+    /// in forms (see `PdfDocumentReference::define_form()`).
+    pub fn begin_tag(&self, tag: StructureType, alt_text: Option<String>)
+    {
+        if self.form.is_some() {
+            return;
+        }
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let PdfDocument { ref mut structure, ref mut pages, .. } = *doc;
+        structure.begin_element(pages, self.page.0, self.layer.0, tag, alt_text);
+    }
+
+    /// Ends the tag that was last opened with `begin_tag()`
+    pub fn end_tag(&self)
+    {
+        if self.form.is_some() {
+            return;
+        }
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let PdfDocument { ref mut structure, ref mut pages, .. } = *doc;
+        if !structure.end_element(pages, self.page.0, self.layer.0) {
+            #[cfg(feature = "logging")] {
+                warn!("end_tag() was called without an open tag");
+            }
+        }
+    }
+
+    /// Marks everything that is drawn inside of `f` as an artifact: content that isn't part of
+    /// the document structure, like background lines, decorations or page numbers. Screen
+    /// readers and accessibility checkers skip artifacts.
+    ///
+    /// ```rust,ignore
+    /// layer.as_artifact(|layer| layer.add_shape(background_line));
+    /// ```
+    pub fn as_artifact<F>(&self, f: F) where F: FnOnce(&PdfLayerReference)
+    {
+        if self.form.is_some() {
+            self.internal_add_operation(Operation::new("BMC", vec![lopdf::Object::Name("Artifact".into())]));
+            f(self);
+            self.internal_add_operation(Operation::new("EMC", vec![]));
+            return;
+        }
+
+        {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            let PdfDocument { ref mut structure, ref mut pages, .. } = *doc;
+            structure.begin_artifact(pages, self.page.0, self.layer.0);
+        }
+
+        f(self);
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let PdfDocument { ref mut structure, ref mut pages, .. } = *doc;
+        structure.end_artifact(pages, self.page.0, self.layer.0);
+    }
+
+    /// Appends a raw content stream operator to the layer, see `PdfLayer::push_operation`.
+    ///
+    /// __NOTE__: It is your responsibility to keep the graphics state balanced.
     #[inline]
-    pub fn use_text<S>(&self, text: S, font_size: i64,
-                       x: Mm, y: Mm, font: &IndirectFontRef)
-    -> () where S: Into<String>
+    pub fn push_operation(&self, operator: &str, operands: Vec<lopdf::Object>) {
+        self.internal_add_operation(Operation::new(operator, operands));
+    }
+
+    /// Add text to the file, x and y are measure in millimeter from the bottom left corner.
+    /// Lines (separated by `\n`) are written below each other, with a leading of
+    /// 1.2 times the font size. See `use_text_with_leading` to change the line spacing.
+    /// The font size is in pt.
+    #[inline]
+    pub fn use_text<S>(&self, text: S, font_size: f64,
+                       x: Mm, y: Mm, font: &IndirectFontRef) where S: Into<String>
+    {
+        let text = text.into();
+        let leading = if text.contains('\n') { Some(Pt(font_size * DEFAULT_LEADING).into()) } else { None };
+        self.internal_use_text(text, font_size, leading, x, y, font);
+    }
+
+    /// Same as `use_text`, but the lines are `leading` apart (baseline to baseline),
+    /// for example 1.5 times the font size for a loose line spacing
+    #[inline]
+    pub fn use_text_with_leading<S>(&self, text: S, font_size: f64, leading: Mm,
+                                    x: Mm, y: Mm, font: &IndirectFontRef) where S: Into<String>
+    {
+        self.internal_use_text(text.into(), font_size, Some(leading), x, y, font);
+    }
+
+    /// Same as `use_text`, but for text that isn't UTF-8, e.g. Latin-1 or Shift-JIS text from
+    /// legacy data. The text is decoded first (see `TextEncoding` for the supported encodings),
+    /// fails with `PdfError::InvalidTextEncoding` if it isn't valid in the encoding.
+    #[inline]
+    pub fn use_text_encoded(&self, text: &[u8], encoding: TextEncoding, font_size: f64,
+                            x: Mm, y: Mm, font: &IndirectFontRef)
+    -> Result<(), Error>
+    {
+        let text = encoding.decode(text)?;
+        self.use_text(text, font_size, x, y, font);
+        Ok(())
+    }
+
+    /// Same as `use_text`, but with synthesized bold and / or italic styles (see `TextOptions`),
+    /// for fonts that only come in a regular weight. Faux bold strokes the glyphs with the
+    /// current fill color, so it only works with device colors (not with patterns).
+    ///
+    /// Fails with `PdfError::MissingGlyph` if the font has no glyph for a character of the
+    /// text and `options.missing_glyphs` is `MissingGlyphPolicy::Error`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn use_text_with_options<S>(&self, text: S, font_size: f64, x: Mm, y: Mm,
+                                    font: &IndirectFontRef, options: TextOptions)
+    -> Result<(), Error> where S: Into<String>
+    {
+        let text = self.replace_missing_glyphs(text.into(), font, options.missing_glyphs)?;
+        let (x, y): (Pt, Pt) = (x.into(), y.into());
+        let fill_color = self.current_state().fill_color;
+
+        self.save_graphics_state();
+        if options.faux_bold {
+            if let Some(color) = fill_color {
+                self.set_outline_color(color);
+            }
+            self.set_outline_thickness(font_size * FAUX_BOLD_STROKE);
+            self.set_text_rendering_mode(TextRenderingMode::FillStroke);
+        }
+
+        self.begin_text_section();
+        self.set_font(font, font_size);
+        if text.contains('\n') {
+            self.set_leading(Pt(font_size * DEFAULT_LEADING).into());
+        }
+        // the line matrix keeps the shear, so the following lines are slanted as well
+        let shear = if options.faux_italic { FAUX_ITALIC_ANGLE.to_radians().tan() } else { 0.0 };
+        self.internal_add_operation(Operation::new("Tm", vec![
+            1.into(), 0.into(), shear.into(), 1.into(), x.into(), y.into()
+        ]));
+        for (idx, line) in text.split('\n').enumerate() {
+            if idx > 0 {
+                self.add_line_break();
+            }
+            self.write_text(line, font);
+        }
+        self.end_text_section();
+        self.restore_graphics_state();
+        Ok(())
+    }
+
+    /// Applies the policy to the characters of the text, that the font has no glyph for
+    fn replace_missing_glyphs(&self, text: String, font: &IndirectFontRef, policy: MissingGlyphPolicy)
+    -> Result<String, Error>
+    {
+        if policy == MissingGlyphPolicy::Notdef {
+            return Ok(text);
+        }
+
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        let face = match doc.fonts.get_font_data(font) {
+            Some(Font::ExternalFont(external)) => external.get_font(),
+            _ => return Ok(text),
+        };
+
+        let mut replaced = String::with_capacity(text.len());
+        for ch in text.chars() {
+            if ch == '\n' || face.glyph_index(ch as u32) != 0 {
+                replaced.push(ch);
+                continue;
+            }
+            match policy {
+                MissingGlyphPolicy::Notdef => replaced.push(ch),
+                MissingGlyphPolicy::Skip => { },
+                MissingGlyphPolicy::Replace(replacement) => replaced.push(replacement),
+                MissingGlyphPolicy::Error => return Err(Error::Pdf(PdfError::MissingGlyph)),
+            }
+        }
+        Ok(replaced)
+    }
+
+    /// Flows the text into `num_columns` columns inside `region`, which are `gutter` apart.
+    /// The text is wrapped at word boundaries (every `\n` starts a new paragraph) with a leading
+    /// of 1.2 times the font size. If all of the text fits, the columns are balanced, so that they
+    /// have (almost) the same length. Otherwise the columns are filled and the text that didn't fit
+    /// is returned, to continue it in the region on the next page.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_text_columns<S>(&self, text: S, font: &IndirectFontRef, font_size: f64,
+                               region: Rect, num_columns: usize, gutter: Mm)
+    -> Option<String> where S: Into<String>
+    {
+        let text = text.into();
+        let num_columns = num_columns.max(1);
+        let gutter: Pt = gutter.into();
+        let column_width = (region.width() - gutter * (num_columns - 1) as f64) / num_columns as f64;
+        let leading = Pt(font_size * DEFAULT_LEADING);
+
+        let lines = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = doc.borrow();
+            wrap_lines(&text, |_| column_width, |line| text_width(&doc.fonts, font, line, font_size))
+        };
+
+        let capacity = line_capacity(region.height(), font_size, leading);
+        let mut remaining = &lines[..];
+
+        for (column, count) in balance_columns(lines.len(), num_columns, capacity).into_iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let column_text = remaining[..count].iter().map(|line| &text[line.clone()]).collect::<Vec<_>>().join("\n");
+            let x = region.llx + (column_width + gutter) * column as f64;
+            self.use_text_with_leading(column_text, font_size, leading.into(), x.into(), (region.ury - Pt(font_size)).into(), font);
+            remaining = &remaining[count..];
+        }
+
+        remaining.first().map(|line| text[line.start..].to_string())
+    }
+
+    /// Flows the text into the region of the page with the name (see
+    /// `PdfPageReference::define_region()`), like `add_text_columns()` with a single column. The
+    /// text is clipped to the region, so that words that are wider than the region don't run
+    /// into the neighbouring regions. Returns the text that didn't fit, to continue it (e.g. in
+    /// the region of the same name on the next page), or `PdfError::UnknownRegion`.
+    pub fn add_text_to_region<S>(&self, name: &str, text: S, font: &IndirectFontRef, font_size: f64)
+    -> Result<Option<String>, Error> where S: Into<String>
+    {
+        let region = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = doc.borrow();
+            doc.layer_page(self).regions.iter().find(|region| region.0 == name).map(|region| region.1)
+        }.ok_or(Error::Pdf(PdfError::UnknownRegion))?;
+
+        self.save_graphics_state();
+        self.internal_add_operation(Operation::new("re", vec![
+            region.llx.into(), region.lly.into(), region.width().into(), region.height().into()
+        ]));
+        self.internal_add_operation(Operation::new("W", vec![]));
+        self.internal_add_operation(Operation::new("n", vec![]));
+        let overflow = self.add_text_columns(text, font, font_size, region, 1, Mm(0.0));
+        self.restore_graphics_state();
+
+        Ok(overflow)
+    }
+
+    /// Uses the outlines of `text` (written like `use_text()`) as a clipping path for everything
+    /// that is drawn inside of `f`, for example to fill a headline with an image or a gradient.
+    /// The text itself isn't painted (text rendering mode 7) and the clipping path is removed
+    /// again after `f`.
+    ///
+    /// ```rust,ignore
+    /// layer.clip_to_text("HEADLINE", &font, 72.0, Mm(10.0), Mm(250.0), |layer| {
+    ///     image.add_to_layer(layer.clone(), Some(Mm(10.0)), Some(Mm(240.0)), None, None, None, None);
+    /// });
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn clip_to_text<S, F>(&self, text: S, font: &IndirectFontRef, font_size: f64, x: Mm, y: Mm, f: F)
+    where S: Into<String>, F: FnOnce(&PdfLayerReference)
+    {
+        self.save_graphics_state();
+        self.set_text_rendering_mode(TextRenderingMode::Clip);
+        self.use_text(text, font_size, x, y, font);
+        f(self);
+        self.restore_graphics_state();
+    }
+
+    /// Fills `rect` with the current fill color and knocks `text` out of it, so that whatever
+    /// is behind the box (the page or the content of the layers below) shows through the
+    /// letters. The text is centered in the box.
+    ///
+    /// The box and the text are drawn into a knockout group: the text is painted with an
+    /// opacity of 0, which replaces the box instead of being composited on top of it.
+    ///
+    /// __NOTE__: Knockout groups require PDF 1.4
+    pub fn add_knockout_text<S>(&self, rect: Rect, text: S, font: &IndirectFontRef, font_size: f64)
+    where S: Into<String>
+    {
+        let text = text.into();
+        let (form_layer, width) = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            let width = text_width(&doc.fonts, font, &text, font_size);
+
+            let (mut pdf_page, layer) = PdfPage::new(Mm(0.0), Mm(0.0), "Knockout", 0);
+            let transparent = pdf_page.add_graphics_state(ExtendedGraphicsStateBuilder::new().with_current_fill_alpha(0.0).build());
+            pdf_page.layers[layer.0].operations = vec![
+                Operation::new("re", vec![rect.llx.into(), rect.lly.into(), rect.width().into(), rect.height().into()]),
+                Operation::new("f", vec![]),
+                Operation::new("gs", vec![lopdf::Object::Name(transparent.gs_name.into())]),
+            ];
+            let id = doc.add_form_page(pdf_page);
+
+            (PdfLayerReference::form_layer(self.document.clone(), id, layer), width)
+        };
+
+        let x = rect.llx + (rect.width() - width) / 2.0;
+        let y = rect.lly + (rect.height() - Pt(font_size * DROP_CAP_HEIGHT)) / 2.0;
+        form_layer.use_text(text, font_size, x.into(), y.into(), font);
+
+        let index = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = doc.borrow_mut();
+            let mut page = doc.remove_form_page(form_layer.form.unwrap()).unwrap();
+            let operations = page.layers.pop().map(|layer| layer.operations).unwrap_or_default();
+            doc.forms.push(ReusableForm { operations, resources: page.resources, bbox: rect, knockout: true });
+            XObjectIndex(doc.forms.len() - 1)
+        };
+        self.draw_form(&index, &[]);
+    }
+
+    /// Draws a label chip (e.g. a status badge): `text` in `text_color`, centered in a rectangle
+    /// filled with `fill_color`, that is `padding` larger than the text on every side and has
+    /// rounded corners (limited to half of the height, which gives a pill shape). `position`
+    /// is the bottom left corner of the chip. The colors of the layer aren't changed. Returns
+    /// the box of the chip, e.g. to place the next chip to the right of it.
+    ///
+    /// __NOTE:__ The text is centered vertically with an approximate cap height of 0.7 em.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_label_chip<S>(&self, text: S, font: &IndirectFontRef, font_size: f64, position: Point,
+                             fill_color: Color, text_color: Color, padding: Mm, corner_radius: Mm)
+    -> Rect where S: Into<String>
+    {
+        let text = text.into();
+        let width = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = doc.borrow();
+            text_width(&doc.fonts, font, &text, font_size)
+        };
+
+        let padding: Pt = padding.into();
+        let chip = Rect {
+            llx: position.x,
+            lly: position.y,
+            urx: position.x + width + padding * 2.0,
+            ury: position.y + Pt(font_size * DROP_CAP_HEIGHT) + padding * 2.0,
+        };
+
+        self.save_graphics_state();
+        self.set_fill_color(fill_color);
+        for operation in rounded_rect_operations(chip, corner_radius.into()) {
+            self.internal_add_operation(operation);
+        }
+        self.internal_add_operation(Operation::new("f", vec![]));
+        self.set_fill_color(text_color);
+        self.use_text(text, font_size, (chip.llx + padding).into(), (chip.lly + padding).into(), font);
+        self.restore_graphics_state();
+
+        chip
+    }
+
+    /// Writes a paragraph into `region`, which starts with a drop cap: the first letter is written
+    /// in `cap_font`, large enough to span the first `cap_lines` lines, and these lines are indented
+    /// by the width of the letter. The rest of the text is wrapped like `add_text_columns()` (with a
+    /// single column) and the text that doesn't fit into the region is returned.
+    ///
+    /// __NOTE:__ The size of the letter is computed from an approximate cap height of 0.7 em, so
+    /// that the top of the letter is aligned with the top of the capital letters of the first line.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_paragraph_with_dropcap<S>(&self, text: S, body_font: &IndirectFontRef, font_size: f64,
+                                         cap_font: &IndirectFontRef, cap_lines: usize, region: Rect)
+    -> Option<String> where S: Into<String>
+    {
+        let text = text.into();
+        let cap = match text.chars().next() {
+            Some(cap) => cap.to_string(),
+            None => return None,
+        };
+        let body = &text[cap.len()..];
+        let cap_lines = cap_lines.max(1);
+        let leading = Pt(font_size * DEFAULT_LEADING);
+        let first_baseline = region.ury - Pt(font_size);
+
+        // the letter sits on the baseline of the last line it spans
+        let cap_size = (leading.0 * (cap_lines - 1) as f64 + DROP_CAP_HEIGHT * font_size) / DROP_CAP_HEIGHT;
+        let (cap_width, lines) = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = doc.borrow();
+            let cap_width = text_width(&doc.fonts, cap_font, &cap, cap_size) + Pt(font_size * 0.5);
+            let lines = wrap_lines(body, |line| if line < cap_lines { region.width() - cap_width } else { region.width() },
+                                   |line| text_width(&doc.fonts, body_font, line, font_size));
+            (cap_width, lines)
+        };
+
+        let capacity = line_capacity(region.height(), font_size, leading);
+        let shown = lines.len().min(capacity);
+        let indented = shown.min(cap_lines);
+        let join = |lines: &[::std::ops::Range<usize>]| lines.iter().map(|line| &body[line.clone()]).collect::<Vec<_>>().join("\n");
+
+        self.use_text(cap, cap_size, region.llx.into(), (first_baseline - leading * (cap_lines - 1) as f64).into(), cap_font);
+        if indented > 0 {
+            self.use_text_with_leading(join(&lines[..indented]), font_size, leading.into(),
+                                       (region.llx + cap_width).into(), first_baseline.into(), body_font);
+        }
+        if shown > indented {
+            self.use_text_with_leading(join(&lines[indented..shown]), font_size, leading.into(),
+                                       region.llx.into(), (first_baseline - leading * indented as f64).into(), body_font);
+        }
+
+        lines.get(shown).map(|line| body[line.start..].to_string())
+    }
+
+    /// Writes the text along a path (e.g. a circle, see `Line`), starting `offset` from the start
+    /// of the path. Every character is placed on its own with its baseline on the path and rotated
+    /// along the path, so the text is above the path in the direction the path is drawn.
+    /// The path itself is not drawn. The text that doesn't fit on the path is returned.
+    ///
+    /// __NOTE:__ Kerning and ligatures are not applied, and the characters are rotated along the
+    /// chord of the path below them, so very tight curves look uneven.
+    pub fn add_text_on_path<S>(&self, text: S, path: &Line, font: &IndirectFontRef, font_size: f64, offset: Mm)
+    -> Option<String> where S: Into<String>
+    {
+        let text = text.into();
+        let glyphs: Vec<(usize, char)> = text.char_indices().collect();
+        let advances: Vec<Pt> = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = doc.borrow();
+            let mut buffer = [0; 4];
+            glyphs.iter().map(|(_, ch)| text_width(&doc.fonts, font, ch.encode_utf8(&mut buffer), font_size)).collect()
+        };
+        let positions = glyph_positions(&flatten(path), &advances, offset.into());
+
+        self.begin_text_section();
+        self.set_font(font, font_size);
+        for (&(_, ch), position) in glyphs.iter().zip(positions.iter()) {
+            if let Some((origin, angle)) = *position {
+                self.set_text_matrix(TextMatrix::TranslateRotate(origin.x, origin.y, angle));
+                self.write_text(ch.to_string(), font);
+            }
+        }
+        self.end_text_section();
+
+        positions.iter().position(Option::is_none).map(|idx| text[glyphs[idx].0..].to_string())
+    }
+
+    /// Draws the text as filled paths (the outlines of the glyphs) in the current fill color,
+    /// starting with the baseline at the position. The font is not added to the document and
+    /// never embedded, which is useful for fonts whose license doesn't allow embedding.
+    /// Color glyphs (COLR fonts, e.g. emoji) are drawn with the colors of their layers.
+    ///
+    /// __NOTE:__ The text can't be selected or searched in the PDF. Kerning and ligatures
+    /// are not applied.
+    pub fn add_text_as_paths<S>(&self, text: S, font: &ExternalFont, font_size: f64, x: Mm, y: Mm)
+    where S: Into<String>
+    {
+        let face = font.get_font();
+        let (Pt(mut pen_x), Pt(origin_y)) = (x.into(), y.into());
+        let mut operations = Vec::new();
+        let mut path = Vec::new();
+
+        for ch in text.into().chars() {
+            let glyph = face.glyph_index(ch as u32);
+            match color_layers(&font.font_bytes, glyph) {
+                Some(layers) => {
+                    fill_path(&mut operations, &mut path);
+                    operations.extend(color_glyph_operations(font, &layers, font_size, (pen_x, origin_y)));
+                },
+                // all other glyphs are filled together, so that the holes of the letters stay empty
+                None => path.extend(outline_operations(&font.glyph_outline(glyph), font_size, (pen_x, origin_y))),
+            }
+            pen_x += face.advance_width(glyph).unwrap_or(0.0) / face.units_per_em() * font_size;
+        }
+        fill_path(&mut operations, &mut path);
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        doc.layer_page_mut(self).layers[self.layer.0].operations.extend(operations);
+    }
+
+    /// Writes a single line of text at the position, like `use_text()`, but the color glyphs
+    /// of COLR fonts (e.g. emoji) are drawn as paths with the colors of their layers, while
+    /// all other glyphs are written as text. Only COLR version 0 fonts are supported, not
+    /// color bitmaps (`sbix`, `CBDT`). For built-in fonts this is the same as `use_text()`.
+    ///
+    /// __NOTE:__ The color glyphs can't be selected or searched in the PDF.
+    pub fn add_color_text<S>(&self, text: S, font_size: f64, x: Mm, y: Mm, font: &IndirectFontRef)
+    where S: Into<String>
+    {
+        use lopdf::Object::*;
+        use lopdf::StringFormat::Hexadecimal;
+
+        let text = text.into();
+        let doc = self.document.upgrade().unwrap();
+        let external_font = match doc.borrow().fonts.get_font_data(font) {
+            Some(Font::ExternalFont(font)) => font.clone(),
+            _ => return self.use_text(text, font_size, x, y, font),
+        };
+        let face = external_font.get_font();
+
+        let (Pt(mut pen_x), Pt(origin_y)) = (x.into(), y.into());
+        let mut operations = Vec::new();
+        let mut run: Vec<u8> = Vec::new();
+        let mut run_start = pen_x;
+        let flush = |operations: &mut Vec<Operation>, run: &mut Vec<u8>, start: f64| if !run.is_empty() {
+            operations.push(Operation::new("BT", vec![]));
+            operations.push(Operation::new("Tf", vec![font.name.clone().into(), font_size.into()]));
+            operations.push(Operation::new("Td", vec![Pt(start).into(), Pt(origin_y).into()]));
+            operations.push(Operation::new("Tj", vec![String(::std::mem::take(run), Hexadecimal)]));
+            operations.push(Operation::new("ET", vec![]));
+        };
+
+        for glyph in external_font.encode_text(&face, &text) {
+            match color_layers(&external_font.font_bytes, glyph) {
+                Some(layers) => {
+                    flush(&mut operations, &mut run, run_start);
+                    operations.extend(color_glyph_operations(&external_font, &layers, font_size, (pen_x, origin_y)));
+                },
+                None => {
+                    if run.is_empty() {
+                        run_start = pen_x;
+                    }
+                    run.extend_from_slice(&glyph.to_be_bytes());
+                },
+            }
+            pen_x += face.advance_width(glyph).unwrap_or(0.0) / face.units_per_em() * font_size;
+        }
+        flush(&mut operations, &mut run, run_start);
+
+        let mut doc = doc.borrow_mut();
+        doc.layer_page_mut(self).layers[self.layer.0].operations.extend(operations);
+    }
+
+    fn internal_use_text(&self, text: String, font_size: f64, leading: Option<Mm>,
+                         x: Mm, y: Mm, font: &IndirectFontRef)
     {
-            self.begin_text_section();
-            self.set_font(font, font_size);
-            self.set_text_cursor(x, y);
-            self.write_text(text, font);
-            self.end_text_section();
+        self.begin_text_section();
+        self.set_font(font, font_size);
+        if let Some(leading) = leading {
+            self.set_leading(leading);
+        }
+        self.set_text_cursor(x, y);
+        for (idx, line) in text.split('\n').enumerate() {
+            if idx > 0 {
+                self.add_line_break();
+            }
+            self.write_text(line, font);
+        }
+        self.end_text_section();
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        if doc.layer_page(self).layers[self.layer.0].cursor_mode {
+            let lines = text.split('\n').count();
+            let last_line = text.rsplit('\n').next().unwrap_or("");
+            let width: Mm = text_width(&doc.fonts, font, last_line, font_size).into();
+            let down = leading.map(|leading| leading.0 * (lines - 1) as f64).unwrap_or(0.0);
+            doc.layer_page_mut(self).layers[self.layer.0].cursor = Some((x + width, Mm(y.0 - down)));
+        }
     }
 
 /*
@@ -492,7 +1576,7 @@ impl PdfLayerReference {
     {
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let page_mut = &mut doc.pages[self.page.0];
+        let page_mut = doc.layer_page_mut(self);
 
         page_mut.layers[self.layer.0]
           .operations.push(lopdf::content::Operation::new(
@@ -506,7 +1590,561 @@ impl PdfLayerReference {
     {
         let doc = self.document.upgrade().unwrap();
         let mut doc = doc.borrow_mut();
-        let layer = &mut doc.pages[self.page.0].layers[self.layer.0];
+        let layer = &mut doc.layer_page_mut(self).layers[self.layer.0];
         layer.operations.push(op.into());
     }
 }
+
+/// Text (in UTF-16 code units) of each glyph for the `ToUnicode` map, see
+/// `PdfLayerReference::add_glyphs_with_text()`. Empty if the glyphs can't be matched
+/// with the characters, e.g. if some of them are ligatures.
+fn shaped_glyph_text(glyphs: &[(u16, Point)], text: &str)
+-> Vec<(u16, Vec<u32>)>
+{
+    let utf16 = |text: &str| text.encode_utf16().map(u32::from).collect::<Vec<u32>>();
+    let characters = text.chars().collect::<Vec<char>>();
+
+    if characters.len() == glyphs.len() {
+        glyphs.iter().zip(characters).map(|(glyph, c)| (glyph.0, utf16(&c.to_string()))).collect()
+    } else if glyphs.len() == 1 {
+        vec![(glyphs[0].0, utf16(text))]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Path operations of a rectangle with rounded corners, the radius is limited to half of the
+/// width and height. The corners are approximated with one Bézier curve each.
+fn rounded_rect_operations(rect: Rect, radius: Pt)
+-> Vec<Operation>
+{
+    // distance of the control points from the ends of a quarter circle
+    const KAPPA: f64 = 0.552_284_75;
+
+    let r = radius.0.max(0.0).min(rect.width().0 / 2.0).min(rect.height().0 / 2.0);
+    let k = r * (1.0 - KAPPA);
+    let (left, bottom, right, top) = (rect.llx.0, rect.lly.0, rect.urx.0, rect.ury.0);
+    let point = |points: &[f64]| points.iter().map(|p| Pt(*p).into()).collect::<Vec<lopdf::Object>>();
+
+    vec![
+        Operation::new("m", point(&[left + r, bottom])),
+        Operation::new("l", point(&[right - r, bottom])),
+        Operation::new("c", point(&[right - k, bottom, right, bottom + k, right, bottom + r])),
+        Operation::new("l", point(&[right, top - r])),
+        Operation::new("c", point(&[right, top - k, right - k, top, right - r, top])),
+        Operation::new("l", point(&[left + r, top])),
+        Operation::new("c", point(&[left + k, top, left, top - k, left, top - r])),
+        Operation::new("l", point(&[left, bottom + r])),
+        Operation::new("c", point(&[left, bottom + k, left + k, bottom, left + r, bottom])),
+        Operation::new("h", vec![]),
+    ]
+}
+
+/// Path operations of a glyph outline, scaled to the font size and moved to the origin
+fn outline_operations(outline: &[PathSegment], font_size: f64, (origin_x, origin_y): (f64, f64))
+-> Vec<Operation>
+{
+    use types::plugins::graphics::two_dimensional::outline::PathSegment::*;
+
+    let point = |x: f64, y: f64| vec![Pt(origin_x + x).into(), Pt(origin_y + y).into()];
+    let mut current = (0.0, 0.0);
+    outline.iter().map(|segment| match segment.scaled(font_size) {
+        MoveTo(x, y) => {
+            current = (x, y);
+            Operation::new("m", point(x, y))
+        },
+        LineTo(x, y) => {
+            current = (x, y);
+            Operation::new("l", point(x, y))
+        },
+        QuadTo(x1, y1, x, y) => {
+            // the same curve as a cubic bezier curve
+            let mut operands = point(current.0 + (x1 - current.0) * 2.0 / 3.0, current.1 + (y1 - current.1) * 2.0 / 3.0);
+            operands.extend(point(x + (x1 - x) * 2.0 / 3.0, y + (y1 - y) * 2.0 / 3.0));
+            operands.extend(point(x, y));
+            current = (x, y);
+            Operation::new("c", operands)
+        },
+        CurveTo(x1, y1, x2, y2, x, y) => {
+            let mut operands = point(x1, y1);
+            operands.extend(point(x2, y2));
+            operands.extend(point(x, y));
+            current = (x, y);
+            Operation::new("c", operands)
+        },
+        Close => Operation::new("h", Vec::new()),
+    }).collect()
+}
+
+/// Fills the collected path operations, if there are any
+fn fill_path(operations: &mut Vec<Operation>, path: &mut Vec<Operation>)
+{
+    if !path.is_empty() {
+        operations.append(path);
+        operations.push(Operation::new("f", Vec::new()));
+    }
+}
+
+/// Draws the layers of a color glyph, each in its own graphics state
+fn color_glyph_operations(font: &ExternalFont, layers: &[(u16, Option<[u8; 3]>)], font_size: f64, origin: (f64, f64))
+-> Vec<Operation>
+{
+    let mut operations = Vec::new();
+    for &(glyph, color) in layers {
+        operations.push(Operation::new("q", Vec::new()));
+        if let Some([r, g, b]) = color {
+            let color = Color::Rgb(Rgb::new(f64::from(r) / 255.0, f64::from(g) / 255.0, f64::from(b) / 255.0, None));
+            operations.push(PdfColor::FillColor(color).into());
+        }
+        let mut path = outline_operations(&font.glyph_outline(glyph), font_size, origin);
+        fill_path(&mut operations, &mut path);
+        operations.push(Operation::new("Q", Vec::new()));
+    }
+    operations
+}
+
+#[test]
+fn test_push_operation()
+{
+    let mut layer = PdfLayer::new("Layer 1");
+    layer.push_operation("w", vec![2.into()]);
+
+    let content = lopdf::content::Content { operations: layer.operations };
+    assert_eq!(content.encode().unwrap(), b"2 w\n".to_vec());
+}
+
+#[test]
+fn test_miter_limit()
+{
+    use PdfDocument;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.set_line_join_style(LineJoinStyle::Miter);
+    layer.set_miter_limit(4.0);
+    layer.set_miter_limit(0.5);
+
+    let document = doc.document.borrow();
+    let operations = &document.pages[0].layers[0].operations;
+    let limits: Vec<(&str, f64)> = operations[1..].iter().map(|op| (op.operator.as_str(), op.operands[0].as_f64().unwrap())).collect();
+    assert_eq!(operations[0].operator, "j");
+    assert_eq!(limits, vec![("M", 4.0), ("M", 1.0)]);
+}
+
+#[test]
+fn test_text_as_paths()
+{
+    use PdfDocument;
+    use types::plugins::graphics::two_dimensional::sfnt::test_font;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let layer = doc.get_page(page).get_layer(layer);
+    let font = ExternalFont::new(&test_font()[..], 0).unwrap();
+    layer.add_text_as_paths("AA", &font, 10.0, Mm(0.0), Mm(0.0));
+
+    // 'A' is a square of 0.1 em, with an advance of 0.6 em
+    let document = doc.document.borrow();
+    let operations: Vec<(&str, Vec<f64>)> = document.pages[0].layers[0].operations.iter()
+        .map(|op| (op.operator.as_str(), op.operands.iter().map(|o| o.as_f64().unwrap()).collect())).collect();
+    let square = |x: f64| vec![("m", vec![x, 0.0]), ("l", vec![x + 1.0, 0.0]), ("l", vec![x + 1.0, 1.0]), ("l", vec![x, 1.0]), ("h", vec![])];
+    let mut expected = square(0.0);
+    expected.extend(square(6.0));
+    expected.push(("f", vec![]));
+    assert_eq!(operations, expected);
+    assert!(document.fonts.is_empty());
+}
+
+#[test]
+fn test_color_glyphs()
+{
+    use PdfDocument;
+    use types::plugins::graphics::two_dimensional::sfnt::test_font_with;
+    use types::plugins::graphics::two_dimensional::colr::test_color_tables;
+
+    // 'B' is a color glyph: a red square below a square in the fill color
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&test_font_with(test_color_tables())[..]).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.add_color_text("AB", 10.0, Mm(0.0), Mm(0.0), &font);
+
+    let document = doc.document.borrow();
+    let operators: Vec<&str> = document.pages[0].layers[0].operations.iter().map(|op| op.operator.as_str()).collect();
+    let square = ["m", "l", "l", "l", "h", "f"];
+    let mut expected = vec!["BT", "Tf", "Td", "Tj", "ET", "q", "rg"];
+    expected.extend_from_slice(&square);
+    expected.extend_from_slice(&["Q", "q"]);
+    expected.extend_from_slice(&square);
+    expected.push("Q");
+    assert_eq!(operators, expected);
+
+    // the color glyph starts after the advance of 'A'
+    let operations = &document.pages[0].layers[0].operations;
+    assert_eq!(operations[7].operands.iter().map(|o| o.as_f64().unwrap()).collect::<Vec<f64>>(), vec![6.0, 0.0]);
+}
+
+#[test]
+fn test_use_text_leading()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+
+    layer.use_text("first\nsecond", 10.0, Mm(10.0), Mm(10.0), &font);
+    layer.use_text_with_leading("single", 10.5, Pt(15.0).into(), Mm(10.0), Mm(10.0), &font);
+
+    let document = doc.document.borrow();
+    let operators: Vec<&str> = document.pages[0].layers[0].operations.iter().map(|op| op.operator.as_str()).collect();
+    assert_eq!(operators, vec!["BT", "Tf", "TL", "Td", "Tj", "T*", "Tj", "ET", "BT", "Tf", "TL", "Td", "Tj", "ET"]);
+
+    // fractional font sizes are written as they are
+    let set_font = &document.pages[0].layers[0].operations[9];
+    assert_eq!(set_font.operands[1].as_f64().unwrap(), 10.5);
+}
+
+#[test]
+fn test_set_overprint()
+{
+    use PdfDocument;
+    use types::plugins::graphics::extgstate::{OVERPRINT_FILL, OVERPRINT_STROKE, OVERPRINT_MODE};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.get_page(page).get_layer(layer).set_overprint(true, false, OverprintMode::KeepUnderlying);
+
+    let document = doc.document.borrow();
+    let operations = &document.pages[0].layers[0].operations;
+    assert_eq!(operations.len(), 1);
+    assert_eq!(operations[0].operator, "gs");
+
+    let name = operations[0].operands[0].as_name_str().unwrap();
+    let state = &document.pages[0].resources.graphics_states.all_graphics_states[name].1;
+    assert!(state.overprint_fill && !state.overprint_stroke);
+    assert_eq!(state.overprint_mode, OverprintMode::KeepUnderlying);
+    for field in &[OVERPRINT_FILL, OVERPRINT_STROKE, OVERPRINT_MODE] {
+        assert!(state.changed_fields.contains(field));
+    }
+}
+
+#[test]
+fn test_add_line_gradient()
+{
+    use PdfDocument;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let start = Point { x: Pt(0.0), y: Pt(0.0) };
+    let end = Point { x: Pt(100.0), y: Pt(0.0) };
+    let line = Line { points: vec![(start, false), (end, false)], has_stroke: true, .. Line::default() };
+    let gradient = LinearGradient::new(start, end, Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None)), Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None)));
+    doc.get_page(page).get_layer(layer).add_line(line, 2.0, gradient);
+
+    let document = doc.document.borrow();
+    let operators = document.pages[0].layers[0].operations.iter().map(|o| o.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["q", "cs", "scn", "m", "l", "l", "l", "h", "f", "Q"]);
+    assert_eq!(document.pages[0].resources.patterns.len(), 1);
+}
+
+#[test]
+fn test_add_text_to_region()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let page = doc.get_page(page);
+    // room for two lines of 10pt
+    page.define_region("sidebar", Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(100.0), ury: Pt(32.0) });
+    page.define_region("body", Rect { llx: Pt(110.0), lly: Pt(10.0), urx: Pt(500.0), ury: Pt(500.0) });
+    page.define_region("sidebar", Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(100.0), ury: Pt(33.0) });
+    assert_eq!(page.region("sidebar"), Some(Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(100.0), ury: Pt(33.0) }));
+
+    let layer = page.get_layer(layer);
+    let overflow = layer.add_text_to_region("sidebar", "one\ntwo\nthree", &font, 10.0).unwrap();
+    assert_eq!(overflow, Some("three".to_string()));
+    assert_eq!(layer.add_text_to_region("body", "one", &font, 10.0).unwrap(), None);
+    assert!(layer.add_text_to_region("footer", "one", &font, 10.0).is_err());
+
+    let document = doc.document.borrow();
+    let operators: Vec<&str> = document.pages[0].layers[0].operations.iter().map(|op| op.operator.as_str()).collect();
+    assert_eq!(&operators[..4], &["q", "re", "W", "n"]);
+    assert_eq!(operators.iter().filter(|op| **op == "Q").count(), 2);
+}
+
+#[test]
+fn test_current_state()
+{
+    use {PdfDocument, BuiltinFont, Cmyk};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    assert_eq!(layer.current_state().depth, 0);
+    assert_eq!(layer.current_state().line_width, 1.0);
+
+    layer.set_fill_color(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None)));
+    layer.save_graphics_state();
+    layer.set_outline_color(Color::Cmyk(Cmyk::new(0.0, 0.5, 0.0, 0.0, None)));
+    layer.set_outline_thickness(2.0);
+    layer.set_font(&font, 12.0);
+    layer.internal_add_operation(Operation::new("cm", vec![1.into(), 0.into(), 0.into(), 1.into(), 10.into(), 20.into()]));
+
+    let state = layer.current_state();
+    assert_eq!(state.depth, 1);
+    assert_eq!(state.ctm, [1.0, 0.0, 0.0, 1.0, 10.0, 20.0]);
+    assert_eq!(state.fill_color, Some(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None))));
+    assert_eq!(state.outline_color, Some(Color::Cmyk(Cmyk::new(0.0, 0.5, 0.0, 0.0, None))));
+    assert_eq!((state.line_width, state.font, state.font_size), (2.0, Some(font), 12.0));
+
+    // the fill color was set before the graphics state was saved
+    layer.restore_graphics_state();
+    let state = layer.current_state();
+    assert_eq!(state.depth, 0);
+    assert_eq!(state.line_width, 1.0);
+    assert_eq!(state.fill_color, Some(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None))));
+    assert_eq!(state.font, None);
+}
+
+#[test]
+fn test_set_opacity()
+{
+    use PdfDocument;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let background = page.add_layer("Background");
+    background.set_opacity(0.5);
+    page.get_layer(layer).set_opacity(-1.0);
+
+    let document = doc.document.borrow();
+    let opacities = document.pages[0].layers.iter().map(|layer| layer.opacity).collect::<Vec<f64>>();
+    assert_eq!(opacities, vec![0.0, 0.5]);
+}
+
+#[test]
+fn test_clip_to_text()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::HelveticaBold).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.clip_to_text("Title", &font, 72.0, Mm(10.0), Mm(250.0), |layer| {
+        layer.push_operation("re", vec![0.into(), 0.into(), 595.into(), 842.into()]);
+        layer.push_operation("f", vec![]);
+    });
+
+    let document = doc.document.borrow();
+    let operations = &document.pages[0].layers[0].operations;
+    let operators = operations.iter().map(|op| op.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["q", "Tr", "BT", "Tf", "Td", "Tj", "ET", "re", "f", "Q"]);
+    assert_eq!(operations[1].operands[0].as_i64().unwrap(), 7);
+}
+
+#[test]
+fn test_add_knockout_text()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::HelveticaBold).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    let banner = Rect::new(Mm(10.0), Mm(250.0), Mm(190.0), Mm(30.0));
+    layer.add_knockout_text(banner, "SALE", &font, 48.0);
+
+    let document = doc.document.borrow();
+    assert_eq!(document.pages.len(), 1);
+    let operators = document.pages[0].layers[0].operations.iter().map(|op| op.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["q", "Do", "Q"]);
+
+    let form = &document.forms[0];
+    assert!(form.knockout);
+    assert_eq!(form.bbox, banner);
+    let operators = form.operations.iter().map(|op| op.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["re", "f", "gs", "BT", "Tf", "Td", "Tj", "ET"]);
+}
+
+#[test]
+fn test_use_text_with_options()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None)));
+    layer.use_text_with_options("Bold", 20.0, Mm(10.0), Mm(10.0), &font, TextOptions { faux_bold: true, ..TextOptions::default() }).unwrap();
+    layer.use_text_with_options("Italic", 20.0, Mm(10.0), Mm(10.0), &font, TextOptions { faux_italic: true, ..TextOptions::default() }).unwrap();
+
+    let document = doc.document.borrow();
+    let operations = &document.pages[0].layers[0].operations;
+    let operators = operations.iter().map(|op| op.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec![
+        "rg", "q", "RG", "w", "Tr", "BT", "Tf", "Tm", "Tj", "ET", "Q",
+        "q", "BT", "Tf", "Tm", "Tj", "ET", "Q",
+    ]);
+    assert_eq!(operations[3].operands[0].as_f64().unwrap(), 0.6);
+    assert_eq!(operations[7].operands[2].as_f64().unwrap(), 0.0);
+    assert!((operations[14].operands[2].as_f64().unwrap() - 0.2126).abs() < 1e-4);
+    assert_eq!(layer.current_state().line_width, 1.0);
+}
+
+#[test]
+fn test_missing_glyph_policy()
+{
+    use PdfDocument;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&include_bytes!("../../assets/fonts/RobotoMedium.ttf")[..]).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    let options = |missing_glyphs| TextOptions { missing_glyphs, ..TextOptions::default() };
+
+    // Roboto has no CJK glyphs
+    let text = "a\u{4e2d}b\nc".to_string();
+    assert_eq!(layer.replace_missing_glyphs(text.clone(), &font, MissingGlyphPolicy::Notdef).unwrap(), text);
+    assert_eq!(layer.replace_missing_glyphs(text.clone(), &font, MissingGlyphPolicy::Skip).unwrap(), "ab\nc");
+    assert_eq!(layer.replace_missing_glyphs(text.clone(), &font, MissingGlyphPolicy::Replace('?')).unwrap(), "a?b\nc");
+
+    let error = layer.use_text_with_options(text, 10.0, Mm(10.0), Mm(10.0), &font, options(MissingGlyphPolicy::Error));
+    assert!(matches!(error, Err(Error::Pdf(PdfError::MissingGlyph))));
+    assert!(doc.document.borrow().pages[0].layers[0].operations.is_empty());
+}
+
+#[test]
+fn test_cursor_mode()
+{
+    use PdfDocument;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&include_bytes!("../../assets/fonts/RobotoMedium.ttf")[..]).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+
+    layer.use_text("ab", 10.0, Mm(10.0), Mm(100.0), &font);
+    assert!(layer.text_cursor().is_none());
+
+    layer.set_cursor_mode(true);
+    layer.use_text("ab", 10.0, Mm(10.0), Mm(100.0), &font);
+    let width: Mm = text_width(&doc.document.borrow().fonts, &font, "ab", 10.0).into();
+    assert_eq!(layer.text_cursor(), Some((Mm(10.0) + width, Mm(100.0))));
+
+    layer.use_text_with_leading("first line\nab", 10.0, Mm(5.0), Mm(10.0), Mm(100.0), &font);
+    assert_eq!(layer.text_cursor(), Some((Mm(10.0) + width, Mm(95.0))));
+
+    layer.set_cursor_mode(false);
+    assert!(layer.text_cursor().is_none());
+}
+
+#[test]
+fn test_shaped_glyph_text()
+{
+    let position = Point::new(Mm(10.0), Mm(10.0));
+    assert_eq!(shaped_glyph_text(&[(900, position), (901, position)], "a\u{1d11e}"), vec![(900, vec![0x61]), (901, vec![0xd834, 0xdd1e])]);
+    assert_eq!(shaped_glyph_text(&[(902, position)], "ffi"), vec![(902, vec![0x66, 0x66, 0x69])]);
+    assert!(shaped_glyph_text(&[(903, position), (904, position)], "abc").is_empty());
+}
+
+#[test]
+fn test_add_label_chip()
+{
+    use {PdfDocument, Rgb};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(&include_bytes!("../../assets/fonts/RobotoMedium.ttf")[..]).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+
+    let green = Color::Rgb(Rgb::new(0.1, 0.6, 0.2, None));
+    let white = Color::Rgb(Rgb::new(1.0, 1.0, 1.0, None));
+    let chip = layer.add_label_chip("Done", &font, 10.0, Point { x: Pt(100.0), y: Pt(200.0) }, green, white, Mm(2.0), Mm(10.0));
+
+    let width = text_width(&doc.document.borrow().fonts, &font, "Done", 10.0);
+    let padding: Pt = Mm(2.0).into();
+    assert_eq!(chip, Rect { llx: Pt(100.0), lly: Pt(200.0), urx: Pt(100.0) + width + padding * 2.0, ury: Pt(207.0) + padding * 2.0 });
+
+    let document = doc.document.borrow();
+    let operators = document.pages[0].layers[0].operations.iter().map(|op| op.operator.as_str()).collect::<Vec<_>>();
+    assert_eq!(&operators[..13], &["q", "rg", "m", "l", "c", "l", "c", "l", "c", "l", "c", "h", "f"]);
+    assert_eq!(operators.last(), Some(&"Q"));
+
+    // the radius is limited to half of the height
+    let operations = rounded_rect_operations(chip, Pt(100.0));
+    let start = operations[0].operands[0].as_f64().unwrap();
+    assert!((start - (100.0 + chip.height().0 / 2.0)).abs() < 1e-6);
+}
+
+#[test]
+fn test_soft_mask_and_pattern_reuse_form()
+{
+    use {PdfDocument, Image, ColorSpace, ColorBits, Px};
+
+    let (doc, page1, layer1) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let (page2, layer2) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    let image = ImageXObject::new(Px(16), Px(16), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![128; 256]);
+    let form = doc.define_form(|layer| Image::from(image).add_to_layer(layer.clone(), None, None, None, None, None, Some(72.0)));
+    let rect = Rect { llx: Pt(0.0), lly: Pt(0.0), urx: Pt(16.0), ury: Pt(16.0) };
+
+    for (page, layer) in vec![(page1, layer1), (page2, layer2)] {
+        let layer = doc.get_page(page).get_layer(layer);
+        layer.set_soft_mask(&form, rect);
+        layer.set_fill_pattern(&form, Mm(5.0), Mm(5.0));
+    }
+    assert!(doc.unused_resources().is_empty());
+
+    // two soft masks and two patterns, the image is written once
+    let bytes = doc.save_to_bytes().unwrap();
+    let count = |needle: &[u8]| bytes.windows(needle.len()).filter(|w| *w == needle).count();
+    assert_eq!(count(b"/Image"), 1);
+    assert_eq!(count(b"/PatternType"), 2);
+    assert_eq!(count(b"/Luminosity"), 2);
+}
+
+#[test]
+fn test_fractional_font_sizes()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.use_text("whole", 12.0, Mm(10.0), Mm(10.0), &font);
+    layer.use_text("fractional", 10.5, Mm(10.0), Mm(20.0), &font);
+
+    let document = doc.document.borrow();
+    let sizes = document.pages[0].layers[0].operations.iter()
+        .filter(|op| op.operator == "Tf")
+        .map(|op| op.operands[1].as_f64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, vec![12.0, 10.5]);
+}
+
+#[test]
+fn test_paragraph_with_dropcap()
+{
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let body_font = doc.add_builtin_font(BuiltinFont::TimesRoman).unwrap();
+    let cap_font = doc.add_builtin_font(BuiltinFont::TimesBold).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    let text = "Once upon a time there was a paragraph that was long enough to wrap around the large \
+                first letter and to continue below it for a few more lines of text.";
+    let region = Rect { llx: Pt(100.0), lly: Pt(100.0), urx: Pt(250.0), ury: Pt(400.0) };
+    assert_eq!(layer.add_paragraph_with_dropcap(text, &body_font, 10.0, &cap_font, 2, region), None);
+
+    let document = doc.document.borrow();
+    let operations = &document.pages[0].layers[0].operations;
+    let sizes = operations.iter().filter(|op| op.operator == "Tf").map(|op| op.operands[1].as_f64().unwrap()).collect::<Vec<_>>();
+    let x = operations.iter().filter(|op| op.operator == "Td").map(|op| op.operands[0].as_f64().unwrap()).collect::<Vec<_>>();
+    // the letter spans two lines, the first two lines are indented by its width
+    assert_eq!(sizes.len(), 3);
+    assert!(sizes[0] > 2.0 * 10.0 && sizes[1] == 10.0 && sizes[2] == 10.0);
+    assert_eq!(x.len(), 3);
+    assert!((x[0] - 100.0).abs() < 0.001 && (x[2] - 100.0).abs() < 0.001);
+    assert!(x[1] > 100.0);
+    let first = operations.iter().find(|op| op.operator == "Tj").unwrap();
+    assert_eq!(format!("{:?}", first.operands), "[(O)]");
+
+    // the text that doesn't fit into the region is returned
+    let small = Rect { llx: Pt(100.0), lly: Pt(370.0), urx: Pt(250.0), ury: Pt(400.0) };
+    drop(document);
+    let rest = layer.add_paragraph_with_dropcap(text, &body_font, 10.0, &cap_font, 2, small).unwrap();
+    assert!(text.ends_with(&rest) && rest.len() < text.len() - 1);
+}
@@ -7,6 +7,7 @@ use {
 };
 
 use glob_defines::ICC_PROFILE_ECI_V2;
+use utils::random_character_string_32;
 
 /// This is a wrapper in order to keep shared data between the documents XMP metadata and
 /// the "Info" dictionary in sync
@@ -20,8 +21,8 @@ pub struct PdfMetadata {
 	pub metadata_date: OffsetDateTime,
 	/// PDF document title
 	pub document_title: String,
-	/// Is the document trapped?
-	pub trapping: bool,
+	/// Is the document trapped? Written as `/Trapped` into the document info and the XMP metadata
+	pub trapping: Trapped,
 	/// PDF document version
 	pub document_version: u32,
 	/// PDF Standard
@@ -34,6 +35,40 @@ pub struct PdfMetadata {
 	pub target_icc_profile: Option<IccProfile>,
 }
 
+/// Trapping state of the document: have the traps (small overlaps of adjacent colors, which hide
+/// gaps caused by misregistration on the press) already been added? Prepress workflows check this
+/// to decide whether the RIP has to trap the document.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Trapped {
+	/// The document is fully trapped, the RIP mustn't add traps
+	True,
+	/// The document hasn't been trapped yet
+	#[default]
+	False,
+	/// The trapping state is unknown. Not allowed in PDF/X documents.
+	Unknown,
+}
+
+impl Trapped {
+
+	/// Value of the `/Trapped` entry and the `pdf:Trapped` XMP property
+	pub(crate) fn as_str(self)
+	-> &'static str
+	{
+		match self {
+			Trapped::True => "True",
+			Trapped::False => "False",
+			Trapped::Unknown => "Unknown",
+		}
+	}
+}
+
+impl From<bool> for Trapped {
+	fn from(trapped: bool) -> Self {
+		if trapped { Trapped::True } else { Trapped::False }
+	}
+}
+
 impl PdfMetadata {
 
 	/// Creates a new metadat object
@@ -47,7 +82,7 @@ impl PdfMetadata {
 			modification_date: current_time,
 			metadata_date: current_time,
 			document_title: title.into(),
-			trapping: trapping,
+			trapping: trapping.into(),
 			document_version: document_version,
 			conformance: conformance,
 			xmp_metadata: XmpMetadata::new(Some("default".into()), 1),
@@ -59,11 +94,20 @@ impl PdfMetadata {
 	/// Consumes the metadata, returning the (Option<xmp_metadata>, document_info, icc_profile_stream).
 	pub fn into_obj(self)
 	-> (Option<lopdf::Object>, lopdf::Object, Option<IccProfile>)
+	{
+		self.into_obj_with_instance_id(random_character_string_32())
+	}
+
+	/// Same as `into_obj()`, but with the given instance ID in the XMP metadata
+	/// instead of a random one
+	pub(crate) fn into_obj_with_instance_id(self, instance_id: String)
+	-> (Option<lopdf::Object>, lopdf::Object, Option<IccProfile>)
 	{
 		let xmp_obj = {
 			if self.conformance.must_have_xmp_metadata() {
 				Some(self.xmp_metadata.into_obj(
 					 	self.conformance.clone(),
+						instance_id,
 						self.trapping,
 						self.creation_date,
 						self.modification_date,
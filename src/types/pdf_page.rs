@@ -1,15 +1,39 @@
 //! PDF page management
 
 use lopdf;
-use std::rc::Weak;
+use lopdf::content::Operation;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
+use std::io::BufWriter;
 
 use indices::{PdfPageIndex, PdfLayerIndex};
+use types::plugins::graphics::xobject::{XObjectCache, StreamedXObjects};
+use types::plugins::graphics::two_dimensional::bounding_box::content_bounding_box;
+use types::plugins::graphics::two_dimensional::redaction::redact_operations;
+use types::plugins::graphics::two_dimensional::text_extraction::{extract_text, replace_text, text_runs};
+use types::plugins::graphics::raster::pixels;
 use {
-    PdfResources, PdfLayer, PdfDocument, ExtendedGraphicsState, ExtendedGraphicsStateRef, Pattern, XObject, XObjectRef,
-    PdfLayerReference, PatternRef, Mm, Pt
+    PdfResources, PdfLayer, PdfDocument, ExtendedGraphicsState, ExtendedGraphicsStateBuilder, ExtendedGraphicsStateRef, Pattern, XObject, XObjectRef, FormXObject,
+    PdfLayerReference, PatternRef, OCGRef, FontList, IndirectFontRef, ValidationError, Rect, NoteIcon, PdfAnnotationReference, LinkTarget, Color, Mm, Pt,
+    PdfDocumentReference, RasterBackend, RasterPage, RasterImage, Error, ButtonAction, TextRun, Point
 };
 
+/// Name of the layer with the black boxes of redacted areas, see `PdfPageReference::redact()`
+const REDACTION_LAYER: &str = "Redactions";
+/// Name of the non-printing layer with the guides, see `PdfPageReference::add_guide()`
+const GUIDES_LAYER: &str = "Guides";
+/// Width of the guide lines in pt
+const GUIDE_WIDTH: f64 = 0.25;
+
+/// Direction of a guide line, see `PdfPageReference::add_guide()`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GuideOrientation {
+    /// Line across the page, the position is measured from the bottom edge
+    Horizontal,
+    /// Line from the top to the bottom of the page, the position is measured from the left edge
+    Vertical,
+}
+
 /// PDF page
 #[derive(Debug, Clone)]
 pub struct PdfPage {
@@ -23,6 +47,106 @@ pub struct PdfPage {
     pub layers: Vec<PdfLayer>,
     /// Resources used in this page
     pub(crate) resources: PdfResources,
+    /// Scale of the content of the layers, see `PdfPageReference::set_drawing_scale()`
+    pub(crate) drawing_scale: f64,
+    /// Real world scale of an area, see `PdfPageReference::set_measurement()`
+    pub(crate) measurement: Option<Measurement>,
+    /// Visible area of the page, see `PdfPageReference::set_crop_box()`
+    pub(crate) crop_box: Option<Rect>,
+    /// Whether the content is clipped to the crop box, see `PdfPageReference::set_clip_to_crop_box()`
+    pub(crate) clip_to_crop_box: bool,
+    /// Named areas of the page, see `PdfPageReference::define_region()`
+    pub(crate) regions: Vec<(String, Rect)>,
+    /// Guide lines (in page coordinates), see `PdfPageReference::add_guide()`
+    pub(crate) guides: Vec<(GuideOrientation, Pt)>,
+    /// Margin of the safe area, see `PdfPageReference::set_safe_area()`
+    pub(crate) safe_area: Option<Pt>,
+}
+
+/// Scale of an area of the page (a viewport), which viewers use for their measuring tools
+#[derive(Debug, Clone)]
+pub(crate) struct Measurement {
+    /// Area of the page in pt
+    bounds: Rect,
+    /// Real world units per mm on the page
+    ratio: f64,
+    /// Name of the real world unit
+    units: String,
+}
+
+impl Measurement {
+
+    /// Factor that converts pt on the page into the real world unit
+    fn conversion_factor(&self)
+    -> f64
+    {
+        self.ratio * 25.4 / 72.0
+    }
+
+    /// Returns the viewport dictionary (`/VP`) with its rectilinear measure dictionary
+    pub(crate) fn into_viewport(self)
+    -> lopdf::Dictionary
+    {
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
+
+        let number_format = |unit: &str, factor: f64| Dictionary(lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("NumberFormat".into())),
+            ("U", lopdf::Object::string_literal(unit)),
+            ("C", Real(factor)),
+            // precision of 1/100
+            ("D", Integer(100)),
+        ]));
+
+        let factor = self.conversion_factor();
+        let measure = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("Measure".into())),
+            ("Subtype", Name("RL".into())),
+            ("R", lopdf::Object::string_literal(format!("1 mm = {} {}", self.ratio, self.units))),
+            // distances along both axes, distances in general and areas
+            ("X", Array(vec![number_format(&self.units, factor)])),
+            ("D", Array(vec![number_format(&self.units, 1.0)])),
+            ("A", Array(vec![number_format(&format!("sq {}", self.units), 1.0)])),
+        ]);
+
+        lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("Viewport".into())),
+            ("BBox", self.bounds.into()),
+            ("Measure", Dictionary(measure)),
+        ])
+    }
+}
+
+/// Saved state of a single page, see `PdfDocumentReference::checkpoint()`
+#[derive(Debug, Clone)]
+pub(crate) struct PageCheckpoint {
+    /// Number of operations for each layer on the page
+    layer_operations: Vec<usize>,
+    /// Number of XObjects in the page resources
+    xobjects: usize,
+    /// Number of patterns in the page resources
+    patterns: usize,
+    /// Number of graphics states in the page resources
+    graphics_states: usize,
+    /// Named regions, see `define_region()`. Redefining a region changes it, so they are copied.
+    regions: Vec<(String, Rect)>,
+    /// Number of guides
+    guides: usize,
+}
+
+/// Layers of a page, together with the names of their optional content groups.
+/// Encoding the layers doesn't need access to the document, so
+/// the pages can be encoded independently of each other
+#[derive(Debug)]
+pub(crate) struct PageContents {
+    /// Layers of the page
+    layers: Vec<PdfLayer>,
+    /// OCG for each layer, in the same order as the layers
+    ocg_refs: Vec<OCGRef>,
+    /// Scale of the content of the layers
+    drawing_scale: f64,
+    /// Area that the content is clipped to, in page coordinates
+    clip: Option<Rect>,
 }
 
 /// A "reference" to the current page, allows for inner mutability
@@ -51,6 +175,13 @@ impl PdfPage {
             height: height.into(),
             layers: Vec::new(),
             resources: PdfResources::new(),
+            drawing_scale: 1.0,
+            measurement: None,
+            crop_box: None,
+            clip_to_crop_box: false,
+            regions: Vec::new(),
+            guides: Vec::new(),
+            safe_area: None,
         };
 
         let initial_layer = PdfLayer::new(layer_name);
@@ -61,9 +192,53 @@ impl PdfPage {
         (page, PdfLayerIndex(layer_index))
     }
 
+    /// Adds a non-printing layer with the guides and the outline of the safe area,
+    /// if the page has any, see `PdfDocumentReference::set_show_guides()`
+    pub(crate) fn add_guides_layer(&mut self)
+    {
+        use lopdf::Object::*;
+
+        if self.guides.is_empty() && self.safe_area.is_none() {
+            return;
+        }
+
+        // the layers are drawn with the drawing scale, the guides are in page coordinates
+        let scale = self.drawing_scale;
+        let (width, height) = (self.width.0 / scale, self.height.0 / scale);
+        let mut operations = vec![
+            Operation::new("q", vec![]),
+            Operation::new("w", vec![Real(GUIDE_WIDTH / scale)]),
+            // cyan guides, magenta safe area
+            Operation::new("RG", vec![Real(0.0), Real(1.0), Real(1.0)]),
+        ];
+        for &(orientation, position) in &self.guides {
+            let position = position.0 / scale;
+            let (start, end) = match orientation {
+                GuideOrientation::Horizontal => ((0.0, position), (width, position)),
+                GuideOrientation::Vertical => ((position, 0.0), (position, height)),
+            };
+            operations.push(Operation::new("m", vec![Real(start.0), Real(start.1)]));
+            operations.push(Operation::new("l", vec![Real(end.0), Real(end.1)]));
+            operations.push(Operation::new("S", vec![]));
+        }
+        if let Some(margin) = self.safe_area {
+            let margin = margin.0 / scale;
+            operations.push(Operation::new("RG", vec![Real(1.0), Real(0.0), Real(1.0)]));
+            operations.push(Operation::new("re", vec![Real(margin), Real(margin), Real(width - margin * 2.0), Real(height - margin * 2.0)]));
+            operations.push(Operation::new("S", vec![]));
+        }
+        operations.push(Operation::new("Q", vec![]));
+
+        let mut layer = PdfLayer::new(GUIDES_LAYER);
+        layer.operations = operations;
+        layer.printable = false;
+        self.layers.push(layer);
+    }
+
     /// Iterates through the layers attached to this page and gathers all resources,
     /// which the layers need. Then returns a dictonary with all the resources
-    /// (fonts, image XObjects, etc.)
+    /// (fonts, image XObjects, etc.) and the layers, which still have to be encoded
+    /// into the content stream of the page (see `PageContents::encode`)
     ///
     /// While originally I had planned to build a system where you can reference contents
     /// from all over the document, this turned out to be a problem, because each type had
@@ -72,44 +247,188 @@ impl PdfPage {
     /// `layers` should be a Vec with all layers (optional content groups) that were added
     /// to the document on a document level, it should contain the indices of the layers
     /// (they will be ignored, todo) and references to the actual OCG dictionaries
-    #[inline]
-    pub(crate) fn collect_resources_and_streams(self, doc: &mut lopdf::Document, layers: &[(usize, lopdf::Object)])
-    -> (lopdf::Dictionary, Vec<lopdf::Stream>)
+    ///
+    /// `xobject_cache` is shared between all pages, so that deduplicated XObjects
+    /// (e.g. the same logo on every page) are only written once. Streamed images
+    /// are added to `streamed`, their data is copied into the output by the writer
+    ///
+    /// Layers with an opacity below 1 are written as transparency groups: the operations
+    /// of the layer move into a form XObject (with the fonts of `font_dict_id` and the
+    /// rest of the page resources), the layer only draws the form with its opacity.
+    pub(crate) fn collect_resources(mut self, doc: &mut lopdf::Document, layers: &[(usize, lopdf::Object)],
+                                    font_dict_id: Option<lopdf::ObjectId>, precision: Option<u8>,
+                                    xobject_cache: &mut XObjectCache, streamed: &mut StreamedXObjects)
+    -> (lopdf::Dictionary, PageContents)
     {
+        use lopdf::Object::*;
+
+        let mut group_states = Vec::new();
+        for layer in &self.layers {
+            group_states.push(if layer.opacity < 1.0 {
+                let state = ExtendedGraphicsStateBuilder::new()
+                    .with_current_fill_alpha(layer.opacity)
+                    .with_current_stroke_alpha(layer.opacity)
+                    .build();
+                Some(self.resources.add_graphics_state(state))
+            } else {
+                None
+            });
+        }
+
         let cur_layers = layers.iter().map(|l| l.1.clone()).collect();
-        let (resource_dictionary, ocg_refs) = self.resources.into_with_document_and_layers(doc, cur_layers);
+        let (mut resource_dictionary, ocg_refs) = self.resources.into_with_document_layers_and_cache(doc, cur_layers, xobject_cache, streamed);
+        if let Some(f) = font_dict_id {
+            resource_dictionary.set("Font", Reference(f));
+        }
 
-        // set contents
-        let mut layer_streams = Vec::<lopdf::Stream>::new();
-        use lopdf::content::Operation;
-        use lopdf::Object::*;
+        // the forms draw in the coordinates of the layer, so the box is scaled like the content
+        let group_bbox = Rect {
+            llx: Pt(0.0), lly: Pt(0.0),
+            urx: Pt(self.width.0 / self.drawing_scale), ury: Pt(self.height.0 / self.drawing_scale),
+        };
+        let form_resources = resource_dictionary.clone();
+        for (index, (layer, state)) in self.layers.iter_mut().zip(group_states).enumerate() {
+            let state = match state {
+                Some(state) => state,
+                None => continue,
+            };
+            let operations = ::std::mem::take(&mut layer.operations);
+            let form_id = transparency_group(doc, operations, form_resources.clone(), group_bbox, precision);
+            let form_name = format!("LayerGroup{}", index);
 
-        for (idx, mut layer) in self.layers.into_iter().enumerate() {
+            if resource_dictionary.get(b"XObject").is_err() {
+                resource_dictionary.set("XObject", Dictionary(lopdf::Dictionary::new()));
+            }
+            if let Ok(Dictionary(xobjects)) = resource_dictionary.get_mut(b"XObject") {
+                xobjects.set(form_name.clone(), Reference(form_id));
+            }
 
-            // push OCG and q to the beginning of the layer
-            layer.operations.insert(0, Operation::new("q".into(), vec![]));
-            layer.operations.insert(0, Operation::new("BDC".into(), vec![
-                Name("OC".into()),
-                Name(ocg_refs[idx].name.clone().into())
-            ]));
+            layer.operations = vec![
+                Operation::new("gs", vec![Name(state.gs_name.into())]),
+                Operation::new("Do", vec![Name(form_name.into())]),
+            ];
+        }
 
-            // push OCG END and Q to the end of the layer stream
-            layer.operations.push(Operation::new("Q".into(), vec![]));
-            layer.operations.push(Operation::new("EMC".into(), vec![]));
+        let clip = if self.clip_to_crop_box { self.crop_box } else { None };
+        (resource_dictionary, PageContents { layers: self.layers, ocg_refs, drawing_scale: self.drawing_scale, clip })
+    }
 
-            // should end up looking like this:
+    /// Checks that the fonts, XObjects and graphics states used by the layers exist and
+    /// that the graphics state and text sections are balanced. `page` is the index of the
+    /// page, used for the reported errors. Found problems are appended to `errors`.
+    pub(crate) fn validate(&self, page: usize, fonts: &FontList, forbid_empty: bool, check_numbers: bool, errors: &mut Vec<ValidationError>)
+    {
+        use lopdf::Object::Name;
 
-            // /OC /MC0 BDC
-            // q
-            // <layer stream content>
-            // Q
-            // EMC
+        if check_numbers && !(is_valid_page_length(self.width) && is_valid_page_length(self.height)) {
+            errors.push(ValidationError::InvalidPageSize { page });
+        }
 
-            let layer_stream = layer.into();
-            layer_streams.push(layer_stream);
+        if forbid_empty && self.layers.iter().all(|layer| layer.operations.is_empty()) {
+            errors.push(ValidationError::EmptyPage { page });
         }
 
-        (resource_dictionary, layer_streams)
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+
+            let mut graphics_state_depth = 0_i64;
+            let mut text_section_depth = 0_i64;
+            let mut graphics_state_balanced = true;
+            let mut text_section_balanced = true;
+            let mut non_finite = None;
+
+            for operation in &layer.operations {
+                if check_numbers && non_finite.is_none() && !operation.operands.iter().all(is_finite) {
+                    non_finite = Some(operation.operator.clone());
+                }
+
+                let name = match operation.operands.first() {
+                    Some(Name(name)) => Some(String::from_utf8_lossy(name).into_owned()),
+                    _ => None,
+                };
+
+                match (operation.operator.as_str(), name) {
+                    ("Tf", Some(font)) if fonts.get_font_data(&IndirectFontRef::new(font.clone())).is_none() => {
+                        errors.push(ValidationError::MissingFont { page, layer: layer_idx, font });
+                    },
+                    ("Do", Some(xobject)) if !self.resources.xobjects.contains(&xobject) => {
+                        errors.push(ValidationError::MissingXObject { page, layer: layer_idx, xobject });
+                    },
+                    ("gs", Some(graphics_state)) if !self.resources.graphics_states.all_graphics_states.contains_key(&graphics_state) => {
+                        errors.push(ValidationError::MissingGraphicsState { page, layer: layer_idx, graphics_state });
+                    },
+                    ("q", _) => graphics_state_depth += 1,
+                    ("Q", _) => graphics_state_depth -= 1,
+                    ("BT", _) => text_section_depth += 1,
+                    ("ET", _) => text_section_depth -= 1,
+                    _ => { },
+                }
+
+                // a restore without a previous save (or a nested text section) is an error,
+                // even if the depth is zero again at the end of the layer
+                if graphics_state_depth < 0 { graphics_state_balanced = false; }
+                if !(0..=1).contains(&text_section_depth) { text_section_balanced = false; }
+            }
+
+            if !graphics_state_balanced || graphics_state_depth != 0 {
+                errors.push(ValidationError::UnbalancedGraphicsState { page, layer: layer_idx });
+            }
+
+            if !text_section_balanced || text_section_depth != 0 {
+                errors.push(ValidationError::UnbalancedTextSection { page, layer: layer_idx });
+            }
+
+            if let Some(operator) = non_finite {
+                errors.push(ValidationError::NonFiniteNumber { page, layer: layer_idx, operator });
+            }
+        }
+    }
+
+    /// Replaces NaN with 0 and clamps infinite numbers in the operations of the layers,
+    /// a width or height that isn't positive is replaced with the smallest page size.
+    /// See `CoordinateValidation::Lenient`.
+    pub(crate) fn clamp_numbers(&mut self)
+    {
+        if !is_valid_page_length(self.width) {
+            self.width = MIN_PAGE_LENGTH;
+        }
+        if !is_valid_page_length(self.height) {
+            self.height = MIN_PAGE_LENGTH;
+        }
+
+        for layer in &mut self.layers {
+            for operation in &mut layer.operations {
+                operation.operands.iter_mut().for_each(clamp_number);
+            }
+        }
+    }
+
+    /// Records the current length of the layers and resources on this page
+    pub(crate) fn checkpoint(&self)
+    -> PageCheckpoint
+    {
+        PageCheckpoint {
+            layer_operations: self.layers.iter().map(|layer| layer.operations.len()).collect(),
+            xobjects: self.resources.xobjects.len(),
+            patterns: self.resources.patterns.len(),
+            graphics_states: self.resources.graphics_states.len(),
+            regions: self.regions.clone(),
+            guides: self.guides.len(),
+        }
+    }
+
+    /// Rolls the page back to the given checkpoint, removing all layers, operations,
+    /// resources, regions and guides that were added after the checkpoint was taken
+    pub(crate) fn restore(&mut self, checkpoint: &PageCheckpoint)
+    {
+        self.layers.truncate(checkpoint.layer_operations.len());
+        for (layer, len) in self.layers.iter_mut().zip(checkpoint.layer_operations.iter()) {
+            layer.operations.truncate(*len);
+        }
+        self.resources.xobjects.truncate(checkpoint.xobjects);
+        self.resources.patterns.truncate(checkpoint.patterns);
+        self.resources.graphics_states.truncate(checkpoint.graphics_states);
+        self.regions = checkpoint.regions.clone();
+        self.guides.truncate(checkpoint.guides);
     }
 
     /// Change the graphics state. Before this operation is done, you should save
@@ -125,7 +444,7 @@ impl PdfPage {
         self.resources.add_graphics_state(added_state)
     }
 
-    /// __STUB__: Adds a pattern to the pages resources
+    /// Adds a pattern to the pages resources
     #[inline]
     pub fn add_pattern(&mut self, pattern: Pattern)
     -> PatternRef
@@ -143,6 +462,126 @@ impl PdfPage {
     }
 }
 
+impl PageContents {
+
+    /// Merges all layers into the uncompressed content stream of the page, the numbers
+    /// are rounded to `precision` decimal places. If `pretty` is set, the operators are
+    /// indented, see `indentation()`.
+    pub(crate) fn encode(self, precision: Option<u8>, pretty: bool)
+    -> Vec<u8>
+    {
+        let operations = self.operations(precision);
+        if pretty {
+            split_content(operations, usize::MAX, true).concat()
+        } else {
+            // writing into a Vec<u8> can't fail
+            lopdf::content::Content { operations }.encode().unwrap()
+        }
+    }
+
+    /// Same as `encode()`, but the content is split into several streams of at most
+    /// `max_size` bytes, see `split_content()`
+    pub(crate) fn encode_split(self, precision: Option<u8>, max_size: usize, pretty: bool)
+    -> Vec<Vec<u8>>
+    {
+        split_content(self.operations(precision), max_size, pretty)
+    }
+
+    /// Operations of all layers, in the order of the layers
+    fn operations(self, precision: Option<u8>)
+    -> Vec<Operation>
+    {
+        use lopdf::Object::*;
+
+        let mut operations = Vec::<Operation>::new();
+
+        // the clipping path is set outside of the layers, so that the drawing scale doesn't apply
+        if let Some(clip) = self.clip {
+            operations.push(Operation::new("q", vec![]));
+            operations.push(Operation::new("re", vec![
+                Real(clip.llx.0), Real(clip.lly.0), Real(clip.width().0), Real(clip.height().0)
+            ]));
+            operations.push(Operation::new("W", vec![]));
+            operations.push(Operation::new("n", vec![]));
+        }
+
+        // the sort is stable, layers with the same role stay in the order they were added
+        let mut layers = self.layers.into_iter().zip(self.ocg_refs).collect::<Vec<_>>();
+        layers.sort_by_key(|(layer, _)| layer.role);
+
+        for (mut layer, ocg_ref) in layers {
+
+            // should end up looking like this:
+
+            // /OC /MC0 BDC
+            // q
+            // <layer stream content>
+            // Q
+            // EMC
+
+            operations.push(Operation::new("BDC".into(), vec![
+                Name("OC".into()),
+                Name(ocg_ref.name.into())
+            ]));
+            operations.push(Operation::new("q".into(), vec![]));
+            if self.drawing_scale != 1.0 {
+                let scale = self.drawing_scale;
+                operations.push(Operation::new("cm", vec![Real(scale), Real(0.0), Real(0.0), Real(scale), Real(0.0), Real(0.0)]));
+            }
+            operations.append(&mut layer.operations);
+            operations.push(Operation::new("Q".into(), vec![]));
+            operations.push(Operation::new("EMC".into(), vec![]));
+        }
+
+        if self.clip.is_some() {
+            operations.push(Operation::new("Q", vec![]));
+        }
+
+        if let Some(precision) = precision {
+            let factor = 10_f64.powi(i32::from(precision));
+            for operation in &mut operations {
+                operation.operands.iter_mut().for_each(|operand| round_number(operand, factor));
+            }
+        }
+
+        operations
+    }
+}
+
+/// Encodes the operations into content streams of at most `max_size` bytes. The streams of a
+/// page are concatenated by the reader, so they can be split between any two operators, except
+/// inside of an inline image (`BI` ... `EI`). Operators that are larger than `max_size` (e.g.
+/// inline images) get a stream of their own. If `pretty` is set, the operators are indented.
+fn split_content(operations: Vec<Operation>, max_size: usize, pretty: bool)
+-> Vec<Vec<u8>>
+{
+    let mut streams = vec![Vec::new()];
+    let mut inline_image = false;
+    let indentation = if pretty { indentation(&operations) } else { vec![0; operations.len()] };
+
+    for (operation, depth) in operations.into_iter().zip(indentation) {
+        let splittable = !inline_image;
+        match operation.operator.as_str() {
+            "BI" => inline_image = true,
+            "EI" => inline_image = false,
+            _ => { },
+        }
+
+        let mut encoded = vec![b' '; depth * 2];
+        // writing into a Vec<u8> can't fail
+        encoded.extend(lopdf::content::Content { operations: vec![operation] }.encode().unwrap());
+        let current_len = streams.last().map(|stream| stream.len()).unwrap_or(0);
+        if splittable && current_len > 0 && current_len + encoded.len() > max_size {
+            streams.push(Vec::new());
+        }
+        if let Some(stream) = streams.last_mut() {
+            stream.extend_from_slice(&encoded);
+        }
+    }
+
+    streams
+}
+
 impl PdfPageReference {
 
     /// Adds a page and returns the index of the currently added page
@@ -163,9 +602,220 @@ impl PdfPageReference {
             document: self.document.clone(),
             page: self.page,
             layer: index,
+            form: None,
         }
     }
 
+    /// Adds a layer, which viewers show as a child of the `parent` layer in their layer
+    /// panel. The hierarchy only groups the layers in the panel, each layer is still
+    /// shown and hidden on its own.
+    pub fn add_layer_child<S>(&self, parent: PdfLayerIndex, layer_name: S)
+    -> PdfLayerReference where S: Into<String>
+    {
+        let layer = self.add_layer(layer_name);
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let layers = &mut doc.pages[self.page.0].layers;
+        let _ = &layers[parent.0];
+        layers[layer.layer.0].parent = Some(parent.0);
+        layer
+    }
+
+    /// Returns the text on the page, in reading order: lines from the top to the bottom,
+    /// characters of a line from the left to the right. Text in builtin fonts is decoded
+    /// with the WinAnsi encoding, text in external fonts by the glyphs of the font.
+    pub fn extract_text(&self)
+    -> String
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        let layers = doc.pages[self.page.0].layers.iter().map(|l| &l.operations[..]).collect::<Vec<_>>();
+        extract_text(&layers, &doc.fonts)
+    }
+
+    /// Returns the text on the page with its position: a run for every piece of text that
+    /// was shown with a single operator (e.g. a line written with `write_text()`), in the
+    /// order it was written. For search indexes or reflowing the text, see also `extract_text()`.
+    pub fn text_runs(&self)
+    -> Vec<TextRun>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        let layers = doc.pages[self.page.0].layers.iter().map(|l| &l.operations[..]).collect::<Vec<_>>();
+        text_runs(&layers, &doc.fonts)
+    }
+
+    /// Replaces every occurrence of `find` in the text on the page with `replace` and returns
+    /// the number of replacements, e.g. to fill placeholders like `{{name}}` in a template.
+    /// The replacement is written in the font and size of the replaced text.
+    ///
+    /// __NOTE__: The text layout isn't updated. If the replacement has a different width,
+    /// the rest of the text that was written with the same `write_text()` call moves, other
+    /// text stays where it is. Text that was written with separate calls (e.g. the words of
+    /// a justified line) isn't found.
+    pub fn replace_text(&self, find: &str, replace: &str)
+    -> usize
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let doc = &mut *doc;
+
+        let mut count = 0;
+        for layer in &mut doc.pages[self.page.0].layers {
+            let (operations, replaced) = replace_text(::std::mem::take(&mut layer.operations), find, replace, false, &doc.fonts);
+            layer.operations = operations;
+            count += replaced;
+        }
+        count
+    }
+
+    /// Redacts the area `rect` of the page: the content below it is removed from all layers
+    /// of the page and a black box is drawn over it, on a layer "Redactions" on top of the page.
+    /// Characters that overlap the area are removed from the text, the rest of the text stays
+    /// in place. Images that overlap the area are removed completely, paths are only removed
+    /// if they are completely inside of it (the box covers the rest). Annotations that overlap
+    /// the area are removed, form fields with a widget in the area lose their value
+    /// (set with `PdfDocumentReference::set_field_value()`), the fields themselves stay.
+    ///
+    /// __NOTE__: Only content that was added before is redacted. The text of glyphs drawn with
+    /// `PdfLayerReference::add_glyphs_with_text()` stays in the `/ToUnicode` map of the font.
+    pub fn redact(&self, rect: Rect)
+    {
+        use lopdf::content::Operation;
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let doc = &mut *doc;
+        doc.annotations.redact(self.page.0, &rect);
+        doc.form.redact(self.page.0, &rect);
+        let page = &mut doc.pages[self.page.0];
+
+        // the content of the layers is drawn with the drawing scale
+        let area = rect.scaled(1.0 / page.drawing_scale);
+        let mut removed_xobjects = Vec::new();
+        for layer in &mut page.layers {
+            let (operations, mut removed) = redact_operations(::std::mem::take(&mut layer.operations), area, &doc.fonts);
+            layer.operations = operations;
+            removed_xobjects.append(&mut removed);
+        }
+
+        // removed images that aren't drawn anywhere else on the page are blanked,
+        // otherwise their data would still be in the file
+        for name in removed_xobjects {
+            let used = page.layers.iter().flat_map(|l| &l.operations)
+                .any(|o| o.operator == "Do" && o.operands.first().and_then(|n| n.as_name_str().ok()) == Some(&name));
+            if !used {
+                page.resources.xobjects.redact(&name);
+            }
+        }
+
+        if page.layers.last().map(|l| l.name != REDACTION_LAYER).unwrap_or(true) {
+            page.layers.push(PdfLayer::new(REDACTION_LAYER));
+        }
+        let layer = page.layers.last_mut().unwrap();
+        layer.operations.push(Operation::new("g", vec![0.into()]));
+        layer.operations.push(Operation::new("re", vec![area.llx.into(), area.lly.into(), area.width().into(), area.height().into()]));
+        layer.operations.push(Operation::new("f", vec![]));
+    }
+
+    /// Adds a sticky note with the text `contents` to the page. The viewer shows
+    /// the icon at the upper left corner of `rect`.
+    #[inline]
+    pub fn add_note<S>(&self, rect: Rect, contents: S, icon: NoteIcon)
+    -> PdfAnnotationReference where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let annotation = doc.borrow_mut().annotations.add_note(self.page.0, rect, contents.into(), icon);
+        PdfAnnotationReference { document: self.document.clone(), annotation }
+    }
+
+    /// Highlights the areas `quads` (usually one per line of the highlighted text) with
+    /// the given color. Returns `None` (and adds nothing) if `quads` is empty.
+    #[inline]
+    pub fn add_highlight(&self, quads: &[Rect], color: Color)
+    -> Option<PdfAnnotationReference>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let annotation = doc.borrow_mut().annotations.add_highlight(self.page.0, quads.to_vec(), color)?;
+        Some(PdfAnnotationReference { document: self.document.clone(), annotation })
+    }
+
+    /// Adds a rubber stamp annotation, for example an "APPROVED" stamp. Unlike content drawn
+    /// on a layer, the stamp can be moved or removed in a viewer. `appearance` is drawn
+    /// relative to the lower left corner of `rect`, in a box of the size of `rect` (in pt).
+    /// `name` is the name of the stamp, such as `"Approved"`, `"Draft"` or `"Confidential"`.
+    #[inline]
+    pub fn add_stamp_annotation<S>(&self, rect: Rect, appearance: FormXObject, name: S)
+    -> PdfAnnotationReference where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let annotation = doc.borrow_mut().annotations.add_stamp(self.page.0, rect, appearance, name.into());
+        PdfAnnotationReference { document: self.document.clone(), annotation }
+    }
+
+    /// Adds a link: clicking into `rect` jumps to the target, a position in this
+    /// document, a named destination or a named destination in another file.
+    /// The link has no appearance, draw the text or button on a layer. The link has no
+    /// visible border, see `PdfAnnotationReference::set_border()`.
+    #[inline]
+    pub fn add_link(&self, rect: Rect, target: LinkTarget)
+    -> PdfAnnotationReference
+    {
+        let doc = self.document.upgrade().unwrap();
+        let annotation = doc.borrow_mut().annotations.add_link(self.page.0, rect, target);
+        PdfAnnotationReference { document: self.document.clone(), annotation }
+    }
+
+    /// Adds a single line text form field to the page, the value can be set with
+    /// `PdfDocumentReference::set_field_value()`
+    #[inline]
+    pub fn add_text_field<S>(&self, name: S, rect: Rect) where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().form.add_text_field(name.into(), self.page.0, rect);
+    }
+
+    /// Adds a checkbox form field with the given (unique) name to the page. The appearance
+    /// of the checked and unchecked state is drawn into `rect`.
+    #[inline]
+    pub fn add_checkbox<S>(&self, name: S, rect: Rect, checked: bool) where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().form.add_checkbox(name.into(), self.page.0, rect, checked);
+    }
+
+    /// Adds a group of radio buttons to the page. `options` are the export values of the
+    /// buttons and their position, only one button of the group can be selected at a time.
+    /// Initially no button is selected, see `PdfDocumentReference::set_field_value()`.
+    #[inline]
+    pub fn add_radio_group<S>(&self, name: S, options: &[(&str, Rect)]) where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let options = options.iter().map(|&(value, rect)| (value.to_string(), rect)).collect();
+        doc.borrow_mut().form.add_radio_group(name.into(), self.page.0, options);
+    }
+
+    /// Adds a dropdown (`combo = true`) or list box form field to the page. The selected
+    /// option can be set with `PdfDocumentReference::set_field_value()`, by default
+    /// no option is selected.
+    #[inline]
+    pub fn add_choice_field<S>(&self, name: S, rect: Rect, options: &[String], combo: bool) where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().form.add_choice_field(name.into(), self.page.0, rect, options.to_vec(), combo);
+    }
+
+    /// Adds a push button with the caption to the page, which runs the `action` when it is
+    /// clicked: resetting the form, submitting it to a server or running JavaScript.
+    /// The caption is written with the default appearance of the form, see
+    /// `PdfDocumentReference::set_form_default_appearance()`.
+    #[inline]
+    pub fn add_push_button<S, C>(&self, name: S, rect: Rect, caption: C, action: ButtonAction) where S: Into<String>, C: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().form.add_push_button(name.into(), self.page.0, rect, caption.into(), action);
+    }
+
     /// Validates that a layer is present and returns a reference to it
     #[inline]
     #[cfg_attr(feature = "cargo-clippy", allow(no_effect))]
@@ -181,6 +831,519 @@ impl PdfPageReference {
             document: self.document.clone(),
             page: self.page,
             layer: layer,
+            form: None,
+        }
+    }
+
+    /// Returns the bounding box of the content of all layers on this page,
+    /// see `PdfLayerReference::bounding_box()`
+    pub fn bounding_box(&self)
+    -> Option<Rect>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        let page = &doc.pages[self.page.0];
+        page.layers.iter()
+            .filter_map(|layer| content_bounding_box(&layer.operations, &doc.fonts))
+            .fold(None, |bbox: Option<Rect>, layer_bbox| Some(bbox.map_or(layer_bbox, |bbox| bbox.union(&layer_bbox))))
+            .map(|bbox| bbox.scaled(page.drawing_scale))
+    }
+
+    /// Renders the page into an image with a `RasterBackend`, for thumbnails or visual
+    /// regression tests. The backend gets a copy of the whole document, serialized with
+    /// the current options, so this is about as expensive as saving the document.
+    /// The data of streamed images is read into memory for this, so that the document
+    /// can still be saved afterwards.
+    pub fn rasterize<B>(&self, backend: &B, dpi: f64)
+    -> Result<RasterImage, Error> where B: RasterBackend
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut copy = doc.borrow().clone();
+        // the copy shares the readers of the streamed images with the document
+        for page in &mut copy.pages {
+            page.resources.xobjects.buffer_streamed_images()?;
+        }
+        for form in &mut copy.forms {
+            form.resources.xobjects.buffer_streamed_images()?;
+        }
+        let (width, height) = (copy.pages[self.page.0].width, copy.pages[self.page.0].height);
+
+        let mut target = BufWriter::new(Vec::new());
+        PdfDocumentReference { document: Rc::new(RefCell::new(copy)) }.save(&mut target)?;
+        let pdf = target.into_inner().map_err(|e| e.into_error())?;
+
+        backend.rasterize(RasterPage {
+            pdf: &pdf,
+            page: self.page.0,
+            dpi,
+            width: pixels(width, dpi),
+            height: pixels(height, dpi),
+        })
+    }
+
+    /// Adds a guide line for aligning content while designing a layout. `position` is the
+    /// distance from the bottom edge (horizontal guides) or the left edge (vertical guides)
+    /// of the page. Guides are only drawn if the document is saved with
+    /// `PdfDocumentReference::set_show_guides(true)`, on a layer that is never printed.
+    #[inline]
+    pub fn add_guide(&self, orientation: GuideOrientation, position: Mm)
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().pages[self.page.0].guides.push((orientation, position.into()));
+    }
+
+    /// Outlines the area `margin` inside the edges of the page, in which important content
+    /// should stay. Like the guides, the outline is only drawn with `set_show_guides(true)`.
+    #[inline]
+    pub fn set_safe_area(&self, margin: Mm)
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().pages[self.page.0].safe_area = Some(margin.into());
+    }
+
+    /// Scales everything that is drawn on the layers of this page by `ratio`, e.g. `1.0 / 50.0`
+    /// for an engineering drawing at 1:50, so that the content can be drawn in real world units.
+    /// This also applies to the content drawn before. The page size stays the same, as well as
+    /// the positions of annotations, links and form fields, which are always in page coordinates.
+    /// Bounding boxes and redaction areas are in page coordinates as well.
+    ///
+    /// Default: __1.0__
+    #[inline]
+    pub fn set_drawing_scale(&self, ratio: f64)
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().pages[self.page.0].drawing_scale = ratio;
+    }
+
+    /// Sets the real world scale of the area `bounds` of the page (e.g. a map or a drawing),
+    /// so that the measuring tools of viewers show real world distances and areas in it.
+    /// `ratio` is the number of real world `units` per mm on the page, e.g. `0.05` with
+    /// `"m"` for a drawing at 1:50. Only one area per page is supported, calling this again
+    /// replaces the area.
+    #[inline]
+    pub fn set_measurement<S>(&self, bounds: Rect, ratio: f64, units: S) where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().pages[self.page.0].measurement = Some(Measurement { bounds, ratio, units: units.into() });
+    }
+
+    /// Sets the visible area of the page (`/CropBox`), in page coordinates. Viewers only show
+    /// and print this area, the content outside of it is still part of the file.
+    /// `None` shows the whole page.
+    ///
+    /// Default: __None__
+    #[inline]
+    pub fn set_crop_box(&self, crop_box: Option<Rect>)
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().pages[self.page.0].crop_box = crop_box;
+    }
+
+    /// If enabled, the content of the page is clipped to the crop box in the content stream
+    /// itself, so that nothing outside of it shows up when the page is placed into another
+    /// document, which usually ignores the crop box. Has no effect without a crop box.
+    ///
+    /// Default: __false__
+    #[inline]
+    pub fn set_clip_to_crop_box(&self, clip: bool)
+    {
+        let doc = self.document.upgrade().unwrap();
+        doc.borrow_mut().pages[self.page.0].clip_to_crop_box = clip;
+    }
+
+    /// Defines a named region of the page in page coordinates, e.g. "header", "body" and
+    /// "sidebar" of a report template. Text is flowed into a region by its name with
+    /// `PdfLayerReference::add_text_to_region()` or `TextFlow::in_region()`. Defining a
+    /// region with the same name again replaces it.
+    pub fn define_region<S>(&self, name: S, rect: Rect) where S: Into<String>
+    {
+        let name = name.into();
+        let doc = self.document.upgrade().unwrap();
+        let regions = &mut doc.borrow_mut().pages[self.page.0].regions;
+        match regions.iter_mut().find(|region| region.0 == name) {
+            Some(region) => region.1 = rect,
+            None => regions.push((name, rect)),
+        }
+    }
+
+    /// Returns the region with the name, see `define_region()`
+    pub fn region(&self, name: &str)
+    -> Option<Rect>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        doc.pages[self.page.0].regions.iter().find(|region| region.0 == name).map(|region| region.1)
+    }
+
+    /// Returns the point at `x_pct` percent of the width (from the left edge) and `y_pct`
+    /// percent of the height (from the top edge) of the page, in the coordinates of the layers
+    /// (see `set_drawing_scale()`). For example, `point_pct(50.0, 10.0)` is centered horizontally,
+    /// a tenth of the height below the top. Percentages outside of 0 to 100 are clamped, so the
+    /// point is always on the page.
+    pub fn point_pct(&self, x_pct: f64, y_pct: f64)
+    -> Point
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        let page = &doc.pages[self.page.0];
+        let (width, height) = (page.width.0 / page.drawing_scale, page.height.0 / page.drawing_scale);
+        Point {
+            x: Pt(width * x_pct.clamp(0.0, 100.0) / 100.0),
+            y: Pt(height * (1.0 - y_pct.clamp(0.0, 100.0) / 100.0)),
+        }
+    }
+
+    /// Resizes the page to the bounding box of its content plus `margin` on every side
+    /// and moves the content, so that the lower left corner of the box ends up at
+    /// (`margin`, `margin`). Pages without content are left unchanged.
+    ///
+    /// Annotations and form fields are moved with the content.
+    ///
+    /// __NOTE__: Call this after everything was drawn, the size is only computed once.
+    /// The crop box is removed.
+    pub fn shrink_to_content(&self, margin: Mm)
+    {
+        use lopdf::content::Operation;
+
+        let bbox = match self.bounding_box() {
+            Some(bbox) => bbox,
+            None => return,
+        };
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        let page = &mut doc.pages[self.page.0];
+
+        // the translation is inserted into the layers, in the coordinates of the drawing scale
+        let margin: Pt = margin.into();
+        let (dx, dy) = ((margin.0 - bbox.llx.0) / page.drawing_scale, (margin.0 - bbox.lly.0) / page.drawing_scale);
+        page.width = bbox.width() + margin + margin;
+        page.height = bbox.height() + margin + margin;
+        page.crop_box = None;
+
+        // annotations are positioned in page coordinates, without the drawing scale
+        let (page_dx, page_dy) = (margin - bbox.llx, margin - bbox.lly);
+        let page_index = self.page.0;
+
+        // every layer is wrapped in q / Q when the page is written, so the
+        // translation only applies to the layer it was inserted into
+        for layer in page.layers.iter_mut().filter(|layer| !layer.operations.is_empty()) {
+            layer.operations.insert(0, Operation::new("cm", vec![1.into(), 0.into(), 0.into(), 1.into(), dx.into(), dy.into()]));
         }
+
+        doc.annotations.translate(page_index, page_dx, page_dy);
+        doc.form.translate(page_index, page_dx, page_dy);
+    }
+}
+
+/// Adds a form XObject with the operations of a layer, which is composited as a whole
+/// (`/Group << /S /Transparency >>`), see `PdfLayerReference::set_opacity()`
+fn transparency_group(doc: &mut lopdf::Document, mut operations: Vec<Operation>, resources: lopdf::Dictionary,
+                      bbox: Rect, precision: Option<u8>)
+-> lopdf::ObjectId
+{
+    use lopdf::Object::*;
+    use std::iter::FromIterator;
+
+    if let Some(precision) = precision {
+        let factor = 10_f64.powi(i32::from(precision));
+        for operation in &mut operations {
+            operation.operands.iter_mut().for_each(|operand| round_number(operand, factor));
+        }
+    }
+
+    let group = lopdf::Dictionary::from_iter(vec![
+        ("S", Name("Transparency".into())),
+    ]);
+    let dict = lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("XObject".into())),
+        ("Subtype", Name("Form".into())),
+        ("BBox", bbox.into()),
+        ("Group", Dictionary(group)),
+        ("Resources", Dictionary(resources)),
+    ]);
+    // writing into a Vec<u8> can't fail
+    let content = lopdf::content::Content { operations }.encode().unwrap();
+    doc.add_object(lopdf::Stream::new(dict, content))
+}
+
+/// Rounds a number (or the numbers in an array, e.g. of `TJ`) to the multiples of `1 / factor`
+fn round_number(object: &mut lopdf::Object, factor: f64)
+{
+    match *object {
+        lopdf::Object::Real(ref mut r) => {
+            // avoids writing "-0"
+            *r = (*r * factor).round() / factor + 0.0;
+        },
+        lopdf::Object::Array(ref mut array) => array.iter_mut().for_each(|o| round_number(o, factor)),
+        _ => { },
+    }
+}
+
+/// Nesting depth of each operation, for indenting the content stream: saved graphics states
+/// (`q` ... `Q`), text sections (`BT` ... `ET`) and marked content (`BDC` ... `EMC`)
+fn indentation(operations: &[Operation])
+-> Vec<usize>
+{
+    let mut depth = 0_usize;
+    operations.iter().map(|operation| match operation.operator.as_str() {
+        "q" | "BT" | "BDC" | "BMC" => {
+            depth += 1;
+            depth - 1
+        },
+        "Q" | "ET" | "EMC" => {
+            depth = depth.saturating_sub(1);
+            depth
+        },
+        _ => depth,
+    }).collect()
+}
+
+/// Smallest width and height of a page that PDF readers have to support (3 x 3 units)
+const MIN_PAGE_LENGTH: Pt = Pt(3.0);
+
+/// Checks that the width or height of a page is positive and finite
+fn is_valid_page_length(length: Pt)
+-> bool
+{
+    length.0.is_finite() && length.0 > 0.0
+}
+
+/// Checks that the number (or all numbers in the array) is neither NaN nor infinite
+fn is_finite(object: &lopdf::Object)
+-> bool
+{
+    match *object {
+        lopdf::Object::Real(r) => r.is_finite(),
+        lopdf::Object::Array(ref array) => array.iter().all(is_finite),
+        _ => true,
     }
 }
+
+/// Replaces NaN with 0, infinite numbers are clamped to the range of single precision
+/// floats, the largest numbers that PDF readers have to support
+fn clamp_number(object: &mut lopdf::Object)
+{
+    match *object {
+        lopdf::Object::Real(ref mut r) => {
+            let max = f64::from(f32::MAX);
+            *r = if r.is_nan() { 0.0 } else { r.clamp(-max, max) };
+        },
+        lopdf::Object::Array(ref mut array) => array.iter_mut().for_each(clamp_number),
+        _ => { },
+    }
+}
+
+#[test]
+fn test_shrink_to_content()
+{
+    use {PdfDocument, Line, Point};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let layer = page.get_layer(layer);
+    layer.set_outline_thickness(2.0);
+    layer.add_shape(Line {
+        points: vec![(Point { x: Pt(100.0), y: Pt(200.0) }, false), (Point { x: Pt(150.0), y: Pt(260.0) }, false)],
+        is_closed: false,
+        has_fill: false,
+        has_stroke: true,
+        is_clipping_path: false,
+    });
+
+    page.shrink_to_content(Pt(10.0).into());
+
+    let document = doc.document.borrow();
+    // 50 x 60 pt line, 1 pt of stroke and 10 pt of margin on every side
+    assert_eq!(document.pages[0].width, Pt(72.0));
+    assert_eq!(document.pages[0].height, Pt(82.0));
+    let translation = &document.pages[0].layers[0].operations[0];
+    assert_eq!(translation.operator, "cm");
+    assert!((translation.operands[4].as_f64().unwrap() + 89.0).abs() < 0.001);
+    assert!((translation.operands[5].as_f64().unwrap() + 189.0).abs() < 0.001);
+}
+
+#[test]
+fn test_encode_number_precision()
+{
+    let mut layer = PdfLayer::new("Layer 1");
+    layer.push_operation("m", vec![lopdf::Object::Real(12.345_678), lopdf::Object::Real(-0.000_1)]);
+    let contents = PageContents { layers: vec![layer], ocg_refs: vec![OCGRef::new(0)], drawing_scale: 1.0, clip: None };
+    let encoded = String::from_utf8(contents.encode(Some(3), false)).unwrap();
+    assert!(encoded.contains("12.346 0.0 m"), "{}", encoded);
+}
+
+#[test]
+fn test_drawing_scale()
+{
+    use {PdfDocument, Line, Point};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    page.set_drawing_scale(0.02);
+    page.get_layer(layer).add_shape(Line {
+        points: vec![(Point { x: Pt(0.0), y: Pt(0.0) }, false), (Point { x: Pt(5000.0), y: Pt(2500.0) }, false)],
+        is_closed: true,
+        has_fill: true,
+        has_stroke: false,
+        is_clipping_path: false,
+    });
+
+    // the bounding box is in page coordinates
+    let bbox = page.bounding_box().unwrap();
+    assert!((bbox.urx.0 - 100.0).abs() < 0.001 && (bbox.ury.0 - 50.0).abs() < 0.001);
+
+    let mut document = doc.document.borrow_mut();
+    let page = document.pages.pop().unwrap();
+    let contents = PageContents { layers: page.layers, ocg_refs: vec![OCGRef::new(0)], drawing_scale: page.drawing_scale, clip: None };
+    let encoded = String::from_utf8(contents.encode(None, false)).unwrap();
+    assert!(encoded.starts_with("/OC /MC0 BDC\nq\n0.02 0.0 0.0 0.02 0.0 0.0 cm\n"), "{}", encoded);
+}
+
+#[test]
+fn test_clip_to_crop_box()
+{
+    use {PdfDocument, Line, Point};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(100.0), Mm(100.0), "Layer 1");
+    let page = doc.get_page(page);
+    page.set_crop_box(Some(Rect::new(Mm(10.0), Mm(10.0), Mm(50.0), Mm(50.0))));
+    page.set_clip_to_crop_box(true);
+    // partially outside of the crop box
+    page.get_layer(layer).add_shape(Line {
+        points: vec![(Point::new(Mm(0.0), Mm(0.0)), false), (Point::new(Mm(40.0), Mm(40.0)), false)],
+        is_closed: false,
+        has_fill: false,
+        has_stroke: true,
+        is_clipping_path: false,
+    });
+
+    let mut document = doc.document.borrow_mut();
+    let page = document.pages.pop().unwrap();
+    let clip = if page.clip_to_crop_box { page.crop_box } else { None };
+    assert!(clip.is_some());
+    let contents = PageContents { layers: page.layers, ocg_refs: vec![OCGRef::new(0)], drawing_scale: 1.0, clip };
+    let encoded = String::from_utf8(contents.encode(Some(2), false)).unwrap();
+    assert!(encoded.starts_with("q\n28.35 28.35 141.73 141.73 re\nW\nn\n/OC /MC0 BDC\n"), "{}", encoded);
+    assert!(encoded.ends_with("EMC\nQ\n"), "{}", encoded);
+}
+
+#[test]
+fn test_layer_roles()
+{
+    use {PdfDocument, LayerRole};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(100.0), Mm(100.0), "Content");
+    let page = doc.get_page(page);
+    page.get_layer(layer).push_operation("n", vec![]);
+    let foreground = page.add_layer("Foreground");
+    foreground.push_operation("f", vec![]);
+    foreground.set_role(LayerRole::Foreground);
+    let background = page.add_layer("Background");
+    background.push_operation("b", vec![]);
+    background.set_role(LayerRole::Background);
+    page.add_layer("More content").push_operation("S", vec![]);
+
+    let mut document = doc.document.borrow_mut();
+    let page = document.pages.pop().unwrap();
+    let ocg_refs = (0..4).map(OCGRef::new).collect();
+    let contents = PageContents { layers: page.layers, ocg_refs, drawing_scale: 1.0, clip: None };
+    let encoded = String::from_utf8(contents.encode(None, false)).unwrap();
+    assert_eq!(encoded, "/OC /MC2 BDC\nq\nb\nQ\nEMC\n/OC /MC0 BDC\nq\nn\nQ\nEMC\n\
+                         /OC /MC3 BDC\nq\nS\nQ\nEMC\n/OC /MC1 BDC\nq\nf\nQ\nEMC\n");
+}
+
+#[test]
+fn test_pretty_content()
+{
+    use PdfDocument;
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(100.0), Mm(100.0), "Layer 1");
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.save_graphics_state();
+    layer.begin_text_section();
+    layer.push_operation("Tj", vec![lopdf::Object::string_literal("a")]);
+    layer.end_text_section();
+    layer.restore_graphics_state();
+
+    let mut document = doc.document.borrow_mut();
+    let page = document.pages.pop().unwrap();
+    let contents = |layers| PageContents { layers, ocg_refs: vec![OCGRef::new(0)], drawing_scale: 1.0, clip: None };
+    let encoded = String::from_utf8(contents(page.layers.clone()).encode(None, true)).unwrap();
+    assert_eq!(encoded, "/OC /MC0 BDC\n  q\n    q\n      BT\n        (a) Tj\n      ET\n    Q\n  Q\nEMC\n");
+    assert_eq!(contents(page.layers).encode_split(None, 20, true).concat(), encoded.into_bytes());
+}
+
+#[test]
+fn test_measurement_conversion()
+{
+    // 72 pt are 25.4 mm, at 1:50 they are 1.27 m
+    let measurement = Measurement { bounds: Rect::new(Mm(0.0), Mm(0.0), Mm(100.0), Mm(100.0)), ratio: 0.05, units: "m".into() };
+    assert!((measurement.conversion_factor() * 72.0 - 1.27).abs() < 0.000_001);
+}
+
+#[test]
+fn test_split_content()
+{
+    use lopdf::Object::*;
+
+    let rect = || Operation::new("re", vec![Integer(10), Integer(10), Integer(20), Integer(20)]);
+    let mut operations = vec![Operation::new("q", vec![]), rect(), Operation::new("f", vec![]), rect()];
+    operations.push(Operation::new("BI", vec![]));
+    operations.push(Operation::new("ID", vec![Name("W".into()), Integer(1)]));
+    operations.push(Operation::new("EI", vec![]));
+    operations.push(Operation::new("Q", vec![]));
+
+    let streams = split_content(operations.clone(), 20, false);
+    let streams = streams.iter().map(|s| ::std::str::from_utf8(s).unwrap()).collect::<Vec<&str>>();
+    assert_eq!(streams, vec![
+        "q\n10 10 20 20 re\nf\n",
+        "10 10 20 20 re\nBI\n/W 1 ID\nEI\n",
+        "Q\n",
+    ]);
+
+    // the streams are the same content as a single stream
+    let joined = split_content(operations.clone(), 20, false).concat();
+    assert_eq!(joined, lopdf::content::Content { operations }.encode().unwrap());
+}
+
+#[test]
+fn test_point_pct()
+{
+    use PdfDocument;
+
+    let (doc, page, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let (width, height): (Pt, Pt) = (Mm(210.0).into(), Mm(297.0).into());
+
+    let center = page.point_pct(50.0, 10.0);
+    assert!((center.x.0 - width.0 / 2.0).abs() < 1e-3);
+    assert!((center.y.0 - height.0 * 0.9).abs() < 1e-3);
+    // values outside of the page are clamped to its edges
+    let corner = page.point_pct(-20.0, 150.0);
+    assert!(corner.x.0.abs() < 1e-9 && corner.y.0.abs() < 1e-9);
+
+    page.set_drawing_scale(0.5);
+    assert!((page.point_pct(100.0, 0.0).x.0 - width.0 * 2.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_add_guides_layer()
+{
+    let (mut page, _) = PdfPage::new(Mm(100.0), Mm(100.0), "Layer 1", 0);
+    page.add_guides_layer();
+    assert_eq!(page.layers.len(), 1);
+
+    page.guides.push((GuideOrientation::Vertical, Pt(50.0)));
+    page.safe_area = Some(Pt(10.0));
+    page.drawing_scale = 0.5;
+    page.add_guides_layer();
+
+    let guides = page.layers.last().unwrap();
+    assert_eq!(guides.name, GUIDES_LAYER);
+    assert!(!guides.printable);
+    let encoded = ::std::string::String::from_utf8(lopdf::content::Content { operations: guides.operations.clone() }.encode().unwrap()).unwrap();
+    let height = page.height.0 * 2.0;
+    assert!(encoded.contains(&format!("100.0 0.0 m\n100.0 {} l\nS\n", height)), "{}", encoded);
+    assert!(encoded.contains(&format!("20.0 20.0 {} {} re\n", height - 40.0, height - 40.0)), "{}", encoded);
+}
@@ -0,0 +1,293 @@
+//! Loading of existing files with broken cross-reference tables or encryption
+//!
+//! Many generators write wrong byte offsets into the cross-reference table (for example after
+//! the file was edited as text, or converted between line endings) or no table at all. If
+//! `lopdf` can't load such a file, the objects are found by scanning the file for `N G obj`
+//! headers instead, and the table is rebuilt from them. The trailer is taken from the last
+//! `trailer` of the file, if it has no `/Root`, the document catalog is the last object with
+//! `/Type /Catalog`.
+
+use lopdf;
+use lopdf::Object;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use types::object_ids::MAX_OBJECT_NUMBER;
+use types::plugins::security::encrypt::decrypt_objects;
+use types::plugins::security::password::{StandardSecurityHandler, password_bytes};
+use {Error, PdfError};
+
+/// Loads a file, rebuilding the cross-reference table if it is broken. Returns an error if the
+/// file can't be loaded even with the rebuilt table.
+///
+/// Note that this returns the `lopdf::Document`, the pages of a loaded document can't be edited
+/// with the `PdfDocument` API.
+pub fn load_lenient(bytes: &[u8])
+-> Result<lopdf::Document, Error>
+{
+    if let Ok(doc) = lopdf::Document::load_mem(bytes) {
+        return Ok(doc);
+    }
+
+    let repaired = rebuild_xref(bytes).ok_or(Error::Pdf(PdfError::CorruptDocument))?;
+    #[cfg(feature = "logging")] {
+        warn!("The cross-reference table of the document is broken, rebuilt it from {} objects", object_offsets(bytes).len());
+    }
+    lopdf::Document::load_mem(&repaired).map_err(|_| Error::Pdf(PdfError::CorruptDocument))
+}
+
+/// Loads an encrypted file (see `load_lenient()`) and decrypts its strings and streams with the
+/// user or the owner password. Documents that aren't encrypted are loaded as they are.
+///
+/// Only the standard security handler with RC4 and AES-128 (revisions 2 to 4) is supported, fails
+/// with `PdfError::UnsupportedEncryption` for other handlers and with `PdfError::WrongPassword`
+/// if the password is wrong. The `/Encrypt` entry is removed, so that the document can be saved
+/// unencrypted.
+///
+/// Files with object streams (`/Type /ObjStm`) fail with `PdfError::UnsupportedEncryption` as
+/// well: `lopdf` unpacks the object streams while loading, before they are decrypted, so the
+/// objects in them would be lost.
+pub fn load_encrypted(bytes: &[u8], password: &str)
+-> Result<lopdf::Document, Error>
+{
+    let mut doc = load_lenient(bytes)?;
+
+    let encrypt_id = match doc.trailer.get(b"Encrypt") {
+        Ok(&Object::Reference(id)) => Some(id),
+        Ok(_) => None,
+        Err(_) => return Ok(doc),
+    };
+    if has_object_streams(bytes) {
+        return Err(Error::Pdf(PdfError::UnsupportedEncryption));
+    }
+    let encrypt = match encrypt_id {
+        Some(id) => doc.get_dictionary(id).map_err(|_| Error::Pdf(PdfError::CorruptDocument))?.clone(),
+        None => doc.trailer.get(b"Encrypt").and_then(Object::as_dict).map_err(|_| Error::Pdf(PdfError::CorruptDocument))?.clone(),
+    };
+    let id = match doc.trailer.get(b"ID").and_then(Object::as_array).ok().and_then(|ids| ids.first()) {
+        Some(Object::String(id, _)) => id.clone(),
+        _ => Vec::new(),
+    };
+
+    let handler = StandardSecurityHandler::new(&encrypt, &id)?;
+    let file_key = handler.file_key(&password_bytes(password)).ok_or(Error::Pdf(PdfError::WrongPassword))?;
+
+    let mut skipped = HashSet::new();
+    skipped.extend(encrypt_id);
+    if !handler.encrypt_metadata {
+        let metadata = doc.catalog().and_then(|catalog| catalog.get(b"Metadata")).and_then(Object::as_reference);
+        skipped.extend(metadata.ok());
+    }
+
+    decrypt_objects(&mut doc, &file_key, handler.strings, handler.streams, &skipped);
+
+    doc.trailer.remove(b"Encrypt");
+    if let Some(id) = encrypt_id {
+        doc.objects.remove(&id);
+    }
+    Ok(doc)
+}
+
+/// Returns the objects of the file with a new cross-reference table and trailer, or `None` if no
+/// objects or no document catalog were found. Everything after the last object is replaced, as
+/// `lopdf` would otherwise find the old `startxref` in the last bytes of the file.
+fn rebuild_xref(bytes: &[u8])
+-> Option<Vec<u8>>
+{
+    let offsets = object_offsets(bytes);
+    let size = offsets.keys().next_back()? + 1;
+
+    let trailer = rfind(bytes, b"trailer", bytes.len()).map(|start| &bytes[start..]).unwrap_or(&[]);
+    let root = find_reference(trailer, b"/Root").or_else(|| {
+        offsets.iter().rev()
+            .find(|&(_, &(_, offset))| has_type(object_bytes(bytes, offset), b"/Catalog"))
+            .map(|(number, &(generation, _))| format!("{} {} R", number, generation).into_bytes())
+    })?;
+
+    let objects_end = rfind(bytes, b"endobj", bytes.len())? + 6;
+    let mut repaired = bytes[..objects_end].to_vec();
+    repaired.push(b'\n');
+    let xref_offset = repaired.len();
+
+    writeln!(repaired, "xref\n0 {}", size).ok()?;
+    for number in 0..size {
+        match offsets.get(&number) {
+            Some(&(generation, offset)) => writeln!(repaired, "{:010} {:05} n ", offset, generation).ok()?,
+            None => repaired.extend_from_slice(b"0000000000 65535 f \n"),
+        }
+    }
+
+    write!(repaired, "trailer\n<</Size {}/Root ", size).ok()?;
+    repaired.extend_from_slice(&root);
+    if let Some(info) = find_reference(trailer, b"/Info") {
+        repaired.extend_from_slice(b"/Info ");
+        repaired.extend_from_slice(&info);
+    }
+    writeln!(repaired, ">>\nstartxref\n{}\n%%EOF", xref_offset).ok()?;
+
+    Some(repaired)
+}
+
+/// Finds all `N G obj` headers in the file, returns the generation and the byte offset of each
+/// object number. If an object is defined more than once (incremental updates), the last
+/// definition is used. Numbers above `MAX_OBJECT_NUMBER` are skipped, the rebuilt table would
+/// otherwise need an entry for every number up to them.
+fn object_offsets(bytes: &[u8])
+-> BTreeMap<u32, (u16, usize)>
+{
+    let mut offsets = BTreeMap::new();
+    let mut position = 0;
+
+    while let Some(found) = find(bytes, b"obj", position) {
+        position = found + 3;
+        // "endobj" and names like "/objective" aren't headers
+        if bytes.get(position).map(|c| c.is_ascii_alphanumeric()).unwrap_or(false) {
+            continue;
+        }
+        if let Some((start, number, generation)) = parse_header(&bytes[..found]) {
+            if number > MAX_OBJECT_NUMBER {
+                continue;
+            }
+            offsets.insert(number, (generation, start));
+        }
+    }
+
+    offsets
+}
+
+/// Parses the `N G ` before an `obj` keyword, returns the offset of `N`
+fn parse_header(before: &[u8])
+-> Option<(usize, u32, u16)>
+{
+    let is_space = |c: &u8| b" \t\r\n\x0c\x00".contains(c);
+
+    let mut end = before.len();
+    let generation_end = before[..end].iter().rposition(|c| !is_space(c))? + 1;
+    if generation_end == end {
+        return None;
+    }
+    let generation_start = before[..generation_end].iter().rposition(|c| !c.is_ascii_digit()).map(|p| p + 1).unwrap_or(0);
+    end = generation_start;
+    let number_end = before[..end].iter().rposition(|c| !is_space(c))? + 1;
+    if number_end == end {
+        return None;
+    }
+    let number_start = before[..number_end].iter().rposition(|c| !c.is_ascii_digit()).map(|p| p + 1).unwrap_or(0);
+    // the number has to start a token
+    if number_start > 0 && !is_space(&before[number_start - 1]) && !b">])}".contains(&before[number_start - 1]) {
+        return None;
+    }
+
+    let number = ::std::str::from_utf8(&before[number_start..number_end]).ok()?.parse().ok()?;
+    let generation = ::std::str::from_utf8(&before[generation_start..generation_end]).ok()?.parse().ok()?;
+    if number == 0 {
+        return None;
+    }
+    Some((number_start, number, generation))
+}
+
+/// Returns the bytes of the object at `offset`, up to its `endobj`
+fn object_bytes(bytes: &[u8], offset: usize)
+-> &[u8]
+{
+    let end = find(bytes, b"endobj", offset).unwrap_or(bytes.len());
+    &bytes[offset..end]
+}
+
+/// Checks if the object has the entry `/Type type_name`, e.g. `/Type /Catalog`
+fn has_type(object: &[u8], type_name: &[u8])
+-> bool
+{
+    let mut position = 0;
+    while let Some(found) = find(object, b"/Type", position) {
+        position = found + 5;
+        let value = object[position..].iter().skip_while(|c| c.is_ascii_whitespace()).cloned().take(type_name.len() + 1).collect::<Vec<u8>>();
+        if value.starts_with(type_name) && value.get(type_name.len()).map(|c| !c.is_ascii_alphanumeric()).unwrap_or(true) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Checks if the file contains object streams. Their dictionaries aren't encrypted, so
+/// they can be found without decrypting the file.
+fn has_object_streams(bytes: &[u8])
+-> bool
+{
+    object_offsets(bytes).values().any(|&(_, offset)| has_type(object_bytes(bytes, offset), b"/ObjStm"))
+}
+
+/// Returns the `N G R` after `key`
+fn find_reference(bytes: &[u8], key: &[u8])
+-> Option<Vec<u8>>
+{
+    let start = find(bytes, key, 0)? + key.len();
+    let end = find(bytes, b"R", start)? + 1;
+    let reference = String::from_utf8(bytes[start..end].to_vec()).ok()?;
+    let parts = reference.split_whitespace().collect::<Vec<&str>>();
+    match parts.as_slice() {
+        [number, generation, "R"] if number.parse::<u32>().is_ok() && generation.parse::<u16>().is_ok() =>
+            Some(format!("{} {} R", number, generation).into_bytes()),
+        _ => None,
+    }
+}
+
+/// Position of the first occurrence of `pattern` at or after `start`
+fn find(bytes: &[u8], pattern: &[u8], start: usize)
+-> Option<usize>
+{
+    if start >= bytes.len() {
+        return None;
+    }
+    bytes[start..].windows(pattern.len()).position(|w| w == pattern).map(|p| p + start)
+}
+
+/// Position of the last occurrence of `pattern` before `end`
+fn rfind(bytes: &[u8], pattern: &[u8], end: usize)
+-> Option<usize>
+{
+    bytes[..end].windows(pattern.len()).rposition(|w| w == pattern)
+}
+
+#[test]
+fn test_rebuild_xref() {
+    // the offsets in the table are wrong, object 2 is defined twice
+    let file = b"%PDF-1.4\n\
+        1 0 obj\n<</Type /Catalog /Pages 2 0 R>>\nendobj\n\
+        2 0 obj <</Type/Pages/Kids[]/Count 0>> endobj\n\
+        2 0 obj<</Type/Pages/Kids[]/Count 0/Objective 1>>endobj\n\
+        xref\n0 3\n0000000000 65535 f \n0000000001 00000 n \n0000000002 00000 n \n\
+        trailer\n<</Size 3>>\nstartxref\n999\n%%EOF";
+
+    let offsets = object_offsets(file);
+    assert_eq!(offsets.len(), 2);
+    assert_eq!(offsets[&1], (0, 9));
+    assert_eq!(&file[offsets[&2].1..offsets[&2].1 + 7], b"2 0 obj");
+    assert!(offsets[&2].1 > 60);
+
+    let repaired = rebuild_xref(file).unwrap();
+    let objects_end = rfind(file, b"endobj", file.len()).unwrap() + 6;
+    assert_eq!(&repaired[..objects_end], &file[..objects_end]);
+    let xref = String::from_utf8(repaired[objects_end..].to_vec()).unwrap();
+    assert_eq!(xref, format!(
+        "\nxref\n0 3\n0000000000 65535 f \n0000000009 00000 n \n{:010} 00000 n \n\
+        trailer\n<</Size 3/Root 1 0 R>>\nstartxref\n{}\n%%EOF\n", offsets[&2].1, objects_end + 1));
+
+    assert!(rebuild_xref(b"%PDF-1.4\nnot a pdf").is_none());
+
+    // a huge object number doesn't make the table huge
+    let file = b"%PDF-1.4\n1 0 obj<</Type/Catalog>>endobj\n4000000000 0 obj 1 endobj\n";
+    assert_eq!(object_offsets(file).keys().collect::<Vec<_>>(), vec![&1]);
+    assert!(String::from_utf8(rebuild_xref(file).unwrap()).unwrap().contains("xref\n0 2\n"));
+    assert_eq!(find_reference(b"<</Root 12 0 R /Info 3 0 R>>", b"/Info"), Some(b"3 0 R".to_vec()));
+}
+
+#[test]
+fn test_has_object_streams() {
+    let file = b"%PDF-1.5\n\
+        1 0 obj\n<</Type /Catalog /Pages 2 0 R>>\nendobj\n\
+        3 0 obj\n<</Type/ObjStm/N 1/First 4/Length 10>>stream\n\x8f\x02\x11\x9a\x00\x01\x02\x03\x04\x05\nendstream\nendobj\n";
+    assert!(has_object_streams(file));
+    assert!(!has_object_streams(&file[..60]));
+    assert!(has_type(b"<</Type /Catalog>>", b"/Catalog"));
+    assert!(!has_type(b"<</Type /ObjStmX>>", b"/ObjStm"));
+}
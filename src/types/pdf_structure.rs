@@ -0,0 +1,421 @@
+//! Structure tree for tagged PDF (accessibility)
+//!
+//! Content on the pages is marked with marked-content sequences (`/P <</MCID 0>> BDC ... EMC`),
+//! which belong to structure elements (headings, paragraphs, figures). The structure elements
+//! form a tree, which screen readers use to read the document in a logical order.
+//!
+//! Marked-content sequences are never nested: if a tag is opened inside another tag, the
+//! sequence of the outer tag is closed and reopened (with a new MCID) once the inner tag is closed.
+
+use lopdf;
+use utils::pdf_text_string;
+use PdfPage;
+
+/// Standard structure types (see PDF 1.7, section 10.7.3 "Standard Structure Types")
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StructureType {
+    /// Whole document
+    Document,
+    /// Large division of a document (e.g. a chapter)
+    Part,
+    /// Section
+    Sect,
+    /// Generic block-level element
+    Div,
+    /// Block quote
+    BlockQuote,
+    /// Caption of a table or figure
+    Caption,
+    /// Table of contents
+    TOC,
+    /// Item in a table of contents
+    TOCI,
+    /// Index
+    Index,
+    /// Paragraph
+    P,
+    /// Generic heading
+    H,
+    /// Heading, level 1
+    H1,
+    /// Heading, level 2
+    H2,
+    /// Heading, level 3
+    H3,
+    /// Heading, level 4
+    H4,
+    /// Heading, level 5
+    H5,
+    /// Heading, level 6
+    H6,
+    /// List
+    L,
+    /// List item
+    LI,
+    /// Label of a list item (bullet, number)
+    Lbl,
+    /// Body of a list item
+    LBody,
+    /// Table
+    Table,
+    /// Table row
+    TR,
+    /// Table header cell
+    TH,
+    /// Table data cell
+    TD,
+    /// Header rows of a table
+    THead,
+    /// Body rows of a table
+    TBody,
+    /// Footer rows of a table
+    TFoot,
+    /// Generic inline element
+    Span,
+    /// Inline quote
+    Quote,
+    /// Footnote or endnote
+    Note,
+    /// Reference to another part of the document
+    Reference,
+    /// Computer code
+    Code,
+    /// Hyperlink
+    Link,
+    /// Figure, should have an alternate description
+    Figure,
+    /// Mathematical formula, should have an alternate description
+    Formula,
+    /// Form widget
+    Form,
+    /// Custom structure type, must be mapped to a standard structure
+    /// type with `PdfDocumentReference::add_role_mapping()`
+    Custom(String),
+}
+
+impl StructureType {
+
+    /// Returns the name of the structure type, as written in the PDF
+    pub fn name(&self)
+    -> &str
+    {
+        use self::StructureType::*;
+        match *self {
+            Document => "Document",
+            Part => "Part",
+            Sect => "Sect",
+            Div => "Div",
+            BlockQuote => "BlockQuote",
+            Caption => "Caption",
+            TOC => "TOC",
+            TOCI => "TOCI",
+            Index => "Index",
+            P => "P",
+            H => "H",
+            H1 => "H1",
+            H2 => "H2",
+            H3 => "H3",
+            H4 => "H4",
+            H5 => "H5",
+            H6 => "H6",
+            L => "L",
+            LI => "LI",
+            Lbl => "Lbl",
+            LBody => "LBody",
+            Table => "Table",
+            TR => "TR",
+            TH => "TH",
+            TD => "TD",
+            THead => "THead",
+            TBody => "TBody",
+            TFoot => "TFoot",
+            Span => "Span",
+            Quote => "Quote",
+            Note => "Note",
+            Reference => "Reference",
+            Code => "Code",
+            Link => "Link",
+            Figure => "Figure",
+            Formula => "Formula",
+            Form => "Form",
+            Custom(ref name) => name,
+        }
+    }
+}
+
+/// Child of a structure element
+#[derive(Debug, Clone)]
+enum StructureKid {
+    /// Another structure element (index into `StructureTree::elements`)
+    Element(usize),
+    /// Marked-content sequence on a page
+    MarkedContent { page: usize, mcid: i64 },
+}
+
+/// Structure element (`/StructElem`)
+#[derive(Debug, Clone)]
+struct StructureElement {
+    structure_type: StructureType,
+    alt_text: Option<String>,
+    parent: Option<usize>,
+    kids: Vec<StructureKid>,
+}
+
+/// The marked-content sequence that is currently open
+#[derive(Debug, Copy, Clone)]
+struct OpenSequence {
+    page: usize,
+    layer: usize,
+    /// Number of operations on the layer right after the `BDC` operator
+    operations: usize,
+}
+
+/// Structure tree of a document, built by `PdfLayerReference::begin_tag()` / `end_tag()`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StructureTree {
+    /// All structure elements, in the order they were opened
+    elements: Vec<StructureElement>,
+    /// Stack of currently open structure elements
+    open_elements: Vec<usize>,
+    /// Marked-content sequence of the innermost open element
+    open_sequence: Option<OpenSequence>,
+    /// Next free MCID, for each page
+    next_mcid: Vec<i64>,
+    /// Custom structure types, mapped to standard structure types
+    pub(crate) role_map: Vec<(String, StructureType)>,
+}
+
+impl StructureTree {
+
+    /// Returns if the document contains any structure elements
+    #[inline]
+    pub(crate) fn is_empty(&self)
+    -> bool
+    {
+        self.elements.is_empty()
+    }
+
+    /// Returns the types of all structure elements that were opened, but not closed
+    pub(crate) fn unclosed_elements(&self)
+    -> Vec<StructureType>
+    {
+        self.open_elements.iter().map(|&idx| self.elements[idx].structure_type.clone()).collect()
+    }
+
+    /// Returns all custom structure types, which aren't mapped to a standard structure type
+    pub(crate) fn unmapped_types(&self)
+    -> Vec<String>
+    {
+        let mut unmapped = Vec::<String>::new();
+        for element in &self.elements {
+            if let StructureType::Custom(ref name) = element.structure_type {
+                if !self.role_map.iter().any(|(custom, _)| custom == name) && !unmapped.contains(name) {
+                    unmapped.push(name.clone());
+                }
+            }
+        }
+        unmapped
+    }
+
+    /// Opens a new structure element, as a child of the currently open element
+    pub(crate) fn begin_element(&mut self, pages: &mut [PdfPage], page: usize, layer: usize,
+                                structure_type: StructureType, alt_text: Option<String>)
+    {
+        self.close_sequence(pages);
+
+        let idx = self.elements.len();
+        let parent = self.open_elements.last().cloned();
+
+        self.elements.push(StructureElement {
+            structure_type,
+            alt_text,
+            parent,
+            kids: Vec::new(),
+        });
+
+        if let Some(parent) = parent {
+            self.elements[parent].kids.push(StructureKid::Element(idx));
+        }
+
+        self.open_elements.push(idx);
+        self.open_sequence(pages, page, layer);
+    }
+
+    /// Closes the innermost open structure element. Content that is drawn afterwards
+    /// belongs to the parent element again. Returns `false` if no element was open.
+    pub(crate) fn end_element(&mut self, pages: &mut [PdfPage], page: usize, layer: usize)
+    -> bool
+    {
+        self.close_sequence(pages);
+
+        if self.open_elements.pop().is_none() {
+            return false;
+        }
+
+        if !self.open_elements.is_empty() {
+            self.open_sequence(pages, page, layer);
+        }
+
+        true
+    }
+
+    /// Starts an artifact (e.g. decorations, page numbers), which screen readers skip.
+    /// The marked-content sequence of the open element is interrupted until the artifact ends.
+    pub(crate) fn begin_artifact(&mut self, pages: &mut [PdfPage], page: usize, layer: usize)
+    {
+        use lopdf::content::Operation;
+        use lopdf::Object::Name;
+
+        self.close_sequence(pages);
+        pages[page].layers[layer].operations.push(Operation::new("BMC", vec![Name("Artifact".into())]));
+    }
+
+    /// Ends an artifact and continues the marked content of the open element
+    pub(crate) fn end_artifact(&mut self, pages: &mut [PdfPage], page: usize, layer: usize)
+    {
+        use lopdf::content::Operation;
+
+        pages[page].layers[layer].operations.push(Operation::new("EMC", vec![]));
+        self.open_sequence(pages, page, layer);
+    }
+
+    /// Starts a marked-content sequence for the innermost open element on the given layer
+    fn open_sequence(&mut self, pages: &mut [PdfPage], page: usize, layer: usize)
+    {
+        use lopdf::content::Operation;
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
+
+        let element = match self.open_elements.last() {
+            Some(&element) => element,
+            None => return,
+        };
+
+        if self.next_mcid.len() <= page {
+            self.next_mcid.resize(page + 1, 0);
+        }
+
+        let mcid = self.next_mcid[page];
+        self.next_mcid[page] += 1;
+
+        let properties = lopdf::Dictionary::from_iter(vec![("MCID", Integer(mcid))]);
+        let structure_type = Name(self.elements[element].structure_type.name().as_bytes().to_vec());
+
+        let operations = &mut pages[page].layers[layer].operations;
+        operations.push(Operation::new("BDC", vec![structure_type, Dictionary(properties)]));
+
+        self.elements[element].kids.push(StructureKid::MarkedContent { page, mcid });
+        self.open_sequence = Some(OpenSequence { page, layer, operations: operations.len() });
+    }
+
+    /// Ends the currently open marked-content sequence. If nothing was drawn
+    /// since the sequence was started, the sequence is removed instead.
+    pub(crate) fn close_sequence(&mut self, pages: &mut [PdfPage])
+    {
+        use lopdf::content::Operation;
+
+        let sequence = match self.open_sequence.take() {
+            Some(sequence) => sequence,
+            None => return,
+        };
+
+        let operations = &mut pages[sequence.page].layers[sequence.layer].operations;
+
+        if operations.len() == sequence.operations {
+            // empty sequence: the MCID was the last one that was handed out on this page
+            operations.pop();
+            self.next_mcid[sequence.page] -= 1;
+            if let Some(&element) = self.open_elements.last() {
+                self.elements[element].kids.pop();
+            }
+        } else {
+            operations.push(Operation::new("EMC", vec![]));
+        }
+    }
+
+    /// Returns the key into the parent tree for the page (`/StructParents`),
+    /// if the page contains any marked content
+    pub(crate) fn struct_parents(&self, page: usize)
+    -> Option<i64>
+    {
+        match self.next_mcid.get(page) {
+            Some(&mcid) if mcid > 0 => Some(page as i64),
+            _ => None,
+        }
+    }
+
+    /// Adds the structure elements, the parent tree and the `/StructTreeRoot` to the document.
+    /// `page_ids` are the object IDs of the pages. Returns the ID of the `/StructTreeRoot`.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, page_ids: &[lopdf::ObjectId])
+    -> lopdf::ObjectId
+    {
+        use lopdf::Object::*;
+        use lopdf::Dictionary as LoDictionary;
+        use std::iter::FromIterator;
+
+        let root_id = doc.new_object_id();
+        let element_ids: Vec<lopdf::ObjectId> = self.elements.iter().map(|_| doc.new_object_id()).collect();
+
+        // page -> element for each MCID on the page
+        let mut parent_tree: Vec<Vec<lopdf::Object>> = self.next_mcid.iter().map(|&count| vec![Null; count as usize]).collect();
+
+        let mut top_level = Vec::<lopdf::Object>::new();
+
+        for (idx, element) in self.elements.into_iter().enumerate() {
+
+            let kids: Vec<lopdf::Object> = element.kids.iter().map(|kid| match *kid {
+                StructureKid::Element(child) => Reference(element_ids[child]),
+                StructureKid::MarkedContent { page, mcid } => {
+                    parent_tree[page][mcid as usize] = Reference(element_ids[idx]);
+                    Dictionary(LoDictionary::from_iter(vec![
+                        ("Type", Name("MCR".into())),
+                        ("Pg", Reference(page_ids[page])),
+                        ("MCID", Integer(mcid)),
+                    ]))
+                },
+            }).collect();
+
+            let parent = match element.parent {
+                Some(parent) => element_ids[parent],
+                None => { top_level.push(Reference(element_ids[idx])); root_id },
+            };
+
+            let mut dict = LoDictionary::from_iter(vec![
+                ("Type", Name("StructElem".into())),
+                ("S", Name(element.structure_type.name().as_bytes().to_vec())),
+                ("P", Reference(parent)),
+                ("K", Array(kids)),
+            ]);
+
+            if let Some(alt_text) = element.alt_text {
+                dict.set("Alt", pdf_text_string(&alt_text));
+            }
+
+            doc.objects.insert(element_ids[idx], Dictionary(dict));
+        }
+
+        let mut nums = Vec::<lopdf::Object>::new();
+        for (page, elements) in parent_tree.into_iter().enumerate() {
+            if !elements.is_empty() {
+                nums.push(Integer(page as i64));
+                nums.push(Array(elements));
+            }
+        }
+
+        let mut root = LoDictionary::from_iter(vec![
+            ("Type", Name("StructTreeRoot".into())),
+            ("K", Array(top_level)),
+            ("ParentTree", Dictionary(LoDictionary::from_iter(vec![("Nums", Array(nums))]))),
+            ("ParentTreeNextKey", Integer(page_ids.len() as i64)),
+        ]);
+
+        if !self.role_map.is_empty() {
+            root.set("RoleMap", Dictionary(self.role_map.into_iter().map(|(custom, standard)|
+                (custom, Name(standard.name().as_bytes().to_vec()))
+            ).collect()));
+        }
+
+        doc.objects.insert(root_id, Dictionary(root));
+        root_id
+    }
+}
@@ -0,0 +1,937 @@
+//! Serialization of a `lopdf::Document` for the cases that `lopdf` can't handle itself:
+//! PDF 1.5 object streams (`/ObjStm`) and cross-reference streams (`/XRef`), linearized
+//! files ("fast web view"), as well as streamed images, whose data is copied from a
+//! reader directly into the output.
+//!
+//! Object streams pack many small indirect objects (dictionaries, arrays, numbers)
+//! into one compressed stream, which shrinks documents with thousands of small
+//! objects significantly. Streams themselves can't be put into an object stream,
+//! they are written as regular indirect objects.
+
+use lopdf;
+use lopdf::{Object, ObjectId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Write, Error as IoError, ErrorKind, Result as IoResult};
+use types::object_ids::ObjectIdAllocator;
+use types::plugins::graphics::xobject::StreamedXObjects;
+use {Error, StreamedImageXObject};
+
+/// Maximum number of objects packed into one object stream
+const OBJECTS_PER_OBJECT_STREAM: usize = 100;
+
+/// Written after the content of a stream object
+const STREAM_FOOTER: &[u8] = b"\nendstream\nendobj\n";
+
+/// Entry in the cross-reference stream
+#[derive(Debug, Copy, Clone)]
+enum XrefStreamEntry {
+    /// Type 0: free object
+    Free,
+    /// Type 1: object at byte offset (offset, generation)
+    Normal(u32, u16),
+    /// Type 2: object inside an object stream (object stream number, index in the stream)
+    Compressed(u32, u16),
+}
+
+/// Writer that keeps track of the number of bytes written, for the byte offsets
+/// in the cross-reference table, and of the number of objects of the document that
+/// were written completely, see `Error::PartialWrite`
+pub(crate) struct CountingWriter<'a, W: Write + 'a> {
+    inner: &'a mut W,
+    pub(crate) written: usize,
+    pub(crate) objects: usize,
+}
+
+impl<'a, W: Write + 'a> CountingWriter<'a, W> {
+
+    #[inline]
+    pub(crate) fn new(inner: &'a mut W)
+    -> Self
+    {
+        Self { inner, written: 0, objects: 0 }
+    }
+}
+
+impl<'a, W: Write + 'a> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8])
+    -> IoResult<usize>
+    {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self)
+    -> IoResult<()>
+    {
+        self.inner.flush()
+    }
+}
+
+/// Writes the `%PDF-x.y` header and the binary marker
+fn write_header<W: Write>(out: &mut W, version: &str)
+-> IoResult<()>
+{
+    writeln!(out, "%PDF-{}", version)?;
+    // binary marker, so that the file isn't treated as a text file
+    out.write_all(b"%\xE2\xE3\xCF\xD3\n")
+}
+
+/// Saves the document with a classic cross-reference table, writing the data
+/// of the `streamed` images directly from their readers into the output.
+/// Every object in `streamed` must be the (placeholder) stream dictionary of the image.
+pub(crate) fn save_with_streamed_objects<W: Write>(doc: &mut lopdf::Document, streamed: &StreamedXObjects, out: &mut CountingWriter<W>)
+-> IoResult<()>
+{
+    write_header(out, &doc.version)?;
+
+    let mut offsets = vec![None; doc.max_id as usize + 1];
+
+    for (&(id, generation), object) in &doc.objects {
+        offsets[id as usize] = Some((out.written, generation));
+        match streamed.get(&(id, generation)) {
+            Some(image) => write_streamed_object(out, id, generation, object, image)?,
+            None => write_indirect_object(out, id, generation, object)?,
+        }
+        out.objects += 1;
+    }
+
+    let xref_offset = out.written;
+    writeln!(out, "xref\n0 {}", offsets.len())?;
+    for offset in &offsets {
+        // each entry has to be exactly 20 bytes long, including the line break
+        match *offset {
+            Some((offset, generation)) => writeln!(out, "{:010} {:05} n ", offset, generation)?,
+            None => out.write_all(b"0000000000 65535 f \n")?,
+        }
+    }
+
+    let mut trailer = doc.trailer.clone();
+    trailer.set("Size", Object::Integer(offsets.len() as i64));
+
+    let mut trailer_bytes = Vec::<u8>::new();
+    write_dictionary(&mut trailer_bytes, &trailer)?;
+    out.write_all(b"trailer\n")?;
+    out.write_all(&trailer_bytes)?;
+    write!(out, "\nstartxref\n{}\n%%EOF", xref_offset)?;
+
+    out.flush()
+}
+
+/// Saves the document using object streams and a cross-reference stream instead
+/// of a classic cross-reference table. The version in the header is raised to
+/// 1.5 if the document has a lower version (object streams require PDF 1.5).
+/// The `streamed` images are written like in `save_with_streamed_objects`.
+pub(crate) fn save_with_object_streams<W: Write>(doc: &mut lopdf::Document, streamed: &StreamedXObjects, out: &mut CountingWriter<W>)
+-> IoResult<()>
+{
+    use lopdf::{Dictionary as LoDictionary, Stream as LoStream};
+    use lopdf::Object::*;
+    use std::iter::FromIterator;
+
+    let version = if doc.version.as_str() < "1.5" { "1.5".to_string() } else { doc.version.clone() };
+
+    write_header(out, &version)?;
+
+    let mut entries = vec![XrefStreamEntry::Free; doc.max_id as usize + 1];
+
+    // streams and objects with a non-zero generation number are not allowed in object streams
+    let mut packed_objects = Vec::<(u32, &Object)>::new();
+
+    for (&(id, generation), object) in &doc.objects {
+        if let Some(image) = streamed.get(&(id, generation)) {
+            entries[id as usize] = XrefStreamEntry::Normal(out.written as u32, generation);
+            write_streamed_object(out, id, generation, object, image)?;
+            out.objects += 1;
+            continue;
+        }
+
+        match *object {
+            Stream(_) => {
+                entries[id as usize] = XrefStreamEntry::Normal(out.written as u32, generation);
+                write_indirect_object(out, id, generation, object)?;
+                out.objects += 1;
+            },
+            _ if generation != 0 => {
+                entries[id as usize] = XrefStreamEntry::Normal(out.written as u32, generation);
+                write_indirect_object(out, id, generation, object)?;
+                out.objects += 1;
+            },
+            _ => packed_objects.push((id, object)),
+        }
+    }
+
+    let to_io_error = |e: Error| IoError::new(ErrorKind::InvalidData, e.to_string());
+    let mut ids = ObjectIdAllocator::new(doc.max_id);
+
+    for chunk in packed_objects.chunks(OBJECTS_PER_OBJECT_STREAM) {
+
+        let object_stream_id = ids.allocate().map_err(to_io_error)?.0;
+
+        // "id offset" pairs, followed by the objects themselves
+        let mut header = Vec::<u8>::new();
+        let mut body = Vec::<u8>::new();
+
+        for (index, &(id, object)) in chunk.iter().enumerate() {
+            write!(header, "{} {} ", id, body.len())?;
+            write_object(&mut body, object)?;
+            body.push(b'\n');
+            entries[id as usize] = XrefStreamEntry::Compressed(object_stream_id, index as u16);
+        }
+
+        let first = header.len();
+        header.append(&mut body);
+
+        let mut object_stream = LoStream::new(LoDictionary::from_iter(vec![
+            ("Type", Name("ObjStm".into())),
+            ("N", Integer(chunk.len() as i64)),
+            ("First", Integer(first as i64)),
+        ]), header);
+        // if compression fails, the stream is simply written uncompressed
+        let _ = object_stream.compress();
+
+        entries.push(XrefStreamEntry::Normal(out.written as u32, 0));
+        write_indirect_object(out, object_stream_id, 0, &Stream(object_stream))?;
+        out.objects += chunk.len();
+    }
+
+    // the cross-reference stream has to contain an entry for itself
+    let xref_stream_id = ids.allocate().map_err(to_io_error)?.0;
+    let xref_offset = out.written as u32;
+    entries.push(XrefStreamEntry::Normal(xref_offset, 0));
+
+    // field widths: 1 byte type, 4 bytes offset / object stream number, 2 bytes generation / index
+    let mut xref_data = Vec::<u8>::with_capacity(entries.len() * 7);
+    for entry in &entries {
+        let (entry_type, field2, field3) = match *entry {
+            XrefStreamEntry::Free => (0_u8, 0_u32, 0xffff_u16),
+            XrefStreamEntry::Normal(offset, generation) => (1, offset, generation),
+            XrefStreamEntry::Compressed(stream_id, index) => (2, stream_id, index),
+        };
+        xref_data.push(entry_type);
+        xref_data.extend_from_slice(&[(field2 >> 24) as u8, (field2 >> 16) as u8, (field2 >> 8) as u8, field2 as u8]);
+        xref_data.extend_from_slice(&[(field3 >> 8) as u8, field3 as u8]);
+    }
+
+    let mut xref_dict = LoDictionary::from_iter(vec![
+        ("Type", Name("XRef".into())),
+        ("Size", Integer(entries.len() as i64)),
+        ("W", Array(vec![Integer(1), Integer(4), Integer(2)])),
+    ]);
+
+    for key in &["Root", "Info", "ID"] {
+        if let Ok(value) = doc.trailer.get(key.as_bytes()) {
+            xref_dict.set(*key, value.clone());
+        }
+    }
+
+    let mut xref_stream = LoStream::new(xref_dict, xref_data);
+    let _ = xref_stream.compress();
+    write_indirect_object(out, xref_stream_id, 0, &Stream(xref_stream))?;
+
+    write!(out, "startxref\n{}\n%%EOF", xref_offset)?;
+
+    doc.max_id = xref_stream_id;
+    out.flush()
+}
+
+/// An indirect object of a linearized file, serialized up to the (possibly large)
+/// stream data, so that the length of each object is known before anything is written
+enum LinearizedObject<'a> {
+    /// Object without stream data
+    Plain(Vec<u8>),
+    /// Stream object: everything up to the `stream` keyword, followed by the stream content
+    Stream(Vec<u8>, &'a [u8]),
+    /// Streamed image: everything up to the `stream` keyword, the data is read from the image
+    Streamed(Vec<u8>, &'a StreamedImageXObject),
+}
+
+impl<'a> LinearizedObject<'a> {
+
+    fn len(&self)
+    -> usize
+    {
+        match *self {
+            LinearizedObject::Plain(ref bytes) => bytes.len(),
+            LinearizedObject::Stream(ref header, content) => header.len() + content.len() + STREAM_FOOTER.len(),
+            LinearizedObject::Streamed(ref header, image) => header.len() + image.length + STREAM_FOOTER.len(),
+        }
+    }
+
+    fn write<W: Write>(&self, out: &mut W)
+    -> IoResult<()>
+    {
+        match *self {
+            LinearizedObject::Plain(ref bytes) => out.write_all(bytes),
+            LinearizedObject::Stream(ref header, content) => {
+                out.write_all(header)?;
+                out.write_all(content)?;
+                out.write_all(STREAM_FOOTER)
+            },
+            LinearizedObject::Streamed(ref header, image) => {
+                out.write_all(header)?;
+                image.copy_data(out)?;
+                out.write_all(STREAM_FOOTER)
+            },
+        }
+    }
+}
+
+/// Writes values with a fixed number of bits, for the hint tables of a linearized file
+/// (and the codes of the LZW and CCITT filters)
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    pub(crate) bytes: Vec<u8>,
+    /// Number of bits used in the last byte, 0 if the last byte is full
+    used_bits: u8,
+}
+
+impl BitWriter {
+
+    /// Appends the lowest `bits` bits of `value`, most significant bit first
+    pub(crate) fn write(&mut self, value: u64, bits: u8)
+    {
+        for bit in (0..bits).rev() {
+            if self.used_bits == 0 {
+                self.bytes.push(0);
+            }
+            if (value >> bit) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> self.used_bits;
+            }
+            self.used_bits = (self.used_bits + 1) % 8;
+        }
+    }
+
+    /// Pads the last byte with zeros, each item of a hint table starts at a byte boundary
+    fn pad(&mut self)
+    {
+        self.used_bits = 0;
+    }
+}
+
+/// Number of bits needed to represent `value`
+fn bits_needed(value: u64)
+-> u8
+{
+    (64 - value.leading_zeros()) as u8
+}
+
+/// `/P` and `/Parent` keys are not followed
+/// when the objects of a page are collected, they point to the page itself
+/// or "upwards" (to the page tree or to the parent of a form field)
+const LINEARIZATION_SKIPPED_KEYS: [&[u8]; 2] = [b"Parent", b"P"];
+
+/// Appends the objects that are directly referenced by `object` to `references`
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>)
+{
+    match *object {
+        Object::Reference(id) => references.push(id),
+        Object::Array(ref array) => for item in array { collect_references(item, references); },
+        Object::Dictionary(ref dict) => collect_dictionary_references(dict, references),
+        Object::Stream(ref stream) => collect_dictionary_references(&stream.dict, references),
+        _ => { },
+    }
+}
+
+fn collect_dictionary_references(dict: &lopdf::Dictionary, references: &mut Vec<ObjectId>)
+{
+    for (key, value) in dict {
+        if !LINEARIZATION_SKIPPED_KEYS.contains(&key.as_slice()) {
+            collect_references(value, references);
+        }
+    }
+}
+
+/// Returns the objects needed to render a page (the page object first), without
+/// following references to the `excluded` objects (other pages, the page tree, the catalog)
+fn page_objects(doc: &lopdf::Document, page: ObjectId, excluded: &HashSet<ObjectId>)
+-> Vec<ObjectId>
+{
+    let mut objects = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![page];
+
+    while let Some(id) = stack.pop() {
+        if !visited.insert(id) || (id != page && excluded.contains(&id)) {
+            continue;
+        }
+        let object = match doc.objects.get(&id) {
+            Some(object) => object,
+            None => continue,
+        };
+        objects.push(id);
+        let mut references = Vec::new();
+        collect_references(object, &mut references);
+        // reversed, so that the objects are visited in the order they are referenced
+        stack.extend(references.into_iter().rev());
+    }
+
+    objects
+}
+
+/// Saves the document as a linearized file, so that viewers can show the first page
+/// before the whole file is downloaded (PDF Reference, Appendix F). The file looks like this:
+///
+/// 1. Header
+/// 2. Linearization parameter dictionary
+/// 3. Cross-reference table and trailer for the first page section (parts 2 - 6)
+/// 4. Document catalog
+/// 5. Primary hint stream (page offset and shared object hint tables)
+/// 6. Objects of the first page, including the objects it shares with other pages
+/// 7. Objects of the remaining pages, one page after another
+/// 8. Objects that are shared by the remaining pages
+/// 9. All other objects (page tree, document info, outlines, form fields, ...)
+/// 10. Main cross-reference table and trailer
+///
+/// All objects are renumbered: parts 7 - 9 are numbered from 1, the first page section
+/// gets the numbers after that. The `streamed` images are written like in `save_with_streamed_objects`.
+pub(crate) fn save_linearized<W: Write>(doc: &mut lopdf::Document, streamed: &StreamedXObjects, out: &mut CountingWriter<W>)
+-> IoResult<()>
+{
+    let invalid = |message: &str| IoError::new(ErrorKind::InvalidData, message.to_string());
+
+    let catalog_id = doc.trailer.get(b"Root").and_then(Object::as_reference).map_err(|_| invalid("the trailer has no /Root"))?;
+    let pages_id = doc.get_dictionary(catalog_id).and_then(|c| c.get(b"Pages")).and_then(Object::as_reference)
+        .map_err(|_| invalid("the catalog has no /Pages"))?;
+    let page_ids: Vec<ObjectId> = doc.get_dictionary(pages_id).and_then(|p| p.get(b"Kids")).and_then(Object::as_array)
+        .map_err(|_| invalid("the page tree has no /Kids"))?
+        .iter().filter_map(|kid| kid.as_reference().ok()).collect();
+
+    if page_ids.is_empty() {
+        return Err(invalid("a linearized file needs at least one page"));
+    }
+
+    // ----- sort the objects into the parts of the file
+
+    let mut excluded: HashSet<ObjectId> = page_ids.iter().cloned().collect();
+    excluded.insert(pages_id);
+    excluded.insert(catalog_id);
+
+    let pages: Vec<Vec<ObjectId>> = page_ids.iter().map(|&page| page_objects(doc, page, &excluded)).collect();
+
+    let first_page: Vec<ObjectId> = pages[0].clone();
+    let first_page_set: HashSet<ObjectId> = first_page.iter().cloned().collect();
+
+    // number of remaining pages that use an object
+    let mut usage = HashMap::<ObjectId, usize>::new();
+    for page in &pages[1..] {
+        for id in page {
+            *usage.entry(*id).or_insert(0) += 1;
+        }
+    }
+
+    let other_pages: Vec<Vec<ObjectId>> = pages[1..].iter().map(|page|
+        page.iter().cloned().filter(|id| !first_page_set.contains(id) && usage[id] == 1).collect()
+    ).collect();
+
+    let mut shared = Vec::<ObjectId>::new();
+    let mut shared_set = HashSet::<ObjectId>::new();
+    for id in pages[1..].iter().flatten() {
+        if !first_page_set.contains(id) && usage[id] > 1 && shared_set.insert(*id) {
+            shared.push(*id);
+        }
+    }
+
+    let mut assigned: HashSet<ObjectId> = first_page_set.iter().chain(shared.iter()).chain(other_pages.iter().flatten()).cloned().collect();
+    assigned.insert(catalog_id);
+    let remaining: Vec<ObjectId> = doc.objects.keys().cloned().filter(|id| !assigned.contains(id)).collect();
+
+    // ----- renumber the objects
+
+    let to_io_error = |e: Error| IoError::new(ErrorKind::InvalidData, e.to_string());
+    let mut ids = ObjectIdAllocator::new(0);
+    let main_objects: Vec<ObjectId> = other_pages.iter().flatten().chain(shared.iter()).chain(remaining.iter()).cloned().collect();
+    for id in &main_objects {
+        ids.remap(*id).map_err(to_io_error)?;
+    }
+
+    // the main cross-reference table contains the objects 0 .. main_size
+    let main_size = ids.max_id() + 1;
+    let linearization_number = ids.allocate().map_err(to_io_error)?.0;
+    ids.remap(catalog_id).map_err(to_io_error)?;
+    let hint_number = ids.allocate().map_err(to_io_error)?.0;
+    for id in &first_page {
+        ids.remap(*id).map_err(to_io_error)?;
+    }
+    let total_size = ids.max_id() + 1;
+
+    // all objects have a number now, so this only renumbers the references
+    let mut objects = BTreeMap::new();
+    ids.import(&mut objects, ::std::mem::take(&mut doc.objects)).map_err(to_io_error)?;
+
+    let mut trailer = doc.trailer.clone();
+    for (_, value) in trailer.iter_mut() {
+        ids.renumber_references(value);
+    }
+
+    let number = |id: &ObjectId| -> u32 { ids.get(*id).map(|(number, _)| number).unwrap_or(0) };
+
+    // ----- serialize the objects (except for the stream content)
+
+    let serialize = |id: &ObjectId| -> IoResult<LinearizedObject> {
+        let number = number(id);
+        let object = &objects[&(number, 0)];
+        if let Some(image) = streamed.get(id) {
+            return Ok(LinearizedObject::Streamed(streamed_object_header(number, 0, object)?, image));
+        }
+        let mut bytes = Vec::<u8>::new();
+        writeln!(bytes, "{} 0 obj", number)?;
+        match *object {
+            Object::Stream(ref stream) => {
+                write_dictionary(&mut bytes, &stream.dict)?;
+                bytes.extend_from_slice(b"\nstream\n");
+                Ok(LinearizedObject::Stream(bytes, &stream.content))
+            },
+            _ => {
+                write_object(&mut bytes, object)?;
+                bytes.extend_from_slice(b"\nendobj\n");
+                Ok(LinearizedObject::Plain(bytes))
+            },
+        }
+    };
+
+    let catalog = serialize(&catalog_id)?;
+    let first_page_objects = first_page.iter().map(&serialize).collect::<IoResult<Vec<_>>>()?;
+    let main_serialized = main_objects.iter().map(&serialize).collect::<IoResult<Vec<_>>>()?;
+
+    // ----- layout, the dictionary and the first cross-reference table have a fixed length
+
+    let mut header = Vec::<u8>::new();
+    write_header(&mut header, &doc.version)?;
+
+    let linearization_dict = |file_length: usize, hint_offset: usize, hint_length: usize,
+                              first_page_end: usize, main_xref_entries: usize| {
+        format!("{} 0 obj\n<</Linearized 1/L {:010}/H [{:010} {:010}]/O {}/E {:010}/N {}/T {:010}>>\nendobj\n",
+                linearization_number, file_length, hint_offset, hint_length,
+                number(&first_page[0]), first_page_end, page_ids.len(), main_xref_entries).into_bytes()
+    };
+
+    let first_xref_length = {
+        let mut trailer_bytes = Vec::new();
+        write_first_page_trailer(&mut trailer_bytes, &trailer, total_size, 0)?;
+        format!("xref\n{} {}\n", main_size, total_size - main_size).len() + 20 * (total_size - main_size) as usize + trailer_bytes.len()
+    };
+
+    let first_xref_offset = header.len() + linearization_dict(0, 0, 0, 0, 0).len();
+    let catalog_offset = first_xref_offset + first_xref_length;
+    let hint_offset = catalog_offset + catalog.len();
+
+    // offsets in the hint tables are computed as if the hint stream didn't exist
+    let mut offset = hint_offset;
+    let first_page_offsets: Vec<usize> = first_page_objects.iter().map(|o| { let start = offset; offset += o.len(); start }).collect();
+    let first_page_end = offset;
+    let main_offsets: Vec<usize> = main_serialized.iter().map(|o| { let start = offset; offset += o.len(); start }).collect();
+    let main_xref_offset_without_hints = offset;
+
+    let object_offset = |id: &ObjectId| -> usize {
+        let number = number(id);
+        if number < main_size { main_offsets[number as usize - 1] } else { first_page_offsets[(number - main_size - 3) as usize] }
+    };
+    let object_length = |id: &ObjectId| -> usize {
+        let number = number(id);
+        if number < main_size { main_serialized[number as usize - 1].len() } else { first_page_objects[(number - main_size - 3) as usize].len() }
+    };
+
+    // ----- hint tables
+
+    let shared_index: HashMap<ObjectId, usize> = first_page.iter().chain(shared.iter()).enumerate().map(|(idx, id)| (*id, idx)).collect();
+
+    let page_lengths: Vec<usize> = std::iter::once(first_page_end - hint_offset)
+        .chain(other_pages.iter().map(|page| page.iter().map(&object_length).sum()))
+        .collect();
+    let page_object_counts: Vec<usize> = std::iter::once(first_page.len()).chain(other_pages.iter().map(|page| page.len())).collect();
+    // the first page contains all objects it needs, only the remaining pages reference shared objects
+    let page_shared_references: Vec<Vec<usize>> = std::iter::once(Vec::new()).chain(pages[1..].iter().map(|page|
+        page.iter().filter(|id| first_page_set.contains(id) || shared_set.contains(id)).map(|id| shared_index[id]).collect()
+    )).collect();
+
+    let min_objects = *page_object_counts.iter().min().unwrap();
+    let max_objects = *page_object_counts.iter().max().unwrap();
+    let min_length = *page_lengths.iter().min().unwrap();
+    let max_length = *page_lengths.iter().max().unwrap();
+    let max_references = page_shared_references.iter().map(|r| r.len()).max().unwrap();
+    let max_shared_index = page_shared_references.iter().flatten().max().cloned().unwrap_or(0);
+
+    let objects_bits = bits_needed((max_objects - min_objects) as u64);
+    let length_bits = bits_needed((max_length - min_length) as u64);
+    let references_bits = bits_needed(max_references as u64);
+    let shared_index_bits = bits_needed(max_shared_index as u64);
+
+    // page offset hint table: the content stream items are set to cover the whole page,
+    // like Acrobat does it
+    let mut hints = BitWriter::default();
+    hints.write(min_objects as u64, 32);
+    hints.write(first_page_offsets[0] as u64, 32);
+    hints.write(objects_bits as u64, 16);
+    hints.write(min_length as u64, 32);
+    hints.write(length_bits as u64, 16);
+    hints.write(0, 32);
+    hints.write(0, 16);
+    hints.write(min_length as u64, 32);
+    hints.write(length_bits as u64, 16);
+    hints.write(references_bits as u64, 16);
+    hints.write(shared_index_bits as u64, 16);
+    hints.write(0, 16);
+    hints.write(1, 16);
+
+    for count in &page_object_counts { hints.write((count - min_objects) as u64, objects_bits); }
+    hints.pad();
+    for length in &page_lengths { hints.write((length - min_length) as u64, length_bits); }
+    hints.pad();
+    for references in &page_shared_references { hints.write(references.len() as u64, references_bits); }
+    hints.pad();
+    for index in page_shared_references.iter().flatten() { hints.write(*index as u64, shared_index_bits); }
+    hints.pad();
+    // numerators of the fractional positions use 0 bits, the content stream offsets as well
+    for length in &page_lengths { hints.write((length - min_length) as u64, length_bits); }
+    hints.pad();
+
+    let shared_table_offset = hints.bytes.len();
+
+    // shared object hint table: one group for each object of the first page, then the shared objects
+    let group_lengths: Vec<usize> = first_page.iter().chain(shared.iter()).map(&object_length).collect();
+    let min_group_length = *group_lengths.iter().min().unwrap();
+    let group_length_bits = bits_needed((group_lengths.iter().max().unwrap() - min_group_length) as u64);
+
+    hints.write(shared.first().map(&number).unwrap_or(0) as u64, 32);
+    hints.write(shared.first().map(&object_offset).unwrap_or(0) as u64, 32);
+    hints.write(first_page.len() as u64, 32);
+    hints.write(group_lengths.len() as u64, 32);
+    hints.write(0, 16);
+    hints.write(min_group_length as u64, 32);
+    hints.write(group_length_bits as u64, 16);
+
+    for length in &group_lengths { hints.write((length - min_group_length) as u64, group_length_bits); }
+    hints.pad();
+    // no MD5 signatures
+    for _ in &group_lengths { hints.write(0, 1); }
+    hints.pad();
+
+    let mut hint_stream = Vec::<u8>::new();
+    writeln!(hint_stream, "{} 0 obj\n<</Length {}/S {}>>\nstream", hint_number, hints.bytes.len(), shared_table_offset)?;
+    hint_stream.extend_from_slice(&hints.bytes);
+    hint_stream.extend_from_slice(STREAM_FOOTER);
+    let hint_length = hint_stream.len();
+
+    // ----- actual offsets
+
+    let main_xref_offset = main_xref_offset_without_hints + hint_length;
+    let main_xref_header = format!("xref\n0 {}\n", main_size);
+    let mut main_xref = main_xref_header.clone().into_bytes();
+    main_xref.extend_from_slice(b"0000000000 65535 f \n");
+    for offset in &main_offsets {
+        writeln!(main_xref, "{:010} 00000 n ", offset + hint_length)?;
+    }
+    write!(main_xref, "trailer\n<</Size {}>>\nstartxref\n{}\n%%EOF", main_size, first_xref_offset)?;
+
+    let file_length = main_xref_offset + main_xref.len();
+    // offset of the line break before the first entry of the main cross-reference table
+    let main_xref_entries = main_xref_offset + main_xref_header.len() - 1;
+
+    let mut first_xref = format!("xref\n{} {}\n", main_size, total_size - main_size).into_bytes();
+    writeln!(first_xref, "{:010} 00000 n ", header.len())?;
+    writeln!(first_xref, "{:010} 00000 n ", catalog_offset)?;
+    writeln!(first_xref, "{:010} 00000 n ", hint_offset)?;
+    for offset in &first_page_offsets {
+        writeln!(first_xref, "{:010} 00000 n ", offset + hint_length)?;
+    }
+    write_first_page_trailer(&mut first_xref, &trailer, total_size, main_xref_offset)?;
+
+    // ----- write the file
+
+    out.write_all(&header)?;
+    out.write_all(&linearization_dict(file_length, hint_offset, hint_length, first_page_end + hint_length, main_xref_entries))?;
+    out.write_all(&first_xref)?;
+    catalog.write(out)?;
+    out.objects += 1;
+    out.write_all(&hint_stream)?;
+    for object in first_page_objects.iter().chain(main_serialized.iter()) {
+        object.write(out)?;
+        out.objects += 1;
+    }
+    out.write_all(&main_xref)?;
+
+    // the offsets in the linearization dictionary and the hint tables are wrong otherwise
+    if out.written != file_length {
+        return Err(invalid(&format!("wrote {} bytes, but the linearization dictionary expects {}", out.written, file_length)));
+    }
+
+    // the objects are renumbered, so the document can't be saved again
+    doc.objects = objects;
+    doc.max_id = total_size - 1;
+    out.flush()
+}
+
+/// Writes the trailer of the first page cross-reference table,
+/// `/Prev` has a fixed width, so that the length is known before the offset
+fn write_first_page_trailer(out: &mut Vec<u8>, trailer: &lopdf::Dictionary, size: u32, main_xref_offset: usize)
+-> IoResult<()>
+{
+    let mut trailer = trailer.clone();
+    trailer.set("Size", Object::Integer(size as i64));
+    trailer.remove(b"Prev");
+
+    let mut trailer_bytes = Vec::<u8>::new();
+    write_dictionary(&mut trailer_bytes, &trailer)?;
+    // insert /Prev before the closing ">>"
+    trailer_bytes.truncate(trailer_bytes.len() - 2);
+    write!(trailer_bytes, "/Prev {:010}>>", main_xref_offset)?;
+
+    out.extend_from_slice(b"trailer\n");
+    out.extend_from_slice(&trailer_bytes);
+    out.extend_from_slice(b"\nstartxref\n0\n%%EOF\n");
+    Ok(())
+}
+
+/// Writes `id generation obj <object> endobj`
+fn write_indirect_object<W: Write>(out: &mut W, id: u32, generation: u16, object: &Object)
+-> IoResult<()>
+{
+    let mut bytes = Vec::<u8>::new();
+    writeln!(bytes, "{} {} obj", id, generation)?;
+
+    // the stream content is written directly, to avoid copying (possibly large) streams
+    match *object {
+        Object::Stream(ref stream) => {
+            write_dictionary(&mut bytes, &stream.dict)?;
+            bytes.extend_from_slice(b"\nstream\n");
+            out.write_all(&bytes)?;
+            out.write_all(&stream.content)?;
+            out.write_all(STREAM_FOOTER)
+        },
+        _ => {
+            write_object(&mut bytes, object)?;
+            bytes.extend_from_slice(b"\nendobj\n");
+            out.write_all(&bytes)
+        },
+    }
+}
+
+/// Writes the stream dictionary `object` of a streamed image, followed by the
+/// image data, which is read from the reader of the image
+fn write_streamed_object<W: Write>(out: &mut W, id: u32, generation: u16, object: &Object,
+                                   image: &StreamedImageXObject)
+-> IoResult<()>
+{
+    out.write_all(&streamed_object_header(id, generation, object)?)?;
+    image.copy_data(out)?;
+    out.write_all(STREAM_FOOTER)
+}
+
+/// `id generation obj <stream dictionary> stream`, written before the data of a streamed image
+fn streamed_object_header(id: u32, generation: u16, object: &Object)
+-> IoResult<Vec<u8>>
+{
+    let mut bytes = Vec::<u8>::new();
+    writeln!(bytes, "{} {} obj", id, generation)?;
+    write_object(&mut bytes, object)?;
+    bytes.extend_from_slice(b"\nstream\n");
+    Ok(bytes)
+}
+
+/// Serializes a single PDF object
+pub(crate) fn write_object(out: &mut Vec<u8>, object: &Object)
+-> IoResult<()>
+{
+    use lopdf::Object::*;
+
+    match *object {
+        Null => out.extend_from_slice(b"null"),
+        Boolean(value) => out.extend_from_slice(if value { b"true" } else { b"false" }),
+        Integer(value) => write!(out, "{}", value)?,
+        Real(value) => write_real(out, value)?,
+        Name(ref name) => write_name(out, name),
+        String(ref text, ref format) => write_string(out, text, format)?,
+        Array(ref array) => {
+            out.push(b'[');
+            for (idx, object) in array.iter().enumerate() {
+                if idx != 0 { out.push(b' '); }
+                write_object(out, object)?;
+            }
+            out.push(b']');
+        },
+        Dictionary(ref dict) => write_dictionary(out, dict)?,
+        Stream(ref stream) => {
+            write_dictionary(out, &stream.dict)?;
+            out.extend_from_slice(b"\nstream\n");
+            out.extend_from_slice(&stream.content);
+            out.extend_from_slice(b"\nendstream");
+        },
+        Reference((id, generation)) => write!(out, "{} {} R", id, generation)?,
+    }
+
+    Ok(())
+}
+
+/// PDF doesn't allow exponents in numbers. Rusts `Display` for `f64` never
+/// uses an exponent, but it can print NaN and infinity, which are invalid
+fn write_real(out: &mut Vec<u8>, value: f64)
+-> IoResult<()>
+{
+    if value.is_finite() {
+        write!(out, "{}", value)
+    } else {
+        out.push(b'0');
+        Ok(())
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &[u8])
+{
+    out.push(b'/');
+    for &byte in name {
+        // white-space, delimiters and bytes outside of 33 (!) to 126 (~) are encoded as #XX
+        if b" \t\n\r\x0C()<>[]{}/%#".contains(&byte) || !(33..=126).contains(&byte) {
+            out.extend_from_slice(format!("#{:02X}", byte).as_bytes());
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, text: &[u8], format: &lopdf::StringFormat)
+-> IoResult<()>
+{
+    match *format {
+        lopdf::StringFormat::Literal => {
+            out.push(b'(');
+            for &byte in text {
+                match byte {
+                    b'(' | b')' | b'\\' => { out.push(b'\\'); out.push(byte); },
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    _ => out.push(byte),
+                }
+            }
+            out.push(b')');
+        },
+        lopdf::StringFormat::Hexadecimal => {
+            out.push(b'<');
+            for &byte in text {
+                write!(out, "{:02X}", byte)?;
+            }
+            out.push(b'>');
+        },
+    }
+
+    Ok(())
+}
+
+fn write_dictionary(out: &mut Vec<u8>, dict: &lopdf::Dictionary)
+-> IoResult<()>
+{
+    out.extend_from_slice(b"<<");
+    for (key, value) in dict {
+        write_name(out, key);
+        out.push(b' ');
+        write_object(out, value)?;
+    }
+    out.extend_from_slice(b">>");
+    Ok(())
+}
+
+#[test]
+fn test_write_object()
+{
+    use lopdf::Object::*;
+    use lopdf::StringFormat;
+
+    let mut out = Vec::new();
+    write_object(&mut out, &Array(vec![
+        Integer(1), Real(0.5), Name("A B".into()), Reference((3, 0)),
+        String("a(b)".into(), StringFormat::Literal), String(vec![0x0a, 0xff], StringFormat::Hexadecimal),
+    ])).unwrap();
+
+    assert_eq!(out, b"[1 0.5 /A#20B 3 0 R (a\\(b\\)) <0AFF>]".to_vec());
+}
+
+#[test]
+fn test_bit_writer()
+{
+    let mut bits = BitWriter::default();
+    bits.write(0b101, 3);
+    bits.write(0b1, 1);
+    bits.pad();
+    bits.write(0x1234, 16);
+    bits.write(0b11, 2);
+
+    assert_eq!(bits.bytes, vec![0b1011_0000, 0x12, 0x34, 0b1100_0000]);
+    assert_eq!(bits_needed(0), 0);
+    assert_eq!(bits_needed(1), 1);
+    assert_eq!(bits_needed(255), 8);
+}
+
+#[test]
+fn test_counting_writer_partial_write()
+{
+    /// Writer that fails as soon as more than `limit` bytes are written
+    struct LimitedWriter { bytes: Vec<u8>, limit: usize }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            if self.bytes.len() + buf.len() > self.limit {
+                return Err(IoError::new(ErrorKind::BrokenPipe, "connection closed"));
+            }
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    let mut doc = lopdf::Document::with_version("1.4");
+    for value in 0..3 {
+        doc.add_object(Object::Integer(value));
+    }
+
+    // header (15 bytes) and the first object ("1 0 obj\n0\nendobj\n", 17 bytes) fit
+    let mut target = LimitedWriter { bytes: Vec::new(), limit: 40 };
+    let mut out = CountingWriter::new(&mut target);
+    let error = save_with_streamed_objects(&mut doc, &StreamedXObjects::new(), &mut out).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    assert_eq!((out.written, out.objects), (32, 1));
+    assert_eq!(target.bytes.len(), 32);
+}
+
+#[test]
+fn test_save_linearized_offsets()
+{
+    use {PdfDocument, Mm};
+
+    let (doc, _, _) = PdfDocument::new("Linearized", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    doc.set_linearized(true);
+    let bytes = doc.save_to_bytes().unwrap();
+
+    let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle).unwrap();
+    let value = |key: &str| -> usize {
+        let start = find(key.as_bytes()) + key.len();
+        let end = start + bytes[start..].iter().position(|b| !b.is_ascii_digit()).unwrap();
+        ::std::str::from_utf8(&bytes[start..end]).unwrap().parse().unwrap()
+    };
+
+    assert_eq!(value("/L "), bytes.len());
+
+    // the hint stream, "<number> 0 obj ... endobj"
+    let hint_offset = value("/H [");
+    let hint_length = value(&format!("/H [{:010} ", hint_offset));
+    let hint_stream = &bytes[hint_offset..hint_offset + hint_length];
+    let number_length = hint_stream.iter().position(|b| !b.is_ascii_digit()).unwrap();
+    assert!(number_length > 0 && hint_stream[number_length..].starts_with(b" 0 obj\n<</Length"));
+    assert!(hint_stream.ends_with(b"endstream\nendobj\n"));
+
+    // the first page object is in the first page section, the main part starts after it
+    let first_page_end = value("/E ");
+    let first_page = format!("\n{} 0 obj\n<</Type /Page", value("/O "));
+    assert!(find(first_page.as_bytes()) < first_page_end);
+    assert!(bytes[first_page_end..].starts_with(b"1 0 obj\n"));
+
+    // the line break before the first entry of the main cross-reference table
+    let main_xref = value("/T ");
+    let subsection = bytes[..main_xref].windows(5).rposition(|w| w == b"xref\n").unwrap() + 5;
+    assert!(bytes[subsection..main_xref].starts_with(b"0 "));
+    assert!(bytes[subsection + 2..main_xref].iter().all(u8::is_ascii_digit));
+    assert!(bytes[main_xref..].starts_with(b"\n0000000000 65535 f \n"));
+}
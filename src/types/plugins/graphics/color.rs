@@ -55,7 +55,7 @@ impl Into<Operation> for PdfColor {
 }
 
 /// Color space (enum for marking the number of bits a color has)
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ColorSpace {
     Rgb,
     Rgba,
@@ -97,7 +97,7 @@ impl Into<&'static str> for ColorSpace {
 }
 
 /// How many bits does a color have?
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ColorBits {
     Bit1,
     Bit8,
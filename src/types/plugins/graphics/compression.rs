@@ -0,0 +1,450 @@
+//! Compression filters for the page content streams and images, see
+//! `PdfDocumentReference::set_content_filter()` and `set_image_filter()`
+
+use lopdf;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use types::pdf_writer::BitWriter;
+
+/// Filter for compressing a stream without loss
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamFilter {
+    /// The stream is written as it is
+    Uncompressed,
+    /// `FlateDecode` (zlib), the best choice for most streams
+    Flate,
+    /// `LZWDecode`, only for old readers that don't support `FlateDecode`
+    Lzw,
+}
+
+/// Compression of the images. Images that a filter can't be used for
+/// (see the variants) are compressed with `Flate` instead.
+///
+/// __NOTE__: Only applies to images that are held in memory, not to `StreamedImageXObject`s
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageCompression {
+    /// The image samples are written as they are
+    Uncompressed,
+    /// `FlateDecode` (zlib), lossless
+    Flate,
+    /// `LZWDecode`, lossless
+    Lzw,
+    /// `DCTDecode` (JPEG) with a quality from 1 to 100, lossy. Only for 8 bit greyscale and
+    /// RGB images, requires the `embedded_images` feature.
+    Dct { quality: u8 },
+    /// `CCITTFaxDecode` with Group 4 encoding, lossless. Only for 1 bit (black and white)
+    /// images, where it is much smaller than `Flate` (e.g. scanned text documents).
+    CcittFaxG4,
+}
+
+/// Compresses a stream with the filter, `Flate` is only used if it makes the stream smaller
+pub(crate) fn compress_stream(stream: &mut lopdf::Stream, filter: StreamFilter)
+{
+    match filter {
+        StreamFilter::Uncompressed => stream.allows_compression = false,
+        StreamFilter::Flate => {
+            stream.allows_compression = true;
+            // if compression fails, the stream is simply written uncompressed
+            let _ = stream.compress();
+            stream.allows_compression = false;
+        },
+        StreamFilter::Lzw => {
+            let encoded = lzw_encode(&stream.content);
+            stream.dict.set("Filter", lopdf::Object::Name("LZWDecode".into()));
+            stream.set_content(encoded);
+            stream.allows_compression = false;
+        },
+    }
+}
+
+/// Encodes the data with `ASCII85Decode`, including the `~>` end marker
+pub(crate) fn ascii85_encode(data: &[u8])
+-> Vec<u8>
+{
+    let mut encoded = Vec::with_capacity(data.len() * 5 / 4 + 2);
+    for chunk in data.chunks(4) {
+        let mut group = [0_u8; 4];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(group);
+        if chunk.len() == 4 && value == 0 {
+            encoded.push(b'z');
+            continue;
+        }
+        let mut digits = [0_u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = (value % 85) as u8 + b'!';
+            value /= 85;
+        }
+        // a partial group of n bytes is written as n + 1 digits
+        encoded.extend_from_slice(&digits[..chunk.len() + 1]);
+    }
+    encoded.extend_from_slice(b"~>");
+    encoded
+}
+
+/// Compresses all images in the document that don't have a filter yet
+pub(crate) fn compress_images(doc: &mut lopdf::Document, compression: ImageCompression)
+{
+    // images with an ICC profile are encoded like their device color space
+    let icc_color_spaces = doc.objects.iter().filter_map(|(id, object)| match *object {
+        lopdf::Object::Stream(ref stream) => match stream.dict.get(b"N").and_then(|n| n.as_i64()) {
+            Ok(1) => Some((*id, "DeviceGray")),
+            Ok(3) => Some((*id, "DeviceRGB")),
+            Ok(4) => Some((*id, "DeviceCMYK")),
+            _ => None,
+        },
+        _ => None,
+    }).collect::<HashMap<lopdf::ObjectId, &'static str>>();
+
+    for object in doc.objects.values_mut() {
+        if let lopdf::Object::Stream(ref mut stream) = *object {
+            let is_image = stream.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(&b"Image"[..]);
+            if is_image && stream.dict.get(b"Filter").is_err() {
+                let color_space = match stream.dict.get(b"ColorSpace") {
+                    Ok(lopdf::Object::Name(name)) => Some(String::from_utf8_lossy(name).into_owned()),
+                    Ok(lopdf::Object::Array(array)) => match array.get(1) {
+                        Some(&lopdf::Object::Reference(id)) => icc_color_spaces.get(&id).map(|name| name.to_string()),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                #[cfg(feature = "logging")]
+                let uncompressed = stream.content.len();
+                compress_image(stream, color_space, compression);
+                #[cfg(feature = "logging")] {
+                    debug!("Compressed image ({:?}): {} -> {} bytes", compression, uncompressed, stream.content.len());
+                }
+            }
+        }
+    }
+}
+
+fn compress_image(stream: &mut lopdf::Stream, color_space: Option<String>, compression: ImageCompression)
+{
+    use lopdf::Object::*;
+
+    let integer = |key: &[u8]| stream.dict.get(key).and_then(|o| o.as_i64()).unwrap_or(0);
+    let (width, height, bits) = (integer(b"Width"), integer(b"Height"), integer(b"BitsPerComponent"));
+    let image_mask = matches!(stream.dict.get(b"ImageMask"), Ok(&Boolean(true)));
+
+    match compression {
+        ImageCompression::Uncompressed => compress_stream(stream, StreamFilter::Uncompressed),
+        ImageCompression::Flate => compress_stream(stream, StreamFilter::Flate),
+        ImageCompression::Lzw => compress_stream(stream, StreamFilter::Lzw),
+        ImageCompression::Dct { quality } => {
+            match dct_encode(&stream.content, width, height, bits, color_space.as_deref(), quality) {
+                Some(encoded) => {
+                    stream.dict.set("Filter", Name("DCTDecode".into()));
+                    stream.set_content(encoded);
+                    stream.allows_compression = false;
+                },
+                None => compress_stream(stream, StreamFilter::Flate),
+            }
+        },
+        ImageCompression::CcittFaxG4 => {
+            let black_and_white = bits == 1 && (image_mask || color_space.as_deref() == Some("DeviceGray"));
+            let row_length = (width as usize).div_ceil(8);
+            if !black_and_white || width <= 0 || stream.content.len() < row_length * height as usize {
+                compress_stream(stream, StreamFilter::Flate);
+                return;
+            }
+
+            let encoded = ccitt_g4_encode(&stream.content, width as usize, height as usize);
+            stream.dict.set("Filter", Name("CCITTFaxDecode".into()));
+            stream.dict.set("DecodeParms", Dictionary(ccitt_g4_decode_parms(width, height)));
+            stream.set_content(encoded);
+            stream.allows_compression = false;
+        },
+    }
+}
+
+/// Encodes 8 bit greyscale and RGB samples as JPEG, returns `None` for other images
+#[cfg(feature = "embedded_images")]
+fn dct_encode(samples: &[u8], width: i64, height: i64, bits: i64, color_space: Option<&str>, quality: u8)
+-> Option<Vec<u8>>
+{
+    use image::ColorType;
+    use image::jpeg::JPEGEncoder;
+
+    let (color_type, components) = match color_space {
+        Some("DeviceGray") => (ColorType::L8, 1),
+        Some("DeviceRGB") => (ColorType::Rgb8, 3),
+        _ => return None,
+    };
+
+    if bits != 8 || width <= 0 || height <= 0 || samples.len() != (width * height) as usize * components {
+        return None;
+    }
+
+    let mut encoded = Vec::new();
+    JPEGEncoder::new_with_quality(&mut encoded, quality.clamp(1, 100))
+        .encode(samples, width as u32, height as u32, color_type)
+        .ok()?;
+    Some(encoded)
+}
+
+#[cfg(not(feature = "embedded_images"))]
+fn dct_encode(_: &[u8], _: i64, _: i64, _: i64, _: Option<&str>, _: u8)
+-> Option<Vec<u8>>
+{
+    None
+}
+
+/// LZW code that resets the table
+const LZW_CLEAR_TABLE: u64 = 256;
+/// LZW code that marks the end of the data
+const LZW_END_OF_DATA: u64 = 257;
+/// The table is reset before the codes need more than 12 bits
+const LZW_MAX_CODE: u64 = 4094;
+
+/// Encodes the data with LZW, as expected by `LZWDecode` with the default `/EarlyChange 1`
+fn lzw_encode(data: &[u8])
+-> Vec<u8>
+{
+    let mut output = BitWriter::default();
+    let mut table = HashMap::<(u64, u8), u64>::new();
+    let mut next_code = LZW_END_OF_DATA + 1;
+    let mut code_length = 9;
+
+    output.write(LZW_CLEAR_TABLE, code_length);
+
+    let mut current: Option<u64> = None;
+    for &byte in data {
+        let prefix = match current {
+            None => { current = Some(u64::from(byte)); continue; },
+            Some(prefix) => prefix,
+        };
+
+        if let Some(&code) = table.get(&(prefix, byte)) {
+            current = Some(code);
+            continue;
+        }
+
+        output.write(prefix, code_length);
+        table.insert((prefix, byte), next_code);
+        next_code += 1;
+        current = Some(u64::from(byte));
+
+        // "early change": the reader switches to the longer codes one code early
+        if next_code == LZW_MAX_CODE {
+            output.write(LZW_CLEAR_TABLE, code_length);
+            table.clear();
+            next_code = LZW_END_OF_DATA + 1;
+            code_length = 9;
+        } else if next_code == 1 << code_length {
+            code_length += 1;
+        }
+    }
+
+    if let Some(prefix) = current {
+        output.write(prefix, code_length);
+        // the reader adds an entry for the last code too, which can make the codes one bit longer
+        if next_code + 1 == 1 << code_length {
+            code_length += 1;
+        }
+    }
+    output.write(LZW_END_OF_DATA, code_length);
+
+    output.bytes
+}
+
+/// Codes for white runs of 0 - 63 pixels (ITU-T T.4, table 2)
+const WHITE_TERMINATING_CODES: [&str; 64] = [
+    "00110101", "000111", "0111", "1000", "1011", "1100", "1110", "1111",
+    "10011", "10100", "00111", "01000", "001000", "000011", "110100", "110101",
+    "101010", "101011", "0100111", "0001100", "0001000", "0010111", "0000011", "0000100",
+    "0101000", "0101011", "0010011", "0100100", "0011000", "00000010", "00000011", "00011010",
+    "00011011", "00010010", "00010011", "00010100", "00010101", "00010110", "00010111", "00101000",
+    "00101001", "00101010", "00101011", "00101100", "00101101", "00000100", "00000101", "00001010",
+    "00001011", "01010010", "01010011", "01010100", "01010101", "00100100", "00100101", "01011000",
+    "01011001", "01011010", "01011011", "01001010", "01001011", "00110010", "00110011", "00110100",
+];
+
+/// Codes for black runs of 0 - 63 pixels (ITU-T T.4, table 2)
+const BLACK_TERMINATING_CODES: [&str; 64] = [
+    "0000110111", "010", "11", "10", "011", "0011", "0010", "00011",
+    "000101", "000100", "0000100", "0000101", "0000111", "00000100", "00000111", "000011000",
+    "0000010111", "0000011000", "0000001000", "00001100111", "00001101000", "00001101100", "00000110111", "00000101000",
+    "00000010111", "00000011000", "000011001010", "000011001011", "000011001100", "000011001101", "000001101000", "000001101001",
+    "000001101010", "000001101011", "000011010010", "000011010011", "000011010100", "000011010101", "000011010110", "000011010111",
+    "000001101100", "000001101101", "000011011010", "000011011011", "000001010100", "000001010101", "000001010110", "000001010111",
+    "000001100100", "000001100101", "000001010010", "000001010011", "000000100100", "000000110111", "000000111000", "000000100111",
+    "000000101000", "000001011000", "000001011001", "000000101011", "000000101100", "000001011010", "000001100110", "000001100111",
+];
+
+/// Codes for white runs of 64 - 1728 pixels, in steps of 64 (ITU-T T.4, table 3)
+const WHITE_MAKEUP_CODES: [&str; 27] = [
+    "11011", "10010", "010111", "0110111", "00110110", "00110111", "01100100", "01100101",
+    "01101000", "01100111", "011001100", "011001101", "011010010", "011010011", "011010100", "011010101",
+    "011010110", "011010111", "011011000", "011011001", "011011010", "011011011", "010011000", "010011001",
+    "010011010", "011000", "010011011",
+];
+
+/// Codes for black runs of 64 - 1728 pixels, in steps of 64 (ITU-T T.4, table 3)
+const BLACK_MAKEUP_CODES: [&str; 27] = [
+    "0000001111", "000011001000", "000011001001", "000001011011", "000000110011", "000000110100", "000000110101", "0000001101100",
+    "0000001101101", "0000001001010", "0000001001011", "0000001001100", "0000001001101", "0000001110010", "0000001110011", "0000001110100",
+    "0000001110101", "0000001110110", "0000001110111", "0000001010010", "0000001010011", "0000001010100", "0000001010101", "0000001011010",
+    "0000001011011", "0000001100100", "0000001100101",
+];
+
+/// Codes for runs of 1792 - 2560 pixels of both colors, in steps of 64 (ITU-T T.4, table 3)
+const EXTENDED_MAKEUP_CODES: [&str; 13] = [
+    "00000001000", "00000001100", "00000001101", "000000010010", "000000010011", "000000010100", "000000010101",
+    "000000010110", "000000010111", "000000011100", "000000011101", "000000011110", "000000011111",
+];
+
+/// Codes of the two-dimensional modes (ITU-T T.4, table 4)
+const PASS_MODE: &str = "0001";
+const HORIZONTAL_MODE: &str = "001";
+/// Vertical mode, indexed by `a1 - b1 + 3` (VL3 ... V0 ... VR3)
+const VERTICAL_MODES: [&str; 7] = ["0000010", "000010", "010", "1", "011", "000011", "0000011"];
+/// End of facsimile block, two EOLs
+const END_OF_BLOCK: &str = "000000000001000000000001";
+
+fn write_code(output: &mut BitWriter, code: &str)
+{
+    for bit in code.bytes() {
+        output.write(u64::from(bit == b'1'), 1);
+    }
+}
+
+/// Writes the codes for a run of `length` pixels
+fn write_run(output: &mut BitWriter, mut length: usize, black: bool)
+{
+    let (terminating, makeup) = if black {
+        (&BLACK_TERMINATING_CODES, &BLACK_MAKEUP_CODES)
+    } else {
+        (&WHITE_TERMINATING_CODES, &WHITE_MAKEUP_CODES)
+    };
+
+    while length >= 64 {
+        let steps = (length / 64).min(40);
+        let code = if steps <= 27 { makeup[steps - 1] } else { EXTENDED_MAKEUP_CODES[steps - 28] };
+        write_code(output, code);
+        length -= steps * 64;
+    }
+
+    write_code(output, terminating[length]);
+}
+
+/// Positions of the changing elements of a row (pixels with a different color than the pixel
+/// before them, the pixel before the row is white). Followed by the width for convenience.
+fn changing_elements(row: &[u8], width: usize)
+-> Vec<usize>
+{
+    let mut changes = Vec::new();
+    let mut previous_black = false;
+    for x in 0..width {
+        // 1 bits are white, 0 bits are black (as in DeviceGray)
+        let black = (row[x / 8] >> (7 - x % 8)) & 1 == 0;
+        if black != previous_black {
+            changes.push(x);
+            previous_black = black;
+        }
+    }
+    changes.push(width);
+    changes.push(width);
+    changes
+}
+
+/// `/DecodeParms` of an image encoded with `ccitt_g4_encode()`
+pub(crate) fn ccitt_g4_decode_parms(width: i64, height: i64)
+-> lopdf::Dictionary
+{
+    use lopdf::Object::Integer;
+
+    lopdf::Dictionary::from_iter(vec![
+        ("K", Integer(-1)),
+        ("Columns", Integer(width)),
+        ("Rows", Integer(height)),
+    ])
+}
+
+/// Encodes a 1 bit image (rows padded to full bytes) with CCITT Group 4 (ITU-T T.6)
+pub(crate) fn ccitt_g4_encode(data: &[u8], width: usize, height: usize)
+-> Vec<u8>
+{
+    let row_length = width.div_ceil(8);
+    let mut output = BitWriter::default();
+    // the line above the first line is white
+    let mut reference = vec![width, width];
+
+    for row in data.chunks(row_length).take(height) {
+        let coding = changing_elements(row, width);
+
+        // a0 starts on an imaginary white pixel before the line
+        let mut a0: isize = -1;
+        let mut black = false;
+
+        loop {
+            let after_a0 = |changes: &[usize]| changes.iter().position(|&c| c as isize > a0).unwrap_or(changes.len() - 2);
+
+            let a1_index = after_a0(&coding);
+            let (a1, a2) = (coding[a1_index], coding[(a1_index + 1).min(coding.len() - 1)]);
+
+            // b1 has the opposite color of a0: changes to black are at even indices
+            let mut b1_index = after_a0(&reference);
+            if (b1_index % 2 == 0) == black && b1_index < reference.len() - 2 {
+                b1_index += 1;
+            }
+            let (b1, b2) = (reference[b1_index], reference[(b1_index + 1).min(reference.len() - 1)]);
+
+            if b2 < a1 {
+                write_code(&mut output, PASS_MODE);
+                a0 = b2 as isize;
+            } else if (a1 as isize - b1 as isize).abs() <= 3 {
+                write_code(&mut output, VERTICAL_MODES[(a1 as isize - b1 as isize + 3) as usize]);
+                a0 = a1 as isize;
+                black = !black;
+            } else {
+                write_code(&mut output, HORIZONTAL_MODE);
+                write_run(&mut output, a1 - a0.max(0) as usize, black);
+                write_run(&mut output, a2 - a1, !black);
+                a0 = a2 as isize;
+            }
+
+            if a0 >= width as isize {
+                break;
+            }
+        }
+
+        reference = coding;
+    }
+
+    write_code(&mut output, END_OF_BLOCK);
+    output.bytes
+}
+
+#[test]
+fn test_ascii85_encode() {
+    assert_eq!(ascii85_encode(b"Man "), b"9jqo^~>".to_vec());
+    assert_eq!(ascii85_encode(&[0, 0, 0, 0, 0]), b"z!!~>".to_vec());
+    assert_eq!(ascii85_encode(b""), b"~>".to_vec());
+}
+
+#[test]
+fn test_ccitt_codes_are_prefix_free() {
+    for (terminating, makeup) in &[(&WHITE_TERMINATING_CODES, &WHITE_MAKEUP_CODES), (&BLACK_TERMINATING_CODES, &BLACK_MAKEUP_CODES)] {
+        let codes: Vec<&str> = terminating.iter().chain(makeup.iter()).chain(EXTENDED_MAKEUP_CODES.iter()).cloned().collect();
+        for (i, a) in codes.iter().enumerate() {
+            for (j, b) in codes.iter().enumerate() {
+                assert!(i == j || !b.starts_with(a), "{} is a prefix of {}", a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ccitt_g4_encode() {
+    // a white line is a single V0 code, followed by the end of block
+    let encoded = ccitt_g4_encode(&[0xFF, 0xFF], 16, 1);
+    assert_eq!(encoded, vec![0b1000_0000, 0b0000_1000, 0b0000_0000, 0b1000_0000]);
+}
+
+#[test]
+fn test_lzw_encode() {
+    // example from the PDF reference (7.4.4.2): 45 45 45 45 45 65 45 45 45 66
+    let encoded = lzw_encode(&[45, 45, 45, 45, 45, 65, 45, 45, 45, 66]);
+    assert_eq!(encoded, vec![0x80, 0x0B, 0x60, 0x50, 0x22, 0x0C, 0x0C, 0x85, 0x01]);
+}
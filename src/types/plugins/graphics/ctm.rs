@@ -7,7 +7,7 @@ use {Mm, Pt};
 /// PDF "current transformation matrix". Once set, will operate on all following shapes,
 /// until the `layer.restore_graphics_state()` is called. It is important to
 /// call `layer.save_graphics_state()` earlier.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum CurTransMat {
     /// Translation matrix (in points from bottom left corner)
     /// X and Y can have different values
@@ -34,6 +34,9 @@ pub enum TextMatrix {
     /// Text translate matrix, used for indenting (transforming) text
     /// (different to regular text placement)
     Translate(Mm, Mm),
+    /// Translation to a point and counterclockwise rotation (in degrees) around it,
+    /// used for placing single glyphs (e.g. text on a path)
+    TranslateRotate(Pt, Pt, f64),
 }
 
 impl Into<[f64; 6]> for TextMatrix {
@@ -49,6 +52,7 @@ impl Into<[f64; 6]> for TextMatrix {
                 [ 1.0, 0.0, 0.0, 1.0, x_pt.0, y_pt.0 ] 
             }
             Rotate(rot) => { let rad = (360.0 - rot).to_radians(); [rad.cos(), -rad.sin(), rad.sin(), rad.cos(), 0.0, 0.0 ] /* cos sin -sin cos 0 0 cm */ }
+            TranslateRotate(x, y, rot) => { let rad = rot.to_radians(); [rad.cos(), rad.sin(), -rad.sin(), rad.cos(), x.0, y.0 ] }
         }
     }
 }
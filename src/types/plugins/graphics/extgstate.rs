@@ -38,9 +38,11 @@ use lopdf;
 use lopdf::content::Operation;
 use lopdf::Object::*;
 use std::string::String;
-use indices::FontIndex;
+use indices::{FontIndex, XObjectIndex};
 use std::collections::HashSet;
 use std::collections::HashMap;
+use types::plugins::graphics::xobject::XObjectCache;
+use Rect;
 
 // identifiers for tracking the changed fields
 pub (crate) const LINE_WIDTH: &'static str = "line_width";
@@ -108,6 +110,30 @@ impl ExtendedGraphicsStateList {
         self.latest_graphics_state = (self.latest_graphics_state.0, added_state);
         gs_ref
     }
+
+    /// Returns the number of graphics states in this list
+    #[inline]
+    pub fn len(&self)
+    -> usize
+    {
+        self.all_graphics_states.len()
+    }
+
+    /// Returns if the graphics state list is empty
+    #[inline]
+    pub fn is_empty(&self)
+    -> bool
+    {
+        self.all_graphics_states.is_empty()
+    }
+
+    /// Removes all graphics states that were added after the list had `len` entries
+    pub(crate) fn truncate(&mut self, len: usize)
+    {
+        for index in len..self.all_graphics_states.len() {
+            self.all_graphics_states.remove(&ExtendedGraphicsStateRef::new(index).gs_name);
+        }
+    }
 }
 
 impl Into<lopdf::Dictionary> for ExtendedGraphicsStateList {
@@ -118,7 +144,7 @@ impl Into<lopdf::Dictionary> for ExtendedGraphicsStateList {
     {
         let mut ext_g_state_resources = lopdf::Dictionary::new();
 
-        for (name, (_, graphics_state)) in self.all_graphics_states {
+        for (name, graphics_state) in self.sorted() {
             let gs: lopdf::Object = graphics_state.into();
             ext_g_state_resources.set(name.to_string(), gs);
         }
@@ -127,6 +153,55 @@ impl Into<lopdf::Dictionary> for ExtendedGraphicsStateList {
     }
 }
 
+impl ExtendedGraphicsStateList {
+
+    /// Returns the graphics states sorted by their name, so that
+    /// they are always written in the same order
+    fn sorted(self)
+    -> Vec<(String, ExtendedGraphicsState)>
+    {
+        let mut states: Vec<(String, ExtendedGraphicsState)> = self.all_graphics_states.into_iter()
+            .map(|(name, (_, graphics_state))| (name, graphics_state))
+            .collect();
+        states.sort_by(|a, b| a.0.cmp(&b.0));
+        states
+    }
+
+    /// Indices of the forms that the soft masks draw
+    pub(crate) fn forms(&self)
+    -> Vec<usize>
+    {
+        self.all_graphics_states.values()
+            .filter_map(|(_, state)| state.soft_mask.as_ref().map(|mask| mask.form.0))
+            .collect()
+    }
+
+    /// Same as `Into<lopdf::Dictionary>`, but soft masks are added to the document, since
+    /// the soft mask dictionary has to reference the form that is used as the mask. The
+    /// forms are looked up in the `cache`.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, cache: &XObjectCache)
+    -> lopdf::Dictionary
+    {
+        let mut ext_g_state_resources = lopdf::Dictionary::new();
+
+        for (name, graphics_state) in self.sorted() {
+            let soft_mask = if graphics_state.changed_fields.contains(SOFT_MASK) {
+                graphics_state.soft_mask
+            } else {
+                None
+            };
+            let mut gs: lopdf::Object = graphics_state.into();
+            if let (Some(soft_mask), Dictionary(ref mut dict)) = (soft_mask, &mut gs) {
+                dict.set("SMask", soft_mask.into_with_document(doc, cache));
+                dict.set("Type", "ExtGState");
+            }
+            ext_g_state_resources.set(name, gs);
+        }
+
+        ext_g_state_resources
+    }
+}
+
 /// `ExtGState` dictionary
 #[derive(Debug, PartialEq, Clone)]
 pub struct ExtendedGraphicsState {
@@ -347,7 +422,7 @@ impl ExtendedGraphicsStateBuilder {
         self
     }
 
-    /// Sets the miter limit
+    /// Sets the miter limit, see `PdfLayerReference::set_miter_limit()`. Default: __10.0__
     #[inline]
     pub fn with_miter_limit(mut self, miter_limit: f64)
     -> Self
@@ -578,6 +653,18 @@ impl ExtendedGraphicsStateBuilder {
     }
 }
 
+impl ExtendedGraphicsState {
+
+    /// Returns if the graphics state uses transparency (blend modes, soft masks,
+    /// alpha constants), which requires PDF 1.4
+    pub(crate) fn uses_transparency(&self)
+    -> bool
+    {
+        [BLEND_MODE, SOFT_MASK, CURRENT_STROKE_ALPHA, CURRENT_FILL_ALPHA, ALPHA_IS_SHAPE, TEXT_KNOCKOUT]
+            .iter().any(|field| self.changed_fields.contains(field))
+    }
+}
+
 impl Default for ExtendedGraphicsState {
     /// Creates a default ExtGState dictionary. Useful for resetting
     fn default()
@@ -588,7 +675,7 @@ impl Default for ExtendedGraphicsState {
             line_width: 1.0,
             line_cap: LineCapStyle::Butt,
             line_join: LineJoinStyle::Miter,
-            miter_limit: 0.0,
+            miter_limit: 10.0,
             line_dash_pattern: None,
             rendering_intent: RenderingIntent::RelativeColorimetric,
             overprint_stroke: false,
@@ -658,12 +745,12 @@ impl Into<lopdf::Object> for ExtendedGraphicsState {
             gs_operations.push(("SA".to_string(), self.stroke_adjustment.into()));
         }
 
-        if self.changed_fields.contains(OVERPRINT_FILL) {
-            gs_operations.push(("OP".to_string(), self.overprint_fill.into()));
+        if self.changed_fields.contains(OVERPRINT_STROKE) {
+            gs_operations.push(("OP".to_string(), self.overprint_stroke.into()));
         }
 
-        if self.changed_fields.contains(OVERPRINT_STROKE) {
-            gs_operations.push(("op".to_string(), self.overprint_stroke.into()));
+        if self.changed_fields.contains(OVERPRINT_FILL) {
+            gs_operations.push(("op".to_string(), self.overprint_fill.into()));
         }
 
         if self.changed_fields.contains(OVERPRINT_MODE) {
@@ -671,11 +758,11 @@ impl Into<lopdf::Object> for ExtendedGraphicsState {
         }
 
         if self.changed_fields.contains(CURRENT_FILL_ALPHA) {
-            gs_operations.push(("CA".to_string(), self.current_fill_alpha.into()));
+            gs_operations.push(("ca".to_string(), self.current_fill_alpha.into()));
         }
 
         if self.changed_fields.contains(CURRENT_STROKE_ALPHA) {
-            gs_operations.push(("ca".to_string(), self.current_stroke_alpha.into()));
+            gs_operations.push(("CA".to_string(), self.current_stroke_alpha.into()));
         }
 
         if self.changed_fields.contains(BLEND_MODE) {
@@ -753,10 +840,10 @@ impl Into<lopdf::Object> for ExtendedGraphicsState {
         }
 
         if self.changed_fields.contains(SOFT_MASK) {
-            if let Some(ref soft_mask) = self.soft_mask {
-
-            } else {
-                gs_operations.push(("SM".to_string(), Name("None".as_bytes().to_vec())));
+            // the soft mask dictionary references streams, it is added by
+            // `ExtendedGraphicsStateList::into_with_document()`
+            if self.soft_mask.is_none() {
+                gs_operations.push(("SMask".to_string(), Name("None".as_bytes().to_vec())));
             }
         }
 
@@ -1323,18 +1410,39 @@ pub enum RenderingIntent {
 
 /* ri name */
 impl RenderingIntent {
-    pub fn into_stream_op(self)
-    -> Vec<Operation>
+
+    /// Returns the name of the rendering intent, as written in the PDF
+    pub fn name(&self)
+    -> &'static str
     {
         use self::RenderingIntent::*;
-        let rendering_intent_string = match self {
+        match *self {
             AbsoluteColorimetric => "AbsoluteColorimetric",
             RelativeColorimetric => "RelativeColorimetric",
             Saturation => "Saturation",
             Perceptual => "Perceptual",
-        };
+        }
+    }
 
-        vec![ Operation::new("ri", vec![ Name(rendering_intent_string.as_bytes().to_vec()) ]) ]
+    /// Parses one of the four standard rendering intent names (PDF Reference, table 4.20),
+    /// returns `None` for other names. Viewers treat unknown names as `RelativeColorimetric`.
+    pub fn from_name(name: &str)
+    -> Option<Self>
+    {
+        use self::RenderingIntent::*;
+        match name {
+            "AbsoluteColorimetric" => Some(AbsoluteColorimetric),
+            "RelativeColorimetric" => Some(RelativeColorimetric),
+            "Saturation" => Some(Saturation),
+            "Perceptual" => Some(Perceptual),
+            _ => None,
+        }
+    }
+
+    pub fn into_stream_op(self)
+    -> Vec<Operation>
+    {
+        vec![ Operation::new("ri", vec![ Name(self.name().as_bytes().to_vec()) ]) ]
     }
 }
 
@@ -1344,15 +1452,7 @@ impl Into<lopdf::Object> for RenderingIntent {
     fn into(self)
     -> lopdf::Object
     {
-        use self::RenderingIntent::*;
-        let rendering_intent_string = match self {
-            AbsoluteColorimetric => "AbsoluteColorimetric",
-            RelativeColorimetric => "RelativeColorimetric",
-            Saturation => "Saturation",
-            Perceptual => "Perceptual",
-        };
-
-        Name(rendering_intent_string.as_bytes().to_vec())
+        Name(self.name().as_bytes().to_vec())
     }
 }
 
@@ -1362,12 +1462,68 @@ impl Into<lopdf::Object> for RenderingIntent {
 /// Can also be used for Vignettes, etc.
 /// Beware of color spaces!
 /// __See PDF Reference Page 545__ - Soft masks
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub struct SoftMask {
-    /// The data to be used as a soft mask
-    data: Vec<u8>,
-    /// Bits per component (1 for black / white, 8 for greyscale, up to 16)
-    bits_per_component: u8,
+    /// The form to be used as a soft mask (see `define_form()`), usually draws a greyscale image
+    form: XObjectIndex,
+    /// Area of the page that the form is drawn in, everything outside of it is masked out
+    rect: Rect,
+    /// How the mask values are computed from the form
+    function: SoftMaskFunction,
+}
+
+impl SoftMask {
+
+    /// Creates a soft mask from a form (see `define_form()`), which is drawn as is, clipped
+    /// to `rect`. The form and the rectangle are in the coordinate system that is active
+    /// when the graphics state is set with the `gs` operator.
+    #[inline]
+    pub fn new(form: &XObjectIndex, rect: Rect, function: SoftMaskFunction)
+    -> Self
+    {
+        Self { form: *form, rect, function }
+    }
+
+    /// Adds the transparency group that draws the form to the document and returns the soft
+    /// mask dictionary. The form is looked up in the `cache`, it is written before the pages.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, cache: &XObjectCache)
+    -> lopdf::Object
+    {
+        use lopdf::Object::*;
+        use lopdf::content::{Content, Operation};
+        use std::iter::FromIterator;
+
+        // the form is missing if it was removed by `restore()` (or is from another document), everything is masked out
+        let mask_form = cache.forms.get(&self.form);
+        let operations = match mask_form {
+            Some(_) => vec![Operation::new("Do", vec![Name("Fm0".into())])],
+            None => Vec::new(),
+        };
+
+        let group = lopdf::Dictionary::from_iter(vec![
+            ("S", Name("Transparency".into())),
+            ("CS", Name("DeviceGray".into())),
+        ]);
+        let resources = mask_form.map(|id| lopdf::Dictionary::from_iter(vec![
+            ("XObject", Dictionary(lopdf::Dictionary::from_iter(vec![("Fm0", Reference(*id))]))),
+        ])).unwrap_or_default();
+        let form = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("XObject".into())),
+            ("Subtype", Name("Form".into())),
+            ("BBox", self.rect.into()),
+            ("Group", Dictionary(group)),
+            ("Resources", Dictionary(resources)),
+        ]);
+        // writing into a Vec<u8> can't fail
+        let form_id = doc.add_object(lopdf::Stream::new(form, Content { operations }.encode().unwrap()));
+
+        let function: &'static str = self.function.into();
+        Dictionary(lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("Mask".into())),
+            ("S", Name(function.into())),
+            ("G", Reference(form_id)),
+        ]))
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -1376,17 +1532,30 @@ pub enum SoftMaskFunction {
     /// In this function, the old (backdrop) color does not contribute to the result.
     /// This is the easies function, but may look bad at edges.
     GroupAlpha,
-    //
+    /// The mask values are the luminosity of the image: white is opaque, black is transparent
     GroupLuminosity,
 
 }
+
+impl From<SoftMaskFunction> for &'static str {
+    fn from(function: SoftMaskFunction)
+    -> Self
+    {
+        match function {
+            SoftMaskFunction::GroupAlpha => "Alpha",
+            SoftMaskFunction::GroupLuminosity => "Luminosity",
+        }
+    }
+}
+
 /// __See PDF Reference Page 216__ - Line join style
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum LineJoinStyle {
     /// Miter join. The outer edges of the strokes for the two segments are extended
     /// until they meet at an angle, as in a picture frame. If the segments meet at too
     /// sharp an angle (as defined by the miter limit parameter—see “Miter Limit,”
-    /// above), a bevel join is used instead.
+    /// above), a bevel join is used instead. The limit is set with
+    /// `PdfLayerReference::set_miter_limit()`.
     Miter,
     /// Round join. An arc of a circle with a diameter equal to the line width is drawn
     /// around the point where the two segments meet, connecting the outer edges of
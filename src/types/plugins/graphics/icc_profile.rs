@@ -37,6 +37,26 @@ impl IccProfile {
         }
     }
 
+    /// The sRGB color space (IEC 61966-2-1) as an ICC profile. The profile is generated,
+    /// so it doesn't need to be shipped with the application.
+    pub fn srgb()
+    -> Self
+    {
+        Self::new(srgb_profile(), IccProfileType::Rgb)
+    }
+
+    /// Hashes the profile data, used for writing identical profiles only once
+    pub(crate) fn content_hash(&self)
+    -> u64
+    {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        self.icc.hash(&mut hasher);
+        hasher.finish()
+    }
+
     /// Does the ICC profile have an alternate version (such as "DeviceCMYk")?
     #[inline]
     pub fn with_alternate_profile(mut self, has_alternate: bool)
@@ -135,3 +155,129 @@ impl IccProfileList {
         IccProfileRef::new(cur_len)
     }
 }
+
+/// Number of entries of the tone curves of the sRGB profile
+const SRGB_CURVE_ENTRIES: usize = 1024;
+
+/// Generates an ICC (version 2.1) display profile for sRGB with the D50-adapted primaries
+/// and the sRGB tone curve as a table
+fn srgb_profile()
+-> Vec<u8>
+{
+    fn s15_fixed16(value: f64) -> [u8; 4] { ((value * 65536.0).round() as i32).to_be_bytes() }
+    fn xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for value in &[x, y, z] { tag.extend_from_slice(&s15_fixed16(*value)); }
+        tag
+    }
+
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend_from_slice(&5_u32.to_be_bytes());
+    desc.extend_from_slice(b"sRGB\0");
+    desc.extend_from_slice(&[0; 8 + 3 + 67]);
+
+    let mut cprt = b"text\0\0\0\0".to_vec();
+    cprt.extend_from_slice(b"No copyright, use freely\0");
+
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend_from_slice(&(SRGB_CURVE_ENTRIES as u32).to_be_bytes());
+    for idx in 0..SRGB_CURVE_ENTRIES {
+        let value = idx as f64 / (SRGB_CURVE_ENTRIES - 1) as f64;
+        let linear = if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) };
+        curve.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc),
+        (b"cprt", cprt),
+        (b"wtpt", xyz(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", xyz(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", xyz(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", xyz(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", curve),
+    ];
+
+    // the three tone curves share the same data
+    let tag_count = tags.len() + 2;
+    let mut table = (tag_count as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + tag_count * 12;
+    for (signature, tag) in &tags {
+        let offset = (data_start + data.len()) as u32;
+        let entries: &[&[u8; 4]] = if *signature == b"rTRC" { &[b"rTRC", b"gTRC", b"bTRC"] } else { &[*signature] };
+        for entry in entries {
+            table.extend_from_slice(*entry);
+            table.extend_from_slice(&offset.to_be_bytes());
+            table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        }
+        data.extend_from_slice(tag);
+        while data.len() % 4 != 0 { data.push(0); }
+    }
+
+    let mut profile = vec![0; 128];
+    let size = (128 + table.len() + data.len()) as u32;
+    profile[0..4].copy_from_slice(&size.to_be_bytes());
+    profile[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    profile[12..16].copy_from_slice(b"mntr");
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[20..24].copy_from_slice(b"XYZ ");
+    profile[36..40].copy_from_slice(b"acsp");
+    profile[68..72].copy_from_slice(&s15_fixed16(0.9642));
+    profile[72..76].copy_from_slice(&s15_fixed16(1.0));
+    profile[76..80].copy_from_slice(&s15_fixed16(0.8249));
+    profile.extend(table);
+    profile.extend(data);
+    profile
+}
+
+/// Replaces the `/DeviceRGB` color space of the images in the document, which have no
+/// ICC profile, with the ICC profile (see `PdfDocumentReference::set_assume_srgb()`)
+pub(crate) fn tag_untagged_rgb_images(doc: &mut lopdf::Document, profile: IccProfile)
+{
+    use lopdf::Object::*;
+
+    let is_untagged_rgb_image = |dict: &lopdf::Dictionary| {
+        dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(&b"Image"[..]) &&
+        dict.get(b"ColorSpace").and_then(|s| s.as_name()).ok() == Some(&b"DeviceRGB"[..])
+    };
+
+    let images = doc.objects.iter().filter(|(_, object)| match **object {
+        Stream(ref stream) => is_untagged_rgb_image(&stream.dict),
+        // placeholder dictionaries of streamed images
+        Dictionary(ref dict) => is_untagged_rgb_image(dict),
+        _ => false,
+    }).map(|(id, _)| *id).collect::<Vec<_>>();
+
+    if images.is_empty() {
+        return;
+    }
+
+    let stream: lopdf::Stream = profile.into();
+    let profile_id = doc.add_object(stream);
+    let color_space = Array(vec![Name("ICCBased".into()), Reference(profile_id)]);
+
+    for id in images {
+        match doc.objects.get_mut(&id) {
+            Some(&mut Stream(ref mut stream)) => stream.dict.set("ColorSpace", color_space.clone()),
+            Some(&mut Dictionary(ref mut dict)) => dict.set("ColorSpace", color_space.clone()),
+            _ => { },
+        }
+    }
+}
+
+#[test]
+fn test_srgb_profile() {
+    let profile = srgb_profile();
+    assert_eq!(u32::from_be_bytes([profile[0], profile[1], profile[2], profile[3]]) as usize, profile.len());
+    assert_eq!(&profile[36..40], b"acsp");
+
+    // 9 tags, the tone curves share one table that ends at the end of the profile
+    assert_eq!(&profile[128..132], &[0, 0, 0, 9]);
+    let trc = 132 + 6 * 12;
+    assert_eq!(&profile[trc..trc + 4], b"rTRC");
+    assert_eq!(profile[trc + 4..trc + 8], profile[trc + 16..trc + 20]);
+    let offset = u32::from_be_bytes([profile[trc + 4], profile[trc + 5], profile[trc + 6], profile[trc + 7]]) as usize;
+    assert_eq!(&profile[offset..offset + 4], b"curv");
+    assert_eq!(profile.len(), offset + 12 + SRGB_CURVE_ENTRIES * 2);
+    assert_eq!(&profile[profile.len() - 2..], &[0xff, 0xff]);
+}
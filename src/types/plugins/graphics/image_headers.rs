@@ -0,0 +1,156 @@
+//! Reads the size of pre-encoded images (JPEG 2000, JBIG2) from their headers,
+//! so that the data can be embedded without decoding it
+
+/// Size and components of a JPEG 2000 image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Jpeg2000Header {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// Number of color components
+    pub(crate) components: usize,
+    /// Bits per component
+    pub(crate) bits: u8,
+}
+
+/// Signature box at the start of a JP2 file
+const JP2_SIGNATURE: [u8; 12] = [0x00, 0x00, 0x00, 0x0C, 0x6A, 0x50, 0x20, 0x20, 0x0D, 0x0A, 0x87, 0x0A];
+/// Start of codestream and image and tile size markers of a raw JPEG 2000 codestream
+const J2K_SOC_SIZ: [u8; 4] = [0xFF, 0x4F, 0xFF, 0x51];
+/// Segment type of the page information segment of JBIG2
+const JBIG2_PAGE_INFORMATION: u8 = 48;
+
+fn u16_at(data: &[u8], offset: usize)
+-> Option<usize>
+{
+    data.get(offset..offset + 2).map(|b| (usize::from(b[0]) << 8) | usize::from(b[1]))
+}
+
+fn u32_at(data: &[u8], offset: usize)
+-> Option<usize>
+{
+    data.get(offset..offset + 4).map(|b| b.iter().fold(0, |value, byte| (value << 8) | usize::from(*byte)))
+}
+
+/// Reads the header of a JP2 file or a raw JPEG 2000 codestream (J2K)
+pub(crate) fn jpeg2000_header(data: &[u8])
+-> Option<Jpeg2000Header>
+{
+    if data.starts_with(&J2K_SOC_SIZ) {
+        // SIZ: Lsiz, Rsiz, Xsiz, Ysiz, XOsiz, YOsiz, 4 tile sizes, Csiz, Ssiz of the first component
+        let siz = 4;
+        let (x, y) = (u32_at(data, siz + 4)?, u32_at(data, siz + 8)?);
+        let (x_offset, y_offset) = (u32_at(data, siz + 12)?, u32_at(data, siz + 16)?);
+        let components = u16_at(data, siz + 36)?;
+        let bits = (data.get(siz + 38)? & 0x7F) + 1;
+        return Some(Jpeg2000Header { width: x.checked_sub(x_offset)?, height: y.checked_sub(y_offset)?, components, bits });
+    }
+
+    if !data.starts_with(&JP2_SIGNATURE) {
+        return None;
+    }
+
+    // the image header box (ihdr) is the first box in the JP2 header box (jp2h)
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let (length, kind) = (u32_at(data, offset)?, &data[offset + 4..offset + 8]);
+        let (header_length, length) = match length {
+            0 => (8, data.len() - offset),
+            1 => (16, u32_at(data, offset + 12)?),
+            _ => (8, length),
+        };
+
+        match kind {
+            b"jp2h" => offset += header_length,
+            b"ihdr" => {
+                let ihdr = offset + header_length;
+                return Some(Jpeg2000Header {
+                    height: u32_at(data, ihdr)?,
+                    width: u32_at(data, ihdr + 4)?,
+                    components: u16_at(data, ihdr + 8)?,
+                    bits: (data.get(ihdr + 10)? & 0x7F) + 1,
+                });
+            },
+            _ if length < header_length => return None,
+            _ => offset += length,
+        }
+    }
+
+    None
+}
+
+/// Reads the size of the page from the page information segment of JBIG2 data
+/// in the embedded stream format (the segments without the file header)
+pub(crate) fn jbig2_size(data: &[u8])
+-> Option<(usize, usize)>
+{
+    let mut offset = 0;
+    while offset < data.len() {
+        let number = u32_at(data, offset)?;
+        let flags = *data.get(offset + 4)?;
+        let mut position = offset + 5;
+
+        // referred-to segments: up to 4 in the short form, the long form has a 29 bit count
+        let referred = *data.get(position)?;
+        let referred_count = if referred >> 5 == 7 {
+            let count = u32_at(data, position)? & 0x1FFF_FFFF;
+            position += 4 + (count + 1).div_ceil(8);
+            count
+        } else {
+            position += 1;
+            usize::from(referred >> 5)
+        };
+        let number_size = if number <= 256 { 1 } else if number <= 65536 { 2 } else { 4 };
+        position += referred_count * number_size;
+
+        // page association, 4 bytes if bit 6 of the flags is set
+        position += if flags & 0x40 != 0 { 4 } else { 1 };
+
+        let data_length = u32_at(data, position)?;
+        position += 4;
+
+        if flags & 0x3F == JBIG2_PAGE_INFORMATION {
+            return Some((u32_at(data, position)?, u32_at(data, position + 4)?));
+        }
+
+        // the length of an immediate generic region can be unknown (0xffffffff)
+        if data_length == 0xFFFF_FFFF {
+            return None;
+        }
+        offset = position + data_length;
+    }
+
+    None
+}
+
+#[test]
+fn test_jpeg2000_header() {
+    // JP2 file: signature, file type box and header box with an image header of 300 x 200 px, 3 components, 8 bit
+    let mut jp2 = JP2_SIGNATURE.to_vec();
+    jp2.extend_from_slice(&[0, 0, 0, 20, b'f', b't', b'y', b'p', b'j', b'p', b'2', b' ', 0, 0, 0, 0, b'j', b'p', b'2', b' ']);
+    jp2.extend_from_slice(&[0, 0, 0, 30, b'j', b'p', b'2', b'h', 0, 0, 0, 22, b'i', b'h', b'd', b'r']);
+    jp2.extend_from_slice(&[0, 0, 0, 200, 0, 0, 1, 44, 0, 3, 7, 7, 0, 0]);
+    assert_eq!(jpeg2000_header(&jp2), Some(Jpeg2000Header { width: 300, height: 200, components: 3, bits: 8 }));
+
+    // codestream with a 10 px offset, 1 component with 1 bit
+    let mut j2k = J2K_SOC_SIZ.to_vec();
+    j2k.extend_from_slice(&[0, 41, 0, 0, 0, 0, 0, 110, 0, 0, 0, 60, 0, 0, 0, 10, 0, 0, 0, 10]);
+    j2k.extend_from_slice(&[0, 0, 0, 100, 0, 0, 0, 50, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1]);
+    assert_eq!(jpeg2000_header(&j2k), Some(Jpeg2000Header { width: 100, height: 50, components: 1, bits: 1 }));
+
+    assert_eq!(jpeg2000_header(b"not an image"), None);
+}
+
+#[test]
+fn test_jbig2_size() {
+    // page information segment: number 0, type 48, no referred-to segments, page 1, 19 bytes of data
+    let mut data = vec![0, 0, 0, 0, 48, 0, 1, 0, 0, 0, 19];
+    data.extend_from_slice(&[0, 0, 9, 0xB0, 0, 0, 0x0C, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0]);
+    assert_eq!(jbig2_size(&data), Some((2480, 3200)));
+
+    // the page information is found after other segments (a segment referring to segment 0)
+    let mut with_symbols = vec![0, 0, 0, 1, 0, 0x20, 0, 1, 0, 0, 0, 2, 0xAA, 0xBB];
+    with_symbols.extend_from_slice(&data);
+    assert_eq!(jbig2_size(&with_symbols), Some((2480, 3200)));
+
+    assert_eq!(jbig2_size(&[]), None);
+}
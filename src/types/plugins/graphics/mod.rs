@@ -14,6 +14,9 @@ pub mod extgstate;
 pub mod xobject;
 pub mod pattern;
 pub mod pdf_resources;
+pub mod raster;
+pub mod compression;
+pub(crate) mod image_headers;
 
 pub use self::ctm::*;
 pub use self::ocg::*;
@@ -23,3 +26,5 @@ pub use self::extgstate::*;
 pub use self::xobject::*;
 pub use self::pattern::*;
 pub use self::pdf_resources::*;
+pub use self::raster::*;
+pub use self::compression::*;
@@ -5,6 +5,8 @@ pub struct OCGList {
     /// The reference to the layer as well as a reference to the
     /// OCG dictionary
     pub(crate) layers: Vec<(OCGRef, lopdf::Object)>,
+    /// Index of the first name (`MC0`), so that the names are unique in the whole document
+    pub(crate) first_index: usize,
 }
 
 impl OCGList {
@@ -20,7 +22,7 @@ impl OCGList {
     -> OCGRef
     {
         let len = self.layers.len();
-        let ocg_ref = OCGRef::new(len);
+        let ocg_ref = OCGRef::new(self.first_index + len);
         self.layers.push((ocg_ref.clone(), obj));
         ocg_ref
     }
@@ -57,3 +59,70 @@ impl OCGRef {
         }
     }
 }
+
+/// Alternative configurations of the layers (`/Configs`) and groups of layers of which only one
+/// is visible at a time (`/RBGroups`). Layers are identified by `(page index, layer index)`.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct OCConfigurations {
+    /// Name of each configuration and the layers that are visible in it
+    pub(crate) configurations: Vec<(String, Vec<(usize, usize)>)>,
+    /// Layers that behave like radio buttons in the layers panel of the viewer
+    pub(crate) radio_groups: Vec<Vec<(usize, usize)>>,
+}
+
+impl OCConfigurations {
+
+    /// Returns the OCGs of the layers, `ocgs` are the OCGs of the layers of each page.
+    /// Layers that don't exist are skipped.
+    fn resolve(layers: &[(usize, usize)], ocgs: &[Vec<lopdf::Object>])
+    -> Vec<lopdf::Object>
+    {
+        layers.iter().filter_map(|&(page, layer)| ocgs.get(page).and_then(|p| p.get(layer)).cloned()).collect()
+    }
+
+    /// The `/RBGroups` array, an array of OCGs for each group
+    pub(crate) fn radio_button_groups(&self, ocgs: &[Vec<lopdf::Object>])
+    -> Vec<lopdf::Object>
+    {
+        self.radio_groups.iter().map(|group| lopdf::Object::Array(Self::resolve(group, ocgs))).collect()
+    }
+
+    /// Layers that are initially hidden: all layers of the radio groups except the first one
+    pub(crate) fn initially_hidden(&self, ocgs: &[Vec<lopdf::Object>])
+    -> Vec<lopdf::Object>
+    {
+        self.radio_groups.iter().flat_map(|group| Self::resolve(group.get(1..).unwrap_or(&[]), ocgs)).collect()
+    }
+
+    /// The optional content configuration dictionaries for `/Configs`, they show the
+    /// layers in the order `order` like the default configuration
+    pub(crate) fn configuration_dictionaries(&self, ocgs: &[Vec<lopdf::Object>], order: &[lopdf::Object])
+    -> Vec<lopdf::Dictionary>
+    {
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
+        use utils::pdf_text_string;
+
+        self.configurations.iter().map(|(name, visible)| lopdf::Dictionary::from_iter(vec![
+            ("Name", pdf_text_string(name)),
+            ("BaseState", Name("OFF".into())),
+            ("ON", Array(Self::resolve(visible, ocgs))),
+            ("Order", Array(order.to_vec())),
+            ("RBGroups", Array(self.radio_button_groups(ocgs))),
+        ])).collect()
+    }
+}
+
+#[test]
+fn test_oc_configurations() {
+    use lopdf::Object::Reference;
+
+    let ocgs = vec![vec![Reference((1, 0)), Reference((2, 0))], vec![Reference((3, 0))]];
+    let configurations = OCConfigurations {
+        configurations: vec![],
+        radio_groups: vec![vec![(0, 1), (1, 0), (1, 5)]],
+    };
+    assert_eq!(format!("{:?}", configurations.radio_button_groups(&ocgs)),
+               format!("{:?}", vec![lopdf::Object::Array(vec![Reference((2, 0)), Reference((3, 0))])]));
+    assert_eq!(format!("{:?}", configurations.initially_hidden(&ocgs)), format!("{:?}", vec![Reference((3, 0))]));
+}
@@ -1,18 +1,153 @@
 use lopdf;
 use std::collections::HashMap;
+use indices::XObjectIndex;
+use types::plugins::graphics::xobject::XObjectCache;
+use {Color, Mm, Point, Pt};
 
-/// __STUB__
-#[derive(Default, Debug, Copy, Clone)]
+/// Pattern, which can be used like a color to fill an area
+#[derive(Debug, Clone)]
 pub struct Pattern {
+    kind: PatternKind,
+}
+
+#[derive(Debug, Clone)]
+enum PatternKind {
+    /// Tiling pattern, painting a form in each tile
+    Tiling {
+        /// Form that is painted in each tile, see `define_form()`
+        form: XObjectIndex,
+        /// Width of one tile
+        tile_width: Pt,
+        /// Height of one tile
+        tile_height: Pt,
+    },
+    /// Shading pattern, painting a gradient
+    Gradient(LinearGradient),
+}
+
+/// Linear (axial) color gradient from `start` to `end`. The colors are extended beyond the
+/// two points, perpendicular to the line between them. The points are in the coordinates of
+/// the page, the transformations of the layer don't change the gradient.
+///
+/// Both colors must be in the same color space (e.g. both `Color::Rgb`).
+#[derive(Debug, Clone)]
+pub struct LinearGradient {
+    /// Point where the gradient starts, with `start_color`
+    pub start: Point,
+    /// Point where the gradient ends, with `end_color`
+    pub end: Point,
+    /// Color at the start
+    pub start_color: Color,
+    /// Color at the end
+    pub end_color: Color,
+}
+
+impl LinearGradient {
+    /// Creates a new gradient from `start_color` at `start` to `end_color` at `end`
+    pub fn new(start: Point, end: Point, start_color: Color, end_color: Color)
+    -> Self
+    {
+        Self { start, end, start_color, end_color }
+    }
+
+    /// Returns the axial shading dictionary of the gradient
+    fn into_shading(self)
+    -> lopdf::Dictionary
+    {
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
 
+        let color_space = match self.start_color {
+            Color::Rgb(_) => "DeviceRGB",
+            Color::Cmyk(_) | Color::SpotColor(_) => "DeviceCMYK",
+            Color::Greyscale(_) => "DeviceGray",
+        };
+
+        // exponential interpolation with an exponent of 1, i.e. linear
+        let function = lopdf::Dictionary::from_iter(vec![
+            ("FunctionType", Integer(2)),
+            ("Domain", Array(vec![0.into(), 1.into()])),
+            ("C0", Array(self.start_color.into_vec().into_iter().map(Real).collect())),
+            ("C1", Array(self.end_color.into_vec().into_iter().map(Real).collect())),
+            ("N", Integer(1)),
+        ]);
+
+        lopdf::Dictionary::from_iter(vec![
+            ("ShadingType", Integer(2)),
+            ("ColorSpace", Name(color_space.into())),
+            ("Coords", Array(vec![self.start.x.into(), self.start.y.into(), self.end.x.into(), self.end.y.into()])),
+            ("Function", Dictionary(function)),
+            ("Extend", Array(vec![Boolean(true), Boolean(true)])),
+        ])
+    }
 }
 
 impl Pattern {
-    /// Creates a new Pattern
-    pub fn new()
+    /// Creates a new tiling pattern, which draws the form (see `define_form()`) in each tile.
+    /// The tiles show the area from (0, 0) to (`tile_width`, `tile_height`) of the form.
+    pub fn new(form: &XObjectIndex, tile_width: Mm, tile_height: Mm)
+    -> Self
+    {
+        Self {
+            kind: PatternKind::Tiling {
+                form: *form,
+                tile_width: tile_width.into(),
+                tile_height: tile_height.into(),
+            },
+        }
+    }
+
+    /// Creates a new shading pattern, which paints the gradient
+    pub fn linear_gradient(gradient: LinearGradient)
     -> Self
     {
-        Self::default()
+        Self { kind: PatternKind::Gradient(gradient) }
+    }
+
+    /// Returns the pattern stream (tiling patterns) or dictionary (shading patterns). The
+    /// form of a tiling pattern is looked up in the `cache`, the forms are written before the pages.
+    fn into_with_document(self, cache: &XObjectCache)
+    -> lopdf::Object
+    {
+        use lopdf::Object::*;
+        use lopdf::content::{Content, Operation};
+        use std::iter::FromIterator;
+
+        let (form, tile_width, tile_height) = match self.kind {
+            PatternKind::Tiling { form, tile_width, tile_height } => (form, tile_width, tile_height),
+            PatternKind::Gradient(gradient) => {
+                return Dictionary(lopdf::Dictionary::from_iter(vec![
+                    ("Type", Name("Pattern".into())),
+                    ("PatternType", Integer(2)),
+                    ("Shading", Dictionary(gradient.into_shading())),
+                ]));
+            },
+        };
+
+        // the form is missing if it was removed by `restore()` (or is from another document), the tiles are empty then
+        let form_id = cache.forms.get(&form);
+        let operations = match form_id {
+            Some(_) => vec![Operation::new("Do", vec![Name("Fm0".into())])],
+            None => Vec::new(),
+        };
+        let resources = form_id.map(|id| lopdf::Dictionary::from_iter(vec![
+            ("XObject", Dictionary(lopdf::Dictionary::from_iter(vec![("Fm0", Reference(*id))]))),
+        ])).unwrap_or_default();
+
+        let dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("Pattern".into())),
+            // tiling pattern, colored, constant spacing
+            ("PatternType", Integer(1)),
+            ("PaintType", Integer(1)),
+            ("TilingType", Integer(1)),
+            ("BBox", Array(vec![0.into(), 0.into(), tile_width.into(), tile_height.into()])),
+            ("XStep", tile_width.into()),
+            ("YStep", tile_height.into()),
+            ("Resources", Dictionary(resources)),
+        ]);
+
+        // writing into a Vec<u8> can't fail
+        Stream(lopdf::Stream::new(dict, Content { operations }.encode().unwrap()))
     }
 }
 
@@ -56,13 +191,59 @@ impl PatternList {
         self.patterns.insert(pattern_ref.name.clone(), pattern);
         pattern_ref
     }
-}
 
-impl Into<lopdf::Dictionary> for PatternList {
-    fn into(self)
+    /// Returns the number of patterns in this list
+    #[inline]
+    pub fn len(&self)
+    -> usize
+    {
+        self.patterns.len()
+    }
+
+    /// Returns if the pattern list is empty
+    #[inline]
+    pub fn is_empty(&self)
+    -> bool
+    {
+        self.patterns.is_empty()
+    }
+
+    /// Removes all patterns that were added after the list had `len` entries
+    pub(crate) fn truncate(&mut self, len: usize)
+    {
+        for index in len..self.patterns.len() {
+            self.patterns.remove(&PatternRef::new(index).name);
+        }
+    }
+
+    /// Indices of the forms that the tiling patterns draw
+    pub(crate) fn forms(&self)
+    -> Vec<usize>
+    {
+        self.patterns.values().filter_map(|pattern| match pattern.kind {
+            PatternKind::Tiling { form, .. } => Some(form.0),
+            PatternKind::Gradient(_) => None,
+        }).collect()
+    }
+
+    /// Adds the patterns to the document and returns the `/Pattern` resource
+    /// dictionary, see `XObjectList::into_with_document`. The tiles of tiling patterns
+    /// are empty, use `PdfDocumentReference::save()` to write the forms they draw.
+    pub fn into_with_document(self, doc: &mut lopdf::Document)
+    -> lopdf::Dictionary
+    {
+        self.into_with_document_and_cache(doc, &XObjectCache::new())
+    }
+
+    /// Same as `into_with_document`, the forms of the tiling patterns are looked up in the `cache`
+    pub(crate) fn into_with_document_and_cache(self, doc: &mut lopdf::Document, cache: &XObjectCache)
     -> lopdf::Dictionary
     {
-        // todo
-        lopdf::Dictionary::new()
+        let mut patterns: Vec<(String, Pattern)> = self.patterns.into_iter().collect();
+        patterns.sort_by(|a, b| a.0.cmp(&b.0));
+        patterns.into_iter().map(|(name, pattern)| {
+            let pattern = pattern.into_with_document(cache);
+            (name, lopdf::Object::Reference(doc.add_object(pattern)))
+        }).collect()
     }
 }
@@ -1,8 +1,9 @@
 use lopdf;
+use types::plugins::graphics::xobject::{XObjectCache, StreamedXObjects, read_streamed_images};
 use {
     XObject, Pattern, ExtendedGraphicsState, ExtendedGraphicsStateList, 
     PatternRef, OCGRef, XObjectList, XObjectRef, ExtendedGraphicsStateRef,
-    OCGList, PatternList
+    OCGList, PatternList, Error
 };
 
 /// Struct for storing the PDF Resources, to be used on a PDF page
@@ -10,7 +11,7 @@ use {
 pub struct PdfResources {
     /// External graphics objects
     pub xobjects: XObjectList,
-    /// Patterns used on this page. Do not yet, use, placeholder.
+    /// Patterns used on this page
     pub patterns: PatternList,
     /// Graphics states used on this page
     pub graphics_states: ExtendedGraphicsStateList,
@@ -43,7 +44,7 @@ impl PdfResources {
         self.xobjects.add_xobject(xobj)
     }
 
-    /// __STUB__: Adds a pattern to the resources, to be used like a color
+    /// Adds a pattern to the resources, to be used like a color
     #[inline]
     pub fn add_pattern(&mut self, pattern: Pattern)
     -> PatternRef
@@ -51,11 +52,36 @@ impl PdfResources {
         self.patterns.add_pattern(pattern)
     }
 
+    /// Indices of the forms (see `define_form()`) that the patterns and soft masks draw
+    pub(crate) fn drawn_forms(&self)
+    -> Vec<usize>
+    {
+        let mut forms = self.patterns.forms();
+        forms.extend(self.graphics_states.forms());
+        forms
+    }
+
     /// See `XObject::Into_with_document`.
     /// The resources also need access to the layers (the optional content groups), this should be a
     /// `Vec<lopdf::Object::Reference>` (to the actual OCG groups, which are added on the document level)
-    #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
+    ///
+    /// The data of streamed images is read into memory, fails if it can't be read completely.
     pub fn into_with_document_and_layers(self, doc: &mut lopdf::Document, layers: Vec<lopdf::Object>)
+    -> Result<(lopdf::Dictionary, Vec<OCGRef>), Error>
+    {
+        let mut streamed = StreamedXObjects::new();
+        let resources = self.into_with_document_layers_and_cache(doc, layers, &mut XObjectCache::new(), &mut streamed);
+        read_streamed_images(doc, streamed)?;
+        Ok(resources)
+    }
+
+    /// Same as `into_with_document_and_layers`, but deduplicated XObjects are looked up
+    /// in (and added to) the document-wide `xobject_cache`. Streamed images are collected
+    /// in `streamed`, see `XObjectList::into_with_document_and_cache`
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
+    pub(crate) fn into_with_document_layers_and_cache(self, doc: &mut lopdf::Document, layers: Vec<lopdf::Object>,
+                                                      xobject_cache: &mut XObjectCache,
+                                                      streamed: &mut StreamedXObjects)
     -> (lopdf::Dictionary, Vec<OCGRef>)
     {
             let mut dict = lopdf::Dictionary::new();
@@ -63,9 +89,9 @@ impl PdfResources {
             let mut ocg_dict = self.layers;
             let mut ocg_references = Vec::<OCGRef>::new();
 
-            let xobjects_dict: lopdf::Dictionary = self.xobjects.into_with_document(doc);
-            let patterns_dict: lopdf::Dictionary = self.patterns.into();
-            let graphics_state_dict: lopdf::Dictionary = self.graphics_states.into();
+            let xobjects_dict: lopdf::Dictionary = self.xobjects.into_with_document_and_cache(doc, xobject_cache, streamed);
+            let patterns_dict: lopdf::Dictionary = self.patterns.into_with_document_and_cache(doc, xobject_cache);
+            let graphics_state_dict: lopdf::Dictionary = self.graphics_states.into_with_document(doc, xobject_cache);
 
             if !layers.is_empty() {
 
@@ -95,3 +121,62 @@ impl PdfResources {
             return (dict, ocg_references);
     }
 }
+
+/// Merges the resource dictionary of a page into the `shared` resources (inherited from the
+/// `/Pages` node), if none of its resource names already stands for a different resource.
+/// Returns whether the resources were merged.
+pub(crate) fn merge_into_shared_resources(shared: &mut lopdf::Dictionary, resources: &lopdf::Dictionary)
+-> bool
+{
+    use lopdf::Object::Dictionary;
+
+    let same = |a: &lopdf::Object, b: &lopdf::Object| format!("{:?}", a) == format!("{:?}", b);
+    let compatible = resources.iter().all(|(category, value)| match (shared.get(category), value) {
+        (Ok(Dictionary(existing)), Dictionary(entries)) => entries.iter()
+            .all(|(name, entry)| existing.get(name).map(|existing| same(existing, entry)).unwrap_or(true)),
+        (Ok(existing), value) => same(existing, value),
+        (Err(_), _) => true,
+    });
+
+    if !compatible {
+        return false;
+    }
+
+    for (category, value) in resources.iter() {
+        let existing = shared.get_mut(category).ok();
+        match (existing, value) {
+            (Some(Dictionary(existing)), Dictionary(entries)) => {
+                for (name, entry) in entries.iter() {
+                    existing.set(name.clone(), entry.clone());
+                }
+            },
+            (Some(_), _) => { },
+            (None, value) => shared.set(category.clone(), value.clone()),
+        }
+    }
+
+    true
+}
+
+#[test]
+fn test_merge_into_shared_resources() {
+    use std::iter::FromIterator;
+    use lopdf::Object::{Dictionary, Reference};
+
+    let resources = |entries: Vec<(&str, lopdf::ObjectId)>| lopdf::Dictionary::from_iter(vec![
+        ("Font", Reference((1, 0))),
+        ("XObject", Dictionary(lopdf::Dictionary::from_iter(entries.into_iter().map(|(name, id)| (name, Reference(id)))))),
+    ]);
+
+    let mut shared = lopdf::Dictionary::new();
+    assert!(merge_into_shared_resources(&mut shared, &resources(vec![("X0", (2, 0))])));
+    // a second image is added to the shared images
+    assert!(merge_into_shared_resources(&mut shared, &resources(vec![("X0", (2, 0)), ("X1", (3, 0))])));
+    // the name X1 already stands for another image, so this page keeps its own resources
+    assert!(!merge_into_shared_resources(&mut shared, &resources(vec![("X1", (4, 0))])));
+
+    let xobjects = shared.get(b"XObject").unwrap().as_dict().unwrap();
+    assert_eq!(xobjects.len(), 2);
+    assert_eq!(xobjects.get(b"X1").unwrap().as_reference().unwrap(), (3, 0));
+    assert_eq!(shared.get(b"Font").unwrap().as_reference().unwrap(), (1, 0));
+}
@@ -0,0 +1,85 @@
+//! Hook for converting pages into images. printpdf doesn't contain a renderer,
+//! the rendering is done by a `RasterBackend` (for example a binding to pdfium).
+
+use {Error, Pt, Px};
+
+/// A page that should be rendered, handed to a `RasterBackend`
+#[derive(Debug, Copy, Clone)]
+pub struct RasterPage<'a> {
+    /// The complete, serialized document
+    pub pdf: &'a [u8],
+    /// Index of the page that should be rendered (zero-based)
+    pub page: usize,
+    /// Resolution of the image in dots per inch
+    pub dpi: f64,
+    /// Width of the image (the page width at the resolution)
+    pub width: Px,
+    /// Height of the image (the page height at the resolution)
+    pub height: Px,
+}
+
+/// Rendered page: 8 bit RGB pixels, row by row from the top left corner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RasterImage {
+    /// Width of the image in pixels
+    pub width: Px,
+    /// Height of the image in pixels
+    pub height: Px,
+    /// `width * height * 3` bytes of pixel data
+    pub pixels: Vec<u8>,
+}
+
+/// Renders pages into images, see `PdfPageReference::rasterize()`.
+/// Errors of the backend should be returned as `Error::Rasterization`.
+pub trait RasterBackend {
+    /// Renders the page `page.page` of the document `page.pdf`
+    fn rasterize(&self, page: RasterPage)
+    -> Result<RasterImage, Error>;
+}
+
+/// Reference backend, which doesn't render anything: it returns a white image of the size
+/// of the page. Useful for testing code that uses the hook without an actual renderer.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BlankRasterBackend;
+
+impl RasterBackend for BlankRasterBackend {
+    fn rasterize(&self, page: RasterPage)
+    -> Result<RasterImage, Error>
+    {
+        Ok(RasterImage {
+            width: page.width,
+            height: page.height,
+            pixels: vec![255; page.width.0 * page.height.0 * 3],
+        })
+    }
+}
+
+impl RasterImage {
+
+    /// Converts the image into an `image::RgbImage`, for saving it or comparing it with
+    /// other images. Returns `None` if the number of pixels doesn't match the size.
+    #[cfg(feature = "embedded_images")]
+    pub fn into_rgb_image(self)
+    -> Option<::image::RgbImage>
+    {
+        ::image::RgbImage::from_raw(self.width.0 as u32, self.height.0 as u32, self.pixels)
+    }
+}
+
+/// Size in pixels of a length in pt at the given resolution
+#[inline]
+pub(crate) fn pixels(length: Pt, dpi: f64)
+-> Px
+{
+    Px((length.0 / 72.0 * dpi).round().max(0.0) as usize)
+}
+
+#[test]
+fn test_blank_raster_backend() {
+    let page = RasterPage { pdf: &[], page: 0, dpi: 72.0, width: pixels(Pt(595.0), 72.0), height: pixels(Pt(842.0), 72.0) };
+    let image = BlankRasterBackend.rasterize(page).unwrap();
+    assert_eq!(image.width, Px(595));
+    assert_eq!(image.height, Px(842));
+    assert_eq!(image.pixels.len(), 595 * 842 * 3);
+    assert_eq!(pixels(Pt(72.0), 300.0), Px(300));
+}
@@ -0,0 +1,133 @@
+//! Direction of text and reordering of right-to-left text (simplified Unicode bidi algorithm)
+
+/// Direction of the text written with `PdfLayerReference::write_text()`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TextDirection {
+    /// Left-to-right, the text is written in the order of the characters
+    #[default]
+    Ltr,
+    /// Right-to-left (Arabic, Hebrew). The text is reordered into the visual order,
+    /// embedded left-to-right runs (latin words, numbers) keep their order.
+    Rtl,
+}
+
+/// Bidi class of a character, reduced to the classes that matter for ordering a single line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BidiClass {
+    /// Strong left-to-right
+    L,
+    /// Strong right-to-left
+    R,
+    /// Number, written left-to-right but doesn't change the direction of its surroundings
+    EN,
+    /// Neutral (whitespace, punctuation), takes the direction of its surroundings
+    N,
+}
+
+fn bidi_class(c: char)
+-> BidiClass
+{
+    match c as u32 {
+        0x0030..=0x0039 | 0x0660..=0x0669 | 0x06F0..=0x06F9 => BidiClass::EN,
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF => BidiClass::R,
+        _ if c.is_alphabetic() => BidiClass::L,
+        _ => BidiClass::N,
+    }
+}
+
+/// Mirrored character, used for characters in right-to-left runs
+fn mirror(c: char)
+-> char
+{
+    match c {
+        '(' => ')', ')' => '(',
+        '[' => ']', ']' => '[',
+        '{' => '}', '}' => '{',
+        '<' => '>', '>' => '<',
+        '«' => '»', '»' => '«',
+        _ => c,
+    }
+}
+
+/// Reorders a line of text from the logical order (the order in which it is typed)
+/// into the visual order (left to right on the page). For `TextDirection::Ltr` the
+/// text is returned unchanged.
+///
+/// __NOTE__: This is a simplified version of the Unicode bidi algorithm (no explicit
+/// embeddings or isolates) and it doesn't shape Arabic text - use presentation
+/// forms (U+FB50 - U+FEFF) for the contextual forms of Arabic letters.
+pub fn reorder_text(text: &str, direction: TextDirection)
+-> String
+{
+    if direction == TextDirection::Ltr {
+        return text.to_string();
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut classes: Vec<BidiClass> = chars.iter().map(|c| bidi_class(*c)).collect();
+
+    // numbers following left-to-right text belong to the left-to-right text (W7).
+    // The line has a right-to-left base direction, so the start counts as R
+    let mut previous_strong = BidiClass::R;
+    for class in classes.iter_mut() {
+        match *class {
+            BidiClass::L | BidiClass::R => previous_strong = *class,
+            BidiClass::EN if previous_strong == BidiClass::L => *class = BidiClass::L,
+            _ => { },
+        }
+    }
+
+    // neutrals between two characters of the same direction take that direction,
+    // otherwise the base direction (N1, N2). Numbers count as R here
+    let strong = |class: BidiClass| if class == BidiClass::EN { BidiClass::R } else { class };
+    let mut idx = 0;
+    while idx < classes.len() {
+        if classes[idx] != BidiClass::N {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        while idx < classes.len() && classes[idx] == BidiClass::N {
+            idx += 1;
+        }
+        let before = if start == 0 { BidiClass::R } else { strong(classes[start - 1]) };
+        let after = if idx == classes.len() { BidiClass::R } else { strong(classes[idx]) };
+        let resolved = if before == after { before } else { BidiClass::R };
+        for class in &mut classes[start..idx] {
+            *class = resolved;
+        }
+    }
+
+    // embedding levels for a right-to-left paragraph (I2): R is 1, L and numbers are 2
+    let levels: Vec<u8> = classes.iter().map(|c| if *c == BidiClass::R { 1 } else { 2 }).collect();
+
+    // reverse every sequence at the level or higher, from the highest level to the lowest odd level (L2)
+    let mut visual: Vec<(char, u8)> = chars.into_iter().zip(levels).collect();
+    for level in (1..=2).rev() {
+        let mut idx = 0;
+        while idx < visual.len() {
+            if visual[idx].1 < level {
+                idx += 1;
+                continue;
+            }
+            let start = idx;
+            while idx < visual.len() && visual[idx].1 >= level {
+                idx += 1;
+            }
+            visual[start..idx].reverse();
+        }
+    }
+
+    visual.into_iter().map(|(c, level)| if level % 2 == 1 { mirror(c) } else { c }).collect()
+}
+
+#[test]
+fn test_reorder_text() {
+    assert_eq!(reorder_text("abc (def)", TextDirection::Ltr), "abc (def)");
+    assert_eq!(reorder_text("שלום", TextDirection::Rtl), "םולש");
+    // numbers and latin words keep their order
+    assert_eq!(reorder_text("שלום 123", TextDirection::Rtl), "123 םולש");
+    assert_eq!(reorder_text("שלום world!", TextDirection::Rtl), "!world םולש");
+    // brackets are mirrored
+    assert_eq!(reorder_text("(שלום)", TextDirection::Rtl), "(םולש)");
+}
@@ -0,0 +1,678 @@
+//! Bounding box of the content of a layer, computed by interpreting its operations
+
+use lopdf::content::Operation;
+use lopdf::{Object, StringFormat};
+use {Cmyk, Color, ExternalFont, Font, FontList, GraphicsState as LayerState, Greyscale, IndirectFontRef, Pt, Rect, Rgb};
+use types::plugins::graphics::two_dimensional::afm::BuiltinMetrics;
+use types::plugins::graphics::two_dimensional::font::FontFace;
+
+/// Text in fonts that aren't in the font list (and characters without a glyph in a builtin
+/// font) is measured with an average character width (in em)
+const BUILTIN_ADVANCE: f64 = 0.5;
+/// Ascent of fonts that aren't in the font list (in em)
+const BUILTIN_ASCENT: f64 = 0.8;
+/// Descent of fonts that aren't in the font list (in em)
+const BUILTIN_DESCENT: f64 = -0.2;
+
+/// Transformation matrix `[a b c d e f]`, as in the `cm` operator
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Returns `first × second`, the transformation that applies `first` and then `second`
+fn multiply(first: &Matrix, second: &Matrix)
+-> Matrix
+{
+    [
+        first[0] * second[0] + first[1] * second[2],
+        first[0] * second[1] + first[1] * second[3],
+        first[2] * second[0] + first[3] * second[2],
+        first[2] * second[1] + first[3] * second[3],
+        first[4] * second[0] + first[5] * second[2] + second[4],
+        first[4] * second[1] + first[5] * second[3] + second[5],
+    ]
+}
+
+fn transform(matrix: &Matrix, x: f64, y: f64)
+-> (f64, f64)
+{
+    (matrix[0] * x + matrix[2] * y + matrix[4], matrix[1] * x + matrix[3] * y + matrix[5])
+}
+
+/// The parts of the graphics state that change the extents of the content, and the colors
+#[derive(Debug, Clone)]
+struct GraphicsState {
+    ctm: Matrix,
+    line_width: f64,
+    /// `None` for colors in other color spaces than the device color spaces (patterns, ...)
+    fill_color: Option<Color>,
+    outline_color: Option<Color>,
+    font: Option<String>,
+    font_size: f64,
+    leading: f64,
+    character_spacing: f64,
+    word_spacing: f64,
+    horizontal_scaling: f64,
+    rise: f64,
+}
+
+impl Default for GraphicsState {
+    fn default() -> Self {
+        Self {
+            ctm: IDENTITY,
+            line_width: 1.0,
+            fill_color: Some(Color::Greyscale(Greyscale::new(0.0, None))),
+            outline_color: Some(Color::Greyscale(Greyscale::new(0.0, None))),
+            font: None,
+            font_size: 0.0,
+            leading: 0.0,
+            character_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scaling: 1.0,
+            rise: 0.0,
+        }
+    }
+}
+
+/// Minimum and maximum coordinates of a set of points
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Extents {
+    pub(super) min_x: f64,
+    pub(super) min_y: f64,
+    pub(super) max_x: f64,
+    pub(super) max_y: f64,
+}
+
+impl Extents {
+
+    pub(super) fn include(extents: &mut Option<Extents>, (x, y): (f64, f64))
+    {
+        *extents = Some(match *extents {
+            None => Extents { min_x: x, min_y: y, max_x: x, max_y: y },
+            Some(e) => Extents { min_x: e.min_x.min(x), min_y: e.min_y.min(y), max_x: e.max_x.max(x), max_y: e.max_y.max(y) },
+        });
+    }
+
+    pub(super) fn union(extents: &mut Option<Extents>, other: Extents)
+    {
+        Self::include(extents, (other.min_x, other.min_y));
+        Self::include(extents, (other.max_x, other.max_y));
+    }
+
+    fn expanded(self, padding: f64)
+    -> Extents
+    {
+        Extents { min_x: self.min_x - padding, min_y: self.min_y - padding, max_x: self.max_x + padding, max_y: self.max_y + padding }
+    }
+
+    /// Is the area completely inside of the rectangle?
+    pub(super) fn inside(&self, rect: &Rect)
+    -> bool
+    {
+        self.min_x >= rect.llx.0 && self.min_y >= rect.lly.0 && self.max_x <= rect.urx.0 && self.max_y <= rect.ury.0
+    }
+
+    /// Do the area and the rectangle overlap? Touching edges don't count.
+    pub(super) fn overlaps(&self, rect: &Rect)
+    -> bool
+    {
+        self.min_x < rect.urx.0 && self.max_x > rect.llx.0 && self.min_y < rect.ury.0 && self.max_y > rect.lly.0
+    }
+}
+
+impl From<Extents> for Rect {
+    fn from(e: Extents) -> Self {
+        Rect { llx: Pt(e.min_x), lly: Pt(e.min_y), urx: Pt(e.max_x), ury: Pt(e.max_y) }
+    }
+}
+
+/// Metrics of the font used by the text operators
+enum FontMetrics {
+    /// Builtin font, one byte per character. `None` if the font isn't in the font list
+    Builtin(Option<BuiltinMetrics>),
+    /// External font, two bytes (glyph ID) per glyph
+    External(FontFace, Box<ExternalFont>),
+}
+
+impl FontMetrics {
+
+    fn new(fonts: &FontList, name: &str)
+    -> Self
+    {
+        match fonts.get_font_data(&IndirectFontRef::new(name)) {
+            Some(Font::ExternalFont(font)) => FontMetrics::External(font.get_font(), Box::new(font.clone())),
+            Some(Font::BuiltinFont(font)) => FontMetrics::Builtin(Some(BuiltinMetrics::new(*font))),
+            None => FontMetrics::Builtin(None),
+        }
+    }
+
+    /// Ascent and descent in em
+    fn vertical(&self)
+    -> (f64, f64)
+    {
+        match *self {
+            FontMetrics::Builtin(Some(ref metrics)) => (metrics.ascent, metrics.descent),
+            FontMetrics::Builtin(None) => (BUILTIN_ASCENT, BUILTIN_DESCENT),
+            FontMetrics::External(ref font, _) => {
+                let (ascent, descent) = font.vertical_metrics();
+                (ascent / font.units_per_em(), descent / font.units_per_em())
+            },
+        }
+    }
+
+    /// Encodes the text like `PdfLayerReference::write_text()`
+    fn encode(&self, text: &str)
+    -> Vec<u8>
+    {
+        match *self {
+            FontMetrics::Builtin(_) => text.as_bytes().to_vec(),
+            FontMetrics::External(ref face, ref font) => font.encode_text(face, text).into_iter()
+                .flat_map(|gid| vec![(gid >> 8) as u8, (gid & 255) as u8])
+                .collect(),
+        }
+    }
+
+    /// Number of bytes of a character code
+    fn code_length(&self)
+    -> usize
+    {
+        match *self {
+            FontMetrics::Builtin(_) => 1,
+            FontMetrics::External(..) => 2,
+        }
+    }
+
+    /// Advance widths (in em) of the characters in a string, and whether the character is a space
+    fn advances(&self, bytes: &[u8])
+    -> Vec<(f64, bool)>
+    {
+        match *self {
+            FontMetrics::Builtin(ref metrics) => bytes.iter().map(|b| {
+                let advance = metrics.and_then(|m| m.advance(*b)).unwrap_or(BUILTIN_ADVANCE);
+                (advance, *b == b' ')
+            }).collect(),
+            FontMetrics::External(ref font, _) => {
+                let units_per_em = font.units_per_em();
+                bytes.chunks(2).map(|pair| {
+                    let gid = u16::from(pair[0]) << 8 | u16::from(*pair.get(1).unwrap_or(&0));
+                    let advance = font.advance_width(gid).unwrap_or(0.0);
+                    // word spacing only applies to single-byte codes
+                    (advance / units_per_em, false)
+                }).collect()
+            },
+        }
+    }
+}
+
+fn number(object: &Object)
+-> Option<f64>
+{
+    match *object {
+        Object::Integer(i) => Some(i as f64),
+        Object::Real(r) => Some(r),
+        _ => None,
+    }
+}
+
+fn numbers(operation: &Operation)
+-> Vec<f64>
+{
+    operation.operands.iter().filter_map(number).collect()
+}
+
+/// State of the text object between `BT` and `ET`
+struct TextState {
+    matrix: Matrix,
+    line_matrix: Matrix,
+    metrics: Option<(String, FontMetrics)>,
+}
+
+impl TextState {
+
+    fn next_line(&mut self, tx: f64, ty: f64)
+    {
+        self.line_matrix = multiply(&[1.0, 0.0, 0.0, 1.0, tx, ty], &self.line_matrix);
+        self.matrix = self.line_matrix;
+    }
+}
+
+/// A character or an adjustment shown by a text showing operator
+pub(super) enum ShownText {
+    /// Code of a character (one byte for builtin fonts, two bytes for external fonts), its
+    /// displacement in text space, its box in page coordinates (from descent to ascent)
+    /// and its origin on the baseline in page coordinates
+    Character { code: Vec<u8>, displacement: f64, bounds: Extents, origin: (f64, f64) },
+    /// Adjustment of a `TJ` array, in thousandths of an em
+    Adjustment(f64),
+}
+
+/// Interprets the operations of a content stream, keeps track of the graphics state and
+/// the position of the text
+pub(super) struct Interpreter {
+    state: GraphicsState,
+    saved_states: Vec<GraphicsState>,
+    text: TextState,
+}
+
+impl Interpreter {
+
+    pub(super) fn new()
+    -> Self
+    {
+        Self {
+            state: GraphicsState::default(),
+            saved_states: Vec::new(),
+            text: TextState { matrix: IDENTITY, line_matrix: IDENTITY, metrics: None },
+        }
+    }
+
+    /// Applies the changes of the graphics state and the text position of an operation.
+    /// `'` and `"` move to the next line, call `show_text()` afterwards to show their text.
+    pub(super) fn update(&mut self, operation: &Operation)
+    {
+        let n = numbers(operation);
+        let state = &mut self.state;
+        let text = &mut self.text;
+        match operation.operator.as_str() {
+            "q" => self.saved_states.push(state.clone()),
+            "Q" => if let Some(saved) = self.saved_states.pop() { *state = saved; },
+            "cm" if n.len() == 6 => state.ctm = multiply(&[n[0], n[1], n[2], n[3], n[4], n[5]], &state.ctm),
+            "w" if n.len() == 1 => state.line_width = n[0],
+
+            // colors
+            "g" if n.len() == 1 => state.fill_color = Some(Color::Greyscale(Greyscale::new(n[0], None))),
+            "G" if n.len() == 1 => state.outline_color = Some(Color::Greyscale(Greyscale::new(n[0], None))),
+            "rg" if n.len() == 3 => state.fill_color = Some(Color::Rgb(Rgb::new(n[0], n[1], n[2], None))),
+            "RG" if n.len() == 3 => state.outline_color = Some(Color::Rgb(Rgb::new(n[0], n[1], n[2], None))),
+            "k" if n.len() == 4 => state.fill_color = Some(Color::Cmyk(Cmyk::new(n[0], n[1], n[2], n[3], None))),
+            "K" if n.len() == 4 => state.outline_color = Some(Color::Cmyk(Cmyk::new(n[0], n[1], n[2], n[3], None))),
+            "cs" | "sc" | "scn" => state.fill_color = None,
+            "CS" | "SC" | "SCN" => state.outline_color = None,
+
+            // text state
+            "Tf" => {
+                if let Some(Ok(name)) = operation.operands.first().map(|o| o.as_name_str()) {
+                    state.font = Some(name.to_string());
+                }
+                if let Some(size) = operation.operands.get(1).and_then(number) {
+                    state.font_size = size;
+                }
+            },
+            "TL" if n.len() == 1 => state.leading = n[0],
+            "Tc" if n.len() == 1 => state.character_spacing = n[0],
+            "Tw" if n.len() == 1 => state.word_spacing = n[0],
+            "Tz" if n.len() == 1 => state.horizontal_scaling = n[0] / 100.0,
+            "Ts" if n.len() == 1 => state.rise = n[0],
+
+            // text positioning
+            "BT" => {
+                text.matrix = IDENTITY;
+                text.line_matrix = IDENTITY;
+            },
+            "Td" if n.len() == 2 => text.next_line(n[0], n[1]),
+            "TD" if n.len() == 2 => {
+                state.leading = -n[1];
+                text.next_line(n[0], n[1]);
+            },
+            "Tm" if n.len() == 6 => {
+                text.line_matrix = [n[0], n[1], n[2], n[3], n[4], n[5]];
+                text.matrix = text.line_matrix;
+            },
+            "T*" | "'" => text.next_line(0.0, -state.leading),
+            "\"" if operation.operands.len() == 3 => {
+                if let (Some(word_spacing), Some(character_spacing)) = (number(&operation.operands[0]), number(&operation.operands[1])) {
+                    state.word_spacing = word_spacing;
+                    state.character_spacing = character_spacing;
+                }
+                text.next_line(0.0, -state.leading);
+            },
+
+            _ => { },
+        }
+    }
+
+    /// Points of a path construction operator (`m`, `l`, `c`, `v`, `y`, `re`) in page
+    /// coordinates, control points of curves are included
+    pub(super) fn path_points(&self, operation: &Operation)
+    -> Vec<(f64, f64)>
+    {
+        let n = numbers(operation);
+        let ctm = &self.state.ctm;
+        match operation.operator.as_str() {
+            "m" | "l" | "c" | "v" | "y" => n.chunks(2).filter(|p| p.len() == 2).map(|p| transform(ctm, p[0], p[1])).collect(),
+            "re" if n.len() == 4 => {
+                [(n[0], n[1]), (n[0] + n[2], n[1]), (n[0], n[1] + n[3]), (n[0] + n[2], n[1] + n[3])]
+                    .iter().map(|&(x, y)| transform(ctm, x, y)).collect()
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Extents of the painted path, stroked paths are expanded by half of the line width
+    pub(super) fn painted_extents(&self, operator: &str, path: Extents)
+    -> Option<Extents>
+    {
+        let ctm = &self.state.ctm;
+        match operator {
+            "S" | "s" | "B" | "B*" | "b" | "b*" => {
+                let scale = (ctm[0] * ctm[3] - ctm[1] * ctm[2]).abs().sqrt();
+                Some(path.expanded(self.state.line_width / 2.0 * scale))
+            },
+            "f" | "F" | "f*" => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Extents of an XObject drawn with `Do`, approximated by the unit square
+    pub(super) fn xobject_extents(&self)
+    -> Extents
+    {
+        let mut extents = None;
+        for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            Extents::include(&mut extents, transform(&self.state.ctm, x, y));
+        }
+        extents.unwrap()
+    }
+
+    /// Shows the string of a text showing operator (`Tj`, `TJ`, `'` or `"`), returns the
+    /// characters and moves the text matrix to the end of the text
+    pub(super) fn show_text(&mut self, operation: &Operation, fonts: &FontList)
+    -> Vec<ShownText>
+    {
+        let operands = match operation.operator.as_str() {
+            "\"" => operation.operands.get(2..).unwrap_or(&[]),
+            _ => &operation.operands[..],
+        };
+        let state = &self.state;
+        let text = &mut self.text;
+
+        let font_name = match state.font {
+            Some(ref font) => font.clone(),
+            None => return Vec::new(),
+        };
+
+        let reload = match text.metrics {
+            Some((ref name, _)) => *name != font_name,
+            None => true,
+        };
+        if reload {
+            let metrics = FontMetrics::new(fonts, &font_name);
+            text.metrics = Some((font_name, metrics));
+        }
+        let metrics = &text.metrics.as_ref().unwrap().1;
+
+        let (ascent, descent) = metrics.vertical();
+        let bottom = descent * state.font_size + state.rise;
+        let top = ascent * state.font_size + state.rise;
+
+        let mut shown = Vec::new();
+        let mut x = 0.0;
+        let show_string = |bytes: &[u8], shown: &mut Vec<ShownText>, x: &mut f64| {
+            let to_device = multiply(&text.matrix, &state.ctm);
+            for (code, (advance, is_space)) in bytes.chunks(metrics.code_length()).zip(metrics.advances(bytes)) {
+                let spacing = if is_space { state.word_spacing } else { 0.0 };
+                let displacement = (advance * state.font_size + state.character_spacing + spacing) * state.horizontal_scaling;
+                let mut bounds = None;
+                for &(cx, cy) in &[(*x, bottom), (*x + displacement, bottom), (*x, top), (*x + displacement, top)] {
+                    Extents::include(&mut bounds, transform(&to_device, cx, cy));
+                }
+                let origin = transform(&to_device, *x, state.rise);
+                shown.push(ShownText::Character { code: code.to_vec(), displacement, bounds: bounds.unwrap(), origin });
+                *x += displacement;
+            }
+        };
+
+        for operand in operands {
+            match *operand {
+                Object::String(ref bytes, _) => show_string(bytes, &mut shown, &mut x),
+                Object::Array(ref items) => {
+                    for item in items {
+                        match *item {
+                            Object::String(ref bytes, _) => show_string(bytes, &mut shown, &mut x),
+                            // adjustments are in thousandths of an em, positive values move to the left
+                            _ => {
+                                let adjustment = number(item).unwrap_or(0.0);
+                                x -= adjustment / 1000.0 * state.font_size * state.horizontal_scaling;
+                                shown.push(ShownText::Adjustment(adjustment));
+                            },
+                        }
+                    }
+                },
+                _ => { },
+            }
+        }
+
+        text.matrix = multiply(&[1.0, 0.0, 0.0, 1.0, x, 0.0], &text.matrix);
+        shown
+    }
+
+    /// Name of the current font, e.g. `F0`
+    pub(super) fn font(&self)
+    -> Option<&str>
+    {
+        self.state.font.as_deref()
+    }
+
+    /// Horizontal scaling (`Tz`), 1.0 is the normal width
+    pub(super) fn horizontal_scaling(&self)
+    -> f64
+    {
+        self.state.horizontal_scaling
+    }
+
+    /// Width of the character codes in text space, if they were shown with the current state
+    pub(super) fn displacement(&mut self, code: &[u8], fonts: &FontList)
+    -> f64
+    {
+        let matrix = self.text.matrix;
+        let operation = Operation::new("Tj", vec![Object::String(code.to_vec(), StringFormat::Hexadecimal)]);
+        let shown = self.show_text(&operation, fonts);
+        self.text.matrix = matrix;
+        shown.iter().map(|s| match *s {
+            ShownText::Character { displacement, .. } => displacement,
+            ShownText::Adjustment(_) => 0.0,
+        }).sum()
+    }
+
+    /// Font size in page coordinates, scaled by the text matrix and the current transformation matrix
+    pub(super) fn font_size_on_page(&self)
+    -> f64
+    {
+        let matrix = multiply(&self.text.matrix, &self.state.ctm);
+        self.state.font_size * (matrix[2] * matrix[2] + matrix[3] * matrix[3]).sqrt()
+    }
+
+    /// Font size and horizontal scaling, which convert a displacement into a `TJ` adjustment
+    pub(super) fn text_scale(&self)
+    -> f64
+    {
+        self.state.font_size * self.state.horizontal_scaling
+    }
+}
+
+/// Builds the array of a `TJ` operator from character codes and adjustments,
+/// consecutive adjustments are merged
+#[derive(Default)]
+pub(super) struct TextArray {
+    items: Vec<Object>,
+    string: Vec<u8>,
+    adjustment: f64,
+}
+
+impl TextArray {
+
+    pub(super) fn push_code(&mut self, code: &[u8])
+    {
+        if self.adjustment != 0.0 {
+            self.flush_string();
+            self.items.push(Object::Real(::std::mem::replace(&mut self.adjustment, 0.0)));
+        }
+        self.string.extend_from_slice(code);
+    }
+
+    /// Adds an adjustment in thousandths of an em, positive values move to the left
+    pub(super) fn push_adjustment(&mut self, adjustment: f64)
+    {
+        self.adjustment += adjustment;
+    }
+
+    fn flush_string(&mut self)
+    {
+        if !self.string.is_empty() {
+            self.items.push(Object::String(::std::mem::take(&mut self.string), StringFormat::Hexadecimal));
+        }
+    }
+
+    pub(super) fn is_empty(&self)
+    -> bool
+    {
+        self.items.is_empty() && self.string.is_empty() && self.adjustment == 0.0
+    }
+
+    /// Returns the `TJ` operator
+    pub(super) fn into_operation(mut self)
+    -> Operation
+    {
+        self.flush_string();
+        if self.adjustment != 0.0 {
+            self.items.push(Object::Real(self.adjustment));
+        }
+        Operation::new("TJ", vec![Object::Array(self.items)])
+    }
+}
+
+/// `'` and `"` also move to the next line (and set the spacing). If they are replaced by
+/// `TJ` operators, these are the operations that have to be written first.
+pub(super) fn line_operations(original: &Operation)
+-> Vec<Operation>
+{
+    match original.operator.as_str() {
+        "\"" if original.operands.len() == 3 => vec![
+            Operation::new("Tw", vec![original.operands[0].clone()]),
+            Operation::new("Tc", vec![original.operands[1].clone()]),
+            Operation::new("T*", vec![]),
+        ],
+        "'" => vec![Operation::new("T*", vec![])],
+        _ => Vec::new(),
+    }
+}
+
+/// Encodes the text like `PdfLayerReference::write_text()` in the font with the resource name `font`
+pub(super) fn encode_text(fonts: &FontList, font: &str, text: &str)
+-> Vec<u8>
+{
+    let metrics = FontMetrics::new(fonts, font);
+    metrics.encode(text)
+}
+
+/// Width of a single line of text written with `write_text()` in the font and size, without
+/// character or word spacing
+pub(crate) fn text_width(fonts: &FontList, font: &IndirectFontRef, text: &str, font_size: f64)
+-> Pt
+{
+    let metrics = FontMetrics::new(fonts, &font.name);
+    Pt(metrics.advances(&metrics.encode(text)).iter().map(|(advance, _)| advance * font_size).sum())
+}
+
+/// Graphics state after the operations, see `PdfLayerReference::current_state()`
+pub(crate) fn graphics_state(operations: &[Operation])
+-> LayerState
+{
+    let mut interpreter = Interpreter::new();
+    for operation in operations {
+        interpreter.update(operation);
+    }
+
+    let state = interpreter.state;
+    LayerState {
+        ctm: state.ctm,
+        fill_color: state.fill_color,
+        outline_color: state.outline_color,
+        line_width: state.line_width,
+        font: state.font.map(IndirectFontRef::new),
+        font_size: state.font_size,
+        depth: interpreter.saved_states.len(),
+    }
+}
+
+/// Computes the bounding box of the content drawn by the operations, in page coordinates.
+/// Stroked paths are expanded by half of the line width. The box of text is approximated
+/// with the ascent and descent of the font, images are the unit square transformed by the
+/// current transformation matrix. Returns `None` if the operations don't draw anything.
+pub(crate) fn content_bounding_box(operations: &[Operation], fonts: &FontList)
+-> Option<Rect>
+{
+    let mut extents = None;
+    let mut path: Option<Extents> = None;
+    let mut interpreter = Interpreter::new();
+
+    for operation in operations {
+        interpreter.update(operation);
+        match operation.operator.as_str() {
+            "m" | "l" | "c" | "v" | "y" | "re" => {
+                for point in interpreter.path_points(operation) {
+                    Extents::include(&mut path, point);
+                }
+            },
+            "n" => path = None,
+            "S" | "s" | "B" | "B*" | "b" | "b*" | "f" | "F" | "f*" => {
+                if let Some(painted) = path.take().and_then(|path| interpreter.painted_extents(&operation.operator, path)) {
+                    Extents::union(&mut extents, painted);
+                }
+            },
+            "Do" | "EI" => Extents::union(&mut extents, interpreter.xobject_extents()),
+            "Tj" | "TJ" | "'" | "\"" => {
+                for shown in interpreter.show_text(operation, fonts) {
+                    if let ShownText::Character { bounds, .. } = shown {
+                        Extents::union(&mut extents, bounds);
+                    }
+                }
+            },
+            _ => { },
+        }
+    }
+
+    extents.map(|e| e.into())
+}
+
+#[test]
+fn test_content_bounding_box() {
+    let fonts = FontList::new();
+
+    // nothing is drawn
+    let operations = vec![Operation::new("m", vec![10.into(), 10.into()]), Operation::new("n", vec![])];
+    assert_eq!(content_bounding_box(&operations, &fonts), None);
+
+    // stroked line, expanded by half of the line width
+    let operations = vec![
+        Operation::new("w", vec![2.into()]),
+        Operation::new("m", vec![10.into(), 20.into()]),
+        Operation::new("l", vec![110.into(), 70.into()]),
+        Operation::new("S", vec![]),
+    ];
+    assert_eq!(content_bounding_box(&operations, &fonts), Some(Rect { llx: Pt(9.0), lly: Pt(19.0), urx: Pt(111.0), ury: Pt(71.0) }));
+
+    // image placed with a transformation matrix inside a saved graphics state
+    let operations = vec![
+        Operation::new("q", vec![]),
+        Operation::new("cm", vec![50.into(), 0.into(), 0.into(), 30.into(), 5.into(), 5.into()]),
+        Operation::new("Do", vec![Object::Name("X0".into())]),
+        Operation::new("Q", vec![]),
+        Operation::new("re", vec![1.into(), 1.into(), 1.into(), 1.into()]),
+        Operation::new("f", vec![]),
+    ];
+    assert_eq!(content_bounding_box(&operations, &fonts), Some(Rect { llx: Pt(1.0), lly: Pt(1.0), urx: Pt(55.0), ury: Pt(35.0) }));
+}
+
+#[test]
+fn test_text_width_builtin() {
+    use {BuiltinFont, DirectFontRef};
+
+    let mut fonts = FontList::new();
+    let helvetica = fonts.add_font(IndirectFontRef::new("F1"), DirectFontRef { inner_obj: (1, 0), data: Font::BuiltinFont(BuiltinFont::Helvetica) });
+
+    // "W" is 944/1000 em wide in Helvetica, fonts that aren't in the list use the average width
+    assert!((text_width(&fonts, &helvetica, "W", 10.0).0 - 9.44).abs() < 1e-9);
+    assert!((text_width(&fonts, &helvetica, "Wi", 10.0).0 - 11.66).abs() < 1e-9);
+    assert!((text_width(&fonts, &IndirectFontRef::new("F2"), "W", 10.0).0 - 5.0).abs() < 1e-9);
+}
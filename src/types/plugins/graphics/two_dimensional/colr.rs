@@ -0,0 +1,79 @@
+//! Color glyphs of COLR (version 0) fonts, e.g. emoji fonts: a color glyph is drawn as a
+//! stack of ordinary glyphs, each filled with a color of the palette in the `CPAL` table.
+
+use types::plugins::graphics::two_dimensional::sfnt::{find_table, read_u16, read_u32};
+
+/// Palette index of layers that are drawn in the current fill color
+const FOREGROUND_COLOR: u16 = 0xFFFF;
+
+/// Layers of a color glyph, from bottom to top: the glyph that makes up the layer and its
+/// color from the first palette (RGB), `None` for the current fill color. Returns `None` if
+/// the font has no color glyphs or the glyph is not a color glyph.
+pub(crate) fn color_layers(font: &[u8], glyph: u16)
+-> Option<Vec<(u16, Option<[u8; 3]>)>>
+{
+    let colr = find_table(font, b"COLR")?;
+    let cpal = find_table(font, b"CPAL")?;
+
+    let base_glyphs = read_u16(colr, 2)? as usize;
+    let base_glyphs_offset = read_u32(colr, 4)? as usize;
+    let layers_offset = read_u32(colr, 8)? as usize;
+
+    // the base glyph records are sorted by glyph ID
+    let (mut low, mut high) = (0, base_glyphs);
+    let (first_layer, num_layers) = loop {
+        if low >= high {
+            return None;
+        }
+        let middle = (low + high) / 2;
+        let record = base_glyphs_offset + middle * 6;
+        let base_glyph = read_u16(colr, record)?;
+        if base_glyph == glyph {
+            break (read_u16(colr, record + 2)? as usize, read_u16(colr, record + 4)? as usize);
+        } else if base_glyph < glyph {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    };
+
+    let palette_entries = read_u16(cpal, 2)?;
+    let colors_offset = read_u32(cpal, 8)? as usize;
+    let first_color = read_u16(cpal, 12)? as usize;
+
+    (first_layer..first_layer + num_layers).map(|layer| {
+        let record = layers_offset + layer * 4;
+        let palette_index = read_u16(colr, record + 2)?;
+        let color = if palette_index == FOREGROUND_COLOR || palette_index >= palette_entries {
+            None
+        } else {
+            let color = cpal.get(colors_offset + (first_color + palette_index as usize) * 4..)?;
+            // stored as blue, green, red, alpha
+            Some([*color.get(2)?, *color.get(1)?, *color.first()?])
+        };
+        Some((read_u16(colr, record)?, color))
+    }).collect()
+}
+
+/// `COLR` and `CPAL` tables for tests: glyph 2 is a red glyph 1 below glyph 1 in the foreground color
+#[cfg(test)]
+pub(crate) fn test_color_tables()
+-> Vec<([u8; 4], Vec<u8>)>
+{
+    fn u16s(values: &[u16]) -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect() }
+
+    let colr = u16s(&[0, 1, 0, 14, 0, 20, 2, 2, 0, 2, 1, 0, 1, FOREGROUND_COLOR]);
+    let mut cpal = u16s(&[0, 1, 1, 1, 0, 14, 0]);
+    cpal.extend_from_slice(&[0, 0, 255, 255]);
+    vec![(*b"COLR", colr), (*b"CPAL", cpal)]
+}
+
+#[test]
+fn test_color_layers() {
+    use types::plugins::graphics::two_dimensional::sfnt::{test_font, test_font_with};
+
+    let font = test_font_with(test_color_tables());
+    assert_eq!(color_layers(&font, 2), Some(vec![(1, Some([255, 0, 0])), (1, None)]));
+    assert_eq!(color_layers(&font, 1), None);
+    assert_eq!(color_layers(&test_font(), 2), None);
+}
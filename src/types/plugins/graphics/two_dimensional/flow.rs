@@ -0,0 +1,953 @@
+//! Text flow across pages: paragraphs are wrapped into a region of the page, and when the
+//! region is full, the text continues in the same region on the next page
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use indices::PdfPageIndex;
+use types::plugins::graphics::two_dimensional::bounding_box::text_width;
+use types::plugins::graphics::two_dimensional::text_layout::{wrap_ranges, line_capacity};
+use {Color, Error, Image, IndirectFontRef, Line, PdfDocumentReference, PdfError, PdfLayerReference, Mm, Point, Pt, Px, Rect};
+
+/// Leading of the flowed text, as a multiple of the font size
+const FLOW_LEADING: f64 = 1.2;
+/// Space between the border of a table cell and its text
+const CELL_PADDING: Pt = Pt(3.0);
+/// Width of the borders of table cells
+const CELL_BORDER: f64 = 0.5;
+/// Space between an image and its caption, see `TextFlow::add_image_flowed()`
+const CAPTION_GAP: Pt = Pt(4.0);
+/// Distance to a grid line (in multiples of the grid spacing), below which a baseline counts
+/// as being on the grid line, so that rounding errors don't skip a line
+const GRID_TOLERANCE: f64 = 1e-6;
+
+/// Runs of text (text, font and color) of a table cell
+type CellRuns<'a> = Vec<(&'a str, &'a IndirectFontRef, Option<&'a Color>)>;
+
+/// Text that flows through a region on consecutive pages. Paragraphs are wrapped to the
+/// width of the region, when the region is full, the flow continues on the next page (which
+/// is added if it doesn't exist yet) in a layer with the same name.
+///
+/// Paragraphs that are added with `keep_with_next` (e.g. headings) are kept on the same page
+/// as the first lines of the next paragraph. They are drawn together with that paragraph, or
+/// when the flow is finished or dropped.
+#[derive(Debug)]
+pub struct TextFlow {
+    /// Layer the text is currently written to
+    layer: PdfLayerReference,
+    /// Region of the text on the current page
+    region: Rect,
+    /// Name of the region, if the flow was started with `in_region()`
+    region_name: Option<String>,
+    /// Top of the remaining space in the region
+    cursor: Pt,
+    /// Paragraphs which are kept with the next paragraph, not drawn yet
+    pending: Vec<Paragraph>,
+    /// Indentation of the paragraphs from the left edge of the region, for example
+    /// for lists or quotes. Default: __Mm(0.0)__
+    pub indent: Mm,
+    /// Minimum number of lines of a paragraph at the top of a page (the last lines of a
+    /// paragraph, that was split). Default: __2__
+    pub widow_lines: usize,
+    /// Minimum number of lines of a paragraph at the bottom of a page (the first lines of a
+    /// paragraph, that was split). Fewer lines move to the next page. Default: __2__
+    pub orphan_lines: usize,
+}
+
+/// Text in one font and color
+#[derive(Debug, Clone)]
+struct Run {
+    text: String,
+    font: IndirectFontRef,
+    /// Fill color of the text, `None` for the current fill color of the layer
+    color: Option<Color>,
+}
+
+/// Wrapped paragraph, each line consists of runs of text
+#[derive(Debug, Clone)]
+struct Paragraph {
+    lines: Vec<Vec<Run>>,
+    font_size: f64,
+    /// Indentation in pt
+    indent: Pt,
+}
+
+/// Horizontal position of the text in a table cell
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HorizontalAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical position of the text in a table cell
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VerticalAlignment {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Cell of a table, see `TextFlow::add_table_flowed()`. A cell can span several columns
+/// and rows, the merged area has a border only around its outside.
+#[derive(Debug, Clone)]
+pub struct TableCell<'a> {
+    /// Runs of text (text, font and color), see `TextFlow::add_colored_paragraph()`
+    pub content: CellRuns<'a>,
+    /// Number of columns the cell spans. Default: __1__
+    pub colspan: usize,
+    /// Number of rows the cell spans. Default: __1__
+    pub rowspan: usize,
+    /// Alignment of every line of the text in the cell. Default: __HorizontalAlignment::Left__
+    pub align: HorizontalAlignment,
+    /// Alignment of the text in a cell, that is higher than its text.
+    /// Default: __VerticalAlignment::Top__
+    pub valign: VerticalAlignment,
+}
+
+impl<'a> TableCell<'a> {
+
+    /// Creates a cell, that spans one column and one row
+    #[inline]
+    pub fn new(content: CellRuns<'a>)
+    -> Self
+    {
+        Self { content, colspan: 1, rowspan: 1, align: HorizontalAlignment::Left, valign: VerticalAlignment::Top }
+    }
+
+    /// Creates a cell with a single run of text
+    #[inline]
+    pub fn text(text: &'a str, font: &'a IndirectFontRef)
+    -> Self
+    {
+        Self::new(vec![(text, font, None)])
+    }
+}
+
+impl<'a> From<CellRuns<'a>> for TableCell<'a> {
+    fn from(content: CellRuns<'a>) -> Self {
+        Self::new(content)
+    }
+}
+
+/// Cells of a table, with their wrapped lines, and the height of every row
+#[derive(Debug, Clone)]
+struct TableGrid {
+    cells: Vec<GridCell>,
+    heights: Vec<Pt>,
+}
+
+/// Cell of a table at its position in the grid
+#[derive(Debug, Clone)]
+struct GridCell {
+    row: usize,
+    column: usize,
+    colspan: usize,
+    rowspan: usize,
+    align: HorizontalAlignment,
+    valign: VerticalAlignment,
+    lines: Vec<Vec<Run>>,
+}
+
+impl TableGrid {
+
+    /// Height of the rows in the range
+    fn height(&self, rows: Range<usize>)
+    -> Pt
+    {
+        self.heights[rows].iter().fold(Pt(0.0), |height, row| height + *row)
+    }
+
+    /// Splits the rows into groups, that are connected by cells spanning several rows.
+    /// Tables are only broken between the groups.
+    fn blocks(&self)
+    -> Vec<Range<usize>>
+    {
+        let mut blocks = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+        for row in 0..self.heights.len() {
+            end = self.cells.iter().filter(|cell| cell.row == row).fold(end.max(row + 1), |end, cell| end.max(row + cell.rowspan));
+            if end == row + 1 {
+                blocks.push(start..end);
+                start = end;
+            }
+        }
+        blocks
+    }
+}
+
+impl Paragraph {
+
+    #[inline]
+    fn leading(&self)
+    -> Pt
+    {
+        Pt(self.font_size * FLOW_LEADING)
+    }
+}
+
+impl TextFlow {
+
+    /// Starts a flow at the top of `region` on the page of `layer`
+    pub fn new(layer: PdfLayerReference, region: Rect)
+    -> Self
+    {
+        Self {
+            layer,
+            region,
+            region_name: None,
+            cursor: region.ury,
+            pending: Vec::new(),
+            indent: Mm(0.0),
+            widow_lines: 2,
+            orphan_lines: 2,
+        }
+    }
+
+    /// Starts a flow at the top of the region with the name on the page of `layer` (see
+    /// `PdfPageReference::define_region()`). On the following pages, the flow continues in
+    /// the region of the same name, if the page defines it, otherwise in the same area.
+    pub fn in_region(layer: PdfLayerReference, name: &str)
+    -> Result<Self, Error>
+    {
+        let region = PdfDocumentReference { document: layer.document.upgrade().unwrap() }
+            .get_page(layer.page).region(name)
+            .ok_or(Error::Pdf(PdfError::UnknownRegion))?;
+        let mut flow = Self::new(layer, region);
+        flow.region_name = Some(name.to_string());
+        Ok(flow)
+    }
+
+    /// Returns the page the flow is currently on
+    #[inline]
+    pub fn current_page(&self)
+    -> PdfPageIndex
+    {
+        self.layer.page
+    }
+
+    /// Returns the region of the text on the current page
+    #[inline]
+    pub fn region(&self)
+    -> Rect
+    {
+        self.region
+    }
+
+    /// Adds a paragraph (every `\n` starts a new line) in the font and size. With
+    /// `keep_with_next`, the paragraph is never separated from the paragraph after it,
+    /// for example for headings.
+    #[inline]
+    pub fn add_paragraph<S>(&mut self, text: S, font: &IndirectFontRef, font_size: f64, keep_with_next: bool) where S: Into<String>
+    {
+        let text = text.into();
+        self.add_rich_paragraph(&[(&text, font)], font_size, keep_with_next);
+    }
+
+    /// Adds a paragraph of runs of text in different fonts (e.g. regular, **bold** and *italic*),
+    /// see `add_paragraph()`. The runs are wrapped as one text, so a run should end with a space
+    /// if the next run starts a new word.
+    #[inline]
+    pub fn add_rich_paragraph(&mut self, runs: &[(&str, &IndirectFontRef)], font_size: f64, keep_with_next: bool)
+    {
+        let runs: Vec<_> = runs.iter().map(|&(text, font)| (text, font, None)).collect();
+        self.add_colored_paragraph(&runs, font_size, keep_with_next);
+    }
+
+    /// Adds a paragraph of runs of text in different fonts and colors, see `add_rich_paragraph()`.
+    /// Runs without a color use the current fill color of the layer.
+    pub fn add_colored_paragraph(&mut self, runs: &[(&str, &IndirectFontRef, Option<&Color>)], font_size: f64, keep_with_next: bool)
+    {
+        let indent: Pt = self.indent.into();
+        let lines = self.wrap_runs(runs, self.region.width() - indent, font_size);
+        let paragraph = Paragraph { lines, font_size, indent };
+
+        if keep_with_next {
+            self.pending.push(paragraph);
+            return;
+        }
+
+        if !self.pending.is_empty() {
+            // the kept paragraphs and the first lines of this paragraph have to be on the same page
+            let first_lines = paragraph.lines.len().min(self.orphan_lines) as f64;
+            let needed = self.pending.iter().fold(paragraph.leading() * first_lines, |height, p| height + p.leading() * p.lines.len() as f64);
+            if self.cursor - self.region.lly < needed && !self.is_at_top() {
+                self.next_page();
+            }
+            self.flush();
+        }
+
+        self.place(paragraph);
+    }
+
+    /// Adds lines without wrapping them and with their whitespace intact (e.g. source code).
+    /// Lines that are wider than the region are cut off by the viewer.
+    pub fn add_preformatted<S>(&mut self, text: S, font: &IndirectFontRef, font_size: f64) where S: Into<String>
+    {
+        let lines = text.into().lines().map(|line| vec![Run { text: line.to_string(), font: font.clone(), color: None }]).collect();
+        self.flush();
+        self.place(Paragraph { lines, font_size, indent: self.indent.into() });
+    }
+
+    /// Adds vertical space, which is left out at the top of a new page
+    pub fn add_space(&mut self, space: Mm)
+    {
+        let space: Pt = space.into();
+        self.flush();
+        if !self.is_at_top() {
+            self.cursor = Pt((self.cursor - space).0.max(self.region.lly.0));
+        }
+    }
+
+    /// Places an image with the size at the left edge of the region (plus the indentation).
+    /// An image that doesn't fit below the current position moves to the next page.
+    pub fn add_image(&mut self, image: Image, width: Mm, height: Mm)
+    {
+        let (width, height): (Pt, Pt) = (width.into(), height.into());
+        self.flush();
+        if self.cursor - self.region.lly < height && !self.is_at_top() {
+            self.next_page();
+        }
+        self.draw_image(image, width, height);
+    }
+
+    /// Places an image with its aspect ratio, at most `max_width` wide (and never wider than
+    /// the region), with an optional caption below it (text, font and font size). The image
+    /// and its caption are kept together: if they don't fit below the current position, they
+    /// move to the next page. An image that is higher than the whole region is scaled down.
+    pub fn add_image_flowed(&mut self, image: Image, max_width: Mm, caption: Option<(&str, &IndirectFontRef, f64)>)
+    {
+        self.flush();
+        let indent: Pt = self.indent.into();
+        let max_width: Pt = max_width.into();
+        let mut width = Pt(max_width.0.min((self.region.width() - indent).0));
+        let (Px(pixel_width), Px(pixel_height)) = (image.image.width, image.image.height);
+        let mut height = width * (pixel_height as f64 / pixel_width as f64);
+
+        let caption = caption.map(|(text, font, font_size)| (self.wrap_runs(&[(text, font, None)], width, font_size), font_size));
+        let caption_height = caption.as_ref()
+            .map(|&(ref lines, font_size)| CAPTION_GAP + Pt(font_size * FLOW_LEADING) * lines.len() as f64)
+            .unwrap_or(Pt(0.0));
+
+        let max_height = self.region.height() - caption_height;
+        if height > max_height && max_height.0 > 0.0 {
+            width *= max_height.0 / height.0;
+            height = max_height;
+        }
+        if self.cursor - self.region.lly < height + caption_height && !self.is_at_top() {
+            self.next_page();
+        }
+        self.draw_image(image, width, height);
+
+        if let Some((lines, font_size)) = caption {
+            let leading = Pt(font_size * FLOW_LEADING);
+            let top = self.cursor - CAPTION_GAP;
+            for (idx, line) in lines.iter().enumerate() {
+                self.draw_line(line, font_size, self.region.llx + indent, top - Pt(font_size) - leading * idx as f64);
+            }
+            self.cursor = Pt((top - leading * lines.len() as f64).0.max(self.region.lly.0));
+        }
+    }
+
+    /// Keeps everything that `f` adds to the flow (e.g. a figure, its caption and a source line)
+    /// on the same page: if the content doesn't fit below the current position, it is removed
+    /// again and `f` is called a second time, at the top of the next page. Content that doesn't
+    /// fit on an empty page is split as usual.
+    ///
+    /// Everything that `f` adds to the document is rolled back with `PdfDocumentReference::restore()`,
+    /// so `f` should only add content through the flow.
+    ///
+    /// ```rust,ignore
+    /// flow.flow_group(|flow| {
+    ///     flow.add_image_flowed(figure.clone(), Mm(120.0), Some(("Figure 3: Results", &font, 9.0)));
+    ///     flow.add_paragraph("Source: survey 2020", &font, 8.0, false);
+    /// });
+    /// ```
+    pub fn flow_group<F>(&mut self, mut f: F) where F: FnMut(&mut TextFlow)
+    {
+        self.flush();
+        let doc = PdfDocumentReference { document: self.layer.document.upgrade().unwrap() };
+        let checkpoint = doc.checkpoint();
+        let start = (self.layer.clone(), self.region, self.cursor);
+
+        f(self);
+        self.flush();
+
+        if self.layer.page != start.0.page && start.2 != start.1.ury {
+            doc.restore(checkpoint);
+            let (layer, region, cursor) = start;
+            self.layer = layer;
+            self.region = region;
+            self.cursor = cursor;
+            self.next_page();
+            f(self);
+            self.flush();
+        }
+    }
+
+    /// Draws the image with the size at the current position and moves the position below it
+    fn draw_image(&mut self, image: Image, width: Pt, height: Pt)
+    {
+        // the resolution at which the image has the requested size
+        let (Px(pixel_width), Px(pixel_height)) = (image.image.width, image.image.height);
+        let scale_y = (height.0 / width.0) * (pixel_width as f64 / pixel_height as f64);
+        let dpi = pixel_width as f64 * 72.0 / width.0;
+        let x = self.region.llx + self.indent.into();
+        image.add_to_layer(self.layer.clone(), Some(x.into()), Some((self.cursor - height).into()), None, None, Some(scale_y), Some(dpi));
+        self.cursor = Pt((self.cursor - height).0.max(self.region.lly.0));
+    }
+
+    /// Adds a row of a table with a border around every cell. The cells are wrapped to the
+    /// `widths` of the columns, the row is never split: a row that doesn't fit below the
+    /// current position moves to the next page.
+    pub fn add_table_row(&mut self, cells: &[Vec<(&str, &IndirectFontRef, Option<&Color>)>], widths: &[Mm], font_size: f64)
+    {
+        let widths: Vec<Pt> = widths.iter().map(|width| (*width).into()).collect();
+        self.flush();
+
+        let cells: Vec<TableCell> = cells.iter().take(widths.len()).map(|runs| TableCell::new(runs.clone())).collect();
+        let row = self.layout_table(&[cells], &widths, font_size).expect("cells without spans always fit the grid");
+        if self.cursor - self.region.lly < row.height(0..1) && !self.is_at_top() {
+            self.next_page();
+        }
+        self.draw_table_rows(&row, 0..1, &widths, font_size);
+    }
+
+    /// Adds a table, that continues on the next page when the rows exceed the region. Rows are
+    /// never split, a row that doesn't fit below the current position moves to the next page
+    /// (together with the rows that cells spanning several rows connect it to). The `header`
+    /// rows are kept together with the first row, with `repeat_header` they are repeated at
+    /// the top of every page that the table continues on.
+    ///
+    /// The cells of a row fill the columns from the left, skipping columns that are covered by
+    /// a cell of a row above. Fails with `PdfError::InvalidTableSpan` if a cell overlaps another
+    /// cell or spans beyond the last column or row (of the header or the body).
+    pub fn add_table_flowed(&mut self, header: &[Vec<TableCell>], rows: &[Vec<TableCell>],
+                            widths: &[Mm], font_size: f64, repeat_header: bool)
+    -> Result<(), Error>
+    {
+        let widths: Vec<Pt> = widths.iter().map(|width| (*width).into()).collect();
+        self.flush();
+
+        let header = self.layout_table(header, &widths, font_size)?;
+        let header_rows = 0..header.heights.len();
+        let header_height = header.height(header_rows.clone());
+        let body = self.layout_table(rows, &widths, font_size)?;
+        let blocks = body.blocks();
+
+        let first_height = blocks.first().map(|block| body.height(block.clone())).unwrap_or(Pt(0.0));
+        if self.cursor - self.region.lly < header_height + first_height && !self.is_at_top() {
+            self.next_page();
+        }
+        self.draw_table_rows(&header, header_rows.clone(), &widths, font_size);
+
+        // rows at the top of a page (below the repeated header) are drawn even if they don't fit
+        let mut page_start = true;
+        for block in blocks {
+            if self.cursor - self.region.lly < body.height(block.clone()) && !page_start && !self.is_at_top() {
+                self.next_page();
+                if repeat_header {
+                    self.draw_table_rows(&header, header_rows.clone(), &widths, font_size);
+                }
+            }
+            self.draw_table_rows(&body, block, &widths, font_size);
+            page_start = false;
+        }
+        Ok(())
+    }
+
+    /// Places the cells into the grid of the columns and wraps them to the width of their columns.
+    /// A row is as high as its highest cell, cells that span several rows make the last of the
+    /// rows higher if necessary.
+    fn layout_table(&self, rows: &[Vec<TableCell>], widths: &[Pt], font_size: f64)
+    -> Result<TableGrid, Error>
+    {
+        let leading = Pt(font_size * FLOW_LEADING);
+        let cell_height = |lines: usize| leading * lines.max(1) as f64 + CELL_PADDING * 2.0;
+
+        let cells = place_cells(rows, widths.len())?.into_iter().map(|(row, column, cell)| {
+            let width = widths[column..column + cell.colspan].iter().fold(Pt(0.0), |width, w| width + *w);
+            let lines = self.wrap_runs(&cell.content, width - CELL_PADDING * 2.0, font_size);
+            GridCell { row, column, colspan: cell.colspan, rowspan: cell.rowspan, align: cell.align, valign: cell.valign, lines }
+        }).collect::<Vec<_>>();
+
+        let mut heights = vec![cell_height(0); rows.len()];
+        for cell in cells.iter().filter(|cell| cell.rowspan == 1) {
+            heights[cell.row] = Pt(heights[cell.row].0.max(cell_height(cell.lines.len()).0));
+        }
+        for cell in cells.iter().filter(|cell| cell.rowspan > 1) {
+            let spanned = heights[cell.row..cell.row + cell.rowspan].iter().fold(Pt(0.0), |height, row| height + *row);
+            let missing = cell_height(cell.lines.len()) - spanned;
+            if missing.0 > 0.0 {
+                heights[cell.row + cell.rowspan - 1] += missing;
+            }
+        }
+
+        Ok(TableGrid { cells, heights })
+    }
+
+    /// Draws the rows of the table with a border around every cell at the current
+    /// position and moves the position below them
+    fn draw_table_rows(&mut self, grid: &TableGrid, rows: Range<usize>, widths: &[Pt], font_size: f64)
+    {
+        let leading = Pt(font_size * FLOW_LEADING);
+        let top = self.cursor;
+        let left = self.region.llx + self.indent.into();
+
+        for cell in grid.cells.iter().filter(|cell| rows.contains(&cell.row)) {
+            let x = left + widths[..cell.column].iter().fold(Pt(0.0), |x, width| x + *width);
+            let y = top - grid.height(rows.start..cell.row);
+            let width = widths[cell.column..cell.column + cell.colspan].iter().fold(Pt(0.0), |width, w| width + *w);
+            let height = grid.height(cell.row..cell.row + cell.rowspan);
+
+            let (inner_width, inner_height) = (width - CELL_PADDING * 2.0, height - CELL_PADDING * 2.0);
+            let text_height = leading * cell.lines.len() as f64;
+            let offset_y = match cell.valign {
+                VerticalAlignment::Top => Pt(0.0),
+                VerticalAlignment::Middle => (inner_height - text_height) * 0.5,
+                VerticalAlignment::Bottom => inner_height - text_height,
+            };
+            for (idx, line) in cell.lines.iter().enumerate() {
+                let offset_x = match cell.align {
+                    HorizontalAlignment::Left => Pt(0.0),
+                    HorizontalAlignment::Center => (inner_width - self.line_width(line, font_size)) * 0.5,
+                    HorizontalAlignment::Right => inner_width - self.line_width(line, font_size),
+                };
+                let baseline = y - CELL_PADDING - offset_y - leading * idx as f64 - Pt(font_size);
+                self.draw_line(line, font_size, x + CELL_PADDING + offset_x, baseline);
+            }
+
+            let corner = |x: Pt, y: Pt| (Point { x, y }, false);
+            self.layer.save_graphics_state();
+            self.layer.set_outline_thickness(CELL_BORDER);
+            self.layer.add_shape(Line {
+                points: vec![corner(x, y), corner(x + width, y), corner(x + width, y - height), corner(x, y - height)],
+                is_closed: true,
+                has_fill: false,
+                has_stroke: true,
+                is_clipping_path: false,
+            });
+            self.layer.restore_graphics_state();
+        }
+
+        self.cursor = Pt((top - grid.height(rows)).0.max(self.region.lly.0));
+    }
+
+    /// Wraps runs of text to the width, returns the runs of each line
+    fn wrap_runs(&self, runs: &[(&str, &IndirectFontRef, Option<&Color>)], width: Pt, font_size: f64)
+    -> Vec<Vec<Run>>
+    {
+        let text: String = runs.iter().map(|run| run.0).collect();
+        // byte offset of the start of every run
+        let starts: Vec<usize> = runs.iter().scan(0, |offset, run| { let start = *offset; *offset += run.0.len(); Some(start) }).collect();
+        let split = |range: ::std::ops::Range<usize>| -> Vec<Run> {
+            runs.iter().zip(starts.iter()).filter_map(|(run, start)| {
+                let (from, to) = (range.start.max(*start), range.end.min(start + run.0.len()));
+                if from < to {
+                    Some(Run { text: text[from..to].to_string(), font: run.1.clone(), color: run.2.cloned() })
+                } else {
+                    None
+                }
+            }).collect()
+        };
+
+        let doc = self.layer.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        wrap_ranges(&text, |_| width, |range| {
+            split(range).iter().fold(Pt(0.0), |width, run| width + text_width(&doc.fonts, &run.font, &run.text, font_size))
+        }).into_iter().map(split).collect()
+    }
+
+    /// Draws the paragraphs, that are still kept for the next paragraph.
+    /// This is also done when the flow is dropped.
+    pub fn finish(mut self)
+    {
+        self.flush();
+    }
+
+    fn flush(&mut self)
+    {
+        for paragraph in ::std::mem::take(&mut self.pending) {
+            self.place(paragraph);
+        }
+    }
+
+    #[inline]
+    fn is_at_top(&self)
+    -> bool
+    {
+        self.cursor == self.region.ury
+    }
+
+    /// Draws the paragraph, split over as many pages as necessary
+    fn place(&mut self, paragraph: Paragraph)
+    {
+        let font_size = Pt(paragraph.font_size);
+        let grid = self.baseline_grid();
+        let leading = match grid {
+            Some((spacing, _)) => spacing * (paragraph.leading().0 / spacing.0 - GRID_TOLERANCE).ceil().max(1.0),
+            None => paragraph.leading(),
+        };
+        let mut lines = &paragraph.lines[..];
+
+        while !lines.is_empty() {
+            let mut baseline = self.cursor - font_size;
+            if let Some((spacing, offset)) = grid {
+                baseline = snap_to_grid(baseline, self.page_height(), spacing, offset);
+            }
+            let available = line_capacity(baseline + font_size - self.region.lly, paragraph.font_size, leading);
+            let count = split_lines(lines.len(), available, self.orphan_lines, self.widow_lines, self.is_at_top());
+
+            for line in &lines[..count] {
+                self.draw_line(line, paragraph.font_size, self.region.llx + paragraph.indent, baseline);
+                self.cursor = baseline + font_size - leading;
+                baseline -= leading;
+            }
+            lines = &lines[count..];
+
+            if !lines.is_empty() {
+                self.next_page();
+            }
+        }
+    }
+
+    /// Spacing and offset of the baseline grid of the document, see
+    /// `PdfDocumentReference::set_baseline_grid()`
+    fn baseline_grid(&self)
+    -> Option<(Pt, Pt)>
+    {
+        self.layer.document.upgrade().unwrap().borrow().baseline_grid
+    }
+
+    fn page_height(&self)
+    -> Pt
+    {
+        self.layer.document.upgrade().unwrap().borrow().layer_page(&self.layer).height
+    }
+
+    /// Width of the runs of a line
+    fn line_width(&self, runs: &[Run], font_size: f64)
+    -> Pt
+    {
+        let doc = self.layer.document.upgrade().unwrap();
+        let doc = doc.borrow();
+        runs.iter().fold(Pt(0.0), |width, run| width + text_width(&doc.fonts, &run.font, &run.text, font_size))
+    }
+
+    /// Writes the runs of a line, starting at the baseline position
+    fn draw_line(&self, runs: &[Run], font_size: f64, x: Pt, y: Pt)
+    {
+        if runs.is_empty() {
+            return;
+        }
+
+        if runs.iter().all(|run| run.color.is_none()) {
+            self.layer.begin_text_section();
+            self.layer.set_text_cursor(x.into(), y.into());
+            for run in runs {
+                self.layer.set_font(&run.font, font_size);
+                self.layer.write_text(run.text.clone(), &run.font);
+            }
+            self.layer.end_text_section();
+            return;
+        }
+
+        // the color can't be reset inside of a text section, so every run is written on its own
+        let document = self.layer.document.upgrade().unwrap();
+        let mut x = x;
+        for run in runs {
+            if let Some(ref color) = run.color {
+                self.layer.save_graphics_state();
+                self.layer.set_fill_color(color.clone());
+            }
+            self.layer.begin_text_section();
+            self.layer.set_text_cursor(x.into(), y.into());
+            self.layer.set_font(&run.font, font_size);
+            self.layer.write_text(run.text.clone(), &run.font);
+            self.layer.end_text_section();
+            if run.color.is_some() {
+                self.layer.restore_graphics_state();
+            }
+            x += text_width(&document.borrow().fonts, &run.font, &run.text, font_size);
+        }
+    }
+
+    /// Continues on the next page, in a layer with the name of the current layer. Forms have
+    /// no pages, their content continues below the region instead.
+    fn next_page(&mut self)
+    {
+        if self.layer.form.is_some() {
+            self.region = self.region.translated(Pt(0.0), Pt(0.0) - self.region.height());
+            self.cursor = self.region.ury;
+            return;
+        }
+
+        let document = self.layer.document.upgrade().unwrap();
+        let next = self.layer.page.0 + 1;
+        let (exists, width, height, layer_name) = {
+            let doc = document.borrow();
+            let page = &doc.pages[self.layer.page.0];
+            (next < doc.pages.len(), page.width, page.height, page.layers[self.layer.layer.0].name.clone())
+        };
+
+        let doc = PdfDocumentReference { document: Rc::clone(&document) };
+        self.layer = if exists {
+            doc.get_page(PdfPageIndex(next)).add_layer(layer_name)
+        } else {
+            let (page, layer) = doc.add_page(width.into(), height.into(), layer_name);
+            doc.get_page(page).get_layer(layer)
+        };
+        if let Some(region) = self.region_name.as_ref().and_then(|name| doc.get_page(self.layer.page).region(name)) {
+            self.region = region;
+        }
+        self.cursor = self.region.ury;
+    }
+}
+
+impl Drop for TextFlow {
+    fn drop(&mut self)
+    {
+        if self.layer.document.upgrade().is_some() {
+            self.flush();
+        }
+    }
+}
+
+/// Places the cells of the rows into a grid with `columns` columns, returns the row and column
+/// of every cell. The cells of a row fill the free columns from the left.
+fn place_cells<'a, 'b>(rows: &'b [Vec<TableCell<'a>>], columns: usize)
+-> Result<Vec<(usize, usize, &'b TableCell<'a>)>, Error>
+{
+    let invalid = || Error::Pdf(PdfError::InvalidTableSpan);
+    let mut occupied = vec![vec![false; columns]; rows.len()];
+    let mut placed = Vec::new();
+
+    for (row, cells) in rows.iter().enumerate() {
+        let mut column = 0;
+        for cell in cells {
+            while column < columns && occupied[row][column] {
+                column += 1;
+            }
+            if cell.colspan == 0 || cell.rowspan == 0 || column + cell.colspan > columns || row + cell.rowspan > rows.len() {
+                return Err(invalid());
+            }
+            for covered in &mut occupied[row..row + cell.rowspan] {
+                if covered[column..column + cell.colspan].iter().any(|&taken| taken) {
+                    return Err(invalid());
+                }
+                for taken in &mut covered[column..column + cell.colspan] {
+                    *taken = true;
+                }
+            }
+            placed.push((row, column, cell));
+            column += cell.colspan;
+        }
+    }
+
+    Ok(placed)
+}
+
+/// Moves the baseline down to the next line of the grid, whose lines are `spacing` apart,
+/// starting `offset` below the top of the page
+fn snap_to_grid(baseline: Pt, page_height: Pt, spacing: Pt, offset: Pt)
+-> Pt
+{
+    let lines = ((page_height - offset - baseline).0 / spacing.0 - GRID_TOLERANCE).ceil().max(0.0);
+    page_height - offset - spacing * lines
+}
+
+/// Number of lines of a paragraph with `lines` lines, that are placed into the `available` lines
+/// of the current page: fewer than `orphans` lines at the bottom of the page move to the next
+/// page, and at least `widows` lines are left for the next page. If the page is empty (`at_top`),
+/// at least one line is placed, so that the flow always makes progress.
+fn split_lines(lines: usize, available: usize, orphans: usize, widows: usize, at_top: bool)
+-> usize
+{
+    if lines <= available {
+        return lines;
+    }
+
+    let mut count = available;
+    if lines - count < widows {
+        count = lines.saturating_sub(widows);
+    }
+    if count < orphans {
+        count = 0;
+    }
+    if count == 0 && at_top {
+        count = available.clamp(1, lines);
+    }
+    count
+}
+
+#[test]
+fn test_snap_to_grid() {
+    // grid lines at 780, 768, 756, ... on a page of 800pt
+    assert_eq!(snap_to_grid(Pt(790.0), Pt(800.0), Pt(12.0), Pt(20.0)), Pt(780.0));
+    assert_eq!(snap_to_grid(Pt(767.0), Pt(800.0), Pt(12.0), Pt(20.0)), Pt(756.0));
+    // baselines on a grid line stay there
+    assert_eq!(snap_to_grid(Pt(768.0), Pt(800.0), Pt(12.0), Pt(20.0)), Pt(768.0));
+}
+
+#[test]
+fn test_split_lines() {
+    // everything fits
+    assert_eq!(split_lines(5, 10, 2, 2, false), 5);
+    // a single line at the bottom of the page (orphan) moves to the next page
+    assert_eq!(split_lines(5, 1, 2, 2, false), 0);
+    // a single line at the top of the next page (widow) pulls a line over
+    assert_eq!(split_lines(5, 4, 2, 2, false), 3);
+    // both can't be satisfied: the whole paragraph moves
+    assert_eq!(split_lines(3, 2, 2, 2, false), 0);
+    // an empty page always gets lines
+    assert_eq!(split_lines(3, 2, 2, 2, true), 2);
+    assert_eq!(split_lines(3, 0, 2, 2, true), 1);
+    // without widow / orphan control, the page is filled
+    assert_eq!(split_lines(5, 4, 0, 0, false), 4);
+}
+
+#[test]
+fn test_add_image_flowed() {
+    use {PdfDocument, BuiltinFont, ImageXObject, ColorSpace, ColorBits};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    let image = || Image::from(ImageXObject::new(Px(200), Px(100), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![0; 20000]));
+
+    // 100pt wide, 50pt high, with a caption of one line
+    let mut flow = TextFlow::new(layer, Rect { llx: Pt(50.0), lly: Pt(100.0), urx: Pt(250.0), ury: Pt(300.0) });
+    flow.add_image_flowed(image(), Pt(100.0).into(), Some(("Figure 1", &font, 10.0)));
+    assert_eq!(flow.current_page(), PdfPageIndex(0));
+    assert!((flow.cursor.0 - (300.0 - 50.0 - 4.0 - 12.0)).abs() < 1e-3);
+
+    // the image and its caption don't fit below the first one, both move to the next page
+    flow.add_space(Pt(100.0).into());
+    flow.add_image_flowed(image(), Pt(100.0).into(), Some(("Figure 2", &font, 10.0)));
+    assert_eq!(flow.current_page(), PdfPageIndex(1));
+    assert!((flow.cursor.0 - 234.0).abs() < 1e-3);
+
+    // an image that is higher than the region is scaled down, so that it fits with its caption
+    flow.add_image_flowed(Image::from(ImageXObject::new(Px(10), Px(100), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![0; 1000])), Pt(200.0).into(), Some(("Figure 3", &font, 10.0)));
+    assert_eq!(flow.current_page(), PdfPageIndex(2));
+    assert!((flow.cursor.0 - 100.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_flow_group() {
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+
+    // room for 8 lines of 10pt
+    let mut flow = TextFlow::new(layer, Rect { llx: Pt(50.0), lly: Pt(100.0), urx: Pt(250.0), ury: Pt(196.0) });
+    flow.add_paragraph("1\n2\n3\n4\n5", &font, 10.0, false);
+    let mut calls = 0;
+    flow.flow_group(|flow| {
+        calls += 1;
+        flow.add_paragraph("Figure\nCaption", &font, 10.0, false);
+        flow.add_paragraph("Source", &font, 10.0, false);
+        flow.add_paragraph("Note", &font, 10.0, false);
+    });
+
+    // the group was placed at the top of the second page, nothing of it is left on the first page
+    assert_eq!(calls, 2);
+    assert_eq!(flow.current_page(), PdfPageIndex(1));
+    assert_eq!(flow.cursor, Pt(196.0 - 48.0));
+    let document = doc.document.borrow();
+    let first_page_texts = document.pages[0].layers.iter()
+        .map(|layer| layer.operations.iter().filter(|op| op.operator == "Tj").count())
+        .sum::<usize>();
+    assert_eq!(first_page_texts, 5);
+    assert_eq!(document.pages.len(), 2);
+}
+
+#[test]
+fn test_add_table_flowed() {
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+
+    // rows of 10pt text are 18pt high, the region has room for 5 rows
+    let mut flow = TextFlow::new(layer, Rect { llx: Pt(50.0), lly: Pt(100.0), urx: Pt(250.0), ury: Pt(190.0) });
+    let header = vec![vec![TableCell::text("Name", &font), TableCell::text("Value", &font)]];
+    let rows: Vec<_> = (0..10).map(|_| vec![TableCell::text("Row", &font), TableCell::text("1", &font)]).collect();
+    flow.add_table_flowed(&header, &rows, &[Pt(100.0).into(), Pt(100.0).into()], 10.0, true).unwrap();
+
+    // 4 rows below the header on each page, the last 2 rows on the third page
+    assert_eq!(flow.current_page(), PdfPageIndex(2));
+    assert!((flow.cursor.0 - (190.0 - 18.0 * 3.0)).abs() < 1e-3);
+    let document = doc.document.borrow();
+    let texts = |page: usize| document.pages[page].layers.iter()
+        .map(|layer| layer.operations.iter().filter(|op| op.operator == "Tj").count())
+        .sum::<usize>();
+    assert_eq!((texts(0), texts(1), texts(2)), (10, 10, 6));
+}
+
+#[test]
+fn test_table_spans() {
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, _, _) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let cell = |colspan, rowspan| TableCell { colspan, rowspan, ..TableCell::text("x", &font) };
+
+    // | a a | b |
+    // | c | d | b |
+    // | e | f | g |
+    let rows = vec![
+        vec![cell(2, 1), cell(1, 2)],
+        vec![cell(1, 1), cell(1, 1)],
+        vec![cell(1, 1), cell(1, 1), cell(1, 1)],
+    ];
+    let placed: Vec<_> = place_cells(&rows, 3).unwrap().into_iter().map(|(row, column, _)| (row, column)).collect();
+    assert_eq!(placed, vec![(0, 0), (0, 2), (1, 0), (1, 1), (2, 0), (2, 1), (2, 2)]);
+
+    // too many columns, too many rows, overlapping a row span
+    assert!(place_cells(&[vec![cell(2, 1), cell(2, 1)]], 3).is_err());
+    assert!(place_cells(&[vec![cell(1, 2)]], 3).is_err());
+    assert!(place_cells(&[vec![cell(1, 1), cell(1, 2)], vec![cell(2, 1)]], 3).is_err());
+
+    let grid = TableGrid {
+        cells: place_cells(&rows, 3).unwrap().into_iter()
+            .map(|(row, column, cell)| GridCell { row, column, colspan: cell.colspan, rowspan: cell.rowspan, align: cell.align, valign: cell.valign, lines: Vec::new() })
+            .collect(),
+        heights: vec![Pt(10.0); 3],
+    };
+    assert_eq!(grid.blocks(), vec![0..2, 2..3]);
+    assert_eq!(grid.height(0..2), Pt(20.0));
+}
+
+#[test]
+fn test_table_cell_alignment() {
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Courier).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+
+    // the second cell is 3 lines high, "1" (6pt wide in Courier 10pt) is aligned in a 100pt wide cell
+    let mut flow = TextFlow::new(layer, Rect { llx: Pt(50.0), lly: Pt(100.0), urx: Pt(250.0), ury: Pt(300.0) });
+    let number = TableCell { align: HorizontalAlignment::Right, valign: VerticalAlignment::Bottom, ..TableCell::text("1", &font) };
+    let centered = TableCell { align: HorizontalAlignment::Center, valign: VerticalAlignment::Middle, ..TableCell::text("1", &font) };
+    let rows = vec![vec![number, TableCell::text("a\nb\nc", &font)], vec![centered, TableCell::text("a\nb\nc", &font)]];
+    flow.add_table_flowed(&[], &rows, &[Pt(100.0).into(), Pt(100.0).into()], 10.0, false).unwrap();
+
+    let document = doc.document.borrow();
+    let cursors: Vec<(f64, f64)> = document.pages[0].layers[0].operations.iter()
+        .filter(|op| op.operator == "Td")
+        .map(|op| (op.operands[0].as_f64().unwrap(), op.operands[1].as_f64().unwrap()))
+        .collect();
+    // rows are 3 * 12pt + 6pt high, the last line of the row starts 3pt + 24pt + 10pt below the top
+    let (x, y) = cursors[0];
+    assert!((x - (50.0 + 3.0 + 94.0 - 6.0)).abs() < 1e-3);
+    assert!((y - (300.0 - 37.0)).abs() < 1e-3);
+    let (x, y) = cursors[4];
+    assert!((x - (50.0 + 3.0 + 44.0)).abs() < 1e-3);
+    assert!((y - (300.0 - 42.0 - 25.0)).abs() < 1e-3);
+}
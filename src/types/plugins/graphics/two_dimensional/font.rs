@@ -6,9 +6,17 @@ use lopdf::{Stream as LoStream, Dictionary as LoDictionary};
 use lopdf::StringFormat;
 use std::collections::{HashMap, BTreeMap};
 use std::iter::FromIterator;
-use Error;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::fmt;
+use {Error, PdfError};
+use types::plugins::graphics::two_dimensional::sfnt::{OpenTypeFont, has_cff_outlines, find_table, read_u16, read_u32};
+use types::plugins::graphics::two_dimensional::outline::{PathSegment, cff_glyph_outline};
+use types::plugins::graphics::two_dimensional::gsub::{Substitutions, LIGA, DLIG};
+use types::plugins::graphics::two_dimensional::variations::Tag;
 
 use rusttype::FontCollection;
+use rusttype::Font as RusttypeFont;
 use rusttype::Codepoint as Cp;
 use rusttype::GlyphId as Gid;
 
@@ -21,6 +29,19 @@ pub enum Font {
     ExternalFont(ExternalFont),
 }
 
+impl Font {
+
+    /// Outline of a glyph as a path, empty for built-in fonts (see `ExternalFont::glyph_outline()`)
+    pub fn glyph_outline(&self, glyph_id: u16)
+    -> Vec<PathSegment>
+    {
+        match *self {
+            Font::ExternalFont(ref font) => font.glyph_outline(glyph_id),
+            Font::BuiltinFont(_) => Vec::new(),
+        }
+    }
+}
+
 /// Standard built-in PDF fonts
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BuiltinFont {
@@ -82,14 +103,240 @@ impl Into<LoDictionary> for BuiltinFont {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ExternalFont {
     /// Font data
-    pub(crate) font_bytes: Vec<u8>,
+    pub(crate) font_bytes: Arc<[u8]>,
     /// Font name, for adding as a resource on the document
     pub(crate) face_name: String,
     /// Is the font written vertically? Default: false
     pub(crate) vertical_writing: bool,
+    /// The parsed font face. Only the table directory and the metrics are read when the
+    /// font is created, glyph outlines are read when the glyphs are drawn or measured
+    face: FontFace,
+    /// Which ligatures of the font are used. Default: `Ligatures::Standard`
+    pub(crate) ligatures: Ligatures,
+    /// Other OpenType features that are applied to the text. Default: none
+    pub(crate) features: Vec<FeatureTag>,
+    /// The substitution lookups of the `GSUB` table, parsed on first use
+    substitutions: RefCell<Option<Arc<Substitutions>>>,
+    /// Flags of the font descriptor, `None` if they are read from the font tables.
+    /// See `PdfDocumentReference::set_font_flags()`
+    pub(crate) flags: Option<FontFlags>,
+    /// Text (in UTF-16 code units) of glyphs that were drawn with
+    /// `PdfLayerReference::add_glyphs_with_text()`, for the `ToUnicode` map
+    pub(crate) shaped_glyphs: BTreeMap<u16, Vec<u32>>,
+}
+
+/// Tag of an OpenType feature, e.g. `Tag(*b"smcp")`. The features that substitute single glyphs
+/// or sequences of glyphs (`GSUB` lookup types 1, 3 and 4) are supported, such as:
+///
+/// - `smcp` / `c2sc`: small capitals from lowercase / uppercase letters
+/// - `onum` / `lnum`: oldstyle / lining figures
+/// - `tnum` / `pnum`: tabular / proportional figures
+/// - `zero`: slashed zero
+/// - `sups` / `subs`: superscript / subscript glyphs
+/// - `ss01` - `ss20`, `salt`: stylistic sets and alternates (the first alternate is used)
+///
+/// Features that depend on the surrounding glyphs (contextual lookups, e.g. `calt` and
+/// most `frac` implementations) or change the positions of glyphs (`GPOS`, e.g. `kern`)
+/// are not supported. Ligatures are set with `Ligatures` instead.
+pub type FeatureTag = Tag;
+
+/// Style of a font for the `/Flags` of its font descriptor (PDF Reference, section 5.7.1).
+/// Viewers use them to pick a similar substitute font, if the embedded font can't be
+/// used, and to hint the glyphs. By default, the flags are read from the font tables:
+/// `post` (fixed pitch), `OS/2` (serif, script, symbolic, italic) and `cmap` (symbolic).
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FontFlags {
+    /// All glyphs have the same width (monospace)
+    pub fixed_pitch: bool,
+    /// The glyphs have serifs
+    pub serif: bool,
+    /// The font contains glyphs outside of the standard Latin character set,
+    /// e.g. a symbol or pictograph font
+    pub symbolic: bool,
+    /// The glyphs resemble cursive handwriting
+    pub script: bool,
+    /// The glyphs are slanted (italic or oblique)
+    pub italic: bool,
+    /// The font has no lowercase letters
+    pub all_cap: bool,
+    /// The lowercase letters are small capitals
+    pub small_cap: bool,
+    /// Bold glyphs are emboldened at small sizes
+    pub force_bold: bool,
+}
+
+impl FontFlags {
+
+    /// Reads the flags from the tables of a (TrueType or OpenType) font
+    pub(crate) fn from_font(data: &[u8])
+    -> Self
+    {
+        let mut flags = FontFlags::default();
+
+        if let Some(post) = find_table(data, b"post") {
+            flags.fixed_pitch = read_u32(post, 12).map(|fixed_pitch| fixed_pitch != 0).unwrap_or(false);
+        }
+
+        if let Some(os2) = find_table(data, b"OS/2") {
+            // the high byte of sFamilyClass is the IBM font class
+            match read_u16(os2, 30).map(|class| class >> 8) {
+                Some(1..=5) | Some(7) => flags.serif = true,
+                Some(10) => flags.script = true,
+                Some(12) => flags.symbolic = true,
+                _ => { },
+            }
+            // bit 0 of fsSelection: italic, bit 9: oblique
+            flags.italic = read_u16(os2, 62).map(|selection| selection & 0x201 != 0).unwrap_or(false);
+        } else if let Some(head) = find_table(data, b"head") {
+            // bit 1 of macStyle: italic
+            flags.italic = read_u16(head, 44).map(|style| style & 2 != 0).unwrap_or(false);
+        }
+
+        // fonts with only a symbol subtable (platform 3, encoding 0) in their cmap
+        if let Some(cmap) = find_table(data, b"cmap") {
+            let num_tables = read_u16(cmap, 2).unwrap_or(0) as usize;
+            let encodings = (0..num_tables)
+                .filter_map(|idx| Some((read_u16(cmap, 4 + idx * 8)?, read_u16(cmap, 6 + idx * 8)?)))
+                .collect::<Vec<(u16, u16)>>();
+            if encodings.contains(&(3, 0)) && !encodings.iter().any(|&(platform, encoding)| platform == 0 || (platform == 3 && encoding != 0)) {
+                flags.symbolic = true;
+            }
+        }
+
+        flags
+    }
+
+    /// Value of the `/Flags` entry, exactly one of Symbolic (bit 3) and Nonsymbolic (bit 6) is set
+    pub(crate) fn bits(self)
+    -> i64
+    {
+        let bits = [
+            (self.fixed_pitch, 1), (self.serif, 1 << 1), (self.symbolic, 1 << 2), (self.script, 1 << 3),
+            (!self.symbolic, 1 << 5), (self.italic, 1 << 6), (self.all_cap, 1 << 16),
+            (self.small_cap, 1 << 17), (self.force_bold, 1 << 18),
+        ];
+        bits.iter().filter(|bit| bit.0).map(|bit| bit.1).sum()
+    }
+}
+
+/// Italic angle of the font from its `post` table, in degrees counter-clockwise from the vertical
+fn italic_angle(data: &[u8])
+-> f64
+{
+    find_table(data, b"post")
+        .and_then(|post| read_u32(post, 4))
+        .map(|angle| f64::from(angle as i32) / 65536.0)
+        .unwrap_or(0.0)
+}
+
+/// Which ligatures (`GSUB` ligature substitutions) are used for encoding text in an
+/// external font. Fonts without a `GSUB` table are not affected.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Ligatures {
+    /// Every character is drawn with its own glyph
+    None,
+    /// Standard ligatures (`liga`), such as "fi" or "fl"
+    #[default]
+    Standard,
+    /// Standard and discretionary ligatures (`liga` and `dlig`), such as "ct" or "st"
+    Discretionary,
+}
+
+impl Ligatures {
+
+    /// The OpenType features of the ligatures
+    fn features(self)
+    -> &'static [[u8; 4]]
+    {
+        match self {
+            Ligatures::None => &[],
+            Ligatures::Standard => &[LIGA],
+            Ligatures::Discretionary => &[LIGA, DLIG],
+        }
+    }
+}
+
+/// Parsed tables of an external font, used for encoding and measuring text
+#[derive(Clone)]
+pub(crate) enum FontFace {
+    /// Font with TrueType outlines
+    TrueType(RusttypeFont<'static>),
+    /// OpenType font with CFF outlines, which `rusttype` can't read. Only its metrics are parsed.
+    Cff(Arc<OpenTypeFont>),
+}
+
+impl FontFace {
+
+    /// Glyph ID of a character, 0 if the font doesn't contain it
+    pub(crate) fn glyph_index(&self, codepoint: u32)
+    -> u16
+    {
+        match *self {
+            FontFace::TrueType(ref font) => font.glyph(Cp(codepoint)).id().0 as u16,
+            FontFace::Cff(ref font) => font.glyph_index(codepoint),
+        }
+    }
+
+    /// Advance width of a glyph in font units, `None` if there is no such glyph
+    pub(crate) fn advance_width(&self, glyph: u16)
+    -> Option<f64>
+    {
+        match *self {
+            FontFace::TrueType(ref font) => font.glyph(Gid(u32::from(glyph))).standalone().get_data()
+                .map(|data| f64::from(data.unit_h_metrics.advance_width)),
+            FontFace::Cff(ref font) => font.advance_width(glyph).map(f64::from),
+        }
+    }
+
+    /// Height of the outline of a glyph in font units (TrueType fonts only)
+    fn glyph_height(&self, glyph: u16, descent: i32)
+    -> Option<i32>
+    {
+        match *self {
+            FontFace::TrueType(ref font) => font.glyph(Gid(u32::from(glyph))).standalone().get_data()
+                .and_then(|data| data.extents)
+                .map(|extents| extents.max.y - extents.min.y - descent),
+            FontFace::Cff(_) => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn units_per_em(&self)
+    -> f64
+    {
+        match *self {
+            FontFace::TrueType(ref font) => f64::from(font.units_per_em()),
+            FontFace::Cff(ref font) => f64::from(font.units_per_em),
+        }
+    }
+
+    /// Ascent and descent in font units
+    pub(crate) fn vertical_metrics(&self)
+    -> (f64, f64)
+    {
+        match *self {
+            FontFace::TrueType(ref font) => {
+                let metrics = font.v_metrics_unscaled();
+                (f64::from(metrics.ascent), f64::from(metrics.descent))
+            },
+            FontFace::Cff(ref font) => (f64::from(font.ascent), f64::from(font.descent)),
+        }
+    }
+}
+
+impl fmt::Debug for ExternalFont {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExternalFont")
+            .field("face_name", &self.face_name)
+            .field("font_bytes", &self.font_bytes.len())
+            .field("vertical_writing", &self.vertical_writing)
+            .field("ligatures", &self.ligatures)
+            .field("features", &self.features)
+            .finish()
+    }
 }
 
 /// The text rendering mode determines how a text is drawn
@@ -130,27 +377,179 @@ impl Into<i64> for TextRenderingMode {
 
 impl ExternalFont {
 
-    /// Creates a new font. The `index` is used for naming / identifying the font
+    /// Creates a new font. The `index` is used for naming / identifying the font.
+    /// TrueType fonts and OpenType fonts with CFF outlines (`.otf`) are supported,
+    /// the latter require PDF 1.6.
+    ///
+    /// The font face is parsed here, fails if the font can't be parsed. The `GSUB` table
+    /// is parsed when the font is first used, glyphs are read when they are drawn or measured.
     pub fn new<R>(mut font_stream: R, font_index: usize)
     -> Result<Self, Error> where R: ::std::io::Read
     {
-        // read font from stream and parse font metrics
         let mut buf = Vec::<u8>::new();
         font_stream.read_to_end(&mut buf)?;
+        let font_bytes: Arc<[u8]> = Arc::from(buf);
 
-        let face_name = {
-            let collection = FontCollection::from_bytes(buf.clone())?;
-            collection.clone().into_font().unwrap_or(collection.font_at(0)?);
-            format!("F{}", font_index)
+        let face = if has_cff_outlines(&font_bytes) {
+            FontFace::Cff(Arc::new(OpenTypeFont::parse(&font_bytes).ok_or(Error::Pdf(PdfError::FontFaceError))?))
+        } else {
+            let collection = FontCollection::from_bytes(font_bytes.clone())?;
+            FontFace::TrueType(match collection.clone().into_font() {
+                Ok(font) => font,
+                Err(_) => collection.font_at(0)?,
+            })
         };
 
         Ok(Self {
-            font_bytes: buf,
-            face_name: face_name,
+            font_bytes,
+            face_name: format!("F{}", font_index),
             vertical_writing: false,
+            face,
+            ligatures: Ligatures::default(),
+            features: Vec::new(),
+            substitutions: RefCell::new(None),
+            flags: None,
+            shaped_glyphs: BTreeMap::new(),
         })
     }
 
+    /// Whether the font has CFF outlines, which are embedded as an OpenType font program
+    #[inline]
+    pub(crate) fn is_cff(&self)
+    -> bool
+    {
+        has_cff_outlines(&self.font_bytes)
+    }
+
+    /// Returns the parsed font face
+    #[inline]
+    pub(crate) fn get_font(&self)
+    -> FontFace
+    {
+        self.face.clone()
+    }
+
+    /// The substitution lookups of the font, parsing the `GSUB` table on the first call
+    fn substitutions(&self)
+    -> Arc<Substitutions>
+    {
+        self.substitutions.borrow_mut()
+            .get_or_insert_with(|| Arc::new(Substitutions::parse(&self.font_bytes)))
+            .clone()
+    }
+
+    /// The OpenType features that are applied: the ligatures and the other features
+    fn active_features(&self)
+    -> Vec<[u8; 4]>
+    {
+        let mut features = self.ligatures.features().to_vec();
+        features.extend(self.features.iter().map(|feature| feature.0));
+        features
+    }
+
+    /// Glyph IDs of a text, with the ligatures and features of the font substituted
+    pub(crate) fn encode_text(&self, font: &FontFace, text: &str)
+    -> Vec<u16>
+    {
+        let glyphs = text.chars().map(|ch| font.glyph_index(ch as u32)).collect();
+        let features = self.active_features();
+        if features.is_empty() {
+            return glyphs;
+        }
+        self.substitutions().apply(glyphs, &features)
+    }
+
+    /// Maps the glyph IDs back to the text they show. Substituted glyphs (ligatures,
+    /// small caps, ...) map to the characters they replace, glyphs drawn with
+    /// `add_glyphs_with_text()` to the text they were drawn with.
+    pub(crate) fn glyph_text(&self, font: &FontFace)
+    -> HashMap<u16, String>
+    {
+        let mut text = HashMap::new();
+        for unicode in (0x0020..0xffff).rev() {
+            let glyph_id = font.glyph_index(unicode);
+            if glyph_id != 0 {
+                if let Some(ch) = ::std::char::from_u32(unicode) {
+                    // the lowest code point wins if several characters share a glyph
+                    text.insert(glyph_id, ch.to_string());
+                }
+            }
+        }
+
+        for (substitute, glyphs) in self.substitutions().substituted_glyphs(&self.active_features()) {
+            let replaced = glyphs.iter().map(|glyph| text.get(glyph).cloned()).collect::<Option<Vec<String>>>();
+            if let Some(replaced) = replaced {
+                text.entry(substitute).or_insert_with(|| replaced.concat());
+            }
+        }
+
+        for (glyph, units) in &self.shaped_glyphs {
+            let units = units.iter().map(|unit| *unit as u16).collect::<Vec<u16>>();
+            text.entry(*glyph).or_insert_with(|| String::from_utf16_lossy(&units));
+        }
+
+        text
+    }
+
+    /// Glyph ID of a character in this font, 0 if the font doesn't contain it
+    pub fn glyph_id(&self, ch: char)
+    -> u16
+    {
+        self.face.glyph_index(ch as u32)
+    }
+
+    /// Outline of a glyph as a path, scaled to the em square (1.0 = the font size). TrueType
+    /// outlines consist of quadratic curves, CFF outlines of cubic curves. The outline is
+    /// empty for glyphs without an outline (e.g. spaces) and for glyphs that can't be read.
+    pub fn glyph_outline(&self, glyph_id: u16)
+    -> Vec<PathSegment>
+    {
+        let font = self.get_font();
+
+        let outline = match font {
+            FontFace::Cff(_) => cff_glyph_outline(&self.font_bytes, glyph_id).unwrap_or_default(),
+            FontFace::TrueType(ref font) => {
+                use rusttype::{Scale, Segment};
+
+                // scaled to the line height in font units, so that the coordinates are in font units
+                let metrics = font.v_metrics_unscaled();
+                let glyph = font.glyph(Gid(u32::from(glyph_id))).scaled(Scale::uniform(metrics.ascent - metrics.descent));
+                let mut outline = Vec::new();
+                for contour in glyph.shape().unwrap_or_default() {
+                    for (idx, segment) in contour.segments.iter().enumerate() {
+                        let (start, segment) = match *segment {
+                            Segment::Line(ref line) => (line.p[0], PathSegment::LineTo(f64::from(line.p[1].x), f64::from(line.p[1].y))),
+                            Segment::Curve(ref curve) => (curve.p[0], PathSegment::QuadTo(
+                                f64::from(curve.p[1].x), f64::from(curve.p[1].y), f64::from(curve.p[2].x), f64::from(curve.p[2].y))),
+                        };
+                        if idx == 0 {
+                            outline.push(PathSegment::MoveTo(f64::from(start.x), f64::from(start.y)));
+                        }
+                        outline.push(segment);
+                    }
+                    outline.push(PathSegment::Close);
+                }
+                outline
+            },
+        };
+
+        let scale = 1.0 / font.units_per_em();
+        outline.into_iter().map(|segment| segment.scaled(scale)).collect()
+    }
+
+    /// Hashes the font data, used for deduplicating identical fonts
+    pub(crate) fn content_hash(&self)
+    -> u64
+    {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        self.font_bytes.hash(&mut hasher);
+        self.vertical_writing.hash(&mut hasher);
+        hasher.finish()
+    }
+
     /// Takes the font and adds it to the document and consumes the font
     pub(crate) fn into_with_document(self, doc: &mut lopdf::Document)
     -> LoDictionary
@@ -158,24 +557,31 @@ impl ExternalFont {
         use lopdf::Object;
         use lopdf::Object::*;
 
-        let face_name = self.face_name.clone();
+        // the generated names ("F0", "F1", ...) repeat in every document, the tag tells
+        // the font programs apart when documents of different fonts are merged
+        let face_name = format!("{}+{}", font_name_tag(self.content_hash()), self.face_name);
 
-        let font_buf_ref: Box<[u8]> = self.font_bytes.into_boxed_slice();
-        let collection = FontCollection::from_bytes(font_buf_ref.clone()).unwrap();
-        let font = collection.clone().into_font().unwrap_or_else(|_| {
-            collection.font_at(0).unwrap()
-        });
+        let font = self.get_font();
+        let is_cff = self.is_cff();
+        let substituted_glyphs = self.substitutions().substituted_glyphs(&self.active_features());
+        let font_buf_ref = self.font_bytes;
 
         // Extract basic font information
-        let face_metrics = font.v_metrics_unscaled();
+        let (ascent, descent) = font.vertical_metrics();
+        let (ascent, descent) = (ascent as i32, descent as i32);
 
-        let font_stream = LoStream::new(
-            LoDictionary::from_iter(vec![
-                ("Length1", Integer(font_buf_ref.len() as i64)),
-                ]),
-            font_buf_ref.to_vec())
+        // TrueType fonts are embedded as `FontFile2`, CFF fonts as the whole OpenType file (`FontFile3`)
+        let font_stream = if is_cff {
+            LoStream::new(LoDictionary::from_iter(vec![("Subtype", Name("OpenType".into()))]), font_buf_ref.to_vec())
+        } else {
+            LoStream::new(LoDictionary::from_iter(vec![("Length1", Integer(font_buf_ref.len() as i64))]), font_buf_ref.to_vec())
+        }
         .with_compression(false); /* important! font stream must not be compressed! */
 
+        #[cfg(feature = "logging")] {
+            debug!("Embedding font {} ({} bytes)", face_name, font_buf_ref.len());
+        }
+
         // Begin setting required font attributes
         let mut font_vec: Vec<(::std::string::String, Object)> = vec![
             ("Type".into(), Name("Font".into())),
@@ -189,11 +595,11 @@ impl ExternalFont {
         let mut font_descriptor_vec: Vec<(::std::string::String, Object)> = vec![
             ("Type".into(), Name("FontDescriptor".into())),
             ("FontName".into(), Name(face_name.clone().into_bytes())),
-            ("Ascent".into(), Integer(face_metrics.ascent as i64)),
-            ("Descent".into(), Integer(face_metrics.descent as i64)),
-            ("CapHeight".into(), Integer(face_metrics.ascent as i64)),
-            ("ItalicAngle".into(), Integer(0)),
-            ("Flags".into(), Integer(32)),
+            ("Ascent".into(), Integer(ascent as i64)),
+            ("Descent".into(), Integer(descent as i64)),
+            ("CapHeight".into(), Integer(ascent as i64)),
+            ("ItalicAngle".into(), Real(italic_angle(&font_buf_ref))),
+            ("Flags".into(), Integer(self.flags.unwrap_or_else(|| FontFlags::from_font(&font_buf_ref)).bits())),
             ("StemV".into(), Integer(80)),
         ];
 
@@ -208,36 +614,50 @@ impl ExternalFont {
         let mut widths = Vec::<(u32, u32)>::new();
 
         // Glyph IDs - (Unicode IDs - character width, character height)
-        let mut cmap = BTreeMap::<u32, (u32, u32, u32)>::new();
-        cmap.insert(0, (0, 1000, 1000));
+        let mut cmap = BTreeMap::<u32, (Vec<u32>, u32, u32)>::new();
+        cmap.insert(0, (vec![0], 1000, 1000));
 
         for unicode in 0x0000..0xffff {
 
-            let glyph = font.glyph(Cp(unicode));
+            let glyph_id = font.glyph_index(unicode);
 
-            if glyph.id().0 == 0 {
+            if glyph_id == 0 {
                 continue;
             }
 
-            let glyph_id = glyph.id().0;
-            let glyph = font.glyph(Gid(glyph_id));
-
-            if let Some(glyph_metrics) = glyph.standalone().get_data() {
-
-                let w = glyph_metrics.unit_h_metrics.advance_width;
+            if let Some(w) = font.advance_width(glyph_id) {
 
                 // Note: extents can be None, but then the character may still have a
                 // horizontal advance!
-                let h = glyph_metrics.extents.and_then(|extents| {
-                    Some(extents.max.y - extents.min.y - face_metrics.descent as i32)
-                }).unwrap_or(1000);
+                let h = font.glyph_height(glyph_id, descent).unwrap_or(1000);
 
                 if h > max_height {
                     max_height = h;
                 };
 
                 total_width += w as u32;
-                cmap.insert(glyph_id, (unicode as u32, w as u32, h as u32));
+                cmap.insert(u32::from(glyph_id), (vec![unicode], w as u32, h as u32));
+            }
+        }
+
+        // substituted glyphs (ligatures, small caps, ...) map to the characters they replace
+        for (substitute, glyphs) in substituted_glyphs {
+            let unicode = glyphs.iter()
+                .map(|glyph| cmap.get(&u32::from(*glyph)).map(|entry| entry.0.clone()))
+                .collect::<Option<Vec<Vec<u32>>>>();
+            if let (Some(unicode), Some(w)) = (unicode, font.advance_width(substitute)) {
+                let h = font.glyph_height(substitute, descent).unwrap_or(1000);
+                total_width += w as u32;
+                cmap.entry(u32::from(substitute)).or_insert((unicode.concat(), w as u32, h as u32));
+            }
+        }
+
+        // glyphs that were drawn by ID, the cmap of the font doesn't map them to any characters
+        for (glyph, text) in &self.shaped_glyphs {
+            if let Some(w) = font.advance_width(*glyph) {
+                let h = font.glyph_height(*glyph, descent).unwrap_or(1000);
+                total_width += w as u32;
+                cmap.entry(u32::from(*glyph)).or_insert((text.clone(), w as u32, h as u32));
             }
         }
 
@@ -267,8 +687,8 @@ impl ExternalFont {
                     cur_first_bit = (*glyph_id >> 8) as u16;
                 }
 
-                let (unicode, width, _) = *unicode_width_tuple;
-                current_cmap_block.push((*glyph_id, unicode));
+                let (ref unicode, width, _) = *unicode_width_tuple;
+                current_cmap_block.push((*glyph_id, unicode.clone()));
                 widths.push((*glyph_id, width));
             };
 
@@ -292,7 +712,7 @@ impl ExternalFont {
         let mut current_width_vec = Vec::<Object>::new();
 
         // scale the font width so that it sort-of fits into an 1000 unit square
-        let percentage_font_scaling = 1000.0 / (font.units_per_em() as f64);
+        let percentage_font_scaling = 1000.0 / font.units_per_em();
 
         for (gid, width) in widths {
             if gid == current_high_gid {
@@ -324,7 +744,7 @@ impl ExternalFont {
 
         let mut desc_fonts = LoDictionary::from_iter(vec![
             ("Type", Name("Font".into())),
-            ("Subtype", Name("CIDFontType2".into())),
+            ("Subtype", Name(if is_cff { "CIDFontType0" } else { "CIDFontType2" }.into())),
             ("BaseFont", Name(face_name.clone().into())),
             ("CIDSystemInfo", Dictionary(LoDictionary::from_iter(vec![
                     ("Registry", String("Adobe".into(), StringFormat::Literal)),
@@ -334,8 +754,11 @@ impl ExternalFont {
             w, dw,
         ]);
 
-        let font_bbox = vec![ Integer(0), Integer(max_height as i64), Integer(total_width as i64), Integer(max_height as i64) ];
-        font_descriptor_vec.push(("FontFile2".into(), Reference(doc.add_object(font_stream))));
+        let font_bbox = match font {
+            FontFace::Cff(ref font) => font.bbox.iter().map(|value| Integer(i64::from(*value))).collect(),
+            FontFace::TrueType(_) => vec![ Integer(0), Integer(max_height as i64), Integer(total_width as i64), Integer(max_height as i64) ],
+        };
+        font_descriptor_vec.push((if is_cff { "FontFile3" } else { "FontFile2" }.into(), Reference(doc.add_object(font_stream))));
 
         // although the following entry is technically not needed, Adobe Reader needs it
         font_descriptor_vec.push(("FontBBox".into(), Array(font_bbox)));
@@ -351,9 +774,24 @@ impl ExternalFont {
     }
 }
 
+/// Tag of six uppercase letters, which is written with a `+` in front of the name of an embedded
+/// font (PDF Reference, section 5.5.3 "Font Subsets"). The tag is derived from the hash of the
+/// font, so the same font always gets the same tag, which keeps deterministic files identical.
+pub(crate) fn font_name_tag(hash: u64)
+-> String
+{
+    let mut hash = hash;
+    (0..6).map(|_| {
+        let letter = (b'A' + (hash % 26) as u8) as char;
+        hash /= 26;
+        letter
+    }).collect()
+}
+
 type GlyphId = u32;
 type UnicodeCodePoint = u32;
-type CmapBlock = Vec<(GlyphId, UnicodeCodePoint)>;
+/// Glyph IDs and the characters they stand for (several characters for ligatures)
+type CmapBlock = Vec<(GlyphId, Vec<UnicodeCodePoint>)>;
 
 /// Generates a CMAP (character map) from valid cmap blocks
 fn generate_cid_to_unicode_map(face_name: String, all_cmap_blocks: Vec<CmapBlock>) -> String {
@@ -363,7 +801,8 @@ fn generate_cid_to_unicode_map(face_name: String, all_cmap_blocks: Vec<CmapBlock
     for cmap_block in all_cmap_blocks.into_iter().filter(|block| !block.is_empty() || block.len() < 100) {
         cid_to_unicode_map.push_str(format!("{} beginbfchar\r\n", cmap_block.len()).as_str());
         for (glyph_id, unicode) in cmap_block {
-            cid_to_unicode_map.push_str(format!("<{:04x}> <{:04x}>\n", glyph_id, unicode).as_str());
+            let unicode = unicode.iter().map(|codepoint| format!("{:04x}", codepoint)).collect::<String>();
+            cid_to_unicode_map.push_str(format!("<{:04x}> <{}>\n", glyph_id, unicode).as_str());
         }
         cid_to_unicode_map.push_str("endbfchar\r\n");
     }
@@ -412,6 +851,8 @@ impl IndirectFontRef {
 #[derive(Default, Debug, Clone)]
 pub struct FontList {
     fonts: HashMap<IndirectFontRef, DirectFontRef>,
+    /// Content hashes of deduplicated fonts
+    hashes: HashMap<u64, IndirectFontRef>,
 }
 
 impl FontList {
@@ -431,6 +872,38 @@ impl FontList {
         font_ref
     }
 
+    /// Remembers the content hash of an already added font
+    #[inline]
+    pub(crate) fn add_font_hash(&mut self, hash: u64, font_ref: IndirectFontRef)
+    {
+        self.hashes.entry(hash).or_insert(font_ref);
+    }
+
+    /// Returns the font that was added with the given content hash, if any
+    #[inline]
+    pub(crate) fn get_font_by_hash(&self, hash: u64)
+    -> Option<IndirectFontRef>
+    {
+        self.hashes.get(&hash).cloned()
+    }
+
+    /// Returns the font data without cloning it (unlike `get_font`), so that
+    /// the `GSUB` table, which is parsed on first use, is kept in the font of the document
+    #[inline]
+    pub(crate) fn get_font_data(&self, font: &IndirectFontRef)
+    -> Option<&Font>
+    {
+        self.fonts.get(font).map(|f| &f.data)
+    }
+
+    /// Mutable access to the font data, for changing the options of a font
+    #[inline]
+    pub(crate) fn get_font_data_mut(&mut self, font: &IndirectFontRef)
+    -> Option<&mut Font>
+    {
+        self.fonts.get_mut(font).map(|f| &mut f.data)
+    }
+
     /// Turns an indirect font reference into a direct one
     /// (Warning): clones the direct font reference
     #[inline]
@@ -445,6 +918,35 @@ impl FontList {
         }
     }
 
+    /// References to all fonts, sorted by their names
+    pub(crate) fn font_refs(&self)
+    -> Vec<IndirectFontRef>
+    {
+        let mut fonts = self.fonts.keys().cloned().collect::<Vec<IndirectFontRef>>();
+        fonts.sort_by(|a, b| a.name.cmp(&b.name));
+        fonts
+    }
+
+    /// Whether any of the fonts is an OpenType font with CFF outlines (requires PDF 1.6)
+    pub(crate) fn has_cff_fonts(&self)
+    -> bool
+    {
+        self.fonts.values().any(|f| match f.data {
+            Font::ExternalFont(ref font) => font.is_cff(),
+            Font::BuiltinFont(_) => false,
+        })
+    }
+
+    /// Size of the embedded font files in bytes, builtin fonts aren't embedded
+    pub(crate) fn font_data_size(&self)
+    -> usize
+    {
+        self.fonts.values().map(|f| match f.data {
+            Font::ExternalFont(ref font) => font.font_bytes.len(),
+            Font::BuiltinFont(_) => 0,
+        }).sum()
+    }
+
     /// Returns the number of fonts currenly in use
     #[inline]
     pub fn len(&self)
@@ -467,7 +969,11 @@ impl FontList {
     {
         let mut font_dict = lopdf::Dictionary::new();
 
-        for (indirect_ref, direct_font_ref) in self.fonts {
+        // sorted, so that the objects of the fonts are always added in the same order
+        let mut fonts: Vec<(IndirectFontRef, DirectFontRef)> = self.fonts.into_iter().collect();
+        fonts.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+        for (indirect_ref, direct_font_ref) in fonts {
             let font_dict_collected = match direct_font_ref.data {
                 Font::ExternalFont(font) => font.into_with_document(doc),
                 Font::BuiltinFont(font)  => font.into(),
@@ -480,3 +986,49 @@ impl FontList {
         font_dict
     }
 }
+
+#[test]
+fn test_font_name_tag() {
+    let tag = font_name_tag(0x1234_5678_9abc_def0);
+    assert_eq!(tag.len(), 6);
+    assert!(tag.chars().all(|c| c.is_ascii_uppercase()), "{}", tag);
+    assert_eq!(tag, font_name_tag(0x1234_5678_9abc_def0));
+    assert_ne!(tag, font_name_tag(0x1234_5678_9abc_def1));
+    assert_eq!(font_name_tag(0), "AAAAAA");
+}
+
+#[test]
+fn test_font_flags()
+{
+    use types::plugins::graphics::two_dimensional::sfnt::test_font_with;
+
+    fn u16s(values: &[u16]) -> Vec<u8> { values.iter().flat_map(|v| vec![(v >> 8) as u8, *v as u8]).collect() }
+
+    // monospace, italic angle -12, serif (class 1) and italic
+    let mut post = vec![0; 32];
+    post[4..8].copy_from_slice(&((-12 << 16) as u32).to_be_bytes());
+    post[12..16].copy_from_slice(&1_u32.to_be_bytes());
+    let mut os2 = vec![0; 78];
+    os2[30..32].copy_from_slice(&u16s(&[0x0105]));
+    os2[62..64].copy_from_slice(&u16s(&[1]));
+    let font = test_font_with(vec![(*b"post", post), (*b"OS/2", os2)]);
+
+    let flags = FontFlags::from_font(&font);
+    assert_eq!(flags, FontFlags { fixed_pitch: true, serif: true, italic: true, ..FontFlags::default() });
+    assert_eq!(flags.bits(), 1 | 2 | 32 | 64);
+    assert_eq!(italic_angle(&font), -12.0);
+
+    // a regular sans serif font, without the optional tables
+    let plain = ::types::plugins::graphics::two_dimensional::sfnt::test_font();
+    assert_eq!(FontFlags::from_font(&plain).bits(), 32);
+    assert_eq!(FontFlags { symbolic: true, ..FontFlags::default() }.bits(), 4);
+}
+
+#[test]
+fn test_reject_malformed_font()
+{
+    assert!(ExternalFont::new(&b"not a font"[..], 0).is_err());
+    // CFF font without the metric tables
+    assert!(ExternalFont::new(&b"OTTO\x00\x00\x00\x00\x00\x00\x00\x00"[..], 0).is_err());
+    assert!(ExternalFont::new(&include_bytes!("../../../../../assets/fonts/RobotoMedium.ttf")[..], 0).is_ok());
+}
@@ -0,0 +1,102 @@
+//! Footnotes: the markers are written inline with `PdfLayerReference::add_footnote()`,
+//! the notes are collected and drawn at the bottom of their page when the document is saved
+
+use types::plugins::graphics::two_dimensional::text_layout::wrap_lines;
+use {IndirectFontRef, Mm, Pt};
+
+/// Margin of the footnote area to the left, right and bottom edge of the page
+pub(crate) const FOOTNOTE_MARGIN: Mm = Mm(20.0);
+/// Length of the rule above the footnotes
+pub(crate) const FOOTNOTE_RULE_LENGTH: Mm = Mm(50.0);
+/// Maximum share of the page height used for footnotes, the rest continues on the next page
+pub(crate) const FOOTNOTE_MAX_HEIGHT: f64 = 0.4;
+/// Size of the notes relative to the size of the text containing the marker
+pub(crate) const FOOTNOTE_SCALE: f64 = 0.8;
+/// Leading of the notes as a multiple of their font size
+const FOOTNOTE_LEADING: f64 = 1.2;
+
+/// How footnotes are numbered, see `PdfDocumentReference::set_footnote_numbering()`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum FootnoteNumbering {
+    /// The footnotes are numbered through the whole document
+    #[default]
+    PerDocument,
+    /// The numbering starts at 1 on each page
+    PerPage,
+}
+
+/// A footnote, which was added to a page
+#[derive(Debug, Clone)]
+pub(crate) struct Footnote {
+    /// Index of the page with the marker
+    pub(crate) page: usize,
+    pub(crate) marker: String,
+    pub(crate) text: String,
+    pub(crate) font: IndirectFontRef,
+    pub(crate) font_size: f64,
+}
+
+/// Wrapped line of the notes
+#[derive(Debug, Clone)]
+pub(crate) struct FootnoteLine {
+    pub(crate) text: String,
+    pub(crate) font: IndirectFontRef,
+    pub(crate) font_size: f64,
+}
+
+impl FootnoteLine {
+
+    /// Distance to the next line
+    #[inline]
+    pub(crate) fn leading(&self)
+    -> Pt
+    {
+        Pt(self.font_size * FOOTNOTE_LEADING)
+    }
+}
+
+/// Wraps the notes (`marker text`) into lines of the width, `measure` returns the width of
+/// a text in a font and size
+pub(crate) fn footnote_lines<M>(notes: &[&Footnote], width: Pt, measure: M)
+-> Vec<FootnoteLine> where M: Fn(&IndirectFontRef, &str, f64) -> Pt
+{
+    notes.iter().flat_map(|note| {
+        let text = format!("{} {}", note.marker, note.text);
+        wrap_lines(&text, |_| width, |line| measure(&note.font, line, note.font_size))
+            .into_iter()
+            .map(|line| FootnoteLine { text: text[line].to_string(), font: note.font.clone(), font_size: note.font_size })
+            .collect::<Vec<_>>()
+    }).collect()
+}
+
+/// Number of lines that fit into the height. At least one line is placed on every page,
+/// so that very large footnotes still make progress.
+pub(crate) fn fitting_lines(lines: &[FootnoteLine], max_height: Pt)
+-> usize
+{
+    let mut height = Pt(0.0);
+    let count = lines.iter().take_while(|line| {
+        height += line.leading();
+        height <= max_height
+    }).count();
+    count.max(1).min(lines.len())
+}
+
+#[test]
+fn test_footnote_lines() {
+    let note = |marker: &str, text: &str| Footnote {
+        page: 0, marker: marker.into(), text: text.into(), font: IndirectFontRef::new("F1"), font_size: 10.0,
+    };
+    let (first, second) = (note("1", "A short note"), note("2", "A longer note which needs two lines"));
+    // every character is 2 pt wide
+    let measure = |_: &IndirectFontRef, text: &str, _| Pt(text.len() as f64 * 2.0);
+
+    let lines = footnote_lines(&[&first, &second], Pt(50.0), measure);
+    let texts: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+    assert_eq!(texts, vec!["1 A short note", "2 A longer note which", "needs two lines"]);
+
+    // 12 pt per line
+    assert_eq!(fitting_lines(&lines, Pt(30.0)), 2);
+    assert_eq!(fitting_lines(&lines, Pt(100.0)), 3);
+    assert_eq!(fitting_lines(&lines, Pt(5.0)), 1);
+}
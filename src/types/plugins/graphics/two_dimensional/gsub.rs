@@ -0,0 +1,308 @@
+//! Glyph substitution with the `GSUB` table of a font: single substitutions (lookup type 1),
+//! alternate substitutions (type 3, the first alternate is used) and ligature substitutions
+//! (type 4), also inside extension lookups (type 7). The features of the default language
+//! system of the `DFLT` or `latn` script are used, contextual lookups are not supported.
+
+use std::collections::BTreeMap;
+use types::plugins::graphics::two_dimensional::sfnt::{find_table, read_u16, read_u32};
+
+/// Standard ligatures
+pub(crate) const LIGA: [u8; 4] = *b"liga";
+/// Discretionary ligatures
+pub(crate) const DLIG: [u8; 4] = *b"dlig";
+
+/// Ligatures of one lookup: first glyph -> (following glyphs, ligature glyph), in the order of the font
+type LigatureMap = BTreeMap<u16, Vec<(Vec<u16>, u16)>>;
+
+/// Substitutions of one lookup
+#[derive(Debug, Clone)]
+enum Lookup {
+    /// Glyph -> substituted glyph (single and alternate substitutions)
+    Single(BTreeMap<u16, u16>),
+    /// Ligatures
+    Ligature(LigatureMap),
+}
+
+/// The substitution lookups of a font, in the order in which they are applied
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Substitutions {
+    /// The features that use the lookup, and its substitutions
+    lookups: Vec<(Vec<[u8; 4]>, Lookup)>,
+}
+
+/// Glyphs of a coverage table, in the order of their coverage index
+fn coverage_glyphs(gsub: &[u8], offset: usize)
+-> Option<Vec<u16>>
+{
+    let count = read_u16(gsub, offset + 2)? as usize;
+    match read_u16(gsub, offset)? {
+        1 => (0..count).map(|idx| read_u16(gsub, offset + 4 + idx * 2)).collect(),
+        2 => {
+            let mut glyphs = Vec::new();
+            for idx in 0..count {
+                let record = offset + 4 + idx * 6;
+                let (start, end) = (read_u16(gsub, record)?, read_u16(gsub, record + 2)?);
+                glyphs.extend(start..=end);
+            }
+            Some(glyphs)
+        },
+        _ => None,
+    }
+}
+
+/// Feature indices of the default language system of the `DFLT` script, or of the `latn` script
+/// or the first script if the font has no `DFLT` script. `None` if the font has no scripts.
+fn default_features(gsub: &[u8], script_list: usize)
+-> Option<Vec<usize>>
+{
+    let scripts = (0..read_u16(gsub, script_list)? as usize)
+        .map(|idx| script_list + 2 + idx * 6)
+        .map(|record| Some((gsub.get(record..record + 4)?, script_list + read_u16(gsub, record + 4)? as usize)))
+        .collect::<Option<Vec<_>>>()?;
+    let script = scripts.iter().find(|(tag, _)| *tag == b"DFLT")
+        .or_else(|| scripts.iter().find(|(tag, _)| *tag == b"latn"))
+        .or_else(|| scripts.first())?.1;
+
+    let lang_sys = read_u16(gsub, script)? as usize;
+    if lang_sys == 0 {
+        return Some(Vec::new());
+    }
+    let lang_sys = script + lang_sys;
+    let mut features = (0..read_u16(gsub, lang_sys + 4)? as usize)
+        .map(|idx| read_u16(gsub, lang_sys + 6 + idx * 2).map(usize::from))
+        .collect::<Option<Vec<usize>>>()?;
+    match read_u16(gsub, lang_sys + 2)? {
+        0xffff => { },
+        required => features.push(usize::from(required)),
+    }
+    Some(features)
+}
+
+/// Reads a single substitution subtable (format 1: delta, format 2: list of glyphs)
+fn parse_single_subst(gsub: &[u8], offset: usize, map: &mut BTreeMap<u16, u16>)
+-> Option<()>
+{
+    let coverage = coverage_glyphs(gsub, offset + read_u16(gsub, offset + 2)? as usize)?;
+    let (format, value) = (read_u16(gsub, offset)?, read_u16(gsub, offset + 4)?);
+
+    for (idx, glyph) in coverage.into_iter().enumerate() {
+        let substitute = match format {
+            1 => glyph.wrapping_add(value),
+            2 if idx < value as usize => read_u16(gsub, offset + 6 + idx * 2)?,
+            _ => continue,
+        };
+        map.entry(glyph).or_insert(substitute);
+    }
+
+    Some(())
+}
+
+/// Reads an alternate substitution subtable, using the first alternate of each glyph
+fn parse_alternate_subst(gsub: &[u8], offset: usize, map: &mut BTreeMap<u16, u16>)
+-> Option<()>
+{
+    let coverage = coverage_glyphs(gsub, offset + read_u16(gsub, offset + 2)? as usize)?;
+    let set_count = read_u16(gsub, offset + 4)? as usize;
+
+    for (idx, glyph) in coverage.into_iter().enumerate().take(set_count) {
+        let set = offset + read_u16(gsub, offset + 6 + idx * 2)? as usize;
+        if read_u16(gsub, set)? > 0 {
+            map.entry(glyph).or_insert(read_u16(gsub, set + 2)?);
+        }
+    }
+
+    Some(())
+}
+
+/// Reads a ligature substitution subtable into the map
+fn parse_ligature_subst(gsub: &[u8], offset: usize, map: &mut LigatureMap)
+-> Option<()>
+{
+    let coverage = coverage_glyphs(gsub, offset + read_u16(gsub, offset + 2)? as usize)?;
+    let set_count = read_u16(gsub, offset + 4)? as usize;
+
+    for (idx, first) in coverage.into_iter().enumerate().take(set_count) {
+        let set = offset + read_u16(gsub, offset + 6 + idx * 2)? as usize;
+        for ligature in 0..read_u16(gsub, set)? as usize {
+            let ligature = set + read_u16(gsub, set + 2 + ligature * 2)? as usize;
+            let glyph = read_u16(gsub, ligature)?;
+            let components = (1..read_u16(gsub, ligature + 2)? as usize)
+                .map(|component| read_u16(gsub, ligature + 2 + component * 2))
+                .collect::<Option<Vec<u16>>>()?;
+            map.entry(first).or_default().push((components, glyph));
+        }
+    }
+
+    Some(())
+}
+
+/// Reads the subtables of a lookup, `None` for lookup types that aren't supported
+fn parse_lookup(gsub: &[u8], offset: usize)
+-> Option<Lookup>
+{
+    let lookup_type = read_u16(gsub, offset)?;
+    let mut singles = BTreeMap::new();
+    let mut ligatures = LigatureMap::new();
+
+    for subtable in 0..read_u16(gsub, offset + 4)? as usize {
+        let subtable = offset + read_u16(gsub, offset + 6 + subtable * 2)? as usize;
+        // extension lookups point to a subtable with a 32-bit offset
+        let (subtable_type, subtable) = if lookup_type == 7 {
+            (read_u16(gsub, subtable + 2)?, subtable + read_u32(gsub, subtable + 4)? as usize)
+        } else {
+            (lookup_type, subtable)
+        };
+        match subtable_type {
+            1 => parse_single_subst(gsub, subtable, &mut singles)?,
+            3 => parse_alternate_subst(gsub, subtable, &mut singles)?,
+            4 => parse_ligature_subst(gsub, subtable, &mut ligatures)?,
+            _ => return None,
+        }
+    }
+
+    if !ligatures.is_empty() {
+        Some(Lookup::Ligature(ligatures))
+    } else if !singles.is_empty() {
+        Some(Lookup::Single(singles))
+    } else {
+        None
+    }
+}
+
+impl Substitutions {
+
+    /// Reads the lookups of all features, empty if the font has no `GSUB` table
+    pub(crate) fn parse(font: &[u8])
+    -> Self
+    {
+        Self::parse_gsub(font).unwrap_or_default()
+    }
+
+    fn parse_gsub(font: &[u8])
+    -> Option<Self>
+    {
+        let gsub = find_table(font, b"GSUB")?;
+        let script_list = read_u16(gsub, 4)? as usize;
+        let feature_list = read_u16(gsub, 6)? as usize;
+        let lookup_list = read_u16(gsub, 8)? as usize;
+
+        let feature_count = read_u16(gsub, feature_list)? as usize;
+        let feature_indices = default_features(gsub, script_list).unwrap_or_else(|| (0..feature_count).collect());
+
+        // lookup index -> features that use the lookup
+        let mut lookup_features = BTreeMap::<usize, Vec<[u8; 4]>>::new();
+        for idx in feature_indices.into_iter().filter(|idx| *idx < feature_count) {
+            let record = feature_list + 2 + idx * 6;
+            let tag = [*gsub.get(record)?, *gsub.get(record + 1)?, *gsub.get(record + 2)?, *gsub.get(record + 3)?];
+            let feature = feature_list + read_u16(gsub, record + 4)? as usize;
+            for lookup in 0..read_u16(gsub, feature + 2)? as usize {
+                let features = lookup_features.entry(read_u16(gsub, feature + 4 + lookup * 2)? as usize).or_default();
+                if !features.contains(&tag) {
+                    features.push(tag);
+                }
+            }
+        }
+
+        let lookups = lookup_features.into_iter().filter_map(|(idx, features)| {
+            let lookup = lookup_list + read_u16(gsub, lookup_list + 2 + idx * 2)? as usize;
+            parse_lookup(gsub, lookup).map(|lookup| (features, lookup))
+        }).collect();
+
+        Some(Self { lookups })
+    }
+
+    /// The lookups that belong to one of the features
+    fn lookups<'a>(&'a self, features: &'a [[u8; 4]])
+    -> impl Iterator<Item = &'a Lookup> + 'a
+    {
+        self.lookups.iter()
+            .filter(move |(lookup_features, _)| lookup_features.iter().any(|feature| features.contains(feature)))
+            .map(|(_, lookup)| lookup)
+    }
+
+    /// Substitutes the glyphs with the lookups of the features, in the order of the lookups
+    pub(crate) fn apply(&self, glyphs: Vec<u16>, features: &[[u8; 4]])
+    -> Vec<u16>
+    {
+        let mut glyphs = glyphs;
+        for lookup in self.lookups(features) {
+            glyphs = match *lookup {
+                Lookup::Single(ref map) => glyphs.into_iter().map(|glyph| map.get(&glyph).cloned().unwrap_or(glyph)).collect(),
+                Lookup::Ligature(ref map) => {
+                    let mut substituted = Vec::with_capacity(glyphs.len());
+                    let mut idx = 0;
+                    while idx < glyphs.len() {
+                        let rest = &glyphs[idx + 1..];
+                        match map.get(&glyphs[idx]).and_then(|ligatures| ligatures.iter().find(|(components, _)| rest.starts_with(components))) {
+                            Some((components, ligature)) => {
+                                substituted.push(*ligature);
+                                idx += 1 + components.len();
+                            },
+                            None => {
+                                substituted.push(glyphs[idx]);
+                                idx += 1;
+                            },
+                        }
+                    }
+                    substituted
+                },
+            };
+        }
+        glyphs
+    }
+
+    /// All substitutions of the features: the substituted glyph and the glyphs it replaces,
+    /// in the order of the lookups
+    pub(crate) fn substituted_glyphs(&self, features: &[[u8; 4]])
+    -> Vec<(u16, Vec<u16>)>
+    {
+        let mut substituted = Vec::new();
+        for lookup in self.lookups(features) {
+            match *lookup {
+                Lookup::Single(ref map) => substituted.extend(map.iter().map(|(glyph, substitute)| (*substitute, vec![*glyph]))),
+                Lookup::Ligature(ref map) => for (first, ligatures) in map {
+                    substituted.extend(ligatures.iter().map(|(components, ligature)| {
+                        let mut glyphs = vec![*first];
+                        glyphs.extend_from_slice(components);
+                        (*ligature, glyphs)
+                    }));
+                },
+            }
+        }
+        substituted
+    }
+}
+
+/// `GSUB` table for tests: the standard ligature of the glyphs 1 and 2 is glyph 3,
+/// the small cap (`smcp`) of glyph 1 is glyph 2
+#[cfg(test)]
+pub(crate) fn test_gsub()
+-> Vec<u8>
+{
+    fn u16s(values: &[u16]) -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect() }
+
+    let mut gsub = u16s(&[1, 0, 10, 12, 38, 0, 2]);
+    gsub.extend_from_slice(&LIGA);
+    gsub.extend(u16s(&[14]));
+    gsub.extend_from_slice(b"smcp");
+    gsub.extend(u16s(&[20, 0, 1, 0, 0, 1, 1]));
+    gsub.extend(u16s(&[2, 6, 38]));
+    gsub.extend(u16s(&[4, 0, 1, 8, 1, 18, 1, 8, 1, 4, 3, 2, 2, 1, 1, 1]));
+    gsub.extend(u16s(&[1, 0, 1, 8, 1, 6, 1, 1, 1, 1]));
+    gsub
+}
+
+#[test]
+fn test_substitutions() {
+    use types::plugins::graphics::two_dimensional::sfnt::{test_font, test_font_with};
+
+    let substitutions = Substitutions::parse(&test_font_with(vec![(*b"GSUB", test_gsub())]));
+    assert_eq!(substitutions.apply(vec![1, 2, 2, 1, 1, 2], &[LIGA]), vec![3, 2, 1, 3]);
+    assert_eq!(substitutions.apply(vec![1, 2], &[DLIG]), vec![1, 2]);
+    assert_eq!(substitutions.apply(vec![1], &[LIGA]), vec![1]);
+    assert_eq!(substitutions.apply(vec![1, 2, 2, 1], &[*b"smcp"]), vec![2, 2, 2, 2]);
+    assert_eq!(substitutions.apply(vec![1, 2, 2, 1], &[LIGA, *b"smcp"]), vec![3, 2, 2]);
+    assert_eq!(substitutions.substituted_glyphs(&[LIGA]), vec![(3, vec![1, 2])]);
+    assert_eq!(substitutions.substituted_glyphs(&[LIGA, *b"smcp"]), vec![(3, vec![1, 2]), (2, vec![1])]);
+    assert!(Substitutions::parse(&test_font()).substituted_glyphs(&[LIGA, DLIG]).is_empty());
+}
@@ -0,0 +1,161 @@
+//! Hyphenation of words with Knuth-Liang patterns (the algorithm used by TeX)
+
+use std::collections::HashMap;
+
+/// Soft hyphen, marks an explicit break point inside a word
+const SOFT_HYPHEN: char = '\u{AD}';
+
+/// Small built-in set of English patterns (common prefixes, suffixes and double
+/// consonants). It is conservative and misses many break points - for good results
+/// load the full TeX `hyph-en-us` pattern set with `Hyphenator::new()`.
+const ENGLISH_PATTERNS: &str = "
+    1tion 1sion 1ment 1ness 1less 1ful 1ship 1hood 1able 1ible 4ble.
+    b1b c1c d1d f1f g1g l1l m1m n1n p1p r1r t1t z1z
+    s1s 4ss. 4ll. 4ff. 4ck 4sh 4th 4ch 4ph 4gh 4wh
+    .un1 .un2i .dis1 .mis1 .non1 .over1 .under1 .inter1 .counter1
+";
+
+/// Hyphenates words with Knuth-Liang patterns. The patterns are given in the format used
+/// by TeX (`hy3ph he2n hena4 ...`), where the digits between the letters are the
+/// priorities of the break points: a break is allowed if the highest digit at a
+/// position is odd. A `.` marks the beginning or end of a word.
+#[derive(Debug, Clone)]
+pub struct Hyphenator {
+    /// Letters of the pattern mapped to the priorities (one more than the number of letters)
+    patterns: HashMap<String, Vec<u8>>,
+    /// Words that are hyphenated differently, mapped to their syllables
+    exceptions: HashMap<String, Vec<String>>,
+    /// Length (in characters) of the longest pattern
+    max_pattern_length: usize,
+    /// Minimum number of characters before the first break point. Default: __2__
+    pub left_min: usize,
+    /// Minimum number of characters after the last break point. Default: __3__
+    pub right_min: usize,
+}
+
+impl Hyphenator {
+
+    /// Creates a hyphenator from a whitespace separated list of patterns and
+    /// a whitespace separated list of exceptions, which are written with the
+    /// hyphens at the break points (`ta-ble pro-ject`, may be empty)
+    pub fn new(patterns: &str, exceptions: &str)
+    -> Self
+    {
+        let mut hyphenator = Self {
+            patterns: HashMap::new(),
+            exceptions: HashMap::new(),
+            max_pattern_length: 0,
+            left_min: 2,
+            right_min: 3,
+        };
+
+        for pattern in patterns.split_whitespace() {
+            let mut letters = String::new();
+            let mut priorities = vec![0];
+            for c in pattern.chars() {
+                match c.to_digit(10) {
+                    Some(digit) => *priorities.last_mut().unwrap() = digit as u8,
+                    None => { letters.push(c); priorities.push(0); },
+                }
+            }
+            hyphenator.max_pattern_length = hyphenator.max_pattern_length.max(letters.chars().count());
+            hyphenator.patterns.insert(letters, priorities);
+        }
+
+        for exception in exceptions.split_whitespace() {
+            let syllables: Vec<String> = exception.split('-').map(|s| s.to_string()).collect();
+            hyphenator.exceptions.insert(syllables.concat().to_lowercase(), syllables);
+        }
+
+        hyphenator
+    }
+
+    /// Creates a hyphenator with the small built-in set of English patterns
+    #[inline]
+    pub fn english()
+    -> Self
+    {
+        Self::new(ENGLISH_PATTERNS, "")
+    }
+
+    /// Splits a word into the parts between its possible break points,
+    /// `"hyphenation"` becomes `["hy", "phen", "ation"]` (depending on the patterns).
+    /// If the word contains soft hyphens (U+00AD), only the soft hyphens are used as break
+    /// points and they are removed from the returned parts.
+    pub fn hyphenate(&self, word: &str)
+    -> Vec<String>
+    {
+        if word.contains(SOFT_HYPHEN) {
+            return word.split(SOFT_HYPHEN).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+        }
+
+        let lowercase = word.to_lowercase();
+        let chars: Vec<char> = word.chars().collect();
+
+        // lowercasing can change the number of characters, don't hyphenate these (rare) words
+        if lowercase.chars().count() != chars.len() {
+            return vec![word.to_string()];
+        }
+
+        if let Some(syllables) = self.exceptions.get(&lowercase) {
+            // keep the case of the original word
+            let mut parts = Vec::with_capacity(syllables.len());
+            let mut start = 0;
+            for syllable in syllables {
+                let len = syllable.chars().count();
+                parts.push(chars[start..start + len].iter().collect());
+                start += len;
+            }
+            return parts;
+        }
+
+        let dotted: Vec<char> = format!(".{}.", lowercase).chars().collect();
+        // priorities[i] is the priority of the break point before dotted[i]
+        let mut priorities = vec![0u8; dotted.len() + 1];
+
+        for start in 0..dotted.len() {
+            let end = dotted.len().min(start + self.max_pattern_length);
+            let mut substring = String::new();
+            for (offset, c) in dotted[start..end].iter().enumerate() {
+                substring.push(*c);
+                if let Some(pattern) = self.patterns.get(&substring) {
+                    for (idx, &priority) in pattern.iter().enumerate().take(offset + 2) {
+                        let p = &mut priorities[start + idx];
+                        *p = (*p).max(priority);
+                    }
+                }
+            }
+        }
+
+        // break point before chars[i] is priorities[i + 1] (shifted by the leading dot)
+        let mut parts = Vec::new();
+        let mut current = String::new();
+        for (i, c) in chars.iter().enumerate() {
+            let can_break = i >= self.left_min && chars.len() - i >= self.right_min && priorities[i + 1] % 2 == 1;
+            if can_break && !current.is_empty() {
+                parts.push(::std::mem::take(&mut current));
+            }
+            current.push(*c);
+        }
+        parts.push(current);
+        parts
+    }
+}
+
+#[test]
+fn test_hyphenate() {
+    // patterns from Liang's thesis
+    let hyphenator = Hyphenator::new("hy3ph he2n hena4 hen5at 1na n2at 1tio 2io o2n", "ta-ble");
+    assert_eq!(hyphenator.hyphenate("hyphenation"), vec!["hy", "phen", "ation"]);
+    assert_eq!(hyphenator.hyphenate("Table"), vec!["Ta", "ble"]);
+    assert_eq!(hyphenator.hyphenate("go"), vec!["go"]);
+
+    // soft hyphens replace the patterns
+    assert_eq!(hyphenator.hyphenate("hyphe\u{AD}nation"), vec!["hyphe", "nation"]);
+
+    let english = Hyphenator::english();
+    assert_eq!(english.hyphenate("summer"), vec!["sum", "mer"]);
+    assert_eq!(english.hyphenate("kindness"), vec!["kind", "ness"]);
+    assert_eq!(english.hyphenate("uniform"), vec!["uniform"]);
+    assert_eq!(english.hyphenate("unhappy"), vec!["un", "happy"]);
+}
@@ -3,8 +3,9 @@
 
 #[cfg(feature = "embedded_images")]
 use image::{self, ImageDecoder, DynamicImage};
-use Mm;
-use {ImageXObject, PdfLayerReference};
+use lopdf::content::Operation;
+use {Mm, Px, Error};
+use {ImageXObject, StreamedImageXObject, XObjectRef, PdfLayerReference, StructureType, IccProfile, RenderingIntent};
 
 /// Image - wrapper around an `ImageXObject` to allow for more control
 /// within the library
@@ -12,6 +13,8 @@ use {ImageXObject, PdfLayerReference};
 pub struct Image {
     /// The actual image
     pub image: ImageXObject,
+    /// Alternate description of the image for screen readers, see `with_alt_text()`
+    pub alt_text: Option<String>,
 }
 
 impl From<ImageXObject> for Image {
@@ -20,6 +23,7 @@ impl From<ImageXObject> for Image {
     {
         Self {
             image: image,
+            alt_text: None,
         }
     }
 
@@ -33,6 +37,7 @@ impl<'a> Image {
         let image = ImageXObject::try_from(image)?;
         Ok(Self {
             image: image,
+            alt_text: None,
         })
     }
 
@@ -41,12 +46,72 @@ impl<'a> Image {
     {
         Self {
             image: ImageXObject::from_dynamic_image(image),
+            alt_text: None,
         }
     }
 }
 
 impl Image {
 
+    /// Creates a black and white image from packed 1 bit pixels (0 bits are black),
+    /// see `ImageXObject::from_bilevel()`
+    #[inline]
+    pub fn from_bilevel(width: Px, height: Px, packed_bits: &[u8])
+    -> Self
+    {
+        Self::from(ImageXObject::from_bilevel(width, height, packed_bits))
+    }
+
+    /// Embeds a JPEG 2000 image without decoding it, see `ImageXObject::from_jpeg2000()`
+    #[inline]
+    pub fn from_jpeg2000(data: Vec<u8>)
+    -> Result<Self, Error>
+    {
+        ImageXObject::from_jpeg2000(data).map(Self::from)
+    }
+
+    /// Embeds a JBIG2 image without decoding it, see `ImageXObject::from_jbig2()`
+    #[inline]
+    pub fn from_jbig2(data: Vec<u8>, globals: Option<Vec<u8>>)
+    -> Result<Self, Error>
+    {
+        ImageXObject::from_jbig2(data, globals).map(Self::from)
+    }
+
+    /// Sets an alternate description of the image. When the image is added to a layer,
+    /// it is tagged as a `StructureType::Figure` with this description (tagged PDF), so
+    /// that screen readers can describe the image. Purely decorative images should rather
+    /// be marked as an artifact, see `PdfLayerReference::as_artifact()`.
+    ///
+    /// Default: __None__
+    #[inline]
+    pub fn with_alt_text<S>(self, alt_text: S)
+    -> Self where S: Into<String>
+    {
+        Self { alt_text: Some(alt_text.into()), .. self }
+    }
+
+    /// Tags the image with an ICC profile, e.g. `IccProfile::srgb()` for sRGB photos, so
+    /// that viewers show the same colors. The profile must match the color space of the image.
+    pub fn with_icc_profile(mut self, profile: IccProfile)
+    -> Self
+    {
+        self.image.icc_profile = Some(profile);
+        self
+    }
+
+    /// Sets the rendering intent for the colors of the image, e.g. `RenderingIntent::Perceptual`
+    /// for photos, which keeps the relations between out-of-gamut colors when printing.
+    ///
+    /// Default: __None__ (the rendering intent of the layer, see `PdfLayerReference::set_rendering_intent()`)
+    #[inline]
+    pub fn with_rendering_intent(mut self, intent: RenderingIntent)
+    -> Self
+    {
+        self.image.rendering_intent = Some(intent);
+        self
+    }
+
     /// Adds the image to a specific layer and consumes it
     /// This is due to a PDF weirdness - images are basically just "names"
     /// and you have to make sure that they are added to the same page
@@ -61,26 +126,84 @@ impl Image {
                         scale_x: Option<f64>, scale_y: Option<f64>,
                         dpi: Option<f64>)
     {
-        // PDF maps an image to a 1x1 square, we have to adjust the transform matrix
-        // to fix the distortion
-        let dpi = dpi.unwrap_or(300.0);
-
-        //Image at the given dpi should 1px = 1pt
-        let image_w = self.image.width.into_pt(dpi);
-        let image_h = self.image.height.into_pt(dpi);
-
-        let image = layer.add_image(self.image);
-
-        if let Some(scale_x) = scale_x {
-            if let Some(scale_y) = scale_y {
-                layer.use_xobject(image, translate_x, translate_y, rotate_cw, Some(scale_x * image_w.0), Some(image_h.0 * scale_y));
-            } else {
-                layer.use_xobject(image, translate_x, translate_y, rotate_cw, Some(scale_x * image_w.0), Some(image_h.0));
-            }
-        } else if let Some(scale_y) = scale_y {
-            layer.use_xobject(image, translate_x, translate_y, rotate_cw, Some(image_w.0), Some(image_h.0 * scale_y));
-        } else {
-            layer.use_xobject(image, translate_x, translate_y, rotate_cw, Some(image_w.0), Some(image_h.0));
+        let (width, height) = (self.image.width, self.image.height);
+        let image = match layer.inline_image_operations(&self.image) {
+            Some(operations) => PlacedImage::Inline(operations),
+            None => PlacedImage::XObject(layer.add_image(self.image)),
+        };
+
+        match self.alt_text {
+            Some(alt_text) => {
+                layer.begin_tag(StructureType::Figure, Some(alt_text));
+                place_image(&layer, image, width, height, translate_x, translate_y, rotate_cw, scale_x, scale_y, dpi);
+                layer.end_tag();
+            },
+            None => place_image(&layer, image, width, height, translate_x, translate_y, rotate_cw, scale_x, scale_y, dpi),
         }
     }
 }
+
+/// Image, whose data is copied from a reader into the PDF while saving instead of being
+/// held in memory. See `StreamedImageXObject` for the constraints on the image data.
+#[derive(Debug)]
+pub struct StreamedImage {
+    /// The actual image
+    pub image: StreamedImageXObject,
+}
+
+impl From<StreamedImageXObject> for StreamedImage {
+    fn from(image: StreamedImageXObject)
+    -> Self
+    {
+        Self { image }
+    }
+}
+
+impl StreamedImage {
+
+    /// Adds the image to a specific layer and consumes it, see `Image::add_to_layer`
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_to_layer(self, layer: PdfLayerReference,
+                        translate_x: Option<Mm>, translate_y: Option<Mm>,
+                        rotate_cw: Option<f64>,
+                        scale_x: Option<f64>, scale_y: Option<f64>,
+                        dpi: Option<f64>)
+    {
+        let (width, height) = (self.image.width, self.image.height);
+        let image = PlacedImage::XObject(layer.add_streamed_image(self.image));
+        place_image(&layer, image, width, height, translate_x, translate_y, rotate_cw, scale_x, scale_y, dpi);
+    }
+}
+
+/// Image that is drawn by `place_image()`
+enum PlacedImage {
+    /// Image that was already added to the layer
+    XObject(XObjectRef),
+    /// Operations of an inline image, see `PdfDocumentReference::set_inline_image_limit()`
+    Inline(Vec<Operation>),
+}
+
+/// Places an image on the layer
+#[allow(clippy::too_many_arguments)]
+fn place_image(layer: &PdfLayerReference, image: PlacedImage, width: Px, height: Px,
+               translate_x: Option<Mm>, translate_y: Option<Mm>,
+               rotate_cw: Option<f64>,
+               scale_x: Option<f64>, scale_y: Option<f64>,
+               dpi: Option<f64>)
+{
+    // PDF maps an image to a 1x1 square, we have to adjust the transform matrix
+    // to fix the distortion
+    let dpi = dpi.unwrap_or(300.0);
+
+    //Image at the given dpi should 1px = 1pt
+    let image_w = width.into_pt(dpi);
+    let image_h = height.into_pt(dpi);
+
+    let scale_x = Some(scale_x.map(|scale_x| scale_x * image_w.0).unwrap_or(image_w.0));
+    let scale_y = Some(scale_y.map(|scale_y| image_h.0 * scale_y).unwrap_or(image_h.0));
+
+    match image {
+        PlacedImage::XObject(image) => layer.use_xobject(image, translate_x, translate_y, rotate_cw, scale_x, scale_y),
+        PlacedImage::Inline(operations) => layer.use_inline_image(operations, translate_x, translate_y, rotate_cw, scale_x, scale_y),
+    }
+}
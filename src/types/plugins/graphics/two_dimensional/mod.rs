@@ -1,13 +1,39 @@
 //! 2D elements for Pdf (stub), to be expanded
 
 pub mod point;
+pub mod rect;
 pub mod line;
+pub mod bidi;
 pub mod font;
+pub mod hyphenation;
+pub mod footnote;
+pub mod flow;
 // pub mod svg;
 pub mod image;
+pub(crate) mod afm;
+pub(crate) mod bounding_box;
+pub(crate) mod redaction;
+pub(crate) mod text_extraction;
+pub mod text_encoding;
+pub(crate) mod text_layout;
+pub(crate) mod text_path;
+pub(crate) mod stroke;
+pub(crate) mod sfnt;
+pub mod variations;
+pub mod outline;
+pub(crate) mod colr;
+pub(crate) mod gsub;
 
 pub use self::point::Point;
+pub use self::rect::Rect;
 pub use self::line::Line;
+pub use self::bidi::TextDirection;
 pub use self::font::*;
+pub use self::variations::{Tag, FontAxis, NamedInstance};
+pub use self::outline::PathSegment;
+pub use self::hyphenation::Hyphenator;
+pub use self::footnote::FootnoteNumbering;
+pub use self::text_encoding::TextEncoding;
+pub use self::flow::{TextFlow, TableCell, HorizontalAlignment, VerticalAlignment};
 // pub use self::svg::Svg;
 pub use self::image::Image;
\ No newline at end of file
@@ -0,0 +1,525 @@
+//! Glyph outlines as vector paths (see `ExternalFont::glyph_outline()`), for drawing text
+//! as curves without embedding the font. TrueType outlines are read with `rusttype`,
+//! CFF outlines with the Type 2 charstring interpreter in this module.
+
+use types::plugins::graphics::two_dimensional::sfnt::{find_table, read_u16, read_i16};
+
+/// Segment of a glyph outline. The coordinates are in em (1.0 = the font size),
+/// relative to the origin of the glyph, the y axis points up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PathSegment {
+    /// Starts a new contour at the point
+    MoveTo(f64, f64),
+    /// Straight line to the point
+    LineTo(f64, f64),
+    /// Quadratic bezier curve (TrueType outlines): control point, end point
+    QuadTo(f64, f64, f64, f64),
+    /// Cubic bezier curve (CFF outlines): two control points, end point
+    CurveTo(f64, f64, f64, f64, f64, f64),
+    /// Closes the current contour
+    Close,
+}
+
+impl PathSegment {
+
+    /// Scales all coordinates of the segment by the factor
+    pub(crate) fn scaled(self, factor: f64)
+    -> Self
+    {
+        use self::PathSegment::*;
+        match self {
+            MoveTo(x, y) => MoveTo(x * factor, y * factor),
+            LineTo(x, y) => LineTo(x * factor, y * factor),
+            QuadTo(x1, y1, x, y) => QuadTo(x1 * factor, y1 * factor, x * factor, y * factor),
+            CurveTo(x1, y1, x2, y2, x, y) => CurveTo(x1 * factor, y1 * factor, x2 * factor, y2 * factor, x * factor, y * factor),
+            Close => Close,
+        }
+    }
+}
+
+/// Maximum nesting of subroutine calls in a charstring
+const MAX_SUBR_DEPTH: usize = 10;
+
+/// CFF INDEX structure: a list of byte strings
+#[derive(Debug, Copy, Clone)]
+struct Index<'a> {
+    count: usize,
+    off_size: usize,
+    offsets: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> Index<'a> {
+
+    /// Reads the INDEX at the offset, returns it and the offset after it
+    fn parse(cff: &'a [u8], offset: usize)
+    -> Option<(Self, usize)>
+    {
+        let count = read_u16(cff, offset)? as usize;
+        if count == 0 {
+            return Some((Index { count: 0, off_size: 1, offsets: &[], data: &[] }, offset + 2));
+        }
+        let off_size = *cff.get(offset + 2)? as usize;
+        if off_size == 0 || off_size > 4 {
+            return None;
+        }
+        let offsets = cff.get(offset + 3..offset + 3 + (count + 1) * off_size)?;
+        let mut index = Index { count, off_size, offsets, data: &[] };
+        let data_start = offset + 2 + (count + 1) * off_size;
+        let data_end = data_start + index.offset(count)?;
+        index.data = cff.get(data_start + 1..data_end)?;
+        Some((index, data_end))
+    }
+
+    fn offset(&self, idx: usize)
+    -> Option<usize>
+    {
+        let bytes = self.offsets.get(idx * self.off_size..(idx + 1) * self.off_size)?;
+        Some(bytes.iter().fold(0, |offset, byte| offset << 8 | *byte as usize))
+    }
+
+    fn get(&self, idx: usize)
+    -> Option<&'a [u8]>
+    {
+        if idx >= self.count {
+            return None;
+        }
+        let (start, end) = (self.offset(idx)?.checked_sub(1)?, self.offset(idx + 1)?.checked_sub(1)?);
+        self.data.get(start..end)
+    }
+}
+
+/// Reads the operands and operators of a DICT, operators with two bytes are `1200 + second byte`
+fn parse_dict(data: &[u8])
+-> Option<Vec<(u16, Vec<f64>)>>
+{
+    let mut entries = Vec::new();
+    let mut operands = Vec::new();
+    let mut position = 0;
+
+    while position < data.len() {
+        let b0 = data[position];
+        position += 1;
+        match b0 {
+            0..=21 => {
+                let operator = if b0 == 12 {
+                    position += 1;
+                    1200 + u16::from(*data.get(position - 1)?)
+                } else {
+                    u16::from(b0)
+                };
+                entries.push((operator, ::std::mem::take(&mut operands)));
+            },
+            28 => {
+                operands.push(f64::from(read_i16(data, position)?));
+                position += 2;
+            },
+            29 => {
+                let bytes = data.get(position..position + 4)?;
+                operands.push(f64::from(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])));
+                position += 4;
+            },
+            30 => {
+                // real number, as nibbles - only needed to skip it
+                while position < data.len() {
+                    let byte = data[position];
+                    position += 1;
+                    if byte & 0x0F == 0x0F || byte >> 4 == 0x0F {
+                        break;
+                    }
+                }
+                operands.push(0.0);
+            },
+            32..=246 => operands.push(f64::from(b0) - 139.0),
+            247..=250 => {
+                operands.push((f64::from(b0) - 247.0) * 256.0 + f64::from(*data.get(position)?) + 108.0);
+                position += 1;
+            },
+            251..=254 => {
+                operands.push(-(f64::from(b0) - 251.0) * 256.0 - f64::from(*data.get(position)?) - 108.0);
+                position += 1;
+            },
+            _ => return None,
+        }
+    }
+
+    Some(entries)
+}
+
+fn dict_value(dict: &[(u16, Vec<f64>)], operator: u16)
+-> Option<&[f64]>
+{
+    dict.iter().find(|(op, _)| *op == operator).map(|(_, operands)| &operands[..])
+}
+
+/// Bias of subroutine numbers, depending on the number of subroutines
+fn subr_bias(count: usize)
+-> i32
+{
+    if count < 1240 { 107 } else if count < 33900 { 1131 } else { 32768 }
+}
+
+/// Reads the local subroutines of a Private DICT, given as (size, offset)
+fn local_subrs<'a>(cff: &'a [u8], private: &[f64])
+-> Option<Index<'a>>
+{
+    let (size, offset) = match *private {
+        [size, offset] => (size as usize, offset as usize),
+        _ => return None,
+    };
+    let dict = parse_dict(cff.get(offset..offset + size)?)?;
+    let subrs = dict_value(&dict, 19)?.first()?;
+    Index::parse(cff, offset + *subrs as usize).map(|(index, _)| index)
+}
+
+/// State of the charstring interpreter
+struct Charstring<'a> {
+    global_subrs: Index<'a>,
+    local_subrs: Option<Index<'a>>,
+    stack: Vec<f64>,
+    x: f64,
+    y: f64,
+    stems: usize,
+    width_parsed: bool,
+    open: bool,
+    segments: Vec<PathSegment>,
+}
+
+impl<'a> Charstring<'a> {
+
+    fn move_to(&mut self, dx: f64, dy: f64)
+    {
+        self.close();
+        self.x += dx;
+        self.y += dy;
+        self.segments.push(PathSegment::MoveTo(self.x, self.y));
+        self.open = true;
+    }
+
+    fn line_to(&mut self, dx: f64, dy: f64)
+    {
+        self.x += dx;
+        self.y += dy;
+        self.segments.push(PathSegment::LineTo(self.x, self.y));
+    }
+
+    fn curve_to(&mut self, d: [f64; 6])
+    {
+        let (x1, y1) = (self.x + d[0], self.y + d[1]);
+        let (x2, y2) = (x1 + d[2], y1 + d[3]);
+        self.x = x2 + d[4];
+        self.y = y2 + d[5];
+        self.segments.push(PathSegment::CurveTo(x1, y1, x2, y2, self.x, self.y));
+    }
+
+    fn close(&mut self)
+    {
+        if self.open {
+            self.segments.push(PathSegment::Close);
+            self.open = false;
+        }
+    }
+
+    /// The first stack-clearing operator may have the advance width as an additional first operand
+    fn parse_width(&mut self, has_width: bool)
+    {
+        if !self.width_parsed && has_width && !self.stack.is_empty() {
+            self.stack.remove(0);
+        }
+        self.width_parsed = true;
+    }
+
+    /// Runs the charstring, returns `Some(true)` after `endchar`
+    fn run(&mut self, data: &[u8], depth: usize)
+    -> Option<bool>
+    {
+        if depth > MAX_SUBR_DEPTH {
+            return None;
+        }
+
+        let mut position = 0;
+        while position < data.len() {
+            let b0 = data[position];
+            position += 1;
+            match b0 {
+                1 | 3 | 18 | 23 => {
+                    // hstem, vstem, hstemhm, vstemhm
+                    let odd = self.stack.len() % 2 == 1;
+                    self.parse_width(odd);
+                    self.stems += self.stack.len() / 2;
+                    self.stack.clear();
+                },
+                19 | 20 => {
+                    // hintmask, cntrmask: the operands are an implicit vstem
+                    let odd = self.stack.len() % 2 == 1;
+                    self.parse_width(odd);
+                    self.stems += self.stack.len() / 2;
+                    self.stack.clear();
+                    position += self.stems.div_ceil(8);
+                },
+                21 => {
+                    let has_width = self.stack.len() > 2;
+                    self.parse_width(has_width);
+                    let (dx, dy) = (*self.stack.first()?, *self.stack.get(1)?);
+                    self.move_to(dx, dy);
+                    self.stack.clear();
+                },
+                22 | 4 => {
+                    // hmoveto, vmoveto
+                    let has_width = self.stack.len() > 1;
+                    self.parse_width(has_width);
+                    let delta = *self.stack.first()?;
+                    if b0 == 22 { self.move_to(delta, 0.0) } else { self.move_to(0.0, delta) }
+                    self.stack.clear();
+                },
+                5 => {
+                    for pair in self.stack.clone().chunks(2).filter(|pair| pair.len() == 2) {
+                        self.line_to(pair[0], pair[1]);
+                    }
+                    self.stack.clear();
+                },
+                6 | 7 => {
+                    // hlineto, vlineto: alternating horizontal and vertical lines
+                    for (idx, delta) in self.stack.clone().into_iter().enumerate() {
+                        if (idx % 2 == 0) == (b0 == 6) { self.line_to(delta, 0.0) } else { self.line_to(0.0, delta) }
+                    }
+                    self.stack.clear();
+                },
+                8 | 24 | 25 => {
+                    // rrcurveto, rcurveline, rlinecurve
+                    let stack = self.stack.clone();
+                    let (lines_before, curves_end) = match b0 {
+                        24 => (0, stack.len().saturating_sub(2)),
+                        25 => (stack.len().saturating_sub(6), stack.len()),
+                        _ => (0, stack.len()),
+                    };
+                    for pair in stack[..lines_before].chunks(2).filter(|pair| pair.len() == 2) {
+                        self.line_to(pair[0], pair[1]);
+                    }
+                    for curve in stack[lines_before..curves_end].chunks(6).filter(|curve| curve.len() == 6) {
+                        self.curve_to([curve[0], curve[1], curve[2], curve[3], curve[4], curve[5]]);
+                    }
+                    if b0 == 24 && stack.len() >= 2 {
+                        self.line_to(stack[stack.len() - 2], stack[stack.len() - 1]);
+                    }
+                    self.stack.clear();
+                },
+                26 | 27 => {
+                    // vvcurveto, hhcurveto: an odd operand count starts with the other delta of the first curve
+                    let stack = self.stack.clone();
+                    let mut first = if stack.len() % 2 == 1 { stack[0] } else { 0.0 };
+                    for curve in stack[stack.len() % 2..].chunks(4).filter(|curve| curve.len() == 4) {
+                        if b0 == 26 {
+                            self.curve_to([first, curve[0], curve[1], curve[2], 0.0, curve[3]]);
+                        } else {
+                            self.curve_to([curve[0], first, curve[1], curve[2], curve[3], 0.0]);
+                        }
+                        first = 0.0;
+                    }
+                    self.stack.clear();
+                },
+                30 | 31 => {
+                    // vhcurveto, hvcurveto: the curves alternate between starting vertical and horizontal
+                    let stack = self.stack.clone();
+                    let mut horizontal = b0 == 31;
+                    let mut idx = 0;
+                    while idx + 4 <= stack.len() {
+                        let last = if stack.len() - idx == 5 { stack[idx + 4] } else { 0.0 };
+                        let s = &stack[idx..idx + 4];
+                        if horizontal {
+                            self.curve_to([s[0], 0.0, s[1], s[2], last, s[3]]);
+                        } else {
+                            self.curve_to([0.0, s[0], s[1], s[2], s[3], last]);
+                        }
+                        horizontal = !horizontal;
+                        idx += 4;
+                    }
+                    self.stack.clear();
+                },
+                10 | 29 => {
+                    // callsubr, callgsubr
+                    let subrs = if b0 == 10 { self.local_subrs? } else { self.global_subrs };
+                    let number = self.stack.pop()? as i32 + subr_bias(subrs.count);
+                    if number < 0 {
+                        return None;
+                    }
+                    let subr = subrs.get(number as usize)?;
+                    if self.run(subr, depth + 1)? {
+                        return Some(true);
+                    }
+                },
+                11 => return Some(false),
+                14 => {
+                    let has_width = self.stack.len() == 1 || self.stack.len() == 5;
+                    self.parse_width(has_width);
+                    self.close();
+                    return Some(true);
+                },
+                12 => {
+                    let operator = *data.get(position)?;
+                    position += 1;
+                    let s = self.stack.clone();
+                    let (start_x, start_y) = (self.x, self.y);
+                    match (operator, s.len()) {
+                        (35, 13) => {
+                            self.curve_to([s[0], s[1], s[2], s[3], s[4], s[5]]);
+                            self.curve_to([s[6], s[7], s[8], s[9], s[10], s[11]]);
+                        },
+                        (34, 7) => {
+                            self.curve_to([s[0], 0.0, s[1], s[2], s[3], 0.0]);
+                            self.curve_to([s[4], 0.0, s[5], -s[2], s[6], 0.0]);
+                        },
+                        (36, 9) => {
+                            self.curve_to([s[0], s[1], s[2], s[3], s[4], 0.0]);
+                            let dy = start_y - self.y - s[7];
+                            self.curve_to([s[5], 0.0, s[6], s[7], s[8], dy]);
+                        },
+                        (37, 11) => {
+                            self.curve_to([s[0], s[1], s[2], s[3], s[4], s[5]]);
+                            let dx: f64 = s[..10].iter().step_by(2).sum();
+                            let dy: f64 = s[1..10].iter().step_by(2).sum();
+                            let (x, y) = (self.x + s[6] + s[8], self.y + s[7] + s[9]);
+                            let last = if dx.abs() > dy.abs() { [s[10], start_y - y] } else { [start_x - x, s[10]] };
+                            self.curve_to([s[6], s[7], s[8], s[9], last[0], last[1]]);
+                        },
+                        _ => { },
+                    }
+                    self.stack.clear();
+                },
+                28 => {
+                    self.stack.push(f64::from(read_i16(data, position)?));
+                    position += 2;
+                },
+                32..=246 => self.stack.push(f64::from(b0) - 139.0),
+                247..=250 => {
+                    self.stack.push((f64::from(b0) - 247.0) * 256.0 + f64::from(*data.get(position)?) + 108.0);
+                    position += 1;
+                },
+                251..=254 => {
+                    self.stack.push(-(f64::from(b0) - 251.0) * 256.0 - f64::from(*data.get(position)?) - 108.0);
+                    position += 1;
+                },
+                255 => {
+                    let bytes = data.get(position..position + 4)?;
+                    self.stack.push(f64::from(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])) / 65536.0);
+                    position += 4;
+                },
+                _ => self.stack.clear(),
+            }
+        }
+
+        Some(false)
+    }
+}
+
+/// Selects the Font DICT of a glyph in a CID-keyed font
+fn fd_index(cff: &[u8], fd_select: usize, glyph: u16)
+-> Option<usize>
+{
+    match *cff.get(fd_select)? {
+        0 => cff.get(fd_select + 1 + glyph as usize).map(|fd| *fd as usize),
+        3 => {
+            let ranges = read_u16(cff, fd_select + 1)? as usize;
+            (0..ranges).find_map(|idx| {
+                let record = fd_select + 3 + idx * 3;
+                let (first, next) = (read_u16(cff, record)?, read_u16(cff, record + 5)?);
+                if glyph >= first && glyph < next { cff.get(record + 2).map(|fd| *fd as usize) } else { None }
+            })
+        },
+        _ => None,
+    }
+}
+
+/// Outline of a glyph of the `CFF ` table in font units, `None` if the table or the glyph can't be read
+pub(crate) fn cff_glyph_outline(font: &[u8], glyph: u16)
+-> Option<Vec<PathSegment>>
+{
+    let cff = find_table(font, b"CFF ")?;
+    let header_size = *cff.get(2)? as usize;
+    let (_names, offset) = Index::parse(cff, header_size)?;
+    let (top_dicts, offset) = Index::parse(cff, offset)?;
+    let (_strings, offset) = Index::parse(cff, offset)?;
+    let (global_subrs, _) = Index::parse(cff, offset)?;
+
+    let top_dict = parse_dict(top_dicts.get(0)?)?;
+    let (charstrings, _) = Index::parse(cff, *dict_value(&top_dict, 17)?.first()? as usize)?;
+
+    // CID-keyed fonts have a Private DICT per Font DICT
+    let private = match (dict_value(&top_dict, 1236), dict_value(&top_dict, 1237)) {
+        (Some(fd_array), Some(fd_select)) => {
+            let (fd_array, _) = Index::parse(cff, *fd_array.first()? as usize)?;
+            let font_dict = parse_dict(fd_array.get(fd_index(cff, *fd_select.first()? as usize, glyph)?)?)?;
+            dict_value(&font_dict, 18).map(|private| private.to_vec())
+        },
+        _ => dict_value(&top_dict, 18).map(|private| private.to_vec()),
+    };
+
+    let mut charstring = Charstring {
+        global_subrs,
+        local_subrs: private.and_then(|private| local_subrs(cff, &private)),
+        stack: Vec::new(),
+        x: 0.0,
+        y: 0.0,
+        stems: 0,
+        width_parsed: false,
+        open: false,
+        segments: Vec::new(),
+    };
+    charstring.run(charstrings.get(glyph as usize)?, 0)?;
+    charstring.close();
+
+    Some(charstring.segments)
+}
+
+/// Builds a minimal `CFF ` table for tests. Glyph 1 is a square of 100 units, drawn with a
+/// global subroutine for its right edge.
+#[cfg(test)]
+pub(crate) fn test_cff()
+-> Vec<u8>
+{
+    let int = |value: i32| { let mut bytes = vec![29]; bytes.extend_from_slice(&value.to_be_bytes()); bytes };
+
+    // 600 (width) 0 0 rmoveto 100 hlineto -107 callgsubr -100 hlineto endchar
+    let glyph = vec![248, 236, 139, 139, 21, 239, 6, 32, 29, 39, 6, 14];
+    let charstrings_offset = 4 + 9 + 22 + 2 + 8;
+    let private_offset = charstrings_offset as i32 + 6 + 1 + glyph.len() as i32;
+
+    let mut top_dict = int(charstrings_offset);
+    top_dict.push(17);
+    top_dict.extend(int(0));
+    top_dict.extend(int(private_offset));
+    top_dict.push(18);
+
+    let mut cff = vec![1, 0, 4, 1];
+    cff.extend_from_slice(&[0, 1, 1, 1, 5]);
+    cff.extend_from_slice(b"Test");
+    cff.extend_from_slice(&[0, 1, 1, 1, top_dict.len() as u8 + 1]);
+    cff.extend(top_dict);
+    cff.extend_from_slice(&[0, 0]);
+    // global subroutine: 100 vlineto return
+    cff.extend_from_slice(&[0, 1, 1, 1, 4, 239, 7, 11]);
+    cff.extend_from_slice(&[0, 2, 1, 1, 2, glyph.len() as u8 + 2, 14]);
+    cff.extend(glyph);
+    cff
+}
+
+#[test]
+fn test_cff_glyph_outline() {
+    use self::PathSegment::*;
+    use types::plugins::graphics::two_dimensional::sfnt::test_font;
+
+    let font = test_font();
+    assert_eq!(cff_glyph_outline(&font, 1), Some(vec![MoveTo(0.0, 0.0), LineTo(100.0, 0.0), LineTo(100.0, 100.0), LineTo(0.0, 100.0), Close]));
+    assert_eq!(cff_glyph_outline(&font, 0), Some(vec![]));
+    assert_eq!(cff_glyph_outline(&font, 2), None);
+
+    // curves: 0 0 rmoveto 10 20 30 40 hvcurveto 10 20 30 40 50 60 rrcurveto endchar
+    let mut charstring = Charstring {
+        global_subrs: Index { count: 0, off_size: 1, offsets: &[], data: &[] },
+        local_subrs: None, stack: Vec::new(), x: 0.0, y: 0.0, stems: 0, width_parsed: false, open: false, segments: Vec::new(),
+    };
+    charstring.run(&[139, 139, 21, 149, 159, 169, 179, 31, 149, 159, 169, 179, 189, 199, 8, 14], 0).unwrap();
+    assert_eq!(charstring.segments, vec![
+        MoveTo(0.0, 0.0), CurveTo(10.0, 0.0, 30.0, 30.0, 30.0, 70.0), CurveTo(40.0, 90.0, 70.0, 130.0, 120.0, 190.0), Close,
+    ]);
+}
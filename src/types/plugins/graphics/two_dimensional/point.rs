@@ -1,3 +1,4 @@
+use std::ops::{Add, Sub, Mul};
 use {Mm, Pt};
 
 #[derive(Debug, Copy, Clone)]
@@ -10,6 +11,9 @@ pub struct Point {
 
 impl Point {
 
+    /// The bottom left corner of the page
+    pub const ZERO: Point = Point { x: Pt(0.0), y: Pt(0.0) };
+
     /// Create a new point.
     /// **WARNING: The reference point for a point is the bottom left corner, not the top left**
     #[inline]
@@ -21,6 +25,45 @@ impl Point {
             y: y.into(),
         }
     }
+
+    /// Create a new point from millimeters, shorthand for `Point::new(Mm(x), Mm(y))`
+    #[inline]
+    pub fn from_mm(x: f64, y: f64)
+    -> Self
+    {
+        Self::new(Mm(x), Mm(y))
+    }
+}
+
+impl Add for Point {
+    type Output = Point;
+
+    fn add(self, other: Point)
+    -> Point
+    {
+        Point { x: self.x + other.x, y: self.y + other.y }
+    }
+}
+
+impl Sub for Point {
+    type Output = Point;
+
+    fn sub(self, other: Point)
+    -> Point
+    {
+        Point { x: self.x - other.x, y: self.y - other.y }
+    }
+}
+
+/// Scales the point relative to the bottom left corner of the page
+impl Mul<f64> for Point {
+    type Output = Point;
+
+    fn mul(self, factor: f64)
+    -> Point
+    {
+        Point { x: self.x * factor, y: self.y * factor }
+    }
 }
 
 impl PartialEq for Point {
@@ -39,4 +82,13 @@ impl PartialEq for Point {
 
         false
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_point_arithmetic() {
+    let corner = Point::from_mm(10.0, 20.0);
+    assert_eq!(corner, Point::new(Mm(10.0), Mm(20.0)));
+    assert_eq!(corner + Point::from_mm(5.0, 1.0), Point::from_mm(15.0, 21.0));
+    assert_eq!(corner - Point::from_mm(5.0, 1.0), Point::from_mm(5.0, 19.0));
+    assert_eq!(corner * 1.5, Point::from_mm(15.0, 30.0));
+    assert_eq!(Point::ZERO + corner, corner);
+}
@@ -0,0 +1,103 @@
+use lopdf;
+use {Mm, Pt};
+
+/// Axis-aligned rectangle on a page, used for the position of
+/// form fields and annotations.
+/// **WARNING: The reference point is the bottom left corner of the page, not the top left**
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect {
+    /// x position of the lower left corner in pt
+    pub llx: Pt,
+    /// y position of the lower left corner in pt
+    pub lly: Pt,
+    /// x position of the upper right corner in pt
+    pub urx: Pt,
+    /// y position of the upper right corner in pt
+    pub ury: Pt,
+}
+
+impl Rect {
+
+    /// Creates a new rectangle from the lower left corner and its size
+    #[inline]
+    pub fn new(x: Mm, y: Mm, width: Mm, height: Mm)
+    -> Self
+    {
+        let (x, y, width, height): (Pt, Pt, Pt, Pt) = (x.into(), y.into(), width.into(), height.into());
+        Self {
+            llx: x,
+            lly: y,
+            urx: x + width,
+            ury: y + height,
+        }
+    }
+
+    /// Width of the rectangle in pt
+    #[inline]
+    pub fn width(&self)
+    -> Pt
+    {
+        self.urx - self.llx
+    }
+
+    /// Height of the rectangle in pt
+    #[inline]
+    pub fn height(&self)
+    -> Pt
+    {
+        self.ury - self.lly
+    }
+
+    /// Returns the smallest rectangle that contains both rectangles
+    #[inline]
+    pub fn union(&self, other: &Rect)
+    -> Self
+    {
+        Self {
+            llx: Pt(self.llx.0.min(other.llx.0)),
+            lly: Pt(self.lly.0.min(other.lly.0)),
+            urx: Pt(self.urx.0.max(other.urx.0)),
+            ury: Pt(self.ury.0.max(other.ury.0)),
+        }
+    }
+
+    /// Checks if the rectangles have an area in common
+    #[inline]
+    pub(crate) fn overlaps(&self, other: &Rect)
+    -> bool
+    {
+        self.llx.0 < other.urx.0 && self.urx.0 > other.llx.0 && self.lly.0 < other.ury.0 && self.ury.0 > other.lly.0
+    }
+
+    /// Moves the rectangle by `dx` and `dy`
+    #[inline]
+    pub(crate) fn translated(&self, dx: Pt, dy: Pt)
+    -> Self
+    {
+        Self {
+            llx: self.llx + dx,
+            lly: self.lly + dy,
+            urx: self.urx + dx,
+            ury: self.ury + dy,
+        }
+    }
+
+    /// Multiplies all coordinates with `factor`
+    #[inline]
+    pub(crate) fn scaled(&self, factor: f64)
+    -> Self
+    {
+        Self {
+            llx: Pt(self.llx.0 * factor),
+            lly: Pt(self.lly.0 * factor),
+            urx: Pt(self.urx.0 * factor),
+            ury: Pt(self.ury.0 * factor),
+        }
+    }
+}
+
+impl From<Rect> for lopdf::Object {
+    fn from(rect: Rect) -> Self {
+        lopdf::Object::Array(vec![rect.llx.into(), rect.lly.into(), rect.urx.into(), rect.ury.into()])
+    }
+}
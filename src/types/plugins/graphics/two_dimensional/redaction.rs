@@ -0,0 +1,243 @@
+//! Redaction: removes the content in an area of a page from its operations
+
+use lopdf::Object;
+use lopdf::content::Operation;
+use {FontList, Rect};
+use types::plugins::graphics::two_dimensional::bounding_box::{Extents, Interpreter, ShownText, TextArray, line_operations};
+
+/// Removes everything that is drawn into `area` (in page coordinates) from the operations:
+///
+/// - characters that overlap the area are removed from the strings of the text operators, the
+///   other characters of the text keep their position
+/// - paths that are completely inside the area are removed. Paths that are only partially
+///   inside stay, as well as clipping paths
+/// - XObjects (images, SVGs) and inline images that overlap the area are removed
+/// - marked content that something was removed from loses its replacement text (`/ActualText`,
+///   `/Alt` and `/E`, e.g. of `PdfLayerReference::add_glyphs_with_text()`), which would
+///   otherwise still contain the removed text
+///
+/// Returns the remaining operations and the names of the removed XObjects
+pub(crate) fn redact_operations(operations: Vec<Operation>, area: Rect, fonts: &FontList)
+-> (Vec<Operation>, Vec<String>)
+{
+    let mut redacted = Vec::with_capacity(operations.len());
+    let mut removed_xobjects = Vec::new();
+    let mut interpreter = Interpreter::new();
+    // the path is only written once it is known whether it is painted inside the area
+    let mut path = Vec::new();
+    let mut path_extents: Option<Extents> = None;
+    let mut clips = false;
+    // operations of an inline image, from `BI` to `EI`
+    let mut inline_image: Option<Vec<Operation>> = None;
+    // open marked content: index of the `BDC` operation (if it has a property list),
+    // and whether something inside was removed
+    let mut marked_content: Vec<(Option<usize>, bool)> = Vec::new();
+
+    for operation in operations {
+        if let Some(mut image) = inline_image.take() {
+            let end = operation.operator == "EI";
+            image.push(operation);
+            if !end {
+                inline_image = Some(image);
+            } else if !interpreter.xobject_extents().overlaps(&area) {
+                redacted.append(&mut image);
+            } else {
+                mark_removed(&mut marked_content);
+            }
+            continue;
+        }
+
+        interpreter.update(&operation);
+        match operation.operator.as_str() {
+            "BI" => inline_image = Some(vec![operation]),
+            "BMC" => {
+                marked_content.push((None, false));
+                redacted.push(operation);
+            },
+            "BDC" => {
+                let has_properties = operation.operands.get(1).map(|o| o.as_dict().is_ok()).unwrap_or(false);
+                marked_content.push((if has_properties { Some(redacted.len()) } else { None }, false));
+                redacted.push(operation);
+            },
+            "EMC" => {
+                if let Some((Some(index), true)) = marked_content.pop() {
+                    remove_replacement_text(&mut redacted[index]);
+                }
+                redacted.push(operation);
+            },
+            "m" | "l" | "c" | "v" | "y" | "re" | "h" => {
+                for point in interpreter.path_points(&operation) {
+                    Extents::include(&mut path_extents, point);
+                }
+                path.push(operation);
+            },
+            "W" | "W*" => {
+                clips = true;
+                path.push(operation);
+            },
+            "S" | "s" | "B" | "B*" | "b" | "b*" | "f" | "F" | "f*" | "n" => {
+                let painted = path_extents.take().and_then(|extents| interpreter.painted_extents(&operation.operator, extents));
+                let covered = painted.map(|extents| extents.inside(&area)).unwrap_or(false);
+                let clipping = ::std::mem::replace(&mut clips, false);
+                if !covered || clipping {
+                    redacted.append(&mut path);
+                    redacted.push(operation);
+                } else {
+                    mark_removed(&mut marked_content);
+                }
+                path.clear();
+            },
+            "Do" if interpreter.xobject_extents().overlaps(&area) => {
+                mark_removed(&mut marked_content);
+                if let Some(Ok(name)) = operation.operands.first().map(|o| o.as_name_str()) {
+                    removed_xobjects.push(name.to_string());
+                }
+            },
+            "Tj" | "TJ" | "'" | "\"" => {
+                let scale = interpreter.text_scale();
+                let shown = interpreter.show_text(&operation, fonts);
+                let overlapped = shown.iter().any(|s| match *s {
+                    ShownText::Character { ref bounds, .. } => bounds.overlaps(&area),
+                    ShownText::Adjustment(_) => false,
+                });
+                if !overlapped || scale == 0.0 {
+                    redacted.push(operation);
+                    continue;
+                }
+
+                mark_removed(&mut marked_content);
+                redacted.extend(line_operations(&operation));
+                redacted.push(redact_text(shown, &area, scale).into_operation());
+            },
+            _ => redacted.push(operation),
+        }
+    }
+
+    redacted.append(&mut path);
+    redacted.extend(inline_image.unwrap_or_default());
+    (redacted, removed_xobjects)
+}
+
+/// Something inside the open marked content sequences was removed
+fn mark_removed(marked_content: &mut [(Option<usize>, bool)])
+{
+    for &mut (_, ref mut removed) in marked_content {
+        *removed = true;
+    }
+}
+
+/// Removes the replacement texts from the property list of a `BDC` operation. If nothing
+/// else is left in the list, the operation is replaced with a `BMC` of the same tag.
+fn remove_replacement_text(operation: &mut Operation)
+{
+    let is_empty = match operation.operands.get_mut(1).map(Object::as_dict_mut) {
+        Some(Ok(properties)) => {
+            for key in &[&b"ActualText"[..], b"Alt", b"E"] {
+                properties.remove(key);
+            }
+            properties.is_empty()
+        },
+        _ => false,
+    };
+    if is_empty {
+        operation.operator = "BMC".into();
+        operation.operands.truncate(1);
+    }
+}
+
+/// Builds the array of a `TJ` operator from the shown text, the characters that overlap the
+/// area are replaced by adjustments of the same width. `scale` is the font size multiplied
+/// with the horizontal scaling.
+fn redact_text(shown: Vec<ShownText>, area: &Rect, scale: f64)
+-> TextArray
+{
+    let mut array = TextArray::default();
+    for s in shown {
+        match s {
+            ShownText::Character { ref bounds, displacement, .. } if bounds.overlaps(area) => {
+                array.push_adjustment(-displacement * 1000.0 / scale);
+            },
+            ShownText::Character { code, .. } => array.push_code(&code),
+            ShownText::Adjustment(adjustment) => array.push_adjustment(adjustment),
+        }
+    }
+    array
+}
+
+#[test]
+fn test_redact_operations() {
+    use lopdf::Object;
+    use Pt;
+
+    let fonts = FontList::new();
+    let area = Rect { llx: Pt(0.0), lly: Pt(0.0), urx: Pt(20.0), ury: Pt(20.0) };
+
+    let operations = vec![
+        // covered rectangle, removed
+        Operation::new("re", vec![5.into(), 5.into(), 10.into(), 10.into()]),
+        Operation::new("f", vec![]),
+        // partially covered rectangle, stays
+        Operation::new("re", vec![5.into(), 5.into(), 30.into(), 10.into()]),
+        Operation::new("f", vec![]),
+        // image in the area, removed
+        Operation::new("q", vec![]),
+        Operation::new("cm", vec![10.into(), 0.into(), 0.into(), 10.into(), 15.into(), 15.into()]),
+        Operation::new("Do", vec![Object::Name("X0".into())]),
+        Operation::new("Q", vec![]),
+        // inline image in the area, removed
+        Operation::new("q", vec![]),
+        Operation::new("cm", vec![10.into(), 0.into(), 0.into(), 10.into(), 0.into(), 0.into()]),
+        Operation::new("BI", vec![]),
+        Operation::new("ID", vec![Object::Name("W".into()), 1.into(), Object::Name("H".into()), 1.into()]),
+        Operation::new("!!~>", vec![]),
+        Operation::new("EI", vec![]),
+        Operation::new("Q", vec![]),
+        // fonts that aren't in the font list are measured with 0.5 em per character: the first two
+        // characters (of 5pt each) are in the area, the third one overlaps it
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name("F1".into()), 10.into()]),
+        Operation::new("Td", vec![5.into(), 5.into()]),
+        Operation::new("Tj", vec![Object::string_literal("abcdef")]),
+        Operation::new("ET", vec![]),
+    ];
+
+    let (redacted, removed) = redact_operations(operations, area, &fonts);
+    assert_eq!(removed, vec!["X0".to_string()]);
+
+    let operators = redacted.iter().map(|o| o.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["re", "f", "q", "cm", "Q", "q", "cm", "Q", "BT", "Tf", "Td", "TJ", "ET"]);
+    assert_eq!(format!("{:?}", redacted[11].operands), format!("{:?}", vec![Object::Array(vec![
+        Object::Real(-1500.0),
+        Object::String(b"def".to_vec(), lopdf::StringFormat::Hexadecimal),
+    ])]));
+}
+
+#[test]
+fn test_redact_marked_content() {
+    use lopdf::Dictionary;
+    use Pt;
+
+    let fonts = FontList::new();
+    let area = Rect { llx: Pt(0.0), lly: Pt(0.0), urx: Pt(20.0), ury: Pt(20.0) };
+    let text = |x: i64| vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name("F1".into()), 10.into()]),
+        Operation::new("Td", vec![x.into(), 5.into()]),
+        Operation::new("Tj", vec![Object::string_literal("ab")]),
+        Operation::new("ET", vec![]),
+    ];
+
+    // like `add_glyphs_with_text()`: the text inside the area loses its property list
+    // (with the `/ActualText`), the text outside keeps it
+    let mut operations = vec![Operation::new("BDC", vec![Object::Name("Span".into()), Object::Dictionary(Dictionary::new())])];
+    operations.extend(text(5));
+    operations.push(Operation::new("EMC", vec![]));
+    operations.push(Operation::new("BDC", vec![Object::Name("Span".into()), Object::Dictionary(Dictionary::new())]));
+    operations.extend(text(50));
+    operations.push(Operation::new("EMC", vec![]));
+
+    let (redacted, _) = redact_operations(operations, area, &fonts);
+    let operators = redacted.iter().map(|o| o.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["BMC", "BT", "Tf", "Td", "TJ", "ET", "EMC", "BDC", "BT", "Tf", "Td", "Tj", "ET", "EMC"]);
+    assert_eq!(format!("{:?}", redacted[0].operands), format!("{:?}", vec![Object::Name("Span".into())]));
+}
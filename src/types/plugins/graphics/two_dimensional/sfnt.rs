@@ -0,0 +1,311 @@
+//! Reader for the metric tables of OpenType fonts (`head`, `hhea`, `maxp`, `hmtx` and `cmap`).
+//! `rusttype` requires TrueType outlines (`glyf`), so fonts with CFF outlines are measured
+//! and encoded with this reader. The outlines themselves are never read.
+
+use std::collections::BTreeMap;
+
+/// Metrics and character map of an OpenType font
+#[derive(Debug, Clone)]
+pub(crate) struct OpenTypeFont {
+    pub(crate) units_per_em: u16,
+    pub(crate) ascent: i16,
+    pub(crate) descent: i16,
+    /// Bounding box of all glyphs: x min, y min, x max, y max
+    pub(crate) bbox: [i16; 4],
+    pub(crate) num_glyphs: u16,
+    /// Advance widths of the first `numberOfHMetrics` glyphs, the remaining glyphs
+    /// have the width of the last one
+    advances: Vec<u16>,
+    /// Unicode code point to glyph ID
+    cmap: BTreeMap<u32, u16>,
+}
+
+#[inline]
+pub(crate) fn read_u16(data: &[u8], offset: usize)
+-> Option<u16>
+{
+    data.get(offset..offset + 2).map(|bytes| u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+}
+
+#[inline]
+pub(crate) fn read_i16(data: &[u8], offset: usize)
+-> Option<i16>
+{
+    read_u16(data, offset).map(|value| value as i16)
+}
+
+#[inline]
+pub(crate) fn read_u32(data: &[u8], offset: usize)
+-> Option<u32>
+{
+    Some(u32::from(read_u16(data, offset)?) << 16 | u32::from(read_u16(data, offset + 2)?))
+}
+
+/// Returns the data of a table of the font
+pub(crate) fn find_table<'a>(data: &'a [u8], tag: &[u8; 4])
+-> Option<&'a [u8]>
+{
+    let num_tables = read_u16(data, 4)? as usize;
+    (0..num_tables).find_map(|idx| {
+        let record = 12 + idx * 16;
+        if data.get(record..record + 4)? != tag {
+            return None;
+        }
+        let offset = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        data.get(offset..offset.checked_add(length)?)
+    })
+}
+
+/// Returns the tags and the data of all tables of the font
+pub(crate) fn tables(data: &[u8])
+-> Vec<([u8; 4], &[u8])>
+{
+    let num_tables = read_u16(data, 4).unwrap_or(0) as usize;
+    (0..num_tables).filter_map(|idx| {
+        let record = 12 + idx * 16;
+        let mut tag = [0; 4];
+        tag.copy_from_slice(data.get(record..record + 4)?);
+        let offset = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        Some((tag, data.get(offset..offset.checked_add(length)?)?))
+    }).collect()
+}
+
+/// Checksum of a table: the sum of its big-endian 32-bit words
+fn checksum(data: &[u8])
+-> u32
+{
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Writes a font file with the tables and the `sfnt` version (`0x00010000` for TrueType
+/// outlines). The checksums and the `checkSumAdjustment` of the `head` table are recalculated.
+pub(crate) fn write_font(version: u32, mut tables: Vec<([u8; 4], Vec<u8>)>)
+-> Vec<u8>
+{
+    tables.sort_by_key(|table| table.0);
+    let num_tables = tables.len();
+    let entry_selector = (0..16).rev().find(|exponent| 1 << exponent <= num_tables).unwrap_or(0);
+    let search_range = (1 << entry_selector) * 16;
+
+    let mut font = version.to_be_bytes().to_vec();
+    for value in &[num_tables, search_range, entry_selector, (num_tables * 16).saturating_sub(search_range)] {
+        font.extend_from_slice(&(*value as u16).to_be_bytes());
+    }
+
+    let mut offset = 12 + num_tables * 16;
+    let mut head_offset = None;
+    for (tag, data) in &mut tables {
+        if tag == b"head" && data.len() >= 12 {
+            data[8..12].copy_from_slice(&[0; 4]);
+            head_offset = Some(offset);
+        }
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum(data).to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        offset += (data.len() + 3) & !3;
+    }
+    for (_, data) in &tables {
+        font.extend_from_slice(data);
+        font.resize((font.len() + 3) & !3, 0);
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&font));
+        font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+
+    font
+}
+
+/// Whether the font has CFF (PostScript) outlines instead of TrueType outlines
+pub(crate) fn has_cff_outlines(data: &[u8])
+-> bool
+{
+    data.starts_with(b"OTTO") || find_table(data, b"CFF ").is_some() || find_table(data, b"CFF2").is_some()
+}
+
+impl OpenTypeFont {
+
+    /// Reads the metrics of the font, `None` if a required table is missing or damaged
+    pub(crate) fn parse(data: &[u8])
+    -> Option<Self>
+    {
+        let head = find_table(data, b"head")?;
+        let hhea = find_table(data, b"hhea")?;
+        let maxp = find_table(data, b"maxp")?;
+        let hmtx = find_table(data, b"hmtx")?;
+        let cmap = find_table(data, b"cmap")?;
+
+        let number_of_h_metrics = read_u16(hhea, 34)? as usize;
+        let advances = (0..number_of_h_metrics).map(|idx| read_u16(hmtx, idx * 4)).collect::<Option<Vec<u16>>>()?;
+        if advances.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            units_per_em: read_u16(head, 18).filter(|units| *units > 0)?,
+            ascent: read_i16(hhea, 4)?,
+            descent: read_i16(hhea, 6)?,
+            bbox: [read_i16(head, 36)?, read_i16(head, 38)?, read_i16(head, 40)?, read_i16(head, 42)?],
+            num_glyphs: read_u16(maxp, 4)?,
+            advances,
+            cmap: parse_cmap(cmap)?,
+        })
+    }
+
+    /// Glyph ID of a character, 0 (the missing glyph) if the font doesn't contain it
+    #[inline]
+    pub(crate) fn glyph_index(&self, codepoint: u32)
+    -> u16
+    {
+        self.cmap.get(&codepoint).cloned().unwrap_or(0)
+    }
+
+    /// Advance width of a glyph in font units, `None` for glyph IDs that are not in the font
+    pub(crate) fn advance_width(&self, glyph: u16)
+    -> Option<u16>
+    {
+        if glyph >= self.num_glyphs {
+            return None;
+        }
+        self.advances.get(glyph as usize).or_else(|| self.advances.last()).cloned()
+    }
+}
+
+/// Reads the best Unicode subtable of the `cmap` table (format 12 for all of Unicode,
+/// format 4 for the basic multilingual plane)
+fn parse_cmap(cmap: &[u8])
+-> Option<BTreeMap<u32, u16>>
+{
+    let num_tables = read_u16(cmap, 2)? as usize;
+    let mut best: Option<(u8, usize)> = None;
+
+    for idx in 0..num_tables {
+        let record = 4 + idx * 8;
+        let (platform, encoding) = (read_u16(cmap, record)?, read_u16(cmap, record + 2)?);
+        let offset = read_u32(cmap, record + 4)? as usize;
+        let format = read_u16(cmap, offset)?;
+        let priority = match (platform, encoding, format) {
+            (3, 10, 12) | (0, _, 12) => 2,
+            (3, 1, 4) | (0, _, 4) => 1,
+            _ => continue,
+        };
+        if best.map(|(best_priority, _)| priority > best_priority).unwrap_or(true) {
+            best = Some((priority, offset));
+        }
+    }
+
+    let (_, offset) = best?;
+    let subtable = cmap.get(offset..)?;
+    let mut map = BTreeMap::new();
+
+    match read_u16(subtable, 0)? {
+        4 => {
+            let segments = read_u16(subtable, 6)? as usize / 2;
+            let (ends, starts, deltas, range_offsets) = (14, 16 + segments * 2, 16 + segments * 4, 16 + segments * 6);
+            for segment in 0..segments {
+                let end = read_u16(subtable, ends + segment * 2)?;
+                let start = read_u16(subtable, starts + segment * 2)?;
+                let delta = read_u16(subtable, deltas + segment * 2)?;
+                let range_offset = read_u16(subtable, range_offsets + segment * 2)? as usize;
+                for codepoint in start..=end {
+                    if codepoint == 0xFFFF {
+                        break;
+                    }
+                    let glyph = if range_offset == 0 {
+                        codepoint.wrapping_add(delta)
+                    } else {
+                        let address = range_offsets + segment * 2 + range_offset + (codepoint - start) as usize * 2;
+                        match read_u16(subtable, address)? {
+                            0 => 0,
+                            glyph => glyph.wrapping_add(delta),
+                        }
+                    };
+                    if glyph != 0 {
+                        map.insert(u32::from(codepoint), glyph);
+                    }
+                }
+            }
+        },
+        12 => {
+            let groups = read_u32(subtable, 12)? as usize;
+            for group in 0..groups {
+                let record = 16 + group * 12;
+                let (start, end, start_glyph) = (read_u32(subtable, record)?, read_u32(subtable, record + 4)?, read_u32(subtable, record + 8)?);
+                for codepoint in start..=end.min(0x10FFFF) {
+                    let glyph = start_glyph + (codepoint - start);
+                    if glyph != 0 && glyph <= 0xFFFF {
+                        map.insert(codepoint, glyph as u16);
+                    }
+                }
+            }
+        },
+        _ => return None,
+    }
+
+    Some(map)
+}
+
+/// Builds a minimal OpenType font with CFF outlines for tests: glyph 1 is 'A' (600 units wide),
+/// glyph 2 is 'B' (700 units wide), all other glyphs have the width of 'B'
+#[cfg(test)]
+pub(crate) fn test_font()
+-> Vec<u8>
+{
+    test_font_with(Vec::new())
+}
+
+/// The test font with additional tables
+#[cfg(test)]
+pub(crate) fn test_font_with(tables: Vec<([u8; 4], Vec<u8>)>)
+-> Vec<u8>
+{
+    use types::plugins::graphics::two_dimensional::outline::test_cff;
+
+    fn u16s(values: &[u16]) -> Vec<u8> { values.iter().flat_map(|v| vec![(v >> 8) as u8, *v as u8]).collect() }
+
+    let mut head = vec![0; 54];
+    head[18..20].copy_from_slice(&u16s(&[1000]));
+    head[36..44].copy_from_slice(&u16s(&[0, (-200i16) as u16, 900, 800]));
+    let mut hhea = vec![0; 36];
+    hhea[4..8].copy_from_slice(&u16s(&[800, (-200i16) as u16]));
+    hhea[34..36].copy_from_slice(&u16s(&[3]));
+    let maxp = u16s(&[0, 0x5000, 4]);
+    let hmtx = u16s(&[500, 0, 600, 0, 700, 0, 0]);
+    // format 4 with the segments 'A'..'B' and the final 0xFFFF segment
+    let mut cmap = u16s(&[0, 1, 3, 1, 0, 12]);
+    cmap.extend(u16s(&[4, 32, 0, 4, 4, 1, 0, 0x42, 0xFFFF, 0, 0x41, 0xFFFF, (1i16 - 0x41) as u16, 1, 0, 0]));
+    let mut font_tables = vec![
+        (*b"CFF ", test_cff()), (*b"cmap", cmap), (*b"head", head), (*b"hhea", hhea), (*b"hmtx", hmtx), (*b"maxp", maxp),
+    ];
+    font_tables.extend(tables);
+    write_font(0x4F54_544F, font_tables)
+}
+
+#[test]
+fn test_parse_opentype_font() {
+    let data = test_font();
+    assert!(has_cff_outlines(&data));
+
+    let font = OpenTypeFont::parse(&data).unwrap();
+    assert_eq!(font.units_per_em, 1000);
+    assert_eq!((font.ascent, font.descent), (800, -200));
+    assert_eq!(font.bbox, [0, -200, 900, 800]);
+    assert_eq!(font.glyph_index('A' as u32), 1);
+    assert_eq!(font.glyph_index('B' as u32), 2);
+    assert_eq!(font.glyph_index('C' as u32), 0);
+    assert_eq!(font.advance_width(1), Some(600));
+    assert_eq!(font.advance_width(3), Some(700));
+    assert_eq!(font.advance_width(4), None);
+
+    // damaged fonts are rejected instead of panicking
+    assert!(OpenTypeFont::parse(&data[..100]).is_none());
+    assert!(!has_cff_outlines(&[0, 1, 0, 0, 0, 0]));
+}
@@ -0,0 +1,76 @@
+//! Converts a stroke into an outline that can be filled, for strokes that are painted with
+//! something that PDF can only fill, like a gradient.
+//!
+//! The outline is a union of subpaths (filled with the nonzero winding rule): a rectangle for
+//! each segment of the polyline and a circle for each joint, so all joints are round. The
+//! line join style and the miter limit of the graphics state are ignored, the ends are
+//! always butt caps. Curves are approximated with straight segments first.
+
+use std::f64::consts::PI;
+
+/// Number of segments of the circles that connect the segments of the line
+const JOINT_SEGMENTS: usize = 16;
+
+/// Returns the closed subpaths that cover the stroke of the polyline with the given width.
+/// All subpaths are counterclockwise, so that they don't cancel each other out.
+pub(crate) fn stroke_outline(polyline: &[(f64, f64)], width: f64, closed: bool)
+-> Vec<Vec<(f64, f64)>>
+{
+    let half = width / 2.0;
+    let mut subpaths = Vec::new();
+    if half <= 0.0 {
+        return subpaths;
+    }
+
+    let segments = polyline.windows(2)
+        .filter(|s| s[0] != s[1])
+        .map(|s| (s[0], s[1]))
+        .collect::<Vec<_>>();
+
+    for &(start, end) in &segments {
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        // normal to the left of the direction of the segment
+        let (nx, ny) = (-dy / length * half, dx / length * half);
+        subpaths.push(vec![
+            (start.0 - nx, start.1 - ny),
+            (end.0 - nx, end.1 - ny),
+            (end.0 + nx, end.1 + ny),
+            (start.0 + nx, start.1 + ny),
+        ]);
+    }
+
+    // the joints between two segments, the start of a closed line is also a joint
+    let mut joints = segments.iter().skip(1).map(|s| s.0).collect::<Vec<_>>();
+    if closed && segments.len() > 1 {
+        joints.push(segments[0].0);
+    }
+
+    for (x, y) in joints {
+        subpaths.push((0..JOINT_SEGMENTS).map(|i| {
+            let angle = 2.0 * PI * i as f64 / JOINT_SEGMENTS as f64;
+            (x + half * angle.cos(), y + half * angle.sin())
+        }).collect());
+    }
+
+    subpaths
+}
+
+#[test]
+fn test_stroke_outline() {
+    // an L-shaped line, 2 pt wide
+    let outline = stroke_outline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (10.0, 10.0)], 2.0, false);
+    assert_eq!(outline.len(), 3);
+    assert_eq!(outline[0], vec![(0.0, -1.0), (10.0, -1.0), (10.0, 1.0), (0.0, 1.0)]);
+    assert_eq!(outline[1], vec![(11.0, 0.0), (11.0, 10.0), (9.0, 10.0), (9.0, 0.0)]);
+    assert_eq!(outline[2].len(), JOINT_SEGMENTS);
+
+    // counterclockwise, the signed area is positive
+    for subpath in &outline {
+        let area: f64 = subpath.iter().zip(subpath.iter().cycle().skip(1)).map(|(a, b)| a.0 * b.1 - b.0 * a.1).sum();
+        assert!(area > 0.0);
+    }
+
+    let closed = stroke_outline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 0.0)], 2.0, true);
+    assert_eq!(closed.len(), 6);
+}
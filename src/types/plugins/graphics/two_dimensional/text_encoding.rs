@@ -0,0 +1,75 @@
+//! Decoding of text in legacy encodings, see `PdfLayerReference::use_text_encoded()`
+
+use encoding::{DecoderTrap, EncodingRef};
+use encoding::all;
+use {Error, PdfError};
+
+/// Encoding of text that isn't UTF-8 yet, e.g. from legacy databases or files.
+/// The text is decoded to Unicode first, so it can be written in any font that has
+/// glyphs for the characters (Japanese text needs a Japanese font, etc.).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TextEncoding {
+    /// UTF-8, the bytes are only checked
+    Utf8,
+    /// UTF-16, big endian
+    Utf16Be,
+    /// UTF-16, little endian
+    Utf16Le,
+    /// ISO-8859-1 (Latin-1), Western European
+    Latin1,
+    /// Windows-1252 (Western European, the Latin-1 superset of Windows)
+    Windows1252,
+    /// Shift-JIS (Windows-31J), Japanese
+    ShiftJis,
+    /// EUC-JP, Japanese
+    EucJp,
+    /// GBK, Simplified Chinese
+    Gbk,
+    /// Big5, Traditional Chinese
+    Big5,
+    /// EUC-KR (Windows-949), Korean
+    EucKr,
+}
+
+impl TextEncoding {
+
+    /// Decodes the text, fails with `PdfError::InvalidTextEncoding` if the bytes aren't
+    /// valid in the encoding. A byte order mark at the start of UTF-8 or UTF-16 text is removed.
+    pub fn decode(self, bytes: &[u8])
+    -> Result<String, Error>
+    {
+        let text = self.encoding().decode(bytes, DecoderTrap::Strict)
+            .map_err(|_| Error::Pdf(PdfError::InvalidTextEncoding))?;
+        Ok(text.trim_start_matches('\u{feff}').to_string())
+    }
+
+    // The `encoding` crate is only used here and not exposed, so it can be replaced
+    // by `encoding_rs` (the maintained successor) without an API change.
+    fn encoding(self)
+    -> EncodingRef
+    {
+        match self {
+            TextEncoding::Utf8 => all::UTF_8,
+            TextEncoding::Utf16Be => all::UTF_16BE,
+            TextEncoding::Utf16Le => all::UTF_16LE,
+            TextEncoding::Latin1 => all::ISO_8859_1,
+            TextEncoding::Windows1252 => all::WINDOWS_1252,
+            TextEncoding::ShiftJis => all::WINDOWS_31J,
+            TextEncoding::EucJp => all::EUC_JP,
+            TextEncoding::Gbk => all::GBK,
+            TextEncoding::Big5 => all::BIG5_2003,
+            TextEncoding::EucKr => all::WINDOWS_949,
+        }
+    }
+}
+
+#[test]
+fn test_decode_text()
+{
+    assert_eq!(TextEncoding::Latin1.decode(b"Gr\xfc\xdfe").unwrap(), "Grüße");
+    assert_eq!(TextEncoding::Windows1252.decode(b"\x80 5").unwrap(), "€ 5");
+    assert_eq!(TextEncoding::Utf16Le.decode(b"\xff\xfeA\x00").unwrap(), "A");
+    assert_eq!(TextEncoding::ShiftJis.decode(b"\x93\xfa\x96\x7b").unwrap(), "日本");
+    assert_eq!(TextEncoding::Utf8.decode("日本".as_bytes()).unwrap(), "日本");
+    assert!(matches!(TextEncoding::Utf8.decode(b"\xff"), Err(Error::Pdf(PdfError::InvalidTextEncoding))));
+}
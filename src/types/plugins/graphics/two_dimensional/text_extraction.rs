@@ -0,0 +1,370 @@
+//! Extracts and replaces the text of a page in the text showing operators of its layers
+
+use lopdf::content::Operation;
+use lopdf::Object;
+use std::collections::HashMap;
+use {Font, FontList, IndirectFontRef, Rect};
+use types::plugins::graphics::two_dimensional::bounding_box::{Extents, Interpreter, ShownText, TextArray, encode_text, line_operations};
+
+/// Characters of the WinAnsi encoding (used by the builtin fonts) from 0x80 to 0x9F,
+/// the other codes are the same as in Latin-1
+const WIN_ANSI_80_9F: [char; 32] = [
+    '€', '\u{fffd}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{fffd}', 'Ž', '\u{fffd}',
+    '\u{fffd}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{fffd}', 'ž', 'Ÿ',
+];
+
+/// Text shown by a single text showing operator, see `PdfPageReference::text_runs()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    /// The decoded text
+    pub text: String,
+    /// Font size in page coordinates (in points), including the scaling of the text
+    pub font_size: f64,
+    /// Box of the text in page coordinates, from the descent to the ascent of the font
+    pub bounds: Rect,
+}
+
+/// A character shown on the page
+struct PlacedText {
+    text: String,
+    /// Start of the character on the baseline
+    x: f64,
+    y: f64,
+    /// End of the character
+    end_x: f64,
+    /// Height of the box of the character, roughly the font size
+    height: f64,
+}
+
+/// Decodes the character codes of the fonts of the page
+struct Decoder<'a> {
+    fonts: &'a FontList,
+    /// Text of the glyphs of the external fonts, by the name of the font
+    glyphs: HashMap<String, Option<HashMap<u16, String>>>,
+}
+
+impl<'a> Decoder<'a> {
+
+    fn new(fonts: &'a FontList)
+    -> Self
+    {
+        Self { fonts, glyphs: HashMap::new() }
+    }
+
+    fn decode(&mut self, font: &str, code: &[u8])
+    -> String
+    {
+        let fonts = self.fonts;
+        let glyphs = self.glyphs.entry(font.to_string()).or_insert_with(|| {
+            match fonts.get_font_data(&IndirectFontRef::new(font)) {
+                Some(Font::ExternalFont(font)) => Some(font.glyph_text(&font.get_font())),
+                _ => None,
+            }
+        });
+
+        match *glyphs {
+            Some(ref glyphs) => {
+                let gid = u16::from(code[0]) << 8 | u16::from(*code.get(1).unwrap_or(&0));
+                glyphs.get(&gid).cloned().unwrap_or_default()
+            },
+            None => code.iter().map(|b| match *b {
+                0x80..=0x9f => WIN_ANSI_80_9F[usize::from(*b - 0x80)],
+                b => char::from(b),
+            }).collect(),
+        }
+    }
+}
+
+/// Returns the text shown by the operations of the layers, in reading order: the characters
+/// are grouped into lines by their baseline, lines are sorted from the top to the bottom
+/// and the characters of a line from the left to the right. Gaps between the characters
+/// that are wider than a quarter of the font size are returned as spaces.
+pub(crate) fn extract_text(layers: &[&[Operation]], fonts: &FontList)
+-> String
+{
+    let mut decoder = Decoder::new(fonts);
+    let mut placed = Vec::new();
+
+    // every layer starts with the initial graphics state
+    for operations in layers {
+        let mut interpreter = Interpreter::new();
+        for operation in operations.iter() {
+            interpreter.update(operation);
+            match operation.operator.as_str() {
+                "Tj" | "TJ" | "'" | "\"" => {
+                    let font = interpreter.font().unwrap_or_default().to_string();
+                    for shown in interpreter.show_text(operation, fonts) {
+                        if let ShownText::Character { code, bounds, origin, .. } = shown {
+                            placed.push(PlacedText {
+                                text: decoder.decode(&font, &code),
+                                x: origin.0,
+                                y: origin.1,
+                                end_x: bounds.max_x.max(origin.0),
+                                height: bounds.max_y - bounds.min_y,
+                            });
+                        }
+                    }
+                },
+                _ => { },
+            }
+        }
+    }
+
+    // the sort is stable, characters on the same baseline stay in the order of the stream
+    placed.sort_by(|a, b| b.y.partial_cmp(&a.y).unwrap_or(::std::cmp::Ordering::Equal));
+
+    let mut lines = Vec::<Vec<PlacedText>>::new();
+    for character in placed {
+        let same_line = lines.last()
+            .and_then(|line| line.first())
+            .map(|first| (first.y - character.y).abs() < first.height.max(character.height) / 2.0)
+            .unwrap_or(false);
+        if same_line {
+            lines.last_mut().unwrap().push(character);
+        } else {
+            lines.push(vec![character]);
+        }
+    }
+
+    let mut text = Vec::with_capacity(lines.len());
+    for mut line in lines {
+        line.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(::std::cmp::Ordering::Equal));
+        let mut line_text = String::new();
+        let mut previous: Option<&PlacedText> = None;
+        for character in &line {
+            if let Some(previous) = previous {
+                let gap = character.x - previous.end_x;
+                let spaced = previous.text.ends_with(' ') || character.text.starts_with(' ');
+                if !spaced && gap > character.height.max(previous.height) / 4.0 {
+                    line_text.push(' ');
+                }
+            }
+            line_text.push_str(&character.text);
+            previous = Some(character);
+        }
+        text.push(line_text);
+    }
+
+    text.join("\n")
+}
+
+/// Returns the runs of text shown by the operations of the layers, in the order of the content
+/// streams. Every text showing operator that shows at least one character is a run.
+pub(crate) fn text_runs(layers: &[&[Operation]], fonts: &FontList)
+-> Vec<TextRun>
+{
+    let mut decoder = Decoder::new(fonts);
+    let mut runs = Vec::new();
+
+    for operations in layers {
+        let mut interpreter = Interpreter::new();
+        for operation in operations.iter() {
+            interpreter.update(operation);
+            if !["Tj", "TJ", "'", "\""].contains(&operation.operator.as_str()) {
+                continue;
+            }
+
+            let font = interpreter.font().unwrap_or_default().to_string();
+            let font_size = interpreter.font_size_on_page();
+            let mut text = String::new();
+            let mut bounds = None;
+            for shown in interpreter.show_text(operation, fonts) {
+                if let ShownText::Character { code, bounds: character, .. } = shown {
+                    text.push_str(&decoder.decode(&font, &code));
+                    Extents::union(&mut bounds, character);
+                }
+            }
+
+            if let Some(bounds) = bounds {
+                runs.push(TextRun { text, font_size, bounds: bounds.into() });
+            }
+        }
+    }
+
+    runs
+}
+
+/// Replaces the text `find` with `replace` in the operations, returns the number of replacements.
+/// Only text that is shown by a single operator is found. The replacement is encoded with the
+/// font of the text. If it is narrower or wider than the replaced text, the rest of the text of
+/// the operator moves accordingly, unless `shrink_to_fit` is set: then a replacement that is
+/// wider is compressed horizontally (`Tz`) to the width of the replaced text.
+pub(crate) fn replace_text(operations: Vec<Operation>, find: &str, replace: &str, shrink_to_fit: bool, fonts: &FontList)
+-> (Vec<Operation>, usize)
+{
+    if find.is_empty() {
+        return (operations, 0);
+    }
+
+    let mut decoder = Decoder::new(fonts);
+    let mut interpreter = Interpreter::new();
+    let mut replaced = Vec::with_capacity(operations.len());
+    let mut count = 0;
+
+    for operation in operations {
+        interpreter.update(&operation);
+        if !["Tj", "TJ", "'", "\""].contains(&operation.operator.as_str()) {
+            replaced.push(operation);
+            continue;
+        }
+
+        let font = interpreter.font().unwrap_or_default().to_string();
+        let scale = interpreter.text_scale();
+        let horizontal_scaling = interpreter.horizontal_scaling();
+        let replacement = encode_text(fonts, &font, replace);
+        let replacement_width = interpreter.displacement(&replacement, fonts);
+        let shown = interpreter.show_text(&operation, fonts);
+
+        // byte range of every character in the decoded text of the operator
+        let mut text = String::new();
+        let mut ranges = Vec::with_capacity(shown.len());
+        for s in &shown {
+            if let ShownText::Character { ref code, .. } = *s {
+                let start = text.len();
+                text.push_str(&decoder.decode(&font, code));
+                ranges.push(start..text.len());
+            }
+        }
+
+        let matches = text.match_indices(find).map(|(start, _)| start..start + find.len()).collect::<Vec<_>>();
+        if matches.is_empty() {
+            replaced.push(operation);
+            continue;
+        }
+        count += matches.len();
+
+        // the match of each shown character or adjustment, a character (e.g. a
+        // ligature) belongs to a match if it overlaps it
+        let mut characters = ranges.into_iter();
+        let mut current_match = None;
+        let matched = shown.iter().map(|s| {
+            if let ShownText::Character { .. } = *s {
+                let range = characters.next().unwrap_or(0..0);
+                current_match = matches.iter().position(|m| range.start < m.end && m.start < range.end);
+            }
+            current_match
+        }).collect::<Vec<Option<usize>>>();
+
+        let mut widths = vec![0.0; matches.len()];
+        for (s, m) in shown.iter().zip(&matched) {
+            if let (Some(m), ShownText::Character { displacement, .. }) = (*m, s) {
+                widths[m] += displacement;
+            }
+        }
+
+        replaced.extend(line_operations(&operation));
+        let mut array = TextArray::default();
+        let mut previous_match = None;
+        for (s, m) in shown.into_iter().zip(matched) {
+            match (s, m) {
+                (ShownText::Character { .. }, Some(m)) if previous_match != Some(m) => {
+                    if shrink_to_fit && replacement_width > widths[m] && widths[m] > 0.0 && scale != 0.0 {
+                        if !array.is_empty() {
+                            replaced.push(::std::mem::take(&mut array).into_operation());
+                        }
+                        let compressed = horizontal_scaling * widths[m] / replacement_width;
+                        let mut compressed_array = TextArray::default();
+                        compressed_array.push_code(&replacement);
+                        replaced.push(Operation::new("Tz", vec![Object::Real(compressed * 100.0)]));
+                        replaced.push(compressed_array.into_operation());
+                        replaced.push(Operation::new("Tz", vec![Object::Real(horizontal_scaling * 100.0)]));
+                    } else {
+                        array.push_code(&replacement);
+                    }
+                },
+                (ShownText::Character { code, .. }, None) => array.push_code(&code),
+                (ShownText::Adjustment(adjustment), None) => array.push_adjustment(adjustment),
+                // the rest of the replaced text and the adjustments inside of it are dropped
+                _ => { },
+            }
+            previous_match = m;
+        }
+        if !array.is_empty() {
+            replaced.push(array.into_operation());
+        }
+    }
+
+    (replaced, count)
+}
+
+#[test]
+fn test_extract_text() {
+    let fonts = FontList::new();
+    let text = |x: i64, y: i64, string: &[u8]| vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name("F1".into()), 10.into()]),
+        Operation::new("Td", vec![x.into(), y.into()]),
+        Operation::new("Tj", vec![Object::String(string.to_vec(), lopdf::StringFormat::Literal)]),
+        Operation::new("ET", vec![]),
+    ];
+
+    // written from the bottom to the top and from the right to the left
+    let mut operations = text(100, 100, b"second line");
+    operations.extend(text(200, 120, b"right"));
+    operations.extend(text(100, 121, b"left"));
+    let layer2 = text(100, 80, b"\x80100");
+
+    let layers: Vec<&[Operation]> = vec![&operations, &layer2];
+    assert_eq!(extract_text(&layers, &fonts), "left right\nsecond line\n€100");
+}
+
+#[test]
+fn test_text_runs() {
+    use Pt;
+
+    let fonts = FontList::new();
+    let operations = vec![
+        Operation::new("q", vec![]),
+        Operation::new("cm", vec![2.into(), 0.into(), 0.into(), 2.into(), 0.into(), 0.into()]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name("F1".into()), 10.into()]),
+        Operation::new("Tm", vec![1.into(), 0.into(), 0.into(), 1.into(), 50.into(), 100.into()]),
+        Operation::new("Tj", vec![Object::string_literal("Title")]),
+        Operation::new("Tj", vec![Object::string_literal("")]),
+        Operation::new("ET", vec![]),
+        Operation::new("Q", vec![]),
+    ];
+
+    let layers: Vec<&[Operation]> = vec![&operations];
+    let runs = text_runs(&layers, &fonts);
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].text, "Title");
+    assert_eq!(runs[0].font_size, 20.0);
+    // 5 characters of 0.5 em, from the descent (-0.2 em) to the ascent (0.8 em), scaled by 2
+    assert_eq!(runs[0].bounds, Rect { llx: Pt(100.0), lly: Pt(196.0), urx: Pt(150.0), ury: Pt(216.0) });
+}
+
+#[test]
+fn test_replace_text() {
+    let fonts = FontList::new();
+    let operations = vec![
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec![Object::Name("F1".into()), 10.into()]),
+        Operation::new("TJ", vec![Object::Array(vec![
+            Object::string_literal("Dear {{na"),
+            Object::Integer(-20),
+            Object::string_literal("me}},"),
+            Object::Integer(100),
+            Object::string_literal("!"),
+        ])]),
+        Operation::new("Tj", vec![Object::string_literal("nothing to replace")]),
+        Operation::new("ET", vec![]),
+    ];
+
+    let (replaced, count) = replace_text(operations.clone(), "{{name}}", "Jane", false, &fonts);
+    assert_eq!(count, 1);
+    assert_eq!(replaced.len(), 5);
+    assert_eq!(format!("{:?}", replaced[2].operands), format!("{:?}", vec![Object::Array(vec![
+        Object::String(b"Dear Jane,".to_vec(), lopdf::StringFormat::Hexadecimal),
+        Object::Real(100.0),
+        Object::String(b"!".to_vec(), lopdf::StringFormat::Hexadecimal),
+    ])]));
+    assert_eq!(format!("{:?}", replaced[3].operands), format!("{:?}", vec![Object::string_literal("nothing to replace")]));
+
+    // the replacement is twice as wide as the token, it is compressed to 50%
+    let (replaced, count) = replace_text(operations, "{{name}}", "Jane Doe & Smith", true, &fonts);
+    assert_eq!(count, 1);
+    let operators = replaced.iter().map(|o| o.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["BT", "Tf", "TJ", "Tz", "TJ", "Tz", "TJ", "Tj", "ET"]);
+    assert_eq!(format!("{:?}", replaced[3].operands), format!("{:?}", vec![Object::Real(50.0)]));
+}
@@ -0,0 +1,121 @@
+//! Line breaking for the text layout functions (columns, paragraphs). The text is
+//! measured with a function, so that the layout doesn't depend on the font handling.
+
+use std::ops::Range;
+use Pt;
+
+/// Byte ranges of the words (runs of non-whitespace characters) in `text[range]`
+fn words(text: &str, range: Range<usize>)
+-> Vec<Range<usize>>
+{
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (idx, ch) in text[range.clone()].char_indices() {
+        let idx = range.start + idx;
+        match (ch.is_whitespace(), start) {
+            (true, Some(word_start)) => { words.push(word_start..idx); start = None; },
+            (false, None) => start = Some(idx),
+            _ => { },
+        }
+    }
+
+    if let Some(word_start) = start {
+        words.push(word_start..range.end);
+    }
+
+    words
+}
+
+/// Breaks the text into lines (as byte ranges into `text`), greedily putting as many words on
+/// a line as fit into `line_width(line_index)`. Words are never split: a word that is wider than
+/// the line gets a line of its own. Every `\n` starts a new paragraph, empty paragraphs become
+/// empty lines. The whitespace between the words of a line is kept, the whitespace at line
+/// breaks is removed.
+pub(crate) fn wrap_lines<W, M>(text: &str, line_width: W, measure: M)
+-> Vec<Range<usize>> where W: Fn(usize) -> Pt, M: Fn(&str) -> Pt
+{
+    wrap_ranges(text, line_width, |range| measure(&text[range]))
+}
+
+/// Same as `wrap_lines`, but the text is measured by its byte range, for text with
+/// several fonts (styled runs)
+pub(crate) fn wrap_ranges<W, M>(text: &str, line_width: W, measure: M)
+-> Vec<Range<usize>> where W: Fn(usize) -> Pt, M: Fn(Range<usize>) -> Pt
+{
+    let mut lines = Vec::new();
+    let mut paragraph_start = 0;
+
+    for paragraph in text.split('\n') {
+        let paragraph_range = paragraph_start..paragraph_start + paragraph.len();
+        paragraph_start = paragraph_range.end + 1;
+
+        let mut line: Option<Range<usize>> = None;
+        for word in words(text, paragraph_range.clone()) {
+            line = Some(match line {
+                None => word,
+                Some(current) => {
+                    if measure(current.start..word.end) <= line_width(lines.len()) {
+                        current.start..word.end
+                    } else {
+                        lines.push(current);
+                        word
+                    }
+                },
+            });
+        }
+
+        lines.push(line.unwrap_or(paragraph_range.start..paragraph_range.start));
+    }
+
+    lines
+}
+
+/// Number of lines that fit into a box of the height, if the first baseline is one font size below the top
+pub(crate) fn line_capacity(height: Pt, font_size: f64, leading: Pt)
+-> usize
+{
+    if height.0 < font_size { 0 } else { ((height.0 - font_size) / leading.0) as usize + 1 }
+}
+
+/// Number of lines in each column, when `line_count` lines are distributed over `columns`
+/// columns with room for `capacity` lines each. If all lines fit, the columns are balanced
+/// (the lines are spread evenly, the last columns may be shorter), otherwise every column is full.
+pub(crate) fn balance_columns(line_count: usize, columns: usize, capacity: usize)
+-> Vec<usize>
+{
+    let per_column = if line_count < columns * capacity { line_count.div_ceil(columns.max(1)) } else { capacity };
+    let mut remaining = line_count;
+
+    (0..columns).map(|_| {
+        let lines = remaining.min(per_column);
+        remaining -= lines;
+        lines
+    }).collect()
+}
+
+#[test]
+fn test_wrap_lines() {
+    // every character is 1 pt wide, the lines are 11 pt
+    let measure = |text: &str| Pt(text.chars().count() as f64);
+    let text = "The quick brown fox jumps over the  lazy dog\n\nextraordinarily long";
+    let lines: Vec<&str> = wrap_lines(text, |_| Pt(11.0), measure).into_iter().map(|r| &text[r]).collect();
+    assert_eq!(lines, vec!["The quick", "brown fox", "jumps over", "the  lazy", "dog", "", "extraordinarily", "long"]);
+
+    // the first two lines are narrower (e.g. next to a drop cap)
+    let lines: Vec<&str> = wrap_lines("aa bb cc dd ee", |line| Pt(if line < 2 { 2.0 } else { 8.0 }), measure)
+        .into_iter().map(|r| &"aa bb cc dd ee"[r]).collect();
+    assert_eq!(lines, vec!["aa", "bb", "cc dd ee"]);
+}
+
+#[test]
+fn test_balance_columns() {
+    assert_eq!(line_capacity(Pt(100.0), 10.0, Pt(12.0)), 8);
+    assert_eq!(line_capacity(Pt(5.0), 10.0, Pt(12.0)), 0);
+
+    assert_eq!(balance_columns(10, 2, 20), vec![5, 5]);
+    assert_eq!(balance_columns(7, 3, 20), vec![3, 3, 1]);
+    assert_eq!(balance_columns(50, 2, 20), vec![20, 20]);
+    assert_eq!(balance_columns(40, 2, 20), vec![20, 20]);
+    assert_eq!(balance_columns(0, 2, 20), vec![0, 0]);
+}
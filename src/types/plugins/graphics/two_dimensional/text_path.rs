@@ -0,0 +1,115 @@
+//! Placement of single glyphs along a path, for text on curves (see
+//! `PdfLayerReference::add_text_on_path()`)
+
+use {Line, Point, Pt};
+
+/// Number of straight segments a bezier curve is approximated with
+const CURVE_SEGMENTS: usize = 32;
+
+/// Approximates the path with straight segments, returns the points of the polyline.
+/// Closed paths end with their first point.
+pub(crate) fn flatten(path: &Line)
+-> Vec<(f64, f64)>
+{
+    let points: Vec<(f64, f64, bool)> = path.points.iter().map(|(p, bezier)| (p.x.0, p.y.0, *bezier)).collect();
+    let mut polyline = Vec::new();
+    let first = match points.first() {
+        Some(first) => first,
+        None => return polyline,
+    };
+    polyline.push((first.0, first.1));
+
+    // same interpretation of the points as `Line::into_stream_op()`
+    let mut current = 1;
+    while current < points.len() {
+        let (p1, p2) = (points[current - 1], points[current]);
+        if p1.2 && p2.2 && current + 2 < points.len() {
+            let (p3, p4) = (points[current + 1], points[current + 2]);
+            for step in 1..=CURVE_SEGMENTS {
+                let t = step as f64 / CURVE_SEGMENTS as f64;
+                let u = 1.0 - t;
+                let bezier = |a: f64, b: f64, c: f64, d: f64| u * u * u * a + 3.0 * u * u * t * b + 3.0 * u * t * t * c + t * t * t * d;
+                polyline.push((bezier(p1.0, p2.0, p3.0, p4.0), bezier(p1.1, p2.1, p3.1, p4.1)));
+            }
+            current += 3;
+        } else {
+            polyline.push((p2.0, p2.1));
+            current += 1;
+        }
+    }
+
+    if path.is_closed {
+        polyline.push((first.0, first.1));
+    }
+
+    polyline
+}
+
+/// Point at the distance along the polyline, `None` beyond its end
+fn point_at(polyline: &[(f64, f64)], distance: f64)
+-> Option<(f64, f64)>
+{
+    let mut remaining = distance;
+    for segment in polyline.windows(2) {
+        let (dx, dy) = (segment[1].0 - segment[0].0, segment[1].1 - segment[0].1);
+        let length = (dx * dx + dy * dy).sqrt();
+        if remaining <= length {
+            let t = if length > 0.0 { remaining / length } else { 0.0 };
+            return Some((segment[0].0 + dx * t, segment[0].1 + dy * t));
+        }
+        remaining -= length;
+    }
+    None
+}
+
+/// Origins and rotations (counterclockwise, in degrees) of glyphs with the advances, when
+/// they are placed one after another along the polyline, starting at the distance `offset`.
+/// Every glyph is rotated along the chord from its start to its end on the path. Glyphs
+/// that don't fit on the path anymore get `None`.
+pub(crate) fn glyph_positions(polyline: &[(f64, f64)], advances: &[Pt], offset: Pt)
+-> Vec<Option<(Point, f64)>>
+{
+    let mut distance = offset.0.max(0.0);
+    advances.iter().map(|advance| {
+        let start = point_at(polyline, distance);
+        let end = point_at(polyline, distance + advance.0);
+        distance += advance.0;
+
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                let angle = if advance.0 > 0.0 { (end.1 - start.1).atan2(end.0 - start.0).to_degrees() } else { 0.0 };
+                Some((Point { x: Pt(start.0), y: Pt(start.1) }, angle))
+            },
+            _ => None,
+        }
+    }).collect()
+}
+
+#[test]
+fn test_glyph_positions() {
+    let point = |x: f64, y: f64, bezier: bool| (Point { x: Pt(x), y: Pt(y) }, bezier);
+
+    // a right angle: the glyphs follow the first leg, then turn up
+    let corner = Line { points: vec![point(0.0, 0.0, false), point(20.0, 0.0, false), point(20.0, 20.0, false)], .. Line::default() };
+    let polyline = flatten(&corner);
+    let positions = glyph_positions(&polyline, &[Pt(10.0), Pt(10.0), Pt(10.0), Pt(10.0), Pt(10.0)], Pt(0.0));
+    let positions: Vec<Option<(f64, f64, f64)>> = positions.into_iter().map(|p| p.map(|(p, angle)| (p.x.0, p.y.0, angle.round()))).collect();
+    assert_eq!(positions, vec![Some((0.0, 0.0, 0.0)), Some((10.0, 0.0, 0.0)), Some((20.0, 0.0, 90.0)), Some((20.0, 10.0, 90.0)), None]);
+
+    // a quarter circle (approximated with a bezier curve) of radius 100
+    let k = 55.228;
+    let arc = Line { points: vec![point(100.0, 0.0, true), point(100.0, k, true), point(k, 100.0, false), point(0.0, 100.0, false)], .. Line::default() };
+    let polyline = flatten(&arc);
+    assert_eq!(polyline.len(), CURVE_SEGMENTS + 1);
+
+    // the middle of the arc is at 45 degrees, a glyph there is rotated by 135 degrees
+    let length = 100.0 * ::std::f64::consts::FRAC_PI_2;
+    let (origin, angle) = glyph_positions(&polyline, &[Pt(2.0)], Pt(length / 2.0 - 1.0))[0].unwrap();
+    assert!((origin.x.0 - 70.7).abs() < 1.0 && (origin.y.0 - 70.7).abs() < 1.0);
+    assert!((angle - 135.0).abs() < 1.0);
+
+    // closed paths continue to their first point
+    let mut triangle = corner.clone();
+    triangle.set_closed(true);
+    assert_eq!(flatten(&triangle).last(), Some(&(0.0, 0.0)));
+}
@@ -0,0 +1,849 @@
+//! Instances of variable TrueType fonts. The axis values are normalized (with `avar`), the
+//! outline and advance width deltas of `gvar` are applied and a static font is written, which
+//! is embedded like any other font.
+//!
+//! Only fonts with TrueType outlines are supported (not `CFF2`). `HVAR`, `MVAR` and `cvar`
+//! are not applied: advance widths come from the phantom points in `gvar`, the vertical
+//! metrics of the font and the hinting stay those of the default instance.
+
+use {Error, PdfError};
+use types::plugins::graphics::two_dimensional::sfnt::{find_table, read_i16, read_u16, read_u32, tables, write_font};
+
+/// Tag of a variation axis, e.g. `Tag(*b"wght")` for the weight or `Tag(*b"wdth")` for the width.
+/// Also used for OpenType features, see `FeatureTag`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tag(pub [u8; 4]);
+
+/// Variation axis of a font. The values are in the units of the axis, e.g. 100 - 900 for the weight.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FontAxis {
+    pub tag: Tag,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
+/// Named instance of a variable font, such as "Bold" or "Condensed Light"
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedInstance {
+    /// Subfamily name of the instance
+    pub name: String,
+    /// Axis values of the instance, for `PdfDocumentReference::add_font_instance()`
+    pub axes: Vec<(Tag, f32)>,
+}
+
+/// Tables that describe the variations, they are not copied into the static font
+const VARIATION_TABLES: [&[u8; 4]; 8] = [b"fvar", b"gvar", b"avar", b"cvar", b"HVAR", b"VVAR", b"MVAR", b"STAT"];
+
+/// Number of phantom points (origin, advance, top and bottom) after the points of every glyph
+const PHANTOM_POINTS: usize = 4;
+
+#[inline]
+fn read_fixed(data: &[u8], offset: usize)
+-> Option<f32>
+{
+    read_u32(data, offset).map(|value| value as i32 as f32 / 65536.0)
+}
+
+#[inline]
+fn read_f2dot14(data: &[u8], offset: usize)
+-> Option<f64>
+{
+    read_i16(data, offset).map(|value| f64::from(value) / 16384.0)
+}
+
+fn read_tag(data: &[u8], offset: usize)
+-> Option<Tag>
+{
+    let mut tag = [0; 4];
+    tag.copy_from_slice(data.get(offset..offset + 4)?);
+    Some(Tag(tag))
+}
+
+/// Named instances in the `fvar` table: subfamily name ID and coordinates
+type Instances = Vec<(u16, Vec<f32>)>;
+
+/// Reads the axes and the named instances of the `fvar` table
+fn parse_fvar(font: &[u8])
+-> Option<(Vec<FontAxis>, Instances)>
+{
+    let fvar = find_table(font, b"fvar")?;
+    let axes_offset = read_u16(fvar, 4)? as usize;
+    let axis_count = read_u16(fvar, 8)? as usize;
+    let axis_size = read_u16(fvar, 10)? as usize;
+    let instance_count = read_u16(fvar, 12)? as usize;
+    let instance_size = read_u16(fvar, 14)? as usize;
+
+    let axes = (0..axis_count).map(|idx| {
+        let record = axes_offset + idx * axis_size;
+        Some(FontAxis {
+            tag: read_tag(fvar, record)?,
+            min: read_fixed(fvar, record + 4)?,
+            default: read_fixed(fvar, record + 8)?,
+            max: read_fixed(fvar, record + 12)?,
+        })
+    }).collect::<Option<Vec<FontAxis>>>()?;
+
+    let instances_offset = axes_offset + axis_count * axis_size;
+    let instances = (0..instance_count).map(|idx| {
+        let record = instances_offset + idx * instance_size;
+        let coordinates = (0..axis_count).map(|axis| read_fixed(fvar, record + 4 + axis * 4)).collect::<Option<Vec<f32>>>()?;
+        Some((read_u16(fvar, record)?, coordinates))
+    }).collect::<Option<Instances>>()?;
+
+    Some((axes, instances))
+}
+
+/// Reads a name from the `name` table, preferring the Unicode names
+fn font_name(font: &[u8], name_id: u16)
+-> Option<String>
+{
+    let name = find_table(font, b"name")?;
+    let count = read_u16(name, 2)? as usize;
+    let storage = read_u16(name, 4)? as usize;
+    let mut fallback = None;
+
+    for idx in 0..count {
+        let record = 6 + idx * 12;
+        if read_u16(name, record + 6)? != name_id {
+            continue;
+        }
+        let (length, offset) = (read_u16(name, record + 8)? as usize, read_u16(name, record + 10)? as usize);
+        let bytes = name.get(storage + offset..storage + offset + length)?;
+        match (read_u16(name, record)?, read_u16(name, record + 2)?) {
+            (0, _) | (3, 1) | (3, 10) => {
+                let units: Vec<u16> = bytes.chunks(2).filter(|pair| pair.len() == 2).map(|pair| u16::from(pair[0]) << 8 | u16::from(pair[1])).collect();
+                return Some(String::from_utf16_lossy(&units));
+            },
+            (1, 0) => fallback = Some(bytes.iter().map(|byte| *byte as char).collect()),
+            _ => { },
+        }
+    }
+
+    fallback
+}
+
+/// Returns the variation axes of a font, empty if the font is not a variable font
+pub fn font_axes(font: &[u8])
+-> Vec<FontAxis>
+{
+    parse_fvar(font).map(|(axes, _)| axes).unwrap_or_default()
+}
+
+/// Returns the named instances of a variable font, empty if the font is not a variable font
+pub fn named_instances(font: &[u8])
+-> Vec<NamedInstance>
+{
+    let (axes, instances) = match parse_fvar(font) {
+        Some(fvar) => fvar,
+        None => return Vec::new(),
+    };
+
+    instances.into_iter().enumerate().map(|(idx, (name_id, coordinates))| NamedInstance {
+        name: font_name(font, name_id).unwrap_or_else(|| format!("Instance {}", idx + 1)),
+        axes: axes.iter().map(|axis| axis.tag).zip(coordinates).collect(),
+    }).collect()
+}
+
+/// Maps the normalized coordinate with the segments of an `avar` axis
+fn map_segment(map: &[(f64, f64)], value: f64)
+-> f64
+{
+    for pair in map.windows(2) {
+        let ((from1, to1), (from2, to2)) = (pair[0], pair[1]);
+        if value <= from2 {
+            return if value <= from1 || from2 <= from1 { to1 } else { to1 + (value - from1) / (from2 - from1) * (to2 - to1) };
+        }
+    }
+    map.last().map(|(_, to)| *to).unwrap_or(value)
+}
+
+/// Normalizes the axis values to -1.0 ..= 1.0, axes that aren't given have their default value
+fn normalized_coordinates(font: &[u8], axes: &[FontAxis], values: &[(Tag, f32)])
+-> Option<Vec<f64>>
+{
+    if values.iter().any(|(tag, _)| !axes.iter().any(|axis| axis.tag == *tag)) {
+        return None;
+    }
+
+    let mut coordinates: Vec<f64> = axes.iter().map(|axis| {
+        let (min, default, max) = (f64::from(axis.min), f64::from(axis.default), f64::from(axis.max));
+        let value = values.iter().rev().find(|(tag, _)| *tag == axis.tag).map(|(_, value)| f64::from(*value)).unwrap_or(default);
+        let value = if min <= max { value.clamp(min, max) } else { default };
+        if value < default {
+            (value - default) / (default - min)
+        } else if value > default {
+            (value - default) / (max - default)
+        } else {
+            0.0
+        }
+    }).collect();
+
+    if let Some(avar) = find_table(font, b"avar") {
+        let mut offset = 8;
+        for coordinate in coordinates.iter_mut() {
+            let count = read_u16(avar, offset)? as usize;
+            let map = (0..count).map(|idx| Some((read_f2dot14(avar, offset + 2 + idx * 4)?, read_f2dot14(avar, offset + 4 + idx * 4)?)))
+                .collect::<Option<Vec<(f64, f64)>>>()?;
+            offset += 2 + count * 4;
+            *coordinate = map_segment(&map, *coordinate);
+        }
+    }
+
+    Some(coordinates)
+}
+
+/// Scale of the deltas of a tuple variation at the normalized coordinates
+fn tuple_scalar(peak: &[f64], intermediate: Option<&(Vec<f64>, Vec<f64>)>, coordinates: &[f64])
+-> f64
+{
+    let mut scalar = 1.0;
+    for (axis, (&peak, &coordinate)) in peak.iter().zip(coordinates).enumerate() {
+        if peak == 0.0 || coordinate == peak {
+            continue;
+        }
+        if coordinate == 0.0 {
+            return 0.0;
+        }
+        match intermediate {
+            Some((start, end)) => {
+                let (start, end) = (start[axis], end[axis]);
+                if coordinate < start || coordinate > end {
+                    return 0.0;
+                }
+                scalar *= if coordinate < peak { (coordinate - start) / (peak - start) } else { (end - coordinate) / (end - peak) };
+            },
+            None => {
+                if coordinate < peak.min(0.0) || coordinate > peak.max(0.0) {
+                    return 0.0;
+                }
+                scalar *= coordinate / peak;
+            },
+        }
+    }
+    scalar
+}
+
+/// Reads packed point numbers, `None` for "all points". Returns the points and the number of bytes read.
+fn read_points(data: &[u8], offset: usize)
+-> Option<(Option<Vec<usize>>, usize)>
+{
+    let first = *data.get(offset)?;
+    let (count, mut position) = if first & 0x80 != 0 {
+        (((first & 0x7F) as usize) << 8 | *data.get(offset + 1)? as usize, offset + 2)
+    } else {
+        (first as usize, offset + 1)
+    };
+
+    if count == 0 {
+        return Some((None, position - offset));
+    }
+
+    let mut points = Vec::with_capacity(count);
+    let mut point = 0;
+    while points.len() < count {
+        let control = *data.get(position)?;
+        position += 1;
+        for _ in 0..=(control & 0x7F) {
+            if control & 0x80 != 0 {
+                point += read_u16(data, position)? as usize;
+                position += 2;
+            } else {
+                point += *data.get(position)? as usize;
+                position += 1;
+            }
+            points.push(point);
+        }
+    }
+    points.truncate(count);
+
+    Some((Some(points), position - offset))
+}
+
+/// Reads `count` packed deltas, returns the deltas and the number of bytes read
+fn read_deltas(data: &[u8], offset: usize, count: usize)
+-> Option<(Vec<f64>, usize)>
+{
+    let mut deltas = Vec::with_capacity(count);
+    let mut position = offset;
+    while deltas.len() < count {
+        let control = *data.get(position)?;
+        position += 1;
+        for _ in 0..=(control & 0x3F) {
+            let delta = if control & 0x80 != 0 {
+                0.0
+            } else if control & 0x40 != 0 {
+                position += 2;
+                f64::from(read_i16(data, position - 2)?)
+            } else {
+                position += 1;
+                f64::from(*data.get(position - 1)? as i8)
+            };
+            deltas.push(delta);
+        }
+    }
+    deltas.truncate(count);
+
+    Some((deltas, position - offset))
+}
+
+/// Interpolates the delta of a point from the deltas of the two reference points
+fn interpolate(point: f64, (ref1, delta1): (f64, f64), (ref2, delta2): (f64, f64))
+-> f64
+{
+    let ((ref1, delta1), (ref2, delta2)) = if ref1 <= ref2 { ((ref1, delta1), (ref2, delta2)) } else { ((ref2, delta2), (ref1, delta1)) };
+    if ref1 == ref2 {
+        if delta1 == delta2 { delta1 } else { 0.0 }
+    } else if point <= ref1 {
+        delta1
+    } else if point >= ref2 {
+        delta2
+    } else {
+        delta1 + (point - ref1) / (ref2 - ref1) * (delta2 - delta1)
+    }
+}
+
+/// Infers the deltas of the points of the contours that have no explicit delta
+/// ("interpolation of untouched points")
+fn interpolate_untouched(deltas: &mut [Option<(f64, f64)>], points: &[(f64, f64)], contour_ends: &[usize])
+{
+    let mut start = 0;
+    for &end in contour_ends {
+        if end < start || end >= deltas.len() {
+            break;
+        }
+        let touched: Vec<usize> = (start..=end).filter(|idx| deltas[*idx].is_some()).collect();
+        if let (Some(&first), Some(&last)) = (touched.first(), touched.last()) {
+            for idx in start..=end {
+                if deltas[idx].is_some() {
+                    continue;
+                }
+                // the closest touched points before and after the point, the contour is closed
+                let previous = touched.iter().rev().find(|touched| **touched < idx).cloned().unwrap_or(last);
+                let next = touched.iter().find(|touched| **touched > idx).cloned().unwrap_or(first);
+                if let (Some(delta1), Some(delta2)) = (deltas[previous], deltas[next]) {
+                    deltas[idx] = Some((
+                        interpolate(points[idx].0, (points[previous].0, delta1.0), (points[next].0, delta2.0)),
+                        interpolate(points[idx].1, (points[previous].1, delta1.1), (points[next].1, delta2.1)),
+                    ));
+                }
+            }
+        }
+        start = end + 1;
+    }
+}
+
+/// The `gvar` table
+struct GlyphVariations<'a> {
+    data: &'a [u8],
+    axis_count: usize,
+    shared_tuples: Vec<Vec<f64>>,
+    data_offset: usize,
+    long_offsets: bool,
+}
+
+impl<'a> GlyphVariations<'a> {
+
+    fn parse(gvar: &'a [u8])
+    -> Option<Self>
+    {
+        let axis_count = read_u16(gvar, 4)? as usize;
+        let shared_tuple_count = read_u16(gvar, 6)? as usize;
+        let shared_tuples_offset = read_u32(gvar, 8)? as usize;
+        let shared_tuples = (0..shared_tuple_count).map(|idx| {
+            (0..axis_count).map(|axis| read_f2dot14(gvar, shared_tuples_offset + (idx * axis_count + axis) * 2)).collect()
+        }).collect::<Option<Vec<Vec<f64>>>>()?;
+
+        Some(Self {
+            data: gvar,
+            axis_count,
+            shared_tuples,
+            data_offset: read_u32(gvar, 16)? as usize,
+            long_offsets: read_u16(gvar, 14)? & 1 != 0,
+        })
+    }
+
+    /// Variation data of a glyph, empty if the glyph has no variations
+    fn glyph_data(&self, glyph: usize)
+    -> Option<&'a [u8]>
+    {
+        let offset = |idx: usize| if self.long_offsets {
+            read_u32(self.data, 20 + idx * 4).map(|offset| offset as usize)
+        } else {
+            read_u16(self.data, 20 + idx * 2).map(|offset| offset as usize * 2)
+        };
+        let (start, end) = (self.data_offset + offset(glyph)?, self.data_offset + offset(glyph + 1)?);
+        if end <= start { Some(&[]) } else { self.data.get(start..end) }
+    }
+
+    fn read_tuple(&self, data: &[u8], offset: usize)
+    -> Option<Vec<f64>>
+    {
+        (0..self.axis_count).map(|axis| read_f2dot14(data, offset + axis * 2)).collect()
+    }
+
+    /// Deltas of all points of a glyph (including the phantom points) at the normalized coordinates
+    fn glyph_deltas(&self, glyph: usize, coordinates: &[f64], points: &[(f64, f64)], contour_ends: &[usize])
+    -> Option<Vec<(f64, f64)>>
+    {
+        let mut total = vec![(0.0, 0.0); points.len()];
+        let data = self.glyph_data(glyph)?;
+        if data.is_empty() {
+            return Some(total);
+        }
+
+        let header = read_u16(data, 0)?;
+        let mut serialized = read_u16(data, 2)? as usize;
+        let shared_points = if header & 0x8000 != 0 {
+            let (points, length) = read_points(data, serialized)?;
+            serialized += length;
+            Some(points)
+        } else {
+            None
+        };
+
+        let mut header_offset = 4;
+        for _ in 0..(header & 0x0FFF) {
+            let size = read_u16(data, header_offset)? as usize;
+            let index = read_u16(data, header_offset + 2)?;
+            header_offset += 4;
+
+            let peak = if index & 0x8000 != 0 {
+                header_offset += self.axis_count * 2;
+                self.read_tuple(data, header_offset - self.axis_count * 2)?
+            } else {
+                self.shared_tuples.get((index & 0x0FFF) as usize)?.clone()
+            };
+            let intermediate = if index & 0x4000 != 0 {
+                header_offset += self.axis_count * 4;
+                Some((self.read_tuple(data, header_offset - self.axis_count * 4)?, self.read_tuple(data, header_offset - self.axis_count * 2)?))
+            } else {
+                None
+            };
+
+            let tuple = data.get(serialized..serialized + size)?;
+            serialized += size;
+            let scalar = tuple_scalar(&peak, intermediate.as_ref(), coordinates);
+            if scalar == 0.0 {
+                continue;
+            }
+
+            let (tuple_points, mut position) = if index & 0x2000 != 0 {
+                read_points(tuple, 0)?
+            } else {
+                (shared_points.clone().unwrap_or(None), 0)
+            };
+            let count = tuple_points.as_ref().map(|points| points.len()).unwrap_or(points.len());
+            let (x_deltas, length) = read_deltas(tuple, position, count)?;
+            position += length;
+            let (y_deltas, _) = read_deltas(tuple, position, count)?;
+
+            let mut deltas = vec![None; points.len()];
+            match tuple_points {
+                None => for (idx, delta) in deltas.iter_mut().enumerate().take(count) {
+                    *delta = Some((x_deltas[idx], y_deltas[idx]));
+                },
+                Some(tuple_points) => {
+                    for (idx, point) in tuple_points.into_iter().enumerate() {
+                        if let Some(delta) = deltas.get_mut(point) {
+                            *delta = Some((x_deltas[idx], y_deltas[idx]));
+                        }
+                    }
+                    interpolate_untouched(&mut deltas, points, contour_ends);
+                },
+            }
+
+            for (total, delta) in total.iter_mut().zip(deltas) {
+                if let Some((x, y)) = delta {
+                    total.0 += x * scalar;
+                    total.1 += y * scalar;
+                }
+            }
+        }
+
+        Some(total)
+    }
+}
+
+/// Component of a composite glyph
+#[derive(Debug, Clone, PartialEq)]
+struct Component {
+    flags: u16,
+    glyph: u16,
+    /// Offset of the component, or the numbers of the points that are matched
+    arguments: (i32, i32),
+    /// Scale or 2x2 transformation, unchanged
+    transform: Vec<u8>,
+}
+
+/// Outline of a glyph in the `glyf` table
+#[derive(Debug, Clone, PartialEq)]
+enum Outline {
+    Empty,
+    Simple { contour_ends: Vec<usize>, points: Vec<(i32, i32, u8)> },
+    Composite { bbox: [i16; 4], components: Vec<Component> },
+}
+
+const ON_CURVE_POINT: u8 = 0x01;
+const X_SHORT_VECTOR: u8 = 0x02;
+const Y_SHORT_VECTOR: u8 = 0x04;
+const REPEAT_FLAG: u8 = 0x08;
+const X_IS_SAME_OR_POSITIVE: u8 = 0x10;
+const Y_IS_SAME_OR_POSITIVE: u8 = 0x20;
+const OVERLAP_SIMPLE: u8 = 0x40;
+
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+fn read_coordinates(data: &[u8], position: &mut usize, flags: &[u8], short: u8, same_or_positive: u8)
+-> Option<Vec<i32>>
+{
+    let mut value = 0;
+    flags.iter().map(|flag| {
+        if flag & short != 0 {
+            let delta = i32::from(*data.get(*position)?);
+            *position += 1;
+            value += if flag & same_or_positive != 0 { delta } else { -delta };
+        } else if flag & same_or_positive == 0 {
+            value += i32::from(read_i16(data, *position)?);
+            *position += 2;
+        }
+        Some(value)
+    }).collect()
+}
+
+fn parse_glyph(data: &[u8])
+-> Option<Outline>
+{
+    if data.is_empty() {
+        return Some(Outline::Empty);
+    }
+
+    let contours = read_i16(data, 0)?;
+    if contours < 0 {
+        let bbox = [read_i16(data, 2)?, read_i16(data, 4)?, read_i16(data, 6)?, read_i16(data, 8)?];
+        let mut position = 10;
+        let mut components = Vec::new();
+        loop {
+            let flags = read_u16(data, position)?;
+            let glyph = read_u16(data, position + 2)?;
+            position += 4;
+            let arguments = match (flags & ARG_1_AND_2_ARE_WORDS != 0, flags & ARGS_ARE_XY_VALUES != 0) {
+                (true, true) => (i32::from(read_i16(data, position)?), i32::from(read_i16(data, position + 2)?)),
+                (true, false) => (i32::from(read_u16(data, position)?), i32::from(read_u16(data, position + 2)?)),
+                (false, true) => (i32::from(*data.get(position)? as i8), i32::from(*data.get(position + 1)? as i8)),
+                (false, false) => (i32::from(*data.get(position)?), i32::from(*data.get(position + 1)?)),
+            };
+            position += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+            let transform_length = if flags & WE_HAVE_A_SCALE != 0 {
+                2
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                4
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                8
+            } else {
+                0
+            };
+            let transform = data.get(position..position + transform_length)?.to_vec();
+            position += transform_length;
+            components.push(Component { flags, glyph, arguments, transform });
+            if flags & MORE_COMPONENTS == 0 {
+                return Some(Outline::Composite { bbox, components });
+            }
+        }
+    }
+
+    let contour_ends = (0..contours as usize).map(|idx| read_u16(data, 10 + idx * 2).map(|end| end as usize)).collect::<Option<Vec<usize>>>()?;
+    let num_points = contour_ends.last().map(|end| end + 1).unwrap_or(0);
+    let instructions = read_u16(data, 10 + contour_ends.len() * 2)? as usize;
+    let mut position = 12 + contour_ends.len() * 2 + instructions;
+
+    let mut flags = Vec::with_capacity(num_points);
+    while flags.len() < num_points {
+        let flag = *data.get(position)?;
+        position += 1;
+        flags.push(flag);
+        if flag & REPEAT_FLAG != 0 {
+            let repeat = *data.get(position)?;
+            position += 1;
+            for _ in 0..repeat {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(num_points);
+
+    let xs = read_coordinates(data, &mut position, &flags, X_SHORT_VECTOR, X_IS_SAME_OR_POSITIVE)?;
+    let ys = read_coordinates(data, &mut position, &flags, Y_SHORT_VECTOR, Y_IS_SAME_OR_POSITIVE)?;
+    let points = xs.into_iter().zip(ys).zip(flags).map(|((x, y), flag)| (x, y, flag & (ON_CURVE_POINT | OVERLAP_SIMPLE))).collect();
+
+    Some(Outline::Simple { contour_ends, points })
+}
+
+/// Writes the glyph without hinting instructions, all coordinates are stored as words
+fn write_glyph(outline: &Outline)
+-> Vec<u8>
+{
+    let mut data = Vec::new();
+    let push = |data: &mut Vec<u8>, value: i32| data.extend_from_slice(&(value as i16).to_be_bytes());
+
+    match *outline {
+        Outline::Empty => { },
+        Outline::Simple { ref contour_ends, ref points } => {
+            let x_min = points.iter().map(|p| p.0).min().unwrap_or(0);
+            let y_min = points.iter().map(|p| p.1).min().unwrap_or(0);
+            let x_max = points.iter().map(|p| p.0).max().unwrap_or(0);
+            let y_max = points.iter().map(|p| p.1).max().unwrap_or(0);
+            for value in &[contour_ends.len() as i32, x_min, y_min, x_max, y_max] {
+                push(&mut data, *value);
+            }
+            for end in contour_ends {
+                data.extend_from_slice(&(*end as u16).to_be_bytes());
+            }
+            data.extend_from_slice(&[0, 0]);
+            data.extend(points.iter().map(|p| p.2));
+            for coordinate in 0..2 {
+                let mut previous = 0;
+                for point in points {
+                    let value = if coordinate == 0 { point.0 } else { point.1 };
+                    push(&mut data, value - previous);
+                    previous = value;
+                }
+            }
+        },
+        Outline::Composite { bbox, ref components } => {
+            push(&mut data, -1);
+            for value in &bbox {
+                push(&mut data, i32::from(*value));
+            }
+            for component in components {
+                let flags = (component.flags | ARG_1_AND_2_ARE_WORDS) & !WE_HAVE_INSTRUCTIONS;
+                data.extend_from_slice(&flags.to_be_bytes());
+                data.extend_from_slice(&component.glyph.to_be_bytes());
+                push(&mut data, component.arguments.0);
+                push(&mut data, component.arguments.1);
+                data.extend_from_slice(&component.transform);
+            }
+        },
+    }
+
+    data
+}
+
+/// Applies the axis values to a variable font and returns the static font
+pub(crate) fn instantiate(font: &[u8], values: &[(Tag, f32)])
+-> Result<Vec<u8>, Error>
+{
+    let (axes, _) = parse_fvar(font).ok_or(Error::Pdf(PdfError::FontVariationError))?;
+    let coordinates = normalized_coordinates(font, &axes, values).ok_or(Error::Pdf(PdfError::FontVariationError))?;
+    instantiate_at(font, &coordinates).ok_or(Error::Pdf(PdfError::FontVariationError))
+}
+
+fn instantiate_at(font: &[u8], coordinates: &[f64])
+-> Option<Vec<u8>>
+{
+    let head = find_table(font, b"head")?;
+    let hhea = find_table(font, b"hhea")?;
+    let hmtx = find_table(font, b"hmtx")?;
+    let loca = find_table(font, b"loca")?;
+    let glyf = find_table(font, b"glyf")?;
+    let num_glyphs = read_u16(find_table(font, b"maxp")?, 4)? as usize;
+    let number_of_h_metrics = read_u16(hhea, 34)? as usize;
+    let long_loca = read_i16(head, 50)? != 0;
+    let gvar = match find_table(font, b"gvar") {
+        Some(gvar) => Some(GlyphVariations::parse(gvar)?),
+        None => None,
+    };
+
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::with_capacity((num_glyphs + 1) * 4);
+    let mut new_hmtx = Vec::with_capacity(num_glyphs * 4);
+    let mut max_advance = 0;
+
+    for glyph in 0..num_glyphs {
+        let location = |idx: usize| if long_loca { read_u32(loca, idx * 4).map(|l| l as usize) } else { read_u16(loca, idx * 2).map(|l| l as usize * 2) };
+        let (start, end) = (location(glyph)?, location(glyph + 1)?);
+        let mut outline = parse_glyph(if end > start { glyf.get(start..end)? } else { &[] })?;
+
+        let metric = glyph.min(number_of_h_metrics.checked_sub(1)?);
+        let advance = read_u16(hmtx, metric * 4)?;
+        let lsb = if glyph < number_of_h_metrics {
+            read_i16(hmtx, glyph * 4 + 2)?
+        } else {
+            read_i16(hmtx, number_of_h_metrics * 4 + (glyph - number_of_h_metrics) * 2)?
+        };
+
+        // points of the glyph that have deltas, followed by the phantom points
+        let (mut points, contour_ends): (Vec<(f64, f64)>, Vec<usize>) = match outline {
+            Outline::Empty => (Vec::new(), Vec::new()),
+            Outline::Simple { ref contour_ends, ref points } => (points.iter().map(|p| (f64::from(p.0), f64::from(p.1))).collect(), contour_ends.clone()),
+            Outline::Composite { ref components, .. } => (components.iter().map(|c| (f64::from(c.arguments.0), f64::from(c.arguments.1))).collect(), Vec::new()),
+        };
+        let x_min = match outline {
+            Outline::Simple { ref points, .. } => points.iter().map(|p| p.0).min().unwrap_or(0),
+            Outline::Composite { bbox, .. } => i32::from(bbox[0]),
+            Outline::Empty => 0,
+        };
+        let origin = f64::from(x_min - i32::from(lsb));
+        points.extend_from_slice(&[(origin, 0.0), (origin + f64::from(advance), 0.0), (0.0, 0.0), (0.0, 0.0)]);
+
+        let deltas = match gvar {
+            Some(ref gvar) => gvar.glyph_deltas(glyph, coordinates, &points, &contour_ends)?,
+            None => vec![(0.0, 0.0); points.len()],
+        };
+        let outline_points = points.len() - PHANTOM_POINTS;
+        let (origin_delta, advance_delta) = (deltas[outline_points].0, deltas[outline_points + 1].0);
+
+        // the outline is moved so that the origin stays at x = 0
+        let shift = origin_delta.round() as i32;
+        let moved = |point: (f64, f64), delta: &(f64, f64)| ((point.0 + delta.0).round() as i32 - shift, (point.1 + delta.1).round() as i32);
+        match outline {
+            Outline::Empty => { },
+            Outline::Simple { ref mut points, .. } => for (point, delta) in points.iter_mut().zip(&deltas) {
+                let (x, y) = moved((f64::from(point.0), f64::from(point.1)), delta);
+                *point = (x, y, point.2);
+            },
+            Outline::Composite { ref mut components, .. } => for (component, delta) in components.iter_mut().zip(&deltas) {
+                if component.flags & ARGS_ARE_XY_VALUES != 0 {
+                    component.arguments = moved((f64::from(component.arguments.0), f64::from(component.arguments.1)), delta);
+                }
+            },
+        }
+
+        let new_advance = (f64::from(advance) + advance_delta - origin_delta).round().max(0.0) as u16;
+        let new_lsb = match outline {
+            Outline::Simple { ref points, .. } => points.iter().map(|p| p.0).min().unwrap_or(0) as i16,
+            _ => lsb,
+        };
+        max_advance = max_advance.max(new_advance);
+        new_hmtx.extend_from_slice(&new_advance.to_be_bytes());
+        new_hmtx.extend_from_slice(&new_lsb.to_be_bytes());
+
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        new_glyf.extend(write_glyph(&outline));
+        new_glyf.resize((new_glyf.len() + 3) & !3, 0);
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+
+    let mut new_head = head.to_vec();
+    new_head[50..52].copy_from_slice(&1u16.to_be_bytes());
+    let mut new_hhea = hhea.to_vec();
+    new_hhea[10..12].copy_from_slice(&max_advance.to_be_bytes());
+    new_hhea[34..36].copy_from_slice(&(num_glyphs as u16).to_be_bytes());
+
+    let output = tables(font).into_iter().filter(|(tag, _)| !VARIATION_TABLES.contains(&tag)).map(|(tag, data)| {
+        let data = match &tag {
+            b"head" => new_head.clone(),
+            b"hhea" => new_hhea.clone(),
+            b"hmtx" => new_hmtx.clone(),
+            b"loca" => new_loca.clone(),
+            b"glyf" => new_glyf.clone(),
+            _ => data.to_vec(),
+        };
+        (tag, data)
+    }).collect();
+
+    Some(write_font(0x0001_0000, output))
+}
+
+/// Builds a minimal variable font for tests: glyph 1 ('A') is a square of 100 units with an
+/// advance of 200 units, the `wght` axis (100 - 400 - 900) makes it up to 50 units wider.
+/// The font has the named instance "Bold" at 700.
+#[cfg(test)]
+fn test_variable_font()
+-> Vec<u8>
+{
+    fn u16s(values: &[u16]) -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect() }
+
+    let mut head = vec![0; 54];
+    head[18..20].copy_from_slice(&u16s(&[1000]));
+    head[36..44].copy_from_slice(&u16s(&[0, 0, 100, 100]));
+    let mut hhea = vec![0; 36];
+    hhea[4..8].copy_from_slice(&u16s(&[800, (-200i16) as u16]));
+    hhea[34..36].copy_from_slice(&u16s(&[2]));
+    let maxp = u16s(&[0, 0x5000, 2]);
+    let hmtx = u16s(&[0, 0, 200, 0]);
+    let mut cmap = u16s(&[0, 1, 3, 1, 0, 12]);
+    cmap.extend(u16s(&[4, 32, 0, 4, 4, 1, 0, 0x41, 0xFFFF, 0, 0x41, 0xFFFF, (1i16 - 0x41) as u16, 1, 0, 0]));
+
+    // a square: one contour, four on-curve points
+    let mut glyph = u16s(&[1, 0, 0, 100, 100, 3, 0]);
+    glyph.extend_from_slice(&[ON_CURVE_POINT; 4]);
+    glyph.extend(u16s(&[0, 100, 0, (-100i16) as u16, 0, 0, 100, 0]));
+    let loca = u16s(&[0, 0, glyph.len() as u16 / 2]);
+
+    // one axis (wght) and one named instance (name ID 256 at 700)
+    let mut fvar = u16s(&[1, 0, 16, 2, 1, 20, 1, 8]);
+    fvar.extend_from_slice(b"wght");
+    fvar.extend(u16s(&[100, 0, 400, 0, 900, 0, 0, 256]));
+    fvar.extend(u16s(&[256, 0, 700, 0]));
+    let mut name = u16s(&[0, 1, 18, 3, 1, 0x409, 256, 8, 0]);
+    name.extend(u16s(&"Bold".encode_utf16().collect::<Vec<u16>>()));
+
+    // at the maximum weight, point 1 and the advance (phantom point 5) move 50 units to the right,
+    // point 0 stays, points 2 and 3 are interpolated
+    let tuple = vec![3, 0x02, 0, 1, 4, 0x02, 0, 50, 50, 0x82];
+    let mut variations = u16s(&[1, 10, tuple.len() as u16, 0xA000, 0x4000]);
+    variations.extend(tuple);
+    let mut gvar = u16s(&[1, 0, 1, 0, 0, 26, 2, 0, 0, 26]);
+    gvar.extend(u16s(&[0, 0, variations.len() as u16 / 2]));
+    gvar.extend(variations);
+
+    write_font(0x0001_0000, vec![
+        (*b"cmap", cmap), (*b"fvar", fvar), (*b"glyf", glyph), (*b"gvar", gvar), (*b"head", head),
+        (*b"hhea", hhea), (*b"hmtx", hmtx), (*b"loca", loca), (*b"maxp", maxp), (*b"name", name),
+    ])
+}
+
+#[test]
+fn test_font_instances() {
+    let font = test_variable_font();
+    assert_eq!(font_axes(&font), vec![FontAxis { tag: Tag(*b"wght"), min: 100.0, default: 400.0, max: 900.0 }]);
+    assert_eq!(named_instances(&font), vec![NamedInstance { name: "Bold".into(), axes: vec![(Tag(*b"wght"), 700.0)] }]);
+    assert!(font_axes(&::types::plugins::graphics::two_dimensional::sfnt::test_font()).is_empty());
+
+    let glyph = |instance: &[u8]| {
+        let loca = find_table(instance, b"loca").unwrap();
+        let (start, end) = (read_u32(loca, 4).unwrap() as usize, read_u32(loca, 8).unwrap() as usize);
+        let advance = read_u16(find_table(instance, b"hmtx").unwrap(), 4).unwrap();
+        match parse_glyph(&find_table(instance, b"glyf").unwrap()[start..end]).unwrap() {
+            Outline::Simple { points, .. } => (points.into_iter().map(|p| (p.0, p.1)).collect::<Vec<(i32, i32)>>(), advance),
+            other => panic!("unexpected outline {:?}", other),
+        }
+    };
+
+    let heavy = instantiate(&font, &[(Tag(*b"wght"), 900.0)]).unwrap();
+    assert_eq!(glyph(&heavy), (vec![(0, 0), (150, 0), (150, 100), (0, 100)], 250));
+    assert!(find_table(&heavy, b"fvar").is_none() && find_table(&heavy, b"gvar").is_none());
+
+    // halfway between the default and the maximum; the values are clamped to the axis
+    let semibold = instantiate(&font, &[(Tag(*b"wght"), 650.0)]).unwrap();
+    assert_eq!(glyph(&semibold), (vec![(0, 0), (125, 0), (125, 100), (0, 100)], 225));
+    assert_eq!(glyph(&instantiate(&font, &[]).unwrap()), glyph(&instantiate(&font, &[(Tag(*b"wght"), 50.0)]).unwrap()));
+
+    // the static font can be read by rusttype
+    let parsed = ::rusttype::FontCollection::from_bytes(heavy).unwrap().into_font().unwrap();
+    let glyph = parsed.glyph(::rusttype::Codepoint('A' as u32));
+    assert_eq!(glyph.id().0, 1);
+    assert_eq!(glyph.standalone().get_data().unwrap().unit_h_metrics.advance_width, 250.0);
+
+    assert!(instantiate(&font, &[(Tag(*b"wdth"), 75.0)]).is_err());
+    assert!(instantiate(&::types::plugins::graphics::two_dimensional::sfnt::test_font(), &[]).is_err());
+}
+
+#[test]
+fn test_avar_segments() {
+    let map = [(-1.0, -1.0), (0.0, 0.0), (0.5, 0.25), (1.0, 1.0)];
+    assert_eq!(map_segment(&map, -0.5), -0.5);
+    assert_eq!(map_segment(&map, 0.25), 0.125);
+    assert_eq!(map_segment(&map, 0.75), 0.625);
+    assert_eq!(map_segment(&map, 1.0), 1.0);
+    assert_eq!(tuple_scalar(&[1.0], None, &[0.5]), 0.5);
+    assert_eq!(tuple_scalar(&[1.0], None, &[-0.5]), 0.0);
+    assert_eq!(tuple_scalar(&[0.5], Some(&(vec![0.0], vec![1.0])), &[0.75]), 0.5);
+}
@@ -2,13 +2,21 @@
 #![cfg_attr(feature = "cargo-clippy", allow(string_lit_as_bytes))]
 
 use lopdf;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Read, Write, Error as IoError, ErrorKind, Result as IoResult};
+use std::rc::Rc;
 #[cfg(feature = "embedded_images")]
 use image::{ImageError, ImageDecoder, DynamicImage, GenericImageView};
 use time::OffsetDateTime;
+use types::plugins::graphics::compression::{ascii85_encode, ccitt_g4_encode, ccitt_g4_decode_parms};
+use types::plugins::graphics::image_headers::{jpeg2000_header, jbig2_size};
 use {
-    ColorSpace, ColorBits, CurTransMat, Px
+    ColorSpace, ColorBits, CurTransMat, Px, Error, PdfError, IccProfile, PdfResources, Rect, RenderingIntent
 };
+use indices::XObjectIndex;
 
 /* Parent: Resources dictionary of the page */
 /// External object that gets reference outside the PDF content stream
@@ -20,6 +28,10 @@ pub enum XObject {
     /* /Subtype /Image */
     /// Image XObject, for images
     Image(ImageXObject),
+    /// Image XObject, with the image data being read from a reader while saving.
+    /// Converting it with `Into<lopdf::Object>` panics if the image data can't be read,
+    /// `XObjectList::into_with_document()` returns the error instead.
+    StreamedImage(StreamedImageXObject),
     /* /Subtype /Form */
     /// Form XObject, for PDF forms
     Form(Box<FormXObject>),
@@ -54,17 +66,46 @@ impl Into<lopdf::Object> for XObject {
     -> lopdf::Object
     {
         match self {
-            XObject::Image(image) => { lopdf::Object::Stream(Self::compress_stream(image.into())) }
+            // images are compressed when the document is saved, see `PdfDocumentReference::set_image_filter()`
+            XObject::Image(image) => { lopdf::Object::Stream(image.into()) }
+            XObject::StreamedImage(image) => { lopdf::Object::Stream(lopdf::Stream::try_from(image).expect("could not read streamed image")) }
             XObject::Form(form) => { let cur_form: FormXObject = *form; lopdf::Object::Stream(Self::compress_stream(cur_form.into())) }
             XObject::PostScript(ps) => { lopdf::Object::Stream(Self::compress_stream(ps.into())) }
         }
     }
 }
 
+/// Cache of the already written XObjects, shared across all pages of a document while saving
+#[derive(Debug, Default, Clone)]
+pub(crate) struct XObjectCache {
+    /// Deduplicated XObjects and ICC profiles, indexed by their content hash
+    pub(crate) hashes: HashMap<u64, lopdf::ObjectId>,
+    /// Reusable forms, see `define_form()`
+    pub(crate) forms: HashMap<XObjectIndex, lopdf::ObjectId>,
+}
+
+impl XObjectCache {
+
+    /// Creates an empty cache
+    pub(crate) fn new()
+    -> Self
+    {
+        Self::default()
+    }
+}
+
+/// Streamed images, which were added to the document as a placeholder dictionary
+/// and whose data still has to be copied into the output (object ID -> image)
+pub(crate) type StreamedXObjects = HashMap<lopdf::ObjectId, StreamedImageXObject>;
+
 /// List of `XObjects`
 #[derive(Debug, Default, Clone)]
 pub struct XObjectList {
     objects: HashMap<String, XObject>,
+    /// Content hashes of deduplicated XObjects, indexed by the name of the XObject
+    hashes: HashMap<String, u64>,
+    /// Reusable forms that are drawn, indexed by the name of their placeholder
+    forms: HashMap<String, XObjectIndex>,
 }
 
 impl XObjectList {
@@ -86,23 +127,335 @@ impl XObjectList {
         xobj_ref
     }
 
+    /// Adds a new XObject to the list, identified by a hash of its content.
+    /// If an XObject with the same hash (and the same data, for images) already exists in
+    /// this list, no new XObject is added and the reference to the existing one is returned.
+    pub(crate) fn add_xobject_with_hash(&mut self, xobj: XObject, hash: u64)
+    -> XObjectRef
+    {
+        // two different images can have the same hash, so they are compared as well
+        let objects = &self.objects;
+        let existing = self.hashes.iter().find(|&(name, h)| *h == hash && match (&objects[name], &xobj) {
+            (XObject::Image(existing), XObject::Image(image)) => existing == image,
+            _ => true,
+        });
+        if let Some(name) = existing.map(|(name, _)| name.clone()) {
+            return XObjectRef { name };
+        }
+
+        let xobj_ref = self.add_xobject(xobj);
+        self.hashes.insert(xobj_ref.name.clone(), hash);
+        xobj_ref
+    }
+
+    /// Adds a placeholder for a reusable form, which is resolved to the written form when
+    /// saving. If the form was already added to this list, the existing reference is returned.
+    pub(crate) fn add_form(&mut self, form: XObjectIndex)
+    -> XObjectRef
+    {
+        if let Some((name, _)) = self.forms.iter().find(|&(_, f)| *f == form) {
+            return XObjectRef { name: name.clone() };
+        }
+
+        let xobj_ref = self.add_xobject(ReusableForm::placeholder());
+        self.forms.insert(xobj_ref.name.clone(), form);
+        xobj_ref
+    }
+
+    /// Returns the number of XObjects in this list
+    #[inline]
+    pub fn len(&self)
+    -> usize
+    {
+        self.objects.len()
+    }
+
+    /// Returns if the XObject list is empty
+    #[inline]
+    pub fn is_empty(&self)
+    -> bool
+    {
+        self.objects.is_empty()
+    }
+
+    /// Approximate size of the data of the XObjects in bytes: the images that are kept in
+    /// memory, the streamed images (from their dimensions, they are read while saving) and
+    /// the content streams of forms. Deduplicated XObjects are only counted if their hash
+    /// isn't in `counted` yet, so that they are counted once for all pages. Reusable forms
+    /// aren't counted, they are written once for the document.
+    pub(crate) fn estimated_sizes(&self, counted: &mut HashSet<u64>)
+    -> (usize, usize, usize)
+    {
+        let (mut images, mut streamed, mut forms) = (0, 0, 0);
+        for (name, xobject) in &self.objects {
+            if self.forms.contains_key(name) {
+                continue;
+            }
+            if let Some(hash) = self.hashes.get(name) {
+                if !counted.insert(*hash) {
+                    continue;
+                }
+            }
+            match *xobject {
+                XObject::Image(ref image) => {
+                    images += image.image_data.len();
+                    if let Some(ImageFilter::JBIG2Decode { globals: Some(ref globals) }) = image.image_filter {
+                        images += globals.len();
+                    }
+                },
+                XObject::StreamedImage(ref image) => {
+                    let components = match image.color_space {
+                        ColorSpace::Greyscale | ColorSpace::Palette => 1,
+                        ColorSpace::GreyscaleAlpha => 2,
+                        ColorSpace::Rgb => 3,
+                        ColorSpace::Rgba | ColorSpace::Cmyk => 4,
+                    };
+                    let bits: i64 = image.bits_per_component.into();
+                    streamed += (image.width.0 * image.height.0 * components * bits as usize).div_ceil(8);
+                },
+                XObject::Form(ref form) => forms += form.bytes.len(),
+                XObject::PostScript(_) => { },
+            }
+        }
+        (images, streamed, forms)
+    }
+
+    /// Returns if an XObject with the given name (e.g. `X0`) is in this list
+    #[inline]
+    pub(crate) fn contains(&self, name: &str)
+    -> bool
+    {
+        self.objects.contains_key(name)
+    }
+
+    /// Names of the XObjects in this list, sorted by their number
+    pub(crate) fn names(&self)
+    -> Vec<String>
+    {
+        let mut names = self.objects.keys().cloned().collect::<Vec<String>>();
+        names.sort_by_key(|name| name[1..].parse::<usize>().unwrap_or(0));
+        names
+    }
+
+    /// Reusable form that the XObject with the given name stands for, see `add_form()`
+    #[inline]
+    pub(crate) fn form(&self, name: &str)
+    -> Option<XObjectIndex>
+    {
+        self.forms.get(name).cloned()
+    }
+
+    /// Replaces the XObject with a blank 1x1 image, so that its data isn't written into the
+    /// file. The name stays reserved, the following XObjects keep their names.
+    pub(crate) fn redact(&mut self, name: &str)
+    {
+        if let Some(xobject) = self.objects.get_mut(name) {
+            *xobject = XObject::Image(ImageXObject::new(Px(1), Px(1), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![255]));
+            self.hashes.remove(name);
+            self.forms.remove(name);
+        }
+    }
+
+    /// Removes all XObjects that were added after the list had `len` entries
+    pub(crate) fn truncate(&mut self, len: usize)
+    {
+        for index in len..self.objects.len() {
+            let name = XObjectRef::new(index).name;
+            self.objects.remove(&name);
+            self.hashes.remove(&name);
+            self.forms.remove(&name);
+        }
+    }
+
+    /// Gives the streamed images their own reader (with the data read into memory),
+    /// for copies of the list that are saved in addition to the original, see `StreamedImageXObject::buffered`
+    pub(crate) fn buffer_streamed_images(&mut self)
+    -> IoResult<()>
+    {
+        for object in self.objects.values_mut() {
+            if let XObject::StreamedImage(ref mut image) = *object {
+                *image = image.buffered()?;
+            }
+        }
+        Ok(())
+    }
+
     /// Same as `Into<lopdf::Dictionary>`, but since the dictionary
     /// items in an XObject dictionary are streams and must be added to
     /// the document as __references__, this function needs an additional
     /// access to the PDF document so that we can add the streams first and
     /// then track the references to them.
+    ///
+    /// The data of streamed images is read into memory, fails if it can't be read completely.
     #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
     pub fn into_with_document(self, doc: &mut lopdf::Document)
+    -> Result<lopdf::Dictionary, Error>
+    {
+        let mut streamed = StreamedXObjects::new();
+        let dict = self.into_with_document_and_cache(doc, &mut XObjectCache::new(), &mut streamed);
+        read_streamed_images(doc, streamed)?;
+        Ok(dict)
+    }
+
+    /// Same as `into_with_document`, but XObjects that were added with a content hash
+    /// are only written once per document: if the `cache` already contains the hash,
+    /// the existing object is referenced instead. Reusable forms are looked up in the cache,
+    /// a form that isn't there (it was removed by `restore()`) is written as an empty form.
+    ///
+    /// Streamed images are only added as a placeholder dictionary and collected in `streamed`,
+    /// the writer copies the image data into the output later on (see `read_streamed_images`).
+    pub(crate) fn into_with_document_and_cache(self, doc: &mut lopdf::Document, cache: &mut XObjectCache,
+                                               streamed: &mut StreamedXObjects)
     -> lopdf::Dictionary
     {
-        self.objects.into_iter().map(|(name, object)| {
-            let obj: lopdf::Object = object.into();
-            let obj_ref =  doc.add_object(obj);
+        let hashes = self.hashes;
+        let forms = self.forms;
+        // sorted, so that the objects are always added to the document in the same order
+        let mut objects: Vec<(String, XObject)> = self.objects.into_iter().collect();
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        objects.into_iter().map(|(name, object)| {
+            if let Some(form) = forms.get(&name) {
+                let form_id = cache.forms.get(form).cloned().unwrap_or_else(|| doc.add_object(ReusableForm::empty()));
+                return (name, lopdf::Object::Reference(form_id));
+            }
+            let hash = hashes.get(&name).cloned();
+            // two different images can have the same hash, so the content is compared as well
+            let cached = match (&object, hash.and_then(|h| cache.hashes.get(&h).cloned())) {
+                (XObject::Image(image), Some(existing)) if !has_stream_content(doc, existing, &image.image_data) => None,
+                (_, cached) => cached,
+            };
+            let obj_ref = match (object, cached) {
+                (_, Some(existing)) => existing,
+                (XObject::StreamedImage(image), None) => {
+                    let obj_ref = doc.add_object(lopdf::Object::Dictionary(image.dictionary()));
+                    streamed.insert(obj_ref, image);
+                    obj_ref
+                },
+                (XObject::Image(image), None) => {
+                    // JBIG2 globals are shared by the images of a document, so they are a separate stream
+                    let globals = match image.image_filter {
+                        Some(ImageFilter::JBIG2Decode { globals: Some(ref globals) }) =>
+                            Some(doc.add_object(lopdf::Stream::new(lopdf::Dictionary::new(), globals.clone()))),
+                        _ => None,
+                    };
+                    // identical profiles are written once, like the images
+                    let profile = image.icc_profile.clone().map(|profile| {
+                        let profile_hash = profile.content_hash();
+                        let stream: lopdf::Stream = profile.into();
+                        match cache.hashes.get(&profile_hash) {
+                            Some(&existing) if has_stream_content(doc, existing, &stream.content) => existing,
+                            _ => {
+                                let profile_id = doc.add_object(stream);
+                                cache.hashes.entry(profile_hash).or_insert(profile_id);
+                                profile_id
+                            },
+                        }
+                    });
+                    let mut stream: lopdf::Stream = image.into();
+                    if let Some(profile) = profile {
+                        stream.dict.set("ColorSpace", lopdf::Object::Array(vec![
+                            lopdf::Object::Name("ICCBased".into()), lopdf::Object::Reference(profile)]));
+                    }
+                    if let Some(globals) = globals {
+                        let mut parms = lopdf::Dictionary::new();
+                        parms.set("JBIG2Globals", lopdf::Object::Reference(globals));
+                        stream.dict.set("DecodeParms", lopdf::Object::Dictionary(parms));
+                    }
+                    let obj_ref = doc.add_object(lopdf::Object::Stream(stream));
+                    if let Some(h) = hash { cache.hashes.entry(h).or_insert(obj_ref); }
+                    obj_ref
+                },
+                (object, None) => {
+                    let obj: lopdf::Object = object.into();
+                    let obj_ref = doc.add_object(obj);
+                    if let Some(h) = hash { cache.hashes.insert(h, obj_ref); }
+                    obj_ref
+                }
+            };
             (name.to_string(), lopdf::Object::Reference(obj_ref))
         }).collect()
     }
 }
 
+/// Checks if the object `id` is a stream with the given content. The streams are compressed
+/// after all objects are added (see `set_image_filter()`), so this compares the raw data.
+fn has_stream_content(doc: &lopdf::Document, id: lopdf::ObjectId, content: &[u8])
+-> bool
+{
+    doc.get_object(id).and_then(lopdf::Object::as_stream).map(|stream| stream.content == content).unwrap_or(false)
+}
+
+/// Content that is defined once for the whole document (see `define_form()`) and drawn
+/// on any number of pages. It is written as a single form XObject, the pages reference it.
+#[derive(Debug, Clone)]
+pub(crate) struct ReusableForm {
+    /// Content stream of the form
+    pub(crate) operations: Vec<lopdf::content::Operation>,
+    /// Images, patterns and graphics states used by the content
+    pub(crate) resources: PdfResources,
+    /// Bounding box of the content, in the coordinates of the form
+    pub(crate) bbox: Rect,
+    /// Whether the form is a knockout group: each object of the form is composited with the
+    /// backdrop of the form instead of the objects that were drawn before it
+    pub(crate) knockout: bool,
+}
+
+impl ReusableForm {
+
+    /// XObject that the pages add for the form (see `XObjectList::add_form()`), it is never written
+    pub(crate) fn placeholder()
+    -> XObject
+    {
+        XObject::Image(ImageXObject::new(Px(1), Px(1), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![255]))
+    }
+
+    /// Form XObject without content, for the pages that draw a form which doesn't exist anymore
+    pub(crate) fn empty()
+    -> lopdf::Stream
+    {
+        use std::iter::FromIterator;
+        use lopdf::Object::*;
+
+        let dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("XObject".into())),
+            ("Subtype", Name("Form".into())),
+            ("BBox", Array(vec![Integer(0), Integer(0), Integer(0), Integer(0)])),
+        ]);
+        lopdf::Stream::new(dict, Vec::new())
+    }
+
+    /// Adds the form to the document. The fonts of the document are shared with the pages,
+    /// the other resources of the form are added like the resources of a page.
+    pub(crate) fn into_object(self, doc: &mut lopdf::Document, font_dict_id: Option<lopdf::ObjectId>,
+                              cache: &mut XObjectCache, streamed: &mut StreamedXObjects)
+    -> lopdf::ObjectId
+    {
+        use std::iter::FromIterator;
+        use lopdf::Object::*;
+
+        let (mut resources, _) = self.resources.into_with_document_layers_and_cache(doc, Vec::new(), cache, streamed);
+        if let Some(font_dict_id) = font_dict_id {
+            resources.set("Font", Reference(font_dict_id));
+        }
+
+        let mut dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("XObject".into())),
+            ("Subtype", Name("Form".into())),
+            ("BBox", Array(vec![Real(self.bbox.llx.0), Real(self.bbox.lly.0), Real(self.bbox.urx.0), Real(self.bbox.ury.0)])),
+            ("Resources", Dictionary(resources)),
+        ]);
+        if self.knockout {
+            dict.set("Group", Dictionary(lopdf::Dictionary::from_iter(vec![
+                ("S", Name("Transparency".into())),
+                ("K", Boolean(true)),
+            ])));
+        }
+        // writing into a Vec<u8> can't fail
+        let content = lopdf::content::Content { operations: self.operations }.encode().unwrap();
+        doc.add_object(lopdf::Stream::new(dict, content))
+    }
+}
+
 /// Named reference to an `XObject`
 #[derive(Debug)]
 pub struct XObjectRef {
@@ -122,9 +475,8 @@ impl XObjectRef {
 }
 
 /* todo: inline images? (icons, logos, etc.) */
-/* todo: JPXDecode filter */
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ImageXObject {
     /// Width of the image (original width, not scaled width)
     pub width: Px,
@@ -146,6 +498,12 @@ pub struct ImageXObject {
     /// Required bounds to clip the image, in unit space
     /// Default value: Identity matrix (`[1 0 0 1 0 0]`) - used when value is `None`
     pub clipping_bbox: Option<CurTransMat>,
+    /// ICC profile of the image, replaces the device color space (e.g. `IccProfile::srgb()`).
+    /// Default: `None`, see also `PdfDocumentReference::set_assume_srgb()`
+    pub icc_profile: Option<IccProfile>,
+    /// Rendering intent for the conversion of the image colors to the output device (`/Intent`).
+    /// Default: `None`, the rendering intent of the graphics state is used
+    pub rendering_intent: Option<RenderingIntent>,
 }
 
 impl<'a> ImageXObject {
@@ -167,6 +525,8 @@ impl<'a> ImageXObject {
             image_data: data,
             image_filter: image_filter,
             clipping_bbox: bbox,
+            icc_profile: None,
+            rendering_intent: None,
         }
     }
 
@@ -200,6 +560,8 @@ impl<'a> ImageXObject {
             interpolate: true,
             image_filter: None,
             clipping_bbox: None,
+            icc_profile: None,
+            rendering_intent: None,
         })
     }
 
@@ -222,7 +584,198 @@ impl<'a> ImageXObject {
             interpolate: true,
             image_filter: None,
             clipping_bbox: None,
+            icc_profile: None,
+            rendering_intent: None,
+        }
+    }
+}
+
+impl ImageXObject {
+
+    /// Creates a black and white image (e.g. a scanned page) from packed 1 bit pixels:
+    /// each row starts at a new byte, the first pixel is the most significant bit, 0 bits are
+    /// black and 1 bits are white. The image is encoded with CCITT Group 4, which is much
+    /// smaller than the other filters for this kind of image.
+    ///
+    /// Panics if `packed_bits` is shorter than `(width + 7) / 8 * height` bytes.
+    pub fn from_bilevel(width: Px, height: Px, packed_bits: &[u8])
+    -> Self
+    {
+        let row_length = width.0.div_ceil(8);
+        assert!(packed_bits.len() >= row_length * height.0, "bilevel image: expected {} bytes of pixel data, got {}",
+                row_length * height.0, packed_bits.len());
+
+        Self {
+            width,
+            height,
+            color_space: ColorSpace::Greyscale,
+            bits_per_component: ColorBits::Bit1,
+            interpolate: false,
+            image_data: ccitt_g4_encode(packed_bits, width.0, height.0),
+            image_filter: Some(ImageFilter::CCITTFaxDecode),
+            clipping_bbox: None,
+            icc_profile: None,
+            rendering_intent: None,
+        }
+    }
+
+    /// Embeds a JPEG 2000 image (a JP2 file or a raw codestream) without decoding it. The size,
+    /// color space and bit depth are read from the header of the image.
+    ///
+    /// Returns `PdfError::ImageFormatError` if the header can't be read, or the image has a
+    /// number of components or a bit depth other than 1, 3 or 4 components with 1, 8 or 16 bits.
+    pub fn from_jpeg2000(data: Vec<u8>)
+    -> Result<Self, Error>
+    {
+        let header = jpeg2000_header(&data).ok_or(Error::Pdf(PdfError::ImageFormatError))?;
+
+        let color_space = match header.components {
+            1 => ColorSpace::Greyscale,
+            3 => ColorSpace::Rgb,
+            4 => ColorSpace::Cmyk,
+            _ => return Err(Error::Pdf(PdfError::ImageFormatError)),
+        };
+
+        let bits_per_component = match header.bits {
+            1 => ColorBits::Bit1,
+            8 => ColorBits::Bit8,
+            16 => ColorBits::Bit16,
+            _ => return Err(Error::Pdf(PdfError::ImageFormatError)),
+        };
+
+        Ok(Self {
+            width: Px(header.width),
+            height: Px(header.height),
+            color_space,
+            bits_per_component,
+            interpolate: false,
+            image_data: data,
+            image_filter: Some(ImageFilter::JPXDecode),
+            clipping_bbox: None,
+            icc_profile: None,
+            rendering_intent: None,
+        })
+    }
+
+    /// Embeds a JBIG2 encoded black and white image without decoding it. The data has to be in
+    /// the embedded stream format of JBIG2, without the file header (e.g. the output of
+    /// `jbig2enc -p`). If the image refers to symbols stored in a separate globals stream
+    /// (`output.sym` of `jbig2enc`), pass them as `globals`.
+    ///
+    /// The size of the image is read from the page information segment, returns
+    /// `PdfError::ImageFormatError` if there is none.
+    pub fn from_jbig2(data: Vec<u8>, globals: Option<Vec<u8>>)
+    -> Result<Self, Error>
+    {
+        let (width, height) = jbig2_size(&data).ok_or(Error::Pdf(PdfError::ImageFormatError))?;
+
+        Ok(Self {
+            width: Px(width),
+            height: Px(height),
+            color_space: ColorSpace::Greyscale,
+            bits_per_component: ColorBits::Bit1,
+            interpolate: false,
+            image_data: data,
+            image_filter: Some(ImageFilter::JBIG2Decode { globals }),
+            clipping_bbox: None,
+            icc_profile: None,
+            rendering_intent: None,
+        })
+    }
+
+    /// Returns the operations that draw the image as an inline image (`BI` ... `ID` ... `EI`)
+    /// in the content stream, in the unit square like `Do`. The data is ASCII85 encoded, so
+    /// that it can be stored in an operation. Returns `None` for images that can't be inlined:
+    /// images with ICC profiles, palettes or alpha channels and JPEG 2000 or JBIG2 data.
+    pub(crate) fn inline_operations(&self)
+    -> Option<Vec<lopdf::content::Operation>>
+    {
+        use lopdf::Object::*;
+        use lopdf::content::Operation;
+
+        if self.icc_profile.is_some() {
+            return None;
         }
+
+        // inline images use abbreviated names (PDF Reference, table 4.43)
+        let color_space = match self.color_space {
+            ColorSpace::Greyscale => "G",
+            ColorSpace::Rgb => "RGB",
+            ColorSpace::Cmyk => "CMYK",
+            _ => return None,
+        };
+
+        let (filter, data) = match self.image_filter {
+            None => (None, ascii85_encode(&self.image_data)),
+            Some(ImageFilter::Ascii85Decode) => (None, self.image_data.clone()),
+            Some(ImageFilter::LzwDecode) => (Some("LZW"), ascii85_encode(&self.image_data)),
+            Some(ImageFilter::DCTDecode) => (Some("DCT"), ascii85_encode(&self.image_data)),
+            Some(ImageFilter::CCITTFaxDecode) => (Some("CCF"), ascii85_encode(&self.image_data)),
+            Some(ImageFilter::JPXDecode) | Some(ImageFilter::JBIG2Decode { .. }) => return None,
+        };
+        let data = ::std::string::String::from_utf8(data).ok()?;
+
+        let mut parameters = vec![
+            Name("W".into()), Integer(self.width.0 as i64),
+            Name("H".into()), Integer(self.height.0 as i64),
+            Name("CS".into()), Name(color_space.into()),
+            Name("BPC".into()), Integer(self.bits_per_component.into()),
+        ];
+        if self.interpolate {
+            parameters.extend(vec![Name("I".into()), Boolean(true)]);
+        }
+        if let Some(intent) = self.rendering_intent {
+            parameters.extend(vec![Name("Intent".into()), intent.into()]);
+        }
+        match filter {
+            Some(filter) => parameters.extend(vec![Name("F".into()), Array(vec![Name(filter.into()), Name("A85".into())])]),
+            None => parameters.extend(vec![Name("F".into()), Name("A85".into())]),
+        }
+        if self.image_filter == Some(ImageFilter::CCITTFaxDecode) {
+            let decode_parms = ccitt_g4_decode_parms(self.width.0 as i64, self.height.0 as i64);
+            parameters.extend(vec![Name("DP".into()), Array(vec![Dictionary(decode_parms), Null])]);
+        }
+
+        Some(vec![
+            Operation::new("BI", vec![]),
+            Operation::new("ID", parameters),
+            // "~>" ends the data, the operator is written as it is
+            Operation { operator: data, operands: Vec::new() },
+            Operation::new("EI", vec![]),
+        ])
+    }
+
+    /// Hashes the image data together with the image properties, used for
+    /// deduplicating identical images
+    pub(crate) fn content_hash(&self)
+    -> u64
+    {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        let cs: &'static str = self.color_space.into();
+        let bits: i64 = self.bits_per_component.into();
+        self.width.0.hash(&mut hasher);
+        self.height.0.hash(&mut hasher);
+        cs.hash(&mut hasher);
+        bits.hash(&mut hasher);
+        self.interpolate.hash(&mut hasher);
+        if let Some(ref filter) = self.image_filter {
+            let name: &'static str = filter.into();
+            name.hash(&mut hasher);
+            if let ImageFilter::JBIG2Decode { globals: Some(ref globals) } = *filter {
+                globals.hash(&mut hasher);
+            }
+        }
+        if let Some(ref profile) = self.icc_profile {
+            profile.content_hash().hash(&mut hasher);
+        }
+        if let Some(intent) = self.rendering_intent {
+            intent.name().hash(&mut hasher);
+        }
+        self.image_data.hash(&mut hasher);
+        hasher.finish()
     }
 }
 
@@ -238,7 +791,7 @@ impl Into<lopdf::Stream> for ImageXObject {
             .unwrap_or(CurTransMat::Identity)
             .into();
 
-        let dict = lopdf::Dictionary::from_iter(vec![
+        let mut dict = lopdf::Dictionary::from_iter(vec![
             ("Type", Name("XObject".as_bytes().to_vec())),
             ("Subtype", Name("Image".as_bytes().to_vec())),
             ("Width", Integer(self.width.0 as i64)),
@@ -249,8 +802,16 @@ impl Into<lopdf::Stream> for ImageXObject {
             ("BBox", bbox),
         ]);
 
-        if self.image_filter.is_some() {
-            /* todo: add filter */
+        if let Some(ref filter) = self.image_filter {
+            if *filter == ImageFilter::CCITTFaxDecode {
+                dict.set("DecodeParms", Dictionary(ccitt_g4_decode_parms(self.width.0 as i64, self.height.0 as i64)));
+            }
+            let filter: &'static str = filter.into();
+            dict.set("Filter", Name(filter.as_bytes().to_vec()));
+        }
+
+        if let Some(intent) = self.rendering_intent {
+            dict.set("Intent", intent);
         }
 
         lopdf::Stream::new(dict, self.image_data)
@@ -263,12 +824,204 @@ pub struct ImageXObjectRef {
     name: String,
 }
 
-/// todo: they don't work yet
-#[derive(Debug, Copy, Clone)]
+/// Filter, which the image data is already encoded with. The data is embedded unchanged.
+#[derive(Debug, Clone, PartialEq)]
 pub enum ImageFilter {
     Ascii85Decode,
     LzwDecode,
     JPXDecode,
+    /// JPEG (baseline DCT) compressed data, a JPEG file can be embedded unchanged
+    DCTDecode,
+    /// CCITT Group 4 encoded 1 bit data, see `ImageXObject::from_bilevel()`.
+    /// The decode parameters are derived from the size of the image.
+    CCITTFaxDecode,
+    /// JBIG2 encoded 1 bit data, see `ImageXObject::from_jbig2()`. The `globals` (symbols
+    /// shared by several images) are written as a separate stream.
+    JBIG2Decode {
+        globals: Option<Vec<u8>>,
+    },
+}
+
+impl<'a> From<&'a ImageFilter> for &'static str {
+    fn from(filter: &'a ImageFilter)
+    -> Self
+    {
+        use self::ImageFilter::*;
+        match *filter {
+            Ascii85Decode => "ASCII85Decode",
+            LzwDecode => "LZWDecode",
+            JPXDecode => "JPXDecode",
+            DCTDecode => "DCTDecode",
+            CCITTFaxDecode => "CCITTFaxDecode",
+            JBIG2Decode { .. } => "JBIG2Decode",
+        }
+    }
+}
+
+impl From<ImageFilter> for &'static str {
+    fn from(filter: ImageFilter)
+    -> Self
+    {
+        (&filter).into()
+    }
+}
+
+/// Image, whose data is not held in memory, but copied from a reader directly into the
+/// output while the document is saved. Use this for very large images (e.g. high-resolution
+/// scans), which would otherwise be decoded into memory completely.
+///
+/// Since the data is copied as-is, there are some constraints:
+///
+/// - `length` must be known in advance, since it has to be written before the data.
+///   If the reader doesn't return exactly `length` bytes, saving fails with an IO error.
+/// - The data has to be in a form that PDF understands: either the raw, uncompressed
+///   samples (`width * height` pixels, rows padded to full bytes), or, with the
+///   `ImageFilter::DCTDecode` filter, a JPEG file, which can be embedded without decoding.
+/// - The data is read exactly once, when the document is saved. It is neither compressed
+///   nor deduplicated.
+#[derive(Clone)]
+pub struct StreamedImageXObject {
+    /// Width of the image (original width, not scaled width)
+    pub width: Px,
+    /// Height of the image (original height, not scaled height)
+    pub height: Px,
+    /// Color space (Greyscale, RGB, CMYK)
+    pub color_space: ColorSpace,
+    /// Bits per color component (1, 2, 4, 8, 16)
+    pub bits_per_component: ColorBits,
+    /// Should the image be interpolated when scaled?
+    pub interpolate: bool,
+    /// Filter, which the data is encoded with, `None` for raw samples
+    pub image_filter: Option<ImageFilter>,
+    /// Exact number of bytes that will be read from the reader
+    pub length: usize,
+    /// The source of the image data, taken out when the document is saved
+    reader: Rc<RefCell<Option<Box<dyn Read>>>>,
+}
+
+impl fmt::Debug for StreamedImageXObject {
+    fn fmt(&self, f: &mut fmt::Formatter)
+    -> fmt::Result
+    {
+        f.debug_struct("StreamedImageXObject")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("color_space", &self.color_space)
+            .field("bits_per_component", &self.bits_per_component)
+            .field("interpolate", &self.interpolate)
+            .field("image_filter", &self.image_filter)
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+impl StreamedImageXObject {
+
+    /// Creates a new streamed image, `length` bytes of image data will be read from
+    /// `reader` when the document is saved
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<R: Read + 'static>(width: Px, height: Px, color_space: ColorSpace,
+                                  bits: ColorBits, interpolate: bool, image_filter: Option<ImageFilter>,
+                                  length: usize, reader: R)
+    -> Self
+    {
+        Self {
+            width,
+            height,
+            color_space,
+            bits_per_component: bits,
+            interpolate,
+            image_filter,
+            length,
+            reader: Rc::new(RefCell::new(Some(Box::new(reader)))),
+        }
+    }
+
+    /// The stream dictionary of the image, including `/Length`
+    pub(crate) fn dictionary(&self)
+    -> lopdf::Dictionary
+    {
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
+
+        let cs: &'static str = self.color_space.into();
+
+        let mut dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("XObject".as_bytes().to_vec())),
+            ("Subtype", Name("Image".as_bytes().to_vec())),
+            ("Width", Integer(self.width.0 as i64)),
+            ("Height", Integer(self.height.0 as i64)),
+            ("Interpolate", self.interpolate.into()),
+            ("BitsPerComponent", Integer(self.bits_per_component.into())),
+            ("ColorSpace", Name(cs.as_bytes().to_vec())),
+            ("Length", Integer(self.length as i64)),
+        ]);
+
+        if let Some(ref filter) = self.image_filter {
+            let filter: &'static str = filter.into();
+            dict.set("Filter", Name(filter.as_bytes().to_vec()));
+        }
+
+        dict
+    }
+
+    /// Copies exactly `length` bytes from the reader into `target`.
+    /// Fails if the reader was already used or returns less than `length` bytes
+    pub(crate) fn copy_data<W: Write>(&self, target: &mut W)
+    -> IoResult<()>
+    {
+        let reader = self.reader.borrow_mut().take();
+        let mut reader = reader.ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "image data of streamed image was already read"))?;
+        let copied = io::copy(&mut reader.by_ref().take(self.length as u64), target)?;
+
+        if copied != self.length as u64 {
+            return Err(IoError::new(ErrorKind::UnexpectedEof,
+                format!("streamed image: expected {} bytes of image data, got {}", self.length, copied)));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the image data into memory and returns a copy of the image with its own reader.
+    /// Both images can be saved afterwards, this image reads the buffered data as well.
+    pub(crate) fn buffered(&self)
+    -> IoResult<Self>
+    {
+        let mut data = Vec::with_capacity(self.length);
+        self.copy_data(&mut data)?;
+        *self.reader.borrow_mut() = Some(Box::new(io::Cursor::new(data.clone())));
+        Ok(Self {
+            reader: Rc::new(RefCell::new(Some(Box::new(io::Cursor::new(data))))),
+            .. self.clone()
+        })
+    }
+}
+
+impl TryFrom<StreamedImageXObject> for lopdf::Stream {
+    type Error = IoError;
+
+    /// Reads the image data into memory, used if the writer can't stream the data.
+    /// Fails like `copy_data`, so that a short read doesn't end up as a truncated stream.
+    fn try_from(image: StreamedImageXObject)
+    -> IoResult<Self>
+    {
+        let mut data = Vec::with_capacity(image.length);
+        image.copy_data(&mut data)?;
+        let dict = image.dictionary();
+        Ok(lopdf::Stream::new(dict, data).with_compression(false))
+    }
+}
+
+/// Replaces the placeholder dictionaries of the `streamed` images with streams that
+/// contain the image data, for documents that aren't saved by the writer
+pub(crate) fn read_streamed_images(doc: &mut lopdf::Document, streamed: StreamedXObjects)
+-> IoResult<()>
+{
+    for (id, image) in streamed {
+        let stream = lopdf::Stream::try_from(image)?;
+        doc.objects.insert(id, lopdf::Object::Stream(stream));
+    }
+    Ok(())
 }
 
 /// __THIS IS NOT A PDF FORM!__ A form `XObject` can be nearly everything.
@@ -376,12 +1129,20 @@ impl Into<lopdf::Stream> for FormXObject {
         use std::iter::FromIterator;
         use lopdf::Object::*;
 
-        let dict = lopdf::Dictionary::from_iter(vec![
+        let mut dict = lopdf::Dictionary::from_iter(vec![
             ("Type", Name("XObject".as_bytes().to_vec())),
             ("Subtype", Name("Form".as_bytes().to_vec())),
             ("FormType", Integer(self.form_type.into())),
         ]);
 
+        if let Some(matrix) = self.matrix {
+            dict.set("Matrix", matrix);
+        }
+
+        if let Some(resources) = self.resources {
+            dict.set("Resources", Dictionary(resources));
+        }
+
         lopdf::Stream::new(dict, self.bytes)
     }
 }
@@ -574,3 +1335,151 @@ impl Into<lopdf::Stream> for PostScriptXObject {
         lopdf::Stream::new(lopdf::Dictionary::new(), Vec::new())
     }
 }
+
+#[test]
+fn test_streamed_image_copy_data()
+{
+    use std::io::Cursor;
+
+    let data = vec![1_u8, 2, 3, 4, 5, 6];
+    let image = StreamedImageXObject::new(Px(2), Px(1), ColorSpace::Rgb, ColorBits::Bit8, false, None, 6, Cursor::new(data.clone()));
+    let mut out = Vec::new();
+    image.copy_data(&mut out).unwrap();
+    assert_eq!(out, data);
+
+    // the reader can only be used once
+    assert!(image.copy_data(&mut Vec::new()).is_err());
+
+    // the reader must return exactly `length` bytes
+    let short = StreamedImageXObject::new(Px(2), Px(2), ColorSpace::Rgb, ColorBits::Bit8, false, None, 12, Cursor::new(data.clone()));
+    assert!(short.copy_data(&mut Vec::new()).is_err());
+
+    // reading the data into memory doesn't produce a truncated stream either
+    let short = StreamedImageXObject::new(Px(2), Px(2), ColorSpace::Rgb, ColorBits::Bit8, false, None, 12, Cursor::new(data));
+    assert_eq!(lopdf::Stream::try_from(short).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_buffered_streamed_image()
+{
+    use std::io::Cursor;
+
+    // like a copy of the document for `rasterize()`, the clone shares the reader
+    let data = vec![1_u8, 2, 3, 4, 5, 6];
+    let image = StreamedImageXObject::new(Px(2), Px(1), ColorSpace::Rgb, ColorBits::Bit8, false, None, 6, Cursor::new(data.clone()));
+    let mut list = XObjectList::new();
+    list.add_xobject(XObject::StreamedImage(image.clone()));
+    list.buffer_streamed_images().unwrap();
+
+    // saving the copy doesn't use up the data of the original
+    let copy = match list.objects.values().next() {
+        Some(XObject::StreamedImage(copy)) => copy.clone(),
+        _ => unreachable!(),
+    };
+    let (mut copied, mut original) = (Vec::new(), Vec::new());
+    copy.copy_data(&mut copied).unwrap();
+    image.copy_data(&mut original).unwrap();
+    assert_eq!(copied, data);
+    assert_eq!(original, data);
+}
+
+#[test]
+fn test_image_from_bilevel() {
+    // 10 x 2 pixels, the first row is white, the second one black
+    let image = ImageXObject::from_bilevel(Px(10), Px(2), &[0xFF, 0xC0, 0x00, 0x00]);
+    assert_eq!(image.bits_per_component, ColorBits::Bit1);
+    assert_eq!(image.image_filter, Some(ImageFilter::CCITTFaxDecode));
+    assert_eq!(image.image_data, ccitt_g4_encode(&[0xFF, 0xC0, 0x00, 0x00], 10, 2));
+}
+
+#[test]
+fn test_image_from_jbig2() {
+    // page information segment of a 2480 x 3200 px page
+    let mut data = vec![0, 0, 0, 0, 48, 0, 1, 0, 0, 0, 19, 0, 0, 9, 0xB0, 0, 0, 0x0C, 0x80];
+    data.extend_from_slice(&[0; 11]);
+    let image = ImageXObject::from_jbig2(data, Some(vec![1, 2, 3])).unwrap();
+    assert_eq!((image.width, image.height), (Px(2480), Px(3200)));
+    assert_eq!(image.image_filter, Some(ImageFilter::JBIG2Decode { globals: Some(vec![1, 2, 3]) }));
+
+    assert_eq!(ImageXObject::from_jpeg2000(vec![0; 16]).err().map(|e| e.to_string()), Some("Invalid or unsupported image data".into()));
+}
+
+#[test]
+fn test_inline_operations() {
+    let image = ImageXObject::new(Px(2), Px(2), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![0, 0, 0, 0]);
+    let operations = image.inline_operations().unwrap();
+    let operators = operations.iter().map(|o| o.operator.as_str()).collect::<Vec<&str>>();
+    assert_eq!(operators, vec!["BI", "ID", "z~>", "EI"]);
+    assert_eq!(format!("{:?}", operations[1].operands), format!("{:?}", vec![
+        lopdf::Object::Name("W".into()), lopdf::Object::Integer(2),
+        lopdf::Object::Name("H".into()), lopdf::Object::Integer(2),
+        lopdf::Object::Name("CS".into()), lopdf::Object::Name("G".into()),
+        lopdf::Object::Name("BPC".into()), lopdf::Object::Integer(8),
+        lopdf::Object::Name("F".into()), lopdf::Object::Name("A85".into()),
+    ]));
+
+    let palette = ImageXObject::new(Px(2), Px(2), ColorSpace::Palette, ColorBits::Bit8, false, None, None, vec![0; 4]);
+    assert!(palette.inline_operations().is_none());
+}
+
+#[test]
+fn test_image_rendering_intent() {
+    let image = ImageXObject::new(Px(1), Px(1), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![0]);
+    let perceptual = ImageXObject { rendering_intent: Some(RenderingIntent::Perceptual), .. image.clone() };
+    // images that only differ in the intent aren't deduplicated
+    assert_ne!(image.content_hash(), perceptual.content_hash());
+
+    let operations = perceptual.inline_operations().unwrap();
+    assert!(format!("{:?}", operations[1].operands).contains("Perceptual"));
+
+    for intent in &[RenderingIntent::AbsoluteColorimetric, RenderingIntent::RelativeColorimetric, RenderingIntent::Saturation, RenderingIntent::Perceptual] {
+        assert_eq!(RenderingIntent::from_name(intent.name()), Some(*intent));
+    }
+    assert_eq!(RenderingIntent::from_name("Colorimetric"), None);
+}
+
+#[test]
+fn test_dedup_hash_collision() {
+    let image = ImageXObject::new(Px(1), Px(1), ColorSpace::Greyscale, ColorBits::Bit8, false, None, None, vec![0]);
+    let other = ImageXObject { image_data: vec![255], .. image.clone() };
+
+    // the same hash for different images, as if they collided
+    let mut list = XObjectList::new();
+    let first = list.add_xobject_with_hash(XObject::Image(image.clone()), 1);
+    assert_eq!(list.add_xobject_with_hash(XObject::Image(image.clone()), 1).name, first.name);
+    assert_ne!(list.add_xobject_with_hash(XObject::Image(other.clone()), 1).name, first.name);
+
+    // the cache of another page has the first image with the same hash
+    let mut doc = lopdf::Document::with_version("1.4");
+    let mut cache = XObjectCache::new();
+    let mut first_page = XObjectList::new();
+    first_page.add_xobject_with_hash(XObject::Image(image), 1);
+    let first_ids = first_page.into_with_document_and_cache(&mut doc, &mut cache, &mut StreamedXObjects::new());
+    let mut second_page = XObjectList::new();
+    second_page.add_xobject_with_hash(XObject::Image(other), 1);
+    let second_ids = second_page.into_with_document_and_cache(&mut doc, &mut cache, &mut StreamedXObjects::new());
+
+    assert_eq!(doc.objects.len(), 2);
+    assert_ne!(format!("{:?}", first_ids.get(b"X0").unwrap()), format!("{:?}", second_ids.get(b"X0").unwrap()));
+}
+
+#[test]
+fn test_add_form() {
+    let mut list = XObjectList::new();
+    let logo = list.add_form(XObjectIndex(0));
+    assert_eq!(list.add_form(XObjectIndex(0)).name, logo.name);
+    let removed = list.add_form(XObjectIndex(1));
+    assert_ne!(removed.name, logo.name);
+    assert_eq!(list.form(&removed.name), Some(XObjectIndex(1)));
+
+    // the first form is written, the second one was removed by `restore()`
+    let mut doc = lopdf::Document::with_version("1.4");
+    let mut cache = XObjectCache::new();
+    let form_id = doc.add_object(ReusableForm::empty());
+    cache.forms.insert(XObjectIndex(0), form_id);
+    let dict = list.into_with_document_and_cache(&mut doc, &mut cache, &mut StreamedXObjects::new());
+
+    assert_eq!(doc.objects.len(), 2);
+    assert_eq!(dict.get(logo.name.as_bytes()).and_then(lopdf::Object::as_reference).ok(), Some(form_id));
+    assert_ne!(dict.get(removed.name.as_bytes()).and_then(lopdf::Object::as_reference).ok(), Some(form_id));
+}
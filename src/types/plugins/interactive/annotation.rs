@@ -0,0 +1,666 @@
+//! Markup annotations, such as sticky notes, highlights and stamps
+
+use lopdf;
+use lopdf::content::{Content, Operation};
+use std::cell::RefCell;
+use std::iter::FromIterator;
+use std::rc::Weak;
+use time::OffsetDateTime;
+
+use errors::ValidationError;
+use indices::{PdfAnnotationIndex, PdfLayerIndex};
+use types::plugins::interactive::destination::{LinkTarget, NamedDestinations};
+use types::plugins::misc::document_info::to_pdf_time_stamp_metadata;
+use utils::pdf_text_string;
+use {Color, FormXObject, PdfColor, PdfDocument, Pt, Rect};
+
+/// Annotation flag (bit 3): print the annotation when printing the page
+const ANNOTATION_FLAG_PRINT: i64 = 1 << 2;
+
+/// Icon of a sticky note, the viewer decides how the icon looks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoteIcon {
+    Comment,
+    Key,
+    Note,
+    Help,
+    NewParagraph,
+    Paragraph,
+    Insert,
+}
+
+impl From<NoteIcon> for &'static str {
+    fn from(icon: NoteIcon) -> Self {
+        use self::NoteIcon::*;
+        match icon {
+            Comment => "Comment",
+            Key => "Key",
+            Note => "Note",
+            Help => "Help",
+            NewParagraph => "NewParagraph",
+            Paragraph => "Paragraph",
+            Insert => "Insert",
+        }
+    }
+}
+
+/// Style of the border of a link (`/S` in the border style dictionary)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorderStyle {
+    Solid,
+    /// Dashed with 3 point dashes and gaps
+    Dashed,
+    Beveled,
+    Inset,
+    /// Only a line at the bottom of the link
+    Underline,
+}
+
+impl From<BorderStyle> for &'static str {
+    fn from(style: BorderStyle) -> Self {
+        use self::BorderStyle::*;
+        match style {
+            Solid => "S",
+            Dashed => "D",
+            Beveled => "B",
+            Inset => "I",
+            Underline => "U",
+        }
+    }
+}
+
+/// How a link is highlighted while the mouse button is pressed on it (`/H`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HighlightMode {
+    /// No highlighting
+    None,
+    /// Inverts the colors of the link area (the default of most viewers)
+    Invert,
+    /// Inverts the border of the link
+    Outline,
+    /// Shows the link as if it was pushed below the page
+    Push,
+}
+
+impl From<HighlightMode> for &'static str {
+    fn from(mode: HighlightMode) -> Self {
+        use self::HighlightMode::*;
+        match mode {
+            None => "N",
+            Invert => "I",
+            Outline => "O",
+            Push => "P",
+        }
+    }
+}
+
+/// A visible border around a link
+#[derive(Debug, Clone)]
+struct LinkBorder {
+    /// Width of the border in points
+    width: f64,
+    style: BorderStyle,
+    color: Color,
+}
+
+/// A "reference" to an annotation, used to set the optional entries
+/// of the annotation after it was added to a page. Setting them has no effect
+/// after the annotation was removed with `PdfDocumentReference::restore()`.
+#[derive(Debug, Clone)]
+pub struct PdfAnnotationReference {
+    /// A weak reference to the document, for inner mutability
+    pub document: Weak<RefCell<PdfDocument>>,
+    /// The index of the annotation in the document
+    pub annotation: PdfAnnotationIndex,
+}
+
+/// All annotations of a document (except form field widgets, see `AcroForm`)
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AnnotationList {
+    /// Annotations in the order they were added
+    annotations: Vec<Annotation>,
+}
+
+/// A single annotation
+#[derive(Debug, Clone)]
+struct Annotation {
+    /// Index of the page the annotation is on
+    page: usize,
+    /// Position of the annotation on the page
+    rect: Rect,
+    /// Text of the annotation (`/Contents`)
+    contents: Option<String>,
+    /// Author of the annotation (`/T`)
+    author: Option<String>,
+    /// Date of the last modification (`/M`)
+    modified: Option<OffsetDateTime>,
+    /// Index of the layer (on the same page) the annotation belongs to (`/OC`)
+    layer: Option<usize>,
+    /// Whether the annotation was removed by `PdfPageReference::redact()`. It is kept in
+    /// the list, so that the indices of the other annotations don't change.
+    redacted: bool,
+    /// Type of the annotation
+    kind: AnnotationKind,
+}
+
+#[derive(Debug, Clone)]
+enum AnnotationKind {
+    /// Sticky note (`/Text`)
+    Note { icon: NoteIcon },
+    /// Highlighted text (`/Highlight`), the quads are the highlighted areas
+    Highlight { quads: Vec<Rect>, color: Color },
+    /// Rubber stamp (`/Stamp`) with the name of the stamp and its appearance
+    Stamp { name: String, appearance: Box<FormXObject> },
+    /// Clickable area (`/Link`), which jumps to the target. Links have no visible
+    /// border if `border` is `None`, the viewer decides the highlighting if `highlight` is `None`.
+    Link { target: LinkTarget, border: Option<LinkBorder>, highlight: Option<HighlightMode> },
+}
+
+/// Object IDs, that are reserved for the annotations before the pages are written,
+/// see `WidgetIds`
+#[derive(Debug)]
+pub(crate) struct AnnotationIds {
+    /// IDs of the annotations, in the same order as the annotations
+    annotations: Vec<lopdf::ObjectId>,
+    /// References to the annotations on each page
+    pub(crate) pages: Vec<Vec<lopdf::Object>>,
+}
+
+impl AnnotationList {
+
+    /// Returns the number of annotations
+    #[inline]
+    pub(crate) fn len(&self)
+    -> usize
+    {
+        self.annotations.len()
+    }
+
+    /// Removes all annotations after the first `len` annotations, see `PdfDocumentReference::restore()`
+    #[inline]
+    pub(crate) fn truncate(&mut self, len: usize)
+    {
+        self.annotations.truncate(len);
+    }
+
+    /// Adds a sticky note on the page with the index `page`
+    pub(crate) fn add_note(&mut self, page: usize, rect: Rect, contents: String, icon: NoteIcon)
+    -> PdfAnnotationIndex
+    {
+        self.add(Annotation {
+            page,
+            rect,
+            contents: Some(contents),
+            author: None,
+            modified: None,
+            layer: None,
+            redacted: false,
+            kind: AnnotationKind::Note { icon },
+        })
+    }
+
+    /// Adds a highlight on the page with the index `page`. The annotation covers the bounding box of the quads.
+    /// Returns `None` if there are no `quads`.
+    pub(crate) fn add_highlight(&mut self, page: usize, quads: Vec<Rect>, color: Color)
+    -> Option<PdfAnnotationIndex>
+    {
+        let rect = quads.iter().skip(1).fold(*quads.first()?, |bbox, quad| bbox.union(quad));
+
+        Some(self.add(Annotation {
+            page,
+            rect,
+            contents: None,
+            author: None,
+            modified: None,
+            layer: None,
+            redacted: false,
+            kind: AnnotationKind::Highlight { quads, color },
+        }))
+    }
+
+    /// Adds a rubber stamp on the page with the index `page`
+    pub(crate) fn add_stamp(&mut self, page: usize, rect: Rect, appearance: FormXObject, name: String)
+    -> PdfAnnotationIndex
+    {
+        self.add(Annotation {
+            page,
+            rect,
+            contents: None,
+            author: None,
+            modified: None,
+            layer: None,
+            redacted: false,
+            kind: AnnotationKind::Stamp { name, appearance: Box::new(appearance) },
+        })
+    }
+
+    /// Adds a link on the page with the index `page`
+    pub(crate) fn add_link(&mut self, page: usize, rect: Rect, target: LinkTarget)
+    -> PdfAnnotationIndex
+    {
+        self.add(Annotation {
+            page,
+            rect,
+            contents: None,
+            author: None,
+            modified: None,
+            layer: None,
+            redacted: false,
+            kind: AnnotationKind::Link { target, border: None, highlight: None },
+        })
+    }
+
+    /// Removes the annotations on the page with the index `page` that overlap `area`
+    pub(crate) fn redact(&mut self, page: usize, area: &Rect)
+    {
+        for annotation in &mut self.annotations {
+            if annotation.page == page && annotation.rect.overlaps(area) {
+                annotation.redacted = true;
+            }
+        }
+    }
+
+    /// Moves the annotations on the page with the index `page` by `dx` and `dy`
+    pub(crate) fn translate(&mut self, page: usize, dx: Pt, dy: Pt)
+    {
+        for annotation in self.annotations.iter_mut().filter(|a| a.page == page) {
+            annotation.rect = annotation.rect.translated(dx, dy);
+            if let AnnotationKind::Highlight { ref mut quads, .. } = annotation.kind {
+                for quad in quads.iter_mut() {
+                    *quad = quad.translated(dx, dy);
+                }
+            }
+        }
+    }
+
+    /// Checks that the targets of the links exist, `page_count` is the number of pages in the document
+    pub(crate) fn validate(&self, destinations: &NamedDestinations, page_count: usize, errors: &mut Vec<ValidationError>)
+    {
+        for annotation in &self.annotations {
+            if let AnnotationKind::Link { ref target, .. } = annotation.kind {
+                target.validate(destinations, page_count, errors);
+            }
+        }
+    }
+
+    #[inline]
+    fn add(&mut self, annotation: Annotation)
+    -> PdfAnnotationIndex
+    {
+        self.annotations.push(annotation);
+        PdfAnnotationIndex(self.annotations.len() - 1)
+    }
+
+    /// Allocates the object IDs of all annotations, `page_count` is the number of pages in the document
+    pub(crate) fn reserve_ids(&self, doc: &mut lopdf::Document, page_count: usize)
+    -> AnnotationIds
+    {
+        let mut ids = AnnotationIds { annotations: Vec::with_capacity(self.annotations.len()), pages: vec![Vec::new(); page_count] };
+
+        for annotation in self.annotations.iter().filter(|a| !a.redacted) {
+            let id = doc.new_object_id();
+            ids.pages[annotation.page].push(lopdf::Object::Reference(id));
+            ids.annotations.push(id);
+        }
+
+        ids
+    }
+
+    /// Adds the annotations to the document. `page_ids` are the object IDs
+    /// of the pages, `layer_ocgs` the optional content groups of the layers
+    /// of each page, in the same order as the pages.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, ids: AnnotationIds, page_ids: &[lopdf::ObjectId], layer_ocgs: &[Vec<lopdf::ObjectId>])
+    {
+        use lopdf::Object::*;
+
+        for (annotation, id) in self.annotations.into_iter().filter(|a| !a.redacted).zip(ids.annotations) {
+
+            let mut dict = lopdf::Dictionary::from_iter(vec![
+                ("Type", Name("Annot".into())),
+                ("Rect", annotation.rect.into()),
+                ("P", Reference(page_ids[annotation.page])),
+                ("F", Integer(ANNOTATION_FLAG_PRINT)),
+            ]);
+
+            if let Some(contents) = annotation.contents {
+                dict.set("Contents", pdf_text_string(&contents));
+            }
+
+            if let Some(author) = annotation.author {
+                dict.set("T", pdf_text_string(&author));
+            }
+
+            if let Some(modified) = annotation.modified {
+                dict.set("M", String(to_pdf_time_stamp_metadata(modified).into_bytes(), lopdf::StringFormat::Literal));
+            }
+
+            let page_ocgs = layer_ocgs.get(annotation.page);
+            if let Some(ocg) = annotation.layer.and_then(|layer| page_ocgs.and_then(|ocgs| ocgs.get(layer))) {
+                dict.set("OC", Reference(*ocg));
+            }
+
+            match annotation.kind {
+                AnnotationKind::Note { icon } => {
+                    let icon: &'static str = icon.into();
+                    dict.set("Subtype", Name("Text".into()));
+                    dict.set("Name", Name(icon.into()));
+                },
+                AnnotationKind::Highlight { quads, color } => {
+                    let appearance = doc.add_object(highlight_appearance(annotation.rect, &quads, color.clone()));
+                    let quad_points = quads.iter().flat_map(|q| vec![
+                        q.llx.into(), q.ury.into(), q.urx.into(), q.ury.into(),
+                        q.llx.into(), q.lly.into(), q.urx.into(), q.lly.into(),
+                    ]).collect();
+                    dict.set("Subtype", Name("Highlight".into()));
+                    dict.set("QuadPoints", Array(quad_points));
+                    dict.set("C", Array(color.into_vec().into_iter().map(Real).collect()));
+                    dict.set("AP", Dictionary(lopdf::Dictionary::from_iter(vec![("N", Reference(appearance))])));
+                },
+                AnnotationKind::Stamp { name, appearance } => {
+                    // the appearance is drawn relative to the lower left corner of the stamp
+                    let mut stream: lopdf::Stream = (*appearance).into();
+                    stream.dict.set("BBox", vec![0.into(), 0.into(), annotation.rect.width().into(), annotation.rect.height().into()]);
+                    let appearance = doc.add_object(stream);
+                    dict.set("Subtype", Name("Stamp".into()));
+                    dict.set("Name", Name(name.into_bytes()));
+                    dict.set("AP", Dictionary(lopdf::Dictionary::from_iter(vec![("N", Reference(appearance))])));
+                },
+                AnnotationKind::Link { target, border, highlight } => {
+                    dict.set("Subtype", Name("Link".into()));
+                    match border {
+                        Some(border) => {
+                            let style: &'static str = border.style.into();
+                            let mut border_style = lopdf::Dictionary::from_iter(vec![
+                                ("W", Real(border.width)),
+                                ("S", Name(style.into())),
+                            ]);
+                            if border.style == BorderStyle::Dashed {
+                                border_style.set("D", Array(vec![Integer(3)]));
+                            }
+                            // `/Border` for viewers that don't support `/BS`
+                            dict.set("Border", Array(vec![Integer(0), Integer(0), Real(border.width)]));
+                            dict.set("BS", Dictionary(border_style));
+                            dict.set("C", Array(border.color.into_vec().into_iter().map(Real).collect()));
+                        },
+                        None => dict.set("Border", Array(vec![Integer(0), Integer(0), Integer(0)])),
+                    }
+                    if let Some(highlight) = highlight {
+                        let highlight: &'static str = highlight.into();
+                        dict.set("H", Name(highlight.into()));
+                    }
+                    if let Some((key, value)) = target.into_entry(page_ids) {
+                        dict.set(key, value);
+                    }
+                },
+            }
+
+            doc.objects.insert(id, Dictionary(dict));
+        }
+    }
+}
+
+impl PdfAnnotationReference {
+
+    /// Sets the author of the annotation, shown as the title of the popup in most viewers
+    #[inline]
+    pub fn set_author<S>(&self, author: S) where S: Into<String>
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        if let Some(annotation) = doc.annotations.annotations.get_mut(self.annotation.0) {
+            annotation.author = Some(author.into());
+        }
+    }
+
+    /// Sets the date and time when the annotation was last modified
+    #[inline]
+    pub fn set_modified(&self, modified: OffsetDateTime)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        if let Some(annotation) = doc.annotations.annotations.get_mut(self.annotation.0) {
+            annotation.modified = Some(modified);
+        }
+    }
+
+    /// Makes the annotation a member of the optional content group of a layer on the same
+    /// page: it is only shown (and printed) while the layer is visible. `None` shows
+    /// the annotation independent of the layers again.
+    #[inline]
+    pub fn set_layer(&self, layer: Option<PdfLayerIndex>)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        if let Some(annotation) = doc.annotations.annotations.get_mut(self.annotation.0) {
+            annotation.layer = layer.map(|l| l.0);
+        }
+    }
+
+    /// Draws a border around a link, `width` is in points. Links have no visible border by default.
+    /// Has no effect on other annotations.
+    #[inline]
+    pub fn set_border(&self, width: f64, style: BorderStyle, color: Color)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        if let Some(Annotation { kind: AnnotationKind::Link { ref mut border, .. }, .. }) = doc.annotations.annotations.get_mut(self.annotation.0) {
+            *border = Some(LinkBorder { width, style, color });
+        }
+    }
+
+    /// Sets how a link is highlighted when it is clicked. Has no effect on other annotations.
+    #[inline]
+    pub fn set_highlight(&self, mode: HighlightMode)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = doc.borrow_mut();
+        if let Some(Annotation { kind: AnnotationKind::Link { ref mut highlight, .. }, .. }) = doc.annotations.annotations.get_mut(self.annotation.0) {
+            *highlight = Some(mode);
+        }
+    }
+}
+
+/// Appearance of a highlight: the quads are filled with the color, which is multiplied with the
+/// content below it (so that the highlighted text stays readable)
+fn highlight_appearance(rect: Rect, quads: &[Rect], color: Color)
+-> lopdf::Stream
+{
+    use lopdf::Object::*;
+
+    let mut operations = vec![
+        Operation::new("gs", vec![Name("GS0".into())]),
+        PdfColor::FillColor(color).into(),
+    ];
+    for quad in quads {
+        operations.push(Operation::new("re", vec![quad.llx.into(), quad.lly.into(), quad.width().into(), quad.height().into()]));
+    }
+    operations.push(Operation::new("f", vec![]));
+
+    let graphics_state = lopdf::Dictionary::from_iter(vec![("BM", Name("Multiply".into()))]);
+    let resources = lopdf::Dictionary::from_iter(vec![
+        ("ExtGState", Dictionary(lopdf::Dictionary::from_iter(vec![("GS0", Dictionary(graphics_state))]))),
+    ]);
+
+    // the bounding box is in page coordinates, so the quads don't have to be moved
+    let dict = lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("XObject".into())),
+        ("Subtype", Name("Form".into())),
+        ("BBox", rect.into()),
+        ("Resources", Dictionary(resources)),
+    ]);
+    // writing into a Vec<u8> can't fail
+    lopdf::Stream::new(dict, Content { operations }.encode().unwrap())
+}
+
+#[test]
+fn test_link_border() {
+    use {PdfDocument, Mm, Pt, Rgb};
+    use types::plugins::interactive::destination::LinkTarget;
+
+    let (doc, page, _) = PdfDocument::new("link border", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let rect = Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(100.0), ury: Pt(20.0) };
+    let link = page.add_link(rect, LinkTarget::Named("chapter".into()));
+    let note = page.add_note(rect, "note", NoteIcon::Note);
+
+    link.set_border(0.5, BorderStyle::Underline, Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None)));
+    link.set_highlight(HighlightMode::Push);
+    note.set_highlight(HighlightMode::Push);
+
+    let inner = doc.document.borrow();
+    match inner.annotations.annotations[link.annotation.0].kind {
+        AnnotationKind::Link { ref border, highlight, .. } => {
+            let border = border.as_ref().unwrap();
+            assert_eq!(border.width, 0.5);
+            assert_eq!(border.style, BorderStyle::Underline);
+            assert_eq!(highlight, Some(HighlightMode::Push));
+        },
+        _ => panic!("not a link"),
+    }
+    let style: &'static str = BorderStyle::Dashed.into();
+    assert_eq!(style, "D");
+}
+
+#[test]
+fn test_redact_annotations() {
+    use {PdfDocument, Mm, Pt};
+
+    let (doc, page, _) = PdfDocument::new("redact annotations", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let inside = page.add_note(Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(30.0), ury: Pt(30.0) }, "secret", NoteIcon::Note);
+    let outside = page.add_note(Rect { llx: Pt(200.0), lly: Pt(200.0), urx: Pt(220.0), ury: Pt(220.0) }, "public", NoteIcon::Note);
+
+    page.redact(Rect { llx: Pt(0.0), lly: Pt(0.0), urx: Pt(100.0), ury: Pt(100.0) });
+
+    let inner = doc.document.borrow();
+    assert!(inner.annotations.annotations[inside.annotation.0].redacted);
+    assert!(!inner.annotations.annotations[outside.annotation.0].redacted);
+}
+
+#[test]
+fn test_annotation_after_restore() {
+    use {PdfDocument, Mm, Pt, Rgb};
+
+    let (doc, page, _) = PdfDocument::new("restore", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let color = Color::Rgb(Rgb::new(1.0, 1.0, 0.0, None));
+    assert!(page.add_highlight(&[], color.clone()).is_none());
+    assert_eq!(doc.document.borrow().annotations.len(), 0);
+
+    let checkpoint = doc.checkpoint();
+    let rect = Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(100.0), ury: Pt(20.0) };
+    let highlight = page.add_highlight(&[rect], color).unwrap();
+    doc.restore(checkpoint);
+
+    // the annotation doesn't exist anymore, setting its entries does nothing
+    highlight.set_author("Reviewer");
+    highlight.set_modified(OffsetDateTime::unix_epoch());
+    highlight.set_layer(None);
+    assert_eq!(doc.document.borrow().annotations.len(), 0);
+}
+
+#[test]
+fn test_shrink_to_content_moves_annotations() {
+    use {PdfDocument, Line, Mm, Point, Rgb};
+
+    let (doc, page, layer) = PdfDocument::new("shrink", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    page.get_layer(layer).add_shape(Line {
+        points: vec![(Point { x: Pt(100.0), y: Pt(200.0) }, false), (Point { x: Pt(150.0), y: Pt(260.0) }, false)],
+        is_closed: false,
+        has_fill: false,
+        has_stroke: true,
+        is_clipping_path: false,
+    });
+    let rect = Rect { llx: Pt(110.0), lly: Pt(210.0), urx: Pt(130.0), ury: Pt(220.0) };
+    let color = Color::Rgb(Rgb::new(1.0, 1.0, 0.0, None));
+    let highlight = page.add_highlight(&[rect], color).unwrap();
+
+    let bbox = page.bounding_box().unwrap();
+    page.shrink_to_content(Pt(10.0).into());
+
+    // the annotation stays at the same position relative to the content
+    let (dx, dy) = (Pt(10.0) - bbox.llx, Pt(10.0) - bbox.lly);
+    let moved = rect.translated(dx, dy);
+    let inner = doc.document.borrow();
+    let annotation = &inner.annotations.annotations[highlight.annotation.0];
+    assert_eq!(annotation.rect, moved);
+    match annotation.kind {
+        AnnotationKind::Highlight { ref quads, .. } => assert_eq!(quads, &vec![moved]),
+        _ => panic!("not a highlight"),
+    }
+}
+
+#[test]
+fn test_stamp_annotation() {
+    use {PdfDocument, Mm, Pt, CurTransMat};
+    use types::plugins::graphics::xobject::FormType;
+
+    let (doc, page, _) = PdfDocument::new("stamp", Mm(210.0), Mm(297.0), "Layer 1");
+    let appearance = FormXObject {
+        form_type: FormType::Type1,
+        bytes: b"0 0 1 rg 0 0 80 30 re f".to_vec(),
+        matrix: Some(CurTransMat::Identity),
+        resources: None,
+        group: None,
+        ref_dict: None,
+        metadata: None,
+        piece_info: None,
+        last_modified: None,
+        struct_parent: None,
+        struct_parents: None,
+        opi: None,
+        oc: None,
+        name: None,
+    };
+    let rect = Rect { llx: Pt(100.0), lly: Pt(200.0), urx: Pt(180.0), ury: Pt(230.0) };
+    doc.get_page(page).add_stamp_annotation(rect, appearance, "Approved");
+
+    let annotations = ::std::mem::replace(&mut doc.document.borrow_mut().annotations, AnnotationList::default());
+    let mut pdf = lopdf::Document::with_version("1.3");
+    let page_ids = vec![pdf.new_object_id()];
+    let ids = annotations.reserve_ids(&mut pdf, 1);
+    let id = ids.annotations[0];
+    annotations.into_with_document(&mut pdf, ids, &page_ids, &[Vec::new()]);
+
+    let dict = pdf.get_object(id).unwrap().as_dict().unwrap();
+    assert_eq!(dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Stamp");
+    assert_eq!(dict.get(b"Name").unwrap().as_name().unwrap(), b"Approved");
+
+    // the appearance stays a form, sized like the stamp
+    let normal = dict.get(b"AP").unwrap().as_dict().unwrap().get(b"N").unwrap().as_reference().unwrap();
+    let stream = pdf.get_object(normal).unwrap().as_stream().unwrap();
+    assert_eq!(stream.dict.get(b"Subtype").unwrap().as_name().unwrap(), b"Form");
+    assert_eq!(format!("{:?}", stream.dict.get(b"BBox").unwrap()), format!("{:?}", lopdf::Object::Array(vec![0.into(), 0.into(), 80.0.into(), 30.0.into()])));
+    assert_eq!(stream.content, b"0 0 1 rg 0 0 80 30 re f".to_vec());
+}
+
+#[test]
+fn test_annotation_layer() {
+    use {PdfDocument, Mm, Pt};
+    use indices::PdfLayerIndex;
+
+    let (doc, page, _) = PdfDocument::new("layers", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page);
+    let rect = Rect { llx: Pt(10.0), lly: Pt(10.0), urx: Pt(30.0), ury: Pt(30.0) };
+    page.add_note(rect, "on the markup layer", NoteIcon::Note).set_layer(Some(PdfLayerIndex(1)));
+    page.add_note(rect, "always visible", NoteIcon::Note);
+    let removed = page.add_note(rect, "visible again", NoteIcon::Note);
+    removed.set_layer(Some(PdfLayerIndex(1)));
+    removed.set_layer(None);
+    page.add_note(rect, "missing layer", NoteIcon::Note).set_layer(Some(PdfLayerIndex(5)));
+
+    let annotations = ::std::mem::replace(&mut doc.document.borrow_mut().annotations, AnnotationList::default());
+    let mut pdf = lopdf::Document::with_version("1.5");
+    let page_ids = vec![pdf.new_object_id()];
+    let ocgs = vec![vec![pdf.new_object_id(), pdf.new_object_id()]];
+    let ids = annotations.reserve_ids(&mut pdf, 1);
+    let annotation_ids = ids.annotations.clone();
+    annotations.into_with_document(&mut pdf, ids, &page_ids, &ocgs);
+
+    // the annotation refers to the optional content group of its layer
+    let oc = annotation_ids.iter()
+        .map(|id| pdf.get_object(*id).unwrap().as_dict().unwrap().get(b"OC").ok().map(|oc| oc.as_reference().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(oc, vec![Some(ocgs[0][1]), None, None, None]);
+}
@@ -0,0 +1,128 @@
+//! Embedded files (attachments) and portfolios
+//!
+//! Attachments are registered under their file name in the `/EmbeddedFiles` name tree of the
+//! document and show up in the attachments panel of the viewer. A portfolio additionally has
+//! a `/Collection` dictionary: the viewer lists the attached files instead of the pages, the
+//! pages are only shown as a cover sheet (and by viewers that don't support portfolios).
+
+use lopdf;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+use utils::pdf_text_string;
+
+/// Files embedded into a document
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Attachments {
+    /// Contents of the files, sorted by name as required for the name tree
+    files: BTreeMap<String, Vec<u8>>,
+    /// Whether the document is a portfolio, see `PdfDocumentReference::into_portfolio()`
+    pub(crate) portfolio: bool,
+}
+
+impl Attachments {
+
+    /// Adds a file, replacing an existing file with the same name
+    #[inline]
+    pub(crate) fn insert(&mut self, name: String, data: Vec<u8>)
+    {
+        self.files.insert(name, data);
+    }
+
+    /// Returns the number of attached files
+    #[inline]
+    pub(crate) fn len(&self)
+    -> usize
+    {
+        self.files.len()
+    }
+
+    /// Returns the total size of the attached files in bytes
+    pub(crate) fn data_size(&self)
+    -> usize
+    {
+        self.files.values().map(|data| data.len()).sum()
+    }
+
+    /// Adds the embedded files and their name tree to the document and returns the ID of the
+    /// tree. Returns `None` if there are no files.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document)
+    -> Option<lopdf::ObjectId>
+    {
+        use lopdf::Object::*;
+
+        if self.files.is_empty() {
+            return None;
+        }
+
+        let mut names = Vec::with_capacity(self.files.len() * 2);
+        for (name, data) in self.files {
+            let params = lopdf::Dictionary::from_iter(vec![("Size", Integer(data.len() as i64))]);
+            let stream_dict = lopdf::Dictionary::from_iter(vec![
+                ("Type", Name("EmbeddedFile".into())),
+                ("Params", Dictionary(params)),
+            ]);
+            let stream_id = doc.add_object(lopdf::Stream::new(stream_dict, data));
+
+            let file_spec = lopdf::Dictionary::from_iter(vec![
+                ("Type", Name("Filespec".into())),
+                ("F", pdf_text_string(&name)),
+                ("UF", pdf_text_string(&name)),
+                ("EF", Dictionary(lopdf::Dictionary::from_iter(vec![("F", Reference(stream_id))]))),
+            ]);
+            let file_spec_id = doc.add_object(file_spec);
+
+            names.push(String(name.into_bytes(), lopdf::StringFormat::Literal));
+            names.push(Reference(file_spec_id));
+        }
+
+        // all names are in the root node of the tree, which is valid for any number of names
+        let tree = lopdf::Dictionary::from_iter(vec![("Names", Array(names))]);
+        Some(doc.add_object(tree))
+    }
+}
+
+/// The `/Collection` dictionary of a portfolio: the files are shown in a list (`/View /D`)
+/// with their name and size, sorted by name
+pub(crate) fn collection()
+-> lopdf::Dictionary
+{
+    use lopdf::Object::*;
+
+    let field = |subtype: &str, name: &str, order: i64| Dictionary(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("CollectionField".into())),
+        ("Subtype", Name(subtype.into())),
+        ("N", pdf_text_string(name)),
+        ("O", Integer(order)),
+    ]));
+
+    let schema = lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("CollectionSchema".into())),
+        ("FileName", field("F", "Name", 1)),
+        ("Size", field("Size", "Size", 2)),
+    ]);
+    let sort = lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("CollectionSort".into())),
+        ("S", Name("FileName".into())),
+        ("A", Boolean(true)),
+    ]);
+
+    lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("Collection".into())),
+        ("Schema", Dictionary(schema)),
+        ("Sort", Dictionary(sort)),
+        ("View", Name("D".into())),
+    ])
+}
+
+#[test]
+fn test_attachments() {
+    let mut attachments = Attachments::default();
+    attachments.insert("b.txt".into(), vec![0; 10]);
+    attachments.insert("a.csv".into(), vec![0; 5]);
+    attachments.insert("b.txt".into(), vec![0; 20]);
+
+    assert_eq!(attachments.len(), 2);
+    assert_eq!(attachments.data_size(), 25);
+    assert_eq!(attachments.files.keys().collect::<Vec<_>>(), vec!["a.csv", "b.txt"]);
+}
@@ -0,0 +1,306 @@
+//! Bookmarks (the document outline shown in the sidebar of viewers) and
+//! the table of contents generated from them
+
+use lopdf;
+use std::iter::FromIterator;
+
+use errors::ValidationError;
+use indices::PdfBookmarkIndex;
+use types::plugins::interactive::destination::{LinkTarget, NamedDestinations};
+use utils::pdf_text_string;
+use {Mm, Pt, Rect};
+
+/// Margin of the table of contents on all sides of the page
+const TOC_MARGIN: Mm = Mm(20.0);
+/// Indentation of each nesting level of the table of contents
+const TOC_INDENT: Mm = Mm(8.0);
+/// Distance of the baselines of two entries, relative to the font size
+const TOC_LINE_HEIGHT: f64 = 1.6;
+
+/// All bookmarks of a document
+#[derive(Debug, Default, Clone)]
+pub(crate) struct BookmarkList {
+    /// Bookmarks in the order they were added, children come after their parent
+    bookmarks: Vec<Bookmark>,
+}
+
+#[derive(Debug, Clone)]
+struct Bookmark {
+    title: String,
+    target: LinkTarget,
+    /// Index of the parent bookmark, `None` for top level bookmarks
+    parent: Option<usize>,
+}
+
+/// Entry of the table of contents, before it is laid out
+#[derive(Debug, Clone)]
+pub(crate) struct TocEntry {
+    /// Nesting level, 0 for top level bookmarks
+    pub(crate) level: usize,
+    pub(crate) title: String,
+    /// Page number (1-based), `None` for targets in other documents
+    pub(crate) page_number: Option<usize>,
+    pub(crate) target: LinkTarget,
+}
+
+/// Laid out line of the table of contents. The texts are positioned at their baseline.
+#[derive(Debug, Clone)]
+pub(crate) struct TocLine {
+    pub(crate) title: (Pt, Pt, String),
+    /// Leader dots between the title and the page number
+    pub(crate) leader: Option<(Pt, Pt, String)>,
+    pub(crate) page_number: Option<(Pt, Pt, String)>,
+    /// Clickable area of the line
+    pub(crate) rect: Rect,
+    pub(crate) target: LinkTarget,
+}
+
+impl BookmarkList {
+
+    /// Adds a bookmark, `parent` is `None` for a top level bookmark
+    pub(crate) fn add(&mut self, title: String, target: LinkTarget, parent: Option<PdfBookmarkIndex>)
+    -> PdfBookmarkIndex
+    {
+        self.bookmarks.push(Bookmark { title, target, parent: parent.map(|p| p.0) });
+        PdfBookmarkIndex(self.bookmarks.len() - 1)
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self)
+    -> bool
+    {
+        self.bookmarks.is_empty()
+    }
+
+    /// Returns the number of bookmarks
+    #[inline]
+    pub(crate) fn len(&self)
+    -> usize
+    {
+        self.bookmarks.len()
+    }
+
+    /// Indices of the children of a bookmark (or of the top level bookmarks), in the order they were added
+    fn children(&self, parent: Option<usize>)
+    -> Vec<usize>
+    {
+        (0..self.bookmarks.len()).filter(|idx| self.bookmarks[*idx].parent == parent).collect()
+    }
+
+    /// Number of all descendants of a bookmark
+    fn descendants(&self, parent: Option<usize>)
+    -> usize
+    {
+        self.children(parent).into_iter().map(|child| 1 + self.descendants(Some(child))).sum()
+    }
+
+    /// Bookmark indices with their nesting level, depth-first in the order they are shown
+    fn walk(&self, parent: Option<usize>, level: usize, out: &mut Vec<(usize, usize)>)
+    {
+        for child in self.children(parent) {
+            out.push((child, level));
+            self.walk(Some(child), level + 1, out);
+        }
+    }
+
+    /// Checks that the targets of the bookmarks exist, `page_count` is the number of pages in the document
+    pub(crate) fn validate(&self, destinations: &NamedDestinations, page_count: usize, errors: &mut Vec<ValidationError>)
+    {
+        for bookmark in &self.bookmarks {
+            bookmark.target.validate(destinations, page_count, errors);
+        }
+    }
+
+    /// Returns the entries for a table of contents, page numbers of named destinations are looked up in `destinations`
+    pub(crate) fn toc_entries(&self, destinations: &NamedDestinations)
+    -> Vec<TocEntry>
+    {
+        let mut order = Vec::new();
+        self.walk(None, 0, &mut order);
+
+        order.into_iter().map(|(idx, level)| {
+            let bookmark = &self.bookmarks[idx];
+            let page_number = match bookmark.target {
+                LinkTarget::Page { page, .. } => Some(page.0 + 1),
+                LinkTarget::Named(ref name) => destinations.page(name).map(|page| page + 1),
+                LinkTarget::Remote { .. } => None,
+            };
+            TocEntry { level, title: bookmark.title.clone(), page_number, target: bookmark.target.clone() }
+        }).collect()
+    }
+
+    /// Adds the outline to the document and returns the ID of its root (for the `/Outlines` of the
+    /// catalog). `page_ids` are the object IDs of the pages, in the same order as the pages.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, page_ids: &[lopdf::ObjectId])
+    -> lopdf::ObjectId
+    {
+        use lopdf::Object::*;
+
+        let root_id = doc.new_object_id();
+        let ids: Vec<lopdf::ObjectId> = self.bookmarks.iter().map(|_| doc.new_object_id()).collect();
+        let id = |idx: Option<usize>| idx.map(|idx| ids[idx]).unwrap_or(root_id);
+
+        // first and last child, and the number of descendants (all bookmarks are open)
+        let tree_entries = |dict: &mut lopdf::Dictionary, parent: Option<usize>| {
+            let children = self.children(parent);
+            if let (Some(first), Some(last)) = (children.first(), children.last()) {
+                dict.set("First", Reference(ids[*first]));
+                dict.set("Last", Reference(ids[*last]));
+                dict.set("Count", Integer(self.descendants(parent) as i64));
+            }
+        };
+
+        let mut root = lopdf::Dictionary::from_iter(vec![("Type", Name("Outlines".into()))]);
+        tree_entries(&mut root, None);
+
+        for (idx, bookmark) in self.bookmarks.iter().enumerate() {
+            let mut dict = lopdf::Dictionary::from_iter(vec![
+                ("Title", pdf_text_string(&bookmark.title)),
+                ("Parent", Reference(id(bookmark.parent))),
+            ]);
+
+            let siblings = self.children(bookmark.parent);
+            let position = siblings.iter().position(|s| *s == idx).unwrap();
+            if position > 0 {
+                dict.set("Prev", Reference(ids[siblings[position - 1]]));
+            }
+            if let Some(next) = siblings.get(position + 1) {
+                dict.set("Next", Reference(ids[*next]));
+            }
+
+            tree_entries(&mut dict, Some(idx));
+
+            if let Some((key, value)) = bookmark.target.clone().into_entry(page_ids) {
+                dict.set(key, value);
+            }
+
+            doc.objects.insert(ids[idx], Dictionary(dict));
+        }
+
+        doc.objects.insert(root_id, Dictionary(root));
+        root_id
+    }
+}
+
+/// Lays out a table of contents on a page of the given size: the titles are indented by
+/// their nesting level, the page numbers are right-aligned and connected with leader dots.
+/// `text_width` measures a text in the font size. Entries that don't fit on the page are left out.
+pub(crate) fn layout_toc<F>(entries: Vec<TocEntry>, page_width: Pt, page_height: Pt, font_size: f64, text_width: F)
+-> Vec<TocLine> where F: Fn(&str) -> Pt
+{
+    let margin: Pt = TOC_MARGIN.into();
+    let indent: Pt = TOC_INDENT.into();
+    let line_height = Pt(font_size * TOC_LINE_HEIGHT);
+    // space between the dots and the texts
+    let gap = Pt(font_size * 0.5);
+    let dot_width = text_width(".");
+
+    let mut lines = Vec::new();
+    let mut baseline = page_height - margin - Pt(font_size);
+
+    for entry in entries {
+        if baseline < margin {
+            break;
+        }
+
+        let title_x = margin + indent * entry.level as f64;
+        let title_end = title_x + text_width(&entry.title);
+        let right = page_width - margin;
+
+        let page_number = entry.page_number.map(|number| {
+            let number = number.to_string();
+            (right - text_width(&number), baseline, number)
+        });
+
+        let leader = page_number.as_ref().and_then(|&(number_x, _, _)| {
+            let space = (number_x - gap) - (title_end + gap);
+            let dots = if dot_width.0 > 0.0 { (space.0 / dot_width.0).floor() } else { 0.0 };
+            if dots < 1.0 {
+                None
+            } else {
+                Some((number_x - gap - dot_width * dots, baseline, ".".repeat(dots as usize)))
+            }
+        });
+
+        // the clickable area covers the whole line, from the descent to the top of the line
+        let rect = Rect {
+            llx: title_x,
+            lly: baseline - Pt(font_size * (TOC_LINE_HEIGHT - 1.0)),
+            urx: right,
+            ury: baseline + Pt(font_size),
+        };
+
+        lines.push(TocLine { title: (title_x, baseline, entry.title), leader, page_number, rect, target: entry.target });
+        baseline -= line_height;
+    }
+
+    lines
+}
+
+#[test]
+fn test_bookmark_tree() {
+    use indices::PdfPageIndex;
+    use Point;
+
+    let target = |page| LinkTarget::Page { page: PdfPageIndex(page), position: Point::from_mm(10.0, 280.0) };
+    let mut bookmarks = BookmarkList::default();
+    let first = bookmarks.add("Introduction".into(), target(1), None);
+    let second = bookmarks.add("Usage".into(), target(2), None);
+    bookmarks.add("Installation".into(), target(1), Some(first));
+    let nested = bookmarks.add("Options".into(), LinkTarget::Named("options".into()), Some(second));
+    bookmarks.add("Defaults".into(), LinkTarget::Remote { file: "other.pdf".into(), name: "defaults".into() }, Some(nested));
+
+    assert_eq!(bookmarks.children(None), vec![0, 1]);
+    assert_eq!(bookmarks.descendants(None), 5);
+    assert_eq!(bookmarks.descendants(Some(1)), 2);
+
+    let mut destinations = NamedDestinations::default();
+    destinations.insert("options".into(), 3, Point::from_mm(10.0, 100.0));
+    let entries = bookmarks.toc_entries(&destinations);
+    let entries: Vec<(usize, &str, Option<usize>)> = entries.iter().map(|e| (e.level, e.title.as_str(), e.page_number)).collect();
+    assert_eq!(entries, vec![
+        (0, "Introduction", Some(2)),
+        (1, "Installation", Some(2)),
+        (0, "Usage", Some(3)),
+        (1, "Options", Some(4)),
+        (2, "Defaults", None),
+    ]);
+}
+
+#[test]
+fn test_layout_toc() {
+    use indices::PdfPageIndex;
+    use Point;
+
+    let entry = |level, title: &str, page_number| TocEntry {
+        level,
+        title: title.into(),
+        page_number,
+        target: LinkTarget::Page { page: PdfPageIndex(0), position: Point::from_mm(0.0, 297.0) },
+    };
+    // every character is 5 pt wide
+    let width = |text: &str| Pt(text.len() as f64 * 5.0);
+    let entries = vec![entry(0, "Intro", Some(2)), entry(1, "Details", Some(12)), entry(0, "Appendix", None)];
+    let lines = layout_toc(entries, Pt(300.0), Pt(400.0), 10.0, width);
+
+    assert_eq!(lines.len(), 3);
+    let margin: Pt = TOC_MARGIN.into();
+    let indent: Pt = TOC_INDENT.into();
+    assert_eq!(lines[0].title.0, margin);
+    assert_eq!(lines[1].title.0, margin + indent);
+    assert_eq!(lines[1].title.1, lines[0].title.1 - Pt(16.0));
+
+    // the page number is right-aligned, the dots fill the space in between
+    let (number_x, _, ref number) = *lines[1].page_number.as_ref().unwrap();
+    assert_eq!(number_x, Pt(300.0) - margin - Pt(10.0));
+    assert_eq!(number, "12");
+    let (dots_x, _, ref dots) = *lines[1].leader.as_ref().unwrap();
+    assert!(dots_x >= margin + indent + Pt(35.0 + 5.0));
+    assert!((dots_x + Pt(dots.len() as f64 * 5.0) - (number_x - Pt(5.0))).0.abs() < 1e-6);
+
+    assert!(lines[2].page_number.is_none() && lines[2].leader.is_none());
+
+    // entries below the bottom margin are left out
+    let many = (0..100).map(|i| entry(0, "Chapter", Some(i))).collect();
+    assert!(layout_toc(many, Pt(300.0), Pt(400.0), 10.0, width).len() < 100);
+}
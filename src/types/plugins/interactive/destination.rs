@@ -0,0 +1,144 @@
+//! Named destinations and the targets of links
+//!
+//! A named destination is a position on a page, which is registered under a name in the
+//! `/Dests` name tree of the document. Links that jump to a name instead of a page stay
+//! valid when pages are reordered, and other documents can link to the name.
+
+use lopdf;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+use errors::ValidationError;
+use indices::PdfPageIndex;
+use utils::pdf_text_string;
+use Point;
+
+/// Target of a link, see `PdfPageReference::add_link()`
+#[derive(Debug, Clone)]
+pub enum LinkTarget {
+    /// Position on a page of this document
+    Page { page: PdfPageIndex, position: Point },
+    /// Named destination of this document, see `PdfDocumentReference::add_named_destination()`
+    Named(String),
+    /// Named destination in another PDF file, `file` is the path of the file
+    /// (relative to this document)
+    Remote { file: String, name: String },
+}
+
+/// Named destinations of a document
+#[derive(Debug, Default, Clone)]
+pub(crate) struct NamedDestinations {
+    /// Page index and position, sorted by name as required for the name tree
+    destinations: BTreeMap<String, (usize, Point)>,
+}
+
+impl NamedDestinations {
+
+    /// Adds a destination, replacing an existing destination with the same name
+    #[inline]
+    pub(crate) fn insert(&mut self, name: String, page: usize, position: Point)
+    {
+        self.destinations.insert(name, (page, position));
+    }
+
+    /// Returns whether a destination with this name exists
+    #[inline]
+    pub(crate) fn contains(&self, name: &str)
+    -> bool
+    {
+        self.destinations.contains_key(name)
+    }
+
+    /// Returns the index of the page of a destination
+    #[inline]
+    pub(crate) fn page(&self, name: &str)
+    -> Option<usize>
+    {
+        self.destinations.get(name).map(|(page, _)| *page)
+    }
+
+    /// Returns the names and pages of the destinations, whose page doesn't exist in a
+    /// document with `page_count` pages
+    pub(crate) fn missing_pages(&self, page_count: usize)
+    -> Vec<(String, usize)>
+    {
+        self.destinations.iter().filter(|(_, (page, _))| *page >= page_count).map(|(name, (page, _))| (name.clone(), *page)).collect()
+    }
+
+    /// Adds the name tree of the destinations to the document and returns its ID. `page_ids` are the
+    /// object IDs of the pages, in the same order as the pages. Returns `None` if there are no destinations.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, page_ids: &[lopdf::ObjectId])
+    -> Option<lopdf::ObjectId>
+    {
+        // all names are in the root node of the tree, which is valid for any number of names
+        let names: Vec<lopdf::Object> = self.destinations.into_iter()
+            .filter_map(|(name, (page, position))| page_ids.get(page).map(|id| (name, destination(*id, position))))
+            .flat_map(|(name, dest)| vec![lopdf::Object::String(name.into_bytes(), lopdf::StringFormat::Literal), dest])
+            .collect();
+
+        if names.is_empty() {
+            return None;
+        }
+
+        let tree = lopdf::Dictionary::from_iter(vec![("Names", lopdf::Object::Array(names))]);
+        Some(doc.add_object(tree))
+    }
+}
+
+impl LinkTarget {
+
+    /// Checks that the target exists, `page_count` is the number of pages in the document
+    pub(crate) fn validate(&self, destinations: &NamedDestinations, page_count: usize, errors: &mut Vec<ValidationError>)
+    {
+        match *self {
+            LinkTarget::Named(ref name) if !destinations.contains(name) =>
+                errors.push(ValidationError::UnknownDestination { name: name.clone() }),
+            LinkTarget::Page { page, .. } if page.0 >= page_count =>
+                errors.push(ValidationError::MissingDestinationPage { name: None, page: page.0 }),
+            _ => { },
+        }
+    }
+
+    /// Returns the key and value of the target in a link annotation or bookmark: a destination
+    /// (`/Dest`) or, for remote targets, an action (`/A`). `page_ids` are the object IDs of the
+    /// pages, returns `None` if the target page doesn't exist.
+    pub(crate) fn into_entry(self, page_ids: &[lopdf::ObjectId])
+    -> Option<(&'static str, lopdf::Object)>
+    {
+        use lopdf::Object::*;
+
+        match self {
+            LinkTarget::Page { page, position } =>
+                page_ids.get(page.0).map(|id| ("Dest", destination(*id, position))),
+            LinkTarget::Named(name) =>
+                Some(("Dest", String(name.into_bytes(), lopdf::StringFormat::Literal))),
+            LinkTarget::Remote { file, name } => Some(("A", Dictionary(lopdf::Dictionary::from_iter(vec![
+                ("S", Name("GoToR".into())),
+                ("F", pdf_text_string(&file)),
+                ("D", String(name.into_bytes(), lopdf::StringFormat::Literal)),
+            ])))),
+        }
+    }
+}
+
+/// Destination array, which shows `position` at the upper left corner of the window (`/XYZ`)
+/// without changing the zoom
+fn destination(page_id: lopdf::ObjectId, position: Point)
+-> lopdf::Object
+{
+    use lopdf::Object::*;
+    Array(vec![Reference(page_id), Name("XYZ".into()), position.x.into(), position.y.into(), Null])
+}
+
+#[test]
+fn test_named_destinations() {
+    let mut destinations = NamedDestinations::default();
+    destinations.insert("chapter-2".into(), 1, Point::from_mm(10.0, 280.0));
+    destinations.insert("chapter-1".into(), 5, Point::from_mm(10.0, 280.0));
+    destinations.insert("chapter-1".into(), 0, Point::from_mm(10.0, 200.0));
+
+    assert!(destinations.contains("chapter-1"));
+    assert!(!destinations.contains("chapter-3"));
+    assert_eq!(destinations.missing_pages(2), vec![]);
+    assert_eq!(destinations.missing_pages(1), vec![("chapter-2".to_string(), 1)]);
+}
@@ -0,0 +1,262 @@
+//! Flattening of annotations and form fields: their appearances are drawn into the
+//! page content, so that they can't be edited anymore
+
+use lopdf;
+use lopdf::content::{Content, Operation};
+use std::iter::FromIterator;
+
+/// Annotation flag (bit 2): the annotation is neither shown nor printed
+const ANNOTATION_FLAG_HIDDEN: i64 = 1 << 1;
+
+/// Draws the normal appearance of every annotation and form field widget on the pages into
+/// the content of the page and removes the annotation. Sticky notes don't have an appearance,
+/// they get a simple note icon. Links have nothing to draw and stay interactive.
+///
+/// `inherited_resources` are the resources of the `/Pages` node, if they are shared by the pages.
+pub(crate) fn flatten_annotations(doc: &mut lopdf::Document, page_ids: &[lopdf::ObjectId], inherited_resources: Option<lopdf::ObjectId>)
+{
+    use lopdf::Object::*;
+
+    for page_id in page_ids {
+        let annotations = match doc.get_object(*page_id).and_then(|p| p.as_dict()).and_then(|p| p.get(b"Annots")).and_then(|a| a.as_array()) {
+            Ok(annotations) => annotations.iter().filter_map(|a| a.as_reference().ok()).collect::<Vec<lopdf::ObjectId>>(),
+            Err(_) => continue,
+        };
+
+        let mut links = Vec::new();
+        let mut operations = Vec::new();
+        let mut xobjects = lopdf::Dictionary::new();
+
+        for id in annotations {
+            let annotation = match doc.objects.remove(&id) {
+                Some(Dictionary(annotation)) => annotation,
+                _ => continue,
+            };
+
+            if annotation.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Link") {
+                doc.objects.insert(id, Dictionary(annotation));
+                links.push(Reference(id));
+                continue;
+            }
+
+            // the field of a radio button group is only referenced by its widgets
+            if let Ok(parent) = annotation.get(b"Parent").and_then(|p| p.as_reference()) {
+                doc.objects.remove(&parent);
+            }
+
+            let flags = annotation.get(b"F").and_then(|f| f.as_i64()).unwrap_or(0);
+            let rect = match annotation.get(b"Rect").ok().and_then(rectangle) {
+                Some(rect) => rect,
+                None => continue,
+            };
+            if flags & ANNOTATION_FLAG_HIDDEN != 0 {
+                continue;
+            }
+
+            let appearance = match normal_appearance(&annotation) {
+                Some(appearance) => appearance,
+                None if annotation.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Text") => {
+                    doc.add_object(note_appearance(rect[2] - rect[0], rect[3] - rect[1]))
+                },
+                None => continue,
+            };
+
+            let matrix = match transformation(doc, appearance, rect) {
+                Some(matrix) => matrix,
+                None => continue,
+            };
+
+            let name = format!("FlatAnnot{}", xobjects.len());
+            operations.push(Operation::new("q", vec![]));
+            operations.push(Operation::new("cm", matrix.iter().map(|m| Real(*m)).collect()));
+            operations.push(Operation::new("Do", vec![Name(name.clone().into_bytes())]));
+            operations.push(Operation::new("Q", vec![]));
+            xobjects.set(name, Reference(appearance));
+        }
+
+        let resources_id = if xobjects.is_empty() { None } else { Some(page_resources(doc, *page_id, inherited_resources)) };
+
+        let page = match doc.get_object_mut(*page_id).and_then(|p| p.as_dict_mut()) {
+            Ok(page) => page,
+            Err(_) => continue,
+        };
+
+        if links.is_empty() {
+            page.remove(b"Annots");
+        } else {
+            page.set("Annots", Array(links));
+        }
+
+        let resources_id = match resources_id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        // the flattened appearances are drawn with the initial graphics state of the page
+        let mut contents = vec![];
+        let original_contents = page.remove(b"Contents");
+        let save_state = Content { operations: vec![Operation::new("q", vec![])] };
+        let mut restore_state = Content { operations: vec![Operation::new("Q", vec![])] };
+        restore_state.operations.extend(operations);
+        // writing into a Vec<u8> can't fail
+        let save_state = lopdf::Stream::new(lopdf::Dictionary::new(), save_state.encode().unwrap());
+        let restore_state = lopdf::Stream::new(lopdf::Dictionary::new(), restore_state.encode().unwrap());
+
+        contents.push(Reference(doc.add_object(save_state)));
+        match original_contents {
+            Some(Array(original)) => contents.extend(original),
+            Some(original) => contents.push(original),
+            None => { },
+        }
+        contents.push(Reference(doc.add_object(restore_state)));
+
+        if let Ok(page) = doc.get_object_mut(*page_id).and_then(|p| p.as_dict_mut()) {
+            page.set("Contents", Array(contents));
+        }
+
+        add_xobjects(doc, resources_id, xobjects);
+    }
+}
+
+/// Returns the normal appearance stream of an annotation, the current state (`/AS`) is
+/// selected if the annotation has several appearances (checkboxes and radio buttons)
+fn normal_appearance(annotation: &lopdf::Dictionary)
+-> Option<lopdf::ObjectId>
+{
+    let normal = annotation.get(b"AP").and_then(|ap| ap.as_dict()).and_then(|ap| ap.get(b"N")).ok()?;
+    match normal {
+        lopdf::Object::Reference(id) => Some(*id),
+        lopdf::Object::Dictionary(states) => {
+            let state = annotation.get(b"AS").and_then(|s| s.as_name()).ok()?;
+            states.get(state).and_then(|s| s.as_reference()).ok()
+        },
+        _ => None,
+    }
+}
+
+/// Matrix that maps the bounding box of the appearance (transformed by its `/Matrix`)
+/// to the rectangle of the annotation, see the PDF reference, section 8.4.4
+fn transformation(doc: &lopdf::Document, appearance: lopdf::ObjectId, rect: [f64; 4])
+-> Option<[f64; 6]>
+{
+    let dict = &doc.get_object(appearance).and_then(|a| a.as_stream()).ok()?.dict;
+    let bbox = dict.get(b"BBox").ok().and_then(rectangle)?;
+    let matrix = dict.get(b"Matrix").and_then(|m| m.as_array()).ok()
+        .and_then(|m| m.iter().map(number).collect::<Option<Vec<f64>>>())
+        .filter(|m| m.len() == 6)
+        .unwrap_or_else(|| vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    let corners = [(bbox[0], bbox[1]), (bbox[2], bbox[1]), (bbox[0], bbox[3]), (bbox[2], bbox[3])];
+    let transformed = corners.iter()
+        .map(|&(x, y)| (matrix[0] * x + matrix[2] * y + matrix[4], matrix[1] * x + matrix[3] * y + matrix[5]))
+        .collect::<Vec<(f64, f64)>>();
+    let llx = transformed.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+    let lly = transformed.iter().map(|c| c.1).fold(f64::INFINITY, f64::min);
+    let urx = transformed.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max);
+    let ury = transformed.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max);
+
+    if urx - llx <= 0.0 || ury - lly <= 0.0 {
+        return None;
+    }
+
+    let scale_x = (rect[2] - rect[0]) / (urx - llx);
+    let scale_y = (rect[3] - rect[1]) / (ury - lly);
+    Some([scale_x, 0.0, 0.0, scale_y, rect[0] - llx * scale_x, rect[1] - lly * scale_y])
+}
+
+/// Returns the ID of the resources of the page. A page that inherits its resources gets a
+/// copy of them, so that the appearances aren't added to the other pages
+fn page_resources(doc: &mut lopdf::Document, page_id: lopdf::ObjectId, inherited_resources: Option<lopdf::ObjectId>)
+-> lopdf::ObjectId
+{
+    let own_resources = doc.get_object(page_id).and_then(|p| p.as_dict()).and_then(|p| p.get(b"Resources")).and_then(|r| r.as_reference());
+    if let Ok(id) = own_resources {
+        return id;
+    }
+
+    let resources = inherited_resources
+        .and_then(|id| doc.get_object(id).and_then(|r| r.as_dict()).ok().cloned())
+        .unwrap_or_default();
+    let id = doc.add_object(lopdf::Object::Dictionary(resources));
+    if let Ok(page) = doc.get_object_mut(page_id).and_then(|p| p.as_dict_mut()) {
+        page.set("Resources", lopdf::Object::Reference(id));
+    }
+    id
+}
+
+/// Adds the appearances to the `/XObject` resources of a page
+fn add_xobjects(doc: &mut lopdf::Document, resources_id: lopdf::ObjectId, xobjects: lopdf::Dictionary)
+{
+    let existing = doc.get_object(resources_id).and_then(|r| r.as_dict()).and_then(|r| r.get(b"XObject")).ok().cloned();
+    let mut merged = match existing {
+        Some(lopdf::Object::Dictionary(existing)) => existing,
+        Some(lopdf::Object::Reference(id)) => doc.get_object(id).and_then(|x| x.as_dict()).ok().cloned().unwrap_or_default(),
+        _ => lopdf::Dictionary::new(),
+    };
+    for (name, xobject) in xobjects.iter() {
+        merged.set(name.clone(), xobject.clone());
+    }
+
+    if let Ok(resources) = doc.get_object_mut(resources_id).and_then(|r| r.as_dict_mut()) {
+        resources.set("XObject", lopdf::Object::Dictionary(merged));
+    }
+}
+
+/// Simple appearance for a sticky note: a yellow sheet with three lines of "text"
+fn note_appearance(width: f64, height: f64)
+-> lopdf::Stream
+{
+    use lopdf::Object::*;
+
+    let mut operations = vec![
+        Operation::new("rg", vec![Real(1.0), Real(0.9), Real(0.3)]),
+        Operation::new("G", vec![Real(0.3)]),
+        Operation::new("w", vec![Real(0.5)]),
+        Operation::new("re", vec![Real(0.5), Real(0.5), Real(width - 1.0), Real(height - 1.0)]),
+        Operation::new("B", vec![]),
+    ];
+    for line in 1..4 {
+        let y = height * f64::from(line) / 4.0;
+        operations.push(Operation::new("m", vec![Real(width * 0.2), Real(y)]));
+        operations.push(Operation::new("l", vec![Real(width * 0.8), Real(y)]));
+    }
+    operations.push(Operation::new("S", vec![]));
+
+    let dict = lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("XObject".into())),
+        ("Subtype", Name("Form".into())),
+        ("BBox", Array(vec![Integer(0), Integer(0), Real(width), Real(height)])),
+    ]);
+    // writing into a Vec<u8> can't fail
+    lopdf::Stream::new(dict, Content { operations }.encode().unwrap())
+}
+
+/// Reads a rectangle (`[llx lly urx ury]`), the corners are normalized
+fn rectangle(object: &lopdf::Object)
+-> Option<[f64; 4]>
+{
+    let values = object.as_array().ok()?.iter().map(number).collect::<Option<Vec<f64>>>()?;
+    if values.len() != 4 {
+        return None;
+    }
+    Some([values[0].min(values[2]), values[1].min(values[3]), values[0].max(values[2]), values[1].max(values[3])])
+}
+
+fn number(object: &lopdf::Object)
+-> Option<f64>
+{
+    match *object {
+        lopdf::Object::Integer(i) => Some(i as f64),
+        lopdf::Object::Real(r) => Some(r),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_flatten_rectangle() {
+    use lopdf::Object::*;
+
+    let rect = Array(vec![Integer(100), Real(50.0), Integer(20), Integer(10)]);
+    assert_eq!(rectangle(&rect), Some([20.0, 10.0, 100.0, 50.0]));
+    assert_eq!(rectangle(&Array(vec![Integer(1)])), None);
+}
@@ -0,0 +1,996 @@
+//! Interactive form fields (AcroForm), such as text fields, checkboxes, radio buttons, dropdowns
+//! and push buttons
+//!
+//! Fields can have JavaScript actions for formatting, validating and calculating their
+//! values (see `PdfDocumentReference::set_field_calculate_action()`). Keep in mind that
+//! support for JavaScript differs a lot between viewers:
+//!
+//! - Adobe Acrobat / Reader runs all actions, including the `AFNumber_Format()` and
+//!   `AFSimple_Calculate()` helper functions, which are built into Acrobat and not part of the PDF
+//! - pdf.js (Firefox) and some other viewers only run a subset of the actions, many viewers
+//!   (browsers, macOS Preview, most mobile viewers) don't run JavaScript at all
+//! - calculated values are only updated when a field changes, the stored value is
+//!   shown until then. Set a sensible initial value with `set_field_value()`
+
+use lopdf;
+use std::iter::FromIterator;
+use lopdf::content::{Content, Operation};
+use utils::{calculate_points_for_circle, pdf_text_string};
+use types::plugins::graphics::two_dimensional::afm::BuiltinMetrics;
+use {BuiltinFont, Color, Greyscale, Line, PdfColor, Point, Pt, Rect, ValidationError};
+
+/// Name of the "on" state of a checkbox
+const CHECKBOX_ON_STATE: &str = "Yes";
+/// Name of the "off" state of checkboxes and radio buttons, fixed by the PDF spec
+const OFF_STATE: &str = "Off";
+/// Field flag (bit 15): a radio group can't be switched off by clicking the selected button
+const FIELD_FLAG_NO_TOGGLE_TO_OFF: i64 = 1 << 14;
+/// Field flag (bit 16): the button field is a radio group
+const FIELD_FLAG_RADIO: i64 = 1 << 15;
+/// Field flag (bit 17): the button field is a push button, which has no value
+const FIELD_FLAG_PUSH_BUTTON: i64 = 1 << 16;
+/// Field flag (bit 18): the choice field is a dropdown (combo box) instead of a list box
+const FIELD_FLAG_COMBO: i64 = 1 << 17;
+/// Annotation flag (bit 3): print the widget when printing the page
+const ANNOTATION_FLAG_PRINT: i64 = 1 << 2;
+/// Submit form flag (bit 3): submit the values as HTML form data instead of FDF
+const SUBMIT_FLAG_EXPORT_FORMAT: i64 = 1 << 2;
+/// Submit form flag (bit 6): submit the values as XFDF
+const SUBMIT_FLAG_XFDF: i64 = 1 << 5;
+/// Submit form flag (bit 9): submit the whole document as PDF
+const SUBMIT_FLAG_SUBMIT_PDF: i64 = 1 << 8;
+
+/// Interactive form of a document, written as the `/AcroForm` dictionary of the catalog
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AcroForm {
+    /// Fields in the order they were added
+    fields: Vec<FormField>,
+    /// Values set with `PdfDocumentReference::set_field_value()`, as `(field name, value)`.
+    /// They are only looked up when saving, so a value can be set before its field is added.
+    values: Vec<(String, String)>,
+    /// JavaScript actions of the fields, as `(field name, trigger, script)`
+    actions: Vec<(String, FieldTrigger, String)>,
+    /// Appearance of the text of all fields, see `PdfDocumentReference::set_form_default_appearance()`
+    default_appearance: DefaultAppearance,
+    /// Appearances of single fields that override the default, as `(field name, appearance)`
+    appearances: Vec<(String, DefaultAppearance)>,
+}
+
+/// Font, size and color of the text of fields with variable text, written
+/// as the default appearance string (`/DA`)
+#[derive(Debug, Clone)]
+pub(crate) struct DefaultAppearance {
+    font: BuiltinFont,
+    /// Font size in points, 0 fits the text to the height of the field
+    size: f64,
+    color: Color,
+}
+
+/// A single (terminal) form field
+#[derive(Debug, Clone)]
+struct FormField {
+    /// Fully qualified name of the field (`/T`)
+    name: String,
+    /// Type of the field and its widgets
+    kind: FormFieldKind,
+}
+
+#[derive(Debug, Clone)]
+enum FormFieldKind {
+    /// Checkbox with a single widget
+    CheckBox { widget: Widget, checked: bool },
+    /// Mutually exclusive radio buttons, each button is a widget with its export value
+    RadioGroup { options: Vec<(String, Widget)> },
+    /// Dropdown (`combo`) or list box, the user selects one of the options
+    Choice { widget: Widget, options: Vec<String>, combo: bool },
+    /// Single line text field
+    Text { widget: Widget },
+    /// Push button with a caption, which runs an action when it is clicked
+    PushButton { widget: Widget, caption: String, action: ButtonAction },
+}
+
+/// Action of a push button, see `PdfPageReference::add_push_button()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ButtonAction {
+    /// Resets all fields of the form to their default values
+    ResetForm,
+    /// Sends the values of the fields to the URL (usually a HTTP POST)
+    SubmitForm { url: String, format: SubmitFormat },
+    /// Runs the JavaScript
+    JavaScript(String),
+}
+
+/// Format in which `ButtonAction::SubmitForm` sends the form
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubmitFormat {
+    /// Forms Data Format, the values of the fields
+    Fdf,
+    /// XML Forms Data Format (PDF 1.4), the values of the fields as XML
+    Xfdf,
+    /// HTML form data (`application/x-www-form-urlencoded`), like a form of a website
+    Html,
+    /// The whole document as PDF (PDF 1.4)
+    Pdf,
+}
+
+/// Event that triggers a JavaScript action of a field (key in the `/AA` dictionary)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FieldTrigger {
+    /// The user types into the field or changes the selection
+    Keystroke,
+    /// The value is shown, the action can change how it is displayed
+    Format,
+    /// The value changed, the action can reject the new value
+    Validate,
+    /// Another field changed, the action recalculates the value of the field
+    Calculate,
+}
+
+/// Position of a widget annotation
+#[derive(Debug, Copy, Clone)]
+struct Widget {
+    /// Index of the page the widget is on
+    page: usize,
+    /// Position of the widget on the page
+    rect: Rect,
+}
+
+/// Object IDs, that are reserved for the widgets before the pages are written
+/// (the pages need to reference their widgets in `/Annots` and the widgets
+/// need to reference their page in `/P`)
+#[derive(Debug)]
+pub(crate) struct WidgetIds {
+    /// IDs of the widgets of each field, in the same order as the fields
+    fields: Vec<Vec<lopdf::ObjectId>>,
+    /// References to the widgets on each page
+    pub(crate) pages: Vec<Vec<lopdf::Object>>,
+}
+
+impl AcroForm {
+
+    /// Returns if there are no fields in the form
+    #[inline]
+    pub(crate) fn is_empty(&self)
+    -> bool
+    {
+        self.fields.is_empty()
+    }
+
+    /// Returns the number of fields in the form
+    #[inline]
+    pub(crate) fn len(&self)
+    -> usize
+    {
+        self.fields.len()
+    }
+
+    /// Removes all fields after the first `len` fields, see `PdfDocumentReference::restore()`
+    #[inline]
+    pub(crate) fn truncate(&mut self, len: usize)
+    {
+        self.fields.truncate(len);
+    }
+
+    /// Adds a checkbox on the page with the index `page`
+    pub(crate) fn add_checkbox(&mut self, name: String, page: usize, rect: Rect, checked: bool)
+    {
+        self.fields.push(FormField {
+            name,
+            kind: FormFieldKind::CheckBox { widget: Widget { page, rect }, checked },
+        });
+    }
+
+    /// Adds a radio group on the page with the index `page`, the options are
+    /// `(export value, position of the button)`. No button is selected initially.
+    pub(crate) fn add_radio_group(&mut self, name: String, page: usize, options: Vec<(String, Rect)>)
+    {
+        let options = options.into_iter().map(|(value, rect)| (value, Widget { page, rect })).collect();
+        self.fields.push(FormField {
+            name,
+            kind: FormFieldKind::RadioGroup { options },
+        });
+    }
+
+    /// Adds a dropdown (`combo = true`) or list box on the page with the index `page`
+    pub(crate) fn add_choice_field(&mut self, name: String, page: usize, rect: Rect, options: Vec<String>, combo: bool)
+    {
+        self.fields.push(FormField {
+            name,
+            kind: FormFieldKind::Choice { widget: Widget { page, rect }, options, combo },
+        });
+    }
+
+    /// Adds a single line text field on the page with the index `page`
+    pub(crate) fn add_text_field(&mut self, name: String, page: usize, rect: Rect)
+    {
+        self.fields.push(FormField {
+            name,
+            kind: FormFieldKind::Text { widget: Widget { page, rect } },
+        });
+    }
+
+    /// Adds a push button on the page with the index `page`
+    pub(crate) fn add_push_button(&mut self, name: String, page: usize, rect: Rect, caption: String, action: ButtonAction)
+    {
+        self.fields.push(FormField {
+            name,
+            kind: FormFieldKind::PushButton { widget: Widget { page, rect }, caption, action },
+        });
+    }
+
+    /// Sets the JavaScript action of the field `name` for the `trigger`,
+    /// replaces the action that was set before for the same trigger
+    pub(crate) fn set_action(&mut self, name: String, trigger: FieldTrigger, script: String)
+    {
+        self.actions.retain(|a| a.0 != name || a.1 != trigger);
+        self.actions.push((name, trigger, script));
+    }
+
+    /// Sets the appearance of the text of all fields that don't have their own appearance
+    #[inline]
+    pub(crate) fn set_default_appearance(&mut self, appearance: DefaultAppearance)
+    {
+        self.default_appearance = appearance;
+    }
+
+    /// Sets the appearance of the text of the field `name`, replaces the appearance that was set before
+    pub(crate) fn set_field_appearance(&mut self, name: String, appearance: DefaultAppearance)
+    {
+        self.appearances.retain(|a| a.0 != name);
+        self.appearances.push((name, appearance));
+    }
+
+    /// Sets the value of the field `name`, replaces values that were set before
+    pub(crate) fn set_value(&mut self, name: String, value: String)
+    {
+        self.values.retain(|v| v.0 != name);
+        self.values.push((name, value));
+    }
+
+    /// Removes the values of the fields that have a widget on the page with the index `page`,
+    /// which overlaps `area`
+    pub(crate) fn redact(&mut self, page: usize, area: &Rect)
+    {
+        let fields = &self.fields;
+        self.values.retain(|(name, _)| {
+            !fields.iter().filter(|f| f.name == *name).flat_map(|f| f.widgets())
+                .any(|widget| widget.page == page && widget.rect.overlaps(area))
+        });
+    }
+
+    /// Moves the widgets on the page with the index `page` by `dx` and `dy`
+    pub(crate) fn translate(&mut self, page: usize, dx: Pt, dy: Pt)
+    {
+        for field in &mut self.fields {
+            for widget in field.widgets_mut().into_iter().filter(|w| w.page == page) {
+                widget.rect = widget.rect.translated(dx, dy);
+            }
+        }
+    }
+
+    /// Checks that the field names are unique, that the options of radio groups and choice
+    /// fields are unique and not empty and that the values set with `set_value()`
+    /// belong to an existing field and are valid for it
+    pub(crate) fn validate(&self, errors: &mut Vec<ValidationError>)
+    {
+        for (idx, field) in self.fields.iter().enumerate() {
+            let reported = self.fields[..idx].iter().filter(|f| f.name == field.name).count();
+            if reported == 1 {
+                errors.push(ValidationError::DuplicateFieldName { name: field.name.clone() });
+            }
+
+            let options = field.options();
+            for (idx, option) in options.iter().enumerate() {
+                let invalid = option.is_empty() || options[..idx].contains(option) ||
+                    // the off state can't be used as the "on" state of a radio button
+                    (field.is_radio_group() && *option == OFF_STATE);
+                if invalid {
+                    errors.push(ValidationError::InvalidFieldOption { field: field.name.clone(), option: option.to_string() });
+                }
+            }
+        }
+
+        let action_fields = self.actions.iter().map(|a| &a.0);
+        let appearance_fields = self.appearances.iter().map(|a| &a.0);
+        for name in action_fields.chain(appearance_fields) {
+            if !self.fields.iter().any(|f| f.name == *name) {
+                errors.push(ValidationError::UnknownField { field: name.clone() });
+            }
+        }
+
+        for (name, value) in &self.values {
+            match self.fields.iter().find(|f| f.name == *name) {
+                None => errors.push(ValidationError::UnknownField { field: name.clone() }),
+                Some(field) if !field.accepts_value(value) =>
+                    errors.push(ValidationError::InvalidFieldValue { field: name.clone(), value: value.clone() }),
+                Some(_) => { },
+            }
+        }
+    }
+
+    /// Allocates the object IDs of all widgets, `page_count` is the number of pages in the document
+    pub(crate) fn reserve_widget_ids(&self, doc: &mut lopdf::Document, page_count: usize)
+    -> WidgetIds
+    {
+        let mut ids = WidgetIds { fields: Vec::with_capacity(self.fields.len()), pages: vec![Vec::new(); page_count] };
+
+        for field in &self.fields {
+            let field_ids = field.widgets().iter().map(|widget| {
+                let id = doc.new_object_id();
+                ids.pages[widget.page].push(lopdf::Object::Reference(id));
+                id
+            }).collect();
+            ids.fields.push(field_ids);
+        }
+
+        ids
+    }
+
+    /// Adds the fields and their widgets to the document and returns the `/AcroForm` dictionary.
+    /// `page_ids` are the object IDs of the pages, in the same order as the pages.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, widget_ids: WidgetIds, page_ids: &[lopdf::ObjectId])
+    -> lopdf::Dictionary
+    {
+        use lopdf::Object::*;
+
+        let mut fields = Vec::<lopdf::Object>::with_capacity(self.fields.len());
+        let mut field_ids = Vec::with_capacity(self.fields.len());
+        // the font of the default appearance is always in the resources of the form
+        let mut font_ids = Vec::new();
+        add_font(doc, &mut font_ids, self.default_appearance.font);
+        let values = self.values;
+
+        for (field, ids) in self.fields.into_iter().zip(widget_ids.fields) {
+            let name = field.name;
+            let value = values.iter().find(|v| v.0 == name).map(|v| v.1.clone());
+            let appearance = self.appearances.iter().find(|a| a.0 == name).map(|a| &a.1).unwrap_or(&self.default_appearance);
+
+            let (field_id, mut dict) = match field.kind {
+                FormFieldKind::CheckBox { widget, checked } => {
+                    let checked = value.map(|v| v == CHECKBOX_ON_STATE).unwrap_or(checked);
+                    let state = if checked { CHECKBOX_ON_STATE } else { OFF_STATE };
+                    // the widget and the field are merged into one dictionary
+                    let appearance = button_appearance(doc, &widget, CHECKBOX_ON_STATE, checkbox_appearance);
+                    let mut dict = widget_dictionary(&widget, page_ids, appearance);
+                    dict.set("FT", Name("Btn".into()));
+                    dict.set("V", Name(state.into()));
+                    dict.set("AS", Name(state.into()));
+                    (ids[0], dict)
+                },
+                FormFieldKind::RadioGroup { options } => {
+                    let parent_id = doc.new_object_id();
+                    let mut kids = Vec::<lopdf::Object>::with_capacity(options.len());
+                    let selected = value.unwrap_or_else(|| OFF_STATE.to_string());
+
+                    for ((option, widget), id) in options.into_iter().zip(ids) {
+                        let appearance = button_appearance(doc, &widget, &option, radio_button_appearance);
+                        let state = if option == selected { option } else { OFF_STATE.to_string() };
+                        let mut dict = widget_dictionary(&widget, page_ids, appearance);
+                        dict.set("Parent", Reference(parent_id));
+                        dict.set("AS", Name(state.into_bytes()));
+                        doc.objects.insert(id, Dictionary(dict));
+                        kids.push(Reference(id));
+                    }
+
+                    let parent = lopdf::Dictionary::from_iter(vec![
+                        ("FT", Name("Btn".into())),
+                        ("Ff", Integer(FIELD_FLAG_RADIO | FIELD_FLAG_NO_TOGGLE_TO_OFF)),
+                        ("V", Name(selected.into_bytes())),
+                        ("Kids", Array(kids)),
+                    ]);
+                    (parent_id, parent)
+                },
+                FormFieldKind::Choice { widget, options, combo } => {
+                    let mut dict = variable_text_widget(doc, &widget, page_ids, &mut font_ids, appearance, &options, combo, value.as_deref());
+                    dict.set("FT", Name("Ch".into()));
+                    dict.set("Ff", Integer(if combo { FIELD_FLAG_COMBO } else { 0 }));
+                    dict.set("Opt", Array(options.iter().map(|o| pdf_text_string(o)).collect()));
+                    (ids[0], dict)
+                },
+                FormFieldKind::Text { widget } => {
+                    let mut dict = variable_text_widget(doc, &widget, page_ids, &mut font_ids, appearance, &[], true, value.as_deref());
+                    dict.set("FT", Name("Tx".into()));
+                    (ids[0], dict)
+                },
+                FormFieldKind::PushButton { widget, caption, action } => {
+                    let font_id = add_font(doc, &mut font_ids, appearance.font);
+                    let operations = push_button_appearance(&widget, appearance, &caption);
+                    let appearance_id = doc.add_object(appearance_stream(
+                        widget.rect.width(), widget.rect.height(), operations, Some(font_resources(&[(appearance.font, font_id)]))));
+
+                    let mut dict = widget_dictionary(&widget, page_ids, Reference(appearance_id));
+                    dict.set("FT", Name("Btn".into()));
+                    dict.set("Ff", Integer(FIELD_FLAG_PUSH_BUTTON));
+                    dict.set("DA", String(appearance.to_string().into_bytes(), lopdf::StringFormat::Literal));
+                    dict.set("MK", Dictionary(lopdf::Dictionary::from_iter(vec![
+                        ("CA", pdf_text_string(&caption)),
+                        ("BG", Array(vec![Real(0.85)])),
+                        ("BC", Array(vec![Real(0.0)])),
+                    ])));
+                    dict.set("A", Dictionary(action.into_dictionary()));
+                    (ids[0], dict)
+                },
+            };
+
+            dict.set("T", pdf_text_string(&name));
+
+            let triggers: Vec<(&'static str, lopdf::Object)> = self.actions.iter()
+                .filter(|a| a.0 == name)
+                .map(|a| (a.1.key(), Dictionary(javascript_action(&a.2))))
+                .collect();
+            if !triggers.is_empty() {
+                dict.set("AA", Dictionary(lopdf::Dictionary::from_iter(triggers)));
+            }
+
+            doc.objects.insert(field_id, Dictionary(dict));
+            fields.push(Reference(field_id));
+            field_ids.push((name, field_id));
+        }
+
+        let acro_form_entries = vec![
+            ("Fields", Array(fields)),
+            ("DA", String(self.default_appearance.to_string().into_bytes(), lopdf::StringFormat::Literal)),
+            ("DR", Dictionary(font_resources(&font_ids))),
+        ];
+        let mut acro_form = lopdf::Dictionary::from_iter(acro_form_entries);
+
+        // the fields are calculated in the order their calculate actions were set
+        let calculation_order: Vec<lopdf::Object> = self.actions.iter()
+            .filter(|a| a.1 == FieldTrigger::Calculate)
+            .filter_map(|a| field_ids.iter().find(|f| f.0 == a.0).map(|f| Reference(f.1)))
+            .collect();
+        if !calculation_order.is_empty() {
+            acro_form.set("CO", Array(calculation_order));
+        }
+        acro_form
+    }
+}
+
+impl FormField {
+
+    /// Widgets of the field, in the order their IDs are reserved
+    fn widgets(&self)
+    -> Vec<Widget>
+    {
+        match self.kind {
+            FormFieldKind::CheckBox { widget, .. } |
+            FormFieldKind::Choice { widget, .. } |
+            FormFieldKind::Text { widget } |
+            FormFieldKind::PushButton { widget, .. } => vec![widget],
+            FormFieldKind::RadioGroup { ref options } => options.iter().map(|o| o.1).collect(),
+        }
+    }
+
+    /// Widgets of the field, see `widgets()`
+    fn widgets_mut(&mut self)
+    -> Vec<&mut Widget>
+    {
+        match self.kind {
+            FormFieldKind::CheckBox { ref mut widget, .. } |
+            FormFieldKind::Choice { ref mut widget, .. } |
+            FormFieldKind::Text { ref mut widget } |
+            FormFieldKind::PushButton { ref mut widget, .. } => vec![widget],
+            FormFieldKind::RadioGroup { ref mut options } => options.iter_mut().map(|o| &mut o.1).collect(),
+        }
+    }
+
+    /// Export values of the radio buttons or options of the choice field
+    fn options(&self)
+    -> Vec<&str>
+    {
+        match self.kind {
+            FormFieldKind::CheckBox { .. } | FormFieldKind::Text { .. } | FormFieldKind::PushButton { .. } => Vec::new(),
+            FormFieldKind::RadioGroup { ref options } => options.iter().map(|o| o.0.as_str()).collect(),
+            FormFieldKind::Choice { ref options, .. } => options.iter().map(|o| o.as_str()).collect(),
+        }
+    }
+
+    #[inline]
+    fn is_radio_group(&self)
+    -> bool
+    {
+        matches!(self.kind, FormFieldKind::RadioGroup { .. })
+    }
+
+    /// Returns if the field can have the value `value`
+    fn accepts_value(&self, value: &str)
+    -> bool
+    {
+        match self.kind {
+            FormFieldKind::CheckBox { .. } => value == CHECKBOX_ON_STATE || value == OFF_STATE,
+            FormFieldKind::RadioGroup { .. } => value == OFF_STATE || self.options().contains(&value),
+            FormFieldKind::Choice { .. } => self.options().contains(&value),
+            FormFieldKind::Text { .. } => true,
+            FormFieldKind::PushButton { .. } => false,
+        }
+    }
+}
+
+impl FieldTrigger {
+
+    /// Key of the trigger in the additional-actions dictionary
+    fn key(self)
+    -> &'static str
+    {
+        match self {
+            FieldTrigger::Keystroke => "K",
+            FieldTrigger::Format => "F",
+            FieldTrigger::Validate => "V",
+            FieldTrigger::Calculate => "C",
+        }
+    }
+}
+
+impl ButtonAction {
+
+    /// Creates the action dictionary
+    fn into_dictionary(self)
+    -> lopdf::Dictionary
+    {
+        use lopdf::Object::*;
+
+        match self {
+            ButtonAction::ResetForm => lopdf::Dictionary::from_iter(vec![("S", Name("ResetForm".into()))]),
+            ButtonAction::SubmitForm { url, format } => {
+                let file_spec = lopdf::Dictionary::from_iter(vec![
+                    ("FS", Name("URL".into())),
+                    ("F", String(url.into_bytes(), lopdf::StringFormat::Literal)),
+                ]);
+                lopdf::Dictionary::from_iter(vec![
+                    ("S", Name("SubmitForm".into())),
+                    ("F", Dictionary(file_spec)),
+                    ("Flags", Integer(format.flags())),
+                ])
+            },
+            ButtonAction::JavaScript(script) => javascript_action(&script),
+        }
+    }
+}
+
+impl SubmitFormat {
+
+    /// Flags of the submit form action that select the format
+    fn flags(self)
+    -> i64
+    {
+        match self {
+            SubmitFormat::Fdf => 0,
+            SubmitFormat::Xfdf => SUBMIT_FLAG_XFDF,
+            SubmitFormat::Html => SUBMIT_FLAG_EXPORT_FORMAT,
+            SubmitFormat::Pdf => SUBMIT_FLAG_SUBMIT_PDF,
+        }
+    }
+}
+
+/// Creates a JavaScript action dictionary
+fn javascript_action(script: &str)
+-> lopdf::Dictionary
+{
+    lopdf::Dictionary::from_iter(vec![
+        ("S", lopdf::Object::Name("JavaScript".into())),
+        ("JS", pdf_text_string(script)),
+    ])
+}
+
+/// Creates the widget annotation dictionary with the normal appearance `/AP /N`
+fn widget_dictionary(widget: &Widget, page_ids: &[lopdf::ObjectId], normal_appearance: lopdf::Object)
+-> lopdf::Dictionary
+{
+    use lopdf::Object::*;
+
+    lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("Annot".into())),
+        ("Subtype", Name("Widget".into())),
+        ("Rect", widget.rect.into()),
+        ("P", Reference(page_ids[widget.page])),
+        ("F", Integer(ANNOTATION_FLAG_PRINT)),
+        ("AP", Dictionary(lopdf::Dictionary::from_iter(vec![("N", normal_appearance)]))),
+    ])
+}
+
+/// Creates the widget of a field with variable text (text field, dropdown or list box). The
+/// font of the appearance is added to the document when it is used for the first time,
+/// `font_ids` are the fonts that were already added.
+#[allow(clippy::too_many_arguments)]
+fn variable_text_widget(doc: &mut lopdf::Document, widget: &Widget, page_ids: &[lopdf::ObjectId], font_ids: &mut Vec<(BuiltinFont, lopdf::ObjectId)>,
+                        appearance: &DefaultAppearance, options: &[String], single_line: bool, value: Option<&str>)
+-> lopdf::Dictionary
+{
+    let font_id = add_font(doc, font_ids, appearance.font);
+    let operations = variable_text_appearance(widget, appearance, options, single_line, value);
+    let appearance_id = doc.add_object(appearance_stream(
+        widget.rect.width(), widget.rect.height(), operations, Some(font_resources(&[(appearance.font, font_id)]))));
+
+    let mut dict = widget_dictionary(widget, page_ids, lopdf::Object::Reference(appearance_id));
+    dict.set("DA", lopdf::Object::String(appearance.to_string().into_bytes(), lopdf::StringFormat::Literal));
+    if let Some(value) = value {
+        dict.set("V", pdf_text_string(value));
+    }
+    dict
+}
+
+/// Returns the ID of the font, the font is added to the document if it isn't in `font_ids` yet
+fn add_font(doc: &mut lopdf::Document, font_ids: &mut Vec<(BuiltinFont, lopdf::ObjectId)>, font: BuiltinFont)
+-> lopdf::ObjectId
+{
+    match font_ids.iter().find(|f| f.0 == font) {
+        Some(&(_, id)) => id,
+        None => {
+            let dict: lopdf::Dictionary = font.into();
+            let id = doc.add_object(dict);
+            font_ids.push((font, id));
+            id
+        },
+    }
+}
+
+/// Adds the appearances of a button with the "on" state `on_state` to the document.
+/// `draw` draws the button into a box of the given width and height, the
+/// last argument tells if the button should be drawn in the "on" state.
+fn button_appearance(doc: &mut lopdf::Document, widget: &Widget, on_state: &str, draw: fn(Pt, Pt, bool) -> Vec<Operation>)
+-> lopdf::Object
+{
+    let (width, height) = (widget.rect.width(), widget.rect.height());
+    let on_id = doc.add_object(appearance_stream(width, height, draw(width, height, true), None));
+    let off_id = doc.add_object(appearance_stream(width, height, draw(width, height, false), None));
+
+    lopdf::Object::Dictionary(lopdf::Dictionary::from_iter(vec![
+        (on_state, lopdf::Object::Reference(on_id)),
+        (OFF_STATE, lopdf::Object::Reference(off_id)),
+    ]))
+}
+
+/// Creates a form XObject for an appearance of a widget
+fn appearance_stream(width: Pt, height: Pt, operations: Vec<Operation>, resources: Option<lopdf::Dictionary>)
+-> lopdf::Stream
+{
+    let mut dict = lopdf::Dictionary::from_iter(vec![
+        ("Type", lopdf::Object::Name("XObject".into())),
+        ("Subtype", lopdf::Object::Name("Form".into())),
+        ("BBox", vec![0.into(), 0.into(), width.into(), height.into()].into()),
+    ]);
+    if let Some(resources) = resources {
+        dict.set("Resources", lopdf::Object::Dictionary(resources));
+    }
+    // writing into a Vec<u8> can't fail
+    lopdf::Stream::new(dict, Content { operations }.encode().unwrap())
+}
+
+impl DefaultAppearance {
+
+    pub(crate) fn new(font: BuiltinFont, size: f64, color: Color)
+    -> Self
+    {
+        Self { font, size: size.max(0.0), color }
+    }
+
+    /// Operations that select the font (with the given size) and the color of the text
+    fn operations(&self, size: f64)
+    -> Vec<Operation>
+    {
+        vec![
+            Operation::new("Tf", vec![lopdf::Object::Name(font_resource_name(self.font).into()), size.into()]),
+            PdfColor::FillColor(self.color.clone()).into(),
+        ]
+    }
+}
+
+impl Default for DefaultAppearance {
+    fn default()
+    -> Self
+    {
+        Self::new(BuiltinFont::Helvetica, 0.0, Color::Greyscale(Greyscale::new(0.0, None)))
+    }
+}
+
+impl ::std::fmt::Display for DefaultAppearance {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter)
+    -> ::std::fmt::Result
+    {
+        // writing into a Vec<u8> can't fail
+        let encoded = Content { operations: self.operations(self.size) }.encode().unwrap();
+        let encoded = ::std::string::String::from_utf8_lossy(&encoded);
+        write!(f, "{}", encoded.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Name of the font in the resources of the form, the abbreviations that Acrobat uses
+fn font_resource_name(font: BuiltinFont)
+-> &'static str
+{
+    use BuiltinFont::*;
+    match font {
+        TimesRoman              => "TiRo",
+        TimesBold               => "TiBo",
+        TimesItalic             => "TiIt",
+        TimesBoldItalic         => "TiBI",
+        Helvetica               => "Helv",
+        HelveticaBold           => "HeBo",
+        HelveticaOblique        => "HeOb",
+        HelveticaBoldOblique    => "HeBO",
+        Courier                 => "Cour",
+        CourierOblique          => "CoOb",
+        CourierBold             => "CoBo",
+        CourierBoldOblique      => "CoBO",
+        Symbol                  => "Symb",
+        ZapfDingbats            => "ZaDb",
+    }
+}
+
+/// Resource dictionary containing the fonts of the form
+fn font_resources(font_ids: &[(BuiltinFont, lopdf::ObjectId)])
+-> lopdf::Dictionary
+{
+    let fonts = lopdf::Dictionary::from_iter(font_ids.iter().map(|&(font, id)| {
+        (font_resource_name(font), lopdf::Object::Reference(id))
+    }).collect::<Vec<_>>());
+    lopdf::Dictionary::from_iter(vec![("Font", lopdf::Object::Dictionary(fonts))])
+}
+
+/// Draws a field with variable text: a text field or dropdown (`single_line`) shows the value,
+/// a list box shows as many options as fit into the box and highlights the selected one
+fn variable_text_appearance(widget: &Widget, appearance: &DefaultAppearance, options: &[String], single_line: bool, selected: Option<&str>)
+-> Vec<Operation>
+{
+    let (width, height) = (widget.rect.width(), widget.rect.height());
+    let padding = Pt(2.0);
+    let font_size = if appearance.size > 0.0 {
+        appearance.size
+    } else if single_line {
+        (height.0 - 2.0 * padding.0).clamp(1.0, 12.0)
+    } else {
+        10.0
+    };
+    let line_height = Pt(font_size * 1.15);
+    // distance of the baseline from the bottom of the line
+    let descent = Pt(font_size * 0.22);
+
+    let mut operations = vec![
+        Operation::new("G", vec![0.into()]),
+        Operation::new("w", vec![1.into()]),
+        Operation::new("re", vec![0.5.into(), 0.5.into(), (width - Pt(1.0)).into(), (height - Pt(1.0)).into()]),
+        Operation::new("S", vec![]),
+        Operation::new("BMC", vec![lopdf::Object::Name("Tx".into())]),
+        Operation::new("q", vec![]),
+        Operation::new("re", vec![1.into(), 1.into(), (width - Pt(2.0)).into(), (height - Pt(2.0)).into()]),
+        Operation::new("W", vec![]),
+        Operation::new("n", vec![]),
+    ];
+
+    // (text, bottom of the line)
+    let lines: Vec<(&str, Pt)> = if single_line {
+        selected.map(|s| (s, (height - line_height) / 2.0)).into_iter().collect()
+    } else {
+        options.iter().enumerate().map(|(idx, o)| (o.as_str(), height - padding - line_height * (idx + 1) as f64)).collect()
+    };
+
+    for &(text, bottom) in &lines {
+        if !single_line && selected == Some(text) {
+            operations.push(Operation::new("rg", vec![0.6.into(), 0.75.into(), 0.85.into()]));
+            operations.push(Operation::new("re", vec![1.into(), bottom.into(), (width - Pt(2.0)).into(), line_height.into()]));
+            operations.push(Operation::new("f", vec![]));
+        }
+        operations.push(Operation::new("BT", vec![]));
+        operations.extend(appearance.operations(font_size));
+        operations.extend(vec![
+            Operation::new("Td", vec![padding.into(), (bottom + descent).into()]),
+            Operation::new("Tj", vec![lopdf::Object::String(text.as_bytes().to_vec(), lopdf::StringFormat::Literal)]),
+            Operation::new("ET", vec![]),
+        ]);
+    }
+
+    operations.push(Operation::new("Q", vec![]));
+    operations.push(Operation::new("EMC", vec![]));
+    operations
+}
+
+/// Draws a push button: a grey box with a border and the caption in the center
+fn push_button_appearance(widget: &Widget, appearance: &DefaultAppearance, caption: &str)
+-> Vec<Operation>
+{
+    let (width, height) = (widget.rect.width(), widget.rect.height());
+    let font_size = if appearance.size > 0.0 { appearance.size } else { (height.0 * 0.6).clamp(1.0, 12.0) };
+    let caption_width = Pt(BuiltinMetrics::new(appearance.font).text_width(caption.as_bytes(), 0.5) * font_size);
+    let baseline = (height - Pt(font_size * 0.7)) / 2.0;
+
+    let mut operations = vec![
+        Operation::new("g", vec![0.85.into()]),
+        Operation::new("G", vec![0.into()]),
+        Operation::new("w", vec![1.into()]),
+        Operation::new("re", vec![0.5.into(), 0.5.into(), (width - Pt(1.0)).into(), (height - Pt(1.0)).into()]),
+        Operation::new("B", vec![]),
+        Operation::new("BT", vec![]),
+    ];
+    operations.extend(appearance.operations(font_size));
+    operations.extend(vec![
+        Operation::new("Td", vec![((width - caption_width) / 2.0).into(), baseline.into()]),
+        Operation::new("Tj", vec![lopdf::Object::String(caption.as_bytes().to_vec(), lopdf::StringFormat::Literal)]),
+        Operation::new("ET", vec![]),
+    ]);
+    operations
+}
+
+/// Draws the border of a checkbox and, if it is checked, a check mark
+fn checkbox_appearance(width: Pt, height: Pt, on: bool)
+-> Vec<Operation>
+{
+    let line_width = Pt(1.0);
+    let mut operations = vec![
+        Operation::new("G", vec![0.into()]),
+        Operation::new("w", vec![line_width.into()]),
+        Operation::new("re", vec![(line_width / 2.0).into(), (line_width / 2.0).into(),
+                                  (width - line_width).into(), (height - line_width).into()]),
+        Operation::new("S", vec![]),
+    ];
+
+    if on {
+        let check_mark = Line {
+            points: vec![
+                (Point { x: width * 0.2, y: height * 0.5 }, false),
+                (Point { x: width * 0.4, y: height * 0.25 }, false),
+                (Point { x: width * 0.8, y: height * 0.8 }, false),
+            ],
+            has_stroke: true,
+            .. Default::default()
+        };
+        let check_width = Pt(width.0.min(height.0) * 0.1);
+        operations.push(Operation::new("w", vec![check_width.into()]));
+        operations.extend(check_mark.into_stream_op());
+    }
+
+    operations
+}
+
+/// Draws the outline of a radio button and, if it is selected, a dot in the center
+fn radio_button_appearance(width: Pt, height: Pt, on: bool)
+-> Vec<Operation>
+{
+    let line_width = Pt(1.0);
+    let radius = Pt(width.0.min(height.0) / 2.0);
+    let (center_x, center_y) = (width / 2.0, height / 2.0);
+
+    let outline = Line {
+        points: calculate_points_for_circle(radius - line_width / 2.0, center_x, center_y),
+        is_closed: true,
+        has_stroke: true,
+        .. Default::default()
+    };
+
+    let mut operations = vec![
+        Operation::new("G", vec![0.into()]),
+        Operation::new("w", vec![line_width.into()]),
+    ];
+    operations.extend(outline.into_stream_op());
+
+    if on {
+        let dot = Line {
+            points: calculate_points_for_circle(radius * 0.5, center_x, center_y),
+            is_closed: true,
+            has_fill: true,
+            .. Default::default()
+        };
+        operations.push(Operation::new("g", vec![0.into()]));
+        operations.extend(dot.into_stream_op());
+    }
+
+    operations
+}
+
+#[test]
+fn test_validate_form() {
+    let rect = Rect::new(::Mm(10.0), ::Mm(10.0), ::Mm(5.0), ::Mm(5.0));
+    let mut form = AcroForm::default();
+    form.add_checkbox("agree".into(), 0, rect, false);
+    form.add_radio_group("size".into(), 0, vec![("S".into(), rect), ("L".into(), rect)]);
+    form.add_choice_field("color".into(), 0, rect, vec!["Red".into(), "Blue".into()], true);
+    form.set_value("size".into(), "L".into());
+    form.set_value("color".into(), "Blue".into());
+    let mut errors = Vec::new();
+    form.validate(&mut errors);
+    assert!(errors.is_empty());
+
+    form.add_checkbox("agree".into(), 1, rect, true);
+    form.add_checkbox("agree".into(), 1, rect, true);
+    form.add_choice_field("shape".into(), 0, rect, vec!["".into(), "Square".into(), "Square".into()], false);
+    form.set_value("color".into(), "Green".into());
+    form.set_value("missing".into(), "Yes".into());
+    form.set_action("total".into(), FieldTrigger::Calculate, "event.value = 1;".into());
+    form.validate(&mut errors);
+    assert_eq!(errors, vec![
+        ValidationError::DuplicateFieldName { name: "agree".into() },
+        ValidationError::InvalidFieldOption { field: "shape".into(), option: "".into() },
+        ValidationError::InvalidFieldOption { field: "shape".into(), option: "Square".into() },
+        ValidationError::UnknownField { field: "total".into() },
+        ValidationError::InvalidFieldValue { field: "color".into(), value: "Green".into() },
+        ValidationError::UnknownField { field: "missing".into() },
+    ]);
+}
+
+#[test]
+fn test_default_appearance() {
+    assert_eq!(DefaultAppearance::default().to_string(), "/Helv 0.0 Tf 0.0 g");
+
+    let appearance = DefaultAppearance::new(BuiltinFont::CourierBold, 11.0, Color::Rgb(::Rgb::new(1.0, 0.0, 0.0, None)));
+    assert_eq!(appearance.to_string(), "/CoBo 11.0 Tf 1.0 0.0 0.0 rg");
+    assert_eq!(format!("{:?}", appearance.operations(8.0)[0].operands), format!("{:?}", vec![lopdf::Object::Name("CoBo".into()), 8.0.into()]));
+}
+
+#[test]
+fn test_button_action() {
+    use lopdf::Object::*;
+
+    let action = ButtonAction::SubmitForm { url: "https://example.com/submit".into(), format: SubmitFormat::Xfdf };
+    assert_eq!(SubmitFormat::Html.flags(), SUBMIT_FLAG_EXPORT_FORMAT);
+    assert_eq!(SubmitFormat::Fdf.flags(), 0);
+
+    let rect = Rect::new(::Mm(10.0), ::Mm(10.0), ::Mm(40.0), ::Mm(20.0));
+    let mut form = AcroForm::default();
+    form.add_push_button("send".into(), 0, rect, "Send".into(), action);
+    form.set_value("send".into(), "Yes".into());
+    let mut errors = Vec::new();
+    form.validate(&mut errors);
+    assert_eq!(errors, vec![ValidationError::InvalidFieldValue { field: "send".into(), value: "Yes".into() }]);
+
+    let widget = Widget { page: 0, rect };
+    let operations = push_button_appearance(&widget, &DefaultAppearance::default(), "Send");
+    let caption = operations.iter().find(|o| o.operator == "Tj").unwrap();
+    assert_eq!(format!("{:?}", caption.operands), format!("{:?}", vec![String(b"Send".to_vec(), lopdf::StringFormat::Literal)]));
+}
+
+#[test]
+fn test_redact_form() {
+    let mut form = AcroForm::default();
+    form.add_text_field("name".into(), 0, Rect::new(::Mm(10.0), ::Mm(10.0), ::Mm(50.0), ::Mm(8.0)));
+    form.add_text_field("city".into(), 0, Rect::new(::Mm(10.0), ::Mm(30.0), ::Mm(50.0), ::Mm(8.0)));
+    form.add_text_field("other".into(), 1, Rect::new(::Mm(10.0), ::Mm(10.0), ::Mm(50.0), ::Mm(8.0)));
+    form.set_value("name".into(), "Jane Doe".into());
+    form.set_value("city".into(), "Berlin".into());
+    form.set_value("other".into(), "Secret".into());
+
+    form.redact(0, &Rect::new(::Mm(0.0), ::Mm(0.0), ::Mm(100.0), ::Mm(20.0)));
+    assert_eq!(form.values, vec![("city".to_string(), "Berlin".to_string()), ("other".to_string(), "Secret".to_string())]);
+    assert_eq!(form.len(), 3);
+}
+
+#[test]
+fn test_translate_widgets() {
+    let rect = Rect::new(::Mm(10.0), ::Mm(10.0), ::Mm(5.0), ::Mm(5.0));
+    let mut form = AcroForm::default();
+    form.add_radio_group("size".into(), 0, vec![("S".into(), rect), ("L".into(), rect)]);
+    form.add_text_field("name".into(), 1, rect);
+
+    form.translate(0, Pt(-5.0), Pt(20.0));
+    let moved = rect.translated(Pt(-5.0), Pt(20.0));
+    assert!(form.fields[0].widgets().iter().all(|w| w.rect == moved));
+    assert_eq!(form.fields[1].widgets()[0].rect, rect);
+}
+
+#[test]
+fn test_field_actions() {
+    let rect = Rect::new(::Mm(10.0), ::Mm(10.0), ::Mm(50.0), ::Mm(8.0));
+    let mut form = AcroForm::default();
+    form.add_text_field("price".into(), 0, rect);
+    form.add_text_field("tax".into(), 0, rect);
+    form.add_text_field("total".into(), 0, rect);
+    form.set_action("total".into(), FieldTrigger::Calculate, "AFSimple_Calculate(\"SUM\", [\"price\", \"tax\"]);".into());
+    form.set_action("total".into(), FieldTrigger::Format, "AFNumber_Format(2, 0, 0, 0, \"\", true);".into());
+    form.set_action("tax".into(), FieldTrigger::Calculate, "event.value = 0.19 * this.getField(\"price\").value;".into());
+
+    let mut doc = lopdf::Document::with_version("1.3");
+    let page_ids = vec![doc.new_object_id()];
+    let widget_ids = form.reserve_widget_ids(&mut doc, 1);
+    let acro_form = form.into_with_document(&mut doc, widget_ids, &page_ids);
+
+    let fields: Vec<lopdf::ObjectId> = acro_form.get(b"Fields").unwrap().as_array().unwrap()
+        .iter().map(|f| f.as_reference().unwrap()).collect();
+    // the fields are calculated in the order the calculate actions were set
+    let order: Vec<lopdf::ObjectId> = acro_form.get(b"CO").unwrap().as_array().unwrap()
+        .iter().map(|f| f.as_reference().unwrap()).collect();
+    assert_eq!(order, vec![fields[2], fields[1]]);
+
+    let triggers = |id| doc.get_object(id).unwrap().as_dict().unwrap().get(b"AA").ok()
+        .map(|aa| aa.as_dict().unwrap().iter().map(|(k, _)| k.clone()).collect::<Vec<_>>());
+    assert_eq!(triggers(fields[0]), None);
+    assert_eq!(triggers(fields[1]), Some(vec![b"C".to_vec()]));
+    assert_eq!(triggers(fields[2]), Some(vec![b"C".to_vec(), b"F".to_vec()]));
+}
@@ -1 +1,8 @@
 //! Interactive elements such a comment / annotation, etc.
+
+pub mod annotation;
+pub(crate) mod attachment;
+pub mod bookmark;
+pub mod destination;
+pub(crate) mod flatten;
+pub mod form;
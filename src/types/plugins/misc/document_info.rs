@@ -3,13 +3,20 @@
 use lopdf;
 use time::OffsetDateTime;
 
-use PdfConformance;
+use utils::pdf_text_string;
+use {PdfConformance, Trapped, Error, PdfError};
+
+/// Keys that are written from the other metadata of the document
+const RESERVED_KEYS: [&str; 5] = ["Title", "Trapped", "CreationDate", "ModDate", "GTS_PDFXVersion"];
 
 /// "Info" dictionary of a PDF document.
 /// Actual data is contained in `DocumentMetadata`, to keep it in sync with the `XmpMetadata`
 /// (if the timestamps / settings are not in sync, Preflight will complain)
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Clone)]
 pub struct DocumentInfo {
+    /// Custom entries (key, value), see `PdfDocumentReference::set_info_entry()`
+    pub(crate) custom_entries: Vec<(String, String)>,
+
     // DocumentInfo is older than XmpMetadata
     // The following is a list of things available to the DocumentInfo dictionary.
     // These keys don't have to be set:
@@ -63,11 +70,28 @@ impl DocumentInfo {
         Self::default()
     }
 
+    /// Adds a custom entry or replaces the value of an existing entry. Fails with
+    /// `PdfError::InvalidInfoKey` if the key isn't a valid PDF name or is one of the
+    /// keys that are written from the other metadata (e.g. `Title`).
+    pub(crate) fn set_entry(&mut self, key: String, value: String)
+    -> Result<(), Error>
+    {
+        if !is_valid_key(&key) || RESERVED_KEYS.contains(&key.as_str()) {
+            return Err(Error::Pdf(PdfError::InvalidInfoKey));
+        }
+
+        match self.custom_entries.iter_mut().find(|entry| entry.0 == key) {
+            Some(entry) => entry.1 = value,
+            None => self.custom_entries.push((key, value)),
+        }
+        Ok(())
+    }
+
     /// This functions is similar to the IntoPdfObject trait method,
     /// but takes additional arguments in order to delay the setting
     pub(in types) fn into_obj<S>(self,
                                  document_title: S,
-                                 trapping: bool,
+                                 trapping: Trapped,
                                  conformance: PdfConformance,
                                  creation_date: OffsetDateTime,
                                  modification_date: OffsetDateTime)
@@ -78,24 +102,37 @@ impl DocumentInfo {
         use lopdf::StringFormat::Literal;
         use std::iter::FromIterator;
 
-        let trapping = if trapping { "True" } else { "False" };
+        let trapping = trapping.as_str();
         let gts_pdfx_version = conformance.get_identifier_string();
 
         let info_mod_date = to_pdf_time_stamp_metadata(modification_date);
         let info_create_date = to_pdf_time_stamp_metadata(creation_date);
 
-        Dictionary(LoDictionary::from_iter(vec![
+        let mut info = LoDictionary::from_iter(vec![
             ("Trapped", trapping.into()),
             ("CreationDate", String(info_create_date.into_bytes(), Literal)),
             ("ModDate", String(info_mod_date.into_bytes(), Literal)),
             ("GTS_PDFXVersion", String(gts_pdfx_version.into(), Literal)),
             ("Title", String(document_title.into().as_bytes().to_vec(), Literal))
-        ]))
+        ]);
+        for (key, value) in self.custom_entries {
+            info.set(key, pdf_text_string(&value));
+        }
+
+        Dictionary(info)
     }
 }
 
+/// Checks that the key can be written as a PDF name without escaping: printable ASCII
+/// without whitespace, delimiters (`()<>[]{}/%`) and `#`
+fn is_valid_key(key: &str)
+-> bool
+{
+    !key.is_empty() && key.bytes().all(|c| (b'!'..=b'~').contains(&c) && !b"()<>[]{}/%#".contains(&c))
+}
+
 // D:20170505150224+02'00'
-fn to_pdf_time_stamp_metadata(date: OffsetDateTime)
+pub(crate) fn to_pdf_time_stamp_metadata(date: OffsetDateTime)
 -> String
 {
     // Since the time is in UTC, we know that the time zone
@@ -109,3 +146,18 @@ fn to_pdf_time_stamp_metadata(date: OffsetDateTime)
         date.second(),
     )
 }
+
+#[test]
+fn test_set_info_entry()
+{
+    let mut info = DocumentInfo::new();
+    info.set_entry("AssetId".into(), "1234".into()).unwrap();
+    info.set_entry("Author".into(), "Jane".into()).unwrap();
+    info.set_entry("AssetId".into(), "5678".into()).unwrap();
+    assert_eq!(info.custom_entries, vec![("AssetId".to_string(), "5678".to_string()), ("Author".to_string(), "Jane".to_string())]);
+
+    for key in &["", "Asset Id", "Asset/Id", "Größe", "Title", "ModDate"] {
+        assert!(matches!(info.set_entry(key.to_string(), "x".into()), Err(Error::Pdf(PdfError::InvalidInfoKey))), "{}", key);
+    }
+    assert_eq!(info.custom_entries.len(), 2);
+}
@@ -0,0 +1,311 @@
+//! Decryption of the strings and streams of an encrypted document
+//! (PDF Reference, section 3.5.1 "General Encryption Algorithm")
+//!
+//! Only the algorithms that the standard security handler of PDF 1.6 uses are implemented:
+//! RC4 with 40 to 128 bit keys and AES with 128 bit keys. The hash and cipher functions are
+//! implemented here, because they are small and the crate has no dependency that provides them.
+
+use lopdf;
+use lopdf::{Object, ObjectId};
+use std::collections::HashSet;
+
+/// Algorithm that is used for the strings or the streams of a document
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum CryptMethod {
+    /// The data isn't encrypted (`/Identity` crypt filter, or `/CFM /None`)
+    Identity,
+    /// RC4, `/V 1`, `/V 2` or `/CFM /V2`
+    Rc4,
+    /// AES-128 in CBC mode, `/CFM /AESV2`
+    Aes128,
+}
+
+/// Key that is used for the strings and streams of an object (Algorithm 3.1)
+pub(crate) fn object_key(file_key: &[u8], id: ObjectId, method: CryptMethod)
+-> Vec<u8>
+{
+    let mut data = file_key.to_vec();
+    data.extend_from_slice(&id.0.to_le_bytes()[..3]);
+    data.extend_from_slice(&id.1.to_le_bytes());
+    if method == CryptMethod::Aes128 {
+        data.extend_from_slice(b"sAlT");
+    }
+    let length = (file_key.len() + 5).min(16);
+    md5(&data)[..length].to_vec()
+}
+
+/// Decrypts a string or the content of a stream of the object `id`. AES data that isn't a
+/// multiple of the block size is returned as it is.
+pub(crate) fn decrypt_data(file_key: &[u8], id: ObjectId, method: CryptMethod, data: &[u8])
+-> Vec<u8>
+{
+    match method {
+        CryptMethod::Identity => data.to_vec(),
+        CryptMethod::Rc4 => rc4(&object_key(file_key, id, method), data),
+        CryptMethod::Aes128 => aes128_cbc_decrypt(&object_key(file_key, id, method), data).unwrap_or_else(|| data.to_vec()),
+    }
+}
+
+/// Decrypts all strings and streams of the document, except for the encryption dictionary
+/// and the objects in `skipped` (the metadata stream, if `/EncryptMetadata` is false).
+/// Cross-reference streams are never encrypted.
+pub(crate) fn decrypt_objects(doc: &mut lopdf::Document, file_key: &[u8], strings: CryptMethod, streams: CryptMethod,
+                              skipped: &HashSet<ObjectId>)
+{
+    for (&id, object) in doc.objects.iter_mut() {
+        if skipped.contains(&id) {
+            continue;
+        }
+        if let Object::Stream(ref mut stream) = *object {
+            let is_xref = stream.dict.get(b"Type").and_then(Object::as_name).map(|t| t == b"XRef").unwrap_or(false);
+            if is_xref {
+                continue;
+            }
+            let content = decrypt_data(file_key, id, streams, &stream.content);
+            stream.set_content(content);
+        }
+        decrypt_strings(object, file_key, id, strings);
+    }
+}
+
+/// Decrypts the strings of the object, including the strings of nested arrays and dictionaries
+fn decrypt_strings(object: &mut Object, file_key: &[u8], id: ObjectId, method: CryptMethod)
+{
+    match *object {
+        Object::String(ref mut text, _) => *text = decrypt_data(file_key, id, method, text),
+        Object::Array(ref mut array) => for item in array { decrypt_strings(item, file_key, id, method); },
+        Object::Dictionary(ref mut dict) => for (_, value) in dict.iter_mut() { decrypt_strings(value, file_key, id, method); },
+        Object::Stream(ref mut stream) => for (_, value) in stream.dict.iter_mut() { decrypt_strings(value, file_key, id, method); },
+        _ => { },
+    }
+}
+
+/// RC4 encryption, which is the same as the decryption
+pub(crate) fn rc4(key: &[u8], data: &[u8])
+-> Vec<u8>
+{
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j = 0_u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0_u8, 0_u8);
+    data.iter().map(|byte| {
+        i = i.wrapping_add(1);
+        j = j.wrapping_add(state[i as usize]);
+        state.swap(i as usize, j as usize);
+        byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+    }).collect()
+}
+
+/// MD5 hash (RFC 1321)
+pub(crate) fn md5(data: &[u8])
+-> [u8; 16]
+{
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+    let constants: Vec<u32> = (0..64).map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32).collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut hash: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+    for chunk in message.chunks(64) {
+        let words: Vec<u32> = chunk.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect();
+        let [mut a, mut b, mut c, mut d] = hash;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a.wrapping_add(f).wrapping_add(constants[i]).wrapping_add(words[g])
+                .rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        hash[0] = hash[0].wrapping_add(a);
+        hash[1] = hash[1].wrapping_add(b);
+        hash[2] = hash[2].wrapping_add(c);
+        hash[3] = hash[3].wrapping_add(d);
+    }
+
+    let mut digest = [0_u8; 16];
+    for (idx, word) in hash.iter().enumerate() {
+        digest[idx * 4..idx * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+/// Decrypts AES-128 data in CBC mode, the first 16 bytes are the initialization vector
+/// and the padding (PKCS #5) is removed. Returns `None` if the data isn't valid.
+pub(crate) fn aes128_cbc_decrypt(key: &[u8], data: &[u8])
+-> Option<Vec<u8>>
+{
+    if key.len() != 16 || data.len() < 32 || !data.len().is_multiple_of(16) {
+        return None;
+    }
+
+    let aes = Aes128::new(key);
+    let mut decrypted = Vec::with_capacity(data.len() - 16);
+    for (previous, block) in data.chunks(16).zip(data.chunks(16).skip(1)) {
+        let mut state = [0_u8; 16];
+        state.copy_from_slice(block);
+        aes.decrypt_block(&mut state);
+        decrypted.extend(state.iter().zip(previous).map(|(a, b)| a ^ b));
+    }
+
+    let padding = *decrypted.last()? as usize;
+    if padding == 0 || padding > 16 {
+        return None;
+    }
+    let length = decrypted.len() - padding;
+    decrypted.truncate(length);
+    Some(decrypted)
+}
+
+/// AES-128 block cipher (FIPS 197), only the decryption
+struct Aes128 {
+    /// Round keys, 11 blocks of 16 bytes
+    round_keys: [[u8; 16]; 11],
+    /// Inverse S-box
+    inverse_sbox: [u8; 256],
+}
+
+impl Aes128 {
+
+    fn new(key: &[u8])
+    -> Self
+    {
+        let sbox = aes_sbox();
+        let mut inverse_sbox = [0_u8; 256];
+        for (idx, value) in sbox.iter().enumerate() {
+            inverse_sbox[*value as usize] = idx as u8;
+        }
+
+        let mut words = [[0_u8; 4]; 44];
+        for (idx, word) in key.chunks(4).enumerate() {
+            words[idx].copy_from_slice(word);
+        }
+        let mut round_constant = 1_u8;
+        for idx in 4..44 {
+            let mut word = words[idx - 1];
+            if idx % 4 == 0 {
+                word = [sbox[word[1] as usize] ^ round_constant, sbox[word[2] as usize], sbox[word[3] as usize], sbox[word[0] as usize]];
+                round_constant = gf_multiply(round_constant, 2);
+            }
+            for byte in 0..4 {
+                words[idx][byte] = words[idx - 4][byte] ^ word[byte];
+            }
+        }
+
+        let mut round_keys = [[0_u8; 16]; 11];
+        for (round, round_key) in round_keys.iter_mut().enumerate() {
+            for column in 0..4 {
+                round_key[column * 4..column * 4 + 4].copy_from_slice(&words[round * 4 + column]);
+            }
+        }
+
+        Self { round_keys, inverse_sbox }
+    }
+
+    fn decrypt_block(&self, state: &mut [u8; 16])
+    {
+        let add_round_key = |state: &mut [u8; 16], round: usize| {
+            for (byte, key) in state.iter_mut().zip(self.round_keys[round].iter()) {
+                *byte ^= key;
+            }
+        };
+
+        add_round_key(state, 10);
+        for round in (0..10).rev() {
+            // inverse shift rows: row r is rotated right by r, the state is stored column by column
+            let copy = *state;
+            for column in 0..4 {
+                for row in 0..4 {
+                    state[column * 4 + row] = self.inverse_sbox[copy[((column + 4 - row) % 4) * 4 + row] as usize];
+                }
+            }
+            add_round_key(state, round);
+            if round == 0 {
+                break;
+            }
+            for column in state.chunks_mut(4) {
+                let c = [column[0], column[1], column[2], column[3]];
+                for row in 0..4 {
+                    column[row] = gf_multiply(c[row], 14) ^ gf_multiply(c[(row + 1) % 4], 11)
+                        ^ gf_multiply(c[(row + 2) % 4], 13) ^ gf_multiply(c[(row + 3) % 4], 9);
+                }
+            }
+        }
+    }
+}
+
+/// Multiplication in GF(2^8) with the AES polynomial
+fn gf_multiply(mut a: u8, mut b: u8)
+-> u8
+{
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// The AES S-box: the multiplicative inverse in GF(2^8), followed by an affine transformation
+fn aes_sbox()
+-> [u8; 256]
+{
+    let mut sbox = [0_u8; 256];
+    for (value, entry) in sbox.iter_mut().enumerate() {
+        let inverse = if value == 0 { 0 } else { (1..=255_u8).find(|i| gf_multiply(value as u8, *i) == 1).unwrap_or(0) };
+        *entry = inverse ^ inverse.rotate_left(1) ^ inverse.rotate_left(2) ^ inverse.rotate_left(3) ^ inverse.rotate_left(4) ^ 0x63;
+    }
+    sbox
+}
+
+#[test]
+fn test_md5_rc4() {
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(hex(&md5(b"The quick brown fox jumps over the lazy dog")), "9e107d9d372bb6826bd81d3542a419d6");
+    assert_eq!(hex(&md5(&[b'a'; 100])), "36a92cc94a9e0fa21f625f8bfb007adf");
+
+    assert_eq!(hex(&rc4(b"Key", b"Plaintext")), "bbf316e8d940af0ad3");
+    assert_eq!(rc4(b"Key", &rc4(b"Key", b"Plaintext")), b"Plaintext".to_vec());
+}
+
+#[test]
+fn test_aes128_decrypt() {
+    // FIPS 197, appendix C.1
+    let key: Vec<u8> = (0..16).collect();
+    let mut block = [0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a];
+    Aes128::new(&key).decrypt_block(&mut block);
+    assert_eq!(block.to_vec(), (0..16).map(|i| i * 0x11).collect::<Vec<u8>>());
+
+    // "printpdf", padded to one block, with the IV 16 ... 31
+    let mut data: Vec<u8> = (16..32).collect();
+    data.extend_from_slice(&[0xd2, 0xab, 0x01, 0x95, 0xfe, 0xbd, 0xd4, 0x7b, 0x29, 0x57, 0x0e, 0xda, 0x7a, 0xf4, 0x47, 0x4b]);
+    assert_eq!(aes128_cbc_decrypt(&key, &data), Some(b"printpdf".to_vec()));
+    assert_eq!(aes128_cbc_decrypt(&key, &data[..24]), None);
+}
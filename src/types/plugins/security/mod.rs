@@ -1,3 +1,3 @@
-pub mod encrypt;
-pub mod password;
+pub(crate) mod encrypt;
+pub(crate) mod password;
 pub mod signature;
\ No newline at end of file
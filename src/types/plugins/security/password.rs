@@ -0,0 +1,234 @@
+//! Passwords of the standard security handler
+//! (PDF Reference, section 3.5.2 "Standard Security Handler", revisions 2 to 4)
+//!
+//! The key that the strings and streams are encrypted with is computed from the user password.
+//! The owner password decrypts the user password from the `/O` entry, so either password can
+//! be used to open a document.
+
+use lopdf;
+use lopdf::Object;
+use {Error, PdfError};
+use super::encrypt::{CryptMethod, md5, rc4};
+
+/// Padding of passwords to 32 bytes (Algorithm 3.2, step 1)
+const PASSWORD_PADDING: [u8; 32] = [
+    0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa, 0x01, 0x08,
+    0x2e, 0x2e, 0x00, 0xb6, 0xd0, 0x68, 0x3e, 0x80, 0x2f, 0x0c, 0xa9, 0xfe, 0x64, 0x53, 0x69, 0x7a,
+];
+
+/// Encryption dictionary of the standard security handler
+#[derive(Debug, Clone)]
+pub(crate) struct StandardSecurityHandler {
+    /// Revision of the handler (`/R`)
+    revision: i64,
+    /// Length of the file key in bytes
+    key_length: usize,
+    /// Hash of the owner and user passwords (`/O`)
+    owner: Vec<u8>,
+    /// Hash of the user password (`/U`)
+    user: Vec<u8>,
+    /// Permissions (`/P`)
+    permissions: i32,
+    /// First string of the `/ID` of the document
+    id: Vec<u8>,
+    /// Whether the metadata stream is encrypted (`/EncryptMetadata`, revision 4)
+    pub(crate) encrypt_metadata: bool,
+    /// Algorithm for strings (`/StrF`)
+    pub(crate) strings: CryptMethod,
+    /// Algorithm for streams (`/StmF`)
+    pub(crate) streams: CryptMethod,
+}
+
+impl StandardSecurityHandler {
+
+    /// Reads the encryption dictionary, fails with `PdfError::UnsupportedEncryption` if the
+    /// document uses another security handler or algorithm
+    pub(crate) fn new(encrypt: &lopdf::Dictionary, id: &[u8])
+    -> Result<Self, Error>
+    {
+        let unsupported = || Error::Pdf(PdfError::UnsupportedEncryption);
+        let integer = |key: &[u8]| encrypt.get(key).and_then(Object::as_i64).ok();
+        let string = |key: &[u8]| match encrypt.get(key) {
+            Ok(Object::String(value, _)) => Some(value.clone()),
+            _ => None,
+        };
+
+        if encrypt.get(b"Filter").and_then(Object::as_name).ok() != Some(&b"Standard"[..]) {
+            return Err(unsupported());
+        }
+
+        let version = integer(b"V").unwrap_or(0);
+        let revision = integer(b"R").ok_or_else(unsupported)?;
+        let (key_length, strings, streams) = match version {
+            1 => (5, CryptMethod::Rc4, CryptMethod::Rc4),
+            2 => (integer(b"Length").unwrap_or(40) as usize / 8, CryptMethod::Rc4, CryptMethod::Rc4),
+            4 => {
+                let filter = |key: &[u8]| -> Result<(CryptMethod, usize), Error> {
+                    let name = encrypt.get(key).and_then(Object::as_name).unwrap_or(b"Identity");
+                    if name == b"Identity" {
+                        return Ok((CryptMethod::Identity, 16));
+                    }
+                    let crypt_filter = encrypt.get(b"CF").and_then(Object::as_dict)
+                        .and_then(|filters| filters.get(name)).and_then(Object::as_dict)
+                        .map_err(|_| unsupported())?;
+                    let length = crypt_filter.get(b"Length").and_then(Object::as_i64).unwrap_or(16);
+                    // the length is in bytes, but some writers use bits
+                    let length = if length > 16 { length / 8 } else { length } as usize;
+                    match crypt_filter.get(b"CFM").and_then(Object::as_name).unwrap_or(b"None") {
+                        b"None" => Ok((CryptMethod::Identity, length)),
+                        b"V2" => Ok((CryptMethod::Rc4, length)),
+                        b"AESV2" => Ok((CryptMethod::Aes128, 16)),
+                        _ => Err(unsupported()),
+                    }
+                };
+                let (strings, string_key_length) = filter(b"StrF")?;
+                let (streams, stream_key_length) = filter(b"StmF")?;
+                (string_key_length.max(stream_key_length), strings, streams)
+            },
+            _ => return Err(unsupported()),
+        };
+
+        let owner = string(b"O").ok_or_else(unsupported)?;
+        let user = string(b"U").ok_or_else(unsupported)?;
+        let permissions = integer(b"P").ok_or_else(unsupported)? as i32;
+        let encrypt_metadata = match encrypt.get(b"EncryptMetadata") {
+            Ok(&Object::Boolean(value)) => value,
+            _ => true,
+        };
+
+        if owner.len() < 32 || user.len() < 32 || !(2..=4).contains(&revision) || !(5..=16).contains(&key_length) {
+            return Err(unsupported());
+        }
+
+        Ok(Self {
+            revision,
+            key_length,
+            owner,
+            user,
+            permissions,
+            id: id.to_vec(),
+            encrypt_metadata,
+            strings,
+            streams,
+        })
+    }
+
+    /// Returns the file key if `password` is the user or the owner password
+    pub(crate) fn file_key(&self, password: &[u8])
+    -> Option<Vec<u8>>
+    {
+        self.user_file_key(password).or_else(|| self.user_file_key(&self.user_password(password)))
+    }
+
+    /// Computes the file key from the user password (Algorithm 3.2) and checks it
+    /// against `/U` (Algorithm 3.4 / 3.5)
+    fn user_file_key(&self, password: &[u8])
+    -> Option<Vec<u8>>
+    {
+        let mut data = pad_password(password).to_vec();
+        data.extend_from_slice(&self.owner[..32]);
+        data.extend_from_slice(&self.permissions.to_le_bytes());
+        data.extend_from_slice(&self.id);
+        if self.revision >= 4 && !self.encrypt_metadata {
+            data.extend_from_slice(&[0xff; 4]);
+        }
+
+        let mut key = md5(&data)[..self.key_length].to_vec();
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                key = md5(&key)[..self.key_length].to_vec();
+            }
+        }
+
+        let matches = if self.revision == 2 {
+            rc4(&key, &PASSWORD_PADDING) == self.user[..32].to_vec()
+        } else {
+            let mut data = PASSWORD_PADDING.to_vec();
+            data.extend_from_slice(&self.id);
+            let hash = rc4_iterations(&key, &md5(&data), 0..20);
+            hash[..16] == self.user[..16]
+        };
+
+        if matches { Some(key) } else { None }
+    }
+
+    /// Decrypts the user password from `/O` with the owner password (Algorithm 3.7)
+    fn user_password(&self, owner_password: &[u8])
+    -> Vec<u8>
+    {
+        let mut hash = md5(&pad_password(owner_password));
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = md5(&hash);
+            }
+        }
+        let length = if self.revision == 2 { 5 } else { self.key_length };
+        let key = &hash[..length];
+
+        if self.revision == 2 {
+            rc4(key, &self.owner[..32])
+        } else {
+            rc4_iterations(key, &self.owner[..32], (0..20).rev())
+        }
+    }
+}
+
+/// Encrypts the data with RC4 once for each number of `iterations`, the key is XOR-ed
+/// with the number (Algorithm 3.5, step 5)
+fn rc4_iterations<I: Iterator<Item = u8>>(key: &[u8], data: &[u8], iterations: I)
+-> Vec<u8>
+{
+    iterations.fold(data.to_vec(), |data, iteration| {
+        let key: Vec<u8> = key.iter().map(|k| k ^ iteration).collect();
+        rc4(&key, &data)
+    })
+}
+
+/// Pads or truncates the password to 32 bytes
+fn pad_password(password: &[u8])
+-> [u8; 32]
+{
+    let mut padded = PASSWORD_PADDING;
+    let length = password.len().min(32);
+    padded[..length].copy_from_slice(&password[..length]);
+    padded[length..].copy_from_slice(&PASSWORD_PADDING[..32 - length]);
+    padded
+}
+
+/// Converts a password to bytes, passwords of the standard security handler are encoded in
+/// PDFDocEncoding, which matches Latin-1 for printable characters
+pub(crate) fn password_bytes(password: &str)
+-> Vec<u8>
+{
+    password.chars().map(|c| if (c as u32) < 256 { c as u8 } else { b'?' }).collect()
+}
+
+#[test]
+fn test_standard_security_handler() {
+    // revision 3, 128 bit RC4, user password "user", owner password "owner"
+    let handler = StandardSecurityHandler {
+        revision: 3,
+        key_length: 16,
+        owner: vec![
+            0x0b, 0xa3, 0x83, 0x5f, 0x88, 0xf9, 0x03, 0x88, 0xe7, 0x4e, 0x54, 0x58, 0x41, 0x25, 0xce, 0x14,
+            0x2b, 0xe0, 0xde, 0x24, 0xc6, 0xb0, 0xd3, 0x77, 0x46, 0xe0, 0x75, 0xb8, 0x91, 0x75, 0x66, 0x71,
+        ],
+        user: vec![
+            0x26, 0x31, 0x99, 0x1c, 0xa1, 0xf6, 0x81, 0x67, 0x0a, 0xba, 0x5d, 0xed, 0xd8, 0x1f, 0x81, 0x2b,
+            0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa, 0x01, 0x08,
+        ],
+        permissions: -4,
+        id: b"0123456789abcdef".to_vec(),
+        encrypt_metadata: true,
+        strings: CryptMethod::Rc4,
+        streams: CryptMethod::Rc4,
+    };
+
+    let key = handler.file_key(b"user").unwrap();
+    assert_eq!(key.len(), 16);
+    assert_eq!(handler.file_key(b"owner"), Some(key));
+    assert_eq!(handler.file_key(b"wrong"), None);
+
+    assert_eq!(pad_password(b"ab")[..4], [b'a', b'b', 0x28, 0xbf]);
+    assert_eq!(password_bytes("pässwörd€"), b"p\xe4ssw\xf6rd?".to_vec());
+}
@@ -3,7 +3,7 @@
 use time::OffsetDateTime;
 use lopdf;
 
-use PdfConformance;
+use {PdfConformance, Trapped};
 use utils::random_character_string_32;
 
 /// Initial struct for Xmp metatdata. This should be expanded later for XML handling, etc.
@@ -34,9 +34,11 @@ impl XmpMetadata {
 
     /// Consumes the XmpMetadata and turns it into a PDF Object.
     /// This is similar to the
+    #[allow(clippy::too_many_arguments)]
     pub(in types) fn into_obj<S>(self,
                            conformance: PdfConformance,
-                           trapping: bool,
+                           instance_id: String,
+                           trapping: Trapped,
                            creation_date: OffsetDateTime,
                            modification_date: OffsetDateTime,
                            metadata_date: OffsetDateTime,
@@ -48,10 +50,8 @@ impl XmpMetadata {
         use std::iter::FromIterator;
 
         // Shared between XmpMetadata and DocumentInfo
-        let trapping = if trapping { "True" } else { "False" };
+        let trapping = trapping.as_str();
 
-        // let xmp_instance_id = "2898d852-f86f-4479-955b-804d81046b19";
-        let instance_id = random_character_string_32();
         let create_date = to_pdf_xmp_date(creation_date);
         let modification_date = to_pdf_xmp_date(modification_date);
         let metadata_date = to_pdf_xmp_date(metadata_date);
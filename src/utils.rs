@@ -1,5 +1,7 @@
 //! Utility / conveniece functions for commonly use graphical shapes
 
+use lopdf;
+use lopdf::content::Operation;
 use scale::Pt;
 use Point;
 
@@ -88,8 +90,16 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 static RAND_SEED: AtomicUsize = AtomicUsize::new(2100);
 
 /// Xorshift-based random number generator. Impure function
+///
+/// The seed is mixed with the random keys of the standard library's `RandomState`
+/// (which come from the operating system), otherwise every run of a program would
+/// produce the same "random" document IDs
 pub(crate) fn rand() -> usize {
-    let mut x = RAND_SEED.fetch_add(21, Ordering::SeqCst);
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let entropy = RandomState::new().build_hasher().finish() as usize;
+    let mut x = RAND_SEED.fetch_add(21, Ordering::SeqCst) ^ entropy;
     #[cfg(target_pointer_width = "64")] {
         x ^= x << 21;
         x ^= x >> 35;
@@ -107,13 +117,44 @@ pub(crate) fn rand() -> usize {
 
 /// Returns a string with 32 random characters
 pub(crate) fn random_character_string_32() -> String {
+    character_string_32(rand)
+}
+
+/// Pseudo-random generator with a fixed seed, so that the "random" parts of a
+/// document are the same in every run, see `PdfDocumentReference::set_random_seed()`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SeededRandom(u64);
+
+impl SeededRandom {
+
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        SeededRandom(if seed == 0 { 2100 } else { seed })
+    }
+
+    /// Xorshift64*
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a string with 32 characters, like `random_character_string_32()`
+    pub(crate) fn character_string_32(&mut self) -> String {
+        character_string_32(|| self.next_u64() as usize)
+    }
+}
+
+/// Builds a string of 32 uppercase letters from the decimal digits of the numbers
+fn character_string_32<F: FnMut() -> usize>(mut next: F) -> String {
 
     const MAX_CHARS: usize = 32;
     let mut final_string = String::with_capacity(MAX_CHARS);
     let mut char_pos = 0;
 
     'outer: while char_pos < MAX_CHARS {
-        let rand = format!("{}", rand());
+        let rand = format!("{}", next());
         for ch in rand.chars() {
             if char_pos < MAX_CHARS {
                 final_string.push(u8_to_char(ch.to_digit(10).unwrap() as u8));
@@ -127,7 +168,71 @@ pub(crate) fn random_character_string_32() -> String {
     final_string
 }
 
+/// Builds a content stream operation without operands, e.g. `operation("Q")`,
+/// for `PdfLayer::push_operation()` and other low-level code
+#[inline]
+pub fn operation<S: Into<String>>(operator: S) -> Operation {
+    operation_with_operands(operator, Vec::new())
+}
+
+/// Builds a content stream operation, e.g. `operation_with_operands("w", vec![2.into()])`
+#[inline]
+pub fn operation_with_operands<S: Into<String>>(operator: S, operands: Vec<lopdf::Object>) -> Operation {
+    Operation { operator: operator.into(), operands }
+}
+
+/// Encodes a text string (titles, alternate descriptions, etc.). ASCII text is written
+/// as a literal string, everything else as UTF-16BE with a byte order mark
+pub(crate) fn pdf_text_string(text: &str) -> lopdf::Object {
+    use lopdf::StringFormat;
+
+    if text.is_ascii() {
+        lopdf::Object::String(text.as_bytes().to_vec(), StringFormat::Literal)
+    } else {
+        let mut bytes = vec![0xFE, 0xFF];
+        for unit in text.encode_utf16() {
+            bytes.push((unit >> 8) as u8);
+            bytes.push(unit as u8);
+        }
+        lopdf::Object::String(bytes, StringFormat::Hexadecimal)
+    }
+}
+
 #[inline(always)]
 fn u8_to_char(input: u8) -> char {
     ('A' as u8 + input) as char
 }
+
+#[test]
+fn test_pdf_text_string() {
+    fn bytes(object: lopdf::Object) -> Vec<u8> {
+        match object { lopdf::Object::String(bytes, _) => bytes, _ => panic!("not a string") }
+    }
+
+    assert_eq!(bytes(pdf_text_string("Logo")), b"Logo".to_vec());
+    assert_eq!(bytes(pdf_text_string("ä")), vec![0xFE, 0xFF, 0x00, 0xE4]);
+}
+
+#[test]
+fn test_random_character_string_32() {
+    let first = random_character_string_32();
+    assert_eq!(first.len(), 32);
+    assert!(first.chars().all(|c| c.is_ascii_uppercase()));
+    assert_ne!(first, random_character_string_32());
+}
+
+#[test]
+fn test_seeded_random() {
+    let (mut first, mut second) = (SeededRandom::new(42), SeededRandom::new(42));
+    assert_eq!(first.character_string_32(), second.character_string_32());
+    assert_eq!(first.next_u64(), second.next_u64());
+    assert_ne!(SeededRandom::new(1).character_string_32(), SeededRandom::new(2).character_string_32());
+    assert_ne!(SeededRandom::new(0).next_u64(), 0);
+}
+
+#[test]
+fn test_operation() {
+    let operations = vec![operation("q"), operation_with_operands("w".to_string(), vec![2.into()]), operation("Q")];
+    let encoded = lopdf::content::Content { operations }.encode().unwrap();
+    assert_eq!(encoded, b"q\n2 w\nQ\n".to_vec());
+}